@@ -0,0 +1,79 @@
+//! Exchanges lines of text with the peer
+//! over the encrypted connection.
+use crate::events::say;
+use gday_encryption::EncryptedStream;
+use owo_colors::OwoColorize;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+
+/// Longest message read from the peer at once.
+/// Longer messages are shown in parts.
+const MAX_MESSAGE_LEN: u64 = 0x10000;
+
+/// Sends each line the user types to the peer on `stream`,
+/// and shows each line the peer sends, until either of them leaves.
+pub async fn run(
+    stream: EncryptedStream<crate::PeerStream>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    say!("Connected. Type a message and press Enter to send it. Press Ctrl-D to leave.");
+    let (mut reader, mut writer) = stream.into_split();
+
+    // A blocking read of standard input can't be cancelled,
+    // so read it on its own thread, which won't delay exiting.
+    let (lines_tx, mut lines_rx) = tokio::sync::mpsc::channel(16);
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lines() {
+            if lines_tx.blocking_send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    let send = async {
+        while let Some(line) = lines_rx.recv().await {
+            writer.write_all(line?.as_bytes()).await?;
+            writer.write_all(b"\n").await?;
+            writer.flush().await?;
+        }
+        writer.shutdown().await
+    };
+
+    let receive = async {
+        while let Some(message) = read_message(&mut reader).await? {
+            say!("{} {message}", "mate:".bold());
+        }
+        std::io::Result::Ok(())
+    };
+
+    tokio::select! {
+        result = send => {
+            result?;
+            say!("You left the chat.");
+        }
+        result = receive => {
+            result?;
+            say!("Your mate left the chat.");
+        }
+    }
+    Ok(())
+}
+
+/// Reads the next line the peer sent from `reader`.
+/// Returns `None` once the peer has left.
+///
+/// Removes control characters, so that the peer can't
+/// mess with the terminal using escape sequences.
+async fn read_message(reader: &mut (impl AsyncBufRead + Unpin)) -> std::io::Result<Option<String>> {
+    let mut message = Vec::new();
+    let len = reader
+        .take(MAX_MESSAGE_LEN)
+        .read_until(b'\n', &mut message)
+        .await?;
+    if len == 0 {
+        return Ok(None);
+    }
+    let message = String::from_utf8_lossy(&message)
+        .chars()
+        .filter(|c| !c.is_control() || *c == '\t')
+        .collect();
+    Ok(Some(message))
+}
@@ -0,0 +1,104 @@
+//! Copies codes to the system clipboard, and pastes them from it,
+//! so they needn't be retyped.
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+
+/// Puts `text` on the system clipboard.
+///
+/// Uses `pbcopy` on macOS, `clip` on Windows, and
+/// `wl-copy`, `xclip`, or `xsel` on Linux and the BSDs.
+pub fn copy(text: &str) -> std::io::Result<()> {
+    let mut command = if cfg!(target_os = "macos") {
+        Command::new("pbcopy")
+    } else if cfg!(windows) {
+        Command::new("clip")
+    } else if wayland() {
+        Command::new("wl-copy")
+    } else if installed("xclip") {
+        let mut command = Command::new("xclip");
+        command.args(["-selection", "clipboard"]);
+        command
+    } else {
+        let mut command = Command::new("xsel");
+        command.args(["--clipboard", "--input"]);
+        command
+    };
+    // the X11 tools keep running to serve the clipboard, so don't wait for them
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|err| named_error(&command, err))?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    stdin.write_all(text.as_bytes())?;
+    Ok(())
+}
+
+/// Returns the text on the system clipboard.
+///
+/// Uses `pbpaste` on macOS, PowerShell on Windows, and
+/// `wl-paste`, `xclip`, or `xsel` on Linux and the BSDs.
+pub fn paste() -> std::io::Result<String> {
+    let mut command = if cfg!(target_os = "macos") {
+        Command::new("pbpaste")
+    } else if cfg!(windows) {
+        let mut command = Command::new("powershell");
+        command.args(["-NoProfile", "-Command", "Get-Clipboard"]);
+        command
+    } else if wayland() {
+        let mut command = Command::new("wl-paste");
+        command.arg("--no-newline");
+        command
+    } else if installed("xclip") {
+        let mut command = Command::new("xclip");
+        command.args(["-selection", "clipboard", "-out"]);
+        command
+    } else {
+        let mut command = Command::new("xsel");
+        command.args(["--clipboard", "--output"]);
+        command
+    };
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|err| named_error(&command, err))?;
+    let mut text = String::new();
+    child
+        .stdout
+        .take()
+        .expect("stdout is piped")
+        .read_to_string(&mut text)?;
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(named_error(
+            &command,
+            std::io::Error::other(format!("failed with {status}")),
+        ));
+    }
+    Ok(text)
+}
+
+/// Returns `true` if this is a Wayland session.
+fn wayland() -> bool {
+    std::env::var_os("WAYLAND_DISPLAY").is_some()
+}
+
+/// Returns `true` if `program` can be run.
+fn installed(program: &str) -> bool {
+    Command::new(program)
+        .arg("-version")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok()
+}
+
+/// Prefixes `err` with the name of the program `command` runs.
+fn named_error(command: &Command, err: std::io::Error) -> std::io::Error {
+    let program = command.get_program().to_string_lossy();
+    std::io::Error::new(err.kind(), format!("{program}: {err}"))
+}
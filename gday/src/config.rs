@@ -0,0 +1,77 @@
+//! Defaults loaded from a config file, which command line flags override.
+use serde::Deserialize;
+use std::path::PathBuf;
+
+/// Defaults read from `config.toml` in the gday config directory,
+/// such as `~/.config/gday/config.toml`.
+///
+/// Every setting is optional, and the matching flag takes precedence.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Domain name of the gday server to use, like `--server`
+    pub server: Option<String>,
+    /// Port of `server`, like `--port`
+    pub port: Option<u16>,
+    /// Whether to connect to `server` with TCP instead of TLS, like `--unencrypted`
+    pub unencrypted: Option<bool>,
+    /// Length of generated codes, like `--length`
+    pub code_length: Option<usize>,
+    /// Directory where received files are saved, like `gday get --path`
+    pub save_dir: Option<PathBuf>,
+    /// Proxy to connect to the server through, such as "socks5://127.0.0.1:9050",
+    /// like `--proxy`
+    pub proxy: Option<String>,
+    /// Transfer speed limit, such as "5MB", like `--limit-rate`
+    pub limit_rate: Option<String>,
+    /// When to color the output, like `--color`
+    pub color: Option<ColorChoice>,
+    /// Whether to show a desktop notification when done, like `--notify`
+    pub notify: Option<bool>,
+    /// Shell command to run when done, like `--on-complete`
+    pub on_complete: Option<String>,
+}
+
+/// When to color the output meant for people.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColorChoice {
+    /// Only when printing to a terminal, and `NO_COLOR` isn't set
+    #[default]
+    Auto,
+    /// Always
+    Always,
+    /// Never
+    Never,
+}
+
+impl Config {
+    /// Reads the config file, or returns the default [`Config`] if there isn't one.
+    pub fn load() -> Result<Self, Box<dyn std::error::Error>> {
+        let Some(path) = path() else {
+            return Ok(Self::default());
+        };
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(format!("Couldn't read '{}': {err}", path.display()).into()),
+        };
+        toml::from_str(&text).map_err(|err| format!("Invalid '{}': {err}", path.display()).into())
+    }
+}
+
+/// Returns the path of the config file, if there's a config directory.
+///
+/// That's `$XDG_CONFIG_HOME/gday/config.toml` or `~/.config/gday/config.toml`,
+/// and `%APPDATA%\gday\config.toml` on Windows.
+fn path() -> Option<PathBuf> {
+    let non_empty = |var| std::env::var_os(var).filter(|value| !value.is_empty());
+    let dir = if cfg!(windows) {
+        PathBuf::from(non_empty("APPDATA")?)
+    } else if let Some(dir) = non_empty("XDG_CONFIG_HOME") {
+        PathBuf::from(dir)
+    } else {
+        PathBuf::from(non_empty("HOME")?).join(".config")
+    };
+    Some(dir.join("gday").join("config.toml"))
+}
@@ -1,30 +1,59 @@
 //! Helper functions for asking the user questions through
 //! the command line.
-use gday_file_transfer::{FileOfferMsg, FileResponseMsg};
-use indicatif::HumanBytes;
+use crate::events::{self, say, say_inline};
+use console::{Key, Term};
+use gday_file_transfer::{
+    DiskSpace, FileOfferMsg, FileResponseMsg, HumanFormat, OfferOverview, PartialDownload,
+    StorageFullAction,
+};
 use owo_colors::OwoColorize;
 use std::{
-    io::{BufRead, Write},
+    io::{IsTerminal, Write},
     path::Path,
+    time::SystemTime,
 };
 
+/// Offers with at least this many files are shown with an [`OfferOverview`].
+const OVERVIEW_MIN_FILES: usize = 10;
+
+/// Runs the prompt `ask` on its own thread, and returns where its answer arrives.
+///
+/// So ctrl-c and the peer disconnecting can still end the program meanwhile.
+/// Not `spawn_blocking()`, since the runtime would wait for the
+/// abandoned prompt to read a line before exiting.
+pub fn spawn_prompt<T: Send + 'static>(
+    ask: impl FnOnce() -> T + Send + 'static,
+) -> tokio::sync::oneshot::Receiver<T> {
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    std::thread::spawn(move || {
+        let _ = tx.send(ask());
+    });
+    rx
+}
+
+/// Shows the names and sizes of these `files` to send,
+/// and their total size, with `format`.
+pub fn show_send(files: &FileOfferMsg, format: HumanFormat) {
+    print_send(files, format);
+    say!(
+        "{} files ({}) in total.",
+        files.files.len(),
+        format.size(files.get_total_offered_size()).bold()
+    );
+}
+
 /// Confirms that the user wants to send these `files``.
 ///
-/// If not, returns false.
-pub fn confirm_send(files: &FileOfferMsg) -> std::io::Result<bool> {
-    // print all the file names and sizes
-    println!("{}", "Files to send:".bold());
-    for file in &files.files {
-        println!("{} ({})", file.short_path.display(), HumanBytes(file.len));
-    }
-    println!();
+/// If not, returns false. Shows sizes with `format`.
+pub fn confirm_send(files: &FileOfferMsg, format: HumanFormat) -> std::io::Result<bool> {
+    print_send(files, format);
 
     // print their total size
     let total_size: u64 = files.get_total_offered_size();
-    print!(
+    say_inline!(
         "Would you like to send these {} files ({})? (y/n): ",
         files.files.len(),
-        HumanBytes(total_size).bold()
+        format.size(total_size).bold()
     );
     std::io::stdout().flush()?;
     let input = get_lowercase_input()?;
@@ -37,91 +66,151 @@ pub fn confirm_send(files: &FileOfferMsg) -> std::io::Result<bool> {
     }
 }
 
-/// Asks the user which of the files in `offer` to accept.
+/// Asks the user whether their mate sees the same verification code.
+pub fn confirm_verification_code() -> std::io::Result<bool> {
+    say_inline!("Does your mate see the same verification code? (y/n): ");
+    std::io::stdout().flush()?;
+    let input = get_lowercase_input()?;
+    Ok(!input.is_empty() && "yes".starts_with(&input))
+}
+
+/// Prints the names and sizes of these `files` to send, with `format`.
+fn print_send(files: &FileOfferMsg, format: HumanFormat) {
+    say!("{}", "Files to send:".bold());
+    for file in &files.files {
+        say!("{} ({})", file.short_path.display(), format.size(file.len));
+    }
+    print_entries(files);
+    say!();
+}
+
+/// Which of the offered files `gday get` accepts without asking the user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcceptPolicy {
+    /// Fully download all files
+    All,
+    /// Only download new files, and resume interrupted downloads
+    OnlyNew,
+    /// Only resume interrupted downloads
+    ResumeOnly,
+    /// Only download files that aren't in the save directory yet,
+    /// downloading interrupted ones again from the start
+    RejectExisting,
+}
+
+/// Shows the files in `offer`, and accepts the ones chosen by `policy`,
+/// without asking the user.
 ///
 /// `save_dir` is the directory where the files will later be saved.
-pub fn ask_receive(
+/// Files longer than `max_file_size` bytes are always rejected.
+/// Shows sizes with `format`.
+pub fn auto_receive(
     offer: &FileOfferMsg,
     save_dir: &Path,
+    policy: AcceptPolicy,
+    max_file_size: Option<u64>,
+    format: HumanFormat,
 ) -> Result<FileResponseMsg, gday_file_transfer::Error> {
-    println!("{}", "Your mate wants to send you:".bold());
-
-    // Print all the offered files.
-    for file in &offer.files {
-        // print file metadata
-        print!("{} ({})", file.short_path.display(), HumanBytes(file.len));
+    let max_file_size = max_file_size.unwrap_or(u64::MAX);
+    print_offer(offer, save_dir, max_file_size, format)?;
 
-        // an interrupted download exists
-        if let Some(local_len) = file.partial_download_exists(save_dir)? {
-            let remaining_len = file.len - local_len;
-
-            print!(
-                " {} {} {}",
-                "CAN RESUME DOWNLOAD.".red().bold(),
-                HumanBytes(remaining_len).red().bold(),
-                "REMAINING".red().bold()
-            );
-
-        // file was already downloaded
-        } else if file.already_exists(save_dir)? {
-            print!(" {}", "ALREADY EXISTS".green().bold());
+    let response = match policy {
+        AcceptPolicy::All => FileResponseMsg::accept_all_files(offer),
+        AcceptPolicy::OnlyNew => FileResponseMsg::accept_only_new_and_interrupted(offer, save_dir)?,
+        AcceptPolicy::ResumeOnly => FileResponseMsg::accept_only_interrupted(offer, save_dir)?,
+        AcceptPolicy::RejectExisting => {
+            FileResponseMsg::accept_only_full_new_files(offer, save_dir)?
         }
-        println!();
     }
+    .reject_larger_than(offer, max_file_size);
 
-    println!();
+    say!(
+        "Accepting {}/{} files ({}).",
+        response.get_num_not_rejected(),
+        offer.files.len(),
+        format.size(offer.get_transfer_size(&response)?).bold()
+    );
+    Ok(response)
+}
 
-    let new_files = FileResponseMsg::accept_only_new_and_interrupted(offer, save_dir)?;
-    let all_files = FileResponseMsg::accept_all_files(offer);
+/// Asks the user which of the files in `offer` to accept.
+///
+/// `save_dir` is the directory where the files will later be saved.
+/// Files longer than `max_file_size` bytes are always rejected.
+/// Shows sizes with `format`.
+pub fn ask_receive(
+    offer: &FileOfferMsg,
+    save_dir: &Path,
+    max_file_size: Option<u64>,
+    format: HumanFormat,
+) -> Result<FileResponseMsg, gday_file_transfer::Error> {
+    let max_file_size = max_file_size.unwrap_or(u64::MAX);
+    print_offer(offer, save_dir, max_file_size, format)?;
+
+    let new_files = FileResponseMsg::accept_only_new_and_interrupted(offer, save_dir)?
+        .reject_larger_than(offer, max_file_size);
+    let all_files =
+        FileResponseMsg::accept_all_files(offer).reject_larger_than(offer, max_file_size);
     let no_files = FileResponseMsg::reject_all_files(offer);
 
+    if all_files.get_num_not_rejected() == 0 && !offer.files.is_empty() {
+        say!(
+            "All files are larger than the maximum of {}.",
+            format.size(max_file_size)
+        );
+        return Ok(no_files);
+    }
+
     // If there are no existing/interrupted files,
     // send or quit.
     if new_files == all_files {
-        print!(
-            "Download all {} files ({})? (y/n): ",
+        say_inline!(
+            "Download all {} files ({})? (y/n, or c to choose): ",
             all_files.get_num_fully_accepted(),
-            HumanBytes(offer.get_transfer_size(&all_files)?).bold()
+            format.size(offer.get_transfer_size(&all_files)?).bold()
         );
         std::io::stdout().flush()?;
         let input = get_lowercase_input()?;
 
-        if "yes".starts_with(&input) {
+        if input == "c" {
+            return pick_files(offer, &all_files, &new_files, save_dir, format);
+        } else if "yes".starts_with(&input) {
             return Ok(all_files);
         } else {
             return Ok(no_files);
         }
     }
 
-    println!(
+    say!(
         "1. Fully download all {} files ({}).",
-        all_files.response.len(),
-        HumanBytes(offer.get_transfer_size(&all_files)?).bold()
+        all_files.get_num_fully_accepted(),
+        format.size(offer.get_transfer_size(&all_files)?).bold()
     );
 
     if new_files.get_num_partially_accepted() == 0 {
-        println!(
+        say!(
             "2. Only download the {} new files ({}).",
             new_files.get_num_fully_accepted(),
-            HumanBytes(offer.get_transfer_size(&new_files)?).bold()
+            format.size(offer.get_transfer_size(&new_files)?).bold()
         );
     } else if new_files.get_num_fully_accepted() == 0 {
-        println!(
+        say!(
             "2. Only resume the {} interrupted downloads ({}).",
             new_files.get_num_partially_accepted(),
-            HumanBytes(offer.get_transfer_size(&new_files)?).bold()
+            format.size(offer.get_transfer_size(&new_files)?).bold()
         );
     } else {
-        println!(
+        say!(
             "2. Only download the {} new files, and resume {} interrupted downloads ({}).",
             new_files.get_num_fully_accepted(),
             new_files.get_num_partially_accepted(),
-            HumanBytes(offer.get_transfer_size(&new_files)?).bold()
+            format.size(offer.get_transfer_size(&new_files)?).bold()
         );
     }
 
-    println!("3. Cancel.");
-    print!("{} ", "Choose an option (1, 2, or 3):".bold());
+    say!("3. Choose which files to download.");
+    say!("4. Cancel.");
+    say_inline!("{} ", "Choose an option (1, 2, 3, or 4):".bold());
     std::io::stdout().flush()?;
 
     match get_lowercase_input()?.as_str() {
@@ -129,20 +218,460 @@ pub fn ask_receive(
         "1" => Ok(all_files),
         // new/interrupted files
         "2" => Ok(new_files),
+        // files chosen one by one
+        "3" => pick_files(offer, &all_files, &new_files, save_dir, format),
         // cancel
         _ => Ok(FileResponseMsg::reject_all_files(offer)),
     }
 }
 
+/// Lets the user choose which files in `offer` to accept,
+/// starting with the ones accepted in `new_files`.
+///
+/// Files rejected in `all_files`, such as ones that are too large, can't be chosen.
+/// Chosen files are resumed if `new_files` resumes them, and fully downloaded otherwise.
+///
+/// Shows a checkbox list in a terminal, and asks for file numbers otherwise.
+/// `save_dir` is the directory where the files will later be saved.
+/// Shows sizes with `format`.
+fn pick_files(
+    offer: &FileOfferMsg,
+    all_files: &FileResponseMsg,
+    new_files: &FileResponseMsg,
+    save_dir: &Path,
+    format: HumanFormat,
+) -> Result<FileResponseMsg, gday_file_transfer::Error> {
+    // say! prints to standard error with --json
+    let term = if events::enabled() {
+        Term::stderr()
+    } else {
+        Term::stdout()
+    };
+
+    let mut labels = Vec::with_capacity(offer.files.len());
+    for (file, accepted) in offer.files.iter().zip(&all_files.response) {
+        let status = if accepted.is_none() {
+            format!(" {}", "TOO LARGE".yellow().bold())
+        } else if file.partial_download_exists(save_dir)?.is_some() {
+            format!(" {}", "CAN RESUME".red().bold())
+        } else if file.already_exists(save_dir)? {
+            format!(" {}", "ALREADY EXISTS".green().bold())
+        } else {
+            String::new()
+        };
+        labels.push(format!(
+            "{} ({}){status}",
+            file.short_path.display(),
+            format.size(file.len)
+        ));
+    }
+    let choosable: Vec<bool> = all_files.response.iter().map(Option::is_some).collect();
+    let initial: Vec<bool> = new_files.response.iter().map(Option::is_some).collect();
+
+    let chosen = if term.is_term() && std::io::stdin().is_terminal() {
+        pick_with_checkboxes(
+            &term,
+            &labels,
+            &choosable,
+            initial,
+            |chosen| {
+                let mut response = FileResponseMsg::reject_all_files(offer);
+                choose(&mut response, chosen, all_files, new_files);
+                offer.get_transfer_size(&response).unwrap_or_default()
+            },
+            format,
+        )?
+    } else {
+        pick_with_numbers(&labels, &choosable)?
+    };
+
+    let mut response = FileResponseMsg::reject_all_files(offer);
+    if let Some(chosen) = chosen {
+        choose(&mut response, &chosen, all_files, new_files);
+    }
+    say!(
+        "Accepting {}/{} files ({}).",
+        response.get_num_not_rejected(),
+        offer.files.len(),
+        format.size(offer.get_transfer_size(&response)?).bold()
+    );
+    Ok(response)
+}
+
+/// Accepts the `chosen` files in `response`, resuming the ones
+/// that `new_files` resumes, and fully downloading the ones `all_files` accepts.
+fn choose(
+    response: &mut FileResponseMsg,
+    chosen: &[bool],
+    all_files: &FileResponseMsg,
+    new_files: &FileResponseMsg,
+) {
+    for (i, &chosen) in chosen.iter().enumerate() {
+        if chosen {
+            response.response[i] = new_files.response[i].or(all_files.response[i]);
+        }
+    }
+}
+
+/// Shows `labels` as a checkbox list on `term`, which the user moves through
+/// with the arrow keys, and checks with space. Only `choosable` items can be checked,
+/// and the `initial` ones start checked.
+///
+/// Shows the total size of the checked items, as returned by `size`, with `format`.
+/// Returns which items were checked, or `None` if the user cancelled.
+fn pick_with_checkboxes(
+    term: &Term,
+    labels: &[String],
+    choosable: &[bool],
+    initial: Vec<bool>,
+    size: impl Fn(&[bool]) -> u64,
+    format: HumanFormat,
+) -> std::io::Result<Option<Vec<bool>>> {
+    let mut chosen: Vec<bool> = initial
+        .iter()
+        .zip(choosable)
+        .map(|(&initial, &choosable)| initial && choosable)
+        .collect();
+    let mut cursor = 0;
+    let mut scroll = 0;
+    // leave room for the instructions and the total
+    let height = usize::from(term.size().0)
+        .saturating_sub(3)
+        .clamp(1, labels.len().max(1));
+    let mut drawn = 0;
+
+    say!(
+        "{}",
+        "Up/down to move, space to check, a to check all, enter to confirm, esc to cancel.".bold()
+    );
+    term.hide_cursor()?;
+    let result = loop {
+        // keep the cursor in view
+        if cursor < scroll {
+            scroll = cursor;
+        } else if cursor >= scroll + height {
+            scroll = cursor + 1 - height;
+        }
+
+        term.clear_last_lines(drawn)?;
+        for (i, label) in labels.iter().enumerate().skip(scroll).take(height) {
+            let pointer = if i == cursor { ">" } else { " " };
+            let checkbox = match (choosable[i], chosen[i]) {
+                (false, _) => "[-]",
+                (true, true) => "[x]",
+                (true, false) => "[ ]",
+            };
+            say!("{pointer} {checkbox} {label}");
+        }
+        say!(
+            "Chosen {}/{} files ({}).",
+            chosen.iter().filter(|&&chosen| chosen).count(),
+            labels.len(),
+            format.size(size(&chosen)).bold()
+        );
+        drawn = height.min(labels.len()) + 1;
+
+        match term.read_key()? {
+            Key::ArrowUp | Key::Char('k') => cursor = cursor.saturating_sub(1),
+            Key::ArrowDown | Key::Char('j') => {
+                cursor = (cursor + 1).min(labels.len().saturating_sub(1));
+            }
+            Key::PageUp => cursor = cursor.saturating_sub(height),
+            Key::PageDown => cursor = (cursor + height).min(labels.len().saturating_sub(1)),
+            Key::Home => cursor = 0,
+            Key::End => cursor = labels.len().saturating_sub(1),
+            Key::Char(' ') if choosable.get(cursor) == Some(&true) => {
+                chosen[cursor] = !chosen[cursor];
+            }
+            // check all, or uncheck all if they're already checked
+            Key::Char('a') => {
+                let all = chosen.iter().zip(choosable).all(|(&c, &ok)| c || !ok);
+                for (chosen, &choosable) in chosen.iter_mut().zip(choosable) {
+                    *chosen = choosable && !all;
+                }
+            }
+            Key::Enter => break Some(chosen),
+            Key::Escape | Key::Char('q') | Key::CtrlC => break None,
+            _ => (),
+        }
+    };
+    term.show_cursor()?;
+    Ok(result)
+}
+
+/// Lists `labels` with numbers, and asks the user for the numbers of
+/// the ones to choose, such as "1,3,5-7". Only `choosable` items can be chosen.
+///
+/// Returns which items were chosen, or `None` if the user chose none.
+fn pick_with_numbers(labels: &[String], choosable: &[bool]) -> std::io::Result<Option<Vec<bool>>> {
+    for (i, label) in labels.iter().enumerate() {
+        say!("{}. {label}", i + 1);
+    }
+    loop {
+        say_inline!("Numbers of the files to download, such as 1,3,5-7: ");
+        std::io::stdout().flush()?;
+        let input = get_input()?;
+        if input.is_empty() {
+            return Ok(None);
+        }
+        match parse_numbers(&input, labels.len()) {
+            Some(chosen) => {
+                let chosen = chosen
+                    .iter()
+                    .zip(choosable)
+                    .map(|(&chosen, &choosable)| chosen && choosable)
+                    .collect();
+                return Ok(Some(chosen));
+            }
+            None => say!("Please enter numbers from 1 to {}.", labels.len()),
+        }
+    }
+}
+
+/// Parses a list of numbers and ranges, such as "1,3,5-7", counting from 1.
+///
+/// Returns which of `len` items it includes,
+/// or `None` if it has anything else, or numbers outside 1 to `len`.
+fn parse_numbers(input: &str, len: usize) -> Option<Vec<bool>> {
+    let mut chosen = vec![false; len];
+    for part in input.split([',', ' ']).filter(|part| !part.is_empty()) {
+        let (start, end) = match part.split_once('-') {
+            Some((start, end)) => (start.trim().parse().ok()?, end.trim().parse().ok()?),
+            None => {
+                let number = part.parse().ok()?;
+                (number, number)
+            }
+        };
+        if start == 0 || start > end || end > len {
+            return None;
+        }
+        chosen[start - 1..end].fill(true);
+    }
+    Some(chosen)
+}
+
+/// Prints the files in `offer`, marking the ones longer than `max_file_size`,
+/// and the ones that already exist or were partially downloaded in `save_dir`.
+///
+/// Warns if `save_dir` doesn't have enough free space. Shows sizes with `format`.
+fn print_offer(
+    offer: &FileOfferMsg,
+    save_dir: &Path,
+    max_file_size: u64,
+    format: HumanFormat,
+) -> Result<(), gday_file_transfer::Error> {
+    say!("{}", "Your mate wants to send you:".bold());
+
+    // Print all the offered files.
+    for file in &offer.files {
+        // print file metadata
+        say_inline!("{} ({})", file.short_path.display(), format.size(file.len));
+
+        // will be rejected
+        if file.len > max_file_size {
+            say_inline!(" {}", "TOO LARGE".yellow().bold());
+
+        // an interrupted download exists
+        } else if let Some(local_len) = file.partial_download_exists(save_dir)? {
+            let remaining_len = file.len - local_len;
+
+            say_inline!(
+                " {} {} {}",
+                "CAN RESUME DOWNLOAD.".red().bold(),
+                format.size(remaining_len).red().bold(),
+                "REMAINING".red().bold()
+            );
+
+        // file was already downloaded
+        } else if file.already_exists(save_dir)? {
+            say_inline!(" {}", "ALREADY EXISTS".green().bold());
+        }
+        say!();
+    }
+    print_entries(offer);
+
+    say!();
+
+    // long lists are hard to take in at a glance
+    if offer.files.len() >= OVERVIEW_MIN_FILES {
+        print_overview(offer, format);
+        say!();
+    }
+
+    if let Some(space) = DiskSpace::check(offer, save_dir)? {
+        if let Some(shortfall) = space.shortfall() {
+            say!(
+                "{} All files need {} more than the {} free in the save directory.",
+                "WARNING:".yellow().bold(),
+                format.size(shortfall),
+                format.size(space.available)
+            );
+            say!();
+        }
+    }
+    Ok(())
+}
+
+/// Prints an [`OfferOverview`] of `offer`, showing sizes with `format`.
+fn print_overview(offer: &FileOfferMsg, format: HumanFormat) {
+    let overview = OfferOverview::of(offer, 3);
+
+    say!("{}", "Overview:".bold());
+    let types: Vec<String> = overview
+        .by_extension
+        .iter()
+        .take(5)
+        .map(|stats| {
+            let extension = match &stats.extension {
+                Some(extension) => format!(".{extension}"),
+                None => "no extension".to_string(),
+            };
+            format!("{} {extension} ({})", stats.count, format.size(stats.bytes))
+        })
+        .collect();
+    let others = overview.by_extension.len().saturating_sub(types.len());
+    if others == 0 {
+        say!("Types: {}", types.join(", "));
+    } else {
+        say!("Types: {}, and {others} more", types.join(", "));
+    }
+
+    let largest: Vec<String> = overview
+        .largest
+        .iter()
+        .map(|&i| {
+            let file = &offer.files[i];
+            format!("{} ({})", file.short_path.display(), format.size(file.len))
+        })
+        .collect();
+    say!("Largest: {}", largest.join(", "));
+
+    if let Some((dir, depth)) = overview.deepest_dir {
+        say!("Deepest folder: {} ({depth} deep)", dir.display());
+    }
+}
+
+/// Prints the symlinks and empty folders in `offer`.
+fn print_entries(offer: &FileOfferMsg) {
+    for link in &offer.symlinks {
+        say!(
+            "{} -> {} (symlink)",
+            link.short_path.display(),
+            link.target.display()
+        );
+    }
+    for dir in &offer.empty_dirs {
+        say!("{} (empty folder)", dir.display());
+    }
+}
+
+/// Asks the user what to do after running out of storage space
+/// while saving files to `save_dir`.
+pub fn ask_storage_full(save_dir: &Path) -> StorageFullAction {
+    say!();
+    say!(
+        "{} while saving to {}.",
+        "Ran out of storage space".red().bold(),
+        save_dir.display()
+    );
+    say!("1. Retry after freeing up space.");
+    say!("2. Continue saving in a different directory.");
+    say!("3. Cancel.");
+    say_inline!("{} ", "Choose an option (1, 2, or 3):".bold());
+
+    let Ok(input) = std::io::stdout()
+        .flush()
+        .and_then(|()| get_lowercase_input())
+    else {
+        return StorageFullAction::Abort;
+    };
+
+    match input.as_str() {
+        "1" => StorageFullAction::Retry,
+        "2" => {
+            say_inline!("{} ", "Directory to save in:".bold());
+            let Ok(path) = std::io::stdout().flush().and_then(|()| get_input()) else {
+                return StorageFullAction::Abort;
+            };
+            StorageFullAction::MoveTo(path.into())
+        }
+        _ => StorageFullAction::Abort,
+    }
+}
+
+/// Confirms that the user wants to delete these `partials`.
+///
+/// If not, returns false. Shows sizes and ages with `format`.
+pub fn confirm_clean(partials: &[PartialDownload], format: HumanFormat) -> std::io::Result<bool> {
+    say!("{}", "Partial downloads:".bold());
+    let now = SystemTime::now();
+    for partial in partials {
+        let age = partial
+            .modified
+            .and_then(|modified| now.duration_since(modified).ok())
+            .map_or_else(|| "unknown age".to_string(), |age| format.duration(age));
+        let orphaned = if partial.path.is_none() {
+            ", info file only"
+        } else {
+            ""
+        };
+        say!(
+            "{} ({}, {age} old{orphaned})",
+            partial.display_path().display(),
+            format.size(partial.len)
+        );
+    }
+    say!();
+
+    let total_len: u64 = partials.iter().map(|partial| partial.len).sum();
+    say_inline!(
+        "Would you like to delete these {} partial downloads ({})? They can't be resumed afterwards. (y/n): ",
+        partials.len(),
+        format.size(total_len).bold()
+    );
+    std::io::stdout().flush()?;
+    let input = get_lowercase_input()?;
+    Ok("yes".starts_with(&input))
+}
+
+/// Lists the `partials` that can be resumed, and how much of each is done,
+/// with sizes and ages shown with `format`.
+pub fn show_resumable(partials: &[PartialDownload], format: HumanFormat) {
+    say!("{}", "Resumable downloads:".bold());
+    let now = SystemTime::now();
+    for partial in partials {
+        let age = partial
+            .modified
+            .and_then(|modified| now.duration_since(modified).ok())
+            .map_or_else(|| "unknown age".to_string(), |age| format.duration(age));
+        let percent = (partial.resumable_len * 100)
+            .checked_div(partial.file_len)
+            .unwrap_or(100);
+        say!(
+            "{} ({}/{} done, {}, {age} old)",
+            partial.display_path().display(),
+            format.size(partial.resumable_len),
+            format.size(partial.file_len),
+            format!("{percent}%").bold()
+        );
+    }
+    say!();
+}
+
 /// Reads a trimmed ascii-lowercase line of input from the user.
 fn get_lowercase_input() -> std::io::Result<String> {
-    let Some(response) = std::io::BufReader::new(std::io::stdin()).lines().next() else {
+    Ok(get_input()?.to_ascii_lowercase())
+}
+
+/// Reads a trimmed line of input from the user.
+fn get_input() -> std::io::Result<String> {
+    // stdin's own buffer keeps piped answers to later questions
+    let Some(response) = std::io::stdin().lines().next() else {
         return Err(std::io::Error::new(
             std::io::ErrorKind::UnexpectedEof,
             "Couldn't read user input.",
         ));
     };
 
-    let response = response?.trim().to_ascii_lowercase();
-    Ok(response)
+    Ok(response?.trim().to_string())
 }
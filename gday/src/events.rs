@@ -0,0 +1,200 @@
+//! Machine-readable events, printed as JSON lines on standard output
+//! with `--json`, so that scripts can follow what gday does.
+//!
+//! Also prints the text meant for people, out of the way of the events.
+use crate::config::ColorChoice;
+use crate::report::TransferSummary;
+use gday_file_transfer::TransferReport;
+use serde::Serialize;
+use std::io::IsTerminal;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// Whether `--json` was passed.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Whether [`say!`] prints to standard error, even without `--json`.
+static TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Whether [`say!`] keeps the colors in what it prints.
+static COLOR: AtomicBool = AtomicBool::new(true);
+
+/// Shortest time between two [`Event::Progress`] of the same file.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Makes [`emit()`] print events,
+/// and [`say!`] print to standard error instead of standard output.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Returns whether [`enable()`] was called.
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Makes [`say!`] print to standard error, such as when
+/// standard output holds the file received by `gday get --stdout`.
+pub fn say_to_stderr() {
+    TO_STDERR.store(true, Ordering::Relaxed);
+}
+
+/// Makes [`say!`] keep or remove colors as chosen by `choice`.
+///
+/// Call after [`enable()`], since that decides where [`say!`] prints.
+pub fn set_color(choice: ColorChoice) {
+    let color = match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            let is_terminal = if enabled() {
+                std::io::stderr().is_terminal()
+            } else {
+                std::io::stdout().is_terminal()
+            };
+            is_terminal && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+        }
+    };
+    COLOR.store(color, Ordering::Relaxed);
+}
+
+/// Like [`println!`], but prints to standard error with `--json`,
+/// which leaves standard output to the events.
+///
+/// Removes colors if [`set_color()`] turned them off.
+macro_rules! say {
+    () => {
+        $crate::events::print_human(format_args!("\n"))
+    };
+    ($($arg:tt)*) => {
+        $crate::events::print_human(format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
+pub(crate) use say;
+
+/// Like [`print!`], but prints to standard error with `--json`.
+///
+/// Removes colors if [`set_color()`] turned them off.
+macro_rules! say_inline {
+    ($($arg:tt)*) => {
+        $crate::events::print_human(format_args!($($arg)*))
+    };
+}
+pub(crate) use say_inline;
+
+/// Prints `text` for [`say!`] and [`say_inline!`].
+pub fn print_human(text: std::fmt::Arguments) {
+    let mut text = text.to_string();
+    if !COLOR.load(Ordering::Relaxed) {
+        text = remove_colors(&text);
+    }
+    if enabled() || TO_STDERR.load(Ordering::Relaxed) {
+        eprint!("{text}");
+    } else {
+        print!("{text}");
+    }
+}
+
+/// Returns `text` without its ANSI escape sequences, such as colors.
+fn remove_colors(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // skip to the final byte of the sequence
+            chars.find(|c| ('@'..='~').contains(c) && *c != '[');
+        } else {
+            plain.push(c);
+        }
+    }
+    plain
+}
+
+/// Something that happened during a run of gday.
+#[derive(Serialize, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    /// A code was generated, which the user should give their mate
+    CodeGenerated {
+        /// The code, of form "server_id.room_code.shared_secret"
+        code: &'a str,
+        /// The same code, as a link of form "gday://server_id/room_code#shared_secret"
+        uri: &'a str,
+    },
+    /// An authenticated encrypted connection with the mate was established
+    PeerConnected {
+        /// [`crate::report::fingerprint()`] of the connection
+        fingerprint: &'a str,
+    },
+    /// Progress of a transfer
+    Progress {
+        /// The file being transferred, as offered
+        file: &'a Path,
+        /// Number of bytes of the file transferred so far
+        file_bytes: u64,
+        /// Length of the file
+        file_len: u64,
+        /// Number of bytes of all files transferred so far
+        bytes: u64,
+        /// Number of bytes of all files to transfer
+        total_bytes: u64,
+        /// Recent transfer speed
+        bytes_per_sec: f64,
+        /// Estimated seconds until the transfer is done, if known
+        eta_secs: Option<f64>,
+    },
+    /// The run failed
+    Error {
+        /// What went wrong
+        message: &'a str,
+    },
+    /// The run ended
+    Complete {
+        /// What the run did
+        summary: &'a TransferSummary,
+    },
+}
+
+/// Prints `event` as a line of JSON on standard output, if [`enabled()`].
+pub fn emit(event: &Event) {
+    if enabled() {
+        let json = serde_json::to_string(event).expect("unreachable: events serialize to JSON");
+        println!("{json}");
+    }
+}
+
+/// Emits [`Event::Progress`] for the reports of a transfer,
+/// at most every [`PROGRESS_INTERVAL`], and whenever a file is done.
+#[derive(Debug, Default)]
+pub struct ProgressEvents {
+    /// When the last event was emitted
+    last: Option<Instant>,
+}
+
+impl ProgressEvents {
+    /// Emits an event for `report`, unless one was emitted recently.
+    pub fn update(&mut self, report: &TransferReport) {
+        if !enabled() {
+            return;
+        }
+        let file_done = report.current_file_processed == report.current_file_len;
+        if !file_done
+            && self
+                .last
+                .is_some_and(|last| last.elapsed() < PROGRESS_INTERVAL)
+        {
+            return;
+        }
+        self.last = Some(Instant::now());
+        emit(&Event::Progress {
+            file: &report.current_file,
+            file_bytes: report.current_file_processed,
+            file_len: report.current_file_len,
+            bytes: report.processed_bytes,
+            total_bytes: report.total_bytes,
+            bytes_per_sec: report.bytes_per_sec,
+            eta_secs: report.eta.map(|eta| eta.as_secs_f64()),
+        });
+    }
+}
@@ -2,26 +2,82 @@
 #![forbid(unsafe_code)]
 #![warn(clippy::all)]
 
+mod chat;
+mod clipboard;
+mod config;
 mod dialog;
+mod events;
+mod notify;
+mod qr;
+mod report;
+mod schedule;
 mod transfer;
 
-use crate::dialog::ask_receive;
+use crate::config::{ColorChoice, Config};
+use crate::dialog::{ask_receive, AcceptPolicy};
+use crate::events::{say, Event};
+use crate::report::{Outcome, TransferSummary};
+use crate::schedule::StartAt;
 use clap::{Parser, Subcommand};
+use gday_contact_exchange_protocol::{FullContact, ServerMsg};
 use gday_encryption::EncryptedStream;
-use gday_file_transfer::{read_from_async, write_to_async, FileOfferMsg, FileResponseMsg};
-use gday_hole_punch::server_connector::{self, DEFAULT_SERVERS};
-use gday_hole_punch::{share_contacts, PeerCode};
+use gday_file_transfer::{
+    read_from_async, write_to_async, Compression, EntryPolicy, FileMetaLocal, FileOfferMsg,
+    FileResponseMsg, Glob, HumanFormat, OfferOptions, OverwritePolicy, PathPolicy, RateLimit,
+    SizeUnits,
+};
+use gday_hole_punch::server_connector::{
+    self, Proxy, ServerConnection, ServerStream, DEFAULT_SERVERS,
+};
+use gday_hole_punch::{share_contacts, Candidate, ConnectionInfo, PeerCode, Secret};
 use log::error;
 use log::info;
+use log::warn;
 use owo_colors::OwoColorize;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, SystemTime};
 
 /// How long to try hole punching before giving up.
 const HOLE_PUNCH_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
 
+/// How long to look for the mate on the local network before giving up.
+const LOCAL_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// How long "gday send --to" waits for a mate who's receiving from someone else.
+const JOIN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(600);
+
+/// How often "gday send --to" tries joining a busy mate again.
+const JOIN_RETRY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(2);
+
 /// How long to try connecting to a server before giving up.
 const SERVER_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
 
+/// How often to show the peer we're still here while the receiver decides on an offer.
+const HEARTBEAT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// How long the peer may send nothing, not even heartbeats,
+/// while we wait for its response, before assuming it vanished.
+const DEAD_PEER_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Most streams to transfer files on, such as with "gday send --streams".
+/// Each extra stream opens a room, and servers limit how many rooms
+/// and relay connections an IP address may request per minute, by default to 10.
+const MAX_STREAMS: u32 = 8;
+
+/// How long to wait for the mate to join each extra connection
+/// of a transfer over several streams.
+const STREAM_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(20);
+
+/// Length of the room code and shared secret to generate, unless configured otherwise.
+const DEFAULT_CODE_LENGTH: usize = 5;
+
+/// How long to send data in each direction during a speedtest, unless configured otherwise.
+const SPEEDTEST_DURATION: std::time::Duration = std::time::Duration::from_secs(3);
+
+/// A connection to the mate, either direct or through a server's relay.
+type PeerStream = ServerStream;
+
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
 struct Args {
@@ -40,45 +96,496 @@ struct Args {
     #[arg(short, long, requires("server"))]
     unencrypted: bool,
 
+    /// Limit the transfer speed to this many bytes per second, such as "5MB",
+    /// so the transfer doesn't slow down other traffic.
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    limit_rate: Option<u64>,
+
+    /// Connect to the server through this SOCKS5 or HTTP proxy,
+    /// such as "socks5://127.0.0.1:9050" for Tor.
+    ///
+    /// The connection to your mate stays direct.
+    #[arg(long, value_name = "URL")]
+    proxy: Option<Proxy>,
+
+    /// Show sizes in powers of 1000 (MB) instead of 1024 (MiB).
+    #[arg(long)]
+    decimal_units: bool,
+
+    /// Print events, such as the generated code, progress, and a summary,
+    /// as lines of JSON on standard output, for scripts to read.
+    ///
+    /// Everything meant for people is printed on standard error instead.
+    #[arg(long)]
+    json: bool,
+
+    /// When to color the output. [default: auto]
+    #[arg(long, value_name = "WHEN")]
+    color: Option<ColorChoice>,
+
     /// Verbosity. (trace, debug, info, warn, error)
     #[arg(short, long, default_value = "warn")]
     verbosity: log::LevelFilter,
+
+    /// When done, show a desktop notification saying how it went.
+    #[arg(long)]
+    notify: bool,
+
+    /// When done, run this shell command, such as a script that moves
+    /// the received files. See the README for the environment variables it gets.
+    #[arg(long, value_name = "CMD")]
+    on_complete: Option<String>,
+
+    /// When done, POST a JSON summary of the transfer to this "http://"
+    /// or "https://" URL, for monitoring automated transfers.
+    #[cfg(feature = "report-url")]
+    #[arg(long, value_name = "URL")]
+    report_url: Option<String>,
 }
 
 #[derive(Subcommand, Debug)]
 enum Command {
     /// Send files and/or directories.
-    Send {
-        /// Files and/or directories to send.
-        #[arg(required = true, num_args = 1..)]
-        paths: Vec<PathBuf>,
-
-        /// Custom shared code of form "server_id.room_code.shared_secret".
-        ///
-        /// A server_id of 0 causes a random server to be used.
-        /// server_id ignored when custom --server set.
-        #[arg(short, long, conflicts_with = "length")]
-        code: Option<PeerCode>,
-
-        /// Length of room_code and shared_secret to generate.
-        #[arg(short, long, default_value = "5", conflicts_with = "code")]
-        length: usize,
-    },
+    Send(SendArgs),
 
     /// Receive files.
-    Get {
-        /// The code your peer gave you (of form "server_id.room_code.shared_secret")
-        code: PeerCode,
+    Get(GetArgs),
+
+    /// Measure latency and throughput between you and your mate.
+    ///
+    /// Run without a code to generate one, then have your mate run
+    /// "gday speedtest <code>".
+    Speedtest(SpeedtestArgs),
+
+    /// Exchange text messages with your mate.
+    ///
+    /// Run without a code to generate one, then have your mate run
+    /// "gday chat <code>". Each line you type is sent to your mate.
+    Chat(ChatArgs),
+
+    /// Resume interrupted downloads.
+    ///
+    /// Lists the partial downloads in a directory, and how much of each can be resumed.
+    /// Once your mate runs "gday send" with the same files again,
+    /// pass their code to receive only the rest of these downloads.
+    Resume(ResumeArgs),
+
+    /// Delete partial downloads left behind by abandoned transfers.
+    ///
+    /// Lists them with their sizes and ages, and asks before deleting.
+    /// Deleted downloads can no longer be resumed.
+    Clean(CleanArgs),
+}
+
+/// The arguments of `gday send`.
+#[derive(clap::Args, Debug)]
+struct SendArgs {
+    /// Files and/or directories to send.
+    #[arg(required_unless_present = "stdin", num_args = 1..)]
+    paths: Vec<PathBuf>,
+
+    /// Send standard input as one file named "--name",
+    /// such as for "tar c dir | gday send --stdin --name backup.tar".
+    ///
+    /// Without "--size", standard input is first read into
+    /// a temporary file, to find its length.
+    #[arg(long, requires = "name", conflicts_with_all = ["paths", "max_downloads", "expire",
+        "follow_symlinks", "include", "exclude", "skip_hidden"])]
+    stdin: bool,
+
+    /// The file name to offer standard input as, with "--stdin".
+    #[arg(long, requires = "stdin", conflicts_with = "paths")]
+    name: Option<PathBuf>,
+
+    /// The length of standard input, such as "2GB", with "--stdin".
+    ///
+    /// Lets it be sent as it's read, without a temporary file.
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, requires = "stdin",
+        conflicts_with_all = ["paths", "checksum"])]
+    size: Option<u64>,
+
+    /// Custom shared code of form "server_id.room_code.shared_secret".
+    ///
+    /// A server_id of 0 causes a random server to be used.
+    /// server_id ignored when custom --server set.
+    #[arg(short, long, conflicts_with_all = ["length", "seed"])]
+    code: Option<PeerCode>,
+
+    /// Length of room_code and shared_secret to generate. [default: 5]
+    #[arg(short, long, conflicts_with_all = ["code", "seed"])]
+    length: Option<usize>,
+
+    /// Send to a mate running "gday get --listen", with the code they gave you.
+    ///
+    /// Waits while your mate receives from someone else.
+    #[arg(long, value_name = "CODE", conflicts_with_all = ["code", "length", "seed", "qr",
+        "local", "max_downloads", "expire"])]
+    to: Option<PeerCode>,
+
+    /// Derive the code from a secret seed you and your mate already share,
+    /// instead of generating one.
+    ///
+    /// Your mate can then run "gday get --seed <SEED>".
+    /// Use a long random seed, since anyone who guesses it can connect.
+    #[arg(long)]
+    seed: Option<String>,
+
+    /// Also show the code as a QR code, which your mate can scan with their phone.
+    #[arg(long)]
+    qr: bool,
+
+    /// Also copy the code to the clipboard, to paste in a message to your mate.
+    #[arg(long, conflicts_with = "to")]
+    copy: bool,
+
+    /// Connect to your mate on the local network, without a server.
+    ///
+    /// Your mate must be on the same network, and run "gday get --local <code>".
+    #[arg(long)]
+    local: bool,
+
+    /// If you can't connect to your mate directly,
+    /// connect through the server's relay, if it has one.
+    ///
+    /// Your mate must pass this too. The server can't read the encrypted files,
+    /// but sees how much you send, and may limit the speed.
+    #[arg(long, conflicts_with = "local")]
+    allow_relay: bool,
+
+    /// Always connect through the server's relay, such as to test it.
+    ///
+    /// Your mate must pass this too.
+    #[arg(long, conflicts_with = "local")]
+    relay_only: bool,
+
+    /// If connecting or the transfer fails, such as when the network drops,
+    /// open the room again with the same code and resume, up to this many times.
+    ///
+    /// Your mate must pass this too.
+    #[arg(long, value_name = "N", default_value_t = 0,
+        conflicts_with_all = ["size", "to", "max_downloads", "expire"])]
+    retries: u32,
+
+    /// Ask you to confirm that your mate sees the same verification code
+    /// before offering the files.
+    ///
+    /// Guards against the server, or whoever learned the code, connecting in their place.
+    #[arg(long)]
+    verify: bool,
+
+    /// Only show which files would be sent, and their sizes,
+    /// without connecting to anyone.
+    ///
+    /// Handy for checking "--include" and "--exclude" on a big folder.
+    #[arg(long, conflicts_with = "stdin")]
+    dry_run: bool,
+
+    /// Keep offering the files to new receivers until this many have connected.
+    ///
+    /// Each receiver after the first gets a newly generated code.
+    #[arg(short, long)]
+    max_downloads: Option<u64>,
+
+    /// Keep offering the files to new receivers until this much time passes.
+    ///
+    /// For example "30m" or "1h". Transfers in progress aren't interrupted.
+    #[arg(short, long)]
+    expire: Option<humantime::Duration>,
+
+    /// Connect to your mate now, but wait until this local time of day
+    /// (for example "22:00") to transfer the files.
+    ///
+    /// Keeps the connection alive until then.
+    /// If your mate also sets a start time, the later one is used.
+    #[arg(long)]
+    start_at: Option<StartAt>,
+
+    /// Send a hash of each file, so your mate can verify it arrived intact.
+    ///
+    /// Takes a while for large files, since they're read an extra time.
+    #[arg(long)]
+    checksum: bool,
+
+    /// Compress files while sending them, at this zstd level
+    /// from 1 (fastest) to 22 (smallest).
+    ///
+    /// Speeds up sending text and source code over slow connections.
+    /// Already compressed formats, such as zip files, are sent as-is.
+    #[arg(long, value_name = "LEVEL", num_args = 0..=1, default_missing_value = "3",
+        value_parser = clap::value_parser!(i32).range(1..=22))]
+    compress: Option<i32>,
+
+    /// Stream the files as one tar archive, instead of one at a time.
+    ///
+    /// Much faster for folders with thousands of tiny files.
+    /// Can't be combined with "--compress".
+    #[arg(long, conflicts_with = "compress")]
+    archive: bool,
+
+    /// Send the files over this many connections at once,
+    /// each carrying different files.
+    ///
+    /// Can speed up sending many large files over fast but distant links,
+    /// where one connection can't use all the bandwidth.
+    /// Each extra connection makes requests to the server,
+    /// which limits how many you may make per minute.
+    /// "--limit-rate" is split evenly between the connections.
+    #[arg(long, value_name = "N",
+        value_parser = clap::value_parser!(u32).range(2..=i64::from(MAX_STREAMS)),
+        conflicts_with_all = ["stdin", "archive"])]
+    streams: Option<u32>,
+
+    /// Send the files and folders that symlinks point to,
+    /// instead of the symlinks themselves.
+    #[arg(long)]
+    follow_symlinks: bool,
+
+    /// Within the given folders, only send files that match this pattern,
+    /// or are in a folder that does. Can be repeated.
+    ///
+    /// For example "*.rs" or "src/**/*.txt".
+    #[arg(short, long, value_name = "GLOB")]
+    include: Vec<Glob>,
+
+    /// Within the given folders, skip files and folders that match this pattern.
+    /// Can be repeated.
+    ///
+    /// For example "target", ".git", or "node_modules".
+    #[arg(short = 'x', long, value_name = "GLOB")]
+    exclude: Vec<Glob>,
+
+    /// Skip hidden files and folders, whose names start with ".".
+    #[arg(long)]
+    skip_hidden: bool,
+}
+
+/// The arguments of `gday get`.
+#[derive(clap::Args, Debug)]
+struct GetArgs {
+    /// The code your peer gave you (of form "server_id.room_code.shared_secret"),
+    /// or the link (of form "gday://server_id/room_code#shared_secret")
+    #[arg(required_unless_present_any = ["seed", "listen", "paste"])]
+    code: Option<PeerCode>,
+
+    /// Derive the code from the secret seed your mate used with "gday send --seed".
+    #[arg(long, conflicts_with = "code")]
+    seed: Option<String>,
+
+    /// Read the code your mate gave you from the clipboard.
+    #[arg(long, conflicts_with_all = ["code", "seed", "listen"])]
+    paste: bool,
+
+    /// Connect to your mate on the local network, without a server,
+    /// if they ran "gday send --local".
+    #[arg(long)]
+    local: bool,
 
-        /// Directory where to save the files.
-        #[arg(short, long, default_value = ".")]
-        path: PathBuf,
-    },
+    /// If you can't connect to your mate directly,
+    /// connect through the server's relay, if it has one.
+    ///
+    /// Your mate must pass this too. The server can't read the encrypted files,
+    /// but sees how much you send, and may limit the speed.
+    #[arg(long, conflicts_with = "local")]
+    allow_relay: bool,
+
+    /// Always connect through the server's relay, such as to test it.
+    ///
+    /// Your mate must pass this too.
+    #[arg(long, conflicts_with = "local")]
+    relay_only: bool,
+
+    /// Instead of joining a mate's code, generate one and receive
+    /// from this many mates in a row, such as to collect photos from a group.
+    ///
+    /// Each mate runs "gday send --to <code> <files>",
+    /// and their files are saved in the same directory.
+    #[arg(long, value_name = "N", conflicts_with_all = ["code", "seed", "local", "stdout"])]
+    listen: Option<u64>,
+
+    /// If connecting or the transfer fails, such as when the network drops,
+    /// join your mate's room again and resume the same files, up to this many times.
+    ///
+    /// Your mate must pass this too.
+    #[arg(long, value_name = "N", default_value_t = 0, conflicts_with_all = ["listen", "stdout"])]
+    retries: u32,
+
+    /// Ask you to confirm that your mate sees the same verification code
+    /// before accepting any files.
+    #[arg(long, conflicts_with = "stdout")]
+    verify: bool,
+
+    /// Directory where to save the files. [default: .]
+    #[arg(short, long)]
+    path: Option<PathBuf>,
+
+    /// Connect to your mate now, but wait until this local time of day
+    /// (for example "22:00") to transfer the files.
+    ///
+    /// Keeps the connection alive until then.
+    /// If your mate also sets a start time, the later one is used.
+    #[arg(long)]
+    start_at: Option<StartAt>,
+
+    /// Keep the permissions and modification times of received files,
+    /// instead of giving them default permissions and the current time.
+    #[arg(long)]
+    preserve: bool,
+
+    /// For accepted files you already have an older version of,
+    /// only download the parts that changed, and replace the older version.
+    #[arg(long)]
+    delta: bool,
+
+    /// Save a manifest in the save directory, saying which files arrived,
+    /// whether they were verified, and how long they took.
+    ///
+    /// Also saved if the transfer fails partway.
+    #[arg(long)]
+    manifest: bool,
+
+    /// What to do with offered symlinks and empty folders:
+    /// "recreate", "skip", or "error".
+    ///
+    /// Symlinks that point outside the save directory are never recreated.
+    #[arg(long, value_name = "POLICY", default_value = "recreate",
+        value_parser = parse_entry_policy)]
+    extras: EntryPolicy,
+
+    /// Reject files larger than this, such as "2GB" or "500MiB",
+    /// while accepting the rest.
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    max_file_size: Option<u64>,
+
+    /// What to do with accepted files that already exist:
+    /// "rename" (save as "name (1)"), "overwrite", "skip", or "fail".
+    #[arg(long, value_name = "POLICY", default_value = "rename",
+        value_parser = parse_overwrite_policy)]
+    on_conflict: OverwritePolicy,
+
+    /// Accept all offered files without asking, such as for scripts and cron jobs.
+    #[arg(short, long, group = "accept")]
+    yes: bool,
+
+    /// Without asking, only accept files that aren't in the save directory yet,
+    /// and resume interrupted downloads.
+    #[arg(long, group = "accept")]
+    only_new: bool,
+
+    /// Without asking, only resume interrupted downloads.
+    #[arg(long, group = "accept")]
+    resume_only: bool,
+
+    /// Without asking, only accept files that aren't in the save directory yet,
+    /// downloading interrupted ones again from the start.
+    #[arg(long, group = "accept")]
+    reject_existing: bool,
+
+    /// Write the offered file to standard output instead of saving it,
+    /// such as for "gday get --stdout <code> | tar x".
+    ///
+    /// Accepts without asking, so your mate must offer exactly one file.
+    /// Progress is shown on standard error.
+    #[arg(long, conflicts_with_all = ["path", "delta", "manifest", "preserve", "on_conflict",
+        "accept"])]
+    stdout: bool,
+}
+
+/// The arguments of `gday speedtest`.
+#[derive(clap::Args, Debug)]
+struct SpeedtestArgs {
+    /// The code your mate gave you. Leave out to generate a new one.
+    code: Option<PeerCode>,
+
+    /// Length of room_code and shared_secret to generate. [default: 5]
+    #[arg(short, long, conflicts_with = "code")]
+    length: Option<usize>,
+
+    /// How long to send data to your mate, such as "10s". [default: 3s]
+    ///
+    /// Your mate sends data for as long as their own --duration.
+    #[arg(short, long)]
+    duration: Option<humantime::Duration>,
+
+    /// Connect to your mate on the local network, without a server.
+    ///
+    /// Your mate must be on the same network, and pass this too.
+    #[arg(long)]
+    local: bool,
+
+    /// If you can't connect to your mate directly,
+    /// connect through the server's relay, if it has one.
+    ///
+    /// Your mate must pass this too. The server may limit the relay's speed,
+    /// so the results may not show how fast a direct connection would be.
+    #[arg(long, conflicts_with = "local")]
+    allow_relay: bool,
+
+    /// Always connect through the server's relay, such as to measure it.
+    ///
+    /// Your mate must pass this too.
+    #[arg(long, conflicts_with = "local")]
+    relay_only: bool,
+}
+
+/// The arguments of `gday chat`.
+#[derive(clap::Args, Debug)]
+struct ChatArgs {
+    /// The code your mate gave you. Leave out to generate a new one.
+    code: Option<PeerCode>,
+
+    /// Length of room_code and shared_secret to generate. [default: 5]
+    #[arg(short, long, conflicts_with = "code")]
+    length: Option<usize>,
+
+    /// Connect to your mate on the local network, without a server.
+    ///
+    /// Your mate must be on the same network, and pass this too.
+    #[arg(long)]
+    local: bool,
+
+    /// If you can't connect to your mate directly,
+    /// connect through the server's relay, if it has one.
+    ///
+    /// Your mate must pass this too. The server can't read the encrypted messages.
+    #[arg(long, conflicts_with = "local")]
+    allow_relay: bool,
+
+    /// Always connect through the server's relay, such as to test it.
+    ///
+    /// Your mate must pass this too.
+    #[arg(long, conflicts_with = "local")]
+    relay_only: bool,
+}
+
+/// The arguments of `gday resume`.
+#[derive(clap::Args, Debug)]
+struct ResumeArgs {
+    /// Directory to search, including its subdirectories.
+    #[arg(default_value = ".")]
+    path: PathBuf,
+
+    /// The code your mate gave you (of form "server_id.room_code.shared_secret"),
+    /// or the link (of form "gday://server_id/room_code#shared_secret")
+    #[arg(short, long)]
+    code: Option<PeerCode>,
+}
+
+/// The arguments of `gday clean`.
+#[derive(clap::Args, Debug)]
+struct CleanArgs {
+    /// Directory to search, including its subdirectories.
+    #[arg(default_value = ".")]
+    path: PathBuf,
+
+    /// Delete without asking.
+    #[arg(short, long)]
+    yes: bool,
 }
 
 #[tokio::main]
-async fn main() {
-    // read command line arguments
+async fn main() -> ExitCode {
+    // read the config file, and the command line arguments that override it
+    let config = Config::load();
     let args = Args::parse();
 
     // initialize logging
@@ -89,203 +596,2037 @@ async fn main() {
         .filter_level(args.verbosity)
         .init();
 
+    if args.json {
+        events::enable();
+    }
+    let (color, notify, on_complete) = match &config {
+        Ok(config) => (
+            args.color.or(config.color),
+            args.notify || config.notify.unwrap_or(false),
+            args.on_complete
+                .clone()
+                .or_else(|| config.on_complete.clone()),
+        ),
+        Err(_) => (args.color, args.notify, args.on_complete.clone()),
+    };
+    events::set_color(color.unwrap_or_default());
+
+    // how to show sizes and times
+    let units = if args.decimal_units {
+        SizeUnits::Decimal
+    } else {
+        SizeUnits::Binary
+    };
+    let format = HumanFormat::from_env(units);
+
+    #[cfg(feature = "report-url")]
+    let report_url = args.report_url.clone();
+    let started = std::time::Instant::now();
+    let mut summary = TransferSummary::default();
+
     // catch and log any errors
-    if let Err(err) = run(args).await {
+    let result = match config {
+        Ok(config) => run(args, config, &mut summary, format).await,
+        Err(err) => Err(err),
+    };
+    if let Err(err) = &result {
         error!("{}", err);
+        events::emit(&Event::Error {
+            message: &err.to_string(),
+        });
+    }
+    summary.finish(&result, started.elapsed());
+    if !summary.peer_fingerprints.is_empty() && result.is_ok() {
+        print_summary(&summary, format);
+    }
+    events::emit(&Event::Complete { summary: &summary });
+
+    if notify {
+        if let Err(err) = notify::desktop(&summary, format) {
+            error!("Couldn't show a desktop notification: {err}");
+        }
+    }
+    if let Some(hook) = on_complete {
+        if let Err(err) = notify::run_hook(&hook, &summary) {
+            error!("Couldn't run the --on-complete command: {err}");
+        }
+    }
+
+    #[cfg(feature = "report-url")]
+    if let Some(url) = report_url {
+        if let Err(err) = report::post(&url, &summary).await {
+            error!("Couldn't post the report to '{url}': {err}");
+        }
     }
+
+    ExitCode::from(summary.outcome.exit_code())
 }
 
-async fn run(args: crate::Args) -> Result<(), Box<dyn std::error::Error>> {
-    // Get the server port
-    let port = if let Some(port) = args.port {
-        port
-    } else {
-        server_connector::DEFAULT_PORT
+/// Prints the totals in `summary` of a run with transfers,
+/// with `format`.
+fn print_summary(summary: &TransferSummary, format: HumanFormat) {
+    let duration = Duration::from_secs_f64(summary.duration_secs);
+    say!(
+        "Transferred {} files ({}) in {}, averaging {}.",
+        summary.files.len(),
+        format.size(summary.bytes),
+        format.duration(duration),
+        format.rate(summary.bytes as f64 / summary.duration_secs.max(f64::EPSILON))
+    );
+    say!(
+        "Skipped {} files, and resumed {}.",
+        summary.skipped,
+        summary.num_resumed()
+    );
+    if summary.failed_peers != 0 {
+        say!("Transfers with {} mate(s) failed.", summary.failed_peers);
+    }
+}
+
+/// Runs the command in `args`, with the defaults in `config`,
+/// and records what happened in `summary`.
+/// Shows sizes and times with `format`.
+async fn run(
+    mut args: crate::Args,
+    config: Config,
+    summary: &mut TransferSummary,
+    format: HumanFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // flags take precedence over the config file,
+    // whose port and encryption only apply to its own server
+    if args.server.is_none() {
+        args.server = config.server;
+        args.port = config.port;
+        args.unencrypted = config.unencrypted.unwrap_or(false);
+    }
+    if args.limit_rate.is_none() {
+        if let Some(limit) = &config.limit_rate {
+            args.limit_rate = Some(
+                parse_size(limit).map_err(|err| format!("Invalid limit-rate in config: {err}"))?,
+            );
+        }
+    }
+    if args.proxy.is_none() {
+        if let Some(proxy) = &config.proxy {
+            args.proxy = Some(
+                proxy
+                    .parse()
+                    .map_err(|err| format!("Invalid proxy in config: {err}"))?,
+            );
+        }
+    }
+
+    let ctx = Context {
+        server: args.server,
+        port: args.port.unwrap_or(server_connector::DEFAULT_PORT),
+        unencrypted: args.unencrypted,
+        proxy: args.proxy,
+        code_length: config.code_length.unwrap_or(DEFAULT_CODE_LENGTH),
+        save_dir: config.save_dir,
+        limit: args.limit_rate.map(RateLimit::new),
+        format,
     };
 
-    // Connect to a custom server if the user chose one.
-    let custom_server = if let Some(domain_name) = args.server {
-        if args.unencrypted {
-            Some(
-                server_connector::connect_tcp(format!("{domain_name}:{port}"), SERVER_TIMEOUT)
-                    .await?,
-            )
+    match args.command {
+        crate::Command::Send(args) => send(args, &ctx, summary).await,
+        crate::Command::Get(args) => get(args, &ctx, summary).await,
+        crate::Command::Speedtest(args) => speedtest(args, &ctx, summary).await,
+        crate::Command::Chat(args) => chat(args, &ctx, summary).await,
+        crate::Command::Resume(args) => resume(args, &ctx, summary).await,
+        crate::Command::Clean(args) => clean(args, &ctx, summary),
+    }
+}
+
+/// What every subcommand needs, as chosen with the global flags and the config file.
+struct Context {
+    /// The domain name of the custom server, if the user chose one
+    server: Option<String>,
+    /// The port of the custom server
+    port: u16,
+    /// Whether to connect to the custom server with TCP instead of TLS
+    unencrypted: bool,
+    /// The proxy to connect to servers through
+    proxy: Option<Proxy>,
+    /// Length of codes to generate, unless a subcommand's `--length` says otherwise
+    code_length: usize,
+    /// Where to save received files, unless `gday get --path` says otherwise
+    save_dir: Option<PathBuf>,
+    /// How fast to transfer files at most
+    limit: Option<RateLimit>,
+    /// How to show sizes and times
+    format: HumanFormat,
+}
+
+impl Context {
+    /// Returns `length` if set, and otherwise the configured code length.
+    fn code_length(&self, length: Option<usize>) -> usize {
+        length.unwrap_or(self.code_length)
+    }
+
+    /// Connects to the custom server, if the user chose one.
+    async fn custom_server(&self) -> Result<Option<ServerConnection>, Box<dyn std::error::Error>> {
+        let Some(domain_name) = &self.server else {
+            return Ok(None);
+        };
+        let connection = connect_to_custom_server(
+            domain_name,
+            self.port,
+            self.unencrypted,
+            self.proxy.as_ref(),
+        )
+        .await?;
+        Ok(Some(connection))
+    }
+
+    /// Connects to the custom server if the user chose one,
+    /// and otherwise to the default server with `server_id`.
+    async fn server(&self, server_id: u64) -> Result<ServerConnection, Box<dyn std::error::Error>> {
+        if let Some(custom_server) = self.custom_server().await? {
+            Ok(custom_server)
         } else {
-            Some(server_connector::connect_tls(domain_name, port, SERVER_TIMEOUT).await?)
+            connect_to_server_id(server_id, self.proxy.as_ref()).await
         }
+    }
+
+    /// Connects to the custom server if the user chose one,
+    /// and otherwise to a random preferred default server.
+    ///
+    /// Returns the connection, and the ID of the server, which is 0 for a custom one.
+    async fn random_server(&self) -> Result<(ServerConnection, u64), Box<dyn std::error::Error>> {
+        if let Some(custom_server) = self.custom_server().await? {
+            Ok((custom_server, 0))
+        } else {
+            connect_to_random_server(self.proxy.as_ref()).await
+        }
+    }
+}
+
+/// Runs `gday send` with `args`, and records what happened in `summary`.
+async fn send(
+    args: SendArgs,
+    ctx: &Context,
+    summary: &mut TransferSummary,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let SendArgs {
+        paths,
+        stdin,
+        name,
+        size,
+        code,
+        length,
+        to,
+        seed,
+        qr,
+        copy,
+        local,
+        allow_relay,
+        relay_only,
+        retries,
+        verify,
+        dry_run,
+        max_downloads,
+        expire,
+        start_at,
+        checksum,
+        compress,
+        archive,
+        streams,
+        follow_symlinks,
+        include,
+        exclude,
+        skip_hidden,
+    } = args;
+    let (limit, format) = (ctx.limit, ctx.format);
+    summary.command = "send";
+
+    // every receiver waits for the same moment
+    let start_at = start_at.map(|start_at| start_at.next());
+
+    let joining = to.is_some();
+    let relay = RelayMode::new(allow_relay, relay_only);
+    let code = code.or(to).or_else(|| {
+        seed.map(|seed| PeerCode::from_seed(seed_server_id(ctx.server.is_some()), &seed))
+    });
+
+    // If the user chose the local network, or a dry run
+    let (mut server_connection, server_id) = if local || dry_run {
+        (None, 0)
+
+    // If the user chose a custom code on a default server
+    } else if let Some(code) = code
+        .as_ref()
+        .filter(|code| code.server_id != 0 && ctx.server.is_none())
+    {
+        (Some(ctx.server(code.server_id).await?), code.server_id)
+
+    // Otherwise, use the custom server, or pick a random one
     } else {
-        None
+        let (server_connection, server_id) = ctx.random_server().await?;
+        (Some(server_connection), server_id)
     };
 
-    match args.command {
-        crate::Command::Send {
-            paths,
-            code,
-            length,
-        } => {
-            // If the user chose a custom server
-            let (mut server_connection, server_id) = if let Some(custom_server) = custom_server {
-                (custom_server, 0)
-
-            // If the user chose a custom code
-            } else if let Some(code) = &code {
-                if code.server_id == 0 {
-                    server_connector::connect_to_random_server(DEFAULT_SERVERS, SERVER_TIMEOUT)
-                        .await?
-                } else {
-                    (
-                        server_connector::connect_to_server_id(
-                            DEFAULT_SERVERS,
-                            code.server_id,
-                            SERVER_TIMEOUT,
-                        )
-                        .await?,
-                        code.server_id,
-                    )
+    // generate random `room_code` and `shared_secret`
+    // if the user didn't provide custom ones
+    let mut peer_code = if let Some(code) = code {
+        PeerCode { server_id, ..code }
+    } else {
+        PeerCode::random(server_id, ctx.code_length(length))
+    };
+
+    // `_spooled` keeps standard input's temporary file until it's sent
+    let (source, mut offer_msg, _spooled) = if let (Some(name), Some(len)) = (&name, size) {
+        // standard input is sent as it's read
+        let offer_msg = FileOfferMsg::from(vec![stdin_meta(name, len)?]);
+        (Source::Stdin, offer_msg, None)
+    } else {
+        // get metadata about the files to transfer
+        let (mut tree, spooled) = if let Some(name) = &name {
+            say!("Reading standard input...");
+            let (file, meta) = spool_stdin(name)?;
+            let tree = gday_file_transfer::FileTreeLocal {
+                files: vec![meta],
+                ..Default::default()
+            };
+            (tree, Some(file))
+        } else {
+            let options = OfferOptions {
+                include,
+                exclude,
+                include_hidden: !skip_hidden,
+                follow_symlinks,
+            };
+            (gday_file_transfer::get_file_tree(&paths, &options)?, None)
+        };
+        // a dry run only lists the files, so needn't read them
+        if !dry_run {
+            // lets the receiver resume partial downloads saved under another folder name
+            for file in &mut tree.files {
+                file.compute_head_hash()?;
+            }
+            if checksum {
+                say!("Hashing files...");
+                for file in &mut tree.files {
+                    file.compute_hash()?;
                 }
+            }
+        }
+        // identical files are sent once, and copied by the receiver
+        let duplicates = if dry_run {
+            Default::default()
+        } else {
+            gday_file_transfer::find_duplicates(&mut tree.files)?
+        };
+        let local_files = tree.files.clone();
+        let mut offer_msg = FileOfferMsg::from(tree);
+        offer_msg.duplicates = duplicates;
+        (Source::Files(local_files), offer_msg, spooled)
+    };
+    offer_msg.compression = compress.map(|level| Compression::Zstd { level });
+    offer_msg.archive = archive;
+    offer_msg.streams = streams;
 
-            // Otherwise, pick a random server
-            } else {
-                server_connector::connect_to_random_server(DEFAULT_SERVERS, SERVER_TIMEOUT).await?
+    if dry_run {
+        dialog::show_send(&offer_msg, format);
+        say!("Dry run, so nothing was sent.");
+        return Ok(());
+    }
+
+    // confirm the user wants to send these files,
+    // unless standard input holds the file instead of the answer
+    if !stdin && !dialog::confirm_send(&offer_msg, format)? {
+        say!("Cancelled.");
+        summary.outcome = Outcome::Cancelled;
+        return Ok(());
+    }
+
+    let options = SendOptions {
+        is_creator: true,
+        relay,
+        qr,
+        copy,
+        verify,
+        start_at,
+        limit,
+        format,
+    };
+
+    // join the room of a mate who receives from several senders
+    if joining {
+        // the mate shows the code, so it isn't shown here
+        let options = SendOptions {
+            is_creator: false,
+            qr: false,
+            copy: false,
+            ..options
+        };
+        let waiting_since = tokio::time::Instant::now();
+        loop {
+            let served = send_to_peer(
+                ctx,
+                server_connection,
+                &peer_code,
+                &source,
+                &offer_msg,
+                options,
+                None,
+            )
+            .await;
+            match served {
+                Ok(served) => {
+                    if let Some((response, fingerprint)) = served {
+                        summary.add_transfer(&offer_msg, &response, fingerprint);
+                    }
+                    return Ok(());
+                }
+                // the room is closed while the mate receives from someone else
+                Err(err)
+                    if matches!(
+                        err.downcast_ref(),
+                        Some(gday_hole_punch::Error::UnexpectedServerReply(
+                            ServerMsg::ErrorNoSuchRoomCode
+                        ))
+                    ) && waiting_since.elapsed() < JOIN_TIMEOUT =>
+                {
+                    if waiting_since.elapsed() < JOIN_RETRY_INTERVAL {
+                        say!("Your mate isn't ready to receive yet. Waiting...");
+                    }
+                    tokio::time::sleep(JOIN_RETRY_INTERVAL).await;
+                }
+                Err(err) => return Err(err),
+            }
+            server_connection = Some(ctx.server(server_id).await?);
+        }
+    }
+
+    // a plain send serves a single receiver
+    if max_downloads.is_none() && expire.is_none() {
+        let mut server_connection = Some(server_connection);
+        let mut failures = 0;
+        loop {
+            let served = async {
+                let server_connection = match server_connection.take() {
+                    Some(server_connection) => server_connection,
+                    None if local => None,
+                    None => Some(ctx.server(server_id).await?),
+                };
+                // retries reuse the code the mate already has
+                let options = SendOptions {
+                    qr: qr && failures == 0,
+                    copy: copy && failures == 0,
+                    ..options
+                };
+                send_to_peer(
+                    ctx,
+                    server_connection,
+                    &peer_code,
+                    &source,
+                    &offer_msg,
+                    options,
+                    None,
+                )
+                .await
             };
+            match served.await {
+                Ok(served) => {
+                    if let Some((response, fingerprint)) = served {
+                        summary.add_transfer(&offer_msg, &response, fingerprint);
+                    }
+                    return Ok(());
+                }
+                // the same code lets the mate reconnect and resume
+                Err(err) if failures < retries && is_transient(&*err) => {
+                    failures += 1;
+                    error!("{err}");
+                    say!("Retrying ({failures}/{retries}). Waiting for your mate to reconnect.");
+                }
+                Err(err) => return Err(err),
+            }
+            tokio::time::sleep(JOIN_RETRY_INTERVAL).await;
+        }
+    }
+
+    // otherwise, keep serving receivers until a limit is reached
+    let deadline = expire.map(|expire| tokio::time::Instant::now() + *expire);
+    let mut download_counts = vec![0_u64; offer_msg.files.len()];
+    let mut num_receivers = 0;
+
+    loop {
+        match send_to_peer(
+            ctx,
+            server_connection,
+            &peer_code,
+            &source,
+            &offer_msg,
+            options,
+            deadline,
+        )
+        .await
+        {
+            Ok(Some((response, fingerprint))) => {
+                summary.add_transfer(&offer_msg, &response, fingerprint);
+                num_receivers += 1;
+                for (count, accepted) in download_counts.iter_mut().zip(response.response.iter()) {
+                    if accepted.is_some() {
+                        *count += 1;
+                    }
+                }
+            }
+            Ok(None) => {
+                say!("Share session expired.");
+                break;
+            }
+            // one failed receiver shouldn't end the session
+            Err(err) => {
+                error!("{err}");
+                summary.failed_peers += 1;
+            }
+        }
+
+        if max_downloads.is_some_and(|max| num_receivers >= max) {
+            say!("Reached the maximum number of downloads.");
+            break;
+        }
+
+        if deadline.is_some_and(|deadline| tokio::time::Instant::now() >= deadline) {
+            say!("Share session expired.");
+            break;
+        }
+
+        // each receiver gets a fresh code
+        peer_code = PeerCode::random(server_id, ctx.code_length(length));
+        server_connection = if local {
+            None
+        } else {
+            Some(ctx.server(server_id).await?)
+        };
+    }
+
+    say!("Served {num_receivers} receiver(s). Download counts:");
+    for (file, count) in offer_msg.files.iter().zip(download_counts) {
+        say!("{count} - {}", file.short_path.display());
+    }
+    Ok(())
+}
+
+/// Runs `gday get` with `args`, and records what happened in `summary`.
+async fn get(
+    args: GetArgs,
+    ctx: &Context,
+    summary: &mut TransferSummary,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let GetArgs {
+        path,
+        code,
+        seed,
+        paste,
+        local,
+        allow_relay,
+        relay_only,
+        listen,
+        retries,
+        verify,
+        start_at,
+        preserve,
+        delta,
+        manifest,
+        extras,
+        max_file_size,
+        on_conflict,
+        yes,
+        only_new,
+        resume_only,
+        reject_existing,
+        stdout,
+    } = args;
+    let (limit, format) = (ctx.limit, ctx.format);
+    // unless this is a `gday resume`
+    if summary.command.is_empty() {
+        summary.command = "get";
+    }
+    let path = path
+        .or_else(|| ctx.save_dir.clone())
+        .unwrap_or_else(|| PathBuf::from("."));
+
+    if stdout && events::enabled() {
+        return Err("--stdout can't be combined with --json, \
+            since both print to standard output."
+            .into());
+    }
+    if stdout {
+        // such as how the connection was made, which isn't part of the file
+        events::say_to_stderr();
+    }
+
+    let relay = RelayMode::new(allow_relay, relay_only);
+    let options = ReceiveOptions {
+        start_at,
+        preserve,
+        delta,
+        manifest,
+        extras,
+        max_file_size,
+        on_conflict,
+        policy: [
+            (yes, AcceptPolicy::All),
+            (only_new, AcceptPolicy::OnlyNew),
+            (resume_only, AcceptPolicy::ResumeOnly),
+            (reject_existing, AcceptPolicy::RejectExisting),
+        ]
+        .into_iter()
+        .find_map(|(chosen, policy)| chosen.then_some(policy)),
+    };
+
+    // keep receiving from new senders with the same code
+    if let Some(max_senders) = listen {
+        let (server_connection, server_id) = ctx.random_server().await?;
+        let peer_code = PeerCode::random(server_id, ctx.code_length(None));
+        let code = String::try_from(&peer_code)?;
+        say!(
+            "Tell up to {max_senders} mates to run \"gday send --to {} <files>\"",
+            code.bold()
+        );
+        events::emit(&Event::CodeGenerated {
+            code: &code,
+            uri: &peer_code.to_uri(),
+        });
 
-            // generate random `room_code` and `shared_secret`
-            // if the user didn't provide custom ones
-            let peer_code = if let Some(code) = code {
-                PeerCode { server_id, ..code }
+        let mut server_connection = Some(server_connection);
+        let mut num_senders = 0;
+        while num_senders < max_senders {
+            // the room closes once a sender joins, so open it again
+            let server_connection = if let Some(connection) = server_connection.take() {
+                connection
             } else {
-                PeerCode::random(server_id, length)
+                ctx.server(server_id).await?
             };
+            let received = async {
+                let connection = connect_to_mate(
+                    Some(server_connection),
+                    &peer_code,
+                    true,
+                    relay,
+                    None,
+                    None,
+                    None,
+                )
+                .await?
+                .expect("unreachable: there's no deadline");
+                let (stream, offer, fingerprint) = open_offer(connection, verify, false).await?;
+                let more_streams = MoreStreams {
+                    peer_code: &peer_code,
+                    is_creator: true,
+                    local: false,
+                    relay,
+                };
+                receive_offer(
+                    ctx,
+                    stream,
+                    offer,
+                    fingerprint,
+                    &path,
+                    &options,
+                    &mut None,
+                    summary,
+                    more_streams,
+                )
+                .await
+            };
+            match received.await {
+                Ok(()) => {
+                    num_senders += 1;
+                    say!("Received from {num_senders}/{max_senders} sender(s).");
+                }
+                // one failed sender shouldn't end the session
+                Err(err) => {
+                    error!("{err}");
+                    summary.failed_peers += 1;
+                }
+            }
+        }
+        return Ok(());
+    }
 
-            // get metadata about the files to transfer
-            let local_files = gday_file_transfer::get_file_metas(&paths)?;
-            let offer_msg = FileOfferMsg::from(local_files.clone());
+    let code = match (code, seed) {
+        (Some(code), _) => code,
+        (None, Some(seed)) => PeerCode::from_seed(seed_server_id(ctx.server.is_some()), &seed),
+        (None, None) if paste => {
+            let pasted =
+                clipboard::paste().map_err(|err| format!("Couldn't read the clipboard: {err}"))?;
+            pasted
+                .trim()
+                .parse()
+                .map_err(|err| format!("The clipboard doesn't hold a code from your mate: {err}"))?
+        }
+        (None, None) => unreachable!("clap requires a code, seed, or paste"),
+    };
+
+    // the files chosen before a failure, which retries accept again
+    let mut accepted = None;
+    let mut failures = 0;
+    let mut failed_at = tokio::time::Instant::now();
+    // a retry's transfer replaces the failed one in the summary
+    let initial_summary = summary.clone();
+    loop {
+        let received = async {
+            let server_connection = if local {
+                None
+            } else {
+                Some(ctx.server(code.server_id).await?)
+            };
+            let connection =
+                connect_to_mate(server_connection, &code, false, relay, None, None, None)
+                    .await?
+                    .expect("unreachable: there's no deadline");
+            let (mut stream, offer, fingerprint) = open_offer(connection, verify, stdout).await?;
 
-            // confirm the user wants to send these files
-            if !dialog::confirm_send(&offer_msg)? {
-                println!("Cancelled.");
+            if stdout {
+                // standard output is for the file, so talk on standard error
+                let [file] = &offer.files[..] else {
+                    return Err(format!(
+                        "--stdout needs your mate to offer exactly one file, \
+                        but they offered {}.",
+                        offer.files.len()
+                    )
+                    .into());
+                };
+                eprintln!(
+                    "Receiving {} ({}) to standard output.",
+                    file.short_path.display(),
+                    format.size(file.len)
+                );
+                let mut response = FileResponseMsg::accept_all_files(&offer);
+                // standard output can only take the file from one stream
+                response.streams = None;
+                write_to_async(&response, &mut stream).await?;
+                summary.add_transfer(&offer, &response, fingerprint);
+                let start_at = start_at.map(|start_at| start_at.next());
+                schedule::wait_for_start(&mut stream, start_at, format).await?;
+                transfer::receive_to_stdout(&offer, &response, &mut stream, limit, format).await?;
                 return Ok(());
             }
 
-            // create a room in the server
-            let (my_contact, peer_contact_fut) =
-                share_contacts(&mut server_connection, peer_code.room_code.as_bytes(), true)
-                    .await?;
+            *summary = initial_summary.clone();
+            let more_streams = MoreStreams {
+                peer_code: &code,
+                is_creator: false,
+                local,
+                relay,
+            };
+            receive_offer(
+                ctx,
+                stream,
+                offer,
+                fingerprint,
+                &path,
+                &options,
+                &mut accepted,
+                summary,
+                more_streams,
+            )
+            .await
+        };
+        match received.await {
+            Ok(()) => return Ok(()),
+            // the mate may not have opened the room again yet
+            Err(err)
+                if failures != 0
+                    && matches!(
+                        err.downcast_ref(),
+                        Some(gday_hole_punch::Error::UnexpectedServerReply(
+                            ServerMsg::ErrorNoSuchRoomCode
+                        ))
+                    )
+                    && failed_at.elapsed() < JOIN_TIMEOUT => {}
+            Err(err) if failures < retries && is_transient(&*err) => {
+                failures += 1;
+                failed_at = tokio::time::Instant::now();
+                error!("{err}");
+                say!("Retrying ({failures}/{retries}). Reconnecting to your mate.");
+            }
+            Err(err) => return Err(err),
+        }
+        tokio::time::sleep(JOIN_RETRY_INTERVAL).await;
+    }
+}
 
-            info!("Your contact is:\n{my_contact}");
+/// Runs `gday speedtest` with `args`, and records what happened in `summary`.
+async fn speedtest(
+    args: SpeedtestArgs,
+    ctx: &Context,
+    summary: &mut TransferSummary,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let format = ctx.format;
+    summary.command = "speedtest";
+    let relay = RelayMode::new(args.allow_relay, args.relay_only);
+    let (mut stream, is_creator) =
+        connect_with_code(ctx, args.code, args.length, args.local, relay, "speedtest").await?;
 
-            println!(
-                "Tell your mate to run \"gday get {}\"",
-                String::try_from(&peer_code)?.bold()
-            );
+    let duration = args.duration.map_or(SPEEDTEST_DURATION, Into::into);
+    say!(
+        "Running speedtest. Sending data to your mate for {}.",
+        format.duration(duration)
+    );
+    let report = gday_file_transfer::run_speedtest(&mut stream, is_creator, duration).await?;
 
-            // get peer's contact
-            let peer_contact = peer_contact_fut.await?;
-            info!("Your mate's contact is:\n{peer_contact}");
-
-            // connect to the peer
-            let (stream, shared_key) = tokio::time::timeout(
-                HOLE_PUNCH_TIMEOUT,
-                gday_hole_punch::try_connect_to_peer(
-                    my_contact.local,
-                    peer_contact,
-                    peer_code.shared_secret.as_bytes(),
-                ),
-            )
+    say!("Round-trip latency: {}", format.duration(report.round_trip));
+    say!(
+        "Upload to mate: {}",
+        format.rate(report.upload_speed as f64)
+    );
+    say!(
+        "Download from mate: {}",
+        format.rate(report.download_speed as f64)
+    );
+    Ok(())
+}
+
+/// Runs `gday chat` with `args`, and records what happened in `summary`.
+async fn chat(
+    args: ChatArgs,
+    ctx: &Context,
+    summary: &mut TransferSummary,
+) -> Result<(), Box<dyn std::error::Error>> {
+    summary.command = "chat";
+    let relay = RelayMode::new(args.allow_relay, args.relay_only);
+    let (stream, _) =
+        connect_with_code(ctx, args.code, args.length, args.local, relay, "chat").await?;
+    chat::run(stream).await?;
+    Ok(())
+}
+
+/// Runs `gday resume` with `args`, and records what happened in `summary`.
+///
+/// Resuming is receiving only what's left of the interrupted downloads.
+async fn resume(
+    args: ResumeArgs,
+    ctx: &Context,
+    summary: &mut TransferSummary,
+) -> Result<(), Box<dyn std::error::Error>> {
+    summary.command = "resume";
+    let partials: Vec<_> = gday_file_transfer::find_partial_downloads(&args.path)?
+        .into_iter()
+        .filter(|partial| partial.resumable_len != 0)
+        .collect();
+    if partials.is_empty() {
+        say!("No resumable partial downloads found.");
+        return Ok(());
+    }
+    dialog::show_resumable(&partials, ctx.format);
+    let Some(code) = args.code else {
+        say!(
+            "To resume them, have your mate run \"gday send\" with the same files, \
+            then run \"gday resume --code <code>\"."
+        );
+        return Ok(());
+    };
+    let args = GetArgs {
+        code: Some(code),
+        seed: None,
+        paste: false,
+        local: false,
+        allow_relay: false,
+        relay_only: false,
+        listen: None,
+        retries: 0,
+        verify: false,
+        path: Some(args.path),
+        start_at: None,
+        preserve: false,
+        delta: false,
+        manifest: false,
+        extras: EntryPolicy::Recreate,
+        max_file_size: None,
+        on_conflict: OverwritePolicy::RenameSuffix,
+        yes: false,
+        only_new: false,
+        resume_only: true,
+        reject_existing: false,
+        stdout: false,
+    };
+    get(args, ctx, summary).await
+}
+
+/// Runs `gday clean` with `args`, and records what happened in `summary`.
+///
+/// Cleaning up doesn't need a server.
+fn clean(
+    args: CleanArgs,
+    ctx: &Context,
+    summary: &mut TransferSummary,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let format = ctx.format;
+    summary.command = "clean";
+    let partials = gday_file_transfer::find_partial_downloads(&args.path)?;
+    if partials.is_empty() {
+        say!("No partial downloads found.");
+        return Ok(());
+    }
+    if !args.yes && !dialog::confirm_clean(&partials, format)? {
+        say!("Cancelled.");
+        summary.outcome = Outcome::Cancelled;
+        return Ok(());
+    }
+    for partial in &partials {
+        partial.remove()?;
+    }
+    let total_len = partials.iter().map(|partial| partial.len).sum();
+    say!(
+        "Deleted {} partial downloads ({}).",
+        partials.len(),
+        format.size(total_len)
+    );
+    Ok(())
+}
+
+/// Parses the policy given to `gday get --extras`.
+fn parse_entry_policy(policy: &str) -> Result<EntryPolicy, String> {
+    match policy {
+        "recreate" => Ok(EntryPolicy::Recreate),
+        "skip" => Ok(EntryPolicy::Skip),
+        "error" => Ok(EntryPolicy::Error),
+        _ => Err(format!(
+            "'{policy}' isn't one of \"recreate\", \"skip\", or \"error\""
+        )),
+    }
+}
+
+/// Parses the policy given to `gday get --on-conflict`.
+fn parse_overwrite_policy(policy: &str) -> Result<OverwritePolicy, String> {
+    match policy {
+        "rename" => Ok(OverwritePolicy::RenameSuffix),
+        "overwrite" => Ok(OverwritePolicy::Overwrite),
+        "skip" => Ok(OverwritePolicy::Skip),
+        "fail" => Ok(OverwritePolicy::FailIfExists),
+        _ => Err(format!(
+            "'{policy}' isn't one of \"rename\", \"overwrite\", \"skip\", or \"fail\""
+        )),
+    }
+}
+
+/// Parses a size given to `gday get --max-file-size`,
+/// such as "2GB", "1.5 GiB", or "1000" bytes.
+fn parse_size(size: &str) -> Result<u64, String> {
+    let invalid = || format!("'{size}' isn't a size like \"2GB\" or \"500MiB\"");
+
+    let unit_start = size
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(unit_start);
+    let number: f64 = number.parse().map_err(|_| invalid())?;
+
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1_000,
+        "m" | "mb" => 1_000_000,
+        "g" | "gb" => 1_000_000_000,
+        "t" | "tb" => 1_000_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        "tib" => 1 << 40,
+        _ => return Err(invalid()),
+    };
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Returns the server ID that both peers use with `--seed`.
+///
+/// Since neither peer sends the other a code, they both use a custom server
+/// if one was chosen, and otherwise the first preferred default server.
+fn seed_server_id(custom_server: bool) -> u64 {
+    if custom_server {
+        0
+    } else {
+        DEFAULT_SERVERS
+            .iter()
+            .find(|server| server.prefer)
+            .map_or(0, |server| server.id)
+    }
+}
+
+/// Connects to the server at `domain_name` and `port`,
+/// over TCP if `unencrypted`, and over TLS otherwise.
+/// Goes through `proxy` if set.
+async fn connect_to_custom_server(
+    domain_name: &str,
+    port: u16,
+    unencrypted: bool,
+    proxy: Option<&Proxy>,
+) -> Result<ServerConnection, Box<dyn std::error::Error>> {
+    let connection = match (proxy, unencrypted) {
+        (Some(proxy), true) => proxy.connect_tcp(domain_name, port, SERVER_TIMEOUT).await?,
+        (Some(proxy), false) => {
+            proxy
+                .connect_tls(domain_name.to_string(), port, SERVER_TIMEOUT)
+                .await?
+        }
+        (None, true) => {
+            server_connector::connect_tcp(format!("{domain_name}:{port}"), SERVER_TIMEOUT).await?
+        }
+        (None, false) => {
+            server_connector::connect_tls(domain_name.to_string(), port, SERVER_TIMEOUT).await?
+        }
+    };
+    Ok(connection)
+}
+
+/// Connects to the default server with `server_id`, through `proxy` if set.
+async fn connect_to_server_id(
+    server_id: u64,
+    proxy: Option<&Proxy>,
+) -> Result<ServerConnection, Box<dyn std::error::Error>> {
+    let Some(proxy) = proxy else {
+        return Ok(server_connector::connect_to_server_id(
+            DEFAULT_SERVERS,
+            server_id,
+            SERVER_TIMEOUT,
+        )
+        .await?);
+    };
+    let server = DEFAULT_SERVERS
+        .iter()
+        .find(|server| server.id == server_id)
+        .ok_or(gday_hole_punch::Error::ServerIDNotFound(server_id))?;
+    let domain_name = server.domain_name.to_string();
+    Ok(proxy
+        .connect_tls(domain_name, server_connector::DEFAULT_PORT, SERVER_TIMEOUT)
+        .await?)
+}
+
+/// Connects to a random preferred default server, through `proxy` if set.
+///
+/// Returns the connection, and the ID of the server.
+async fn connect_to_random_server(
+    proxy: Option<&Proxy>,
+) -> Result<(ServerConnection, u64), Box<dyn std::error::Error>> {
+    let Some(proxy) = proxy else {
+        return Ok(
+            server_connector::connect_to_random_server(DEFAULT_SERVERS, SERVER_TIMEOUT).await?,
+        );
+    };
+    // through a proxy, try the preferred servers in order
+    for server in DEFAULT_SERVERS.iter().filter(|server| server.prefer) {
+        let domain_name = server.domain_name.to_string();
+        match proxy
+            .connect_tls(domain_name, server_connector::DEFAULT_PORT, SERVER_TIMEOUT)
             .await
-            .map_err(|_| gday_hole_punch::Error::HolePunchTimeout)??;
+        {
+            Ok(connection) => return Ok((connection, server.id)),
+            Err(err) => warn!("Couldn't connect to '{}': {err}", server.domain_name),
+        }
+    }
+    Err(gday_hole_punch::Error::CouldntConnectToServers.into())
+}
 
-            // Gracefully terminate TLS
-            server_connection.shutdown().await?;
+/// Where the offered files are sent from.
+enum Source {
+    /// These files on the file system
+    Files(Vec<FileMetaLocal>),
+    /// Standard input, offered as the only file
+    Stdin,
+}
 
-            let mut stream = EncryptedStream::encrypt_connection(stream, &shared_key).await?;
+/// Returns the metadata of standard input, offered as the file `name`,
+/// whose length is `len`.
+fn stdin_meta(name: &Path, len: u64) -> Result<FileMetaLocal, Box<dyn std::error::Error>> {
+    if !matches!(
+        name.components().collect::<Vec<_>>()[..],
+        [std::path::Component::Normal(_)]
+    ) {
+        return Err(format!(
+            "--name must be a file name, such as \"backup.tar\", not '{}'.",
+            name.display()
+        )
+        .into());
+    }
+    Ok(FileMetaLocal {
+        short_path: name.to_path_buf(),
+        local_path: PathBuf::new(),
+        len,
+        hash: None,
+        head_hash: None,
+        // chunks that don't compress well are sent as-is
+        compressible: true,
+        mode: None,
+        modified: Some(SystemTime::now()),
+    })
+}
 
-            info!("Established authenticated encrypted connection with peer.");
+/// Reads all of standard input into a temporary file,
+/// to be offered as the file `name`.
+///
+/// Returns the temporary file, which is deleted once dropped, and its metadata.
+fn spool_stdin(
+    name: &Path,
+) -> Result<(tempfile::NamedTempFile, FileMetaLocal), Box<dyn std::error::Error>> {
+    // check the name before waiting for standard input
+    let meta = stdin_meta(name, 0)?;
+    let mut file = tempfile::NamedTempFile::new()?;
+    let len = std::io::copy(&mut std::io::stdin().lock(), &mut file)?;
+    let meta = FileMetaLocal {
+        local_path: file.path().to_path_buf(),
+        len,
+        ..meta
+    };
+    Ok((file, meta))
+}
 
-            // offer these files to the peer
-            write_to_async(offer_msg, &mut stream).await?;
+/// Connects to the mate that joins with `code`. If `code` is `None`, generates one
+/// with `length`, and tells the user to have their mate run "gday `command` <code>".
+/// Connects on the local network if `local`, and otherwise through a server,
+/// and its relay as `relay` allows.
+///
+/// Returns the encrypted connection, and whether this peer generated the code.
+async fn connect_with_code(
+    ctx: &Context,
+    code: Option<PeerCode>,
+    length: Option<usize>,
+    local: bool,
+    relay: RelayMode,
+    command: &str,
+) -> Result<(EncryptedStream<PeerStream>, bool), Box<dyn std::error::Error>> {
+    let is_creator = code.is_none();
 
-            println!("File offer sent to mate. Waiting on response.");
+    let (server_connection, peer_code) = if let Some(code) = code {
+        let server_connection = if local {
+            None
+        } else {
+            Some(ctx.server(code.server_id).await?)
+        };
+        (server_connection, code)
+    } else if local {
+        (None, PeerCode::random(0, ctx.code_length(length)))
+    } else {
+        let (server_connection, server_id) = ctx.random_server().await?;
+        let peer_code = PeerCode::random(server_id, ctx.code_length(length));
+        (Some(server_connection), peer_code)
+    };
 
-            // receive response from peer
-            let response: FileResponseMsg = read_from_async(&mut stream).await?;
+    let show = ShowCode {
+        command,
+        qr: false,
+        copy: false,
+    };
+    let connection = connect_to_mate(
+        server_connection,
+        &peer_code,
+        is_creator,
+        relay,
+        is_creator.then_some(show),
+        None,
+        None,
+    )
+    .await?
+    .expect("unreachable: there's no deadline");
+    let (mut stream, _) = open_encrypted_stream(connection).await?;
+    verify_peer(&mut stream, false, false).await?;
 
-            // Total number of files accepted
-            let num_accepted = response.get_num_not_rejected();
+    Ok((stream, is_creator))
+}
 
-            // How many of those files are being resumed
-            let resumptions = response.get_num_partially_accepted();
+/// How `gday get` chooses and saves the files of an offer.
+struct ReceiveOptions {
+    /// When to start the transfer
+    start_at: Option<StartAt>,
+    /// Whether to keep the permissions and modification times of the files
+    preserve: bool,
+    /// Whether to only download the changed parts of older versions
+    delta: bool,
+    /// Whether to save a manifest in the save directory
+    manifest: bool,
+    /// What to do with offered symlinks and empty folders
+    extras: EntryPolicy,
+    /// Largest file to accept
+    max_file_size: Option<u64>,
+    /// What to do with accepted files that already exist
+    on_conflict: OverwritePolicy,
+    /// Which files to accept without asking, or `None` to ask
+    policy: Option<AcceptPolicy>,
+}
 
-            println!(
-                "Your mate accepted {}/{} files",
-                num_accepted,
-                local_files.len()
-            );
+/// The files a `gday get` accepted, so that a retry after
+/// a failed transfer can accept them again without asking.
+struct Accepted {
+    /// The offer they were accepted from
+    offer: FileOfferMsg,
+    /// Which files of `offer` were accepted, as chosen before
+    /// renaming or skipping them for `--on-conflict`
+    response: FileResponseMsg,
+    /// Which files of `offer` were already saved before the transfer
+    existed: Vec<bool>,
+}
 
-            if resumptions != 0 {
-                println!("Resuming transfer of {resumptions} previously interrupted file(s).");
-            }
+impl Accepted {
+    /// Remembers that `response` accepted files of `offer` to save in `save_dir`.
+    fn new(
+        offer: &FileOfferMsg,
+        response: &FileResponseMsg,
+        save_dir: &Path,
+    ) -> Result<Self, gday_file_transfer::Error> {
+        let existed = offer
+            .files
+            .iter()
+            .map(|file| file.already_exists(save_dir))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            offer: offer.clone(),
+            response: response.clone(),
+            existed,
+        })
+    }
 
-            if num_accepted != 0 {
-                transfer::send_files(local_files, response, &mut stream).await?;
-            }
+    /// Accepts the same files from `offer` again, resuming the ones
+    /// partially saved in `save_dir`, and skipping the ones already finished.
+    ///
+    /// Returns an error if `offer` has different files than the first one.
+    fn accept_again(
+        &self,
+        offer: &FileOfferMsg,
+        save_dir: &Path,
+        format: HumanFormat,
+    ) -> Result<FileResponseMsg, Box<dyn std::error::Error>> {
+        let same_files = offer.files.len() == self.offer.files.len()
+            && offer
+                .files
+                .iter()
+                .zip(&self.offer.files)
+                .all(|(new, old)| new.short_path == old.short_path && new.len == old.len);
+        if !same_files {
+            return Err("Your mate offered different files after reconnecting.".into());
         }
 
-        // receiving files
-        crate::Command::Get { path, code } => {
-            let mut server_connection = if let Some(custom_server) = custom_server {
-                custom_server
+        let mut response = FileResponseMsg::reject_all_files(offer);
+        for (i, file) in offer.files.iter().enumerate() {
+            if self.response.response[i].is_none() {
+                continue;
+            }
+            response.response[i] = if let Some(start) = file.partial_download_exists(save_dir)? {
+                Some(start)
+            } else if file.already_exists(save_dir)? && !self.existed[i] {
+                // finished before the failure
+                None
             } else {
-                server_connector::connect_to_server_id(
-                    DEFAULT_SERVERS,
-                    code.server_id,
-                    SERVER_TIMEOUT,
-                )
-                .await?
+                Some(0)
             };
+        }
+
+        say!(
+            "Accepting the remaining {}/{} files ({}).",
+            response.get_num_not_rejected(),
+            offer.files.len(),
+            format.size(offer.get_transfer_size(&response)?).bold()
+        );
+        Ok(response)
+    }
+}
+
+/// Returns `true` if `err` may not happen again when retrying,
+/// such as when the network dropped, or the mate disconnected.
+fn is_transient(err: &(dyn std::error::Error + 'static)) -> bool {
+    use gday_hole_punch::Error as PunchError;
+
+    if let Some(err) = err.downcast_ref::<std::io::Error>() {
+        return is_network_error(err);
+    }
+    if let Some(err) = err.downcast_ref::<gday_file_transfer::Error>() {
+        return match err {
+            gday_file_transfer::Error::IO(err) => is_network_error(err),
+            gday_file_transfer::Error::PeerTimedOut(_)
+            | gday_file_transfer::Error::IncompleteTransfer => true,
+            _ => false,
+        };
+    }
+    if let Some(PunchError::IO(err)) = err.downcast_ref() {
+        return is_network_error(err);
+    }
+    matches!(
+        err.downcast_ref(),
+        Some(
+            PunchError::ServerProtocolError(_)
+                | PunchError::CouldntConnectToServers
+                | PunchError::HolePunchTimeout
+                | PunchError::RelayPeerTimedOut
+                | PunchError::LocalPeerNotFound
+        )
+    )
+}
 
-            let (my_contact, peer_contact_fut) =
-                share_contacts(&mut server_connection, code.room_code.as_bytes(), false).await?;
+/// Returns `true` if `err` came from the connection breaking,
+/// rather than from something like a full disk or the user cancelling.
+fn is_network_error(err: &std::io::Error) -> bool {
+    use std::io::ErrorKind;
+    matches!(
+        err.kind(),
+        ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::BrokenPipe
+            | ErrorKind::UnexpectedEof
+            | ErrorKind::TimedOut
+            | ErrorKind::NotConnected
+    )
+}
+
+/// How the peer that generated a code shows it to the user.
+#[derive(Debug, Clone, Copy)]
+struct ShowCode<'a> {
+    /// The subcommand the mate runs with the code, such as "get"
+    command: &'a str,
+    /// Whether to also show the code as a QR code
+    qr: bool,
+    /// Whether to also copy the code to the clipboard
+    copy: bool,
+}
 
-            info!("Your contact is:\n{my_contact}");
+/// Meets the mate with `peer_code` through `server_connection`,
+/// creating its room if `is_creator`, and connects to them with hole punching,
+/// or through the server's relay as `relay` allows.
+/// Connects on the local network instead if `server_connection` is `None`.
+///
+/// Once the mate can join, shows the code as set in `show`,
+/// with the flags the mate needs to connect the same way.
+/// If there's already a `rendezvous` connection to the mate, see [`room_opened()`].
+///
+/// Returns `None` if `deadline` passes before the mate joins.
+async fn connect_to_mate(
+    server_connection: Option<ServerConnection>,
+    peer_code: &PeerCode,
+    is_creator: bool,
+    relay: RelayMode,
+    show: Option<ShowCode<'_>>,
+    deadline: Option<tokio::time::Instant>,
+    rendezvous: Option<&mut EncryptedStream<PeerStream>>,
+) -> Result<Option<(PeerStream, Secret<[u8; 32]>)>, Box<dyn std::error::Error>> {
+    if let Some(server_connection) = server_connection {
+        return connect_through_server(
+            server_connection,
+            peer_code,
+            is_creator,
+            relay,
+            show,
+            deadline,
+            rendezvous,
+        )
+        .await;
+    }
 
-            let peer_contact = peer_contact_fut.await?;
+    // the peers start looking for each other at once
+    room_opened(rendezvous, is_creator).await?;
+    if let Some(show) = show {
+        show_code(
+            peer_code,
+            &format!("{} --local", show.command),
+            show.qr,
+            show.copy,
+        )?;
+    }
+    let Some(connection) = until(deadline, connect_locally(peer_code, is_creator)).await else {
+        return Ok(None);
+    };
+    Ok(Some(connection?))
+}
 
-            info!("Your mate's contact is:\n{peer_contact}");
+/// Meets the mate with `peer_code` through `server_connection`,
+/// creating its room if `is_creator`, and connects to them with hole punching,
+/// or through the server's relay as `relay` allows.
+///
+/// Shows the code as set in `show` once the room is open,
+/// and returns `None` if `deadline` passes before the mate joins it.
+/// If there's already a `rendezvous` connection to the mate, see [`room_opened()`].
+async fn connect_through_server(
+    mut server_connection: ServerConnection,
+    peer_code: &PeerCode,
+    is_creator: bool,
+    relay: RelayMode,
+    show: Option<ShowCode<'_>>,
+    deadline: Option<tokio::time::Instant>,
+    mut rendezvous: Option<&mut EncryptedStream<PeerStream>>,
+) -> Result<Option<(PeerStream, Secret<[u8; 32]>)>, Box<dyn std::error::Error>> {
+    if !is_creator {
+        room_opened(rendezvous.as_deref_mut(), false).await?;
+    }
 
-            let (stream, shared_key) = tokio::time::timeout(
-                HOLE_PUNCH_TIMEOUT,
-                gday_hole_punch::try_connect_to_peer(
-                    my_contact.local,
-                    peer_contact,
-                    code.shared_secret.as_bytes(),
-                ),
-            )
-            .await
-            .map_err(|_| gday_hole_punch::Error::HolePunchTimeout)??;
+    if relay == RelayMode::Only {
+        if is_creator {
+            // the server only relays peers that met in a room
+            gday_hole_punch::create_room(&mut server_connection, &peer_code.room_code).await?;
+            room_opened(rendezvous, true).await?;
+        }
+        if let Some(show) = show {
+            let command = format!("{} --relay-only", show.command);
+            show_code(peer_code, &command, show.qr, show.copy)?;
+        }
+        let connect = connect_through_relay(server_connection, peer_code, is_creator);
+        let Some(connection) = until(deadline, connect).await else {
+            return Ok(None);
+        };
+        return Ok(Some(connection?));
+    }
+
+    // create or join a room in the server
+    let (my_contact, peer_contact_fut) =
+        share_contacts(&mut server_connection, &peer_code.room_code, is_creator).await?;
+    if is_creator {
+        room_opened(rendezvous, true).await?;
+    }
+
+    info!("Your contact is:\n{my_contact}");
+
+    if let Some(show) = show {
+        show_code(peer_code, show.command, show.qr, show.copy)?;
+    }
+
+    let Some(peer_contact) = until(deadline, peer_contact_fut).await else {
+        return Ok(None);
+    };
+    let peer_contact = peer_contact?;
+
+    info!("Your mate's contact is:\n{peer_contact}");
+
+    let connection = punch_or_relay(
+        server_connection,
+        my_contact,
+        peer_contact,
+        peer_code,
+        is_creator,
+        relay,
+    )
+    .await?;
+    Ok(Some(connection))
+}
+
+/// Over `rendezvous`, an existing connection to the mate, tells them
+/// that the room of a new connection is open if `is_creator`,
+/// and otherwise waits until they say so.
+///
+/// Keeps the joining peer from asking for a room that isn't open yet,
+/// which servers count against its request limit.
+async fn room_opened(
+    rendezvous: Option<&mut EncryptedStream<PeerStream>>,
+    is_creator: bool,
+) -> Result<(), gday_file_transfer::Error> {
+    let Some(stream) = rendezvous else {
+        return Ok(());
+    };
+    // the message's arrival is what counts
+    if is_creator {
+        write_to_async((), stream).await
+    } else {
+        read_from_async(stream).await
+    }
+}
+
+/// Awaits `future`, or returns `None` if `deadline` passes first.
+async fn until<T>(
+    deadline: Option<tokio::time::Instant>,
+    future: impl std::future::Future<Output = T>,
+) -> Option<T> {
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline, future).await.ok(),
+        None => Some(future.await),
+    }
+}
+
+/// When to connect to the mate through the server's relay.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RelayMode {
+    /// Never, only directly
+    Never,
+    /// Only if connecting directly fails
+    Fallback,
+    /// Always
+    Only,
+}
+
+impl RelayMode {
+    /// Returns the mode chosen with `--allow-relay` and `--relay-only`.
+    fn new(allow_relay: bool, relay_only: bool) -> Self {
+        if relay_only {
+            Self::Only
+        } else if allow_relay {
+            Self::Fallback
+        } else {
+            Self::Never
+        }
+    }
+}
+
+/// Connects to the peer at `peer_contact` with hole punching,
+/// after sharing `my_contact` with it through `server_connection`.
+///
+/// If that times out, and `relay` allows it, connects through
+/// the server's relay instead, for the room of `peer_code`.
+async fn punch_or_relay(
+    mut server_connection: ServerConnection,
+    my_contact: FullContact,
+    peer_contact: FullContact,
+    peer_code: &PeerCode,
+    is_creator: bool,
+    relay: RelayMode,
+) -> Result<(PeerStream, Secret<[u8; 32]>), Box<dyn std::error::Error>> {
+    let punched = tokio::time::timeout(
+        HOLE_PUNCH_TIMEOUT,
+        gday_hole_punch::try_connect_to_peer(
+            my_contact.local,
+            peer_contact,
+            &peer_code.shared_secret,
+        ),
+    )
+    .await;
 
+    match punched {
+        Ok(connection) => {
+            let (stream, shared_key, connection_info) = connection?;
+            show_connection(&connection_info);
             // Gracefully terminate TLS
             server_connection.shutdown().await?;
+            Ok((PeerStream::TCP(stream), shared_key))
+        }
+        Err(_) if relay == RelayMode::Fallback => {
+            say!(
+                "Couldn't connect to your mate directly, so connecting through the server's relay."
+            );
+            Ok(connect_through_relay(server_connection, peer_code, is_creator).await?)
+        }
+        Err(_) => Err(gday_hole_punch::Error::HolePunchTimeout.into()),
+    }
+}
+
+/// Tells the user how hole punching connected to their mate,
+/// such as "Connected directly via IPv6 (public address)".
+fn show_connection(info: &ConnectionInfo) {
+    let ip_version = if info.is_ipv6() { "IPv6" } else { "IPv4" };
+    let address = match info.candidate {
+        Candidate::Public => "public address",
+        Candidate::Local => "local address",
+        // most likely the mate's NAT gave the connection a port it didn't report
+        Candidate::Unknown => "through a port your mate's NAT mapped",
+    };
+    say!("Connected directly via {ip_version} ({address}).");
+    let how = if info.accepted {
+        "your mate connected to you"
+    } else {
+        "you connected to your mate"
+    };
+    info!(
+        "The connection is from {} to {}, since {how}. Authenticating took {:.1?}.",
+        info.local_addr, info.peer_addr, info.handshake_time
+    );
+}
 
-            let mut stream = EncryptedStream::encrypt_connection(stream, &shared_key).await?;
+/// Connects to the peer with `peer_code` through the relay of the server
+/// that `server_connection` is connected to.
+async fn connect_through_relay(
+    server_connection: ServerConnection,
+    peer_code: &PeerCode,
+    is_creator: bool,
+) -> Result<(PeerStream, Secret<[u8; 32]>), gday_hole_punch::Error> {
+    info!("Connecting to your mate through the server's relay.");
+    gday_hole_punch::connect_through_relay(
+        server_connection,
+        &peer_code.room_code,
+        &peer_code.shared_secret,
+        is_creator,
+    )
+    .await
+}
 
-            info!("Established authenticated encrypted connection with peer.");
+/// Encrypts `connection` to the mate, and tells scripts about it.
+///
+/// Returns the encrypted stream, and the connection's [`report::fingerprint()`].
+async fn open_encrypted_stream(
+    (stream, shared_key): (PeerStream, Secret<[u8; 32]>),
+) -> Result<(EncryptedStream<PeerStream>, String), Box<dyn std::error::Error>> {
+    let stream = EncryptedStream::encrypt_connection(stream, shared_key.expose_secret()).await?;
 
-            // receive file offer from peer
-            let offer: FileOfferMsg = read_from_async(&mut stream).await?;
+    info!("Established authenticated encrypted connection with peer.");
+    let fingerprint = report::fingerprint(&shared_key);
+    events::emit(&Event::PeerConnected {
+        fingerprint: &fingerprint,
+    });
+    Ok((stream, fingerprint))
+}
 
-            let response = ask_receive(&offer, &path)?;
+/// Encrypts `connection` to the sending peer, and receives its file offer.
+/// Shows the verification code on standard error if `stderr`,
+/// and has the user confirm it first if `verify`.
+///
+/// Returns the encrypted stream, the offer, and the connection's [`report::fingerprint()`].
+async fn open_offer(
+    connection: (PeerStream, Secret<[u8; 32]>),
+    verify: bool,
+    stderr: bool,
+) -> Result<(EncryptedStream<PeerStream>, FileOfferMsg, String), Box<dyn std::error::Error>> {
+    let (mut stream, fingerprint) = open_encrypted_stream(connection).await?;
 
-            // respond to the file offer
-            write_to_async(&response, &mut stream).await?;
+    let clock_skew = gday_file_transfer::estimate_clock_skew(&mut stream, false).await?;
+    info!("Estimated clock skew with peer: {clock_skew:?}");
 
-            if response.get_num_not_rejected() == 0 {
-                println!("No files will be downloaded.");
-            } else {
-                transfer::receive_files(offer, response, &path, &mut stream).await?;
+    verify_peer(&mut stream, verify, stderr).await?;
+
+    // receive file offer from peer
+    let mut offer: FileOfferMsg =
+        read_from_async(&mut stream)
+            .await
+            .map_err(|err| -> Box<dyn std::error::Error> {
+                match err {
+                    gday_file_transfer::Error::IO(ref io_err)
+                        if io_err.kind() == std::io::ErrorKind::UnexpectedEof =>
+                    {
+                        std::io::Error::new(
+                            std::io::ErrorKind::UnexpectedEof,
+                            "Your mate disconnected before sending the offer.",
+                        )
+                        .into()
+                    }
+                    err => err.into(),
+                }
+            })?;
+    offer.adjust_for_clock_skew(&clock_skew);
+    Ok((stream, offer, fingerprint))
+}
+
+/// Responds to `offer` with the files chosen as set in `options`,
+/// and saves them in `path`.
+///
+/// If `accepted` is set, accepts the rest of its files instead,
+/// and otherwise sets it to the chosen files.
+///
+/// Records the transfer, whose connection has `fingerprint`, in `summary`.
+/// If the sender offers several streams, opens them as set in `more_streams`.
+#[allow(clippy::too_many_arguments)]
+async fn receive_offer(
+    ctx: &Context,
+    mut stream: EncryptedStream<PeerStream>,
+    mut offer: FileOfferMsg,
+    fingerprint: String,
+    path: &Path,
+    options: &ReceiveOptions,
+    accepted: &mut Option<Accepted>,
+    summary: &mut TransferSummary,
+    more_streams: MoreStreams<'_>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (limit, format) = (ctx.limit, ctx.format);
+    // rename files this file system can't hold, before the transfer fails on them.
+    // files whose names differ only in case would overwrite each other.
+    let path_policy = PathPolicy {
+        case_collisions: gday_file_transfer::is_case_insensitive(path)
+            .unwrap_or(cfg!(any(windows, target_os = "macos"))),
+        ..PathPolicy::default()
+    };
+    for (old, new) in offer.apply_path_policy(&path_policy)? {
+        say!(
+            "'{}' will be saved as '{}', so that it fits this file system.",
+            old.display(),
+            new.display()
+        );
+    }
+
+    gday_file_transfer::check_entries(&offer, options.extras)?;
+
+    // the sender may have renamed the folder since an interrupted transfer
+    for (old, new) in gday_file_transfer::relocate_partial_downloads(&offer, path)? {
+        say!(
+            "Found the partial download '{}', which will be resumed as '{}'.",
+            old.display(),
+            new.display()
+        );
+    }
+
+    let max_file_size = options.max_file_size;
+    let mut response = if let Some(accepted) = accepted {
+        accepted.accept_again(&offer, path, format)?
+    } else if let Some(policy) = options.policy {
+        dialog::auto_receive(&offer, path, policy, max_file_size, format)?
+    } else {
+        // the user may take a while, so keep showing the peer we're here,
+        // and notice if it disconnects meanwhile
+        let ask = {
+            let (offer, path) = (offer.clone(), path.to_path_buf());
+            dialog::spawn_prompt(move || ask_receive(&offer, &path, max_file_size, format))
+        };
+        let (mut reader, mut writer) = tokio::io::split(&mut stream);
+        gday_file_transfer::with_heartbeats_watching_peer(
+            &mut reader,
+            &mut writer,
+            HEARTBEAT_INTERVAL,
+            DEAD_PEER_TIMEOUT,
+            ask,
+        )
+        .await
+        .map_err(|err| -> Box<dyn std::error::Error> {
+            match err {
+                gday_file_transfer::Error::PeerTimedOut(_) => std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "Your mate disconnected while you were choosing files.",
+                )
+                .into(),
+                gday_file_transfer::Error::IO(ref io_err) if is_network_error(io_err) => {
+                    std::io::Error::new(
+                        io_err.kind(),
+                        "Your mate disconnected while you were choosing files.",
+                    )
+                    .into()
+                }
+                err => err.into(),
             }
+        })???
+    };
+    if accepted.is_none() {
+        *accepted = Some(Accepted::new(&offer, &response, path)?);
+    }
+    response.preserve_metadata = options.preserve;
+    response.write_manifest = options.manifest;
+    if options.delta {
+        response = response.request_deltas(&offer, path)?;
+    }
+    response = response.with_overwrite_policy(&offer, path, options.on_conflict)?;
+    response = response.copy_duplicates(&offer);
+    // archives can't be split across streams
+    response.streams = response
+        .streams
+        .filter(|&streams| (2..=MAX_STREAMS).contains(&streams) && !response.archive);
+    if !response.copies.is_empty() {
+        say!(
+            "{} duplicate file(s) will be copied instead of downloaded.",
+            response.copies.len()
+        );
+    }
+
+    // respond to the file offer
+    write_to_async(&response, &mut stream).await?;
+
+    if response.get_num_not_rejected() == 0 {
+        say!("No files will be downloaded.");
+        summary.outcome = Outcome::Cancelled;
+    } else {
+        summary.add_transfer(&offer, &response, fingerprint);
+        let start_at = options.start_at.map(|start_at| start_at.next());
+        schedule::wait_for_start(&mut stream, start_at, format).await?;
+        if let Some(num_streams) = response.streams {
+            let more = more_streams.open(ctx, &mut stream, num_streams - 1).await?;
+            let streams = std::iter::once(stream).chain(more).collect();
+            transfer::receive_files_multi(&offer, &response, path, streams, limit, format).await?;
+        } else {
+            transfer::receive_files(&offer, response, path, stream, limit, format).await?;
         }
+        gday_file_transfer::create_entries(&offer, path, options.extras)?;
     }
+    Ok(())
+}
 
+/// Connects to the mate with `peer_code` on the local network, without a server.
+///
+/// The peer that generated the code waits for its mate, and the other
+/// looks for it for at most [`LOCAL_TIMEOUT`].
+async fn connect_locally(
+    peer_code: &PeerCode,
+    is_creator: bool,
+) -> Result<(PeerStream, Secret<[u8; 32]>), gday_hole_punch::Error> {
+    let connect = gday_hole_punch::connect_on_local_network(
+        &peer_code.room_code,
+        &peer_code.shared_secret,
+        is_creator,
+    );
+    let (stream, shared_key) = if is_creator {
+        connect.await?
+    } else {
+        tokio::time::timeout(LOCAL_TIMEOUT, connect)
+            .await
+            .map_err(|_| gday_hole_punch::Error::LocalPeerNotFound)??
+    };
+    Ok((PeerStream::TCP(stream), shared_key))
+}
+
+/// Tells the user to have their mate run "gday `command` <code>" with `peer_code`,
+/// and shows it as a `gday://` link, and as a QR code if `qr`.
+/// Also copies the code to the clipboard if `copy`.
+fn show_code(
+    peer_code: &PeerCode,
+    command: &str,
+    qr: bool,
+    copy: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let code = String::try_from(peer_code)?;
+    let uri = peer_code.to_uri();
+    say!("Tell your mate to run \"gday {command} {}\"", code.bold());
+    say!("Or send them this link: {uri}");
+    if qr {
+        say!("Or have them scan this QR code:\n{}", qr::render(&code)?);
+    }
+    if copy {
+        // the code is shown anyway, so the transfer can go on without the clipboard
+        match clipboard::copy(&code) {
+            Ok(()) => say!("Copied the code to the clipboard."),
+            Err(err) => warn!("Couldn't copy the code to the clipboard: {err}"),
+        }
+    }
+    events::emit(&Event::CodeGenerated {
+        code: &code,
+        uri: &uri,
+    });
     Ok(())
 }
+
+/// How [`send_to_peer()`] connects to a receiver, and sends them the files.
+#[derive(Debug, Clone, Copy)]
+struct SendOptions {
+    /// Whether to create the room, instead of joining the receiver's
+    is_creator: bool,
+    /// When to connect through the server's relay
+    relay: RelayMode,
+    /// Whether to also show the code as a QR code
+    qr: bool,
+    /// Whether to also copy the code to the clipboard
+    copy: bool,
+    /// Whether to have the user confirm the verification code first
+    verify: bool,
+    /// When to start sending the files
+    start_at: Option<SystemTime>,
+    /// How fast to send the files at most
+    limit: Option<RateLimit>,
+    /// How to show progress
+    format: HumanFormat,
+}
+
+/// Offers the files from `source` to the peer that joins with `peer_code`
+/// with `offer_msg`, and sends the files they accept, as set in `options`.
+///
+/// Meets the peer through `server_connection`,
+/// or on the local network if it's `None`.
+/// If the peer accepts several streams, opens the others the same way.
+///
+/// Returns the peer's response and the connection's [`report::fingerprint()`],
+/// or `None` if `deadline` passes before a peer joins.
+async fn send_to_peer(
+    ctx: &Context,
+    server_connection: Option<ServerConnection>,
+    peer_code: &PeerCode,
+    source: &Source,
+    offer_msg: &FileOfferMsg,
+    options: SendOptions,
+    deadline: Option<tokio::time::Instant>,
+) -> Result<Option<(FileResponseMsg, String)>, Box<dyn std::error::Error>> {
+    let SendOptions {
+        is_creator,
+        relay,
+        qr,
+        copy,
+        verify,
+        start_at,
+        limit,
+        format,
+    } = options;
+    let show = ShowCode {
+        command: "get",
+        qr,
+        copy,
+    };
+    let more_streams = MoreStreams {
+        peer_code,
+        is_creator,
+        local: server_connection.is_none(),
+        relay,
+    };
+    let Some(connection) = connect_to_mate(
+        server_connection,
+        peer_code,
+        is_creator,
+        relay,
+        is_creator.then_some(show),
+        deadline,
+        None,
+    )
+    .await?
+    else {
+        return Ok(None);
+    };
+    let (mut stream, fingerprint) = open_encrypted_stream(connection).await?;
+
+    let clock_skew = gday_file_transfer::estimate_clock_skew(&mut stream, true).await?;
+    info!("Estimated clock skew with peer: {clock_skew:?}");
+
+    verify_peer(&mut stream, verify, false).await?;
+
+    // offer these files to the peer
+    write_to_async(offer_msg, &mut stream).await?;
+
+    say!("File offer sent to mate. Waiting on response.");
+
+    // receive response from peer, meanwhile showing it we're here,
+    // so it notices if we disconnect while it chooses files
+    let (mut reader, mut writer) = tokio::io::split(&mut stream);
+    let response = gday_file_transfer::read_from_async_timeout::<FileResponseMsg>(
+        &mut reader,
+        DEAD_PEER_TIMEOUT,
+    );
+    let response: FileResponseMsg =
+        gday_file_transfer::with_heartbeats(&mut writer, HEARTBEAT_INTERVAL, response)
+            .await
+            .and_then(|response| response)
+            .map_err(|err| -> Box<dyn std::error::Error> {
+                match err {
+                    gday_file_transfer::Error::PeerTimedOut(_) => std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        "Your mate disconnected before responding to the offer.",
+                    )
+                    .into(),
+                    gday_file_transfer::Error::IO(ref io_err)
+                        if io_err.kind() == std::io::ErrorKind::UnexpectedEof =>
+                    {
+                        std::io::Error::new(
+                            std::io::ErrorKind::UnexpectedEof,
+                            "Your mate disconnected before responding to the offer.",
+                        )
+                        .into()
+                    }
+                    err => err.into(),
+                }
+            })?;
+
+    // the mate may decline several streams, but can't ask for others
+    if response.streams.is_some() && response.streams != offer_msg.streams {
+        return Err("Your mate asked for a different number of streams than offered.".into());
+    }
+
+    // Total number of files accepted
+    let num_accepted = response.get_num_not_rejected();
+
+    // How many of those files are being resumed
+    let resumptions = response.get_num_partially_accepted();
+
+    say!(
+        "Your mate accepted {}/{} files",
+        num_accepted,
+        offer_msg.files.len()
+    );
+
+    if resumptions != 0 {
+        say!("Resuming transfer of {resumptions} previously interrupted file(s).");
+    }
+
+    if num_accepted != 0 {
+        schedule::wait_for_start(&mut stream, start_at, format).await?;
+        match (source, response.streams) {
+            (Source::Files(local_files), Some(num_streams)) => {
+                let more = more_streams.open(ctx, &mut stream, num_streams - 1).await?;
+                let streams = std::iter::once(stream).chain(more).collect();
+                transfer::send_files_multi(local_files, &response, streams, limit, format).await?;
+            }
+            (Source::Files(local_files), None) => {
+                transfer::send_files(
+                    local_files.clone(),
+                    response.clone(),
+                    &mut stream,
+                    limit,
+                    format,
+                )
+                .await?;
+            }
+            (Source::Stdin, _) => {
+                transfer::send_from_stdin(offer_msg, &response, &mut stream, limit, format).await?;
+            }
+        }
+    }
+
+    Ok(Some((response, fingerprint)))
+}
+
+/// How to open more connections to the mate, for a transfer over several streams.
+#[derive(Clone, Copy)]
+struct MoreStreams<'a> {
+    /// The code of the first connection, from which the others' are derived
+    peer_code: &'a PeerCode,
+    /// Whether this peer created the room of the first connection,
+    /// and so creates the others' too
+    is_creator: bool,
+    /// Whether to connect on the local network, instead of through a server
+    local: bool,
+    /// When to connect through the server's relay
+    relay: RelayMode,
+}
+
+impl MoreStreams<'_> {
+    /// Opens `n` more encrypted connections to the mate, one after another,
+    /// each in a room whose code is derived from [`Self::peer_code`].
+    ///
+    /// The peers tell each other when each room is open over the `first` connection.
+    async fn open(
+        &self,
+        ctx: &Context,
+        first: &mut EncryptedStream<PeerStream>,
+        n: u32,
+    ) -> Result<Vec<EncryptedStream<PeerStream>>, Box<dyn std::error::Error>> {
+        let mut streams = Vec::new();
+        for i in 1..=n {
+            let peer_code = PeerCode {
+                server_id: self.peer_code.server_id,
+                room_code: Secret::new(format!(
+                    "{}/stream{i}",
+                    self.peer_code.room_code.expose_secret()
+                )),
+                shared_secret: Secret::new(self.peer_code.shared_secret.expose_secret().clone()),
+            };
+            let server_connection = if self.local {
+                None
+            } else {
+                Some(ctx.server(peer_code.server_id).await?)
+            };
+            let deadline = tokio::time::Instant::now() + STREAM_TIMEOUT;
+            let connection = connect_to_mate(
+                server_connection,
+                &peer_code,
+                self.is_creator,
+                self.relay,
+                None,
+                Some(deadline),
+                Some(&mut *first),
+            )
+            .await?;
+            let Some((stream, shared_key)) = connection else {
+                return Err(format!(
+                    "Your mate didn't join connection {} of {} in time.",
+                    i + 1,
+                    n + 1
+                )
+                .into());
+            };
+            let stream =
+                EncryptedStream::encrypt_connection(stream, shared_key.expose_secret()).await?;
+            streams.push(stream);
+        }
+        info!("Opened {n} more connections to your mate.");
+        Ok(streams)
+    }
+}
+
+/// Shows the verification code of `stream`, for the user to compare
+/// with their mate's, on standard error if `stderr`.
+///
+/// If `verify`, also asks the user whether the codes match,
+/// and returns an error if they don't.
+async fn verify_peer(
+    stream: &mut EncryptedStream<PeerStream>,
+    verify: bool,
+    stderr: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let code = stream.fingerprint();
+    if stderr {
+        eprintln!("Verification code: {code}");
+    } else {
+        say!("Verification code: {}", code.bold());
+    }
+    if !verify {
+        return Ok(());
+    }
+
+    // the user may take a while, so keep showing the peer we're here
+    let ask = dialog::spawn_prompt(dialog::confirm_verification_code);
+    if gday_file_transfer::with_heartbeats(stream, HEARTBEAT_INTERVAL, ask).await??? {
+        Ok(())
+    } else {
+        Err("Your verification codes don't match, \
+            so someone else may have connected in place of your mate. \
+            Closed the connection."
+            .into())
+    }
+}
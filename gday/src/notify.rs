@@ -0,0 +1,130 @@
+//! Tells the user that a run of gday ended, with a desktop notification
+//! or a command of their choice, so long transfers needn't be watched.
+use crate::report::{Outcome, TransferSummary};
+use gday_file_transfer::HumanFormat;
+use std::process::{Command, Stdio};
+
+/// Shows a desktop notification saying how the run in `summary` ended,
+/// with sizes shown with `format`.
+///
+/// Uses `notify-send` on Linux and the BSDs, `osascript` on macOS,
+/// and PowerShell on Windows. Doesn't wait for the notification to close.
+pub fn desktop(summary: &TransferSummary, format: HumanFormat) -> std::io::Result<()> {
+    let message = message(summary, format);
+    let mut command = if cfg!(target_os = "macos") {
+        let mut command = Command::new("osascript");
+        // the message is passed as an argument, so it needn't be escaped
+        command.args([
+            "-e",
+            "on run argv",
+            "-e",
+            "display notification (item 1 of argv) with title \"gday\"",
+            "-e",
+            "end run",
+            &message,
+        ]);
+        command
+    } else if cfg!(windows) {
+        let mut command = Command::new("powershell");
+        command
+            .args([
+                "-NoProfile",
+                "-Command",
+                "Add-Type -AssemblyName System.Windows.Forms; \
+                $icon = New-Object System.Windows.Forms.NotifyIcon; \
+                $icon.Icon = [System.Drawing.SystemIcons]::Information; \
+                $icon.Visible = $true; \
+                $icon.ShowBalloonTip(10000, 'gday', $env:GDAY_MESSAGE, 'Info'); \
+                Start-Sleep -Seconds 10; \
+                $icon.Dispose()",
+            ])
+            .env("GDAY_MESSAGE", &message);
+        command
+    } else {
+        let mut command = Command::new("notify-send");
+        command.args(["--app-name=gday", "gday", &message]);
+        command
+    };
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|err| {
+            let program = command.get_program().to_string_lossy();
+            std::io::Error::new(err.kind(), format!("{program}: {err}"))
+        })?;
+    Ok(())
+}
+
+/// Runs `hook` with the system shell, and waits for it to finish.
+///
+/// Describes the run in `summary` with these environment variables:
+/// - `GDAY_COMMAND`: the subcommand, such as "send"
+/// - `GDAY_OUTCOME`: how the run ended, such as "completed" or "failed"
+/// - `GDAY_EXIT_CODE`: the exit code gday will exit with
+/// - `GDAY_FILES`: number of files accepted by peers
+/// - `GDAY_BYTES`: number of bytes in those files, not counting resumed parts
+/// - `GDAY_DURATION_SECS`: how many seconds the run took
+/// - `GDAY_ERROR`: the error that ended the run, if any
+/// - `GDAY_SUMMARY`: all of the above, as the JSON posted to `--report-url`
+pub fn run_hook(hook: &str, summary: &TransferSummary) -> Result<(), Box<dyn std::error::Error>> {
+    let mut command = if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.arg("/C").arg(hook);
+        command
+    } else {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(hook);
+        command
+    };
+
+    let outcome = serde_json::to_value(summary.outcome)?;
+    command
+        .env("GDAY_COMMAND", summary.command)
+        .env("GDAY_OUTCOME", outcome.as_str().unwrap_or_default())
+        .env("GDAY_EXIT_CODE", summary.outcome.exit_code().to_string())
+        .env("GDAY_FILES", summary.files.len().to_string())
+        .env("GDAY_BYTES", summary.bytes.to_string())
+        .env(
+            "GDAY_DURATION_SECS",
+            format!("{:.3}", summary.duration_secs),
+        )
+        .env("GDAY_SUMMARY", serde_json::to_string(summary)?)
+        .stdin(Stdio::null());
+    if let Some(error) = &summary.error {
+        command.env("GDAY_ERROR", error);
+    }
+
+    let status = command.status()?;
+    if !status.success() {
+        return Err(format!("'{hook}' failed with {status}.").into());
+    }
+    Ok(())
+}
+
+/// Returns a sentence saying how the run in `summary` ended,
+/// with sizes shown with `format`.
+fn message(summary: &TransferSummary, format: HumanFormat) -> String {
+    let files = summary.files.len();
+    let size = format.size(summary.bytes);
+    match summary.outcome {
+        Outcome::Completed if summary.command == "send" => {
+            format!("Sent {files} files ({size}).")
+        }
+        Outcome::Completed if matches!(summary.command, "get" | "resume") => {
+            format!("Received {files} files ({size}).")
+        }
+        Outcome::Completed => format!("Finished \"gday {}\".", summary.command),
+        Outcome::Cancelled => "Cancelled.".to_string(),
+        Outcome::Rejected => "Your mate rejected all the files.".to_string(),
+        Outcome::PartiallyFailed => format!(
+            "Transferred {files} files ({size}), but transfers with {} mate(s) failed.",
+            summary.failed_peers
+        ),
+        Outcome::HolePunchTimedOut | Outcome::Failed => format!(
+            "Failed: {}",
+            summary.error.as_deref().unwrap_or("every transfer failed.")
+        ),
+    }
+}
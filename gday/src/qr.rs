@@ -0,0 +1,445 @@
+//! Draws QR codes in the terminal, so that the receiver
+//! can scan a code with their phone instead of typing it.
+//!
+//! Only encodes what gday needs: short texts in byte mode,
+//! with low error correction, in QR versions 1 to 5.
+use owo_colors::OwoColorize;
+
+/// For each supported version: number of data codewords, number of
+/// error correction codewords, and the position of its alignment pattern.
+///
+/// All of these versions use a single block at error correction level L.
+const VERSIONS: [(usize, usize, Option<usize>); 5] = [
+    (19, 7, None),
+    (34, 10, Some(18)),
+    (55, 15, Some(22)),
+    (80, 20, Some(26)),
+    (108, 26, Some(30)),
+];
+
+/// Number of light modules around the code, which scanners need to find it.
+const QUIET_ZONE: usize = 4;
+
+/// Returns `text` drawn as a QR code, to be printed in a terminal.
+///
+/// Fails if `text` is too long for the supported versions.
+pub fn render(text: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let code = QrCode::encode(text.as_bytes())?;
+
+    // each line of text holds two rows of modules
+    let is_dark = |x: usize, y: usize| {
+        let inside = QUIET_ZONE..QUIET_ZONE + code.size;
+        inside.contains(&x) && inside.contains(&y) && code.get(x - QUIET_ZONE, y - QUIET_ZONE)
+    };
+    let width = code.size + 2 * QUIET_ZONE;
+    let mut rendered = String::new();
+    for y in (0..width).step_by(2) {
+        let line: String = (0..width)
+            .map(|x| match (is_dark(x, y), is_dark(x, y + 1)) {
+                (true, true) => '█',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (false, false) => ' ',
+            })
+            .collect();
+        // explicit colors, so the code isn't inverted on dark terminals
+        rendered.push_str(&line.black().on_white().to_string());
+        rendered.push('\n');
+    }
+    Ok(rendered)
+}
+
+/// The modules of a QR code.
+struct QrCode {
+    /// Number of modules along each side
+    size: usize,
+    /// Whether each module is dark, row by row
+    modules: Vec<bool>,
+    /// Whether each module is part of a function pattern,
+    /// instead of holding data
+    is_function: Vec<bool>,
+}
+
+impl QrCode {
+    /// Encodes `data` in the smallest supported version that fits it.
+    fn encode(data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        let code = Self::unmasked(data)?;
+
+        // use the mask that leaves the fewest patterns that confuse scanners
+        let mask = (0..8)
+            .min_by_key(|&mask| code.masked(mask).penalty())
+            .expect("unreachable: there are 8 masks");
+        Ok(code.masked(mask))
+    }
+
+    /// Returns `data` encoded in the smallest supported version that fits it,
+    /// but not yet masked.
+    fn unmasked(data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        // 4 bits of mode and 8 bits of length precede the data
+        let (version, &(data_len, ec_len, alignment)) = VERSIONS
+            .iter()
+            .enumerate()
+            .find(|(_, (data_len, _, _))| data.len() + 2 <= *data_len)
+            .ok_or_else(|| format!("'{}' is too long for a QR code.", data.escape_ascii()))?;
+
+        let mut codewords = data_codewords(data, data_len);
+        codewords.extend(reed_solomon(&codewords, ec_len));
+
+        let size = 21 + 4 * version;
+        let mut code = Self {
+            size,
+            modules: vec![false; size * size],
+            is_function: vec![false; size * size],
+        };
+        code.draw_function_patterns(alignment, 0);
+        code.draw_codewords(&codewords);
+        Ok(code)
+    }
+
+    /// Returns a copy of this unmasked code, masked with `mask`.
+    fn masked(&self, mask: u8) -> Self {
+        let mut masked = Self {
+            size: self.size,
+            modules: self.modules.clone(),
+            is_function: self.is_function.clone(),
+        };
+        masked.apply_mask(mask);
+        masked.draw_format_bits(mask);
+        masked
+    }
+
+    /// Returns whether the module in column `x` and row `y` is dark.
+    fn get(&self, x: usize, y: usize) -> bool {
+        self.modules[y * self.size + x]
+    }
+
+    /// Colors the module in column `x` and row `y`, and marks it as a function pattern.
+    fn set_function(&mut self, x: usize, y: usize, dark: bool) {
+        self.modules[y * self.size + x] = dark;
+        self.is_function[y * self.size + x] = true;
+    }
+
+    /// Draws the finder, timing, and alignment patterns,
+    /// and reserves the modules of the format bits, drawn with `mask`.
+    fn draw_function_patterns(&mut self, alignment: Option<usize>, mask: u8) {
+        let size = self.size;
+        for i in 0..size {
+            self.set_function(6, i, i % 2 == 0);
+            self.set_function(i, 6, i % 2 == 0);
+        }
+
+        // finder patterns in three corners, with their light separators
+        for (cx, cy) in [(3, 3), (size - 4, 3), (3, size - 4)] {
+            for dy in -4_isize..=4 {
+                for dx in -4_isize..=4 {
+                    let (x, y) = (cx as isize + dx, cy as isize + dy);
+                    if (0..size as isize).contains(&x) && (0..size as isize).contains(&y) {
+                        let distance = dx.abs().max(dy.abs());
+                        self.set_function(x as usize, y as usize, distance != 2 && distance != 4);
+                    }
+                }
+            }
+        }
+
+        // the other alignment positions of these versions overlap finder patterns
+        if let Some(center) = alignment {
+            for dy in -2_isize..=2 {
+                for dx in -2_isize..=2 {
+                    let (x, y) = (center as isize + dx, center as isize + dy);
+                    self.set_function(x as usize, y as usize, dx.abs().max(dy.abs()) != 1);
+                }
+            }
+        }
+
+        self.draw_format_bits(mask);
+    }
+
+    /// Draws the error correction level and `mask` in both copies of the format bits.
+    fn draw_format_bits(&mut self, mask: u8) {
+        let bits = format_bits(mask);
+        let bit = |i: usize| (bits >> i) & 1 != 0;
+
+        let size = self.size;
+        for i in 0..6 {
+            self.set_function(8, i, bit(i));
+        }
+        self.set_function(8, 7, bit(6));
+        self.set_function(8, 8, bit(7));
+        self.set_function(7, 8, bit(8));
+        for i in 9..15 {
+            self.set_function(14 - i, 8, bit(i));
+        }
+
+        for i in 0..8 {
+            self.set_function(size - 1 - i, 8, bit(i));
+        }
+        for i in 8..15 {
+            self.set_function(8, size - 15 + i, bit(i));
+        }
+        // always dark
+        self.set_function(8, size - 8, true);
+    }
+
+    /// Draws `codewords` in the zigzag order of the standard,
+    /// in the modules that aren't function patterns.
+    fn draw_codewords(&mut self, codewords: &[u8]) {
+        let size = self.size;
+        let mut i = 0;
+        let mut right = size - 1;
+        loop {
+            // skip the vertical timing pattern
+            if right == 6 {
+                right = 5;
+            }
+            let upward = (right + 1) & 2 == 0;
+            for vert in 0..size {
+                let y = if upward { size - 1 - vert } else { vert };
+                for x in [right, right - 1] {
+                    if !self.is_function[y * size + x] && i < codewords.len() * 8 {
+                        self.modules[y * size + x] = (codewords[i / 8] >> (7 - i % 8)) & 1 != 0;
+                        i += 1;
+                    }
+                    // leftover remainder bits stay light
+                }
+            }
+            if right < 2 {
+                break;
+            }
+            right -= 2;
+        }
+    }
+
+    /// Flips the data modules selected by `mask`.
+    fn apply_mask(&mut self, mask: u8) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let flip = match mask {
+                    0 => (x + y) % 2 == 0,
+                    1 => y % 2 == 0,
+                    2 => x % 3 == 0,
+                    3 => (x + y) % 3 == 0,
+                    4 => (x / 3 + y / 2) % 2 == 0,
+                    5 => x * y % 2 + x * y % 3 == 0,
+                    6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                    _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+                };
+                let index = y * self.size + x;
+                if flip && !self.is_function[index] {
+                    self.modules[index] = !self.modules[index];
+                }
+            }
+        }
+    }
+
+    /// Scores how hard this code is to scan, as defined by the standard.
+    fn penalty(&self) -> usize {
+        let size = self.size;
+        let rows = (0..size).map(|y| (0..size).map(|x| self.get(x, y)).collect::<Vec<_>>());
+        let columns = (0..size).map(|x| (0..size).map(|y| self.get(x, y)).collect::<Vec<_>>());
+        let finder_like = [
+            true, false, true, true, true, false, true, false, false, false, false,
+        ];
+
+        let mut penalty = 0;
+        for line in rows.chain(columns) {
+            // runs of 5 or more modules of the same color
+            for run in line.chunk_by(|a, b| a == b) {
+                if run.len() >= 5 {
+                    penalty += run.len() - 2;
+                }
+            }
+            // patterns that look like finder patterns
+            for window in line.windows(finder_like.len()) {
+                if window == finder_like || window.iter().rev().eq(finder_like.iter()) {
+                    penalty += 40;
+                }
+            }
+        }
+
+        // 2x2 blocks of the same color
+        for y in 0..size - 1 {
+            for x in 0..size - 1 {
+                let color = self.get(x, y);
+                if self.get(x + 1, y) == color
+                    && self.get(x, y + 1) == color
+                    && self.get(x + 1, y + 1) == color
+                {
+                    penalty += 3;
+                }
+            }
+        }
+
+        // too many or too few dark modules
+        let dark = self.modules.iter().filter(|&&dark| dark).count();
+        let total = size * size;
+        penalty
+            + (dark * 20)
+                .abs_diff(total * 10)
+                .div_ceil(total)
+                .saturating_sub(1)
+                * 10
+    }
+}
+
+/// Returns the 15 format bits that hold error correction level L and `mask`,
+/// with their BCH error correction.
+fn format_bits(mask: u8) -> u32 {
+    // error correction level L is 0b01
+    let data = 0b01 << 3 | u32::from(mask);
+    let mut remainder = data;
+    for _ in 0..10 {
+        remainder = (remainder << 1) ^ ((remainder >> 9) * 0x537);
+    }
+    (data << 10 | remainder) ^ 0x5412
+}
+
+/// Returns `data` in byte mode, padded to `len` codewords.
+fn data_codewords(data: &[u8], len: usize) -> Vec<u8> {
+    let len_byte = u8::try_from(data.len()).expect("unreachable: data fits in version 5");
+
+    // the first byte holds the byte mode indicator 0b0100, and half the length
+    let mut codewords = vec![0b0100 << 4 | len_byte >> 4];
+    let mut previous = len_byte;
+    for &byte in data {
+        codewords.push(previous << 4 | byte >> 4);
+        previous = byte;
+    }
+    // the last half byte is followed by a terminator of zeros
+    codewords.push(previous << 4);
+
+    for padding in [0xEC, 0x11].into_iter().cycle() {
+        if codewords.len() >= len {
+            break;
+        }
+        codewords.push(padding);
+    }
+    codewords
+}
+
+/// Returns the `ec_len` Reed-Solomon error correction codewords of `data`.
+fn reed_solomon(data: &[u8], ec_len: usize) -> Vec<u8> {
+    // the generator polynomial (x - 2^0)(x - 2^1)...(x - 2^(ec_len - 1)),
+    // without its leading coefficient of 1
+    let mut generator = vec![0; ec_len];
+    generator[ec_len - 1] = 1;
+    let mut root = 1;
+    for _ in 0..ec_len {
+        for j in 0..ec_len {
+            generator[j] = gf_multiply(generator[j], root);
+            if j + 1 < ec_len {
+                generator[j] ^= generator[j + 1];
+            }
+        }
+        root = gf_multiply(root, 2);
+    }
+
+    // remainder of dividing `data` by the generator
+    let mut remainder = vec![0; ec_len];
+    for &byte in data {
+        let factor = byte ^ remainder[0];
+        remainder.remove(0);
+        remainder.push(0);
+        for (r, &g) in remainder.iter_mut().zip(&generator) {
+            *r ^= gf_multiply(g, factor);
+        }
+    }
+    remainder
+}
+
+/// Multiplies `a` and `b` in the Galois field GF(2^8) used by QR codes.
+fn gf_multiply(a: u8, b: u8) -> u8 {
+    let mut product: u16 = 0;
+    for i in (0..8).rev() {
+        product = (product << 1) ^ ((product >> 7) * 0x11D);
+        product ^= ((u16::from(b) >> i) & 1) * u16::from(a);
+    }
+    product as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_bits, reed_solomon, QrCode};
+
+    /// The error correction codewords of the examples
+    /// in the standard and at thonky.com, both at version 1-M.
+    #[test]
+    fn test_reed_solomon() {
+        // "01234567" in numeric mode
+        let data = [
+            16, 32, 12, 86, 97, 128, 236, 17, 236, 17, 236, 17, 236, 17, 236, 17,
+        ];
+        assert_eq!(
+            reed_solomon(&data, 10),
+            [165, 36, 212, 193, 237, 54, 199, 135, 44, 85]
+        );
+
+        // "HELLO WORLD" in alphanumeric mode
+        let data = [
+            32, 91, 11, 120, 209, 114, 220, 77, 67, 64, 236, 17, 236, 17, 236, 17,
+        ];
+        assert_eq!(
+            reed_solomon(&data, 10),
+            [196, 35, 39, 119, 235, 215, 231, 226, 93, 23]
+        );
+    }
+
+    /// The format bits of level L with each mask, as listed in the standard.
+    #[test]
+    fn test_format_bits() {
+        let expected = [
+            0b111011111000100,
+            0b111001011110011,
+            0b111110110101010,
+            0b111100010011101,
+            0b110011000101111,
+            0b110001100011000,
+            0b110110001000001,
+            0b110100101110110,
+        ];
+        for (mask, expected) in (0..8).zip(expected) {
+            assert_eq!(format_bits(mask), expected, "mask {mask}");
+        }
+    }
+
+    /// A version 2 code with mask 0, as drawn by Kazuhiko Arase's
+    /// reference encoder at level L.
+    #[test]
+    fn test_modules() {
+        let code = QrCode::unmasked(b"gday://12/abcd#efghi").unwrap().masked(0);
+        let expected = [
+            "#######...#...##..#######",
+            "#.....#..#....##..#.....#",
+            "#.###.#.#..#...#..#.###.#",
+            "#.###.#..#..#.##..#.###.#",
+            "#.###.#..#..####..#.###.#",
+            "#.....#...####.##.#.....#",
+            "#######.#.#.#.#.#.#######",
+            "........#..#.#.##........",
+            "###.#####...##..###...#..",
+            "##.#....##.##...####.#.##",
+            "..#######.###.#..#...####",
+            ".#...#.####.###...##...#.",
+            ".###..###.##..#.####.#.##",
+            ".#.##..##.##.....###.####",
+            "#..######.#..#..#.#######",
+            ".#..#..###.#...##..#.#..#",
+            "#.##..###...#...######.##",
+            "........######.##...#####",
+            "#######.#..##.###.#.#..##",
+            "#.....#.#...###.#...##.#.",
+            "#.###.#.#..#..#.#####..##",
+            "#.###.#..#.#..#.#...#.#..",
+            "#.###.#.#.#..#..#...##..#",
+            "#.....#.#..#...###..##.#.",
+            "#######.#.#.#..##..#...##",
+        ];
+        let rows: Vec<String> = (0..code.size)
+            .map(|y| {
+                (0..code.size)
+                    .map(|x| if code.get(x, y) { '#' } else { '.' })
+                    .collect()
+            })
+            .collect();
+        assert_eq!(rows, expected);
+    }
+}
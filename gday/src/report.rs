@@ -0,0 +1,229 @@
+//! Summarizes what a run of gday did, so that automated
+//! transfers can be monitored with `--report-url` or `--json`.
+use gday_file_transfer::{FileOfferMsg, FileResponseMsg};
+use gday_hole_punch::Secret;
+use serde::Serialize;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Summary of a run of gday, in the JSON posted to `--report-url`.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct TransferSummary {
+    /// The subcommand that was run, such as "send" or "get"
+    pub command: &'static str,
+    /// How the run ended
+    pub outcome: Outcome,
+    /// The error that ended the run, if it failed
+    pub error: Option<String>,
+    /// The files that peers accepted
+    pub files: Vec<FileSummary>,
+    /// Number of offered files that peers rejected
+    pub skipped: usize,
+    /// Number of peers whose transfer failed, in a session with several
+    pub failed_peers: usize,
+    /// Total number of bytes to transfer in the accepted files,
+    /// not counting resumed parts
+    pub bytes: u64,
+    /// How many seconds the run took
+    pub duration_secs: f64,
+    /// [`fingerprint()`] of each peer connection
+    pub peer_fingerprints: Vec<String>,
+}
+
+/// An accepted file in a [`TransferSummary`].
+#[derive(Serialize, Debug, Clone)]
+pub struct FileSummary {
+    /// The file's path, as offered
+    pub path: PathBuf,
+    /// Number of bytes to transfer, not counting resumed parts
+    pub bytes: u64,
+    /// Whether an interrupted transfer of the file was resumed
+    pub resumed: bool,
+}
+
+/// How a run of gday ended.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// Finished successfully
+    #[default]
+    Completed,
+    /// The user declined to send or receive anything
+    Cancelled,
+    /// Every peer rejected all the offered files
+    Rejected,
+    /// Some peers' transfers failed, while others succeeded
+    PartiallyFailed,
+    /// Couldn't connect to the peer through their firewall or NAT in time
+    HolePunchTimedOut,
+    /// Ended with another error
+    Failed,
+}
+
+impl Outcome {
+    /// Returns the exit code of a run that ended this way,
+    /// so that scripts can tell the outcomes apart.
+    ///
+    /// Skips 2, which means the command line arguments were invalid.
+    pub fn exit_code(self) -> u8 {
+        match self {
+            Self::Completed | Self::Cancelled => 0,
+            Self::Failed => 1,
+            Self::Rejected => 3,
+            Self::PartiallyFailed => 4,
+            Self::HolePunchTimedOut => 5,
+        }
+    }
+}
+
+impl TransferSummary {
+    /// Adds the files accepted in `response` to `offer`
+    /// by the peer with `fingerprint`.
+    pub fn add_transfer(
+        &mut self,
+        offer: &FileOfferMsg,
+        response: &FileResponseMsg,
+        fingerprint: String,
+    ) {
+        for (file, start) in offer.files.iter().zip(&response.response) {
+            let Some(start) = start else {
+                self.skipped += 1;
+                continue;
+            };
+            let bytes = file.len.saturating_sub(*start);
+            self.bytes += bytes;
+            self.files.push(FileSummary {
+                path: file.short_path.clone(),
+                bytes,
+                resumed: *start != 0,
+            });
+        }
+        self.peer_fingerprints.push(fingerprint);
+    }
+
+    /// Records that the run ended with `result` after `duration`.
+    pub fn finish(&mut self, result: &Result<(), Box<dyn std::error::Error>>, duration: Duration) {
+        self.duration_secs = duration.as_secs_f64();
+        if let Err(err) = result {
+            self.outcome = if matches!(
+                err.downcast_ref(),
+                Some(gday_hole_punch::Error::HolePunchTimeout)
+            ) {
+                Outcome::HolePunchTimedOut
+            } else {
+                Outcome::Failed
+            };
+            self.error = Some(err.to_string());
+        } else if self.failed_peers != 0 {
+            self.outcome = if self.peer_fingerprints.is_empty() {
+                Outcome::Failed
+            } else {
+                Outcome::PartiallyFailed
+            };
+        } else if self.command == "send"
+            && !self.peer_fingerprints.is_empty()
+            && self.files.is_empty()
+            && self.skipped != 0
+        {
+            self.outcome = Outcome::Rejected;
+        }
+    }
+
+    /// Returns the number of accepted files that were resumed.
+    pub fn num_resumed(&self) -> usize {
+        self.files.iter().filter(|file| file.resumed).count()
+    }
+}
+
+/// Returns a short fingerprint of the connection secured with `shared_key`,
+/// such as "3fa0-91c2-77d4-0b1e".
+///
+/// Both peers get the same fingerprint, without revealing the key.
+pub fn fingerprint(shared_key: &Secret<[u8; 32]>) -> String {
+    let hash = blake3::derive_key("gday peer fingerprint", shared_key.expose_secret());
+    hash[..8]
+        .chunks(2)
+        .map(|pair| format!("{:02x}{:02x}", pair[0], pair[1]))
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// How long to try posting a report before giving up.
+#[cfg(feature = "report-url")]
+const REPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// POSTs `summary` as JSON to `url`, which starts with "http://" or "https://".
+///
+/// Returns an error if the server doesn't respond with a 2xx status.
+#[cfg(feature = "report-url")]
+pub async fn post(url: &str, summary: &TransferSummary) -> Result<(), Box<dyn std::error::Error>> {
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+    async fn exchange(
+        mut stream: impl AsyncRead + AsyncWrite + Unpin,
+        request: &[u8],
+    ) -> std::io::Result<Vec<u8>> {
+        stream.write_all(request).await?;
+        stream.flush().await?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+        Ok(response)
+    }
+
+    let (is_tls, rest) = if let Some(rest) = url.strip_prefix("https://") {
+        (true, rest)
+    } else if let Some(rest) = url.strip_prefix("http://") {
+        (false, rest)
+    } else {
+        return Err(format!("Report URL '{url}' doesn't start with http:// or https://").into());
+    };
+    let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    let path = if path.is_empty() { "/" } else { path };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => (host, port.parse()?),
+        _ => (authority, if is_tls { 443 } else { 80 }),
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+
+    let body = serde_json::to_vec(summary)?;
+    let mut request = format!(
+        "POST {path} HTTP/1.1\r\n\
+        Host: {authority}\r\n\
+        User-Agent: gday/{}\r\n\
+        Content-Type: application/json\r\n\
+        Content-Length: {}\r\n\
+        Connection: close\r\n\r\n",
+        env!("CARGO_PKG_VERSION"),
+        body.len()
+    )
+    .into_bytes();
+    request.extend_from_slice(&body);
+
+    let response = tokio::time::timeout(REPORT_TIMEOUT, async {
+        let tcp = tokio::net::TcpStream::connect((host, port)).await?;
+        if is_tls {
+            let root_store = tokio_rustls::rustls::RootCertStore::from_iter(
+                webpki_roots::TLS_SERVER_ROOTS.iter().cloned(),
+            );
+            let config = tokio_rustls::rustls::ClientConfig::builder()
+                .with_root_certificates(root_store)
+                .with_no_client_auth();
+            let name = tokio_rustls::rustls::pki_types::ServerName::try_from(host.to_string())
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+            let connector = tokio_rustls::TlsConnector::from(std::sync::Arc::new(config));
+            exchange(connector.connect(name, tcp).await?, &request).await
+        } else {
+            exchange(tcp, &request).await
+        }
+    })
+    .await
+    .map_err(|_| "Timed out posting the report.")??;
+
+    // such as "HTTP/1.1 200 OK"
+    let status_line = response.split(|&b| b == b'\r').next().unwrap_or_default();
+    let status_line = String::from_utf8_lossy(status_line);
+    match status_line.split(' ').nth(1) {
+        Some(status) if status.starts_with('2') => Ok(()),
+        _ => Err(format!("Report server responded with '{status_line}'.").into()),
+    }
+}
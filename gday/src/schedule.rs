@@ -0,0 +1,77 @@
+//! Delays the start of a transfer until a chosen time of day,
+//! such as off-peak hours.
+use chrono::{Local, NaiveTime};
+use gday_encryption::EncryptedStream;
+use gday_file_transfer::HumanFormat;
+use indicatif::{ProgressBar, ProgressStyle};
+use std::{str::FromStr, time::SystemTime};
+
+/// A local time of day to start a transfer at, such as "22:00".
+#[derive(Debug, Clone, Copy)]
+pub struct StartAt(NaiveTime);
+
+impl FromStr for StartAt {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        NaiveTime::parse_from_str(s, "%H:%M")
+            .or_else(|_| NaiveTime::parse_from_str(s, "%H:%M:%S"))
+            .map(Self)
+            .map_err(|_| format!("'{s}' isn't a time of day like \"22:00\""))
+    }
+}
+
+impl StartAt {
+    /// Returns the next time this time of day comes around.
+    pub fn next(&self) -> SystemTime {
+        let now = Local::now();
+        let mut day = now.date_naive();
+        loop {
+            // a time may not exist on a day when clocks jump forward
+            if let Some(time) = day.and_time(self.0).and_local_timezone(Local).earliest() {
+                if time > now {
+                    return time.into();
+                }
+            }
+            day = day.succ_opt().expect("Ran out of days.");
+        }
+    }
+}
+
+/// Waits until it's time to start the transfer, which is the later of
+/// `start` and the time chosen by the peer, printing a countdown with `format`.
+///
+/// Must be called right before both peers begin the transfer.
+pub async fn wait_for_start(
+    stream: &mut EncryptedStream<crate::PeerStream>,
+    start: Option<SystemTime>,
+    format: HumanFormat,
+) -> Result<(), gday_file_transfer::Error> {
+    let delay = start
+        .and_then(|start| start.duration_since(SystemTime::now()).ok())
+        .unwrap_or_default();
+
+    let style = ProgressStyle::with_template("{spinner} {msg}")
+        .expect("Progress bar style string was invalid.");
+    let spinner = ProgressBar::hidden().with_style(style);
+
+    gday_file_transfer::wait_for_start(stream, delay, |remaining| {
+        if remaining.is_zero() {
+            return;
+        }
+        if spinner.is_hidden() {
+            spinner.set_draw_target(indicatif::ProgressDrawTarget::stderr());
+        }
+        spinner.set_message(format!(
+            "Transfer starts in {}. Keep this running.",
+            format.duration(remaining)
+        ));
+        spinner.tick();
+    })
+    .await?;
+
+    if !spinner.is_hidden() {
+        spinner.finish_with_message("Starting transfer.");
+    }
+    Ok(())
+}
@@ -1,85 +1,539 @@
+use crate::events::{say, ProgressEvents};
 use gday_encryption::EncryptedStream;
-use gday_file_transfer::{FileMetaLocal, FileOfferMsg, FileResponseMsg, TransferReport};
-use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use gday_file_transfer::{
+    read_from_async, write_cancel_async, CancelReason, CancelToken, FileMetaLocal, FileOfferMsg,
+    FileResponseMsg, HumanFormat, RateLimit, RateLimited, StorageFullAction, StreamReport,
+    TransferReport,
+};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressState, ProgressStyle};
+use tokio::io::AsyncReadExt;
 
-/// Sequentially write the given files to this `writer`.
+/// How long to keep the connection open after sending
+/// a cancellation, so the peer has time to read it.
+const CANCEL_LINGER: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Sequentially write the given files to this `stream`.
+///
+/// Meanwhile, listens for the peer cancelling the transfer,
+/// and tells the peer if the user cancels it.
+/// Sends at most `limit` bytes per second, if set.
 pub async fn send_files(
     offer: Vec<FileMetaLocal>,
     response: FileResponseMsg,
-    writer: &mut EncryptedStream<tokio::net::TcpStream>,
+    stream: &mut EncryptedStream<crate::PeerStream>,
+    limit: Option<RateLimit>,
+    format: HumanFormat,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let len = FileOfferMsg::from(offer.clone()).get_transfer_size(&response)?;
-    let progress_bar = create_progress_bar(len);
-    let mut current_file = String::from("Starting...");
-
+    let progress_bar = create_progress_bar(len, format);
+    let mut events = ProgressEvents::default();
     let update_progress = |report: &TransferReport| {
-        progress_bar.set_position(report.processed_bytes);
-        if current_file.as_str() != report.current_file.to_string_lossy() {
-            current_file.clear();
-            current_file.push_str(&report.current_file.to_string_lossy());
-            progress_bar.set_message(format!("Sending {}", current_file));
+        show_progress(&progress_bar, report, "Sending", format);
+        events.update(report);
+    };
+
+    let (mut reader, writer) = tokio::io::split(&mut *stream);
+    let writer = RateLimited::new(writer, limit);
+
+    // resolves only if the peer sends a cancellation
+    let peer_cancelled = async {
+        match read_from_async::<()>(&mut reader).await {
+            Err(gday_file_transfer::Error::PeerCancelled(reason)) => reason,
+            _ => std::future::pending().await,
         }
     };
 
-    match gday_file_transfer::send_files(&offer, &response, writer, update_progress).await {
+    let cancel = CancelToken::new();
+    let result = tokio::select! {
+        result = gday_file_transfer::send_files_cancellable(
+            &offer,
+            &response,
+            writer,
+            update_progress,
+            &cancel,
+        ) => result,
+        reason = peer_cancelled => Err(gday_file_transfer::Error::PeerCancelled(reason)),
+        () = cancel_on_ctrl_c(&cancel) => unreachable!("Never resolves."),
+    };
+
+    match result {
         Ok(()) => {
             progress_bar.finish_with_message("Transfer complete.");
             Ok(())
         }
         Err(err) => {
             progress_bar.abandon_with_message("Send failed.");
+            // a peer that cancelled doesn't need to be told
+            if !matches!(err, gday_file_transfer::Error::PeerCancelled(_)) {
+                tell_peer_cancelled(CancelReason::from_error(&err), stream).await;
+            }
+            Err(err.into())
+        }
+    }
+}
+
+/// Sends the only file accepted in `response` from standard input,
+/// which holds the number of bytes declared in `offer`.
+///
+/// Sends at most `limit` bytes per second, if set.
+pub async fn send_from_stdin(
+    offer: &FileOfferMsg,
+    response: &FileResponseMsg,
+    stream: &mut EncryptedStream<crate::PeerStream>,
+    limit: Option<RateLimit>,
+    format: HumanFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let len = offer.get_transfer_size(response)?;
+    let progress_bar = create_progress_bar(len, format);
+    let mut events = ProgressEvents::default();
+    let update_progress = |report: &TransferReport| {
+        show_progress(&progress_bar, report, "Sending", format);
+        events.update(report);
+    };
+
+    let (mut reader, writer) = tokio::io::split(&mut *stream);
+    let writer = RateLimited::new(writer, limit);
+
+    // resolves only if the peer sends a cancellation
+    let peer_cancelled = async {
+        match read_from_async::<()>(&mut reader).await {
+            Err(gday_file_transfer::Error::PeerCancelled(reason)) => reason,
+            _ => std::future::pending().await,
+        }
+    };
+
+    let result = tokio::select! {
+        result = gday_file_transfer::send_file_from(
+            offer,
+            response,
+            tokio::io::stdin(),
+            writer,
+            update_progress,
+        ) => result.map_err(|err| (CancelReason::from_error(&err), err)),
+        reason = peer_cancelled => Err((
+            reason.clone(),
+            gday_file_transfer::Error::PeerCancelled(reason),
+        )),
+        _ = tokio::signal::ctrl_c() => Err((
+            CancelReason::UserCancelled,
+            std::io::Error::new(std::io::ErrorKind::Interrupted, "Cancelled.").into(),
+        )),
+    };
+
+    match result {
+        Ok(()) => {
+            progress_bar.finish_with_message("Transfer complete.");
+            Ok(())
+        }
+        Err((reason, err)) => {
+            progress_bar.abandon_with_message("Send failed.");
+            // a peer that cancelled doesn't need to be told
+            if !matches!(err, gday_file_transfer::Error::PeerCancelled(_)) {
+                tell_peer_cancelled(reason, stream).await;
+            }
             Err(err.into())
         }
     }
 }
 
-/// Sequentially save the given `files` from this `reader`.
+/// Sequentially save the given `files` from this `stream`.
 ///
 /// `save_dir` is the directory where the files
-/// will be saved.
+/// will be saved. Receives at most `limit` bytes per second, if set.
 pub async fn receive_files(
-    offer: FileOfferMsg,
+    offer: &FileOfferMsg,
     response: FileResponseMsg,
     save_dir: &std::path::Path,
-    reader: &mut EncryptedStream<tokio::net::TcpStream>,
+    stream: EncryptedStream<crate::PeerStream>,
+    limit: Option<RateLimit>,
+    format: HumanFormat,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let len = offer.get_transfer_size(&response)?;
-    let progress_bar = create_progress_bar(len);
-    let mut current_file = String::new();
-
+    let progress_bar = create_progress_bar(len, format);
+    let mut events = ProgressEvents::default();
     let update_progress = |report: &TransferReport| {
-        progress_bar.set_position(report.processed_bytes);
-        if current_file.as_str() != report.current_file.to_string_lossy() {
-            current_file.clear();
-            current_file.push_str(&report.current_file.to_string_lossy());
-            progress_bar.set_message(format!("Receiving {}", current_file));
+        show_progress(&progress_bar, report, "Receiving", format);
+        events.update(report);
+    };
+
+    // heartbeats are sent on `writer` while the transfer is paused
+    let (mut reader, writer) = stream.into_split();
+    let writer = tokio::sync::Mutex::new(writer);
+
+    // pause the transfer and ask the user what to do
+    let on_storage_full = |save_dir: &std::path::Path| {
+        let save_dir = save_dir.to_path_buf();
+        let (progress_bar, writer) = (&progress_bar, &writer);
+        async move {
+            // hidden rather than suspended, since suspending would
+            // lock the progress bar until the prompt returns
+            progress_bar.set_draw_target(ProgressDrawTarget::hidden());
+            let mut rx =
+                crate::dialog::spawn_prompt(move || crate::dialog::ask_storage_full(&save_dir));
+            let mut writer = writer.lock().await;
+            let action = match gday_file_transfer::with_heartbeats(
+                &mut *writer,
+                crate::HEARTBEAT_INTERVAL,
+                &mut rx,
+            )
+            .await
+            {
+                Ok(action) => action,
+                // what the peer already sent may still be saved
+                Err(_) => rx.await,
+            };
+            progress_bar.set_draw_target(progress_draw_target());
+            action.unwrap_or(StorageFullAction::Abort)
         }
     };
 
-    let result =
-        gday_file_transfer::receive_files(&offer, &response, save_dir, reader, update_progress)
-            .await;
+    // stopping cleanly saves what arrived, so it can be resumed
+    let cancel = CancelToken::new();
+    let result = tokio::select! {
+        result = gday_file_transfer::receive_files_cancellable(
+            offer,
+            &response,
+            save_dir,
+            RateLimited::new(&mut reader, limit),
+            update_progress,
+            on_storage_full,
+            &cancel,
+        ) => result,
+        () = cancel_on_ctrl_c(&cancel) => unreachable!("Never resolves."),
+    };
 
     match result {
-        Ok(()) => {
+        Ok(summary) => {
             progress_bar.finish_with_message("Transfer complete.");
+            if let Some(manifest_path) = summary.manifest_path {
+                say!(
+                    "Saved a manifest of the transfer to {}",
+                    manifest_path.display()
+                );
+            }
             Ok(())
         }
         Err(err) => {
             progress_bar.abandon_with_message("Receive failed.");
+            // a peer that cancelled doesn't need to be told
+            if !matches!(err, gday_file_transfer::Error::PeerCancelled(_)) {
+                let mut stream = reader.unsplit(writer.into_inner());
+                tell_peer_cancelled(CancelReason::from_error(&err), &mut stream).await;
+            }
+            Err(err.into())
+        }
+    }
+}
+
+/// Receives the only offered file from this `reader`,
+/// and writes it to standard output instead of saving it.
+///
+/// Receives at most `limit` bytes per second, if set.
+pub async fn receive_to_stdout(
+    offer: &FileOfferMsg,
+    response: &FileResponseMsg,
+    reader: &mut EncryptedStream<crate::PeerStream>,
+    limit: Option<RateLimit>,
+    format: HumanFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let len = offer.get_transfer_size(response)?;
+    let progress_bar = create_progress_bar(len, format);
+    let mut events = ProgressEvents::default();
+    let update_progress = |report: &TransferReport| {
+        show_progress(&progress_bar, report, "Receiving", format);
+        events.update(report);
+    };
+
+    let result = tokio::select! {
+        result = gday_file_transfer::receive_file_to(
+            offer,
+            response,
+            RateLimited::new(&mut *reader, limit),
+            tokio::io::stdout(),
+            update_progress,
+        ) => result.map_err(|err| (CancelReason::from_error(&err), err)),
+        _ = tokio::signal::ctrl_c() => Err((
+            CancelReason::UserCancelled,
+            std::io::Error::new(std::io::ErrorKind::Interrupted, "Cancelled.").into(),
+        )),
+    };
+
+    match result {
+        Ok(()) => {
+            progress_bar.finish_with_message("Transfer complete.");
+            Ok(())
+        }
+        Err((reason, err)) => {
+            progress_bar.abandon_with_message("Receive failed.");
+            tell_peer_cancelled(reason, reader).await;
             Err(err.into())
         }
     }
 }
 
-/// Create a stylded [`ProgressBar`].
-fn create_progress_bar(len: u64) -> ProgressBar {
-    let style = ProgressStyle::with_template(
-        "{msg} [{wide_bar}] {bytes}/{total_bytes} | {bytes_per_sec} | eta: {eta}",
-    )
-    .expect("Progress bar style string was invalid.");
-    let draw = ProgressDrawTarget::stderr_with_hz(2);
-    ProgressBar::with_draw_target(Some(len), draw)
+/// Like [`send_files()`], but sends different files on each of `streams`
+/// at the same time, with [`gday_file_transfer::send_files_multi()`].
+///
+/// Shows the progress of each stream below that of the whole transfer.
+/// Sends at most `limit` bytes per second in total, if set.
+/// Stops if the user presses Ctrl-C, since the streams can't carry a cancellation.
+pub async fn send_files_multi(
+    offer: &[FileMetaLocal],
+    response: &FileResponseMsg,
+    streams: Vec<EncryptedStream<crate::PeerStream>>,
+    limit: Option<RateLimit>,
+    format: HumanFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let len = FileOfferMsg::from(offer.to_vec()).get_transfer_size(response)?;
+    let progress_bars = StreamProgressBars::new(len, streams.len(), format);
+    let mut events = ProgressEvents::default();
+    let update_progress = |progress: &StreamReport| {
+        progress_bars.show(progress, "Sending");
+        events.update(progress.total);
+    };
+
+    let limit = split_limit(limit, streams.len());
+    let mut writers: Vec<_> = streams
+        .into_iter()
+        .map(|stream| RateLimited::new(stream, limit))
+        .collect();
+
+    let result = tokio::select! {
+        result = gday_file_transfer::send_files_multi(
+            offer,
+            response,
+            &mut writers,
+            update_progress,
+        ) => result.map_err(Into::into),
+        _ = tokio::signal::ctrl_c() => Err(
+            std::io::Error::new(std::io::ErrorKind::Interrupted, "Cancelled.").into()
+        ),
+    };
+
+    match result {
+        Ok(()) => {
+            progress_bars.finish();
+            Ok(())
+        }
+        Err(err) => {
+            progress_bars.abandon("Send failed.");
+            Err(err)
+        }
+    }
+}
+
+/// Like [`receive_files()`], but receives different files on each of `streams`
+/// at the same time, with [`gday_file_transfer::receive_files_multi()`].
+///
+/// Shows the progress of each stream below that of the whole transfer.
+/// Receives at most `limit` bytes per second in total, if set.
+/// Stops if the user presses Ctrl-C, since the streams can't carry a cancellation.
+pub async fn receive_files_multi(
+    offer: &FileOfferMsg,
+    response: &FileResponseMsg,
+    save_dir: &std::path::Path,
+    streams: Vec<EncryptedStream<crate::PeerStream>>,
+    limit: Option<RateLimit>,
+    format: HumanFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let len = offer.get_transfer_size(response)?;
+    let progress_bars = StreamProgressBars::new(len, streams.len(), format);
+    let mut events = ProgressEvents::default();
+    let update_progress = |progress: &StreamReport| {
+        progress_bars.show(progress, "Receiving");
+        events.update(progress.total);
+    };
+
+    let limit = split_limit(limit, streams.len());
+    let mut readers: Vec<_> = streams
+        .into_iter()
+        .map(|stream| RateLimited::new(stream, limit))
+        .collect();
+
+    let result = tokio::select! {
+        result = gday_file_transfer::receive_files_multi(
+            offer,
+            response,
+            save_dir,
+            &mut readers,
+            update_progress,
+        ) => result.map_err(Into::into),
+        _ = tokio::signal::ctrl_c() => Err(
+            std::io::Error::new(std::io::ErrorKind::Interrupted, "Cancelled.").into()
+        ),
+    };
+
+    match result {
+        Ok(summary) => {
+            progress_bars.finish();
+            if let Some(manifest_path) = summary.manifest_path {
+                say!(
+                    "Saved a manifest of the transfer to {}",
+                    manifest_path.display()
+                );
+            }
+            Ok(())
+        }
+        Err(err) => {
+            progress_bars.abandon("Receive failed.");
+            Err(err)
+        }
+    }
+}
+
+/// Splits `limit` evenly between `streams` streams.
+fn split_limit(limit: Option<RateLimit>, streams: usize) -> Option<RateLimit> {
+    limit.map(|limit| RateLimit::new((limit.bytes_per_sec / streams as u64).max(1)))
+}
+
+/// The progress bars of a transfer over several streams:
+/// one line for the whole transfer, and one for each stream below it.
+struct StreamProgressBars {
+    total: ProgressBar,
+    streams: Vec<ProgressBar>,
+    format: HumanFormat,
+}
+
+impl StreamProgressBars {
+    /// Creates the bars of a transfer of `len` bytes over `streams` streams,
+    /// that show sizes with `format`.
+    fn new(len: u64, streams: usize, format: HumanFormat) -> Self {
+        let multi_progress = MultiProgress::with_draw_target(progress_draw_target());
+        let total = multi_progress.add(create_progress_bar(len, format));
+        let streams = (0..streams)
+            .map(|_| multi_progress.add(create_progress_bar(0, format)))
+            .collect();
+        Self {
+            total,
+            streams,
+            format,
+        }
+    }
+
+    /// Shows `progress` on the bar of its stream, and on the total,
+    /// with messages starting with `verb`.
+    fn show(&self, progress: &StreamReport, verb: &str) {
+        show_progress(&self.total, progress.total, verb, self.format);
+        self.total.set_message(format!(
+            "{verb} {}/{} files",
+            progress.total.processed_files, progress.total.total_files
+        ));
+
+        // each stream report only counts the file it's on
+        let stream_bar = &self.streams[progress.stream];
+        stream_bar.set_length(progress.stream_report.total_bytes);
+        let label = format!("  Stream {}:", progress.stream + 1);
+        show_progress(stream_bar, progress.stream_report, &label, self.format);
+    }
+
+    /// Leaves the total showing that the transfer is complete,
+    /// and removes the bars of the streams.
+    fn finish(&self) {
+        self.total.finish_with_message("Transfer complete.");
+        for stream_bar in &self.streams {
+            stream_bar.finish_and_clear();
+        }
+    }
+
+    /// Leaves all bars where the transfer stopped,
+    /// with the total showing `message`.
+    fn abandon(&self, message: &'static str) {
+        self.total.abandon_with_message(message);
+        for stream_bar in &self.streams {
+            stream_bar.abandon();
+        }
+    }
+}
+
+/// Cancels `cancel` once the user presses Ctrl-C,
+/// then waits for the transfer to stop.
+async fn cancel_on_ctrl_c(cancel: &CancelToken) {
+    if tokio::signal::ctrl_c().await.is_ok() {
+        cancel.cancel(CancelReason::UserCancelled);
+    }
+    std::future::pending().await
+}
+
+/// Tells the peer the transfer was cancelled because of `reason`.
+///
+/// Then discards incoming data for a bit, so
+/// the connection isn't reset before the peer reads the cancellation.
+/// Ignores any errors, since the connection may already be broken.
+async fn tell_peer_cancelled(
+    reason: CancelReason,
+    stream: &mut EncryptedStream<crate::PeerStream>,
+) {
+    if write_cancel_async(reason, stream).await.is_err() {
+        return;
+    }
+
+    let mut buf = vec![0; 0x10000];
+    let _ = tokio::time::timeout(CANCEL_LINGER, async {
+        while let Ok(1..) = stream.read(&mut buf).await {}
+    })
+    .await;
+}
+
+/// Shows `report` on `progress_bar`, with sizes and times shown with `format`.
+///
+/// The message is `verb` followed by the current file
+/// and how much of it is done.
+fn show_progress(
+    progress_bar: &ProgressBar,
+    report: &TransferReport,
+    verb: &str,
+    format: HumanFormat,
+) {
+    progress_bar.set_position(report.processed_bytes);
+
+    let file_percent = if report.current_file_len == 0 {
+        100
+    } else {
+        (report.current_file_processed as f64 / report.current_file_len as f64 * 100.0) as u64
+    };
+    progress_bar.set_message(format!(
+        "{verb} {} ({file_percent}%)",
+        report.current_file.display()
+    ));
+
+    let eta = report
+        .eta
+        .map_or_else(|| "?".to_string(), |eta| format.duration(eta));
+    progress_bar.set_prefix(format!(
+        "{} | eta: {eta}",
+        format.rate(report.bytes_per_sec)
+    ));
+}
+
+/// Create a stylded [`ProgressBar`], that shows sizes with `format`.
+///
+/// The throughput and ETA go in its prefix, set by [`show_progress()`].
+fn create_progress_bar(len: u64, format: HumanFormat) -> ProgressBar {
+    let style = ProgressStyle::with_template("{msg} [{wide_bar}] {done}/{total} | {prefix}")
+        .expect("Progress bar style string was invalid.")
+        .with_key(
+            "done",
+            move |state: &ProgressState, w: &mut dyn std::fmt::Write| {
+                let _ = write!(w, "{}", format.size(state.pos()));
+            },
+        )
+        .with_key(
+            "total",
+            move |state: &ProgressState, w: &mut dyn std::fmt::Write| {
+                let _ = write!(w, "{}", format.size(state.len().unwrap_or(0)));
+            },
+        );
+    ProgressBar::with_draw_target(Some(len), progress_draw_target())
         .with_style(style)
         .with_message("starting...")
 }
+
+/// Returns where progress bars are drawn.
+fn progress_draw_target() -> ProgressDrawTarget {
+    // with `--json`, progress is reported with events instead
+    if crate::events::enabled() {
+        ProgressDrawTarget::hidden()
+    } else {
+        ProgressDrawTarget::stderr_with_hz(2)
+    }
+}
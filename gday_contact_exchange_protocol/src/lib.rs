@@ -4,11 +4,11 @@
 //! In most cases, you should use one of the following crates:
 //!
 //! - [**gday**](https://crates.io/crates/gday):
-//!     A command line tool for sending files to peers.
+//!   A command line tool for sending files to peers.
 //! - [**gday_hole_punch**](https://docs.rs/gday_hole_punch/):
-//!     A library for establishing a peer-to-peer TCP connection.
+//!   A library for establishing a peer-to-peer TCP connection.
 //! - [**gday_server**](https://crates.io/crates/gday_server):
-//!     A server binary that facilitates this protocol.
+//!   A server binary that facilitates this protocol.
 //!
 //! # Example
 //! First, both peers connect with TLS on both IPv4 and IPv6 (if possible)
@@ -89,7 +89,10 @@ pub const DEFAULT_PORT: u16 = 2311;
 pub const PROTOCOL_VERSION: u8 = 1;
 
 /// A message from client to server.
-#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Copy)]
+///
+/// Its [`Debug`](std::fmt::Debug) output redacts room codes and tokens,
+/// so that logging a message doesn't let others join the room.
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Copy)]
 #[non_exhaustive]
 pub enum ClientMsg {
     /// Requests the server to create a new room.
@@ -137,6 +140,83 @@ pub enum ClientMsg {
         /// or the other client.
         is_creator: bool,
     },
+
+    /// Asks the server to relay this connection's traffic
+    /// to the other peer in the room, for peers that can't
+    /// connect directly.
+    ///
+    /// Only some servers offer relaying. Others respond
+    /// with [`ServerMsg::ErrorRelayDisabled`].
+    ///
+    /// `room_code` must be of a room that's open, or whose clients
+    /// exchanged contacts within the server's room timeout.
+    /// Otherwise the server responds with [`ServerMsg::ErrorNoSuchRoomCode`].
+    ///
+    /// Once both peers have sent this message with the same `room_code`,
+    /// the server sends both of them [`ServerMsg::RelayStarted`].
+    /// From then on, the server forwards all bytes sent on this connection
+    /// to the other peer's connection, until either peer disconnects
+    /// or a limit set by the server is reached.
+    /// The server doesn't look at the relayed bytes, so the peers
+    /// should encrypt and authenticate them end-to-end.
+    RelayConnect {
+        /// The room to relay within.
+        room_code: [u8; 32],
+        /// Whether this is the client that created this room,
+        /// or the other client.
+        is_creator: bool,
+    },
+
+    /// Authenticates this connection as one of the server's tenants,
+    /// for servers shared by several apps or groups of users.
+    ///
+    /// Each tenant's rooms are separate from those of other tenants
+    /// and of unauthenticated clients, and each tenant has its own
+    /// request limits. Affects the requests sent on this connection
+    /// after this message.
+    ///
+    /// Server responds with [`ServerMsg::Authenticated`] on success
+    /// or [`ServerMsg::ErrorInvalidToken`] if no tenant has this token.
+    Authenticate {
+        /// SHA-256 hash of the tenant's API key.
+        token: [u8; 32],
+    },
+}
+
+impl std::fmt::Debug for ClientMsg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let redacted = format_args!("[REDACTED]");
+        match self {
+            Self::CreateRoom { .. } => f
+                .debug_struct("CreateRoom")
+                .field("room_code", &redacted)
+                .finish(),
+            Self::RecordPublicAddr { is_creator, .. } => f
+                .debug_struct("RecordPublicAddr")
+                .field("room_code", &redacted)
+                .field("is_creator", is_creator)
+                .finish(),
+            Self::ReadyToShare {
+                local_contact,
+                is_creator,
+                ..
+            } => f
+                .debug_struct("ReadyToShare")
+                .field("local_contact", local_contact)
+                .field("room_code", &redacted)
+                .field("is_creator", is_creator)
+                .finish(),
+            Self::RelayConnect { is_creator, .. } => f
+                .debug_struct("RelayConnect")
+                .field("room_code", &redacted)
+                .field("is_creator", is_creator)
+                .finish(),
+            Self::Authenticate { .. } => f
+                .debug_struct("Authenticate")
+                .field("token", &redacted)
+                .finish(),
+        }
+    }
 }
 
 /// A message from server to client.
@@ -161,6 +241,15 @@ pub enum ServerMsg {
     /// Contains the other peer's contact info.
     PeerContact(FullContact),
 
+    /// After both clients in a room have sent [`ClientMsg::RelayConnect`],
+    /// the server sends this message. All further bytes on this connection
+    /// are relayed to and from the other peer.
+    RelayStarted,
+
+    /// Immediately responds to a [`ClientMsg::Authenticate`] to indicate
+    /// that this connection now acts as the tenant with that token.
+    Authenticated,
+
     /// Responds to a [`ClientMsg::CreateRoom`] if the given
     /// `room_code` is currently taken.
     ErrorRoomTaken,
@@ -178,6 +267,23 @@ pub enum ServerMsg {
     /// after already sending [`ClientMsg::ReadyToShare`].
     ErrorUnexpectedMsg,
 
+    /// Responds to a [`ClientMsg::RelayConnect`] if this server
+    /// doesn't relay traffic.
+    ErrorRelayDisabled,
+
+    /// Responds to a [`ClientMsg::Authenticate`] if no tenant
+    /// of this server has the given token.
+    ErrorInvalidToken,
+
+    /// Rejects a request if this server only serves tenants,
+    /// and the connection hasn't sent [`ClientMsg::Authenticate`].
+    /// The server then closes the connection.
+    ErrorAuthRequired,
+
+    /// Responds to a [`ClientMsg::CreateRoom`] if the tenant
+    /// already has as many rooms open as the server allows.
+    ErrorTooManyRooms,
+
     /// Rejects a request if an IP address made too many requests.
     /// The server then closes the connection.
     ErrorTooManyRequests,
@@ -201,6 +307,8 @@ impl Display for ServerMsg {
             Self::ReceivedAddr => write!(f, "Server recorded your public address."),
             Self::ClientContact(c) => write!(f, "The server says your contact is {c}."),
             Self::PeerContact(c) => write!(f, "The server says your peer's contact is {c}."),
+            Self::RelayStarted => write!(f, "Server started relaying traffic to your peer."),
+            Self::Authenticated => write!(f, "Server accepted your API key."),
             Self::ErrorRoomTaken => write!(
                 f,
                 "Can't create a room with this room code, because it's already taken."
@@ -217,6 +325,15 @@ impl Display for ServerMsg {
                 "Server received RecordPublicAddr message after a ReadyToShare message. \
                 Maybe someone else tried to join this room with your identity?"
             ),
+            Self::ErrorRelayDisabled => write!(f, "This server doesn't relay traffic."),
+            Self::ErrorInvalidToken => write!(f, "Server didn't accept your API key."),
+            Self::ErrorAuthRequired => {
+                write!(f, "This server only serves clients with an API key.")
+            }
+            Self::ErrorTooManyRooms => write!(
+                f,
+                "Too many rooms are open with your API key. Try again later."
+            ),
             Self::ErrorTooManyRequests => write!(
                 f,
                 "Exceeded request limit from this IP address. Try again in a minute."
@@ -270,6 +387,42 @@ pub struct FullContact {
     /// The server determines this by checking where
     /// [`ClientMsg::RecordPublicAddr`] messages came from.
     pub public: Contact,
+    /// What the server observed about the client's NAT.
+    /// Left as the default by servers that don't report it.
+    #[serde(default)]
+    pub nat: NatBehavior,
+}
+
+/// What the server observed about a client's network,
+/// to help its peer choose how to connect to it.
+///
+/// Which of IPv4 and IPv6 the server observed
+/// is already given by [`FullContact::public`].
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Copy, Default)]
+#[non_exhaustive]
+pub struct NatBehavior {
+    /// How the client's NAT maps its local IPv4 port to a public one.
+    pub port_mapping: PortMapping,
+}
+
+/// How a client's NAT maps its local IPv4 port to a public port.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Copy, Default)]
+#[non_exhaustive]
+pub enum PortMapping {
+    /// The server couldn't tell, for example because the client
+    /// didn't share both its local and public IPv4 address.
+    #[default]
+    Unknown,
+    /// The public port is the same as the local port,
+    /// because there's no NAT, or it preserves ports.
+    Preserved,
+    /// The public port differs from the local port,
+    /// but was the same across the client's connections.
+    Translated,
+    /// The public port differed across the client's connections,
+    /// hinting at a symmetric NAT that maps each connection to a new port.
+    /// The peer can try predicting the port, or fall back to a relay.
+    Varies,
 }
 
 impl std::fmt::Display for FullContact {
@@ -2,7 +2,7 @@
 #![warn(clippy::all)]
 use gday_contact_exchange_protocol::{
     read_from, read_from_async, write_to, write_to_async, ClientMsg, Contact, Error, FullContact,
-    ServerMsg,
+    NatBehavior, PortMapping, ServerMsg,
 };
 use std::io::Write;
 use tokio::io::AsyncWriteExt;
@@ -31,6 +31,30 @@ fn sending_messages() {
     }
 }
 
+/// Confirm contacts from servers that don't report
+/// NAT behavior can still be parsed.
+#[test]
+fn contact_without_nat_behavior() {
+    let json = r#"{"local":{"v4":null,"v6":null},"public":{"v4":"1.2.3.4:5","v6":null}}"#;
+    let contact: FullContact = serde_json::from_str(json).unwrap();
+    assert_eq!(contact.public.v4, Some("1.2.3.4:5".parse().unwrap()));
+    assert_eq!(contact.nat, NatBehavior::default());
+}
+
+/// Confirm that room codes and tokens don't show up in debug output.
+#[test]
+fn client_msg_debug_is_redacted() {
+    for msg in get_client_msg_examples() {
+        let debug = format!("{msg:?}");
+        assert!(debug.contains("room_code: [REDACTED]"), "{debug}");
+        // the bytes of 'f', which every example room code contains
+        assert!(!debug.contains("102"), "{debug}");
+    }
+
+    let msg = ClientMsg::Authenticate { token: [7; 32] };
+    assert_eq!(format!("{msg:?}"), "Authenticate { token: [REDACTED] }");
+}
+
 #[test]
 fn error_on_invalid_json() {
     let mut pipe = std::collections::VecDeque::new();
@@ -122,6 +146,9 @@ fn get_client_msg_examples() -> Vec<ClientMsg> {
 
 /// Get a [`Vec`] of example [`ServerMsg`]s.
 fn get_server_msg_examples() -> Vec<ServerMsg> {
+    let mut nat = NatBehavior::default();
+    nat.port_mapping = PortMapping::Varies;
+
     vec![
         ServerMsg::RoomCreated,
         ServerMsg::ReceivedAddr,
@@ -134,6 +161,7 @@ fn get_server_msg_examples() -> Vec<ServerMsg> {
                 v4: Some("31.31.65.31:324".parse().unwrap()),
                 v6: Some("[2001:db8::1]:8080".parse().unwrap()),
             },
+            nat,
         }),
         ServerMsg::PeerContact(FullContact {
             local: Contact {
@@ -144,6 +172,7 @@ fn get_server_msg_examples() -> Vec<ServerMsg> {
                 v4: Some("31.31.65.31:324".parse().unwrap()),
                 v6: Some("[2001:db8::1]:8080".parse().unwrap()),
             },
+            nat: NatBehavior::default(),
         }),
         ServerMsg::ErrorRoomTaken,
         ServerMsg::ErrorPeerTimedOut,
@@ -1,12 +1,13 @@
 #![forbid(unsafe_code)]
 #![warn(clippy::all)]
 
-use criterion::BatchSize;
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use gday_encryption::EncryptedStream;
+use criterion::{BatchSize, Throughput};
+use gday_encryption::{ChunkSize, Cipher, EncryptedStream};
 use rand::rngs::StdRng;
 use rand::RngCore;
 use rand::SeedableRng;
+use std::io::IoSlice;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
 fn encryption_bench(c: &mut Criterion) {
@@ -89,5 +90,95 @@ fn decryption_bench(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, encryption_bench, decryption_bench);
+fn throughput_bench(c: &mut Criterion) {
+    // generate pseudorandom data from a seed
+    let mut rng = StdRng::seed_from_u64(10);
+
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+
+    let mut key = [0; 32];
+    let mut nonce = [0; 7];
+    rng.fill_bytes(&mut key);
+    rng.fill_bytes(&mut nonce);
+    let mut random_plaintext = vec![0; 8_000_000];
+    rng.fill_bytes(&mut random_plaintext);
+
+    let mut group = c.benchmark_group("EncryptedStream 8,000,000 bytes");
+    group.throughput(Throughput::Bytes(random_plaintext.len() as u64));
+
+    let ciphers = [
+        Cipher::ChaCha20Poly1305,
+        #[cfg(feature = "aes-gcm")]
+        Cipher::Aes256Gcm,
+    ];
+
+    let plaintext = &random_plaintext;
+    for cipher in ciphers {
+        for chunk_size in [ChunkSize::Standard, ChunkSize::Large] {
+            // write the plaintext as two buffers,
+            // like a message header followed by its body
+            group.bench_function(format!("write_vectored {cipher:?} {chunk_size:?}"), |b| {
+                b.to_async(&rt).iter_batched(
+                    || {
+                        let encrypted = Vec::with_capacity(8_500_000);
+                        EncryptedStream::new_with_cipher(encrypted, cipher, &key, &nonce)
+                            .with_chunk_size(chunk_size)
+                    },
+                    |mut stream| async move {
+                        let (first, second) = plaintext.split_at(100);
+                        let mut bufs = [IoSlice::new(first), IoSlice::new(second)];
+                        let mut bufs = &mut bufs[..];
+                        while !bufs.is_empty() {
+                            let written = black_box(stream.write_vectored(bufs)).await.unwrap();
+                            IoSlice::advance_slices(&mut bufs, written);
+                        }
+                        black_box(stream.flush()).await.unwrap();
+                    },
+                    BatchSize::LargeInput,
+                );
+            });
+
+            let ciphertext = rt.block_on(async {
+                let mut ciphertext = Vec::new();
+                let mut encryptor =
+                    EncryptedStream::new_with_cipher(&mut ciphertext, cipher, &key, &nonce)
+                        .with_chunk_size(chunk_size);
+                encryptor.write_all(&random_plaintext).await.unwrap();
+                encryptor.flush().await.unwrap();
+                ciphertext
+            });
+
+            group.bench_function(format!("read {cipher:?} {chunk_size:?}"), |b| {
+                b.to_async(&rt).iter_batched(
+                    || {
+                        (
+                            vec![0; random_plaintext.len()],
+                            EncryptedStream::new_with_cipher(&ciphertext[..], cipher, &key, &nonce)
+                                .with_chunk_size(chunk_size),
+                        )
+                    },
+                    |(mut decrypted, mut decryptor)| async move {
+                        EncryptedStream::read_exact(
+                            black_box(&mut decryptor),
+                            black_box(&mut decrypted),
+                        )
+                        .await
+                        .unwrap()
+                    },
+                    BatchSize::LargeInput,
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    encryption_bench,
+    decryption_bench,
+    throughput_bench
+);
 criterion_main!(benches);
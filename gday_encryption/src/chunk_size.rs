@@ -0,0 +1,94 @@
+/// How large the chunks of an [`crate::EncryptedStream`] can be.
+///
+/// Larger chunks mean fewer headers, tags, and wakeups per byte,
+/// which matters on very fast links, at the cost of more memory per stream.
+/// Both peers must use the same chunk size. [`crate::EncryptedStream::encrypt_connection()`]
+/// takes care of that by using the smaller chunk size either peer asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum ChunkSize {
+    /// Chunks of up to 64 KiB, each with a 2-byte length header.
+    #[default]
+    Standard,
+    /// Chunks of up to 1 MiB, each with a 4-byte length header.
+    Large,
+}
+
+impl ChunkSize {
+    /// Length of the header before each chunk,
+    /// and of the trailer of a padded chunk.
+    pub(crate) fn header_len(self) -> usize {
+        match self {
+            Self::Standard => 2,
+            Self::Large => 4,
+        }
+    }
+
+    /// Longest encrypted chunk, including its tag.
+    pub(crate) fn max_len(self) -> usize {
+        match self {
+            Self::Standard => u16::MAX as usize,
+            Self::Large => 1 << 20,
+        }
+    }
+
+    /// Writes `len` into `header`, which must be [`Self::header_len()`] bytes long.
+    pub(crate) fn encode(self, len: usize, header: &mut [u8]) {
+        match self {
+            Self::Standard => header.copy_from_slice(
+                &u16::try_from(len)
+                    .expect("unreachable: Length of a chunk should always fit in u16")
+                    .to_be_bytes(),
+            ),
+            Self::Large => header.copy_from_slice(
+                &u32::try_from(len)
+                    .expect("unreachable: Length of a chunk should always fit in u32")
+                    .to_be_bytes(),
+            ),
+        }
+    }
+
+    /// Returns the length stored in `header` by [`Self::encode()`],
+    /// which must be [`Self::header_len()`] bytes long.
+    pub(crate) fn decode(self, header: &[u8]) -> usize {
+        match self {
+            Self::Standard => u16::from_be_bytes([header[0], header[1]]) as usize,
+            Self::Large => {
+                u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize
+            }
+        }
+    }
+
+    /// Returns this chunk size as a byte sent during the handshake.
+    pub(crate) fn to_byte(self) -> u8 {
+        match self {
+            Self::Standard => 0,
+            Self::Large => 1,
+        }
+    }
+
+    /// Returns the chunk size sent during the handshake as `byte`.
+    ///
+    /// Unknown values become [`ChunkSize::Large`], since they'd come
+    /// from a peer asking for even larger chunks.
+    pub(crate) fn from_byte(byte: u8) -> Self {
+        match byte {
+            0 => Self::Standard,
+            _ => Self::Large,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ChunkSize;
+
+    #[test]
+    fn test_header() {
+        for chunk_size in [ChunkSize::Standard, ChunkSize::Large] {
+            let mut header = vec![0; chunk_size.header_len()];
+            chunk_size.encode(chunk_size.max_len(), &mut header);
+            assert_eq!(chunk_size.decode(&header), chunk_size.max_len());
+            assert_eq!(ChunkSize::from_byte(chunk_size.to_byte()), chunk_size);
+        }
+    }
+}
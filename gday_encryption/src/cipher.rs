@@ -0,0 +1,248 @@
+use chacha20poly1305::aead::stream::{DecryptorBE32, EncryptorBE32};
+use chacha20poly1305::aead::{self, Buffer};
+use chacha20poly1305::ChaCha20Poly1305;
+
+/// An authenticated cipher that an [`crate::EncryptedStream`] can use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Cipher {
+    /// ChaCha20Poly1305, which is fast even without special CPU instructions.
+    /// Every peer supports it.
+    #[default]
+    ChaCha20Poly1305,
+    /// AES-256-GCM, which is faster than ChaCha20Poly1305 on CPUs
+    /// with AES instructions, like most recent x86 and ARM CPUs.
+    ///
+    /// Requires the `aes-gcm` feature, which is enabled by default.
+    #[cfg(feature = "aes-gcm")]
+    Aes256Gcm,
+}
+
+/// The ciphers in the order peers prefer them,
+/// when both support more than one.
+const PREFERENCE: &[Cipher] = &[
+    #[cfg(feature = "aes-gcm")]
+    Cipher::Aes256Gcm,
+    Cipher::ChaCha20Poly1305,
+];
+
+impl Cipher {
+    /// Returns the ciphers that run fast on this machine.
+    ///
+    /// This always includes [`Cipher::ChaCha20Poly1305`], and includes
+    /// [`Cipher::Aes256Gcm`] if this CPU has AES instructions.
+    pub fn accelerated() -> Vec<Self> {
+        #[cfg(feature = "aes-gcm")]
+        if has_aes_instructions() {
+            return vec![Self::ChaCha20Poly1305, Self::Aes256Gcm];
+        }
+        vec![Self::ChaCha20Poly1305]
+    }
+
+    /// The bit that marks this cipher in a negotiation mask.
+    fn bit(self) -> u8 {
+        match self {
+            Self::ChaCha20Poly1305 => 1,
+            #[cfg(feature = "aes-gcm")]
+            Self::Aes256Gcm => 2,
+        }
+    }
+
+    /// Returns the mask that advertises `ciphers` to the peer.
+    /// Always includes [`Cipher::ChaCha20Poly1305`].
+    pub(crate) fn mask(ciphers: &[Self]) -> u8 {
+        ciphers
+            .iter()
+            .fold(Self::ChaCha20Poly1305.bit(), |mask, cipher| {
+                mask | cipher.bit()
+            })
+    }
+
+    /// Returns the most preferred cipher in both masks.
+    ///
+    /// Unknown bits are ignored, so newer peers can advertise new ciphers.
+    pub(crate) fn negotiate(my_mask: u8, peer_mask: u8) -> Self {
+        PREFERENCE
+            .iter()
+            .copied()
+            .find(|cipher| my_mask & peer_mask & cipher.bit() != 0)
+            .unwrap_or(Self::ChaCha20Poly1305)
+    }
+}
+
+/// Whether this CPU has instructions that accelerate AES-GCM.
+#[cfg(feature = "aes-gcm")]
+fn has_aes_instructions() -> bool {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        std::arch::is_x86_feature_detected!("aes")
+            && std::arch::is_x86_feature_detected!("pclmulqdq")
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        std::arch::is_aarch64_feature_detected!("aes")
+    }
+    #[cfg(not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")))]
+    {
+        false
+    }
+}
+
+/// Encrypts a sequence of chunks with the STREAM construction,
+/// using a big-endian 32-bit counter.
+pub(crate) enum Encryptor {
+    ChaCha(EncryptorBE32<ChaCha20Poly1305>),
+    #[cfg(feature = "aes-gcm")]
+    Aes(aes::AesStream),
+}
+
+impl Encryptor {
+    pub fn new(cipher: Cipher, key: &[u8; 32], nonce: &[u8; 7]) -> Self {
+        match cipher {
+            Cipher::ChaCha20Poly1305 => Self::ChaCha(EncryptorBE32::new(key.into(), nonce.into())),
+            #[cfg(feature = "aes-gcm")]
+            Cipher::Aes256Gcm => Self::Aes(aes::AesStream::new(key, nonce)),
+        }
+    }
+
+    /// Encrypts the next chunk in `buf`, appending its tag,
+    /// which also authenticates `aad`.
+    pub fn encrypt_next(&mut self, aad: &[u8], buf: &mut impl Buffer) -> aead::Result<()> {
+        match self {
+            Self::ChaCha(encryptor) => encryptor.encrypt_next_in_place(aad, buf),
+            #[cfg(feature = "aes-gcm")]
+            Self::Aes(encryptor) => encryptor.seal(aad, buf, false),
+        }
+    }
+
+    /// Encrypts the last chunk in `buf`, appending its tag,
+    /// which also authenticates `aad`.
+    pub fn encrypt_last(self, aad: &[u8], buf: &mut impl Buffer) -> aead::Result<()> {
+        match self {
+            Self::ChaCha(encryptor) => encryptor.encrypt_last_in_place(aad, buf),
+            #[cfg(feature = "aes-gcm")]
+            Self::Aes(mut encryptor) => encryptor.seal(aad, buf, true),
+        }
+    }
+}
+
+/// Decrypts a sequence of chunks encrypted by an [`Encryptor`].
+pub(crate) enum Decryptor {
+    ChaCha(DecryptorBE32<ChaCha20Poly1305>),
+    #[cfg(feature = "aes-gcm")]
+    Aes(aes::AesStream),
+}
+
+impl Decryptor {
+    pub fn new(cipher: Cipher, key: &[u8; 32], nonce: &[u8; 7]) -> Self {
+        match cipher {
+            Cipher::ChaCha20Poly1305 => Self::ChaCha(DecryptorBE32::new(key.into(), nonce.into())),
+            #[cfg(feature = "aes-gcm")]
+            Cipher::Aes256Gcm => Self::Aes(aes::AesStream::new(key, nonce)),
+        }
+    }
+
+    /// Decrypts the next chunk in `buf`, removing its tag,
+    /// if it was encrypted with the same `aad`.
+    pub fn decrypt_next(&mut self, aad: &[u8], buf: &mut impl Buffer) -> aead::Result<()> {
+        match self {
+            Self::ChaCha(decryptor) => decryptor.decrypt_next_in_place(aad, buf),
+            #[cfg(feature = "aes-gcm")]
+            Self::Aes(decryptor) => decryptor.open(aad, buf, false),
+        }
+    }
+
+    /// Decrypts the last chunk in `buf`, removing its tag,
+    /// if it was encrypted with the same `aad`.
+    pub fn decrypt_last(self, aad: &[u8], buf: &mut impl Buffer) -> aead::Result<()> {
+        match self {
+            Self::ChaCha(decryptor) => decryptor.decrypt_last_in_place(aad, buf),
+            #[cfg(feature = "aes-gcm")]
+            Self::Aes(mut decryptor) => decryptor.open(aad, buf, true),
+        }
+    }
+}
+
+#[cfg(feature = "aes-gcm")]
+mod aes {
+    use aws_lc_rs::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM};
+    use chacha20poly1305::aead::{self, Buffer};
+
+    /// The STREAM construction over AES-256-GCM, laid out like
+    /// [`chacha20poly1305::aead::stream::StreamBE32`]: each chunk's nonce is the
+    /// 7-byte nonce prefix, a 4-byte big-endian counter, and a last-chunk flag.
+    pub struct AesStream {
+        key: LessSafeKey,
+        nonce: [u8; 7],
+        counter: u32,
+    }
+
+    impl AesStream {
+        pub fn new(key: &[u8; 32], nonce: &[u8; 7]) -> Self {
+            let key = UnboundKey::new(&AES_256_GCM, key)
+                .expect("unreachable: 32 bytes is a valid AES-256 key length");
+            Self {
+                key: LessSafeKey::new(key),
+                nonce: *nonce,
+                counter: 0,
+            }
+        }
+
+        /// Returns the nonce of the next chunk, and advances the counter.
+        fn next_nonce(&mut self, last: bool) -> aead::Result<Nonce> {
+            // like STREAM, refuse to wrap the counter around
+            if self.counter == u32::MAX {
+                return Err(aead::Error);
+            }
+            let mut nonce = [0; 12];
+            nonce[..7].copy_from_slice(&self.nonce);
+            nonce[7..11].copy_from_slice(&self.counter.to_be_bytes());
+            nonce[11] = u8::from(last);
+            self.counter += 1;
+            Ok(Nonce::assume_unique_for_key(nonce))
+        }
+
+        /// Encrypts the chunk in `buf`, appending its tag.
+        pub fn seal(&mut self, aad: &[u8], buf: &mut impl Buffer, last: bool) -> aead::Result<()> {
+            let nonce = self.next_nonce(last)?;
+            let tag = self
+                .key
+                .seal_in_place_separate_tag(nonce, Aad::from(aad), buf.as_mut())
+                .map_err(|_| aead::Error)?;
+            buf.extend_from_slice(tag.as_ref())
+        }
+
+        /// Decrypts the chunk in `buf`, removing its tag.
+        pub fn open(&mut self, aad: &[u8], buf: &mut impl Buffer, last: bool) -> aead::Result<()> {
+            let nonce = self.next_nonce(last)?;
+            let len = self
+                .key
+                .open_in_place(nonce, Aad::from(aad), buf.as_mut())
+                .map_err(|_| aead::Error)?
+                .len();
+            buf.truncate(len);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cipher;
+
+    #[test]
+    fn test_negotiate() {
+        let chacha = Cipher::mask(&[]);
+        assert_eq!(chacha, Cipher::mask(&[Cipher::ChaCha20Poly1305]));
+        assert_eq!(Cipher::negotiate(chacha, 0xff), Cipher::ChaCha20Poly1305);
+        assert_eq!(Cipher::negotiate(0, 0), Cipher::ChaCha20Poly1305);
+
+        #[cfg(feature = "aes-gcm")]
+        {
+            let aes = Cipher::mask(&[Cipher::Aes256Gcm]);
+            assert_eq!(Cipher::negotiate(aes, aes), Cipher::Aes256Gcm);
+            assert_eq!(Cipher::negotiate(aes, chacha), Cipher::ChaCha20Poly1305);
+            // unknown bits are ignored
+            assert_eq!(Cipher::negotiate(aes, 0xf0 | aes), Cipher::Aes256Gcm);
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+/// Info string for deriving a [`Fingerprint`] with HKDF.
+const FINGERPRINT_INFO: &[u8] = b"gday_encryption fingerprint";
+
+/// A fingerprint of an [`crate::EncryptedStream`]'s session,
+/// which both peers can compare out of band.
+///
+/// Both peers get the same fingerprint only if they have the same key,
+/// and, for [`crate::EncryptedStream::encrypt_connection()`], saw the same handshake.
+/// It's derived with HKDF-SHA256, so it doesn't reveal the key.
+///
+/// Displays as a [`Self::short_auth_string()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Fingerprint([u8; 32]);
+
+impl Fingerprint {
+    /// Derives the fingerprint of a session secured with `key`,
+    /// described by `transcript`.
+    pub(crate) fn new(key: &[u8; 32], transcript: &[&[u8]]) -> Self {
+        let mut info = vec![FINGERPRINT_INFO];
+        info.extend_from_slice(transcript);
+        let mut fingerprint = [0; 32];
+        Hkdf::<Sha256>::new(None, key)
+            .expand_multi_info(&info, &mut fingerprint)
+            .expect("unreachable: 32 bytes is a valid HKDF-SHA256 output length");
+        Self(fingerprint)
+    }
+
+    /// Returns the full fingerprint.
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// Returns a short string for people to read to each other,
+    /// such as "49-banana-curve".
+    ///
+    /// Holds about 22 bits of the fingerprint. That's enough for people
+    /// to notice a tampered connection, since the attacker only gets one try.
+    pub fn short_auth_string(&self) -> String {
+        format!(
+            "{:02}-{}-{}",
+            self.0[0] % 100,
+            WORDS[usize::from(self.0[1])],
+            WORDS[usize::from(self.0[2])]
+        )
+    }
+}
+
+impl std::fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.short_auth_string())
+    }
+}
+
+/// Short, distinct words for [`Fingerprint::short_auth_string()`], one per byte value.
+const WORDS: [&str; 256] = [
+    "acid", "acorn", "actor", "adobe", "alarm", "album", "alien", "amber", "angle", "ankle",
+    "apple", "arena", "armor", "arrow", "atlas", "attic", "audio", "award", "bacon", "bagel",
+    "baker", "bamboo", "banana", "banjo", "barn", "basil", "beard", "beast", "berry", "bison",
+    "blade", "blaze", "blimp", "bloom", "bonus", "boots", "brain", "brick", "broom", "brush",
+    "bucket", "bugle", "cabin", "cable", "cactus", "camel", "canal", "candy", "canoe", "carrot",
+    "castle", "cello", "chalk", "charm", "cheek", "chess", "chili", "cider", "cigar", "cinema",
+    "circus", "clam", "cliff", "clock", "clover", "coach", "cobra", "cocoa", "comet", "coral",
+    "couch", "crayon", "creek", "crown", "curve", "daisy", "dance", "delta", "denim", "diary",
+    "dingo", "disco", "dock", "dolphin", "donkey", "dragon", "eagle", "easel", "echo", "elbow",
+    "ember", "engine", "fable", "falcon", "fern", "ferry", "fiber", "fiddle", "field", "flame",
+    "flute", "forest", "fossil", "fox", "frog", "frost", "fudge", "galaxy", "garden", "genie",
+    "ghost", "giant", "ginger", "glove", "goat", "gopher", "gravy", "guitar", "hammer", "harbor",
+    "harp", "hazel", "helmet", "hippo", "hotel", "igloo", "index", "ink", "iris", "island",
+    "ivory", "jacket", "jelly", "jewel", "joker", "judo", "juice", "kayak", "kettle", "koala",
+    "ladder", "lagoon", "lamp", "laser", "lemon", "lens", "lily", "lizard", "llama", "lobster",
+    "locket", "lotus", "lunar", "magnet", "maple", "marble", "medal", "melon", "meteor", "mint",
+    "mirror", "mocha", "moose", "motor", "muffin", "museum", "nectar", "needle", "noodle", "ocean",
+    "olive", "omelet", "onion", "opera", "orbit", "otter", "oven", "paddle", "panda", "parade",
+    "parrot", "pasta", "peach", "pearl", "piano", "pickle", "pilot", "pirate", "pixel", "pizza",
+    "planet", "plum", "polka", "pony", "potato", "prism", "pumpkin", "puzzle", "quail", "quilt",
+    "rabbit", "radar", "radio", "raven", "reef", "rhino", "ribbon", "robot", "rocket", "rodeo",
+    "ruby", "saddle", "salad", "salmon", "satin", "scarf", "shadow", "shark", "shell", "sierra",
+    "silver", "skate", "sloth", "snail", "sonic", "spark", "spider", "spoon", "squid", "storm",
+    "sugar", "summit", "sunset", "swan", "table", "taco", "tango", "toast", "tomato", "topaz",
+    "torch", "tulip", "tundra", "turtle", "unicorn", "valley", "velvet", "violin", "volcano",
+    "waffle", "walnut", "walrus", "wizard", "yogurt", "zebra", "zipper",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::{Fingerprint, WORDS};
+
+    #[test]
+    fn test_fingerprint() {
+        let fingerprint = Fingerprint::new(&[1; 32], &[b"abc"]);
+        assert_eq!(fingerprint, Fingerprint::new(&[1; 32], &[b"abc"]));
+        assert_ne!(fingerprint, Fingerprint::new(&[2; 32], &[b"abc"]));
+        assert_ne!(fingerprint, Fingerprint::new(&[1; 32], &[b"abd"]));
+
+        let sas = fingerprint.short_auth_string();
+        let parts: Vec<&str> = sas.split('-').collect();
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts[0].len(), 2);
+        assert!(WORDS.contains(&parts[1]) && WORDS.contains(&parts[2]));
+        assert_eq!(fingerprint.to_string(), sas);
+
+        // the words are unique, and don't contain the separator
+        let mut words = WORDS.to_vec();
+        words.sort_unstable();
+        words.dedup();
+        assert_eq!(words.len(), 256);
+        assert!(WORDS.iter().all(|word| !word.contains('-')));
+    }
+}
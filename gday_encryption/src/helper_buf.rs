@@ -3,8 +3,8 @@ use std::ops::{Deref, DerefMut};
 
 /// Buffer for storing bytes.
 /// - Implemented as a heap-allocated array
-///     with a left and right cursor defining
-///     the in-use portion.
+///   with a left and right cursor defining
+///   the in-use portion.
 pub struct HelperBuf {
     inner: Box<[u8]>,
     l_cursor: usize,
@@ -62,7 +62,7 @@ impl HelperBuf {
 
     /// Returns a mutable [`aead::Buffer`] view into the part of this
     /// buffer starting at index `i`.
-    pub fn split_off_aead_buf(&mut self, i: usize) -> HelperBufPart {
+    pub fn split_off_aead_buf(&mut self, i: usize) -> HelperBufPart<'_> {
         let start_i = self.l_cursor + i;
         HelperBufPart {
             parent: self,
@@ -1,4 +1,4 @@
-//! Simple encrypted ChaCha20Poly1305 wrapper around an async IO stream.
+//! Simple encrypted ChaCha20Poly1305 or AES-256-GCM wrapper around an async IO stream.
 //!
 //! This library is used by [gday_file_transfer](https://crates.io/crates/gday_file_transfer),
 //! which is used by [gday](https://crates.io/crates/gday).
@@ -31,7 +31,7 @@
 //!     stream.write_all(b"Hello!").await?;
 //!     stream.flush().await?;
 //!
-//!     Ok::<(), std::io::Error>(())
+//!     Ok::<(), gday_encryption::Error>(())
 //! });
 //!
 //! // Peer 2 receives the "Hello!".
@@ -49,60 +49,194 @@
 //! # }).unwrap();
 //! ```
 //!
+//! # Ciphers
+//! [`EncryptedStream::encrypt_connection()`] picks AES-256-GCM
+//! if both peers' CPUs have AES instructions, and ChaCha20Poly1305 otherwise.
+//! See [`Cipher`] for details.
+//!
+//! # Padding
+//! By default, the length of each encrypted chunk reveals how much data was flushed.
+//! Peers can ask for [`Padding`] in [`EncryptedStream::encrypt_connection_with_options()`]
+//! to hide that.
+//!
+//! # Chunk size
+//! Data is sent in chunks of up to 64 KiB, each with a 2-byte length header.
+//! On very fast links, peers can ask for [`ChunkSize::Large`] chunks of up to 1 MiB,
+//! each with a 4-byte length header, in [`EncryptedStream::encrypt_connection_with_options()`].
+//! [`EncryptedStream`] also supports vectored writes, so several buffers
+//! can be written into one chunk without first copying them together.
+//!
+//! # Handshake
+//! [`EncryptedStream::encrypt_connection()`] exchanges random seeds, then both peers
+//! send a MAC of everything sent so far, keyed with the shared key.
+//! If the peers have different keys, or the handshake was tampered with,
+//! it fails with [`Error::PeerKeyMismatch`] before any data is sent.
+//!
+//! # Fingerprint
+//! Both peers can show their users [`EncryptedStream::fingerprint()`],
+//! such as "49-banana-curve", to check out of band that they have the same
+//! key and saw the same handshake.
+//!
+//! # Session binding
+//! [`EncryptedStream::encrypt_connection()`] authenticates every chunk together with
+//! a hash of the handshake, and of which peer sent the chunk.
+//! So chunks can't be spliced between two sessions that share a key,
+//! reflected back to their sender, or accepted after the handshake was tampered with.
+//! Callers can bind more context, such as a protocol version,
+//! with [`HandshakeOptions::associated_data`].
+//!
+//! # Rekeying
+//! To bound how much traffic a single key protects, each direction of an
+//! [`EncryptedStream`] periodically switches to a new key, as set by its [`RekeyPolicy`].
+//! The sender ends the current key's stream with a rekey frame, and both peers
+//! derive the next key with HKDF-SHA256 from the previous key and a counter.
+//! Since the previous key is then forgotten, a leaked key
+//! doesn't reveal the traffic sent before it.
+//!
 #![forbid(unsafe_code)]
 #![warn(clippy::all)]
 
+mod chunk_size;
+mod cipher;
+mod fingerprint;
 mod helper_buf;
+mod padding;
+mod reader;
+mod split;
+mod writer;
 
-use chacha20poly1305::aead::stream::{DecryptorBE32, EncryptorBE32};
-use chacha20poly1305::aead::Buffer;
-use chacha20poly1305::ChaCha20Poly1305;
-use helper_buf::HelperBuf;
+use reader::Reader;
+use writer::Writer;
 
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
 use pin_project::pin_project;
-use std::io::ErrorKind;
+use sha2::Sha256;
 use std::pin::Pin;
-use std::task::{ready, Context, Poll};
-use tokio::io::{AsyncBufRead, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+pub use chunk_size::ChunkSize;
+pub use cipher::Cipher;
+pub use fingerprint::Fingerprint;
+pub use padding::Padding;
+pub use split::{ReadHalf, WriteHalf};
 
 /// How many bytes larger an encrypted chunk is
 /// from an unencrypted chunk.
 const TAG_SIZE: usize = 16;
 
+/// HKDF info prefix used to derive each new key.
+const REKEY_INFO: &[u8] = b"gday_encryption rekey";
+
+/// HKDF info used to derive the key that confirms the handshake.
+const CONFIRM_INFO: &[u8] = b"gday_encryption confirm";
+
+/// HKDF info prefix used to derive the key of each direction of a session.
+const DIRECTION_INFO: &[u8] = b"gday_encryption direction";
+
+/// Prefix of the handshake hash that [`EncryptedStream::encrypt_connection()`]
+/// binds to every chunk.
+const SESSION_CONTEXT: &[u8] = b"gday_encryption session";
+
+/// When an [`EncryptedStream`] switches to a new key for the data it sends.
+///
+/// A new key is used once either limit is reached.
+/// The limits are only checked when a chunk is sent,
+/// so an idle stream keeps its key until it sends again.
+/// The peer follows each rekey regardless of its own policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RekeyPolicy {
+    /// Most bytes of ciphertext sent with one key.
+    pub max_bytes: u64,
+    /// Longest time one key is used for.
+    pub max_duration: Duration,
+}
+
+impl RekeyPolicy {
+    /// Never switch to a new key.
+    pub const NEVER: Self = Self {
+        max_bytes: u64::MAX,
+        max_duration: Duration::MAX,
+    };
+}
+
+impl Default for RekeyPolicy {
+    /// Rekeys every GiB, or every 10 minutes.
+    fn default() -> Self {
+        Self {
+            max_bytes: 1 << 30,
+            max_duration: Duration::from_secs(10 * 60),
+        }
+    }
+}
+
+/// Error establishing an [`EncryptedStream`].
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The peer proved it has a different key, or the handshake was tampered with.
+    #[error(
+        "Peer's key doesn't match. Either the wrong key was used, \
+        or someone tampered with the connection."
+    )]
+    PeerKeyMismatch,
+
+    /// The peer sent back this peer's own handshake,
+    /// which only happens if someone is reflecting the connection.
+    #[error("Peer sent back the same handshake. Someone may be tampering with the connection.")]
+    ReflectedHandshake,
+
+    /// IO Error
+    #[error("IO Error: {0}")]
+    IO(#[from] std::io::Error),
+}
+
+/// What peers offer each other in [`EncryptedStream::encrypt_connection_with_options()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HandshakeOptions {
+    /// The ciphers to offer.
+    /// [`Cipher::ChaCha20Poly1305`] is always offered.
+    pub ciphers: Vec<Cipher>,
+    /// The padding to ask for.
+    pub padding: Padding,
+    /// Extra data to authenticate with every chunk, such as a protocol version
+    /// or a hash of the key exchange. Must be the same on both peers.
+    pub associated_data: Vec<u8>,
+    /// The largest chunk size to accept.
+    pub chunk_size: ChunkSize,
+}
+
+impl Default for HandshakeOptions {
+    /// Offers [`Cipher::accelerated()`] without padding,
+    /// in [`ChunkSize::Standard`] chunks.
+    fn default() -> Self {
+        Self {
+            ciphers: Cipher::accelerated(),
+            padding: Padding::None,
+            associated_data: Vec::new(),
+            chunk_size: ChunkSize::Standard,
+        }
+    }
+}
+
 /// A simple encrypted wrapper around an IO stream.
-/// Uses [`chacha20poly1305`] with the [`chacha20poly1305::aead::stream`].
+/// Uses a [`Cipher`] with the [`chacha20poly1305::aead::stream`] construction.
 #[pin_project]
 pub struct EncryptedStream<T> {
     /// The IO stream to be wrapped in encryption
     #[pin]
     inner: T,
 
-    /// Stream decryptor
-    decryptor: DecryptorBE32<ChaCha20Poly1305>,
+    /// Decrypts the data received from `inner`
+    reader: Reader,
 
-    /// Stream encryptor
-    encryptor: EncryptorBE32<ChaCha20Poly1305>,
+    /// Encrypts the data sent to `inner`
+    writer: Writer,
 
-    /// Encrypted data received from the inner IO stream.
-    /// - Invariant: Never stores a complete chunk(s).
-    ///
-    /// As soon as full chunk(s) are read, moves and decrypts them
-    /// into `decrypted`.
-    received: HelperBuf,
-
-    /// Data that has been decrypted from `received`.
-    /// - Invariant: This must be empty when calling
-    ///   [`Self::inner_read()`]
-    decrypted: HelperBuf,
-
-    /// Data to be sent. Encrypted only when [`Self::flushing`].
-    /// - Invariant: the first 2 bytes are always
-    ///   reserved for the length
-    /// - Invariant: Data can only be appended when `flushing` is false.
-    to_send: HelperBuf,
-
-    /// Is the content of `to_send` encrypted and ready to write?
-    flushing: bool,
+    /// Fingerprint of this session
+    fingerprint: Fingerprint,
 }
 
 impl<T> EncryptedStream<T> {
@@ -111,23 +245,187 @@ impl<T> EncryptedStream<T> {
     /// - Both peers must have the same `key` and `nonce`.
     /// - The `key` must be a cryptographically random secret.
     /// - The `nonce` shouldn't be reused, but doesn't need to be secret.
+    /// - Data sent and received are encrypted with the same `key` and `nonce`,
+    ///   so only send in one direction, or use [`Self::encrypt_connection()`],
+    ///   which gives each direction its own key.
     ///
     /// - See [`Self::encrypt_connection()`] if you'd like an auto-generatcan't createed nonce.
+    ///
+    /// The stream uses [`Cipher::ChaCha20Poly1305`],
+    /// and rekeys with [`RekeyPolicy::default()`].
+    /// See [`Self::with_rekey_policy()`] to change that.
     pub fn new(io_stream: T, key: &[u8; 32], nonce: &[u8; 7]) -> Self {
-        let mut to_send = HelperBuf::with_capacity(u16::MAX as usize + 2);
-        // add 2 bytes for length header to uphold invariant
-        to_send.extend_from_slice(&[0, 0]).expect("unreachable");
+        Self::new_with_cipher(io_stream, Cipher::ChaCha20Poly1305, key, nonce)
+    }
 
+    /// Like [`Self::new()`], but encrypts with `cipher`,
+    /// which both peers must use.
+    pub fn new_with_cipher(io_stream: T, cipher: Cipher, key: &[u8; 32], nonce: &[u8; 7]) -> Self {
         Self {
             inner: io_stream,
-            decryptor: DecryptorBE32::new(key.into(), nonce.into()),
-            encryptor: EncryptorBE32::new(key.into(), nonce.into()),
-            received: HelperBuf::with_capacity(u16::MAX as usize + 2),
-            decrypted: HelperBuf::with_capacity(u16::MAX as usize + 2),
-            to_send,
-            flushing: false,
+            reader: Reader::new(cipher, key, nonce),
+            writer: Writer::new(cipher, key, nonce),
+            fingerprint: Fingerprint::new(key, &[nonce]),
         }
     }
+
+    /// Returns the cipher this stream uses.
+    pub fn cipher(&self) -> Cipher {
+        self.writer.cipher()
+    }
+
+    /// Returns this session's [`Fingerprint`], which both peers can display,
+    /// for their users to check that they match.
+    ///
+    /// For a stream made with [`Self::new()`], it depends on the key and nonce.
+    /// For one made with [`Self::encrypt_connection()`], it also depends on the handshake.
+    pub fn fingerprint(&self) -> Fingerprint {
+        self.fingerprint
+    }
+
+    /// Sets how this stream pads the chunks it sends,
+    /// and expects the peer to pad the chunks it receives.
+    ///
+    /// Both peers must use the same padding.
+    pub fn with_padding(mut self, padding: Padding) -> Self {
+        self.reader.padding = padding;
+        self.writer.padding = padding;
+        self
+    }
+
+    /// Sets data that is authenticated with every chunk, but not sent,
+    /// such as a hash of the key exchange that created `key`.
+    ///
+    /// Both peers must use the same `aad`, or reading fails
+    /// with [`std::io::ErrorKind::InvalidData`].
+    pub fn with_associated_data(mut self, aad: &[u8]) -> Self {
+        self.reader.aad = aad.to_vec();
+        self.writer.aad = aad.to_vec();
+        self
+    }
+
+    /// Returns how this stream pads its chunks.
+    pub fn padding(&self) -> Padding {
+        self.writer.padding
+    }
+
+    /// Sets how large the chunks this stream sends and receives can be.
+    ///
+    /// Both peers must use the same chunk size.
+    ///
+    /// # Panics
+    /// Panics if any data has already been sent or received.
+    pub fn with_chunk_size(mut self, chunk_size: ChunkSize) -> Self {
+        self.reader.set_chunk_size(chunk_size);
+        self.writer.set_chunk_size(chunk_size);
+        self
+    }
+
+    /// Returns how large this stream's chunks can be.
+    pub fn chunk_size(&self) -> ChunkSize {
+        self.writer.chunk_size()
+    }
+
+    /// Sets when this stream switches to a new key for the data it sends.
+    pub fn with_rekey_policy(mut self, policy: RekeyPolicy) -> Self {
+        self.writer.rekey_policy = policy;
+        self
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> EncryptedStream<T> {
+    /// Splits this stream into a [`ReadHalf`] and a [`WriteHalf`],
+    /// which can be used concurrently, for example from different tasks.
+    ///
+    /// Uses [`tokio::io::split()`] on the inner IO stream.
+    /// Use [`ReadHalf::unsplit()`] to rejoin them.
+    pub fn into_split(
+        self,
+    ) -> (
+        ReadHalf<tokio::io::ReadHalf<T>>,
+        WriteHalf<tokio::io::WriteHalf<T>>,
+    ) {
+        let (read, write) = tokio::io::split(self.inner);
+        (
+            ReadHalf::new(read, self.reader, self.fingerprint),
+            WriteHalf::new(write, self.writer, self.fingerprint),
+        )
+    }
+}
+
+/// Returns the associated data for chunks sent by the peer that sent `sender`
+/// in a handshake where the peers sent `hello_1` and `hello_2`.
+///
+/// Both peers compute the same value, since the hellos are put in a fixed order.
+fn session_aad(hello_1: &[u8], hello_2: &[u8], sender: &[u8], associated_data: &[u8]) -> Vec<u8> {
+    use sha2::Digest;
+    let (first, second) = if hello_1 <= hello_2 {
+        (hello_1, hello_2)
+    } else {
+        (hello_2, hello_1)
+    };
+    Sha256::new()
+        .chain_update(SESSION_CONTEXT)
+        .chain_update(first)
+        .chain_update(second)
+        .chain_update(sender)
+        .chain_update(associated_data)
+        .finalize()
+        .to_vec()
+}
+
+/// Returns a MAC of `session_aad`, keyed with a key derived from `shared_key`,
+/// which proves a peer knows `shared_key` and saw the same handshake.
+fn confirmation(shared_key: &[u8; 32], session_aad: &[u8]) -> Hmac<Sha256> {
+    let mut key = [0; 32];
+    Hkdf::<Sha256>::new(None, shared_key)
+        .expand(CONFIRM_INFO, &mut key)
+        .expect("unreachable: 32 bytes is a valid HKDF-SHA256 output length");
+    let mut mac = Hmac::<Sha256>::new_from_slice(&key).expect("HMAC can take a key of any size");
+    mac.update(session_aad);
+    mac
+}
+
+/// Returns the key that the peer that sent the chunks
+/// with associated data `session_aad` encrypts them with.
+fn direction_key(shared_key: &[u8; 32], session_aad: &[u8]) -> [u8; 32] {
+    let mut key = [0; 32];
+    Hkdf::<Sha256>::new(None, shared_key)
+        .expand_multi_info(&[DIRECTION_INFO, session_aad], &mut key)
+        .expect("unreachable: 32 bytes is a valid HKDF-SHA256 output length");
+    key
+}
+
+/// Returns the key that follows `key`
+/// when switching to key number `epoch`.
+fn next_key(key: &[u8; 32], epoch: u64) -> [u8; 32] {
+    let mut next = [0; 32];
+    Hkdf::<Sha256>::new(None, key)
+        .expand_multi_info(&[REKEY_INFO, &epoch.to_be_bytes()], &mut next)
+        .expect("unreachable: 32 bytes is a valid HKDF-SHA256 output length");
+    next
+}
+
+impl<T: AsyncWrite + Unpin> EncryptedStream<T> {
+    /// Sends `len` bytes read from `file`, like [`tokio::io::AsyncWriteExt::write_all()`].
+    ///
+    /// Reads straight into this stream's encryption buffer,
+    /// so unlike reading `file` into a buffer and writing that,
+    /// the data isn't copied an extra time.
+    /// Doesn't flush, so call [`tokio::io::AsyncWriteExt::flush()`] after.
+    ///
+    /// Like reading `file` directly, this blocks while `file` is read.
+    /// Returns [`std::io::ErrorKind::UnexpectedEof`]
+    /// if `file` ends before `len` bytes are read.
+    pub async fn write_file_chunks(
+        &mut self,
+        file: &mut impl std::io::Read,
+        len: u64,
+    ) -> std::io::Result<()> {
+        self.writer
+            .write_file_chunks(Pin::new(&mut self.inner), file, len)
+            .await
+    }
 }
 
 impl<T: AsyncRead + AsyncWrite + Unpin> EncryptedStream<T> {
@@ -140,204 +438,156 @@ impl<T: AsyncRead + AsyncWrite + Unpin> EncryptedStream<T> {
     /// The nonce is set to the XOR of the two byte strings.
     /// Both peers must call this function for this to work.
     ///
+    /// The peers also agree on a cipher, offering [`Cipher::accelerated()`],
+    /// and don't pad their chunks unless the peer asks to.
+    ///
     /// - See [`Self::new()`] if you'd like to provide your own nonce.
-    pub async fn encrypt_connection(
+    pub async fn encrypt_connection(io_stream: T, shared_key: &[u8; 32]) -> Result<Self, Error> {
+        Self::encrypt_connection_with_options(io_stream, shared_key, &HandshakeOptions::default())
+            .await
+    }
+
+    /// Like [`Self::encrypt_connection()`], but offers the peer `options`.
+    ///
+    /// The peers use the fastest cipher they both offer, preferring
+    /// AES-256-GCM over [`Cipher::ChaCha20Poly1305`], which is always offered.
+    /// They use the stronger [`Padding`] that either peer asks for,
+    /// and the smaller [`ChunkSize`].
+    ///
+    /// Every chunk is authenticated with a hash of what both peers sent
+    /// during this handshake, of which peer sent the chunk,
+    /// and of [`HandshakeOptions::associated_data`].
+    /// Each peer encrypts with its own key, derived from `shared_key` and that hash,
+    /// so the two directions never reuse a key and nonce.
+    ///
+    /// Returns [`Error::PeerKeyMismatch`] if the peer's confirmation of this
+    /// hash doesn't match, which means it has a different `shared_key`
+    /// or `associated_data`, or that the handshake was tampered with.
+    pub async fn encrypt_connection_with_options(
         mut io_stream: T,
         shared_key: &[u8; 32],
-    ) -> std::io::Result<Self> {
-        // Exchange random seeds with peer.
+        options: &HandshakeOptions,
+    ) -> Result<Self, Error> {
+        // Exchange random seeds, the ciphers each peer offers,
+        // and the padding and chunk size each peer asks for.
         let my_seed: [u8; 7] = rand::random();
-        io_stream.write_all(&my_seed).await?;
+        let my_mask = Cipher::mask(&options.ciphers);
+        let mut hello = [0; 10];
+        hello[..7].copy_from_slice(&my_seed);
+        hello[7] = my_mask;
+        hello[8] = options.padding.to_byte();
+        hello[9] = options.chunk_size.to_byte();
+        io_stream.write_all(&hello).await?;
+        io_stream.flush().await?;
+        let mut peer_hello = [0; 10];
+        io_stream.read_exact(&mut peer_hello).await?;
+        if peer_hello == hello {
+            return Err(Error::ReflectedHandshake);
+        }
+        let send_aad = session_aad(&hello, &peer_hello, &hello, &options.associated_data);
+        let receive_aad = session_aad(&hello, &peer_hello, &peer_hello, &options.associated_data);
+
+        // Confirm both peers have the same key and saw the same handshake.
+        io_stream
+            .write_all(&confirmation(shared_key, &send_aad).finalize().into_bytes())
+            .await?;
         io_stream.flush().await?;
-        let mut peer_seed = [0; 7];
-        io_stream.read_exact(&mut peer_seed).await?;
+        let mut peer_confirmation = [0; 32];
+        io_stream.read_exact(&mut peer_confirmation).await?;
+        confirmation(shared_key, &receive_aad)
+            .verify_slice(&peer_confirmation)
+            .map_err(|_| Error::PeerKeyMismatch)?;
+
+        let cipher = Cipher::negotiate(my_mask, peer_hello[7]);
+        let padding = std::cmp::max(options.padding, Padding::from_byte(peer_hello[8]));
+        let chunk_size = std::cmp::min(options.chunk_size, ChunkSize::from_byte(peer_hello[9]));
 
         // The nonce is the XOR of the random seeds.
-        peer_seed
+        let mut nonce = [0; 7];
+        nonce
             .iter_mut()
-            .zip(my_seed.iter())
-            .for_each(|(x1, x2)| *x1 ^= *x2);
-
-        Ok(Self::new(io_stream, shared_key, &peer_seed))
+            .zip(my_seed.iter().zip(&peer_hello[..7]))
+            .for_each(|(n, (x1, x2))| *n = *x1 ^ *x2);
+
+        // Both peers' fingerprints cover the same handshake, in the same order.
+        let (first, second) = if send_aad <= receive_aad {
+            (&send_aad, &receive_aad)
+        } else {
+            (&receive_aad, &send_aad)
+        };
+        let fingerprint = Fingerprint::new(shared_key, &[first, second]);
+
+        let send_key = direction_key(shared_key, &send_aad);
+        let receive_key = direction_key(shared_key, &receive_aad);
+        let mut stream = Self {
+            inner: io_stream,
+            reader: Reader::new(cipher, &receive_key, &nonce),
+            writer: Writer::new(cipher, &send_key, &nonce),
+            fingerprint,
+        }
+        .with_padding(padding)
+        .with_chunk_size(chunk_size);
+        stream.writer.aad = send_aad;
+        stream.reader.aad = receive_aad;
+        Ok(stream)
     }
 }
 
 impl<T: AsyncRead> AsyncRead for EncryptedStream<T> {
     fn poll_read(
-        mut self: Pin<&mut Self>,
+        self: Pin<&mut Self>,
         cx: &mut Context<'_>,
         buf: &mut tokio::io::ReadBuf<'_>,
     ) -> Poll<std::io::Result<()>> {
-        // if we're out of decrypted data, read more
-        if self.decrypted.is_empty() {
-            ready!(self.as_mut().inner_read(cx))?;
-        }
-
         let me = self.project();
-
-        let num_bytes = std::cmp::min(me.decrypted.len(), buf.remaining());
-        buf.put_slice(&me.decrypted[0..num_bytes]);
-        me.decrypted.consume(num_bytes);
-        Poll::Ready(Ok(()))
+        me.reader.poll_read(me.inner, cx, buf)
     }
 }
 
 impl<T: AsyncRead> AsyncBufRead for EncryptedStream<T> {
     fn consume(self: std::pin::Pin<&mut EncryptedStream<T>>, amt: usize) {
-        self.project().decrypted.consume(amt);
+        self.project().reader.consume(amt);
     }
 
-    fn poll_fill_buf(
-        mut self: Pin<&mut Self>,
-        cx: &mut Context<'_>,
-    ) -> Poll<std::io::Result<&[u8]>> {
-        // if we're out of plaintext, read more
-        if self.decrypted.is_empty() {
-            ready!(self.as_mut().inner_read(cx))?;
-        }
-
-        Poll::Ready(Ok(self.project().decrypted))
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<&[u8]>> {
+        let me = self.project();
+        me.reader.poll_fill_buf(me.inner, cx)
     }
 }
 
 impl<T: AsyncWrite> AsyncWrite for EncryptedStream<T> {
     fn poll_write(
-        mut self: Pin<&mut Self>,
+        self: Pin<&mut Self>,
         cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<Result<usize, std::io::Error>> {
-        // Finish up any flushes before proceeding.
-        if self.flushing {
-            ready!(self.as_mut().flush_write_buf(cx))?;
-        }
-
-        let me = self.as_mut().project();
+        let me = self.project();
+        me.writer.poll_write(me.inner, cx, buf)
+    }
 
-        let bytes_taken = std::cmp::min(buf.len(), me.to_send.spare_capacity().len() - TAG_SIZE);
-        me.to_send
-            .extend_from_slice(&buf[0..bytes_taken])
-            .expect("unreachable");
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[std::io::IoSlice<'_>],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        let me = self.project();
+        me.writer.poll_write_vectored(me.inner, cx, bufs)
+    }
 
-        // if `to_send` is full, start the process
-        // of flushing it
-        if me.to_send.spare_capacity().len() - TAG_SIZE == 0 {
-            let _ = self.flush_write_buf(cx)?;
-        }
-        Poll::Ready(Ok(bytes_taken))
+    fn is_write_vectored(&self) -> bool {
+        true
     }
 
-    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
-        ready!(self.as_mut().flush_write_buf(cx))?;
-        self.project().inner.poll_flush(cx)
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let me = self.project();
+        me.writer.poll_flush(me.inner, cx)
     }
 
     fn poll_shutdown(
-        mut self: Pin<&mut Self>,
+        self: Pin<&mut Self>,
         cx: &mut Context<'_>,
     ) -> Poll<Result<(), std::io::Error>> {
-        ready!(self.as_mut().poll_flush(cx))?;
-        self.project().inner.poll_shutdown(cx)
-    }
-}
-
-impl<T: AsyncRead> EncryptedStream<T> {
-    /// Reads and decrypts at least 1 new chunk into [`Self::decrypted`],
-    /// unless reached EOF or the inner reader returned [`Poll::Pending`].
-    /// - Invariant: must only be called when [`Self::decrypted`] is empty,
-    ///     so that it has space to decrypt into.
-    fn inner_read(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
-        let mut me = self.project();
-
-        // ensure we have the full buffer to decrypt into
-        debug_assert!(me.decrypted.is_empty());
-
-        // maximize room to receive more data
-        me.received.left_align();
-
-        /// If there is a full chunk at the beginning of `data`,
-        /// returns it.
-        fn peek_cipher_chunk(data: &[u8]) -> Option<&[u8]> {
-            let len: [u8; 2] = data.get(0..2)?.try_into().expect("unreachable");
-            let len = u16::from_be_bytes(len) as usize;
-            data.get(2..2 + len)
-        }
-
-        // read at least the first 2-byte header
-        while peek_cipher_chunk(me.received).is_none() {
-            let mut read_buf = ReadBuf::new(me.received.spare_capacity());
-            ready!(me.inner.as_mut().poll_read(cx, &mut read_buf))?;
-            let bytes_read = read_buf.filled().len();
-            if bytes_read == 0 {
-                if me.received.is_empty() {
-                    // EOF at chunk boundary
-                    return Poll::Ready(Ok(()));
-                } else {
-                    // Unexpected EOF within chunk
-                    return Poll::Ready(Err(std::io::Error::new(
-                        std::io::ErrorKind::UnexpectedEof,
-                        "Unexpected EOF within encrypted chunk.",
-                    )));
-                }
-            }
-            me.received.increase_len(bytes_read);
-        }
-
-        // decrypt all chunks in `self.received`
-        while let Some(cipher_chunk) = peek_cipher_chunk(me.received) {
-            // decrypt in `self.decrypted`
-            let mut decryption_space = me.decrypted.split_off_aead_buf(me.decrypted.len());
-
-            decryption_space
-                .extend_from_slice(cipher_chunk)
-                .expect("Unreachable");
-
-            me.received.consume(cipher_chunk.len() + 2);
-
-            me.decryptor
-                .decrypt_next_in_place(&[], &mut decryption_space)
-                .map_err(|_| std::io::Error::new(ErrorKind::InvalidData, "Decryption error"))?;
-        }
-
-        Poll::Ready(Ok(()))
-    }
-}
-
-impl<T: AsyncWrite> EncryptedStream<T> {
-    /// Encrypts and fully flushes [`Self::to_send`].
-    fn flush_write_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
-        let mut me = self.project();
-
-        // If we're just starting a flush,
-        // encrypt the data.
-        if !*me.flushing {
-            *me.flushing = true;
-            // encrypt in place
-            let mut msg = me.to_send.split_off_aead_buf(2);
-            me.encryptor
-                .encrypt_next_in_place(&[], &mut msg)
-                .map_err(|_| std::io::Error::new(ErrorKind::InvalidData, "Encryption error"))?;
-
-            let len = u16::try_from(msg.len())
-                .expect("unreachable: Length of message buffer should always fit in u16")
-                .to_be_bytes();
-
-            // write length to header
-            me.to_send[0..2].copy_from_slice(&len);
-        }
-
-        // write until empty
-        while !me.to_send.is_empty() {
-            let bytes_written = ready!(me.inner.as_mut().poll_write(cx, me.to_send))?;
-            me.to_send.consume(bytes_written);
-        }
-
-        // if we've reached this point, flushing has finished
-        *me.flushing = false;
-
-        // make space for new header
-        me.to_send
-            .extend_from_slice(&[0, 0])
-            .expect("unreachable: to_send must have space for the header.");
-        Poll::Ready(Ok(()))
+        let me = self.project();
+        me.writer.poll_shutdown(me.inner, cx)
     }
 }
@@ -0,0 +1,78 @@
+/// How an [`crate::EncryptedStream`] pads its chunks, so that an observer
+/// can't tell exactly how much data each one holds.
+///
+/// Without padding, the length of each encrypted chunk reveals
+/// how many bytes were flushed, which can reveal the sizes of sent files.
+/// Both peers must use the same padding. [`crate::EncryptedStream::encrypt_connection()`]
+/// takes care of that by using the stronger padding either peer asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Padding {
+    /// Chunks aren't padded.
+    #[default]
+    None,
+    /// Each chunk is padded to a power of two, and at least 512 bytes,
+    /// which hides all but the rough size of each chunk.
+    /// Costs at most double the bandwidth on small flushes.
+    PowerOfTwo,
+    /// Each chunk is padded to the largest chunk size,
+    /// which hides the size of each chunk completely.
+    /// Costs a full chunk, 64 KiB or more, per flush.
+    Max,
+}
+
+/// Smallest padded chunk with [`Padding::PowerOfTwo`].
+const MIN_BUCKET: usize = 512;
+
+impl Padding {
+    /// Returns this padding as a byte sent during the handshake.
+    pub(crate) fn to_byte(self) -> u8 {
+        match self {
+            Self::None => 0,
+            Self::PowerOfTwo => 1,
+            Self::Max => 2,
+        }
+    }
+
+    /// Returns the padding sent during the handshake as `byte`.
+    ///
+    /// Unknown values become [`Padding::Max`],
+    /// since they'd come from a peer asking for more privacy.
+    pub(crate) fn from_byte(byte: u8) -> Self {
+        match byte {
+            0 => Self::None,
+            1 => Self::PowerOfTwo,
+            _ => Self::Max,
+        }
+    }
+
+    /// Returns how long a chunk holding `len` bytes and a trailer
+    /// of `trailer_len` bytes should be, given that chunks can't exceed `max_len`.
+    pub(crate) fn padded_len(self, len: usize, trailer_len: usize, max_len: usize) -> usize {
+        let len = len + trailer_len;
+        match self {
+            Self::None => len,
+            Self::PowerOfTwo => len.next_power_of_two().clamp(MIN_BUCKET, max_len),
+            Self::Max => max_len,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Padding;
+
+    #[test]
+    fn test_padded_len() {
+        assert_eq!(Padding::PowerOfTwo.padded_len(0, 2, 65_000), 512);
+        assert_eq!(Padding::PowerOfTwo.padded_len(510, 2, 65_000), 512);
+        assert_eq!(Padding::PowerOfTwo.padded_len(511, 2, 65_000), 1024);
+        assert_eq!(Padding::PowerOfTwo.padded_len(509, 4, 65_000), 1024);
+        assert_eq!(Padding::PowerOfTwo.padded_len(40_000, 2, 65_000), 65_000);
+        assert_eq!(Padding::Max.padded_len(3, 2, 65_000), 65_000);
+
+        for padding in [Padding::None, Padding::PowerOfTwo, Padding::Max] {
+            assert_eq!(Padding::from_byte(padding.to_byte()), padding);
+        }
+        assert_eq!(Padding::from_byte(200), Padding::Max);
+    }
+}
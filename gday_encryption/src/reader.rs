@@ -0,0 +1,249 @@
+use crate::chunk_size::ChunkSize;
+use crate::cipher::{Cipher, Decryptor};
+use crate::helper_buf::HelperBuf;
+use crate::padding::Padding;
+use crate::{next_key, TAG_SIZE};
+use chacha20poly1305::aead::Buffer;
+use std::io::ErrorKind;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, ReadBuf};
+
+/// The receiving half of an encrypted stream's state.
+pub(crate) struct Reader {
+    /// Stream decryptor
+    decryptor: Decryptor,
+
+    /// The cipher the peer encrypts with
+    cipher: Cipher,
+
+    /// Encrypted data received from the inner IO stream.
+    /// - Invariant: Never stores a complete chunk(s).
+    ///
+    /// As soon as full chunk(s) are read, moves and decrypts them
+    /// into `decrypted`.
+    received: HelperBuf,
+
+    /// Data that has been decrypted from `received`.
+    /// - Invariant: This must be empty when calling
+    ///   [`Self::inner_read()`]
+    decrypted: HelperBuf,
+
+    /// The nonce both peers use with every key.
+    nonce: [u8; 7],
+
+    /// The key currently used by [`Self::decryptor`].
+    key: [u8; 32],
+
+    /// How many times [`Self::decryptor`] has switched to a new key.
+    epoch: u64,
+
+    /// How the peer pads each chunk.
+    pub padding: Padding,
+
+    /// Associated data authenticated with every chunk.
+    pub aad: Vec<u8>,
+
+    /// How large the peer's chunks can be.
+    chunk_size: ChunkSize,
+}
+
+impl Reader {
+    pub fn new(cipher: Cipher, key: &[u8; 32], nonce: &[u8; 7]) -> Self {
+        Self {
+            decryptor: Decryptor::new(cipher, key, nonce),
+            cipher,
+            received: Self::new_buf(ChunkSize::Standard),
+            decrypted: Self::new_buf(ChunkSize::Standard),
+            nonce: *nonce,
+            key: *key,
+            epoch: 0,
+            padding: Padding::None,
+            aad: Vec::new(),
+            chunk_size: ChunkSize::Standard,
+        }
+    }
+
+    /// Returns a buffer that fits a chunk of `chunk_size` and its header.
+    fn new_buf(chunk_size: ChunkSize) -> HelperBuf {
+        HelperBuf::with_capacity(chunk_size.header_len() + chunk_size.max_len())
+    }
+
+    /// Sets how large the peer's chunks can be.
+    ///
+    /// # Panics
+    /// Panics if any data has been received yet.
+    pub fn set_chunk_size(&mut self, chunk_size: ChunkSize) {
+        assert!(
+            self.received.is_empty() && self.decrypted.is_empty(),
+            "The chunk size must be set before reading."
+        );
+        self.received = Self::new_buf(chunk_size);
+        self.decrypted = Self::new_buf(chunk_size);
+        self.chunk_size = chunk_size;
+    }
+
+    /// Returns how large the peer's chunks can be.
+    pub fn chunk_size(&self) -> ChunkSize {
+        self.chunk_size
+    }
+
+    /// Returns the cipher this reader decrypts with.
+    pub fn cipher(&self) -> Cipher {
+        self.cipher
+    }
+
+    /// Reads decrypted data from `inner` into `buf`.
+    pub fn poll_read(
+        &mut self,
+        inner: Pin<&mut impl AsyncRead>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        // if we're out of decrypted data, read more
+        if self.decrypted.is_empty() {
+            ready!(self.inner_read(inner, cx))?;
+        }
+
+        let num_bytes = std::cmp::min(self.decrypted.len(), buf.remaining());
+        buf.put_slice(&self.decrypted[0..num_bytes]);
+        self.decrypted.consume(num_bytes);
+        Poll::Ready(Ok(()))
+    }
+
+    /// Returns the decrypted data, reading more from `inner` if there is none.
+    pub fn poll_fill_buf(
+        &mut self,
+        inner: Pin<&mut impl AsyncRead>,
+        cx: &mut Context<'_>,
+    ) -> Poll<std::io::Result<&[u8]>> {
+        // if we're out of plaintext, read more
+        if self.decrypted.is_empty() {
+            ready!(self.inner_read(inner, cx))?;
+        }
+
+        Poll::Ready(Ok(&self.decrypted))
+    }
+
+    /// Marks `amt` bytes of the decrypted data as read.
+    pub fn consume(&mut self, amt: usize) {
+        self.decrypted.consume(amt);
+    }
+
+    /// Reads and decrypts chunks into [`Self::decrypted`] until it holds some data,
+    /// unless reached EOF or the inner reader returned [`Poll::Pending`].
+    /// - Invariant: must only be called when [`Self::decrypted`] is empty,
+    ///   so that it has space to decrypt into.
+    fn inner_read(
+        &mut self,
+        mut inner: Pin<&mut impl AsyncRead>,
+        cx: &mut Context<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        // ensure we have the full buffer to decrypt into
+        debug_assert!(self.decrypted.is_empty());
+
+        // Some chunks, like rekey frames and empty flushes, decrypt to nothing,
+        // so keep reading until there's data, or EOF.
+        loop {
+            let eof = !ready!(self.read_chunks(inner.as_mut(), cx))?;
+            if eof || !self.decrypted.is_empty() {
+                return Poll::Ready(Ok(()));
+            }
+        }
+    }
+
+    /// Reads and decrypts at least 1 new chunk into [`Self::decrypted`].
+    /// Returns `false` if reached EOF instead.
+    fn read_chunks(
+        &mut self,
+        mut inner: Pin<&mut impl AsyncRead>,
+        cx: &mut Context<'_>,
+    ) -> Poll<std::io::Result<bool>> {
+        // maximize room to receive more data
+        self.received.left_align();
+
+        let chunk_size = self.chunk_size;
+        let header_len = chunk_size.header_len();
+
+        /// If there is a full chunk at the beginning of `data`,
+        /// returns it, and whether it's a rekey frame.
+        fn peek_cipher_chunk(data: &[u8], chunk_size: ChunkSize) -> Option<(&[u8], bool)> {
+            let header_len = chunk_size.header_len();
+            let len = chunk_size.decode(data.get(0..header_len)?);
+            // A real chunk always has a tag, so a zero length marks a rekey frame
+            if len == 0 {
+                Some((data.get(header_len..header_len + TAG_SIZE)?, true))
+            } else {
+                Some((data.get(header_len..header_len + len)?, false))
+            }
+        }
+
+        // read at least the first header
+        while peek_cipher_chunk(&self.received, chunk_size).is_none() {
+            let mut read_buf = ReadBuf::new(self.received.spare_capacity());
+            ready!(inner.as_mut().poll_read(cx, &mut read_buf))?;
+            let bytes_read = read_buf.filled().len();
+            if bytes_read == 0 {
+                if self.received.spare_capacity().is_empty() {
+                    // The peer's chunk is longer than its chunk size allows
+                    return Poll::Ready(Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "Encrypted chunk is too long.",
+                    )));
+                } else if self.received.is_empty() {
+                    // EOF at chunk boundary
+                    return Poll::Ready(Ok(false));
+                } else {
+                    // Unexpected EOF within chunk
+                    return Poll::Ready(Err(std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        "Unexpected EOF within encrypted chunk.",
+                    )));
+                }
+            }
+            self.received.increase_len(bytes_read);
+        }
+
+        // decrypt all chunks in `self.received`
+        while let Some((cipher_chunk, is_rekey)) = peek_cipher_chunk(&self.received, chunk_size) {
+            // decrypt in `self.decrypted`
+            let mut decryption_space = self.decrypted.split_off_aead_buf(self.decrypted.len());
+
+            decryption_space
+                .extend_from_slice(cipher_chunk)
+                .expect("Unreachable");
+
+            self.received.consume(cipher_chunk.len() + header_len);
+
+            if is_rekey {
+                // the peer ended its stream with this key,
+                // so switch to the next one
+                self.epoch += 1;
+                self.key = next_key(&self.key, self.epoch);
+                let next = Decryptor::new(self.cipher, &self.key, &self.nonce);
+                std::mem::replace(&mut self.decryptor, next)
+                    .decrypt_last(&self.aad, &mut decryption_space)
+                    .map_err(|_| std::io::Error::new(ErrorKind::InvalidData, "Decryption error"))?;
+            } else {
+                self.decryptor
+                    .decrypt_next(&self.aad, &mut decryption_space)
+                    .map_err(|_| std::io::Error::new(ErrorKind::InvalidData, "Decryption error"))?;
+
+                if self.padding != Padding::None {
+                    // remove the padding, using the trailer's length
+                    let padded_len = decryption_space.len();
+                    let len = padded_len
+                        .checked_sub(header_len)
+                        .map(|i| chunk_size.decode(&decryption_space[i..]))
+                        .filter(|len| len + header_len <= padded_len)
+                        .ok_or_else(|| {
+                            std::io::Error::new(ErrorKind::InvalidData, "Invalid chunk padding")
+                        })?;
+                    decryption_space.truncate(len);
+                }
+            }
+        }
+
+        Poll::Ready(Ok(true))
+    }
+}
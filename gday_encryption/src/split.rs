@@ -0,0 +1,179 @@
+use crate::reader::Reader;
+use crate::writer::Writer;
+use crate::{ChunkSize, Cipher, EncryptedStream, Fingerprint};
+use pin_project::pin_project;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncWrite, ReadBuf};
+
+/// The reading half of an [`EncryptedStream`],
+/// created by [`EncryptedStream::into_split()`].
+#[pin_project]
+pub struct ReadHalf<T> {
+    /// The reading half of the inner IO stream
+    #[pin]
+    inner: T,
+
+    /// Decrypts the data received from `inner`
+    reader: Reader,
+
+    /// Fingerprint of this session
+    fingerprint: Fingerprint,
+}
+
+/// The writing half of an [`EncryptedStream`],
+/// created by [`EncryptedStream::into_split()`].
+#[pin_project]
+pub struct WriteHalf<T> {
+    /// The writing half of the inner IO stream
+    #[pin]
+    inner: T,
+
+    /// Encrypts the data sent to `inner`
+    writer: Writer,
+
+    /// Fingerprint of this session
+    fingerprint: Fingerprint,
+}
+
+impl<T> ReadHalf<T> {
+    pub(crate) fn new(inner: T, reader: Reader, fingerprint: Fingerprint) -> Self {
+        Self {
+            inner,
+            reader,
+            fingerprint,
+        }
+    }
+
+    /// Returns the cipher this stream uses.
+    pub fn cipher(&self) -> Cipher {
+        self.reader.cipher()
+    }
+
+    /// Returns how large this stream's chunks can be.
+    pub fn chunk_size(&self) -> ChunkSize {
+        self.reader.chunk_size()
+    }
+
+    /// Returns this session's fingerprint.
+    /// See [`EncryptedStream::fingerprint()`].
+    pub fn fingerprint(&self) -> Fingerprint {
+        self.fingerprint
+    }
+}
+
+impl<T> WriteHalf<T> {
+    pub(crate) fn new(inner: T, writer: Writer, fingerprint: Fingerprint) -> Self {
+        Self {
+            inner,
+            writer,
+            fingerprint,
+        }
+    }
+
+    /// Returns the cipher this stream uses.
+    pub fn cipher(&self) -> Cipher {
+        self.writer.cipher()
+    }
+
+    /// Returns how large this stream's chunks can be.
+    pub fn chunk_size(&self) -> ChunkSize {
+        self.writer.chunk_size()
+    }
+
+    /// Returns this session's fingerprint.
+    /// See [`EncryptedStream::fingerprint()`].
+    pub fn fingerprint(&self) -> Fingerprint {
+        self.fingerprint
+    }
+}
+
+impl<T: AsyncWrite + Unpin> WriteHalf<T> {
+    /// Sends `len` bytes read from `file`.
+    /// See [`EncryptedStream::write_file_chunks()`].
+    pub async fn write_file_chunks(
+        &mut self,
+        file: &mut impl std::io::Read,
+        len: u64,
+    ) -> std::io::Result<()> {
+        self.writer
+            .write_file_chunks(Pin::new(&mut self.inner), file, len)
+            .await
+    }
+}
+
+impl<T: Unpin> ReadHalf<tokio::io::ReadHalf<T>> {
+    /// Rejoins the halves created by [`EncryptedStream::into_split()`].
+    ///
+    /// Any buffered data is kept, so no data is lost.
+    ///
+    /// # Panics
+    /// Panics if `write` didn't come from the same [`EncryptedStream`].
+    pub fn unsplit(self, write: WriteHalf<tokio::io::WriteHalf<T>>) -> EncryptedStream<T> {
+        EncryptedStream {
+            inner: self.inner.unsplit(write.inner),
+            reader: self.reader,
+            writer: write.writer,
+            fingerprint: self.fingerprint,
+        }
+    }
+}
+
+impl<T: AsyncRead> AsyncRead for ReadHalf<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let me = self.project();
+        me.reader.poll_read(me.inner, cx, buf)
+    }
+}
+
+impl<T: AsyncRead> AsyncBufRead for ReadHalf<T> {
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        self.project().reader.consume(amt);
+    }
+
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<&[u8]>> {
+        let me = self.project();
+        me.reader.poll_fill_buf(me.inner, cx)
+    }
+}
+
+impl<T: AsyncWrite> AsyncWrite for WriteHalf<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        let me = self.project();
+        me.writer.poll_write(me.inner, cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[std::io::IoSlice<'_>],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        let me = self.project();
+        me.writer.poll_write_vectored(me.inner, cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        true
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let me = self.project();
+        me.writer.poll_flush(me.inner, cx)
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        let me = self.project();
+        me.writer.poll_shutdown(me.inner, cx)
+    }
+}
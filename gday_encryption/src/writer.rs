@@ -0,0 +1,326 @@
+use crate::chunk_size::ChunkSize;
+use crate::cipher::{Cipher, Encryptor};
+use crate::helper_buf::HelperBuf;
+use crate::padding::Padding;
+use crate::{next_key, RekeyPolicy, TAG_SIZE};
+use chacha20poly1305::aead::Buffer;
+use std::io::{ErrorKind, IoSlice, Read};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Instant;
+use tokio::io::AsyncWrite;
+
+/// The sending half of an encrypted stream's state.
+pub(crate) struct Writer {
+    /// Stream encryptor
+    encryptor: Encryptor,
+
+    /// The cipher to encrypt with
+    cipher: Cipher,
+
+    /// Data to be sent. Encrypted only when [`Self::flushing`].
+    /// - Invariant: the first [`ChunkSize::header_len()`] bytes are always
+    ///   reserved for the length
+    /// - Invariant: Data can only be appended when `flushing` is false.
+    to_send: HelperBuf,
+
+    /// Is the content of `to_send` encrypted and ready to write?
+    flushing: bool,
+
+    /// The nonce both peers use with every key.
+    nonce: [u8; 7],
+
+    /// The key currently used by [`Self::encryptor`].
+    key: [u8; 32],
+
+    /// How many times [`Self::encryptor`] has switched to a new key.
+    epoch: u64,
+
+    /// When to switch [`Self::encryptor`] to a new key.
+    pub rekey_policy: RekeyPolicy,
+
+    /// Bytes sent with the current [`Self::key`].
+    sent_with_key: u64,
+
+    /// When [`Self::key`] started being used.
+    key_since: Instant,
+
+    /// How to pad each chunk before encrypting it.
+    pub padding: Padding,
+
+    /// Associated data authenticated with every chunk.
+    pub aad: Vec<u8>,
+
+    /// How large each chunk can be.
+    chunk_size: ChunkSize,
+}
+
+impl Writer {
+    pub fn new(cipher: Cipher, key: &[u8; 32], nonce: &[u8; 7]) -> Self {
+        Self {
+            encryptor: Encryptor::new(cipher, key, nonce),
+            cipher,
+            to_send: Self::new_buf(ChunkSize::Standard),
+            flushing: false,
+            nonce: *nonce,
+            key: *key,
+            epoch: 0,
+            rekey_policy: RekeyPolicy::default(),
+            sent_with_key: 0,
+            key_since: Instant::now(),
+            padding: Padding::None,
+            aad: Vec::new(),
+            chunk_size: ChunkSize::Standard,
+        }
+    }
+
+    /// Returns an empty [`Self::to_send`] for chunks of `chunk_size`.
+    fn new_buf(chunk_size: ChunkSize) -> HelperBuf {
+        let header_len = chunk_size.header_len();
+        // reserve room for a rekey frame after a full chunk
+        let rekey_frame_len = header_len + TAG_SIZE;
+        let mut to_send =
+            HelperBuf::with_capacity(header_len + chunk_size.max_len() + rekey_frame_len);
+        // add bytes for length header to uphold invariant
+        to_send.spare_capacity()[..header_len].fill(0);
+        to_send.increase_len(header_len);
+        to_send
+    }
+
+    /// Sets how large each chunk can be.
+    ///
+    /// # Panics
+    /// Panics if any data has been written yet.
+    pub fn set_chunk_size(&mut self, chunk_size: ChunkSize) {
+        assert!(
+            !self.flushing && self.to_send.len() == self.chunk_size.header_len(),
+            "The chunk size must be set before writing."
+        );
+        self.to_send = Self::new_buf(chunk_size);
+        self.chunk_size = chunk_size;
+    }
+
+    /// Returns how large each chunk can be.
+    pub fn chunk_size(&self) -> ChunkSize {
+        self.chunk_size
+    }
+
+    /// Returns the cipher this writer encrypts with.
+    pub fn cipher(&self) -> Cipher {
+        self.cipher
+    }
+
+    /// Buffers some of `buf` to be encrypted and written to `inner`.
+    pub fn poll_write(
+        &mut self,
+        inner: Pin<&mut impl AsyncWrite>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        self.poll_write_vectored(inner, cx, &[IoSlice::new(buf)])
+    }
+
+    /// Buffers some of `bufs`, in order, to be encrypted and written to `inner`.
+    pub fn poll_write_vectored(
+        &mut self,
+        mut inner: Pin<&mut impl AsyncWrite>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        // Finish up any flushes before proceeding.
+        if self.flushing {
+            ready!(self.flush_write_buf(inner.as_mut(), cx))?;
+        }
+
+        // copy as many of the buffers as fit into one chunk
+        let mut bytes_taken = 0;
+        for buf in bufs {
+            let taken = std::cmp::min(buf.len(), self.room());
+            self.to_send
+                .extend_from_slice(&buf[0..taken])
+                .expect("unreachable");
+            bytes_taken += taken;
+            if taken < buf.len() {
+                break;
+            }
+        }
+
+        // if `to_send` is full, start the process
+        // of flushing it
+        if self.room() == 0 {
+            let _ = self.flush_write_buf(inner, cx)?;
+        }
+        Poll::Ready(Ok(bytes_taken))
+    }
+
+    /// Reads up to `len` bytes of `file` straight into [`Self::to_send`],
+    /// to be encrypted and written to `inner`.
+    /// Returns how many bytes were read, which is 0 only at the end of `file`.
+    pub fn poll_write_from(
+        &mut self,
+        mut inner: Pin<&mut impl AsyncWrite>,
+        cx: &mut Context<'_>,
+        file: &mut impl Read,
+        len: u64,
+    ) -> Poll<std::io::Result<usize>> {
+        // Finish up any flushes before proceeding.
+        if self.flushing {
+            ready!(self.flush_write_buf(inner.as_mut(), cx))?;
+        }
+
+        let room = std::cmp::min(self.room() as u64, len) as usize;
+        let bytes_read = loop {
+            match file.read(&mut self.to_send.spare_capacity()[..room]) {
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                result => break result?,
+            }
+        };
+        self.to_send.increase_len(bytes_read);
+
+        // if `to_send` is full, start the process
+        // of flushing it
+        if self.room() == 0 {
+            let _ = self.flush_write_buf(inner, cx)?;
+        }
+        Poll::Ready(Ok(bytes_read))
+    }
+
+    /// Reads exactly `len` bytes of `file` straight into [`Self::to_send`],
+    /// encrypting and writing them to `inner` as each chunk fills up.
+    pub async fn write_file_chunks(
+        &mut self,
+        mut inner: Pin<&mut impl AsyncWrite>,
+        file: &mut impl Read,
+        mut len: u64,
+    ) -> std::io::Result<()> {
+        while len > 0 {
+            let bytes_read =
+                std::future::poll_fn(|cx| self.poll_write_from(inner.as_mut(), cx, file, len))
+                    .await?;
+            if bytes_read == 0 {
+                return Err(std::io::Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "File ended before all of its bytes were sent.",
+                ));
+            }
+            len -= bytes_read as u64;
+        }
+        Ok(())
+    }
+
+    /// Encrypts and writes all buffered data, then flushes `inner`.
+    pub fn poll_flush(
+        &mut self,
+        mut inner: Pin<&mut impl AsyncWrite>,
+        cx: &mut Context<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        ready!(self.flush_write_buf(inner.as_mut(), cx))?;
+        inner.poll_flush(cx)
+    }
+
+    /// Flushes, then shuts down `inner`.
+    pub fn poll_shutdown(
+        &mut self,
+        mut inner: Pin<&mut impl AsyncWrite>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        ready!(self.poll_flush(inner.as_mut(), cx))?;
+        inner.poll_shutdown(cx)
+    }
+
+    /// Returns how many more bytes of data fit in [`Self::to_send`].
+    fn room(&mut self) -> usize {
+        let header_len = self.chunk_size.header_len();
+        // a padded chunk ends with a trailer as long as the header
+        let trailer_len = if self.padding == Padding::None {
+            0
+        } else {
+            header_len
+        };
+        let rekey_frame_len = header_len + TAG_SIZE;
+        self.to_send.spare_capacity().len() - TAG_SIZE - rekey_frame_len - trailer_len
+    }
+
+    /// Encrypts and fully flushes [`Self::to_send`].
+    fn flush_write_buf(
+        &mut self,
+        mut inner: Pin<&mut impl AsyncWrite>,
+        cx: &mut Context<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let header_len = self.chunk_size.header_len();
+
+        // If we're just starting a flush,
+        // encrypt the data.
+        if !self.flushing {
+            self.flushing = true;
+            if self.padding != Padding::None {
+                self.pad();
+            }
+            // encrypt in place
+            let mut msg = self.to_send.split_off_aead_buf(header_len);
+            self.encryptor
+                .encrypt_next(&self.aad, &mut msg)
+                .map_err(|_| std::io::Error::new(ErrorKind::InvalidData, "Encryption error"))?;
+            let len = msg.len();
+
+            // write length to header
+            self.chunk_size
+                .encode(len, &mut self.to_send[0..header_len]);
+
+            self.sent_with_key += len as u64;
+            if self.sent_with_key >= self.rekey_policy.max_bytes
+                || self.key_since.elapsed() >= self.rekey_policy.max_duration
+            {
+                self.rekey()?;
+            }
+        }
+
+        // write until empty
+        while !self.to_send.is_empty() {
+            let bytes_written = ready!(inner.as_mut().poll_write(cx, &self.to_send))?;
+            self.to_send.consume(bytes_written);
+        }
+
+        // if we've reached this point, flushing has finished
+        self.flushing = false;
+
+        // make space for new header
+        self.to_send.increase_len(header_len);
+        Poll::Ready(Ok(()))
+    }
+
+    /// Pads the data in [`Self::to_send`] with zeros,
+    /// followed by a trailer holding the data's length.
+    fn pad(&mut self) {
+        let header_len = self.chunk_size.header_len();
+        let max_plaintext = self.chunk_size.max_len() - TAG_SIZE;
+        let len = self.to_send.len() - header_len;
+        let padded_len = self.padding.padded_len(len, header_len, max_plaintext);
+        // the zeros, followed by space for the trailer
+        let added = padded_len - len;
+        self.to_send.spare_capacity()[..added].fill(0);
+        self.to_send.increase_len(added);
+        let trailer_start = self.to_send.len() - header_len;
+        self.chunk_size
+            .encode(len, &mut self.to_send[trailer_start..]);
+    }
+
+    /// Appends a rekey frame to [`Self::to_send`],
+    /// ending this key's stream, then switches to the next key.
+    fn rekey(&mut self) -> std::io::Result<()> {
+        // a rekey frame starts with a zero length header
+        let header_len = self.chunk_size.header_len();
+        self.to_send.spare_capacity()[..header_len].fill(0);
+        self.to_send.increase_len(header_len);
+        let mut tag = self.to_send.split_off_aead_buf(self.to_send.len());
+        self.epoch += 1;
+        self.key = next_key(&self.key, self.epoch);
+        let next = Encryptor::new(self.cipher, &self.key, &self.nonce);
+        std::mem::replace(&mut self.encryptor, next)
+            .encrypt_last(&self.aad, &mut tag)
+            .map_err(|_| std::io::Error::new(ErrorKind::InvalidData, "Encryption error"))?;
+        self.sent_with_key = 0;
+        self.key_since = Instant::now();
+        Ok(())
+    }
+}
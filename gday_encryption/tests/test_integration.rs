@@ -1,7 +1,8 @@
 #![forbid(unsafe_code)]
 #![warn(clippy::all)]
-use gday_encryption::EncryptedStream;
+use gday_encryption::{ChunkSize, EncryptedStream, Error, HandshakeOptions, Padding, RekeyPolicy};
 use rand::{RngCore, SeedableRng};
+use std::time::Duration;
 use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
 
 /// Transfer `bytes` over [`EncryptedStream`],
@@ -142,3 +143,596 @@ async fn test_unexpected_eof() {
     // confirm its an error
     assert!(result.is_err());
 }
+
+/// Transfer bytes in both directions while
+/// each side keeps switching to new keys.
+#[tokio::test]
+async fn test_rekey() {
+    let nonce: [u8; 7] = [42; 7];
+    let key: [u8; 32] = [123; 32];
+
+    // A pseudorandom test vector
+    let mut rng = rand::rngs::StdRng::seed_from_u64(30);
+    let mut bytes = vec![0_u8; 300_000];
+    rng.fill_bytes(&mut bytes);
+
+    let (pipe_a, pipe_b) = tokio::io::duplex(10_000);
+    let mut stream_a = EncryptedStream::new(pipe_a, &key, &nonce).with_rekey_policy(RekeyPolicy {
+        max_bytes: 5_000,
+        max_duration: Duration::MAX,
+    });
+    let mut stream_b = EncryptedStream::new(pipe_b, &key, &nonce).with_rekey_policy(RekeyPolicy {
+        max_bytes: u64::MAX,
+        max_duration: Duration::ZERO,
+    });
+
+    let bytes_clone = bytes.clone();
+    let handle = tokio::spawn(async move {
+        for chunk in bytes_clone.chunks(1_000) {
+            stream_a.write_all(chunk).await.unwrap();
+            stream_a.flush().await.unwrap();
+        }
+        stream_a.shutdown().await.unwrap();
+
+        let mut received = Vec::new();
+        stream_a.read_to_end(&mut received).await.unwrap();
+        received
+    });
+
+    let mut received = vec![0; bytes.len()];
+    stream_b.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, bytes);
+
+    for chunk in bytes.chunks(3_000) {
+        stream_b.write_all(chunk).await.unwrap();
+        stream_b.flush().await.unwrap();
+    }
+    stream_b.shutdown().await.unwrap();
+
+    assert_eq!(handle.await.unwrap(), bytes);
+}
+
+/// A rekey frame must be authenticated by the old key.
+#[tokio::test]
+async fn test_forged_rekey() {
+    let nonce: [u8; 7] = [42; 7];
+    let key: [u8; 32] = [123; 32];
+    let mut pipe = Vec::new();
+    let mut writer = EncryptedStream::new(&mut pipe, &key, &nonce).with_rekey_policy(RekeyPolicy {
+        max_bytes: 0,
+        max_duration: Duration::MAX,
+    });
+
+    // the message is followed by a rekey frame
+    let msg = b"fjsdka;8u39fsdkaf";
+    writer.write_all(msg).await.unwrap();
+    writer.flush().await.unwrap();
+
+    // tamper with the rekey frame's tag
+    *pipe.last_mut().unwrap() ^= 1;
+
+    let mut reader = EncryptedStream::new(&pipe[..], &key, &nonce);
+    let mut buf = Vec::new();
+    let result = reader.read_to_end(&mut buf).await;
+    assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+}
+
+/// Peers only use AES-256-GCM if both offer it,
+/// and it works like ChaCha20Poly1305.
+#[cfg(feature = "aes-gcm")]
+#[tokio::test]
+async fn test_aes_gcm() {
+    use gday_encryption::Cipher;
+
+    let key: [u8; 32] = [123; 32];
+
+    // A pseudorandom test vector
+    let mut rng = rand::rngs::StdRng::seed_from_u64(35);
+    let mut bytes = vec![0_u8; 300_000];
+    rng.fill_bytes(&mut bytes);
+
+    for (ciphers_a, ciphers_b, expected) in [
+        (
+            &[Cipher::Aes256Gcm][..],
+            &[Cipher::Aes256Gcm][..],
+            Cipher::Aes256Gcm,
+        ),
+        (&[Cipher::Aes256Gcm], &[], Cipher::ChaCha20Poly1305),
+    ] {
+        let (pipe_a, pipe_b) = tokio::io::duplex(10_000);
+        let bytes_clone = bytes.clone();
+        let handle = tokio::spawn(async move {
+            let options = HandshakeOptions {
+                ciphers: ciphers_a.to_vec(),
+                padding: Padding::None,
+                associated_data: Vec::new(),
+                chunk_size: ChunkSize::Standard,
+            };
+            let stream = EncryptedStream::encrypt_connection_with_options(pipe_a, &key, &options);
+            let mut stream = stream.await.unwrap().with_rekey_policy(RekeyPolicy {
+                max_bytes: 50_000,
+                max_duration: Duration::MAX,
+            });
+            assert_eq!(stream.cipher(), expected);
+            for chunk in bytes_clone.chunks(7_000) {
+                stream.write_all(chunk).await.unwrap();
+                stream.flush().await.unwrap();
+            }
+            stream.shutdown().await.unwrap();
+        });
+
+        let options = HandshakeOptions {
+            ciphers: ciphers_b.to_vec(),
+            padding: Padding::None,
+            associated_data: Vec::new(),
+            chunk_size: ChunkSize::Standard,
+        };
+        let mut stream = EncryptedStream::encrypt_connection_with_options(pipe_b, &key, &options)
+            .await
+            .unwrap();
+        assert_eq!(stream.cipher(), expected);
+        let mut received = Vec::new();
+        stream.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, bytes);
+        handle.await.unwrap();
+    }
+
+    // the ciphers aren't interchangeable
+    let nonce: [u8; 7] = [42; 7];
+    let mut pipe = Vec::new();
+    let mut writer = EncryptedStream::new_with_cipher(&mut pipe, Cipher::Aes256Gcm, &key, &nonce);
+    writer.write_all(b"fjsdka;8u39fsdkaf").await.unwrap();
+    writer.flush().await.unwrap();
+    let mut reader = EncryptedStream::new(&pipe[..], &key, &nonce);
+    assert!(reader.read_to_end(&mut Vec::new()).await.is_err());
+}
+
+/// Read and write concurrently from split halves,
+/// then rejoin them.
+#[tokio::test]
+async fn test_into_split() {
+    let key: [u8; 32] = [123; 32];
+
+    // A pseudorandom test vector
+    let mut rng = rand::rngs::StdRng::seed_from_u64(40);
+    let mut bytes = vec![0_u8; 300_000];
+    rng.fill_bytes(&mut bytes);
+
+    let (pipe_a, pipe_b) = tokio::io::duplex(10_000);
+    let (stream_a, stream_b) = tokio::join!(
+        EncryptedStream::encrypt_connection(pipe_a, &key),
+        EncryptedStream::encrypt_connection(pipe_b, &key),
+    );
+    let (mut read_a, write_a) = stream_a.unwrap().into_split();
+    let (mut read_b, write_b) = stream_b.unwrap().into_split();
+
+    // both peers write everything before reading, which
+    // would deadlock on the small pipe without splitting
+    let send = |mut writer: gday_encryption::WriteHalf<_>, bytes: Vec<u8>| async move {
+        for chunk in bytes.chunks(7_000) {
+            writer.write_all(chunk).await.unwrap();
+            writer.flush().await.unwrap();
+        }
+        writer
+    };
+    let write_a = tokio::spawn(send(write_a, bytes.clone()));
+    let write_b = tokio::spawn(send(write_b, bytes.clone()));
+
+    let mut received_a = vec![0; bytes.len()];
+    let mut received_b = vec![0; bytes.len()];
+    read_a.read_exact(&mut received_a).await.unwrap();
+    read_b.read_exact(&mut received_b).await.unwrap();
+    assert_eq!(received_a, bytes);
+    assert_eq!(received_b, bytes);
+
+    // the rejoined streams keep working
+    let mut stream_a = read_a.unsplit(write_a.await.unwrap());
+    let mut stream_b = read_b.unsplit(write_b.await.unwrap());
+    stream_a.write_all(b"Hello!").await.unwrap();
+    stream_a.shutdown().await.unwrap();
+    let mut received = Vec::new();
+    stream_b.read_to_end(&mut received).await.unwrap();
+    assert_eq!(received, b"Hello!");
+}
+
+/// Padded chunks hide how much data was flushed,
+/// and the peers use the stronger padding either asks for.
+#[tokio::test]
+async fn test_padding() {
+    let key: [u8; 32] = [123; 32];
+
+    for (padding_a, padding_b, expected) in [
+        (Padding::None, Padding::PowerOfTwo, Padding::PowerOfTwo),
+        (Padding::Max, Padding::PowerOfTwo, Padding::Max),
+    ] {
+        let (pipe_a, pipe_b) = tokio::io::duplex(10_000);
+        let options_a = HandshakeOptions {
+            padding: padding_a,
+            ..HandshakeOptions::default()
+        };
+        let options_b = HandshakeOptions {
+            padding: padding_b,
+            ..HandshakeOptions::default()
+        };
+        let (stream_a, stream_b) = tokio::join!(
+            EncryptedStream::encrypt_connection_with_options(pipe_a, &key, &options_a),
+            EncryptedStream::encrypt_connection_with_options(pipe_b, &key, &options_b),
+        );
+        let (mut stream_a, mut stream_b) = (stream_a.unwrap(), stream_b.unwrap());
+        assert_eq!(stream_a.padding(), expected);
+        assert_eq!(stream_b.padding(), expected);
+
+        let messages: [&[u8]; 4] = [b"", b"a", &[7; 600], &[9; 100_000]];
+        let handle = tokio::spawn(async move {
+            for msg in messages {
+                stream_a.write_all(msg).await.unwrap();
+                stream_a.flush().await.unwrap();
+            }
+            stream_a.shutdown().await.unwrap();
+        });
+        let mut received = Vec::new();
+        stream_b.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, messages.concat());
+        handle.await.unwrap();
+    }
+
+    // flushes of different sizes produce the same ciphertext length
+    let nonce: [u8; 7] = [42; 7];
+    let mut lens = Vec::new();
+    for msg in [&b"a"[..], &[5; 300]] {
+        let mut pipe = Vec::new();
+        let mut writer =
+            EncryptedStream::new(&mut pipe, &key, &nonce).with_padding(Padding::PowerOfTwo);
+        writer.write_all(msg).await.unwrap();
+        writer.flush().await.unwrap();
+        lens.push(pipe.len());
+    }
+    assert_eq!(lens[0], lens[1]);
+}
+
+/// Chunks that decrypt to nothing, like empty flushes and rekey frames,
+/// aren't mistaken for EOF, even when they arrive on their own.
+#[tokio::test]
+async fn test_empty_chunks() {
+    let nonce: [u8; 7] = [42; 7];
+    let key: [u8; 32] = [123; 32];
+
+    // a pipe that delivers a byte at a time
+    let (pipe_a, pipe_b) = tokio::io::duplex(1);
+    let mut writer = EncryptedStream::new(pipe_a, &key, &nonce).with_rekey_policy(RekeyPolicy {
+        max_bytes: 0,
+        max_duration: Duration::MAX,
+    });
+    let handle = tokio::spawn(async move {
+        for msg in [&b"a"[..], b"", b"b"] {
+            writer.write_all(msg).await.unwrap();
+            writer.flush().await.unwrap();
+        }
+        writer.shutdown().await.unwrap();
+    });
+
+    let mut reader = EncryptedStream::new(pipe_b, &key, &nonce);
+    let mut received = Vec::new();
+    reader.read_to_end(&mut received).await.unwrap();
+    assert_eq!(received, b"ab");
+    handle.await.unwrap();
+}
+
+/// Chunks are only accepted with the same associated data,
+/// and within the session and direction they were sent in.
+#[tokio::test]
+async fn test_associated_data() {
+    let nonce: [u8; 7] = [42; 7];
+    let key: [u8; 32] = [123; 32];
+
+    // streams made with `new()` need the same associated data
+    for (aad_a, aad_b, ok) in [(&b"v1"[..], &b"v1"[..], true), (b"v1", b"v2", false)] {
+        let mut pipe = Vec::new();
+        let mut writer = EncryptedStream::new(&mut pipe, &key, &nonce).with_associated_data(aad_a);
+        writer.write_all(b"fjsdka;8u39fsdkaf").await.unwrap();
+        writer.flush().await.unwrap();
+        let mut reader = EncryptedStream::new(&pipe[..], &key, &nonce).with_associated_data(aad_b);
+        assert_eq!(reader.read_to_end(&mut Vec::new()).await.is_ok(), ok);
+    }
+
+    // so do streams made with `encrypt_connection_with_options()`,
+    // which notice during the handshake
+    for (aad_a, aad_b, ok) in [(&b"v1"[..], &b"v1"[..], true), (b"v1", b"v2", false)] {
+        let (pipe_a, pipe_b) = tokio::io::duplex(10_000);
+        let options_a = HandshakeOptions {
+            associated_data: aad_a.to_vec(),
+            ..HandshakeOptions::default()
+        };
+        let options_b = HandshakeOptions {
+            associated_data: aad_b.to_vec(),
+            ..HandshakeOptions::default()
+        };
+        let (stream_a, stream_b) = tokio::join!(
+            EncryptedStream::encrypt_connection_with_options(pipe_a, &key, &options_a),
+            EncryptedStream::encrypt_connection_with_options(pipe_b, &key, &options_b),
+        );
+        if !ok {
+            assert!(matches!(stream_a, Err(Error::PeerKeyMismatch)));
+            assert!(matches!(stream_b, Err(Error::PeerKeyMismatch)));
+            continue;
+        }
+        let (mut stream_a, mut stream_b) = (stream_a.unwrap(), stream_b.unwrap());
+        stream_a.write_all(b"Hello!").await.unwrap();
+        stream_a.shutdown().await.unwrap();
+        stream_b.read_to_end(&mut Vec::new()).await.unwrap();
+    }
+
+    // a peer's chunks can't be reflected back to it,
+    // by a relay that forwards the handshake
+    let (pipe_a, mut relay_a) = tokio::io::duplex(10_000);
+    let (pipe_b, mut relay_b) = tokio::io::duplex(10_000);
+    let relay = tokio::spawn(async move {
+        for len in [10, 32] {
+            let mut msg = vec![0; len];
+            relay_a.read_exact(&mut msg).await.unwrap();
+            relay_b.write_all(&msg).await.unwrap();
+            relay_b.read_exact(&mut msg).await.unwrap();
+            relay_a.write_all(&msg).await.unwrap();
+        }
+
+        let mut sent = [0; 256];
+        let len = relay_a.read(&mut sent).await.unwrap();
+        relay_a.write_all(&sent[..len]).await.unwrap();
+        relay_b
+    });
+    let (stream_a, stream_b) = tokio::join!(
+        EncryptedStream::encrypt_connection(pipe_a, &key),
+        EncryptedStream::encrypt_connection(pipe_b, &key),
+    );
+    let (mut stream_a, _stream_b) = (stream_a.unwrap(), stream_b.unwrap());
+    stream_a.write_all(b"Hello!").await.unwrap();
+    stream_a.flush().await.unwrap();
+    let _relay_b = relay.await.unwrap();
+    assert!(stream_a.read(&mut [0; 6]).await.is_err());
+}
+
+/// The peers of `encrypt_connection()` encrypt with different keys,
+/// so the same data sent both ways doesn't reuse a keystream.
+#[tokio::test]
+async fn test_direction_keys() {
+    let key: [u8; 32] = [123; 32];
+    let (pipe_a, mut relay_a) = tokio::io::duplex(10_000);
+    let (pipe_b, mut relay_b) = tokio::io::duplex(10_000);
+
+    // forwards the handshake, then returns the first chunk each peer sends
+    let relay = tokio::spawn(async move {
+        for len in [10, 32] {
+            let mut msg = vec![0; len];
+            relay_a.read_exact(&mut msg).await.unwrap();
+            relay_b.write_all(&msg).await.unwrap();
+            relay_b.read_exact(&mut msg).await.unwrap();
+            relay_a.write_all(&msg).await.unwrap();
+        }
+
+        let mut sent_a = [0; 256];
+        let len_a = relay_a.read(&mut sent_a).await.unwrap();
+        let mut sent_b = [0; 256];
+        let len_b = relay_b.read(&mut sent_b).await.unwrap();
+        (sent_a[..len_a].to_vec(), sent_b[..len_b].to_vec())
+    });
+    let (stream_a, stream_b) = tokio::join!(
+        EncryptedStream::encrypt_connection(pipe_a, &key),
+        EncryptedStream::encrypt_connection(pipe_b, &key),
+    );
+    let (mut stream_a, mut stream_b) = (stream_a.unwrap(), stream_b.unwrap());
+    for stream in [&mut stream_a, &mut stream_b] {
+        stream.write_all(b"Same data both ways").await.unwrap();
+        stream.flush().await.unwrap();
+    }
+
+    let (sent_a, sent_b) = relay.await.unwrap();
+    assert_eq!(sent_a.len(), sent_b.len());
+    // besides the tag, which the associated data changes
+    let body_len = sent_a.len() - 16;
+    assert_ne!(sent_a[..body_len], sent_b[..body_len]);
+}
+
+/// The handshake fails cleanly if the peers have different keys,
+/// or the handshake was tampered with.
+#[tokio::test]
+async fn test_key_mismatch() {
+    let (pipe_a, pipe_b) = tokio::io::duplex(10_000);
+    let (stream_a, stream_b) = tokio::join!(
+        EncryptedStream::encrypt_connection(pipe_a, &[1; 32]),
+        EncryptedStream::encrypt_connection(pipe_b, &[2; 32]),
+    );
+    assert!(matches!(stream_a, Err(Error::PeerKeyMismatch)));
+    assert!(matches!(stream_b, Err(Error::PeerKeyMismatch)));
+
+    // a relay that downgrades the padding B asks for
+    let (pipe_a, mut relay_a) = tokio::io::duplex(10_000);
+    let (pipe_b, mut relay_b) = tokio::io::duplex(10_000);
+    let relay = tokio::spawn(async move {
+        let mut hello = [0; 10];
+        relay_a.read_exact(&mut hello).await.unwrap();
+        relay_b.write_all(&hello).await.unwrap();
+        relay_b.read_exact(&mut hello).await.unwrap();
+        hello[8] = 0;
+        relay_a.write_all(&hello).await.unwrap();
+        tokio::io::copy_bidirectional(&mut relay_a, &mut relay_b)
+            .await
+            .unwrap();
+    });
+    let options = HandshakeOptions {
+        padding: Padding::Max,
+        ..HandshakeOptions::default()
+    };
+    let (stream_a, stream_b) = tokio::join!(
+        EncryptedStream::encrypt_connection(pipe_a, &[1; 32]),
+        EncryptedStream::encrypt_connection_with_options(pipe_b, &[1; 32], &options),
+    );
+    assert!(matches!(stream_a, Err(Error::PeerKeyMismatch)));
+    assert!(matches!(stream_b, Err(Error::PeerKeyMismatch)));
+    drop((stream_a, stream_b));
+    relay.await.unwrap();
+}
+
+/// Large chunks are used only if both peers ask for them,
+/// and carry data written with vectored writes.
+#[tokio::test]
+async fn test_large_chunks() {
+    let key: [u8; 32] = [123; 32];
+
+    // A pseudorandom test vector
+    let mut rng = rand::rngs::StdRng::seed_from_u64(36);
+    let mut bytes = vec![0_u8; 3_000_000];
+    rng.fill_bytes(&mut bytes);
+
+    for (chunk_size_a, chunk_size_b, expected) in [
+        (ChunkSize::Large, ChunkSize::Large, ChunkSize::Large),
+        (ChunkSize::Large, ChunkSize::Standard, ChunkSize::Standard),
+    ] {
+        let (pipe_a, pipe_b) = tokio::io::duplex(100_000);
+        let options_a = HandshakeOptions {
+            chunk_size: chunk_size_a,
+            padding: Padding::PowerOfTwo,
+            ..HandshakeOptions::default()
+        };
+        let options_b = HandshakeOptions {
+            chunk_size: chunk_size_b,
+            ..HandshakeOptions::default()
+        };
+        let (stream_a, stream_b) = tokio::join!(
+            EncryptedStream::encrypt_connection_with_options(pipe_a, &key, &options_a),
+            EncryptedStream::encrypt_connection_with_options(pipe_b, &key, &options_b),
+        );
+        let (stream_a, mut stream_b) = (stream_a.unwrap(), stream_b.unwrap());
+        assert_eq!(stream_a.chunk_size(), expected);
+        assert_eq!(stream_b.chunk_size(), expected);
+        assert!(tokio::io::AsyncWrite::is_write_vectored(&stream_a));
+        let mut stream_a = stream_a.with_rekey_policy(RekeyPolicy {
+            max_bytes: 1_500_000,
+            max_duration: Duration::MAX,
+        });
+
+        let bytes_clone = bytes.clone();
+        let handle = tokio::spawn(async move {
+            for part in bytes_clone.chunks(700_000) {
+                let (first, second) = part.split_at(part.len() / 3);
+                let mut bufs = [std::io::IoSlice::new(first), std::io::IoSlice::new(second)];
+                let mut bufs = &mut bufs[..];
+                while !bufs.is_empty() {
+                    let written = stream_a.write_vectored(bufs).await.unwrap();
+                    std::io::IoSlice::advance_slices(&mut bufs, written);
+                }
+                stream_a.flush().await.unwrap();
+            }
+            stream_a.shutdown().await.unwrap();
+        });
+
+        let mut received = Vec::new();
+        stream_b.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, bytes);
+        handle.await.unwrap();
+    }
+
+    // large chunks need fewer headers and tags
+    let nonce: [u8; 7] = [42; 7];
+    let mut lens = Vec::new();
+    for chunk_size in [ChunkSize::Standard, ChunkSize::Large] {
+        let mut pipe = Vec::new();
+        let mut writer = EncryptedStream::new(&mut pipe, &key, &nonce).with_chunk_size(chunk_size);
+        writer.write_all(&bytes[..1_000_000]).await.unwrap();
+        writer.flush().await.unwrap();
+        let mut reader = EncryptedStream::new(&pipe[..], &key, &nonce).with_chunk_size(chunk_size);
+        let mut received = Vec::new();
+        reader.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, &bytes[..1_000_000]);
+        lens.push(pipe.len());
+    }
+    assert!(lens[1] < lens[0]);
+
+    // chunk sizes aren't interchangeable
+    let mut pipe = Vec::new();
+    let mut writer =
+        EncryptedStream::new(&mut pipe, &key, &nonce).with_chunk_size(ChunkSize::Large);
+    writer.write_all(b"fjsdka;8u39fsdkaf").await.unwrap();
+    writer.flush().await.unwrap();
+    let mut reader = EncryptedStream::new(&pipe[..], &key, &nonce);
+    assert!(reader.read_to_end(&mut Vec::new()).await.is_err());
+}
+
+/// Data read straight from a file into the encryption buffer
+/// arrives intact, between ordinary writes.
+#[tokio::test]
+async fn test_write_file_chunks() {
+    let nonce: [u8; 7] = [42; 7];
+    let key: [u8; 32] = [123; 32];
+
+    // A pseudorandom test vector
+    let mut rng = rand::rngs::StdRng::seed_from_u64(37);
+    let mut bytes = vec![0_u8; 500_000];
+    rng.fill_bytes(&mut bytes);
+
+    for chunk_size in [ChunkSize::Standard, ChunkSize::Large] {
+        let (pipe_a, pipe_b) = tokio::io::duplex(10_000);
+        let writer = EncryptedStream::new(pipe_a, &key, &nonce).with_chunk_size(chunk_size);
+        let (_, mut writer) = writer.into_split();
+        let bytes_clone = bytes.clone();
+        let handle = tokio::spawn(async move {
+            let mut file = std::io::Cursor::new(bytes_clone);
+            writer.write_all(b"header").await.unwrap();
+            writer.write_file_chunks(&mut file, 300_000).await.unwrap();
+            writer.flush().await.unwrap();
+            writer.write_file_chunks(&mut file, 200_000).await.unwrap();
+
+            // the file has run out
+            let err = writer.write_file_chunks(&mut file, 1).await.unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+            writer.shutdown().await.unwrap();
+        });
+
+        let mut reader = EncryptedStream::new(pipe_b, &key, &nonce).with_chunk_size(chunk_size);
+        let mut received = Vec::new();
+        reader.read_to_end(&mut received).await.unwrap();
+        assert_eq!(&received[..6], b"header");
+        assert_eq!(&received[6..], bytes);
+        handle.await.unwrap();
+    }
+}
+
+/// Both peers get the same fingerprint,
+/// which differs between sessions.
+#[tokio::test]
+async fn test_fingerprint() {
+    let key: [u8; 32] = [123; 32];
+
+    let mut fingerprints = Vec::new();
+    for _ in 0..2 {
+        let (pipe_a, pipe_b) = tokio::io::duplex(10_000);
+        let (stream_a, stream_b) = tokio::join!(
+            EncryptedStream::encrypt_connection(pipe_a, &key),
+            EncryptedStream::encrypt_connection(pipe_b, &key),
+        );
+        let (stream_a, stream_b) = (stream_a.unwrap(), stream_b.unwrap());
+        assert_eq!(stream_a.fingerprint(), stream_b.fingerprint());
+        assert_eq!(
+            stream_a.fingerprint().to_string(),
+            stream_b.fingerprint().short_auth_string()
+        );
+
+        // the halves of a split stream keep it
+        let (read, write) = stream_a.into_split();
+        assert_eq!(read.fingerprint(), stream_b.fingerprint());
+        assert_eq!(write.fingerprint(), stream_b.fingerprint());
+        fingerprints.push(stream_b.fingerprint());
+    }
+    assert_ne!(fingerprints[0], fingerprints[1]);
+
+    // streams made with `new()` depend on the key and nonce
+    let nonce: [u8; 7] = [42; 7];
+    let fingerprint = EncryptedStream::new(Vec::<u8>::new(), &key, &nonce).fingerprint();
+    assert_eq!(
+        fingerprint,
+        EncryptedStream::new(Vec::<u8>::new(), &key, &nonce).fingerprint()
+    );
+    assert_ne!(
+        fingerprint,
+        EncryptedStream::new(Vec::<u8>::new(), &[1; 32], &nonce).fingerprint()
+    );
+}
@@ -0,0 +1,277 @@
+//! The tar archive that files are sent as when
+//! [`crate::FileResponseMsg::archive`] is set.
+//!
+//! Instead of being split into chunks, the accepted files are streamed
+//! in order as a single ustar archive, which saves a chunk header per
+//! few kilobytes and keeps tiny files packed together.
+//! Each file is a header block, followed by its contents padded to
+//! a multiple of [`BLOCK_LEN`]. Paths longer than the ustar format allows
+//! are given in a preceding GNU long name entry, and files of
+//! 8 GiB or more have their size in GNU base-256 notation.
+//! The archive ends with two zero blocks.
+//!
+//! The receiver doesn't trust the paths in the archive.
+//! It only checks that each entry is the next accepted file,
+//! with the length given in the offer.
+
+use crate::Error;
+use std::path::{Component, Path};
+use std::time::SystemTime;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Length of a tar block.
+pub(crate) const BLOCK_LEN: usize = 512;
+
+/// Name of a GNU long name entry.
+const LONG_NAME: &str = "././@LongLink";
+
+/// A file in the archive, as described by its header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Entry {
+    /// The file's path, with `/` separators
+    pub path: String,
+    /// Length of the file's contents
+    pub len: u64,
+}
+
+/// Returns `short_path` as it's stored in the archive,
+/// with `/` separators.
+pub(crate) fn archive_path(short_path: &Path) -> String {
+    let components: Vec<_> = short_path
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .collect();
+    components.join("/")
+}
+
+/// Returns the number of zero bytes that pad
+/// an entry with `len` bytes of contents.
+pub(crate) fn padding(len: u64) -> usize {
+    (BLOCK_LEN - (len % BLOCK_LEN as u64) as usize) % BLOCK_LEN
+}
+
+/// Returns the header blocks of a file at `path` with `len` bytes,
+/// and the given permissions and modification time.
+pub(crate) fn header(
+    path: &str,
+    len: u64,
+    mode: Option<u32>,
+    modified: Option<SystemTime>,
+) -> Vec<u8> {
+    let mut blocks = Vec::with_capacity(BLOCK_LEN);
+    let name = path.as_bytes();
+
+    // the name field holds 100 bytes
+    if name.len() > 100 {
+        let mut long_name = name.to_vec();
+        long_name.push(0);
+        blocks.extend_from_slice(&header_block(
+            LONG_NAME.as_bytes(),
+            long_name.len() as u64,
+            0,
+            0,
+            b'L',
+        ));
+        blocks.extend_from_slice(&long_name);
+        blocks.resize(blocks.len() + padding(long_name.len() as u64), 0);
+    }
+
+    let mtime = modified
+        .and_then(|modified| modified.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .map_or(0, |mtime| mtime.as_secs());
+    let mode = mode.unwrap_or(0o644) & 0o777;
+    blocks.extend_from_slice(&header_block(
+        &name[0..name.len().min(100)],
+        len,
+        mode,
+        mtime,
+        b'0',
+    ));
+    blocks
+}
+
+/// Returns one ustar header block.
+fn header_block(name: &[u8], len: u64, mode: u32, mtime: u64, kind: u8) -> [u8; BLOCK_LEN] {
+    let mut block = [0; BLOCK_LEN];
+    block[0..name.len()].copy_from_slice(name);
+    write_octal(&mut block[100..108], u64::from(mode));
+    write_octal(&mut block[108..116], 0);
+    write_octal(&mut block[116..124], 0);
+    write_size(&mut block[124..136], len);
+    write_octal(&mut block[136..148], mtime);
+    block[156] = kind;
+    block[257..263].copy_from_slice(b"ustar\0");
+    block[263..265].copy_from_slice(b"00");
+
+    // the checksum is computed with its own field set to spaces
+    block[148..156].fill(b' ');
+    let checksum: u32 = block.iter().map(|&b| u32::from(b)).sum();
+    write_octal(&mut block[148..155], u64::from(checksum));
+    block
+}
+
+/// Writes `value` into `field` as zero-padded octal digits followed by a NUL.
+fn write_octal(field: &mut [u8], value: u64) {
+    let width = field.len() - 1;
+    let digits = format!("{value:0width$o}");
+    field[0..width].copy_from_slice(&digits.as_bytes()[digits.len() - width..]);
+    field[width] = 0;
+}
+
+/// Writes `len` into the size `field`,
+/// in base-256 notation if it doesn't fit in octal.
+fn write_size(field: &mut [u8], len: u64) {
+    if len < 1 << 33 {
+        write_octal(field, len);
+    } else {
+        field.fill(0);
+        field[0] = 0x80;
+        let end = field.len();
+        field[end - 8..].copy_from_slice(&len.to_be_bytes());
+    }
+}
+
+/// Reads the next entry's header from `reader`,
+/// or returns `None` at the end of the archive.
+///
+/// Returns [`Error::InvalidArchive`] if the archive is malformed.
+pub(crate) async fn read_header(
+    reader: &mut (impl AsyncRead + Unpin),
+) -> Result<Option<Entry>, Error> {
+    let mut block = [0; BLOCK_LEN];
+    let mut long_name = None;
+    loop {
+        reader.read_exact(&mut block).await?;
+        if block == [0; BLOCK_LEN] {
+            // the second zero block
+            reader.read_exact(&mut block).await?;
+            if block != [0; BLOCK_LEN] || long_name.is_some() {
+                return Err(Error::InvalidArchive);
+            }
+            return Ok(None);
+        }
+
+        let stored_checksum = parse_octal(&block[148..156])?;
+        block[148..156].fill(b' ');
+        let checksum: u64 = block.iter().map(|&b| u64::from(b)).sum();
+        if checksum != stored_checksum {
+            return Err(Error::InvalidArchive);
+        }
+        let len = parse_size(&block[124..136])?;
+
+        match block[156] {
+            b'L' => {
+                // long names are short enough to read whole
+                if len > 0x10000 || long_name.is_some() {
+                    return Err(Error::InvalidArchive);
+                }
+                let mut name = vec![0; len as usize + padding(len)];
+                reader.read_exact(&mut name).await?;
+                name.truncate(len as usize);
+                long_name = Some(name);
+            }
+            b'0' | 0 => {
+                let name = match long_name {
+                    Some(name) => name,
+                    None => {
+                        let prefix = until_nul(&block[345..500]);
+                        let name = until_nul(&block[0..100]);
+                        if prefix.is_empty() {
+                            name.to_vec()
+                        } else {
+                            [prefix, b"/", name].concat()
+                        }
+                    }
+                };
+                let path = String::from_utf8(until_nul(&name).to_vec())
+                    .map_err(|_| Error::InvalidArchive)?;
+                return Ok(Some(Entry { path, len }));
+            }
+            _ => return Err(Error::InvalidArchive),
+        }
+    }
+}
+
+/// Returns `bytes` up to their first NUL.
+fn until_nul(bytes: &[u8]) -> &[u8] {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    &bytes[0..end]
+}
+
+/// Parses an octal `field`, which may be padded with spaces and NULs.
+fn parse_octal(field: &[u8]) -> Result<u64, Error> {
+    let digits = std::str::from_utf8(field).map_err(|_| Error::InvalidArchive)?;
+    let digits = digits.trim_matches(|c| c == ' ' || c == '\0');
+    u64::from_str_radix(digits, 8).map_err(|_| Error::InvalidArchive)
+}
+
+/// Parses a size `field`, which may be in octal or base-256 notation.
+fn parse_size(field: &[u8]) -> Result<u64, Error> {
+    if field[0] & 0x80 == 0 {
+        return parse_octal(field);
+    }
+    let end = field.len();
+    if field[0] != 0x80 || field[1..end - 8].iter().any(|&b| b != 0) {
+        return Err(Error::InvalidArchive);
+    }
+    Ok(u64::from_be_bytes(field[end - 8..].try_into().unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{archive_path, header, padding, read_header, Entry, BLOCK_LEN};
+    use std::path::Path;
+
+    #[tokio::test]
+    async fn test_headers() {
+        let long_path = "dir/".repeat(40) + "file.txt";
+        let mut archive = Vec::new();
+        for (path, len) in [("a.txt", 5), (long_path.as_str(), 600), ("huge", 1 << 40)] {
+            archive.extend(header(path, len, Some(0o755), None));
+            // contents, except of the huge file
+            if len < 1 << 40 {
+                archive.resize(archive.len() + len as usize + padding(len), 1);
+            }
+        }
+
+        let mut reader = &archive[..];
+        let entry = read_header(&mut reader).await.unwrap();
+        assert_eq!(
+            entry,
+            Some(Entry {
+                path: "a.txt".to_string(),
+                len: 5
+            })
+        );
+        reader = &reader[BLOCK_LEN..];
+        let entry = read_header(&mut reader).await.unwrap().unwrap();
+        assert_eq!(entry.path, long_path);
+        assert_eq!(entry.len, 600);
+        reader = &reader[2 * BLOCK_LEN..];
+        let entry = read_header(&mut reader).await.unwrap().unwrap();
+        assert_eq!(entry.len, 1 << 40);
+
+        // the end of the archive
+        let end = [0; 2 * BLOCK_LEN];
+        assert_eq!(read_header(&mut &end[..]).await.unwrap(), None);
+
+        // a corrupted header
+        let mut corrupted = header("a.txt", 5, None, None);
+        corrupted[0] = b'b';
+        assert!(read_header(&mut &corrupted[..]).await.is_err());
+    }
+
+    #[test]
+    fn test_archive_path() {
+        assert_eq!(
+            archive_path(Path::new("dir/sub/file.txt")),
+            "dir/sub/file.txt"
+        );
+        assert_eq!(padding(0), 0);
+        assert_eq!(padding(5), 507);
+        assert_eq!(padding(512), 0);
+    }
+}
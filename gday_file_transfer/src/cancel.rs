@@ -0,0 +1,123 @@
+use crate::{write_to, write_to_async, Error};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::sync::Arc;
+use tokio::io::AsyncWrite;
+use tokio::sync::watch;
+
+/// Why a peer cancelled the transfer.
+///
+/// Sent with [`write_cancel()`] or [`write_cancel_async()`], and
+/// received as [`Error::PeerCancelled`] by the other peer's next
+/// [`crate::read_from()`] or [`crate::read_from_async()`].
+/// Also sent by [`crate::send_files_cancellable()`] when cancelled
+/// partway through, and received as [`Error::PeerCancelled`]
+/// by the peer's [`crate::receive_files()`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CancelReason {
+    /// The user chose to cancel.
+    UserCancelled,
+
+    /// The peer ran out of storage space.
+    StorageFull,
+
+    /// The peer encountered an error, described by this string.
+    Error(String),
+}
+
+impl CancelReason {
+    /// Returns the [`CancelReason`] to send to the peer
+    /// when `err` aborts a transfer.
+    pub fn from_error(err: &Error) -> Self {
+        match err {
+            Error::IO(io_err) if io_err.kind() == std::io::ErrorKind::StorageFull => {
+                Self::StorageFull
+            }
+            Error::InsufficientDiskSpace { .. } => Self::StorageFull,
+            Error::Cancelled(reason) => reason.clone(),
+            other => Self::Error(other.to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for CancelReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UserCancelled => write!(f, "The user cancelled."),
+            Self::StorageFull => write!(f, "Ran out of storage space."),
+            Self::Error(msg) => write!(f, "{msg}"),
+        }
+    }
+}
+
+/// Lets one task stop a transfer that another task is running with
+/// [`crate::send_files_cancellable()`] or [`crate::receive_files_cancellable()`].
+///
+/// Clones share the same cancellation.
+#[derive(Debug, Clone)]
+pub struct CancelToken(Arc<watch::Sender<Option<CancelReason>>>);
+
+impl CancelToken {
+    /// Returns a token that isn't cancelled yet.
+    pub fn new() -> Self {
+        Self(Arc::new(watch::Sender::new(None)))
+    }
+
+    /// Cancels the transfers using this token because of `reason`.
+    ///
+    /// Only the first reason is kept.
+    pub fn cancel(&self, reason: CancelReason) {
+        self.0.send_if_modified(|cancelled| {
+            if cancelled.is_some() {
+                return false;
+            }
+            *cancelled = Some(reason);
+            true
+        });
+    }
+
+    /// Returns why the token was cancelled, or `None` if it wasn't.
+    pub fn reason(&self) -> Option<CancelReason> {
+        self.0.borrow().clone()
+    }
+
+    /// Resolves once the token is cancelled, with the reason.
+    pub async fn cancelled(&self) -> CancelReason {
+        let mut receiver = self.0.subscribe();
+        let reason = receiver
+            .wait_for(Option::is_some)
+            .await
+            .expect("The token holds the sender.");
+        reason.clone().expect("Waited for a reason.")
+    }
+}
+
+impl Default for CancelToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The message that carries a [`CancelReason`].
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct CancelMsg {
+    pub cancelled: CancelReason,
+}
+
+/// Tells the peer the transfer is being cancelled because of `reason`.
+///
+/// Should be the last thing written to `writer` before closing it.
+pub fn write_cancel(reason: CancelReason, writer: &mut impl Write) -> Result<(), Error> {
+    write_to(CancelMsg { cancelled: reason }, writer)
+}
+
+/// Asynchronously tells the peer the transfer is being cancelled because of `reason`.
+///
+/// Should be the last thing written to `writer` before closing it.
+pub async fn write_cancel_async(
+    reason: CancelReason,
+    writer: &mut (impl AsyncWrite + Unpin),
+) -> Result<(), Error> {
+    write_to_async(CancelMsg { cancelled: reason }, writer).await
+}
@@ -0,0 +1,210 @@
+//! Framing of file contents sent by [`crate::send_files()`].
+//!
+//! Each file is sent as one or more chunks. Every chunk starts with
+//! a [`ChunkHeader`] of [`HEADER_LEN`] bytes:
+//! - 4 bytes: big-endian index of the file in [`crate::FileOfferMsg::files`]
+//! - 8 bytes: big-endian offset in the file where the chunk's contents start
+//! - 1 byte: [`ChunkKind`] of the payload
+//! - 4 bytes: big-endian length of the payload that follows
+//!
+//! A chunk holds at most [`CHUNK_LEN`] bytes of the file,
+//! except for a [`ChunkKind::Copy`] chunk, which may stand for more.
+//! Since every chunk says where it belongs, the receiver doesn't
+//! have to trust the length of a partial download when resuming,
+//! and chunks of different files may be sent on different streams.
+//!
+//! Each file is sent as at least one chunk, even if it's empty,
+//! and ends once its last byte arrived.
+//!
+//! A sender that stops partway sends a [`ChunkKind::Abort`] chunk
+//! in place of the next one, so the receiver can tell it apart
+//! from a broken connection.
+
+use crate::{CancelReason, Error};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Maximum number of bytes of a file in a single chunk.
+pub(crate) const CHUNK_LEN: usize = 0x10000;
+
+/// Length of a [`ChunkHeader`] when sent.
+pub(crate) const HEADER_LEN: usize = 17;
+
+/// Length of the payload of a [`ChunkKind::Copy`] chunk.
+pub(crate) const COPY_PAYLOAD_LEN: usize = 12;
+
+/// Maximum length of the payload of a [`ChunkKind::Abort`] chunk.
+pub(crate) const MAX_ABORT_PAYLOAD_LEN: usize = 0x1000;
+
+/// Number of characters of a [`CancelReason::Error`] message
+/// that fit in an abort chunk, even once escaped.
+const MAX_ABORT_MSG_CHARS: usize = 512;
+
+/// How the payload of a chunk is encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ChunkKind {
+    /// The file's bytes, as is.
+    Raw = 0,
+    /// The file's bytes, as a single zstd frame.
+    Zstd = 1,
+    /// Bytes that the receiver copies from its old version of the file.
+    /// See [`crate::delta`]. The payload holds a big-endian 8 byte offset
+    /// in the old version, and a big-endian 4 byte length.
+    Copy = 2,
+    /// The sender stopped the transfer on purpose. The payload holds
+    /// the [`CancelReason`] as JSON, and the rest of the header is ignored.
+    Abort = 3,
+}
+
+/// Describes the chunk of file contents that follows it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ChunkHeader {
+    /// Index of the file in the offer
+    pub file_index: u32,
+    /// Offset in the file where this chunk's contents start
+    pub offset: u64,
+    /// How the payload is encoded
+    pub kind: ChunkKind,
+    /// Length of the payload, as sent
+    pub len: u32,
+}
+
+impl ChunkHeader {
+    /// Serializes this header.
+    pub fn to_bytes(self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0; HEADER_LEN];
+        bytes[0..4].copy_from_slice(&self.file_index.to_be_bytes());
+        bytes[4..12].copy_from_slice(&self.offset.to_be_bytes());
+        bytes[12] = self.kind as u8;
+        bytes[13..17].copy_from_slice(&self.len.to_be_bytes());
+        bytes
+    }
+
+    /// Reads a header from `reader`.
+    ///
+    /// Returns [`Error::InvalidChunk`] if its kind is unknown,
+    /// or its payload is longer than any valid chunk.
+    pub async fn read(reader: &mut (impl AsyncRead + Unpin)) -> Result<Self, Error> {
+        let mut bytes = [0; HEADER_LEN];
+        reader.read_exact(&mut bytes).await?;
+
+        let kind = match bytes[12] {
+            0 => ChunkKind::Raw,
+            1 => ChunkKind::Zstd,
+            2 => ChunkKind::Copy,
+            3 => ChunkKind::Abort,
+            _ => return Err(Error::InvalidChunk),
+        };
+        let header = Self {
+            file_index: u32::from_be_bytes(bytes[0..4].try_into().unwrap()),
+            offset: u64::from_be_bytes(bytes[4..12].try_into().unwrap()),
+            kind,
+            len: u32::from_be_bytes(bytes[13..17].try_into().unwrap()),
+        };
+
+        if header.len as usize > max_payload_len(kind) {
+            return Err(Error::InvalidChunk);
+        }
+        Ok(header)
+    }
+}
+
+/// Returns the maximum length of a payload of this `kind`.
+pub(crate) fn max_payload_len(kind: ChunkKind) -> usize {
+    match kind {
+        ChunkKind::Raw => CHUNK_LEN,
+        ChunkKind::Zstd => zstd::zstd_safe::compress_bound(CHUNK_LEN),
+        ChunkKind::Copy => COPY_PAYLOAD_LEN,
+        ChunkKind::Abort => MAX_ABORT_PAYLOAD_LEN,
+    }
+}
+
+/// Returns the payload of an abort chunk that carries `reason`.
+pub(crate) fn abort_payload(reason: &CancelReason) -> Vec<u8> {
+    let reason = match reason {
+        CancelReason::Error(msg) => {
+            CancelReason::Error(msg.chars().take(MAX_ABORT_MSG_CHARS).collect())
+        }
+        reason => reason.clone(),
+    };
+    serde_json::to_vec(&reason).expect("Serializing a CancelReason can't fail.")
+}
+
+/// Reads the `len` byte payload of an abort chunk from `reader`,
+/// and returns the [`Error::PeerCancelled`] it stands for.
+pub(crate) async fn read_abort(reader: &mut (impl AsyncRead + Unpin), len: u32) -> Error {
+    let mut payload = vec![0; len as usize];
+    if let Err(err) = reader.read_exact(&mut payload).await {
+        return err.into();
+    }
+    match serde_json::from_slice(&payload) {
+        Ok(reason) => Error::PeerCancelled(reason),
+        Err(_) => Error::InvalidChunk,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        abort_payload, max_payload_len, read_abort, ChunkHeader, ChunkKind, CHUNK_LEN,
+        MAX_ABORT_PAYLOAD_LEN,
+    };
+    use crate::{CancelReason, Error};
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let header = ChunkHeader {
+            file_index: 3,
+            offset: 0x0102_0304_0506,
+            kind: ChunkKind::Zstd,
+            len: 200,
+        };
+        let bytes = header.to_bytes();
+        assert_eq!(bytes[..4], [0, 0, 0, 3]);
+        assert_eq!(bytes[12], 1);
+        assert_eq!(ChunkHeader::read(&mut &bytes[..]).await.unwrap(), header);
+    }
+
+    #[tokio::test]
+    async fn test_invalid() {
+        let header = ChunkHeader {
+            file_index: 0,
+            offset: 0,
+            kind: ChunkKind::Raw,
+            len: CHUNK_LEN as u32 + 1,
+        };
+        let result = ChunkHeader::read(&mut &header.to_bytes()[..]).await;
+        assert!(matches!(result, Err(Error::InvalidChunk)));
+
+        let header = ChunkHeader {
+            len: max_payload_len(ChunkKind::Zstd) as u32,
+            kind: ChunkKind::Zstd,
+            ..header
+        };
+        assert!(ChunkHeader::read(&mut &header.to_bytes()[..]).await.is_ok());
+
+        let mut bytes = header.to_bytes();
+        bytes[12] = 4;
+        let result = ChunkHeader::read(&mut &bytes[..]).await;
+        assert!(matches!(result, Err(Error::InvalidChunk)));
+
+        // cut short
+        let result = ChunkHeader::read(&mut &bytes[..10]).await;
+        assert!(matches!(result, Err(Error::IO(_))));
+    }
+
+    #[tokio::test]
+    async fn test_abort() {
+        let reason = CancelReason::Error("é\"".repeat(10_000));
+        let payload = abort_payload(&reason);
+        assert!(payload.len() <= MAX_ABORT_PAYLOAD_LEN);
+
+        let err = read_abort(&mut &payload[..], payload.len() as u32).await;
+        let Error::PeerCancelled(CancelReason::Error(msg)) = err else {
+            panic!("Expected a cancellation, got {err:?}");
+        };
+        assert_eq!(msg.chars().count(), 512);
+
+        let err = read_abort(&mut &b"nonsense"[..], 8).await;
+        assert!(matches!(err, Error::InvalidChunk));
+    }
+}
@@ -0,0 +1,141 @@
+use crate::{read_from_async, write_to_async, Error};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// Estimated difference between the peer's clock and this machine's clock.
+///
+/// Use it to compare timestamps (such as file modification times)
+/// sent by the peer with local ones.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockSkew {
+    /// Estimated milliseconds that the peer's clock is ahead of this machine's.
+    /// Negative if the peer's clock is behind.
+    pub offset_millis: i64,
+
+    /// Round-trip time of the exchange in milliseconds.
+    /// The offset's error is at most half of this.
+    pub round_trip_millis: u64,
+}
+
+impl ClockSkew {
+    /// Converts a timestamp from the peer's clock to this machine's clock.
+    ///
+    /// Returns `None` if the result can't be represented,
+    /// such as because the peer sent a bogus offset.
+    pub fn peer_to_local(&self, peer_time: SystemTime) -> Option<SystemTime> {
+        shift(peer_time, self.offset_millis.checked_neg()?)
+    }
+
+    /// Converts a timestamp from this machine's clock to the peer's clock.
+    ///
+    /// Returns `None` if the result can't be represented.
+    pub fn local_to_peer(&self, local_time: SystemTime) -> Option<SystemTime> {
+        shift(local_time, self.offset_millis)
+    }
+
+    /// Returns whether the clocks differ by at most `tolerance`,
+    /// accounting for the uncertainty of the estimate.
+    pub fn is_within(&self, tolerance: Duration) -> bool {
+        let worst_case = self.offset_millis.unsigned_abs() + self.round_trip_millis / 2;
+        Duration::from_millis(worst_case) <= tolerance
+    }
+}
+
+/// Timestamps exchanged by [`estimate_clock_skew()`],
+/// in milliseconds since the unix epoch.
+#[derive(Serialize, Deserialize, Debug)]
+struct TimeMsg {
+    /// When the leader sent its request
+    sent: i64,
+    /// When the follower received the request
+    received: i64,
+    /// When the follower sent its reply
+    replied: i64,
+}
+
+/// Estimates how far apart the clocks of this machine and the peer are.
+///
+/// Both peers must call this function at the same time,
+/// with exactly one of them setting `is_leader` to `true`.
+/// Uses a single NTP-style exchange of timestamps, so the estimate is
+/// only as precise as the connection's round-trip time.
+pub async fn estimate_clock_skew(
+    stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+    is_leader: bool,
+) -> Result<ClockSkew, Error> {
+    if is_leader {
+        let sent = now_millis();
+        write_to_async(
+            TimeMsg {
+                sent,
+                received: 0,
+                replied: 0,
+            },
+            stream,
+        )
+        .await?;
+
+        let reply: TimeMsg = read_from_async(stream).await?;
+        let arrived = now_millis();
+
+        // widen to avoid overflow from a bogus reply
+        let (sent, arrived) = (i128::from(sent), i128::from(arrived));
+        let (received, replied) = (i128::from(reply.received), i128::from(reply.replied));
+
+        let offset = ((received - sent) + (replied - arrived)) / 2;
+        let round_trip = (arrived - sent) - (replied - received);
+
+        let skew = ClockSkew {
+            offset_millis: offset.clamp(-i128::from(i64::MAX), i128::from(i64::MAX)) as i64,
+            round_trip_millis: round_trip.clamp(0, i128::from(u64::MAX)) as u64,
+        };
+
+        // tell the follower the result, from its point of view
+        write_to_async(
+            ClockSkew {
+                offset_millis: -skew.offset_millis,
+                ..skew
+            },
+            stream,
+        )
+        .await?;
+
+        Ok(skew)
+    } else {
+        let request: TimeMsg = read_from_async(stream).await?;
+        let received = now_millis();
+
+        write_to_async(
+            TimeMsg {
+                sent: request.sent,
+                received,
+                replied: now_millis(),
+            },
+            stream,
+        )
+        .await?;
+
+        read_from_async(stream).await
+    }
+}
+
+/// Returns the current time in milliseconds since the unix epoch.
+/// Negative if the clock is set before 1970.
+fn now_millis() -> i64 {
+    match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(since) => since.as_millis() as i64,
+        Err(before) => -(before.duration().as_millis() as i64),
+    }
+}
+
+/// Returns `time` shifted by `millis` milliseconds,
+/// or `None` if that overflows.
+fn shift(time: SystemTime, millis: i64) -> Option<SystemTime> {
+    let amount = Duration::from_millis(millis.unsigned_abs());
+    if millis >= 0 {
+        time.checked_add(amount)
+    } else {
+        time.checked_sub(amount)
+    }
+}
@@ -0,0 +1,36 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// A way to compress file contents while they're transferred.
+///
+/// The sender proposes it in [`crate::FileOfferMsg::compression`],
+/// and the receiver accepts it by copying it into
+/// [`crate::FileResponseMsg::compression`].
+/// Then the contents of each [`crate::FileMeta::compressible`] file are compressed.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Compression {
+    /// [Zstandard](https://docs.rs/zstd/), at a `level`
+    /// from 1 (fastest) to 22 (smallest).
+    Zstd { level: i32 },
+}
+
+/// Extensions of file formats that are already compressed,
+/// so compressing them again would only waste time.
+const COMPRESSED_EXTENSIONS: &[&str] = &[
+    "7z", "aac", "apk", "avi", "avif", "br", "bz2", "deb", "docx", "epub", "flac", "gif", "gz",
+    "heic", "jar", "jpeg", "jpg", "lz", "lz4", "lzma", "m4a", "mkv", "mov", "mp3", "mp4", "ogg",
+    "opus", "png", "pptx", "rar", "rpm", "tgz", "txz", "webm", "webp", "whl", "xlsx", "xz", "zip",
+    "zst",
+];
+
+/// Returns whether the file at `path` is worth compressing,
+/// judging by its extension.
+pub(crate) fn is_compressible(path: &Path) -> bool {
+    let Some(extension) = path.extension().and_then(|ext| ext.to_str()) else {
+        return true;
+    };
+    !COMPRESSED_EXTENSIONS
+        .iter()
+        .any(|compressed| compressed.eq_ignore_ascii_case(extension))
+}
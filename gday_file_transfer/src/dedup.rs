@@ -0,0 +1,102 @@
+//! Sends identical files once, and has the receiver copy the rest.
+//!
+//! The sender finds the duplicates with [`find_duplicates()`] and lists them in
+//! [`FileOfferMsg::duplicates`]. The receiver picks the ones it wants to copy
+//! with [`FileResponseMsg::copy_duplicates()`], which the sender then skips.
+//! Once the original of each copy arrives, the receiver copies it.
+
+use crate::path_policy::check_safe_path;
+use crate::transfer::restore_metadata;
+use crate::{Error, FileMetaLocal, FileOfferMsg, FileResponseMsg, FileStatus, TransferSummary};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+/// Returns the `files` with the same contents as an earlier one,
+/// from their index in `files` to the earlier file's index,
+/// for [`FileOfferMsg::duplicates`].
+///
+/// Files are compared by length, then by hash, so only files with the same
+/// length as another are hashed with [`FileMetaLocal::compute_hash()`],
+/// unless they already were.
+/// Empty files are never duplicates, since there's nothing to save.
+pub fn find_duplicates(files: &mut [FileMetaLocal]) -> Result<BTreeMap<usize, usize>, Error> {
+    let mut same_len: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (index, file) in files.iter().enumerate() {
+        if file.len != 0 {
+            same_len.entry(file.len).or_default().push(index);
+        }
+    }
+
+    let mut duplicates = BTreeMap::new();
+    for indices in same_len.into_values().filter(|indices| indices.len() > 1) {
+        let mut originals: HashMap<[u8; 32], usize> = HashMap::new();
+        for index in indices {
+            let file = &mut files[index];
+            if file.hash.is_none() {
+                file.compute_hash()?;
+            }
+            let hash = file.hash.expect("Computed the hash.");
+            match originals.get(&hash) {
+                Some(&original) => {
+                    duplicates.insert(index, original);
+                }
+                None => {
+                    originals.insert(hash, index);
+                }
+            }
+        }
+    }
+    Ok(duplicates)
+}
+
+/// Returns [`Error::InvalidCopy`] unless every file in [`FileResponseMsg::copies`]
+/// is accepted, and has the same length in `lens` as an accepted original
+/// that isn't a copy itself.
+pub(crate) fn check_copies(response: &FileResponseMsg, lens: &[u64]) -> Result<(), Error> {
+    let accepted = |index: usize| response.response.get(index).is_some_and(Option::is_some);
+    for (&copy, &original) in &response.copies {
+        let valid = copy != original
+            && accepted(copy)
+            && accepted(original)
+            && !response.copies.contains_key(&original)
+            && lens.get(copy) == lens.get(original);
+        if !valid {
+            return Err(Error::InvalidCopy(copy));
+        }
+    }
+    Ok(())
+}
+
+/// Saves each file in [`FileResponseMsg::copies`] as a copy of its original,
+/// which `summary` says was already saved, and records it in `summary`.
+pub(crate) fn save_copies(
+    offer: &FileOfferMsg,
+    response: &FileResponseMsg,
+    save_path: &Path,
+    summary: &mut TransferSummary,
+) -> Result<(), Error> {
+    for (&copy, &original) in &response.copies {
+        let Some(from) = summary.files[original].saved_path.clone() else {
+            return Err(Error::InvalidCopy(copy));
+        };
+        let meta = &offer.files[copy];
+        check_safe_path(&meta.short_path)?;
+        let to = meta
+            .get_save_path_with_policy(save_path, response.overwrite)?
+            .ok_or_else(|| Error::FileExists(meta.get_save_path(save_path)))?;
+        if let Some(parent) = to.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::copy(&from, &to)?;
+        if response.preserve_metadata {
+            restore_metadata(meta, &to)?;
+        }
+
+        let hash_verified = summary.files[original].hash_verified;
+        let file = &mut summary.files[copy];
+        file.status = FileStatus::Copied;
+        file.saved_path = Some(to);
+        file.hash_verified = hash_verified;
+    }
+    Ok(())
+}
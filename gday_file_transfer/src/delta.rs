@@ -0,0 +1,366 @@
+//! Rsync-style delta transfers of files that the receiver
+//! already has an older version of.
+//!
+//! The receiver splits its old version into blocks, and sends the
+//! [`Signature`] of each block in [`crate::FileResponseMsg::deltas`].
+//! The sender slides a window over its version of the file,
+//! looking for those blocks with a cheap rolling checksum,
+//! confirmed by a strong hash.
+//! Blocks it finds are sent as chunks that tell the receiver
+//! to copy them from its old version. Everything else is sent as usual.
+
+use crate::chunk::CHUNK_LEN;
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
+
+/// Smallest block length of a [`Signature`].
+const MIN_BLOCK_LEN: u64 = 0x800;
+
+/// Describes the blocks of a file that the receiver already has,
+/// so the sender can avoid sending them again.
+///
+/// Create one with [`Signature::of_file()`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    /// Length of each block. The last block of the file
+    /// is shorter, so it's left out of `blocks`.
+    pub block_len: u32,
+    /// Signatures of the file's full blocks, in order.
+    pub blocks: Vec<BlockSignature>,
+}
+
+/// Identifies one block of a [`Signature`].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockSignature {
+    /// Rsync's rolling checksum of the block.
+    pub weak: u32,
+    /// First 16 bytes of the blake3 hash of the block.
+    pub strong: u128,
+}
+
+impl Signature {
+    /// Computes the [`Signature`] of the file at `path`.
+    ///
+    /// Blocks are about as long as the square root of the file's length,
+    /// so the signature stays small even for large files.
+    pub fn of_file(path: &Path) -> std::io::Result<Self> {
+        let mut file = std::fs::File::open(path)?;
+        let len = file.metadata()?.len();
+        let block_len = (len.isqrt() + 1).clamp(MIN_BLOCK_LEN, CHUNK_LEN as u64) as u32;
+
+        let mut blocks = Vec::with_capacity((len / u64::from(block_len)) as usize);
+        let mut block = vec![0; block_len as usize];
+        loop {
+            match file.read_exact(&mut block) {
+                Ok(()) => blocks.push(BlockSignature {
+                    weak: Rolling::new(&block).value(),
+                    strong: strong_hash(&block),
+                }),
+                Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(Self { block_len, blocks })
+    }
+}
+
+/// A piece of the sender's version of a file, produced by [`DeltaEncoder`].
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum DeltaOp<'a> {
+    /// Bytes the receiver doesn't have. At most [`CHUNK_LEN`] long.
+    Literal(&'a [u8]),
+    /// `len` bytes that the receiver has at `offset` in its old version.
+    Copy { offset: u64, len: u32 },
+}
+
+/// Splits a file into [`DeltaOp`]s against the [`Signature`]
+/// of the receiver's old version.
+pub(crate) struct DeltaEncoder<'a, R> {
+    reader: R,
+    signature: &'a Signature,
+    /// Indices of the blocks with each weak checksum
+    blocks_by_weak: HashMap<u32, Vec<usize>>,
+    /// Bytes read but not yet sent.
+    /// Those before `pos` didn't match any block.
+    buf: Vec<u8>,
+    /// Start of the window that's compared with the blocks
+    pos: usize,
+    /// Bytes at the start of `buf` that the last returned op borrowed
+    consumed: usize,
+    /// Checksum of the window, if it's up to date
+    rolling: Option<Rolling>,
+    /// Matched blocks not yet returned, merged if they're adjacent
+    pending_copy: Option<(u64, u32)>,
+    eof: bool,
+}
+
+impl<'a, R: Read> DeltaEncoder<'a, R> {
+    /// Encodes the contents of `reader` against `signature`.
+    ///
+    /// Returns [`Error::InvalidSignature`] if its blocks are empty
+    /// or longer than [`CHUNK_LEN`].
+    pub fn new(reader: R, signature: &'a Signature) -> Result<Self, Error> {
+        if signature.block_len == 0 || signature.block_len as usize > CHUNK_LEN {
+            return Err(Error::InvalidSignature);
+        }
+
+        let mut blocks_by_weak: HashMap<u32, Vec<usize>> = HashMap::new();
+        for (index, block) in signature.blocks.iter().enumerate() {
+            blocks_by_weak.entry(block.weak).or_default().push(index);
+        }
+
+        Ok(Self {
+            reader,
+            signature,
+            blocks_by_weak,
+            buf: Vec::new(),
+            pos: 0,
+            consumed: 0,
+            rolling: None,
+            pending_copy: None,
+            eof: false,
+        })
+    }
+
+    /// Returns the next piece of the file, or `None` once it all was returned.
+    pub fn next_op(&mut self) -> Result<Option<DeltaOp<'_>>, Error> {
+        self.buf.drain(0..self.consumed);
+        self.consumed = 0;
+
+        let block_len = self.signature.block_len as usize;
+        loop {
+            // keep literals short, so the buffer stays small
+            if self.pos >= CHUNK_LEN {
+                return Ok(Some(self.take_literal(CHUNK_LEN)));
+            }
+
+            self.fill(self.pos + block_len)?;
+
+            // no full window is left, so the rest is literal
+            if self.buf.len() - self.pos < block_len {
+                if let Some((offset, len)) = self.pending_copy.take() {
+                    return Ok(Some(DeltaOp::Copy { offset, len }));
+                }
+                if self.buf.is_empty() {
+                    return Ok(None);
+                }
+                self.pos = self.buf.len();
+                return Ok(Some(self.take_literal(self.pos.min(CHUNK_LEN))));
+            }
+
+            let window = &self.buf[self.pos..self.pos + block_len];
+            let weak = self
+                .rolling
+                .get_or_insert_with(|| Rolling::new(window))
+                .value();
+
+            if let Some(block) = self.find_block(weak, self.pos) {
+                // the unmatched bytes go first. the window will match again next time.
+                if self.pos > 0 {
+                    return Ok(Some(self.take_literal(self.pos)));
+                }
+
+                self.buf.drain(0..block_len);
+                self.rolling = None;
+                let offset = block as u64 * block_len as u64;
+                match &mut self.pending_copy {
+                    Some((pending_offset, pending_len))
+                        if *pending_offset + u64::from(*pending_len) == offset
+                            && pending_len.checked_add(block_len as u32).is_some() =>
+                    {
+                        *pending_len += block_len as u32;
+                    }
+                    pending => {
+                        if let Some((offset, len)) = pending.replace((offset, block_len as u32)) {
+                            return Ok(Some(DeltaOp::Copy { offset, len }));
+                        }
+                    }
+                }
+                continue;
+            }
+
+            // slide the window by one byte
+            self.fill(self.pos + block_len + 1)?;
+            if let Some(&next) = self.buf.get(self.pos + block_len) {
+                let weak = self.rolling.as_mut().expect("computed above");
+                weak.roll(self.buf[self.pos], next);
+                self.pos += 1;
+            } else {
+                self.pos = self.buf.len();
+            }
+        }
+    }
+
+    /// Returns the index of a block that matches the window at `pos`
+    /// in the buffer, which has the checksum `weak`.
+    fn find_block(&self, weak: u32, pos: usize) -> Option<usize> {
+        let candidates = self.blocks_by_weak.get(&weak)?;
+        let window = &self.buf[pos..pos + self.signature.block_len as usize];
+        let strong = strong_hash(window);
+        candidates
+            .iter()
+            .copied()
+            .find(|&index| self.signature.blocks[index].strong == strong)
+    }
+
+    /// Returns the first `len` bytes of the buffer as a literal,
+    /// preceded by any pending copy.
+    fn take_literal(&mut self, len: usize) -> DeltaOp<'_> {
+        if let Some((offset, len)) = self.pending_copy.take() {
+            return DeltaOp::Copy { offset, len };
+        }
+        self.pos -= len;
+        self.consumed = len;
+        DeltaOp::Literal(&self.buf[0..len])
+    }
+
+    /// Reads until the buffer holds at least `len` bytes, or the reader ends.
+    fn fill(&mut self, len: usize) -> std::io::Result<()> {
+        while self.buf.len() < len && !self.eof {
+            let old_len = self.buf.len();
+            self.buf.resize(old_len + CHUNK_LEN, 0);
+            let read = loop {
+                match self.reader.read(&mut self.buf[old_len..]) {
+                    Ok(read) => break read,
+                    Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                    Err(err) => {
+                        self.buf.truncate(old_len);
+                        return Err(err);
+                    }
+                }
+            };
+            self.buf.truncate(old_len + read);
+            self.eof = read == 0;
+        }
+        Ok(())
+    }
+}
+
+/// Rsync's rolling checksum of a window of bytes,
+/// which can be moved by a byte in constant time.
+#[derive(Debug, Clone, Copy)]
+struct Rolling {
+    a: u32,
+    b: u32,
+    len: u32,
+}
+
+impl Rolling {
+    /// Computes the checksum of `window`.
+    fn new(window: &[u8]) -> Self {
+        let len = window.len() as u32;
+        let mut a: u32 = 0;
+        let mut b: u32 = 0;
+        for (i, &byte) in window.iter().enumerate() {
+            a = a.wrapping_add(u32::from(byte));
+            b = b.wrapping_add((len - i as u32).wrapping_mul(u32::from(byte)));
+        }
+        Self { a, b, len }
+    }
+
+    /// Moves the window forward by a byte, removing `old` from its start
+    /// and adding `new` to its end.
+    fn roll(&mut self, old: u8, new: u8) {
+        self.a = self
+            .a
+            .wrapping_sub(u32::from(old))
+            .wrapping_add(u32::from(new));
+        self.b = self
+            .b
+            .wrapping_sub(self.len.wrapping_mul(u32::from(old)))
+            .wrapping_add(self.a);
+    }
+
+    /// Returns the checksum.
+    fn value(&self) -> u32 {
+        (self.a & 0xffff) | (self.b << 16)
+    }
+}
+
+/// Returns the strong hash of `block` used in [`BlockSignature::strong`].
+fn strong_hash(block: &[u8]) -> u128 {
+    let hash = blake3::hash(block);
+    u128::from_le_bytes(hash.as_bytes()[0..16].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DeltaEncoder, DeltaOp, Rolling, Signature};
+
+    #[test]
+    fn test_rolling() {
+        let data: Vec<u8> = (0..1000_u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut rolling = Rolling::new(&data[0..100]);
+        for start in 1..900 {
+            rolling.roll(data[start - 1], data[start + 99]);
+            assert_eq!(
+                rolling.value(),
+                Rolling::new(&data[start..start + 100]).value()
+            );
+        }
+    }
+
+    /// Rebuilds the new file from the old one and the ops.
+    fn apply(old: &[u8], new: &[u8], signature: &Signature) -> (Vec<u8>, usize) {
+        let mut encoder = DeltaEncoder::new(new, signature).unwrap();
+        let mut rebuilt = Vec::new();
+        let mut literal_len = 0;
+        while let Some(op) = encoder.next_op().unwrap() {
+            match op {
+                DeltaOp::Literal(data) => {
+                    assert!(!data.is_empty());
+                    literal_len += data.len();
+                    rebuilt.extend_from_slice(data);
+                }
+                DeltaOp::Copy { offset, len } => {
+                    let offset = offset as usize;
+                    rebuilt.extend_from_slice(&old[offset..offset + len as usize]);
+                }
+            }
+        }
+        (rebuilt, literal_len)
+    }
+
+    #[test]
+    fn test_delta() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("old");
+        let old: Vec<u8> = (0..300_000_u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        std::fs::write(&path, &old).unwrap();
+        let signature = Signature::of_file(&path).unwrap();
+        assert_eq!(
+            signature.blocks.len(),
+            old.len() / signature.block_len as usize
+        );
+
+        // unchanged
+        let (rebuilt, literal_len) = apply(&old, &old, &signature);
+        assert_eq!(rebuilt, old);
+        assert!(literal_len < signature.block_len as usize);
+
+        // bytes inserted, changed, and removed
+        let mut new = b"inserted at the start".to_vec();
+        new.extend_from_slice(&old[0..100_000]);
+        new.extend_from_slice(&[0; 5000]);
+        new.extend_from_slice(&old[105_000..250_000]);
+        new.extend_from_slice(&old[260_000..]);
+        let (rebuilt, literal_len) = apply(&old, &new, &signature);
+        assert_eq!(rebuilt, new);
+        assert!(literal_len < 30_000);
+
+        // nothing in common
+        let unrelated = vec![7; 100_000];
+        let (rebuilt, literal_len) = apply(&old, &unrelated, &signature);
+        assert_eq!(rebuilt, unrelated);
+        assert_eq!(literal_len, unrelated.len());
+
+        let (rebuilt, _) = apply(&old, &[], &signature);
+        assert!(rebuilt.is_empty());
+    }
+}
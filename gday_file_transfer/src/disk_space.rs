@@ -0,0 +1,82 @@
+use crate::{Error, FileOfferMsg, FileResponseMsg};
+use std::path::Path;
+
+/// Free space on the file system where an offer would be saved,
+/// compared to the offer's size.
+///
+/// Lets receivers warn about offers that won't fit
+/// before accepting them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiskSpace {
+    /// Total size of the offered files,
+    /// from [`FileOfferMsg::get_total_offered_size()`]
+    pub offered: u64,
+    /// Number of bytes available to this user
+    /// on the save path's file system
+    pub available: u64,
+}
+
+impl DiskSpace {
+    /// Compares the size of `offer` to the free space
+    /// on the file system of `save_path`.
+    ///
+    /// Returns `None` if the free space can't be determined on this platform.
+    pub fn check(offer: &FileOfferMsg, save_path: &Path) -> Result<Option<Self>, Error> {
+        Ok(available_space(save_path)?.map(|available| Self {
+            offered: offer.get_total_offered_size(),
+            available,
+        }))
+    }
+
+    /// Returns how many more bytes would be needed
+    /// to accept every offered file, or `None` if they fit.
+    pub fn shortfall(&self) -> Option<u64> {
+        (self.offered > self.available).then(|| self.offered - self.available)
+    }
+}
+
+/// Returns [`Error::InsufficientDiskSpace`] if the rest of the files
+/// accepted in `response` won't fit on the file system of `save_path`.
+///
+/// Does nothing if the free space can't be determined on this platform.
+pub fn ensure_disk_space(
+    offer: &FileOfferMsg,
+    response: &FileResponseMsg,
+    save_path: &Path,
+) -> Result<(), Error> {
+    let needed = offer.get_transfer_size(response)?;
+    match available_space(save_path)? {
+        Some(available) if needed > available => {
+            Err(Error::InsufficientDiskSpace { needed, available })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Returns the number of bytes available to this user on
+/// the file system of `path`, or of its closest existing ancestor,
+/// since the save directory may not exist yet.
+///
+/// Returns `None` if this can't be determined on this platform.
+fn available_space(path: &Path) -> Result<Option<u64>, Error> {
+    let path = std::path::absolute(path)?;
+    let existing = path
+        .ancestors()
+        .find(|ancestor| ancestor.exists())
+        .unwrap_or(&path);
+    platform_available_space(existing)
+}
+
+#[cfg(unix)]
+fn platform_available_space(path: &Path) -> Result<Option<u64>, Error> {
+    let stat = rustix::fs::statvfs(path).map_err(std::io::Error::from)?;
+    // these fields are narrower on some platforms
+    #[allow(clippy::unnecessary_cast)]
+    let available = (stat.f_bavail as u64).saturating_mul(stat.f_frsize as u64);
+    Ok(Some(available))
+}
+
+#[cfg(not(unix))]
+fn platform_available_space(_path: &Path) -> Result<Option<u64>, Error> {
+    Ok(None)
+}
@@ -0,0 +1,150 @@
+use crate::file_meta::suffix_with_number;
+use crate::path_policy::check_safe_path;
+use crate::{Error, FileOfferMsg, SymlinkMeta};
+use std::path::{Component, Path, PathBuf};
+
+/// What the receiver does with the [`FileOfferMsg::symlinks`]
+/// and [`FileOfferMsg::empty_dirs`] offered by the peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum EntryPolicy {
+    /// Recreate them in the save directory,
+    /// except for symlinks that point outside of it.
+    #[default]
+    Recreate,
+    /// Ignore them, and only save regular files.
+    Skip,
+    /// Refuse offers that contain any.
+    Error,
+}
+
+/// Checks whether the symlinks and empty directories in `offer`
+/// can be created under `policy`, without creating anything.
+///
+/// Call this before responding to the offer, to fail early.
+///
+/// - With [`EntryPolicy::Error`], returns [`Error::EntriesNotAllowed`]
+///   if there are any.
+/// - With [`EntryPolicy::Recreate`], returns [`Error::SymlinkEscapes`]
+///   if a symlink points outside the save directory, and [`Error::UnsafePath`]
+///   if a symlink or directory would be created outside of it.
+pub fn check_entries(offer: &FileOfferMsg, policy: EntryPolicy) -> Result<(), Error> {
+    match policy {
+        EntryPolicy::Recreate => {
+            let paths = offer.symlinks.iter().map(|link| &link.short_path);
+            for path in paths.chain(&offer.empty_dirs) {
+                check_safe_path(path)?;
+            }
+            if let Some(link) = offer.symlinks.iter().find(|link| escapes(link)) {
+                return Err(Error::SymlinkEscapes(link.short_path.clone()));
+            }
+            Ok(())
+        }
+        EntryPolicy::Skip => Ok(()),
+        EntryPolicy::Error => {
+            if offer.symlinks.is_empty() && offer.empty_dirs.is_empty() {
+                Ok(())
+            } else {
+                Err(Error::EntriesNotAllowed)
+            }
+        }
+    }
+}
+
+/// Creates the symlinks and empty directories in `offer`
+/// within `save_dir`, following `policy`.
+///
+/// Call this after [`crate::receive_files()`], so that
+/// symlinks can't redirect where the files are saved.
+///
+/// Existing symlinks with the same target are left as-is.
+/// If something else is in the way of a symlink, the symlink's name is
+/// suffixed with `" (1)"`, `" (2)"`, ..., like [`crate::FileMeta::get_unoccupied_save_path()`].
+pub fn create_entries(
+    offer: &FileOfferMsg,
+    save_dir: &Path,
+    policy: EntryPolicy,
+) -> Result<(), Error> {
+    check_entries(offer, policy)?;
+    if policy != EntryPolicy::Recreate {
+        return Ok(());
+    }
+
+    for dir in &offer.empty_dirs {
+        std::fs::create_dir_all(save_dir.join(dir))?;
+    }
+
+    for link in &offer.symlinks {
+        let path = save_dir.join(&link.short_path);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let Some(path) = unoccupied_link_path(link, path)? else {
+            continue;
+        };
+        create_symlink(link, &path)?;
+    }
+
+    Ok(())
+}
+
+/// Returns whether `link` would point outside the directory its
+/// [`SymlinkMeta::short_path`] is relative to.
+fn escapes(link: &SymlinkMeta) -> bool {
+    let parent = link.short_path.parent().unwrap_or(Path::new(""));
+    let mut depth: usize = 0;
+    for component in parent.join(&link.target).components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => (),
+            Component::ParentDir => match depth.checked_sub(1) {
+                Some(new_depth) => depth = new_depth,
+                None => return true,
+            },
+            Component::RootDir | Component::Prefix(_) => return true,
+        }
+    }
+    false
+}
+
+/// Returns where to create `link`, starting with `path`,
+/// or `None` if an identical symlink is already there.
+fn unoccupied_link_path(link: &SymlinkMeta, path: PathBuf) -> Result<Option<PathBuf>, Error> {
+    for number in 0..100 {
+        let mut candidate = path.clone();
+        if number != 0 {
+            suffix_with_number(&mut candidate, number);
+        }
+
+        // doesn't follow symlinks, unlike `Path::exists()`
+        if candidate.symlink_metadata().is_err() {
+            return Ok(Some(candidate));
+        }
+        if std::fs::read_link(&candidate).is_ok_and(|target| target == link.target) {
+            return Ok(None);
+        }
+    }
+    Err(Error::FilenameOccupied(path))
+}
+
+/// Creates `link` at `path`.
+fn create_symlink(link: &SymlinkMeta, path: &Path) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(&link.target, path)
+    }
+    #[cfg(windows)]
+    {
+        if link.target_is_dir {
+            std::os::windows::fs::symlink_dir(&link.target, path)
+        } else {
+            std::os::windows::fs::symlink_file(&link.target, path)
+        }
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = (link, path);
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+}
@@ -0,0 +1,114 @@
+//! Lets both peers offer and receive files in one session.
+//!
+//! The peers take turns on the same stream:
+//! 1. The leader sends its [`FileOfferMsg`], then the follower sends its own.
+//! 2. Each peer decides which of the other's files to accept,
+//!    and the [`FileResponseMsg`]s are exchanged in the same order.
+//! 3. The leader sends its accepted files, then the follower sends its own,
+//!    just like [`send_files()`](crate::send_files()).
+//!
+//! A peer with nothing to send simply offers no files.
+
+use crate::{
+    read_from_async, receive_files, send_files, write_to_async, Error, FileMetaLocal, FileOfferMsg,
+    FileResponseMsg, TransferReport, TransferSummary,
+};
+use std::path::Path;
+use tokio::io::{AsyncBufRead, AsyncWrite};
+
+/// Which way files are moving when [`exchange_files()`] reports progress.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// This peer is sending its files.
+    Sending,
+    /// This peer is receiving the peer's files.
+    Receiving,
+}
+
+/// What was offered and accepted in each direction by [`exchange_files()`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExchangeSummary {
+    /// The files the peer offered.
+    pub peer_offer: FileOfferMsg,
+    /// This peer's response to `peer_offer`,
+    /// which says which of the peer's files were received.
+    pub response: FileResponseMsg,
+    /// The peer's response to this peer's offer,
+    /// which says which of this peer's files were sent.
+    pub peer_response: FileResponseMsg,
+    /// What happened to each of the peer's files.
+    pub received: TransferSummary,
+}
+
+/// Swaps files with the peer over `stream`, so that both peers
+/// can send and receive in a single session.
+///
+/// Both peers must call this function at the same time,
+/// with exactly one of them setting `is_leader` to `true`.
+///
+/// - `local_files` are the files behind `offer`, at the same indices.
+///   Both are empty if this peer has nothing to send.
+/// - `respond` is called with the peer's offer, and decides which of its files
+///   to accept, for example with [`FileResponseMsg::accept_only_new_and_interrupted()`].
+/// - Accepted files are saved in `save_path`.
+/// - `progress_callback` is frequently called with [`TransferReport`]
+///   and the [`Direction`] of the files it describes.
+pub async fn exchange_files(
+    stream: &mut (impl AsyncBufRead + AsyncWrite + Unpin),
+    is_leader: bool,
+    local_files: &[FileMetaLocal],
+    offer: &FileOfferMsg,
+    save_path: &Path,
+    respond: impl FnOnce(&FileOfferMsg) -> Result<FileResponseMsg, Error>,
+    mut progress_callback: impl FnMut(Direction, &TransferReport),
+) -> Result<ExchangeSummary, Error> {
+    let peer_offer: FileOfferMsg = swap(stream, is_leader, offer).await?;
+
+    let response = respond(&peer_offer)?;
+    let peer_response: FileResponseMsg = swap(stream, is_leader, &response).await?;
+    if peer_response.response.len() != offer.files.len() {
+        return Err(Error::InvalidResponseLength);
+    }
+
+    // the leader's files go first
+    let mut received = None;
+    for sending in [is_leader, !is_leader] {
+        if sending {
+            send_files(local_files, &peer_response, &mut *stream, |report| {
+                progress_callback(Direction::Sending, report)
+            })
+            .await?;
+        } else {
+            let summary =
+                receive_files(&peer_offer, &response, save_path, &mut *stream, |report| {
+                    progress_callback(Direction::Receiving, report)
+                })
+                .await?;
+            received = Some(summary);
+        }
+    }
+
+    Ok(ExchangeSummary {
+        peer_offer,
+        response,
+        peer_response,
+        received: received.expect("Both peers send and receive."),
+    })
+}
+
+/// Sends `msg` and returns the peer's message of the same kind,
+/// with the leader sending first.
+async fn swap<T: serde::Serialize + serde::de::DeserializeOwned>(
+    stream: &mut (impl AsyncBufRead + AsyncWrite + Unpin),
+    is_leader: bool,
+    msg: &T,
+) -> Result<T, Error> {
+    if is_leader {
+        write_to_async(msg, stream).await?;
+        read_from_async(stream).await
+    } else {
+        let peer_msg = read_from_async(stream).await?;
+        write_to_async(msg, stream).await?;
+        Ok(peer_msg)
+    }
+}
@@ -1,11 +1,40 @@
-use crate::Error;
+use crate::compression::is_compressible;
+use crate::partial_download::{ReadInfo, TmpInfoFile};
+use crate::{Error, Glob};
 use os_str_bytes::OsStrBytesExt;
 use serde::{Deserialize, Serialize};
 use std::{
     ffi::{OsStr, OsString},
+    fs::Metadata,
+    io::Read,
     path::{Path, PathBuf},
+    time::SystemTime,
 };
 
+/// What a receiver does with an accepted file whose
+/// [`FileMeta::get_save_path()`] is already taken.
+///
+/// Set in [`crate::FileResponseMsg::overwrite`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverwritePolicy {
+    /// Replace the existing file.
+    Overwrite,
+
+    /// Save the file with a `" (1)"`, `" (2)"`, ... suffix,
+    /// like [`FileMeta::get_unoccupied_save_path()`].
+    #[default]
+    RenameSuffix,
+
+    /// Don't receive the file, keeping the existing one.
+    Skip,
+
+    /// Fail with [`Error::FileExists`], keeping the existing one.
+    FailIfExists,
+}
+
+/// Number of bytes at the start of a file that [`FileMeta::head_hash`] covers.
+pub const HEAD_LEN: u64 = 0x10000;
+
 /// Information about an offered file.
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, PartialOrd, Eq, Ord)]
 pub struct FileMeta {
@@ -13,6 +42,30 @@ pub struct FileMeta {
     pub short_path: PathBuf,
     /// Length of the offered file in bytes
     pub len: u64,
+    /// [BLAKE3](https://docs.rs/blake3/) hash of the file's contents,
+    /// used by the receiver to verify them.
+    /// `None` if the sender didn't compute one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<[u8; 32]>,
+    /// BLAKE3 hash of the file's first [`HEAD_LEN`] bytes,
+    /// or of all of it if it's shorter.
+    /// Lets the receiver recognize a partial download of this file
+    /// that's saved under a different path, with
+    /// [`crate::relocate_partial_downloads()`].
+    /// `None` if the sender didn't compute one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub head_hash: Option<[u8; 32]>,
+    /// Whether the file's contents are compressed if the peers
+    /// agree on a [`crate::Compression`]
+    #[serde(default)]
+    pub compressible: bool,
+    /// Unix permission bits of the file, such as `0o755`.
+    /// `None` if the sender isn't on unix.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    /// When the file was last modified, by the sender's clock
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<SystemTime>,
 }
 
 /// Information about a locally stored file.
@@ -24,6 +77,103 @@ pub struct FileMetaLocal {
     pub local_path: PathBuf,
     /// Length of the file in bytes
     pub len: u64,
+    /// BLAKE3 hash of the file's contents, if computed with
+    /// [`FileMetaLocal::compute_hash()`]
+    pub hash: Option<[u8; 32]>,
+    /// BLAKE3 hash of the first [`HEAD_LEN`] bytes of the file,
+    /// if computed with [`FileMetaLocal::compute_head_hash()`]
+    pub head_hash: Option<[u8; 32]>,
+    /// Whether to compress the file's contents if the peers agree on a
+    /// [`crate::Compression`]. [`get_file_metas()`] sets this to `false`
+    /// for formats that are already compressed, such as zip files.
+    pub compressible: bool,
+    /// Unix permission bits of the file, if on unix
+    pub mode: Option<u32>,
+    /// When the file was last modified
+    pub modified: Option<SystemTime>,
+}
+
+/// A symlink offered to the peer, which may recreate it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, PartialOrd, Eq, Ord)]
+pub struct SymlinkMeta {
+    /// The path of the symlink offered to the peer
+    pub short_path: PathBuf,
+    /// The path the symlink points to, often relative to its directory
+    pub target: PathBuf,
+    /// Whether the target is a directory,
+    /// since Windows creates directory and file symlinks differently
+    #[serde(default)]
+    pub target_is_dir: bool,
+}
+
+/// Locally stored files, symlinks, and empty directories,
+/// found by [`get_file_tree()`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileTreeLocal {
+    /// The regular files
+    pub files: Vec<FileMetaLocal>,
+    /// The symlinks that weren't followed
+    pub symlinks: Vec<SymlinkMeta>,
+    /// The shortened paths of directories without any offered entries
+    pub empty_dirs: Vec<PathBuf>,
+}
+
+/// Chooses which entries [`get_file_tree()`] offers
+/// from within the given directories.
+///
+/// For example, to send a project without its build outputs:
+/// ```
+/// # use gday_file_transfer::OfferOptions;
+/// let options = OfferOptions {
+///     exclude: vec!["target".parse()?, ".git".parse()?, "node_modules".parse()?],
+///     ..OfferOptions::default()
+/// };
+/// # Ok::<(), gday_file_transfer::Error>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OfferOptions {
+    /// If not empty, only offers files that match one of these patterns,
+    /// or are within a directory that does.
+    pub include: Vec<Glob>,
+    /// Skips files and directories that match any of these patterns.
+    pub exclude: Vec<Glob>,
+    /// Whether to offer hidden files and directories,
+    /// whose names start with `.`
+    pub include_hidden: bool,
+    /// Whether to offer the files and directories that symlinks point to,
+    /// instead of the symlinks themselves.
+    pub follow_symlinks: bool,
+}
+
+impl Default for OfferOptions {
+    /// Offers everything, except that symlinks aren't followed.
+    fn default() -> Self {
+        Self {
+            include: Vec::new(),
+            exclude: Vec::new(),
+            include_hidden: true,
+            follow_symlinks: false,
+        }
+    }
+}
+
+impl OfferOptions {
+    /// Returns whether the entry at `short_path`, and everything in it,
+    /// should be skipped.
+    fn is_skipped(&self, short_path: &Path) -> bool {
+        let is_hidden = short_path
+            .file_name()
+            .is_some_and(|name| name.as_encoded_bytes().starts_with(b"."));
+        (is_hidden && !self.include_hidden) || self.exclude.iter().any(|g| g.matches(short_path))
+    }
+
+    /// Returns whether files at or within `short_path` should be offered,
+    /// given whether its parent directory's are.
+    fn is_included(&self, short_path: &Path, parent_included: bool) -> bool {
+        parent_included
+            || self.include.is_empty()
+            || self.include.iter().any(|g| g.matches(short_path))
+    }
 }
 
 impl FileMeta {
@@ -57,6 +207,26 @@ impl FileMeta {
         Ok(path)
     }
 
+    /// Returns where to save this file in `save_dir` under `policy`,
+    /// or `None` if [`OverwritePolicy::Skip`] should skip it.
+    ///
+    /// Returns [`Error::FileExists`] if
+    /// [`OverwritePolicy::FailIfExists`] refuses to overwrite it.
+    pub fn get_save_path_with_policy(
+        &self,
+        save_dir: &Path,
+        policy: OverwritePolicy,
+    ) -> Result<Option<PathBuf>, Error> {
+        let path = self.get_save_path(save_dir);
+        match policy {
+            OverwritePolicy::RenameSuffix => self.get_unoccupied_save_path(save_dir).map(Some),
+            OverwritePolicy::Overwrite => Ok(Some(path)),
+            _ if path.symlink_metadata().is_err() => Ok(Some(path)),
+            OverwritePolicy::Skip => Ok(None),
+            OverwritePolicy::FailIfExists => Err(Error::FileExists(path)),
+        }
+    }
+
     /// Returns the occupied save path
     /// with the greatest numerical suffix.
     ///
@@ -111,33 +281,109 @@ impl FileMeta {
         Ok(path)
     }
 
+    /// Gets the path of the info file that describes the partial download
+    /// at [`Self::get_partial_download_path()`].
+    ///
+    /// Returns the partial download path suffixed by `".info"`.
+    pub fn get_partial_info_path(&self, save_dir: &Path) -> Result<PathBuf, Error> {
+        let mut path = self.get_partial_download_path(save_dir)?.into_os_string();
+        path.push(".info");
+        Ok(PathBuf::from(path))
+    }
+
     /// Checks if [`Self::get_partial_download_path()`]
     /// already exists and has a length smaller than [`Self::len`].
-    /// If so, returns the length of the partially downloaded file.
+    /// If so, returns how many of its bytes can be resumed.
     /// If it doesn't exist, returns None.
+    ///
+    /// That's usually the length of the partially downloaded file,
+    /// but may be less after a crash, since only the bytes that
+    /// its info file says were synced to disk are trusted.
+    ///
+    /// Also returns `None` if the partial download's info file is
+    /// corrupted, or describes a different file (such as one with a different hash),
+    /// so that the file is downloaded from the start instead.
     pub fn partial_download_exists(&self, save_dir: &Path) -> Result<Option<u64>, Error> {
         let local_path = self.get_partial_download_path(save_dir)?;
 
         // check if the file can be opened
-        if let Ok(file) = std::fs::File::open(local_path) {
-            // check if its length is less than the meta length
-            if let Ok(local_meta) = file.metadata() {
-                let local_len = local_meta.len();
-                if local_len < self.len {
-                    return Ok(Some(local_len));
-                }
-            }
+        let Ok(local_meta) = std::fs::File::open(local_path).and_then(|file| file.metadata())
+        else {
+            return Ok(None);
+        };
+
+        let info = match TmpInfoFile::read(&self.get_partial_info_path(save_dir)?)? {
+            ReadInfo::Valid(info) => info,
+            ReadInfo::Missing => TmpInfoFile::legacy(self),
+            ReadInfo::Corrupted => return Ok(None),
+        };
+        if !info.matches(self) {
+            return Ok(None);
+        }
+
+        // check if its length is less than the meta length
+        let resumable_len = info.resumable_len(local_meta.len());
+        if resumable_len < self.len {
+            Ok(Some(resumable_len))
+        } else {
+            Ok(None)
         }
-        Ok(None)
     }
 }
 
+impl FileMetaLocal {
+    /// Hashes the contents of the file, and stores the result in [`Self::hash`],
+    /// so that the receiver can verify the file.
+    ///
+    /// Returns [`Error::UnexpectedFileLen`] if the file's length changed.
+    pub fn compute_hash(&mut self) -> Result<(), Error> {
+        let mut hasher = blake3::Hasher::new();
+        let file = std::fs::File::open(&self.local_path)?;
+        let len = std::io::copy(&mut std::io::Read::take(file, self.len + 1), &mut hasher)?;
+        if len != self.len {
+            return Err(Error::UnexpectedFileLen);
+        }
+        self.hash = Some(hasher.finalize().into());
+        Ok(())
+    }
+
+    /// Hashes the first [`HEAD_LEN`] bytes of the file, and stores the result
+    /// in [`Self::head_hash`], so that the receiver can resume
+    /// partial downloads of it that are saved under a different path.
+    ///
+    /// Returns [`Error::UnexpectedFileLen`] if the file got shorter.
+    pub fn compute_head_hash(&mut self) -> Result<(), Error> {
+        let file = std::fs::File::open(&self.local_path)?;
+        self.head_hash = Some(head_hash(file, self.len)?);
+        Ok(())
+    }
+}
+
+/// Returns the BLAKE3 hash of the first [`HEAD_LEN`] bytes of `reader`, or of
+/// its first `len` bytes if that's less, as in [`FileMeta::head_hash`].
+///
+/// Returns [`Error::UnexpectedFileLen`] if `reader` ends before them.
+pub(crate) fn head_hash(reader: impl Read, len: u64) -> Result<[u8; 32], Error> {
+    let head_len = len.min(HEAD_LEN);
+    let mut hasher = blake3::Hasher::new();
+    let hashed = std::io::copy(&mut reader.take(head_len), &mut hasher)?;
+    if hashed != head_len {
+        return Err(Error::UnexpectedFileLen);
+    }
+    Ok(hasher.finalize().into())
+}
+
 impl From<FileMetaLocal> for FileMeta {
     /// Converts a [`FileMetaLocal`] into a [`FileMeta`].
     fn from(other: FileMetaLocal) -> Self {
         Self {
             short_path: other.short_path,
             len: other.len,
+            hash: other.hash,
+            head_hash: other.head_hash,
+            compressible: other.compressible,
+            mode: other.mode,
+            modified: other.modified,
         }
     }
 }
@@ -166,7 +412,7 @@ fn get_first_unoccupied_number(path: &Path) -> Result<u32, Error> {
 }
 
 /// Appends the suffix `" ({number})"` to the file stem of `path`.
-fn suffix_with_number(path: &mut PathBuf, number: u32) {
+pub(crate) fn suffix_with_number(path: &mut PathBuf, number: u32) {
     // isolate the file name
     let filename = path.file_name().expect("Path terminates in ..");
 
@@ -189,9 +435,30 @@ fn suffix_with_number(path: &mut PathBuf, number: u32) {
     }
 }
 
+/// Returns whether the file system that holds the directory `dir`
+/// treats file names that differ only in case as the same,
+/// like most macOS and Windows file systems do.
+///
+/// Finds out by briefly creating a file in `dir`.
+pub fn is_case_insensitive(dir: &Path) -> std::io::Result<bool> {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    let name = format!(".gday-case-check-{}-{nanos}", std::process::id());
+
+    let probe = dir.join(&name);
+    std::fs::File::create_new(&probe)?;
+    let result = dir.join(name.to_uppercase()).try_exists();
+    std::fs::remove_file(&probe)?;
+    result
+}
+
 /// Takes a list of distinct `paths`, each of which may be a directory or file.
 ///
 /// Returns the [`FileMetaLocal`] of each file, including those in nested directories.
+/// Follows symlinks, and skips empty directories.
+/// Use [`get_file_tree()`] to offer those too.
 ///
 /// Returns an error if can't access a path, one path is the prefix
 /// of another path, or two of the given `paths` end in the same name.
@@ -199,6 +466,20 @@ fn suffix_with_number(path: &mut PathBuf, number: u32) {
 /// Each file's [`FileMeta::short_path`] will contain the path to the file,
 /// starting at the provided level, ignoring parent directories.
 pub fn get_file_metas(paths: &[PathBuf]) -> Result<Vec<FileMetaLocal>, Error> {
+    let options = OfferOptions {
+        follow_symlinks: true,
+        ..OfferOptions::default()
+    };
+    Ok(get_file_tree(paths, &options)?.files)
+}
+
+/// Like [`get_file_metas()`], but also returns the
+/// symlinks and empty directories in `paths`,
+/// so that the receiver can recreate the directory trees faithfully.
+///
+/// Only offers the entries within `paths` allowed by `options`.
+/// The `paths` themselves are always offered.
+pub fn get_file_tree(paths: &[PathBuf], options: &OfferOptions) -> Result<FileTreeLocal, Error> {
     // canonicalize the paths to remove symlinks
     let paths = paths
         .iter()
@@ -230,55 +511,113 @@ pub fn get_file_metas(paths: &[PathBuf]) -> Result<Vec<FileMetaLocal>, Error> {
         }
     }
 
-    let mut files = Vec::new();
+    let mut tree = FileTreeLocal::default();
     for path in paths {
         // get the parent path
         let top_path = path.parent().unwrap_or(Path::new(""));
 
-        // add all files in this path to the files set
-        get_file_metas_helper(top_path, &path, &mut files)?;
+        // a given directory's contents must still match `options.include`
+        let short_path = path.strip_prefix(top_path).unwrap_or(&path);
+        let included = !path.is_dir() || options.is_included(short_path, false);
+
+        // add all entries in this path to the tree
+        get_file_tree_helper(top_path, &path, options, included, &mut tree)?;
     }
 
-    // build a vec from the set, and return
-    Ok(files)
+    Ok(tree)
 }
 
 /// - The [`FileMetaLocal::short_path`] will strip the prefix
 ///   `top_path` from all paths. `top_path` must be a prefix of `path`.
 /// - `path` is the file or directory where recursive traversal begins.
-/// - `files` is a [`Vec`] to which found files will be inserted.
-fn get_file_metas_helper(
+/// - `options` decides which entries within `path` are offered.
+/// - `included` is whether `path` matches [`OfferOptions::include`],
+///   so files at or within `path` should be offered.
+/// - `tree` is the [`FileTreeLocal`] to which found entries will be added.
+fn get_file_tree_helper(
     top_path: &Path,
     path: &Path,
-    files: &mut Vec<FileMetaLocal>,
+    options: &OfferOptions,
+    included: bool,
+    tree: &mut FileTreeLocal,
 ) -> std::io::Result<()> {
-    if path.is_dir() {
+    // get the shortened path
+    let short_path = path
+        .strip_prefix(top_path)
+        .expect("`top_path` was not a prefix of `path`.")
+        .to_path_buf();
+
+    let metadata = if options.follow_symlinks {
+        // skip broken symlinks
+        let Ok(metadata) = path.metadata() else {
+            return Ok(());
+        };
+        metadata
+    } else {
+        path.symlink_metadata()?
+    };
+
+    if metadata.is_symlink() {
+        if !included {
+            return Ok(());
+        }
+        tree.symlinks.push(SymlinkMeta {
+            short_path,
+            target: std::fs::read_link(path)?,
+            target_is_dir: path.is_dir(),
+        });
+    } else if metadata.is_dir() {
         // recursively traverse subdirectories
+        let mut is_empty = true;
         let entries = std::fs::read_dir(path)?;
         for entry in entries {
-            get_file_metas_helper(top_path, &entry?.path(), files)?;
+            is_empty = false;
+            let entry = entry?.path();
+            let short_entry = entry
+                .strip_prefix(top_path)
+                .expect("`top_path` was not a prefix of `entry`.");
+            if options.is_skipped(short_entry) {
+                continue;
+            }
+            let entry_included = options.is_included(short_entry, included);
+            get_file_tree_helper(top_path, &entry, options, entry_included, tree)?;
+        }
+
+        if is_empty && included {
+            tree.empty_dirs.push(short_path);
         }
-    } else if path.is_file() {
+    } else if metadata.is_file() && included {
         // return an error if a file couldn't be opened.
         std::fs::File::open(path)?;
 
-        // get the shortened path
-        let short_path = path
-            .strip_prefix(top_path)
-            .expect("`top_path` was not a prefix of `path`.")
-            .to_path_buf();
-
-        // get the file's size
-        let len = path.metadata()?.len();
-
         // insert this file metadata into set
         let meta = FileMetaLocal {
             local_path: path.to_path_buf(),
             short_path,
-            len,
+            len: metadata.len(),
+            hash: None,
+            head_hash: None,
+            compressible: is_compressible(path),
+            mode: unix_mode(&metadata),
+            modified: metadata.modified().ok(),
         };
-        files.push(meta);
+        tree.files.push(meta);
     }
 
     Ok(())
 }
+
+/// Returns the unix permission bits in `metadata`,
+/// or `None` if not on unix.
+fn unix_mode(metadata: &Metadata) -> Option<u32> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        Some(metadata.permissions().mode() & 0o777)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
@@ -0,0 +1,142 @@
+use std::time::Duration;
+
+/// Whether sizes are shown in powers of 1024 or 1000.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SizeUnits {
+    /// KiB, MiB, GiB, ... (powers of 1024)
+    #[default]
+    Binary,
+    /// kB, MB, GB, ... (powers of 1000)
+    Decimal,
+}
+
+/// Formats sizes, rates, and durations for people to read,
+/// so that every frontend shows them the same way.
+///
+/// ```
+/// # use gday_file_transfer::{HumanFormat, SizeUnits};
+/// # use std::time::Duration;
+/// let format = HumanFormat::default();
+/// assert_eq!(format.size(1536), "1.50 KiB");
+/// assert_eq!(format.rate(2_500_000.0), "2.38 MiB/s");
+/// assert_eq!(format.duration(Duration::from_secs(125)), "2m 05s");
+///
+/// let format = HumanFormat {
+///     units: SizeUnits::Decimal,
+///     decimal_separator: ',',
+/// };
+/// assert_eq!(format.size(1536), "1,54 kB");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HumanFormat {
+    /// Units to show sizes in
+    pub units: SizeUnits,
+    /// Character between the whole and fractional part of numbers
+    pub decimal_separator: char,
+}
+
+impl Default for HumanFormat {
+    fn default() -> Self {
+        Self {
+            units: SizeUnits::Binary,
+            decimal_separator: '.',
+        }
+    }
+}
+
+/// Languages that write numbers with a decimal comma.
+const DECIMAL_COMMA_LANGUAGES: &[&str] = &[
+    "bg", "ca", "cs", "da", "de", "el", "es", "et", "eu", "fi", "fr", "gl", "hr", "hu", "id", "is",
+    "it", "lt", "lv", "nb", "nl", "nn", "no", "pl", "pt", "ro", "ru", "sk", "sl", "sr", "sv", "tr",
+    "uk", "vi",
+];
+
+impl HumanFormat {
+    /// Returns a [`HumanFormat`] with these `units`, and the decimal separator
+    /// of the user's locale, from the `LC_ALL`, `LC_NUMERIC`, or `LANG`
+    /// environment variable.
+    pub fn from_env(units: SizeUnits) -> Self {
+        let locale = ["LC_ALL", "LC_NUMERIC", "LANG"]
+            .iter()
+            .filter_map(|var| std::env::var(var).ok())
+            .find(|value| !value.is_empty())
+            .unwrap_or_default();
+
+        Self {
+            units,
+            decimal_separator: decimal_separator(&locale),
+        }
+    }
+
+    /// Formats a number of bytes, such as `"1.50 MiB"`.
+    pub fn size(&self, bytes: u64) -> String {
+        let (base, prefixes) = match self.units {
+            SizeUnits::Binary => (1024.0, ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"]),
+            SizeUnits::Decimal => (1000.0, ["kB", "MB", "GB", "TB", "PB", "EB"]),
+        };
+
+        if (bytes as f64) < base {
+            return format!("{bytes} B");
+        }
+
+        let mut value = bytes as f64;
+        let mut prefix = prefixes[0];
+        for next in prefixes {
+            // so that 999.9 kB is shown as 1.00 MB instead of 1000 kB
+            if value < 999.5 {
+                break;
+            }
+            value /= base;
+            prefix = next;
+        }
+        format!("{} {prefix}", self.number(value))
+    }
+
+    /// Formats a transfer rate, such as `"1.50 MiB/s"`.
+    pub fn rate(&self, bytes_per_sec: f64) -> String {
+        format!("{}/s", self.size(bytes_per_sec.max(0.0) as u64))
+    }
+
+    /// Formats a duration with its two largest units, such as `"1h 05m"`,
+    /// or in milliseconds if it's shorter than a second.
+    pub fn duration(&self, duration: Duration) -> String {
+        let secs = duration.as_secs();
+        if secs == 0 {
+            return format!("{} ms", duration.as_millis());
+        }
+
+        let (days, hours, mins, secs) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60, secs % 60);
+        if days != 0 {
+            format!("{days}d {hours:02}h")
+        } else if hours != 0 {
+            format!("{hours}h {mins:02}m")
+        } else if mins != 0 {
+            format!("{mins}m {secs:02}s")
+        } else {
+            format!("{secs}s")
+        }
+    }
+
+    /// Formats `value` with 3 significant digits,
+    /// and this format's decimal separator.
+    fn number(&self, value: f64) -> String {
+        let formatted = if value < 9.995 {
+            format!("{value:.2}")
+        } else if value < 99.95 {
+            format!("{value:.1}")
+        } else {
+            format!("{value:.0}")
+        };
+        formatted.replace('.', &self.decimal_separator.to_string())
+    }
+}
+
+/// Returns the decimal separator used in `locale`, such as `"de_DE.UTF-8"`.
+fn decimal_separator(locale: &str) -> char {
+    let language = locale.split(['_', '.', '@', '-']).next().unwrap_or("");
+    if DECIMAL_COMMA_LANGUAGES.contains(&language.to_ascii_lowercase().as_str()) {
+        ','
+    } else {
+        '.'
+    }
+}
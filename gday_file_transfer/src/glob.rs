@@ -0,0 +1,140 @@
+use crate::Error;
+use std::path::{Component, Path};
+use std::str::FromStr;
+
+/// A shell-style pattern that matches paths, such as `"*.rs"` or `"src/**/test_*"`.
+///
+/// - `*` matches any characters except `/`.
+/// - `**` matches any characters, including `/`.
+///   `**/` also matches no directories at all.
+/// - `?` matches one character except `/`.
+/// - `[abc]`, `[a-z]`, and `[!abc]` match one character in (or not in) a set.
+///
+/// A pattern matches a path if it matches the last components of the path,
+/// so `"target"` matches `"project/target"`, and
+/// `"src/*.rs"` matches `"project/src/main.rs"`.
+///
+/// ```
+/// # use gday_file_transfer::Glob;
+/// # use std::path::Path;
+/// let glob: Glob = "*.rs".parse()?;
+/// assert!(glob.matches(Path::new("project/src/main.rs")));
+/// assert!(!glob.matches(Path::new("project/README.md")));
+/// # Ok::<(), gday_file_transfer::Error>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Glob {
+    pattern: Vec<char>,
+}
+
+impl FromStr for Glob {
+    type Err = Error;
+
+    /// Returns [`Error::InvalidGlob`] if `pattern` is empty,
+    /// or has an unclosed `[`.
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        let chars: Vec<char> = pattern.trim_end_matches('/').chars().collect();
+        if chars.is_empty() {
+            return Err(Error::InvalidGlob(pattern.to_string()));
+        }
+
+        let mut i = 0;
+        while i < chars.len() {
+            if chars[i] == '[' {
+                i = class_end(&chars, i).ok_or_else(|| Error::InvalidGlob(pattern.to_string()))?;
+            }
+            i += 1;
+        }
+        Ok(Self { pattern: chars })
+    }
+}
+
+impl Glob {
+    /// Returns whether this pattern matches the last components of `path`.
+    pub fn matches(&self, path: &Path) -> bool {
+        let components: Vec<String> = path
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+                _ => None,
+            })
+            .collect();
+
+        (0..components.len()).any(|start| {
+            let suffix: Vec<char> = components[start..].join("/").chars().collect();
+            matches_from(&self.pattern, &suffix)
+        })
+    }
+}
+
+/// Returns whether all of `pattern` matches all of `text`.
+fn matches_from(pattern: &[char], text: &[char]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        ['*', '*', rest @ ..] => {
+            // "**/" may match no directories
+            if let ['/', after_slash @ ..] = rest {
+                if matches_from(after_slash, text) {
+                    return true;
+                }
+            }
+            (0..=text.len()).any(|skip| matches_from(rest, &text[skip..]))
+        }
+        ['*', rest @ ..] => {
+            let max_skip = text.iter().position(|&c| c == '/').unwrap_or(text.len());
+            (0..=max_skip).any(|skip| matches_from(rest, &text[skip..]))
+        }
+        ['?', rest @ ..] => match text {
+            [c, text @ ..] if *c != '/' => matches_from(rest, text),
+            _ => false,
+        },
+        ['[', ..] => {
+            let end = class_end(pattern, 0).expect("Unclosed class wasn't rejected.");
+            match text {
+                [c, text @ ..] if *c != '/' && class_matches(&pattern[1..end], *c) => {
+                    matches_from(&pattern[end + 1..], text)
+                }
+                _ => false,
+            }
+        }
+        [p, rest @ ..] => match text {
+            [c, text @ ..] if c == p => matches_from(rest, text),
+            _ => false,
+        },
+    }
+}
+
+/// Returns the index of the `]` that closes the class starting at `pattern[start]`.
+fn class_end(pattern: &[char], start: usize) -> Option<usize> {
+    let mut i = start + 1;
+    if matches!(pattern.get(i), Some('!' | '^')) {
+        i += 1;
+    }
+    // a leading ']' is part of the class
+    if pattern.get(i) == Some(&']') {
+        i += 1;
+    }
+    (i..pattern.len()).find(|&i| pattern[i] == ']')
+}
+
+/// Returns whether `c` is in the `class`, which
+/// is the contents of a `[...]` without the brackets.
+fn class_matches(class: &[char], c: char) -> bool {
+    let (negated, class) = match class {
+        ['!' | '^', rest @ ..] => (true, rest),
+        _ => (false, class),
+    };
+
+    let mut found = false;
+    let mut i = 0;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == '-' {
+            found |= (class[i]..=class[i + 2]).contains(&c);
+            i += 3;
+        } else {
+            found |= class[i] == c;
+            i += 1;
+        }
+    }
+    found != negated
+}
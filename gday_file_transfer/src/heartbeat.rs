@@ -0,0 +1,204 @@
+use crate::offer::read_body_async;
+use crate::{write_to_async, Error};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::future::Future;
+use std::pin::pin;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+
+/// Sent by [`with_heartbeats()`] to show the peer that this peer is still there.
+///
+/// Skipped by [`crate::read_from()`] and [`crate::read_from_async()`].
+#[derive(Serialize, Deserialize, Debug)]
+struct HeartbeatMsg {
+    /// Number of heartbeats sent before this one.
+    heartbeat: u64,
+}
+
+/// Returns whether the message in `buf` is a heartbeat.
+pub(crate) fn is_heartbeat(buf: &[u8]) -> bool {
+    serde_json::from_slice::<HeartbeatMsg>(buf).is_ok()
+}
+
+/// Runs `future` to completion, meanwhile writing a heartbeat
+/// to `writer` every `interval`.
+///
+/// Use it during idle phases of the protocol, such as while the user
+/// decides whether to accept an offer, so the peer can tell with
+/// [`read_from_async_timeout()`] that this peer hasn't vanished.
+///
+/// Heartbeats are never cut short, so `writer` is ready for
+/// the next message once this returns.
+pub async fn with_heartbeats<T>(
+    writer: &mut (impl AsyncWrite + Unpin),
+    interval: Duration,
+    future: impl Future<Output = T>,
+) -> Result<T, Error> {
+    let mut future = pin!(future);
+    let mut heartbeat = 0;
+    loop {
+        tokio::select! {
+            output = &mut future => return Ok(output),
+            () = tokio::time::sleep(interval) => {
+                write_heartbeat(heartbeat, writer).await?;
+                heartbeat += 1;
+            }
+        }
+    }
+}
+
+/// Like [`with_heartbeats()`], but meanwhile also reads from `reader`,
+/// where the peer is expected to send nothing but heartbeats.
+///
+/// Returns [`Error::PeerTimedOut`] if the peer goes `dead_peer_timeout`
+/// without sending a heartbeat, and an error if it closes the connection,
+/// or sends anything else. So the peer should wait for the reply with
+/// [`with_heartbeats()`], sending heartbeats more often than `dead_peer_timeout`.
+///
+/// Stops reading once `future` completes, which may be partway through
+/// a heartbeat, unless `reader` delivers each message whole,
+/// as an encrypted stream does.
+pub async fn with_heartbeats_watching_peer<T>(
+    reader: &mut (impl AsyncRead + Unpin),
+    writer: &mut (impl AsyncWrite + Unpin),
+    interval: Duration,
+    dead_peer_timeout: Duration,
+    future: impl Future<Output = T>,
+) -> Result<T, Error> {
+    // heartbeats are skipped, so this only returns on anything else
+    let peer_gone = read_from_async_timeout::<HeartbeatMsg>(reader, dead_peer_timeout);
+    tokio::select! {
+        output = with_heartbeats(writer, interval, future) => output,
+        Err(err) = peer_gone => Err(err),
+    }
+}
+
+/// Writes the heartbeat that follows `heartbeat` earlier ones to `writer`.
+pub(crate) async fn write_heartbeat(
+    heartbeat: u64,
+    writer: &mut (impl AsyncWrite + Unpin),
+) -> Result<(), Error> {
+    write_to_async(HeartbeatMsg { heartbeat }, writer).await
+}
+
+/// Like [`crate::read_from_async()`], but returns [`Error::PeerTimedOut`]
+/// if the peer goes `dead_peer_timeout` without sending anything,
+/// including heartbeats.
+///
+/// Lets a peer waiting for a reply notice promptly when the other peer
+/// vanished without closing the connection.
+/// The other peer should send heartbeats with [`with_heartbeats()`]
+/// more often than `dead_peer_timeout`.
+pub async fn read_from_async_timeout<T: DeserializeOwned>(
+    reader: &mut (impl AsyncRead + Unpin),
+    dead_peer_timeout: Duration,
+) -> Result<T, Error> {
+    loop {
+        // long messages may take a while to arrive,
+        // so only the start of each one is timed
+        let mut header = [0_u8; 5];
+        tokio::time::timeout(dead_peer_timeout, reader.read_exact(&mut header))
+            .await
+            .map_err(|_| Error::PeerTimedOut(dead_peer_timeout))??;
+
+        if let Some(msg) = read_body_async(reader, header).await? {
+            return Ok(msg);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_from_async_timeout, with_heartbeats, with_heartbeats_watching_peer};
+    use crate::{write_to_async, Error};
+    use std::time::Duration;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_heartbeats() {
+        let (mut a, mut b) = tokio::io::duplex(1000);
+
+        // heartbeats keep the reader waiting until the reply arrives
+        let slow_reply = async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            5_u32
+        };
+        let sender = async {
+            let reply = with_heartbeats(&mut a, Duration::from_secs(5), slow_reply).await;
+            write_to_async(reply.unwrap(), &mut a).await.unwrap();
+        };
+        let ((), received) = tokio::join!(
+            sender,
+            read_from_async_timeout::<u32>(&mut b, Duration::from_secs(20))
+        );
+        assert_eq!(received.unwrap(), 5);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_timeout() {
+        let (_a, mut b) = tokio::io::duplex(1000);
+        let result = read_from_async_timeout::<u32>(&mut b, Duration::from_secs(20)).await;
+        assert!(matches!(result, Err(Error::PeerTimedOut(_))));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_watching_peer() {
+        let (mut a, mut b) = tokio::io::duplex(1000);
+        let (mut a_reader, mut a_writer) = tokio::io::split(&mut a);
+
+        // the peer waiting for the reply sends heartbeats too
+        let slow_reply = async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            5_u32
+        };
+        let replier = async {
+            let reply = with_heartbeats_watching_peer(
+                &mut a_reader,
+                &mut a_writer,
+                Duration::from_secs(5),
+                Duration::from_secs(20),
+                slow_reply,
+            )
+            .await;
+            write_to_async(reply.unwrap(), &mut a_writer).await.unwrap();
+        };
+        let waiter = async {
+            let (mut b_reader, mut b_writer) = tokio::io::split(&mut b);
+            let reply = read_from_async_timeout::<u32>(&mut b_reader, Duration::from_secs(20));
+            with_heartbeats(&mut b_writer, Duration::from_secs(5), reply).await
+        };
+        let ((), received) = tokio::join!(replier, waiter);
+        assert_eq!(received.unwrap().unwrap(), 5);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_watching_vanished_peer() {
+        // a peer that sends nothing times out
+        let (mut a, _b) = tokio::io::duplex(1000);
+        let (mut reader, mut writer) = tokio::io::split(&mut a);
+        let result = with_heartbeats_watching_peer(
+            &mut reader,
+            &mut writer,
+            Duration::from_secs(5),
+            Duration::from_secs(20),
+            std::future::pending::<()>(),
+        )
+        .await;
+        assert!(matches!(result, Err(Error::PeerTimedOut(_))));
+
+        // a peer that closes the connection is noticed at once
+        let (mut a, b) = tokio::io::duplex(1000);
+        drop(b);
+        let (mut reader, mut writer) = tokio::io::split(&mut a);
+        let started = tokio::time::Instant::now();
+        let result = with_heartbeats_watching_peer(
+            &mut reader,
+            &mut writer,
+            Duration::from_secs(5),
+            Duration::from_secs(20),
+            std::future::pending::<()>(),
+        )
+        .await;
+        assert!(matches!(result, Err(Error::IO(_))));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -52,23 +52,78 @@
 #![forbid(unsafe_code)]
 #![warn(clippy::all)]
 
+mod archive;
+mod cancel;
+mod chunk;
+mod clock;
+mod compression;
+mod dedup;
+mod delta;
+mod disk_space;
+mod entries;
+mod exchange;
 mod file_meta;
+mod format;
+mod glob;
+mod heartbeat;
+mod multi_stream;
 mod offer;
+mod overview;
+mod partial_download;
+mod path_policy;
+mod pipe;
+mod rate_limit;
+mod schedule;
+mod speed;
+mod speedtest;
+mod summary;
+mod sync;
 mod transfer;
 
 use std::path::PathBuf;
 use thiserror::Error;
 
-pub use crate::file_meta::{get_file_metas, FileMeta, FileMetaLocal};
+pub use crate::cancel::{write_cancel, write_cancel_async, CancelReason, CancelToken};
+pub use crate::clock::{estimate_clock_skew, ClockSkew};
+pub use crate::compression::Compression;
+pub use crate::dedup::find_duplicates;
+pub use crate::delta::{BlockSignature, Signature};
+pub use crate::disk_space::{ensure_disk_space, DiskSpace};
+pub use crate::entries::{check_entries, create_entries, EntryPolicy};
+pub use crate::exchange::{exchange_files, Direction, ExchangeSummary};
+pub use crate::file_meta::{
+    get_file_metas, get_file_tree, is_case_insensitive, FileMeta, FileMetaLocal, FileTreeLocal,
+    OfferOptions, OverwritePolicy, SymlinkMeta, HEAD_LEN,
+};
+pub use crate::format::{HumanFormat, SizeUnits};
+pub use crate::glob::Glob;
+pub use crate::heartbeat::{
+    read_from_async_timeout, with_heartbeats, with_heartbeats_watching_peer,
+};
+pub use crate::multi_stream::{receive_files_multi, send_files_multi, StreamReport};
 pub use crate::offer::{
     read_from, read_from_async, write_to, write_to_async, FileOfferMsg, FileResponseMsg,
 };
-pub use crate::transfer::{receive_files, send_files, TransferReport};
+pub use crate::overview::{ExtensionStats, OfferOverview};
+pub use crate::partial_download::{
+    find_partial_downloads, relocate_partial_downloads, PartialDownload,
+};
+pub use crate::path_policy::PathPolicy;
+pub use crate::pipe::{receive_file_to, send_file_from};
+pub use crate::rate_limit::{RateLimit, RateLimited};
+pub use crate::schedule::wait_for_start;
+pub use crate::speedtest::{run_speedtest, SpeedtestReport};
+pub use crate::summary::{FileStatus, FileSummary, TransferSummary};
+pub use crate::sync::{receive_sync_session, sync_session, SyncOptions};
+pub use crate::transfer::{
+    receive_files, receive_files_cancellable, receive_files_with_recovery, send_files,
+    send_files_cancellable, StorageFullAction, TransferReport,
+};
 
 /// Version of the protocol.
 /// Different numbers wound indicate
 /// incompatible protocol breaking changes.
-pub const PROTOCOL_VERSION: u8 = 1;
+pub const PROTOCOL_VERSION: u8 = 2;
 
 /// `gday_file_transfer` error.
 #[derive(Error, Debug)]
@@ -85,11 +140,34 @@ pub enum Error {
 
     /// All 100 suitable locations to save [`FileMeta`] are occupied.
     ///
-    /// Comes from [`FileMeta::get_unoccupied_save_path()`]
-    /// or [`FileMeta::get_partial_download_path()`].
+    /// Comes from [`FileMeta::get_unoccupied_save_path()`],
+    /// [`FileMeta::get_partial_download_path()`],
+    /// or [`TransferSummary::write_manifest()`].
     #[error("100 files with base name '{0}' already exist. Aborting save.")]
     FilenameOccupied(PathBuf),
 
+    /// Another transfer is currently writing this partial download,
+    /// probably into the same directory.
+    #[error("'{0}' is being downloaded by another transfer.")]
+    PartialDownloadInUse(PathBuf),
+
+    /// An offered path leaves the save directory, with `..` or a root,
+    /// or has no names in it.
+    #[error("Offered path '{0}' is outside the save directory.")]
+    UnsafePath(PathBuf),
+
+    /// An offered path is nested deeper than [`PathPolicy::max_depth`] allows.
+    #[error("Offered path '{0}' is nested too deeply to save.")]
+    PathTooDeep(PathBuf),
+
+    /// An offered path is longer than [`PathPolicy::max_len`] allows.
+    #[error("Offered path '{0}' is too long to save.")]
+    PathTooLong(PathBuf),
+
+    /// [`OverwritePolicy::FailIfExists`] refused to overwrite this file.
+    #[error("'{0}' already exists, and overwriting it isn't allowed.")]
+    FileExists(PathBuf),
+
     /// [`FileOfferMsg`] or [`FileResponseMsg`] was longer than 2^32
     /// bytes when serialized.
     ///
@@ -134,4 +212,91 @@ pub enum Error {
         Check if this software is up-to-date."
     )]
     IncompatibleProtocol,
+
+    /// A received file's contents didn't match the hash sent by the peer.
+    ///
+    /// The partially downloaded file is deleted,
+    /// so that retrying downloads it from the start.
+    #[error(
+        "Received file '{}' is corrupted: its contents don't match the sender's hash.",
+        .0.display()
+    )]
+    ChecksumMismatch(PathBuf),
+
+    /// A [`Glob`] pattern was empty, or had an unclosed `[`.
+    #[error("Invalid pattern '{0}': it's empty, or has an unclosed '['.")]
+    InvalidGlob(String),
+
+    /// The offer contained symlinks or empty directories,
+    /// which [`EntryPolicy::Error`] doesn't allow.
+    #[error("Your peer offered symlinks or empty folders, which aren't allowed.")]
+    EntriesNotAllowed,
+
+    /// An offered symlink pointed outside the directory it would be saved in.
+    ///
+    /// Such a symlink could later redirect files to anywhere on this machine.
+    #[error("Offered symlink '{}' points outside the save directory.", .0.display())]
+    SymlinkEscapes(PathBuf),
+
+    /// The peer sent a chunk of file contents that wasn't expected,
+    /// such as one for a different file or offset,
+    /// or one that didn't decompress to a valid length.
+    #[error("Peer sent file contents that don't match the offer.")]
+    InvalidChunk,
+
+    /// A [`Signature`] in [`FileResponseMsg::deltas`] had an invalid block length,
+    /// or was for a file that wasn't accepted from its start.
+    #[error("Peer requested a delta transfer with an invalid signature.")]
+    InvalidSignature,
+
+    /// The peer sent a malformed tar archive, or one whose entries
+    /// don't match the accepted files. See [`FileResponseMsg::archive`].
+    #[error("Peer sent an invalid archive.")]
+    InvalidArchive,
+
+    /// [`receive_file_to()`] or [`send_file_from()`] was given a response
+    /// that doesn't accept exactly one file.
+    #[error("Can only stream a response that accepts exactly one file.")]
+    NotSingleFile,
+
+    /// The accepted files won't fit in the free space of the save path's
+    /// file system. Comes from [`ensure_disk_space()`] and [`receive_files()`].
+    #[error("The accepted files need {needed} bytes, but only {available} bytes are free.")]
+    InsufficientDiskSpace {
+        /// Number of bytes left to download
+        needed: u64,
+        /// Number of bytes free on the file system
+        available: u64,
+    },
+
+    /// In a multi-stream transfer, the peer sent a file that wasn't accepted,
+    /// or was already sent.
+    #[error("Peer sent file with index {0}, which wasn't requested.")]
+    InvalidFileIndex(u32),
+
+    /// A file in [`FileResponseMsg::copies`] isn't accepted,
+    /// or isn't the same length as an accepted original that isn't a copy itself.
+    #[error("File with index {0} can't be copied from the file given in the response.")]
+    InvalidCopy(usize),
+
+    /// In a multi-stream transfer, all streams ended
+    /// before every accepted file was received.
+    #[error("Peer finished sending before sending all requested files.")]
+    IncompleteTransfer,
+
+    /// The peer sent nothing, not even a heartbeat, for this long
+    /// while a reply was expected from it.
+    #[error("Your peer sent nothing for {0:?}, and has probably disconnected.")]
+    PeerTimedOut(std::time::Duration),
+
+    /// The peer cancelled the transfer, for this reason.
+    ///
+    /// Comes from a cancellation message, or from the abort chunk
+    /// sent by a cancelled [`send_files_cancellable()`].
+    #[error("Your peer cancelled the transfer: {0}")]
+    PeerCancelled(CancelReason),
+
+    /// This peer cancelled the transfer with a [`CancelToken`], for this reason.
+    #[error("Transfer cancelled: {0}")]
+    Cancelled(CancelReason),
 }
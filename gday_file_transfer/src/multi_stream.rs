@@ -0,0 +1,321 @@
+//! Transfers files over several streams at once,
+//! such as multiple TCP connections, to saturate
+//! high-bandwidth high-latency paths where a single TCP stream can't.
+//!
+//! Each stream carries whole files, one after another.
+//! Before each file, the sender writes its index in
+//! [`FileOfferMsg::files`] as a big-endian `u32`.
+//! After its last file, the sender writes [`END_OF_STREAM`].
+//! The contents of each file are sent in chunks just like by [`send_files()`](crate::send_files()).
+
+use crate::dedup::{check_copies, save_copies};
+use crate::speed::Speedometer;
+use crate::transfer::{receive_some_files, send_some_files};
+use crate::{
+    CancelToken, Error, FileMetaLocal, FileOfferMsg, FileResponseMsg, StorageFullAction,
+    TransferReport, TransferSummary,
+};
+use std::cell::{Cell, RefCell};
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::Poll;
+use std::time::Instant;
+use tokio::io::{AsyncBufRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Written instead of a file index when a stream has no more files.
+const END_OF_STREAM: u32 = u32::MAX;
+
+/// Like [`send_files()`](crate::send_files()), but splits the accepted files across `streams`,
+/// sending a different file on each stream at the same time.
+///
+/// The peer must call [`receive_files_multi()`] with the other ends
+/// of the same streams, in any order.
+///
+/// Each stream picks up the next file as soon as it's done with its last one,
+/// starting with the largest files, so that the streams finish close together.
+///
+/// `progress_callback` gets a [`StreamReport`] whenever one of the streams makes progress.
+pub async fn send_files_multi<S: AsyncWrite + Unpin>(
+    offer: &[FileMetaLocal],
+    response: &FileResponseMsg,
+    streams: &mut [S],
+    progress_callback: impl FnMut(&StreamReport),
+) -> Result<(), Error> {
+    if offer.len() != response.response.len() {
+        return Err(Error::InvalidResponseLength);
+    }
+    let lens: Vec<u64> = offer.iter().map(|file| file.len).collect();
+    check_copies(response, &lens)?;
+
+    // the accepted files, largest remaining size first
+    let mut queue = Vec::new();
+    let mut total_bytes = 0;
+    for (index, (file, start)) in offer.iter().zip(&response.response).enumerate() {
+        if response.copies.contains_key(&index) {
+            continue;
+        }
+        if let Some(start) = start {
+            let remaining = file
+                .len
+                .checked_sub(*start)
+                .ok_or(Error::InvalidStartIndex)?;
+            total_bytes += remaining;
+            queue.push((index, remaining));
+        }
+    }
+    queue.sort_by_key(|&(_, remaining)| std::cmp::Reverse(remaining));
+
+    let progress = RefCell::new(SharedProgress::new(
+        total_bytes,
+        queue.len() as u64,
+        progress_callback,
+    ));
+    let next = Cell::new(0);
+    let cancel = &CancelToken::new();
+    let (queue, progress, next) = (&queue, &progress, &next);
+
+    let workers = streams
+        .iter_mut()
+        .enumerate()
+        .map(|(i, stream)| async move {
+            while let Some(&(index, _)) = queue.get(next.get()) {
+                next.set(next.get() + 1);
+
+                // offers have fewer than 2^32 files, since they're shorter than 2^32 bytes
+                let file_index = index as u32;
+                let start = response.response[index].expect("Queued a rejected file.");
+                stream.write_u32(file_index).await?;
+                let mut last = 0;
+                send_some_files(
+                    &[(file_index, &offer[index], start)],
+                    response,
+                    &mut *stream,
+                    |report| progress.borrow_mut().add(i, report, &mut last),
+                    cancel,
+                )
+                .await?;
+                progress.borrow_mut().finish_file(i);
+            }
+            stream.write_u32(END_OF_STREAM).await?;
+            stream.flush().await?;
+            Ok(())
+        });
+
+    try_join_all(workers.collect()).await
+}
+
+/// Like [`receive_files()`](crate::receive_files()), but receives the accepted files
+/// from all of `streams` at the same time.
+///
+/// The peer must call [`send_files_multi()`] with the other ends
+/// of the same streams, in any order.
+///
+/// Aborts if the disk fills up.
+/// `progress_callback` gets a [`StreamReport`] whenever one of the streams makes progress.
+///
+/// Returns [`Error::InvalidFileIndex`] if the peer sends a file
+/// that wasn't accepted, or was already sent,
+/// and [`Error::IncompleteTransfer`] if the streams end
+/// before all accepted files arrived.
+pub async fn receive_files_multi<S: AsyncBufRead + Unpin>(
+    offer: &FileOfferMsg,
+    response: &FileResponseMsg,
+    save_path: &Path,
+    streams: &mut [S],
+    progress_callback: impl FnMut(&StreamReport),
+) -> Result<TransferSummary, Error> {
+    let started = Instant::now();
+    let summary = RefCell::new(TransferSummary::new(offer, response));
+    let result = receive_all(
+        offer,
+        response,
+        save_path,
+        streams,
+        progress_callback,
+        &summary,
+    )
+    .await;
+    summary
+        .into_inner()
+        .finish(result, started.elapsed(), response, save_path)
+}
+
+/// Does the work of [`receive_files_multi()`],
+/// recording each file that arrives in `summary`.
+async fn receive_all<S: AsyncBufRead + Unpin>(
+    offer: &FileOfferMsg,
+    response: &FileResponseMsg,
+    save_path: &Path,
+    streams: &mut [S],
+    progress_callback: impl FnMut(&StreamReport),
+    summary: &RefCell<TransferSummary>,
+) -> Result<(), Error> {
+    let total_bytes = offer.get_transfer_size(response)?;
+    let lens: Vec<u64> = offer.files.iter().map(|file| file.len).collect();
+    check_copies(response, &lens)?;
+    let num_files = response.get_num_not_rejected() - response.copies.len();
+
+    let progress = RefCell::new(SharedProgress::new(
+        total_bytes,
+        num_files as u64,
+        progress_callback,
+    ));
+    let received = RefCell::new(vec![false; offer.files.len()]);
+    let cancel = &CancelToken::new();
+    let (progress, received_ref) = (&progress, &received);
+
+    let workers = streams
+        .iter_mut()
+        .enumerate()
+        .map(|(i, stream)| async move {
+            loop {
+                let index = stream.read_u32().await?;
+                if index == END_OF_STREAM {
+                    return Ok(());
+                }
+
+                // only accept each accepted file once
+                {
+                    let mut received = received_ref.borrow_mut();
+                    let file = index as usize;
+                    let accepted = response.response.get(file).is_some_and(Option::is_some)
+                        && !response.copies.contains_key(&file);
+                    if !accepted || received[file] {
+                        return Err(Error::InvalidFileIndex(index));
+                    }
+                    received[file] = true;
+                }
+
+                let file = index as usize;
+                let start = response.response[file].expect("Checked that the file was accepted.");
+                let mut last = 0;
+                receive_some_files(
+                    &[(index, &offer.files[file], start)],
+                    response,
+                    save_path,
+                    &mut *stream,
+                    |report| progress.borrow_mut().add(i, report, &mut last),
+                    |_| std::future::ready(StorageFullAction::Abort),
+                    |index, file| summary.borrow_mut().files[index as usize] = file,
+                    cancel,
+                )
+                .await?;
+                progress.borrow_mut().finish_file(i);
+            }
+        });
+
+    try_join_all(workers.collect()).await?;
+
+    let received = received.into_inner();
+    let all_received =
+        response
+            .response
+            .iter()
+            .zip(received)
+            .enumerate()
+            .all(|(i, (accepted, received))| {
+                accepted.is_none() || received || response.copies.contains_key(&i)
+            });
+    if !all_received {
+        return Err(Error::IncompleteTransfer);
+    }
+    save_copies(offer, response, save_path, &mut summary.borrow_mut())
+}
+
+/// Progress of a transfer over several streams,
+/// passed to the `progress_callback` of
+/// [`send_files_multi()`] and [`receive_files_multi()`].
+#[derive(Debug)]
+pub struct StreamReport<'a> {
+    /// Index in `streams` of the stream that made progress
+    pub stream: usize,
+    /// Progress of that stream.
+    /// Its [`TransferReport::total_bytes`] and [`TransferReport::total_files`]
+    /// only count the file it's transferring now.
+    pub stream_report: &'a TransferReport,
+    /// Combined progress of all streams
+    pub total: &'a TransferReport,
+}
+
+/// Combines the progress of all streams into one [`TransferReport`].
+struct SharedProgress<F: FnMut(&StreamReport)> {
+    report: TransferReport,
+    /// The last report of each stream, by index
+    streams: Vec<TransferReport>,
+    speedometer: Speedometer,
+    progress_callback: F,
+}
+
+impl<F: FnMut(&StreamReport)> SharedProgress<F> {
+    fn new(total_bytes: u64, total_files: u64, progress_callback: F) -> Self {
+        Self {
+            report: TransferReport::new(total_bytes, total_files),
+            streams: Vec::new(),
+            speedometer: Speedometer::new(),
+            progress_callback,
+        }
+    }
+
+    /// Adds the progress in `stream_report` of a single file on `stream`,
+    /// given that `last` bytes of it were already added.
+    fn add(&mut self, stream: usize, stream_report: &TransferReport, last: &mut u64) {
+        self.report.processed_bytes += stream_report.processed_bytes - *last;
+        *last = stream_report.processed_bytes;
+        self.report.start_file(
+            &stream_report.current_file,
+            stream_report.current_file_len,
+            stream_report.current_file_processed,
+        );
+        self.speedometer.update(&mut self.report);
+        if self.streams.len() <= stream {
+            self.streams
+                .resize_with(stream + 1, || TransferReport::new(0, 0));
+        }
+        self.streams[stream].clone_from(stream_report);
+        self.report_progress(stream);
+    }
+
+    /// Counts one more file as processed by `stream`.
+    fn finish_file(&mut self, stream: usize) {
+        self.report.processed_files += 1;
+        self.report_progress(stream);
+    }
+
+    /// Passes the progress of `stream` and of all streams to the callback.
+    fn report_progress(&mut self, stream: usize) {
+        if let Some(stream_report) = self.streams.get(stream) {
+            (self.progress_callback)(&StreamReport {
+                stream,
+                stream_report,
+                total: &self.report,
+            });
+        }
+    }
+}
+
+/// Polls all `futures` concurrently, until they all finish
+/// or one of them fails.
+async fn try_join_all<F: Future<Output = Result<(), Error>>>(futures: Vec<F>) -> Result<(), Error> {
+    let mut futures: Vec<Option<Pin<Box<F>>>> =
+        futures.into_iter().map(|f| Some(Box::pin(f))).collect();
+
+    std::future::poll_fn(|cx| {
+        let mut pending = false;
+        for slot in &mut futures {
+            if let Some(future) = slot {
+                match future.as_mut().poll(cx) {
+                    Poll::Ready(Ok(())) => *slot = None,
+                    Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                    Poll::Pending => pending = true,
+                }
+            }
+        }
+        if pending {
+            Poll::Pending
+        } else {
+            Poll::Ready(Ok(()))
+        }
+    })
+    .await
+}
@@ -1,8 +1,14 @@
-use crate::{Error, FileMeta, FileMetaLocal, PROTOCOL_VERSION};
+use crate::file_meta::suffix_with_number;
+use crate::heartbeat::is_heartbeat;
+use crate::{
+    cancel::CancelMsg, ClockSkew, Compression, Error, FileMeta, FileMetaLocal, FileTreeLocal,
+    OverwritePolicy, PathPolicy, Signature, SymlinkMeta, PROTOCOL_VERSION,
+};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::{
+    collections::{BTreeMap, HashSet},
     io::{Read, Write},
-    path::Path,
+    path::{Path, PathBuf},
 };
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
@@ -11,6 +17,36 @@ use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct FileOfferMsg {
     pub files: Vec<FileMeta>,
+    /// Compression that the sender proposes to use.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<Compression>,
+    /// Offered symlinks, which the receiver may recreate
+    /// with [`crate::create_entries()`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub symlinks: Vec<SymlinkMeta>,
+    /// Offered empty directories, which the receiver may recreate
+    /// with [`crate::create_entries()`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub empty_dirs: Vec<PathBuf>,
+    /// Whether the sender proposes to send the accepted files
+    /// as one streamed tar archive, instead of in chunks.
+    ///
+    /// Saves overhead when sending many tiny files.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub archive: bool,
+    /// Number of streams that the sender proposes to send the accepted files on,
+    /// with [`crate::send_files_multi()`]. The peers open the extra streams
+    /// after the offer is answered, in a way of their choosing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub streams: Option<u32>,
+    /// Offered files with the same contents as an earlier offered file,
+    /// from their index in [`Self::files`] to the earlier file's index,
+    /// as found by [`crate::find_duplicates()`].
+    ///
+    /// The receiver may copy them locally instead of receiving them,
+    /// with [`FileResponseMsg::copy_duplicates()`].
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub duplicates: BTreeMap<usize, usize>,
 }
 
 impl FileOfferMsg {
@@ -22,6 +58,8 @@ impl FileOfferMsg {
 
     /// Returns the number of bytes that would be transferred for this
     /// [`FileOfferMsg`] and corresponding [`FileResponseMsg`].
+    ///
+    /// Files in [`FileResponseMsg::copies`] aren't transferred, so aren't counted.
     pub fn get_transfer_size(&self, response: &FileResponseMsg) -> Result<u64, Error> {
         // The response must have the same number of elements
         // as the offer.
@@ -31,7 +69,10 @@ impl FileOfferMsg {
 
         // sum up total transfer size
         let mut total_bytes = 0;
-        for (file, start) in self.files.iter().zip(response.response.iter()) {
+        for (index, (file, start)) in self.files.iter().zip(response.response.iter()).enumerate() {
+            if response.copies.contains_key(&index) {
+                continue;
+            }
             if let Some(start) = start {
                 total_bytes += file
                     .len
@@ -41,13 +82,136 @@ impl FileOfferMsg {
         }
         Ok(total_bytes)
     }
+
+    /// Renames offered files whose [`FileMeta::short_path`]s
+    /// differ only in case, such as `Readme.md` and `README.md`,
+    /// so that one doesn't overwrite the other
+    /// on a case-insensitive file system (see [`crate::is_case_insensitive()`]).
+    ///
+    /// Keeps the first of each such group of files, and suffixes
+    /// the names of the rest with `" (1)"`, `" (2)"`, etc.
+    /// Call this before responding to the offer.
+    ///
+    /// Returns the original and new path of each renamed file.
+    pub fn rename_case_collisions(&mut self) -> Vec<(PathBuf, PathBuf)> {
+        self.rename_collisions(|path| path.to_string_lossy().to_lowercase())
+    }
+
+    /// Adapts the offered paths to `policy`, and renames any files
+    /// whose paths then collide, like [`Self::rename_case_collisions()`].
+    /// Call this before responding to the offer.
+    ///
+    /// Returns the original and new path of each renamed file, symlink,
+    /// and empty directory, or an error if a path can't be adapted,
+    /// such as [`Error::UnsafePath`]. Declines [`Self::archive`] if anything
+    /// was renamed, since the archive holds the original paths.
+    pub fn apply_path_policy(
+        &mut self,
+        policy: &PathPolicy,
+    ) -> Result<Vec<(PathBuf, PathBuf)>, Error> {
+        let mut renamed = Vec::new();
+        let mut sanitize = |path: &mut PathBuf| -> Result<(), Error> {
+            let sanitized = policy.sanitize(path)?;
+            if sanitized != *path {
+                renamed.push((std::mem::replace(path, sanitized.clone()), sanitized));
+            }
+            Ok(())
+        };
+        for path in self.symlinks.iter_mut().map(|link| &mut link.short_path) {
+            sanitize(path)?;
+        }
+        for path in &mut self.empty_dirs {
+            sanitize(path)?;
+        }
+
+        let original: Vec<PathBuf> = self.files.iter().map(|f| f.short_path.clone()).collect();
+        for file in &mut self.files {
+            policy
+                .sanitize(&file.short_path)?
+                .clone_into(&mut file.short_path);
+        }
+        if policy.case_collisions {
+            self.rename_case_collisions();
+        } else {
+            self.rename_collisions(|path| path.to_string_lossy().into_owned());
+        }
+        for (old, file) in original.into_iter().zip(&self.files) {
+            if old != file.short_path {
+                renamed.push((old, file.short_path.clone()));
+            }
+        }
+
+        if !renamed.is_empty() {
+            self.archive = false;
+        }
+        Ok(renamed)
+    }
+
+    /// Suffixes the paths of offered files that are the same as
+    /// an earlier one's after `fold`, and returns the original
+    /// and new path of each renamed file.
+    fn rename_collisions(&mut self, fold: impl Fn(&Path) -> String) -> Vec<(PathBuf, PathBuf)> {
+        let mut taken: HashSet<String> = self.files.iter().map(|f| fold(&f.short_path)).collect();
+        let mut seen = HashSet::new();
+        let mut renamed = Vec::new();
+
+        for file in &mut self.files {
+            if seen.insert(fold(&file.short_path)) {
+                continue;
+            }
+
+            // find a free name, which may have been offered too
+            let mut new_path = file.short_path.clone();
+            for number in 1.. {
+                new_path.clone_from(&file.short_path);
+                suffix_with_number(&mut new_path, number);
+                if taken.insert(fold(&new_path)) {
+                    break;
+                }
+            }
+            renamed.push((
+                std::mem::replace(&mut file.short_path, new_path.clone()),
+                new_path,
+            ));
+        }
+        renamed
+    }
+
+    /// Converts each file's [`FileMeta::modified`] from the peer's clock
+    /// to this machine's clock, so that files restored with
+    /// [`FileResponseMsg::preserve_metadata`] aren't dated in the future.
+    ///
+    /// Drops modification times that can't be converted.
+    pub fn adjust_for_clock_skew(&mut self, skew: &ClockSkew) {
+        for file in &mut self.files {
+            file.modified = file.modified.and_then(|time| skew.peer_to_local(time));
+        }
+    }
 }
 
 impl From<Vec<FileMetaLocal>> for FileOfferMsg {
     fn from(local_files: Vec<FileMetaLocal>) -> Self {
         let files = local_files.into_iter().map(FileMeta::from).collect();
 
-        Self { files }
+        Self {
+            files,
+            compression: None,
+            symlinks: Vec::new(),
+            empty_dirs: Vec::new(),
+            archive: false,
+            streams: None,
+            duplicates: BTreeMap::new(),
+        }
+    }
+}
+
+impl From<FileTreeLocal> for FileOfferMsg {
+    fn from(tree: FileTreeLocal) -> Self {
+        Self {
+            symlinks: tree.symlinks,
+            empty_dirs: tree.empty_dirs,
+            ..Self::from(tree.files)
+        }
     }
 }
 
@@ -67,6 +231,51 @@ pub struct FileResponseMsg {
     /// file from [`FileOfferMsg::files`] at the same index.
     /// Only bytes `(start_byte..)` will be sent.
     pub response: Vec<Option<u64>>,
+    /// Compression that the sender should use.
+    /// Either [`FileOfferMsg::compression`], or `None` to decline it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<Compression>,
+    /// Whether the receiver restores the [`FileMeta::mode`] and
+    /// [`FileMeta::modified`] of each received file.
+    /// Otherwise, received files get default permissions
+    /// and are dated when they were received.
+    #[serde(default)]
+    pub preserve_metadata: bool,
+    /// [`Signature`]s of older versions of offered files that the receiver
+    /// already has at their [`FileMeta::get_save_path()`], by index in
+    /// [`FileOfferMsg::files`].
+    ///
+    /// Those files are sent as deltas against the older versions,
+    /// which they then replace. They must be accepted from byte 0.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub deltas: BTreeMap<usize, Signature>,
+    /// Accepted files that the receiver copies from another accepted file
+    /// with the same contents, instead of receiving them,
+    /// from their index in [`FileOfferMsg::files`] to the other file's index.
+    ///
+    /// Taken from [`FileOfferMsg::duplicates`] by [`Self::copy_duplicates()`].
+    /// The sender skips these files.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub copies: BTreeMap<usize, usize>,
+    /// Whether the sender should send the accepted files as one
+    /// streamed tar archive. Either [`FileOfferMsg::archive`],
+    /// or `false` to decline it. Can't be combined with [`Self::deltas`].
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub archive: bool,
+    /// Number of streams that the sender should send the accepted files on.
+    /// Either [`FileOfferMsg::streams`], or `None` to decline it.
+    /// Can't be combined with [`Self::archive`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub streams: Option<u32>,
+    /// Whether the receiver saves a [`crate::TransferSummary`] of the transfer
+    /// as a manifest in the save directory. Not sent to the peer.
+    #[serde(skip)]
+    pub write_manifest: bool,
+    /// What to do with accepted files whose save path is already taken.
+    /// Not sent to the peer. Set it with [`Self::with_overwrite_policy()`]
+    /// to skip such files without transferring them.
+    #[serde(skip)]
+    pub overwrite: OverwritePolicy,
 }
 
 impl FileResponseMsg {
@@ -75,6 +284,14 @@ impl FileResponseMsg {
     pub fn accept_all_files(offer: &FileOfferMsg) -> Self {
         Self {
             response: vec![Some(0); offer.files.len()],
+            compression: offer.compression,
+            archive: offer.archive,
+            streams: offer.streams,
+            preserve_metadata: false,
+            deltas: BTreeMap::new(),
+            copies: BTreeMap::new(),
+            write_manifest: false,
+            overwrite: OverwritePolicy::default(),
         }
     }
 
@@ -83,6 +300,14 @@ impl FileResponseMsg {
     pub fn reject_all_files(offer: &FileOfferMsg) -> Self {
         Self {
             response: vec![None; offer.files.len()],
+            compression: offer.compression,
+            archive: offer.archive,
+            streams: offer.streams,
+            preserve_metadata: false,
+            deltas: BTreeMap::new(),
+            copies: BTreeMap::new(),
+            write_manifest: false,
+            overwrite: OverwritePolicy::default(),
         }
     }
 
@@ -109,7 +334,17 @@ impl FileResponseMsg {
                 response.push(Some(0));
             }
         }
-        Ok(Self { response })
+        Ok(Self {
+            response,
+            compression: offer.compression,
+            archive: offer.archive,
+            streams: offer.streams,
+            preserve_metadata: false,
+            deltas: BTreeMap::new(),
+            copies: BTreeMap::new(),
+            write_manifest: false,
+            overwrite: OverwritePolicy::default(),
+        })
     }
 
     /// Get a [`FileResponseMsg`] that would:
@@ -134,7 +369,131 @@ impl FileResponseMsg {
                 response.push(Some(0));
             }
         }
-        Ok(FileResponseMsg { response })
+        Ok(FileResponseMsg {
+            response,
+            compression: offer.compression,
+            archive: offer.archive,
+            streams: offer.streams,
+            preserve_metadata: false,
+            deltas: BTreeMap::new(),
+            copies: BTreeMap::new(),
+            write_manifest: false,
+            overwrite: OverwritePolicy::default(),
+        })
+    }
+
+    /// Returns a [`FileResponseMsg`] that would accept only the remaining
+    /// portions of files whose downloads to `save_dir` have been previously interrupted.
+    ///
+    /// Rejects all other files.
+    pub fn accept_only_interrupted(
+        offer: &FileOfferMsg,
+        save_dir: &Path,
+    ) -> Result<FileResponseMsg, Error> {
+        let mut response = Vec::with_capacity(offer.files.len());
+
+        for offered in &offer.files {
+            response.push(offered.partial_download_exists(save_dir)?);
+        }
+        Ok(FileResponseMsg {
+            response,
+            ..Self::reject_all_files(offer)
+        })
+    }
+
+    /// Rejects the files in `offer` that are longer than `max_len` bytes,
+    /// keeping the rest of this response as it is.
+    ///
+    /// Useful for receivers with little storage, for example with
+    /// `FileResponseMsg::accept_all_files(&offer).reject_larger_than(&offer, max_len)`.
+    pub fn reject_larger_than(mut self, offer: &FileOfferMsg, max_len: u64) -> Self {
+        for (response, file) in self.response.iter_mut().zip(&offer.files) {
+            if file.len > max_len {
+                *response = None;
+            }
+        }
+        self
+    }
+
+    /// Requests that the fully accepted files in `offer` which already have
+    /// an older version at their [`FileMeta::get_save_path()`] in `save_dir`
+    /// be sent as deltas, keeping the rest of this response as it is.
+    ///
+    /// Only the changed parts of those files are transferred,
+    /// and the received versions replace the older ones.
+    /// Declines [`Self::archive`] if any deltas are requested,
+    /// since archives can't hold them.
+    pub fn request_deltas(mut self, offer: &FileOfferMsg, save_dir: &Path) -> Result<Self, Error> {
+        for (index, (response, file)) in self.response.iter().zip(&offer.files).enumerate() {
+            let path = file.get_save_path(save_dir);
+            if *response != Some(0) || !path.metadata().is_ok_and(|meta| meta.is_file()) {
+                continue;
+            }
+            self.deltas.insert(index, Signature::of_file(&path)?);
+        }
+        if !self.deltas.is_empty() {
+            self.archive = false;
+        }
+        Ok(self)
+    }
+
+    /// Sets [`Self::overwrite`] to `policy`, and applies it to the accepted
+    /// files in `offer` whose [`FileMeta::get_save_path()`] in `save_dir`
+    /// is already taken, keeping the rest of this response as it is.
+    ///
+    /// Under [`OverwritePolicy::Skip`], rejects those files.
+    /// Under [`OverwritePolicy::FailIfExists`], returns [`Error::FileExists`]
+    /// with the first of them. Files requested as [`Self::deltas`] are
+    /// meant to replace their older versions, so they're left as they are.
+    ///
+    /// If a file's save path is taken during the transfer, the policy is
+    /// applied again once it arrives, and under either of these policies,
+    /// the download is left as a partial download and [`Error::FileExists`]
+    /// is returned.
+    pub fn with_overwrite_policy(
+        mut self,
+        offer: &FileOfferMsg,
+        save_dir: &Path,
+        policy: OverwritePolicy,
+    ) -> Result<Self, Error> {
+        self.overwrite = policy;
+        for (index, (response, file)) in self.response.iter_mut().zip(&offer.files).enumerate() {
+            if response.is_none() || self.deltas.contains_key(&index) {
+                continue;
+            }
+            if file.get_save_path_with_policy(save_dir, policy)?.is_none() {
+                *response = None;
+            }
+        }
+        Ok(self)
+    }
+
+    /// Copies the accepted files in `offer` that are [`FileOfferMsg::duplicates`]
+    /// of another accepted file from that file once it arrives, instead of
+    /// receiving them, keeping the rest of this response as it is.
+    ///
+    /// Call this after deciding which files to accept.
+    /// Files requested as [`Self::deltas`] are left as they are.
+    pub fn copy_duplicates(mut self, offer: &FileOfferMsg) -> Self {
+        for (&duplicate, &original) in &offer.duplicates {
+            let (Some(file), Some(original_file)) =
+                (offer.files.get(duplicate), offer.files.get(original))
+            else {
+                continue;
+            };
+            let accepted = |index: usize| self.response.get(index).is_some_and(Option::is_some);
+            if accepted(duplicate)
+                && accepted(original)
+                && duplicate != original
+                && !offer.duplicates.contains_key(&original)
+                && !self.deltas.contains_key(&duplicate)
+                && file.len == original_file.len
+                && file.hash == original_file.hash
+            {
+                self.copies.insert(duplicate, original);
+            }
+        }
+        self
     }
 
     /// Returns the number of fully accepted files.
@@ -204,34 +563,74 @@ pub async fn write_to_async(
 ///
 /// Assumes the message is prefixed with 1 byte holding the [`PROTOCOL_VERSION`]
 /// and 4 big-endian bytes holding the length of the following message.
+///
+/// Skips any heartbeats sent by [`crate::with_heartbeats()`].
+/// Returns [`Error::PeerCancelled`] if the peer sent a cancellation instead.
 pub fn read_from<T: DeserializeOwned>(reader: &mut impl Read) -> Result<T, Error> {
-    let mut header = [0_u8; 5];
-    reader.read_exact(&mut header)?;
-    if header[0] != PROTOCOL_VERSION {
-        return Err(Error::IncompatibleProtocol);
-    }
-    let len = u32::from_be_bytes(header[1..5].try_into().unwrap()) as usize;
+    loop {
+        let mut header = [0_u8; 5];
+        reader.read_exact(&mut header)?;
+        let len = parse_header(header)?;
 
-    let mut buf = vec![0; len];
-    reader.read_exact(&mut buf)?;
-    Ok(serde_json::from_reader(&buf[..])?)
+        let mut buf = vec![0; len];
+        reader.read_exact(&mut buf)?;
+        if !is_heartbeat(&buf) {
+            return parse_msg(&buf);
+        }
+    }
 }
 
 /// Asynchronously reads a message from `reader` using [`serde_json`].
 ///
 /// Assumes the message is prefixed with 1 byte holding the [`PROTOCOL_VERSION`]
 /// and 4 big-endian bytes holding the length of the following message.
+///
+/// Skips any heartbeats sent by [`crate::with_heartbeats()`].
+/// Returns [`Error::PeerCancelled`] if the peer sent a cancellation instead.
 pub async fn read_from_async<T: DeserializeOwned>(
     reader: &mut (impl AsyncRead + Unpin),
 ) -> Result<T, Error> {
-    let mut header = [0_u8; 5];
-    reader.read_exact(&mut header).await?;
-    if header[0] != PROTOCOL_VERSION {
-        return Err(Error::IncompatibleProtocol);
+    loop {
+        let mut header = [0_u8; 5];
+        reader.read_exact(&mut header).await?;
+        if let Some(msg) = read_body_async(reader, header).await? {
+            return Ok(msg);
+        }
     }
-    let len = u32::from_be_bytes(header[1..5].try_into().unwrap()) as usize;
+}
 
+/// Reads the message that follows `header` from `reader`.
+///
+/// Returns `None` if it was a heartbeat.
+pub(crate) async fn read_body_async<T: DeserializeOwned>(
+    reader: &mut (impl AsyncRead + Unpin),
+    header: [u8; 5],
+) -> Result<Option<T>, Error> {
+    let len = parse_header(header)?;
     let mut buf = vec![0; len];
     reader.read_exact(&mut buf).await?;
-    Ok(serde_json::from_reader(&buf[..])?)
+    if is_heartbeat(&buf) {
+        Ok(None)
+    } else {
+        parse_msg(&buf).map(Some)
+    }
+}
+
+/// Checks the [`PROTOCOL_VERSION`] in a message's `header`,
+/// and returns the length of the message that follows.
+fn parse_header(header: [u8; 5]) -> Result<usize, Error> {
+    if header[0] != PROTOCOL_VERSION {
+        return Err(Error::IncompatibleProtocol);
+    }
+    Ok(u32::from_be_bytes(header[1..5].try_into().unwrap()) as usize)
+}
+
+/// Deserializes `buf` into `T`.
+///
+/// Returns [`Error::PeerCancelled`] if `buf` holds a [`CancelMsg`] instead.
+fn parse_msg<T: DeserializeOwned>(buf: &[u8]) -> Result<T, Error> {
+    if let Ok(msg) = serde_json::from_slice::<CancelMsg>(buf) {
+        return Err(Error::PeerCancelled(msg.cancelled));
+    }
+    Ok(serde_json::from_slice(buf)?)
 }
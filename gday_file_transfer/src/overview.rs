@@ -0,0 +1,88 @@
+use crate::FileOfferMsg;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// A quick overview of a [`FileOfferMsg`], to help
+/// receivers decide on offers with many files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OfferOverview {
+    /// Number and total size of files with each extension,
+    /// largest total size first.
+    pub by_extension: Vec<ExtensionStats>,
+    /// Indices in [`FileOfferMsg::files`] of the largest files,
+    /// largest first.
+    pub largest: Vec<usize>,
+    /// The most deeply nested offered folder, if any,
+    /// and how many folders deep it is.
+    pub deepest_dir: Option<(PathBuf, usize)>,
+}
+
+/// Number and total size of the offered files with one extension,
+/// in an [`OfferOverview`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtensionStats {
+    /// The lowercase extension, such as `"gz"` for `"a.tar.gz"`,
+    /// or `None` for files without one.
+    pub extension: Option<String>,
+    /// Number of files
+    pub count: usize,
+    /// Their total size in bytes
+    pub bytes: u64,
+}
+
+impl OfferOverview {
+    /// Summarizes `offer`, listing its `num_largest` largest files.
+    pub fn of(offer: &FileOfferMsg, num_largest: usize) -> Self {
+        let mut by_extension: HashMap<Option<String>, ExtensionStats> = HashMap::new();
+        for file in &offer.files {
+            let extension = file
+                .short_path
+                .extension()
+                .map(|ext| ext.to_string_lossy().to_lowercase());
+            let stats = by_extension
+                .entry(extension.clone())
+                .or_insert(ExtensionStats {
+                    extension,
+                    count: 0,
+                    bytes: 0,
+                });
+            stats.count += 1;
+            stats.bytes += file.len;
+        }
+        let mut by_extension: Vec<ExtensionStats> = by_extension.into_values().collect();
+        by_extension.sort_by(|a, b| {
+            b.bytes
+                .cmp(&a.bytes)
+                .then(b.count.cmp(&a.count))
+                .then_with(|| a.extension.cmp(&b.extension))
+        });
+
+        let mut largest: Vec<usize> = (0..offer.files.len()).collect();
+        largest.sort_by_key(|&i| std::cmp::Reverse(offer.files[i].len));
+        largest.truncate(num_largest);
+
+        // the folders that hold files, and the empty ones
+        let dirs = offer
+            .files
+            .iter()
+            .filter_map(|file| file.short_path.parent())
+            .chain(offer.empty_dirs.iter().map(PathBuf::as_path))
+            .filter(|dir| *dir != Path::new(""));
+        let deepest_dir = dirs
+            .map(|dir| (dir, dir.components().count()))
+            .fold(
+                None,
+                |deepest: Option<(&Path, usize)>, (dir, depth)| match deepest {
+                    Some((_, deepest_depth)) if deepest_depth >= depth => deepest,
+                    _ => Some((dir, depth)),
+                },
+            )
+            .map(|(dir, depth)| (dir.to_path_buf(), depth));
+
+        Self {
+            by_extension,
+            largest,
+            deepest_dir,
+        }
+    }
+}
@@ -0,0 +1,627 @@
+//! Info files stored next to partial downloads,
+//! so a later transfer can tell whether they're safe to resume.
+//!
+//! Each info file holds:
+//! - 4 bytes: [`MAGIC`]
+//! - 1 byte: format version, currently [`VERSION`]
+//! - 4 bytes: big-endian length of the body
+//! - the body: [`TmpInfoFile`] as JSON
+//! - 32 bytes: BLAKE3 hash of everything before it
+//!
+//! Adding fields to [`TmpInfoFile`] doesn't require a new version,
+//! since readers ignore fields they don't know, and default missing ones.
+//! Partial downloads from before info files existed are
+//! resumed as [`TmpInfoFile::legacy()`].
+//!
+//! Abandoned partial downloads can be found with
+//! [`find_partial_downloads()`] and deleted.
+
+use crate::file_meta::{head_hash, HEAD_LEN};
+use crate::path_policy::check_safe_path;
+use crate::{Error, FileMeta, FileOfferMsg};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io::{ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// First bytes of every info file.
+const MAGIC: [u8; 4] = *b"GDPI";
+
+/// Version of the info file format.
+/// Only changes if old readers can't understand the new format.
+const VERSION: u8 = 1;
+
+/// Length of everything before the body.
+const HEADER_LEN: usize = 9;
+
+/// Length of the hash after the body.
+const HASH_LEN: usize = 32;
+
+/// Describes the file that a partial download will become.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TmpInfoFile {
+    /// Length of the complete file
+    pub len: u64,
+    /// Hash of the complete file offered by the sender, if any
+    #[serde(default)]
+    pub hash: Option<[u8; 32]>,
+    /// Number of bytes at the start of the partial download
+    /// that were synced to disk, if known.
+    ///
+    /// After a crash or power loss, bytes after these may be garbage,
+    /// even if the partial download is longer.
+    #[serde(default)]
+    pub written: Option<u64>,
+}
+
+/// Result of [`TmpInfoFile::read()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ReadInfo {
+    /// The partial download has a valid info file.
+    Valid(TmpInfoFile),
+    /// The partial download has no info file, because it was
+    /// started by an older version or the info file was lost.
+    Missing,
+    /// The info file is unreadable, so the partial download can't be trusted.
+    Corrupted,
+}
+
+impl TmpInfoFile {
+    /// Returns the info of a partial download of `file`,
+    /// whose first `written` bytes were synced to disk.
+    pub fn new(file: &FileMeta, written: u64) -> Self {
+        Self {
+            len: file.len,
+            hash: file.hash,
+            written: Some(written),
+        }
+    }
+
+    /// Returns the info of a partial download without an info file.
+    ///
+    /// The file's length was always part of the partial download's name,
+    /// so that is all that's known.
+    pub fn legacy(file: &FileMeta) -> Self {
+        Self {
+            len: file.len,
+            hash: None,
+            written: None,
+        }
+    }
+
+    /// Returns how much of a partial download that is `local_len` bytes long
+    /// can be resumed.
+    pub fn resumable_len(&self, local_len: u64) -> u64 {
+        self.written
+            .map_or(local_len, |written| written.min(local_len))
+    }
+
+    /// Returns whether a partial download with this info
+    /// can be resumed to download `file`.
+    pub fn matches(&self, file: &FileMeta) -> bool {
+        let hashes_match = match (self.hash, file.hash) {
+            (Some(a), Some(b)) => a == b,
+            _ => true,
+        };
+        self.len == file.len && hashes_match
+    }
+
+    /// Writes this info file to `path`.
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::write(path, self.to_bytes())
+    }
+
+    /// Reads the info file at `path`.
+    ///
+    /// Returns [`ReadInfo::Corrupted`] instead of an error if it can't be parsed.
+    pub fn read(path: &Path) -> std::io::Result<ReadInfo> {
+        match std::fs::read(path) {
+            Ok(bytes) => Ok(Self::from_bytes(&bytes).map_or(ReadInfo::Corrupted, ReadInfo::Valid)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(ReadInfo::Missing),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Serializes the info file.
+    fn to_bytes(&self) -> Vec<u8> {
+        let body = serde_json::to_vec(self).expect("Serializing TmpInfoFile failed.");
+        let body_len = u32::try_from(body.len()).expect("TmpInfoFile is too long.");
+
+        let mut bytes = Vec::with_capacity(HEADER_LEN + body.len() + HASH_LEN);
+        bytes.extend_from_slice(&MAGIC);
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&body_len.to_be_bytes());
+        bytes.extend_from_slice(&body);
+        let hash = blake3::hash(&bytes);
+        bytes.extend_from_slice(hash.as_bytes());
+        bytes
+    }
+
+    /// Deserializes the info file, or returns `None` if it's
+    /// corrupted or has an unknown version.
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let (header, rest) = bytes.split_at_checked(HEADER_LEN)?;
+        if header[0..4] != MAGIC || header[4] != VERSION {
+            return None;
+        }
+        let body_len = u32::from_be_bytes(header[5..9].try_into().unwrap()) as usize;
+        if rest.len() != body_len + HASH_LEN {
+            return None;
+        }
+
+        let (contents, hash) = bytes.split_at(HEADER_LEN + body_len);
+        if blake3::hash(contents).as_bytes() != hash {
+            return None;
+        }
+        serde_json::from_slice(&contents[HEADER_LEN..]).ok()
+    }
+}
+
+/// A partial download left behind by an interrupted transfer,
+/// found by [`find_partial_downloads()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartialDownload {
+    /// Path of the partial download,
+    /// or `None` if only its info file is left.
+    pub path: Option<PathBuf>,
+    /// Path of its info file, or `None` if it has none,
+    /// such as when it was started by an older version.
+    pub info_path: Option<PathBuf>,
+    /// Combined length of the partial download and its info file, in bytes.
+    pub len: u64,
+    /// Length of the complete file, in bytes.
+    pub file_len: u64,
+    /// Number of bytes of the partial download that a transfer can resume from.
+    /// Zero if only the info file is left, or it's corrupted.
+    pub resumable_len: u64,
+    /// When the partial download or its info file was last modified,
+    /// if the platform supports it.
+    pub modified: Option<SystemTime>,
+}
+
+impl PartialDownload {
+    /// Returns the path of the partial download,
+    /// or of its info file if that's all that's left.
+    pub fn display_path(&self) -> &Path {
+        self.path
+            .as_deref()
+            .or(self.info_path.as_deref())
+            .expect("PartialDownload has neither a path nor an info path.")
+    }
+
+    /// Deletes the partial download and its info file.
+    /// Its transfer can then no longer be resumed.
+    pub fn remove(&self) -> std::io::Result<()> {
+        for path in [&self.path, &self.info_path].into_iter().flatten() {
+            match std::fs::remove_file(path) {
+                Err(err) if err.kind() != ErrorKind::NotFound => return Err(err),
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Finds the partial downloads and orphaned info files
+/// in `dir` and its subdirectories, sorted by path.
+/// Skips the ones that an ongoing transfer is writing.
+///
+/// Partial downloads are recognized by their name, which ends in
+/// `".part{len}"`, as given by [`FileMeta::get_partial_download_path()`].
+/// Ones without an info file are only included if they're no longer than `len`,
+/// and info files are only included if they start with this format's magic bytes,
+/// so that unrelated files with similar names are left alone.
+///
+/// Doesn't follow symlinks.
+pub fn find_partial_downloads(dir: &Path) -> Result<Vec<PartialDownload>, Error> {
+    let mut found = Vec::new();
+    find_in(dir, &mut found)?;
+    found.sort_by(|a, b| a.display_path().cmp(b.display_path()));
+    Ok(found)
+}
+
+/// Adds the partial downloads in `dir` and its subdirectories to `found`.
+fn find_in(dir: &Path, found: &mut Vec<PartialDownload>) -> Result<(), Error> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            find_in(&path, found)?;
+            continue;
+        } else if !file_type.is_file() {
+            continue;
+        }
+
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+
+        // an info file, whose partial download is gone
+        if let Some(partial_name) = name.strip_suffix(".info") {
+            let Some(file_len) = part_len(partial_name) else {
+                continue;
+            };
+            if !path.with_extension("").exists() && has_magic(&path)? {
+                let metadata = entry.metadata()?;
+                found.push(PartialDownload {
+                    path: None,
+                    info_path: Some(path),
+                    len: metadata.len(),
+                    file_len,
+                    resumable_len: 0,
+                    modified: metadata.modified().ok(),
+                });
+            }
+            continue;
+        }
+
+        // a partial download
+        let Some(full_len) = part_len(&name) else {
+            continue;
+        };
+        let metadata = entry.metadata()?;
+        let mut info_path = path.clone().into_os_string();
+        info_path.push(".info");
+        let info_path = PathBuf::from(info_path);
+        let info_metadata = std::fs::metadata(&info_path).ok();
+
+        if info_metadata.is_none() && metadata.len() > full_len {
+            continue;
+        }
+        // another transfer is still writing it
+        if is_locked(&path) {
+            continue;
+        }
+
+        let resumable_len = match TmpInfoFile::read(&info_path)? {
+            ReadInfo::Valid(info) if info.len == full_len => info.resumable_len(metadata.len()),
+            ReadInfo::Missing => metadata.len(),
+            _ => 0,
+        };
+        let info_modified = info_metadata.as_ref().and_then(|meta| meta.modified().ok());
+        found.push(PartialDownload {
+            path: Some(path),
+            len: metadata.len() + info_metadata.as_ref().map_or(0, |meta| meta.len()),
+            file_len: full_len,
+            resumable_len,
+            modified: metadata.modified().ok().max(info_modified),
+            info_path: info_metadata.map(|_| info_path),
+        });
+    }
+    Ok(())
+}
+
+/// If `name` is the name of a partial download, returns the
+/// length of the complete file given in its `".part{len}"` suffix.
+fn part_len(name: &str) -> Option<u64> {
+    let (stem, len) = name.rsplit_once(".part")?;
+    if stem.is_empty() || len.is_empty() || !len.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    len.parse().ok()
+}
+
+/// Moves partial downloads in `save_dir` that belong to files in `offer`,
+/// but are saved under a different path, to where `offer` expects them.
+/// Returns the old and new path of each one that was moved.
+///
+/// Helps when the sender offers the same files again, but with a differently
+/// named top-level folder, or a renamed file. Afterwards, they're found by
+/// [`FileMeta::partial_download_exists()`] and resumed as usual.
+///
+/// Looks in `save_dir` itself, and in the folders directly in it
+/// in place of each file's top-level folder. A partial download only
+/// belongs to an offered file if it has the same length, and either the same
+/// [`FileMeta::hash`] in its info file, or matching [`FileMeta::head_hash`]
+/// contents. Files without either hash are left alone.
+///
+/// Returns [`Error::UnsafePath`] if a file's path would leave `save_dir`.
+pub fn relocate_partial_downloads(
+    offer: &FileOfferMsg,
+    save_dir: &Path,
+) -> Result<Vec<(PathBuf, PathBuf)>, Error> {
+    let mut top_dirs = Vec::new();
+    let mut top_partials = Vec::new();
+    match std::fs::read_dir(save_dir) {
+        Ok(entries) => {
+            for entry in entries {
+                let entry = entry?;
+                let file_type = entry.file_type()?;
+                if file_type.is_dir() {
+                    top_dirs.push(entry.path());
+                } else if file_type.is_file()
+                    && part_len(&entry.file_name().to_string_lossy()).is_some()
+                {
+                    top_partials.push(entry.path());
+                }
+            }
+        }
+        // nothing to resume
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    }
+
+    // partial downloads that are already where the offer expects them
+    let mut taken = HashSet::new();
+    for file in &offer.files {
+        check_safe_path(&file.short_path)?;
+        taken.insert(file.get_partial_download_path(save_dir)?);
+    }
+
+    let mut moved = Vec::new();
+    for file in &offer.files {
+        if file.hash.is_none() && file.head_hash.is_none() {
+            continue;
+        }
+        let target = file.get_partial_download_path(save_dir)?;
+        if target.symlink_metadata().is_ok() || file.already_exists(save_dir)? {
+            continue;
+        }
+
+        // the same file under a different top-level folder
+        let mut candidates = Vec::new();
+        let mut components = file.short_path.components();
+        if components.next().is_some() && components.clone().next().is_some() {
+            let rest = FileMeta {
+                short_path: components.as_path().to_path_buf(),
+                ..file.clone()
+            };
+            for dir in &top_dirs {
+                candidates.push(rest.get_partial_download_path(dir)?);
+            }
+        }
+        // or the file itself, renamed
+        candidates.extend(top_partials.iter().cloned());
+
+        for candidate in candidates {
+            let name = candidate.file_name().unwrap_or_default().to_string_lossy();
+            if taken.contains(&candidate) || part_len(&name) != Some(file.len) {
+                continue;
+            }
+            if belongs_to(&candidate, file)? {
+                if let Some(parent) = target.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::rename(&candidate, &target)?;
+                let mut info_path = candidate.clone().into_os_string();
+                info_path.push(".info");
+                match std::fs::rename(&info_path, file.get_partial_info_path(save_dir)?) {
+                    Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+                    _ => (),
+                }
+                taken.insert(candidate.clone());
+                moved.push((candidate, target));
+                break;
+            }
+        }
+    }
+    Ok(moved)
+}
+
+/// Returns whether the partial download at `path`
+/// can be resumed to download `file`, judging by its contents.
+fn belongs_to(path: &Path, file: &FileMeta) -> Result<bool, Error> {
+    let Ok(local) = std::fs::File::open(path) else {
+        return Ok(false);
+    };
+    // another transfer is still writing it
+    if matches!(local.try_lock(), Err(std::fs::TryLockError::WouldBlock)) {
+        return Ok(false);
+    }
+    let mut info_path = path.to_path_buf().into_os_string();
+    info_path.push(".info");
+    let info = match TmpInfoFile::read(Path::new(&info_path))? {
+        ReadInfo::Valid(info) => info,
+        ReadInfo::Missing => TmpInfoFile::legacy(file),
+        ReadInfo::Corrupted => return Ok(false),
+    };
+    let resumable_len = info.resumable_len(local.metadata()?.len());
+    if !info.matches(file) || resumable_len >= file.len {
+        return Ok(false);
+    }
+
+    // the whole file's hash identifies it
+    if let (Some(a), Some(b)) = (info.hash, file.hash) {
+        return Ok(a == b);
+    }
+    // otherwise, enough of it must be there to compare its start
+    match file.head_hash {
+        Some(expected) if resumable_len >= HEAD_LEN => Ok(head_hash(&local, file.len)? == expected),
+        _ => Ok(false),
+    }
+}
+
+/// Returns whether another transfer holds the lock on the
+/// partial download at `path`, since it's still writing it.
+fn is_locked(path: &Path) -> bool {
+    std::fs::File::open(path)
+        .is_ok_and(|file| matches!(file.try_lock(), Err(std::fs::TryLockError::WouldBlock)))
+}
+
+/// Returns whether the file at `path` starts with [`MAGIC`].
+fn has_magic(path: &Path) -> std::io::Result<bool> {
+    let mut start = [0; MAGIC.len()];
+    match std::fs::File::open(path)?.read_exact(&mut start) {
+        Ok(()) => Ok(start == MAGIC),
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        find_partial_downloads, ReadInfo, TmpInfoFile, HASH_LEN, HEADER_LEN, MAGIC, VERSION,
+    };
+    use crate::FileMeta;
+
+    fn file_meta(hash: Option<[u8; 32]>) -> FileMeta {
+        FileMeta {
+            short_path: "file.txt".into(),
+            len: 10,
+            hash,
+            head_hash: None,
+            compressible: true,
+            mode: None,
+            modified: None,
+        }
+    }
+
+    /// Returns an info file with `body` as its body.
+    fn with_body(body: &[u8]) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(body);
+        let hash = blake3::hash(&bytes);
+        bytes.extend_from_slice(hash.as_bytes());
+        bytes
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt.part10.info");
+
+        let info = TmpInfoFile::new(&file_meta(Some([5; 32])), 4);
+        info.write(&path).unwrap();
+        assert_eq!(TmpInfoFile::read(&path).unwrap(), ReadInfo::Valid(info));
+
+        let info = TmpInfoFile::new(&file_meta(None), 4);
+        assert_eq!(TmpInfoFile::from_bytes(&info.to_bytes()), Some(info));
+
+        let missing = dir.path().join("other.part10.info");
+        assert_eq!(TmpInfoFile::read(&missing).unwrap(), ReadInfo::Missing);
+    }
+
+    #[test]
+    fn test_unknown_fields() {
+        // written by a newer version with more fields
+        let bytes = with_body(br#"{"len":10,"hash":null,"written":4,"mtime":12345}"#);
+        let expected = TmpInfoFile::new(&file_meta(None), 4);
+        assert_eq!(TmpInfoFile::from_bytes(&bytes), Some(expected));
+
+        // written without optional fields
+        let bytes = with_body(br#"{"len":10}"#);
+        let expected = TmpInfoFile::legacy(&file_meta(None));
+        assert_eq!(TmpInfoFile::from_bytes(&bytes), Some(expected));
+    }
+
+    #[test]
+    fn test_corruption() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt.part10.info");
+        let bytes = TmpInfoFile::new(&file_meta(None), 4).to_bytes();
+
+        // a flipped bit in the body
+        let mut flipped = bytes.clone();
+        flipped[HEADER_LEN + 2] ^= 1;
+        std::fs::write(&path, flipped).unwrap();
+        assert_eq!(TmpInfoFile::read(&path).unwrap(), ReadInfo::Corrupted);
+
+        // cut short
+        std::fs::write(&path, &bytes[..bytes.len() - HASH_LEN / 2]).unwrap();
+        assert_eq!(TmpInfoFile::read(&path).unwrap(), ReadInfo::Corrupted);
+
+        // unknown version
+        let mut newer = bytes.clone();
+        newer[4] = VERSION + 1;
+        assert_eq!(TmpInfoFile::from_bytes(&newer), None);
+
+        // valid hash, but not a `TmpInfoFile`
+        assert_eq!(TmpInfoFile::from_bytes(&with_body(b"[1, 2]")), None);
+        assert_eq!(TmpInfoFile::from_bytes(b""), None);
+    }
+
+    #[test]
+    fn test_matches() {
+        let info = TmpInfoFile::new(&file_meta(Some([5; 32])), 4);
+        assert!(info.matches(&file_meta(Some([5; 32]))));
+        assert!(info.matches(&file_meta(None)));
+        assert!(!info.matches(&file_meta(Some([6; 32]))));
+
+        let legacy = TmpInfoFile::legacy(&file_meta(Some([5; 32])));
+        assert!(legacy.matches(&file_meta(Some([6; 32]))));
+        assert_eq!(legacy.resumable_len(7), 7);
+        assert_eq!(info.resumable_len(7), 4);
+        assert_eq!(info.resumable_len(3), 3);
+        assert!(!legacy.matches(&FileMeta {
+            len: 11,
+            ..file_meta(None)
+        }));
+    }
+
+    #[test]
+    fn test_find_partial_downloads() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        std::fs::create_dir(dir.join("folder")).unwrap();
+
+        // a partial download with an info file, in a subfolder
+        std::fs::write(dir.join("folder/a.txt.part10"), [1; 4]).unwrap();
+        TmpInfoFile::new(&file_meta(None), 4)
+            .write(&dir.join("folder/a.txt.part10.info"))
+            .unwrap();
+
+        // a partial download from before info files existed
+        std::fs::write(dir.join("b.txt.part10"), [1; 4]).unwrap();
+
+        // an info file whose partial download is gone
+        TmpInfoFile::new(&file_meta(None), 4)
+            .write(&dir.join("c.txt.part10.info"))
+            .unwrap();
+
+        // unrelated files with similar names
+        std::fs::write(dir.join("d.txt.part2"), [1; 4]).unwrap();
+        std::fs::write(dir.join("e.part1.rar"), [1; 4]).unwrap();
+        std::fs::write(dir.join("f.txt.partial"), [1; 4]).unwrap();
+        std::fs::write(dir.join("g.txt.part10.info"), b"not an info file").unwrap();
+        std::fs::write(dir.join("h.txt"), [1; 4]).unwrap();
+        // still being written by another transfer
+        std::fs::write(dir.join("i.txt.part10"), [1; 4]).unwrap();
+        let in_use = std::fs::File::open(dir.join("i.txt.part10")).unwrap();
+        in_use.lock().unwrap();
+
+        let found = find_partial_downloads(dir).unwrap();
+        let paths: Vec<_> = found.iter().map(|partial| partial.display_path()).collect();
+        assert_eq!(
+            paths,
+            [
+                dir.join("b.txt.part10"),
+                dir.join("c.txt.part10.info"),
+                dir.join("folder/a.txt.part10"),
+            ]
+        );
+        assert_eq!(found[0].info_path, None);
+        assert_eq!(found[1].path, None);
+        assert_eq!(
+            found[2].info_path,
+            Some(dir.join("folder/a.txt.part10.info"))
+        );
+        assert!(found[2].len > 4);
+        assert_eq!(
+            found
+                .iter()
+                .map(|partial| partial.file_len)
+                .collect::<Vec<_>>(),
+            [10, 10, 10]
+        );
+        assert_eq!(
+            found
+                .iter()
+                .map(|partial| partial.resumable_len)
+                .collect::<Vec<_>>(),
+            [4, 0, 4]
+        );
+
+        for partial in &found {
+            partial.remove().unwrap();
+        }
+        assert!(find_partial_downloads(dir).unwrap().is_empty());
+        assert!(dir.join("d.txt.part2").exists());
+        assert!(dir.join("g.txt.part10.info").exists());
+        assert!(dir.join("i.txt.part10").exists());
+        assert!(dir.join("folder").exists());
+    }
+}
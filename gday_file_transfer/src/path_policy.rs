@@ -0,0 +1,233 @@
+use crate::Error;
+use std::path::{Component, Path, PathBuf};
+
+/// Names that Windows reserves for devices, even with an extension.
+const WINDOWS_RESERVED: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Characters that Windows doesn't allow in file names.
+const WINDOWS_FORBIDDEN: [char; 8] = ['<', '>', ':', '"', '\\', '|', '?', '*'];
+
+/// Longest extension kept by [`PathPolicy::max_name_len`] when shortening a name.
+const MAX_KEPT_EXTENSION_LEN: usize = 16;
+
+/// How a receiver adapts the offered paths to its file system,
+/// so that saving a file doesn't fail partway through a transfer.
+///
+/// Apply it with [`crate::FileOfferMsg::apply_path_policy()`]
+/// before responding to the offer.
+/// Paths that leave the save directory, with `..` or a root,
+/// are always rejected with [`Error::UnsafePath`].
+///
+/// For example, to receive from a Linux peer onto a Windows-formatted drive:
+/// ```
+/// # use gday_file_transfer::PathPolicy;
+/// let policy = PathPolicy {
+///     windows_names: true,
+///     case_collisions: true,
+///     ..PathPolicy::default()
+/// };
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct PathPolicy {
+    /// Longest allowed file or directory name, in bytes.
+    /// Longer names are shortened, keeping their extension.
+    pub max_name_len: usize,
+    /// Most names allowed in a path, including the file's own.
+    /// Deeper paths are rejected with [`Error::PathTooDeep`].
+    pub max_depth: usize,
+    /// Longest allowed path relative to the save directory, in bytes.
+    /// Longer paths are rejected with [`Error::PathTooLong`].
+    pub max_len: usize,
+    /// Whether to rename names that Windows doesn't allow: reserved device
+    /// names such as `CON` and `NUL` get a `_` appended, forbidden characters
+    /// such as `:` and `?` become `_`, and trailing dots and spaces are removed.
+    pub windows_names: bool,
+    /// Whether to rename files whose paths differ only in case,
+    /// like [`crate::FileOfferMsg::rename_case_collisions()`].
+    pub case_collisions: bool,
+    /// Normalizes each name, so that names that look the same
+    /// are saved the same way on every file system.
+    ///
+    /// For example, `|name| name.nfc().collect()`
+    /// with the `unicode-normalization` crate.
+    pub normalize_unicode: Option<fn(&str) -> String>,
+}
+
+impl Default for PathPolicy {
+    /// Fits the limits of common file systems,
+    /// and of this platform's file names.
+    fn default() -> Self {
+        Self {
+            max_name_len: 255,
+            max_depth: 128,
+            max_len: 4096,
+            windows_names: cfg!(windows),
+            case_collisions: cfg!(any(windows, target_os = "macos")),
+            normalize_unicode: None,
+        }
+    }
+}
+
+impl PathPolicy {
+    /// Returns `path` adapted to this policy.
+    ///
+    /// Returns [`Error::UnsafePath`] if `path` leaves the directory
+    /// it's relative to, or has no names in it.
+    pub fn sanitize(&self, path: &Path) -> Result<PathBuf, Error> {
+        check_safe_path(path)?;
+
+        let mut sanitized = PathBuf::new();
+        for component in path.components() {
+            let Component::Normal(name) = component else {
+                continue;
+            };
+            let needs_str = self.windows_names
+                || self.normalize_unicode.is_some()
+                || name.len() > self.max_name_len;
+            match name.to_str() {
+                Some(name) if needs_str => sanitized.push(self.sanitize_name(name)),
+                None if needs_str => sanitized.push(self.sanitize_name(&name.to_string_lossy())),
+                _ => sanitized.push(name),
+            }
+        }
+
+        if sanitized.components().count() > self.max_depth {
+            return Err(Error::PathTooDeep(path.to_path_buf()));
+        }
+        if sanitized.as_os_str().len() > self.max_len {
+            return Err(Error::PathTooLong(path.to_path_buf()));
+        }
+        Ok(sanitized)
+    }
+
+    /// Returns the file or directory `name` adapted to this policy.
+    fn sanitize_name(&self, name: &str) -> String {
+        let mut name = match self.normalize_unicode {
+            Some(normalize) => normalize(name),
+            None => name.to_string(),
+        };
+
+        if self.windows_names {
+            name = name
+                .chars()
+                .map(|c| {
+                    if c.is_control() || WINDOWS_FORBIDDEN.contains(&c) {
+                        '_'
+                    } else {
+                        c
+                    }
+                })
+                .collect();
+            name.truncate(name.trim_end_matches(['.', ' ']).len());
+            if name.is_empty() {
+                name.push('_');
+            }
+            let stem = name.split('.').next().unwrap_or_default();
+            if WINDOWS_RESERVED
+                .iter()
+                .any(|reserved| stem.eq_ignore_ascii_case(reserved))
+            {
+                name.insert(stem.len(), '_');
+            }
+        }
+
+        if name.len() > self.max_name_len {
+            name = shorten(&name, self.max_name_len);
+        }
+        name
+    }
+}
+
+/// Returns `name` shortened to at most `max_len` bytes,
+/// keeping its extension if it's short.
+fn shorten(name: &str, max_len: usize) -> String {
+    let extension = match name.rfind('.') {
+        Some(dot) if dot > 0 && name.len() - dot <= MAX_KEPT_EXTENSION_LEN => &name[dot..],
+        _ => "",
+    };
+    let extension = if extension.len() < max_len {
+        extension
+    } else {
+        ""
+    };
+
+    let mut stem_len = max_len - extension.len();
+    while !name.is_char_boundary(stem_len) {
+        stem_len -= 1;
+    }
+    format!("{}{extension}", &name[..stem_len])
+}
+
+/// Returns [`Error::UnsafePath`] if `path` leaves the directory
+/// it's relative to, with `..` or a root, or has no names in it.
+pub(crate) fn check_safe_path(path: &Path) -> Result<(), Error> {
+    let mut has_name = false;
+    for component in path.components() {
+        match component {
+            Component::Normal(_) => has_name = true,
+            Component::CurDir => (),
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                return Err(Error::UnsafePath(path.to_path_buf()))
+            }
+        }
+    }
+    if has_name {
+        Ok(())
+    } else {
+        Err(Error::UnsafePath(path.to_path_buf()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PathPolicy;
+    use crate::Error;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn test_sanitize() {
+        let policy = PathPolicy {
+            max_name_len: 12,
+            max_depth: 3,
+            max_len: 30,
+            windows_names: true,
+            case_collisions: false,
+            normalize_unicode: Some(|name| name.replace('é', "e")),
+        };
+        let sanitize = |path: &str| policy.sanitize(Path::new(path));
+
+        assert_eq!(sanitize("./a/b.txt").unwrap(), PathBuf::from("a/b.txt"));
+        assert_eq!(sanitize("CON").unwrap(), PathBuf::from("CON_"));
+        assert_eq!(
+            sanitize("dir/nul.tar.gz").unwrap(),
+            PathBuf::from("dir/nul_.tar.gz")
+        );
+        assert_eq!(sanitize("what?: a*.").unwrap(), PathBuf::from("what__ a_"));
+        assert_eq!(sanitize("café.txt").unwrap(), PathBuf::from("cafe.txt"));
+        assert_eq!(
+            sanitize("a_very_long_name.txt").unwrap(),
+            PathBuf::from("a_very_l.txt")
+        );
+        assert_eq!(sanitize("ééééééé").unwrap(), PathBuf::from("eeeeeee"));
+
+        assert!(matches!(sanitize("a/b/c/d"), Err(Error::PathTooDeep(_))));
+        assert!(matches!(
+            sanitize("aaaaaaaaaa/bbbbbbbbbb/cccccccccc"),
+            Err(Error::PathTooLong(_))
+        ));
+        for unsafe_path in ["../a", "a/../../b", "/etc/passwd", "", "."] {
+            assert!(matches!(sanitize(unsafe_path), Err(Error::UnsafePath(_))));
+        }
+    }
+
+    #[test]
+    fn test_shorten() {
+        assert_eq!(super::shorten("ñññ", 5), "ññ");
+        assert_eq!(super::shorten("abcdef.tar", 6), "ab.tar");
+        assert_eq!(super::shorten("abc.xyzxyzxyz", 6), "abc.xy");
+        assert_eq!(super::shorten(".hidden_name", 4), ".hid");
+    }
+}
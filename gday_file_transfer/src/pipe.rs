@@ -0,0 +1,270 @@
+//! Transfers a single file to or from a stream instead of the file system,
+//! so that it can be piped to or from another program.
+//!
+//! The file is sent just like by [`crate::send_files()`],
+//! so either side may use these functions while the other
+//! uses the usual file system ones.
+
+use crate::archive::{self, archive_path, BLOCK_LEN};
+use crate::chunk::{read_abort, ChunkHeader, ChunkKind, CHUNK_LEN};
+use crate::transfer::{ChunkWriter, Progress};
+use crate::{Compression, Error, FileMeta, FileOfferMsg, FileResponseMsg, TransferReport};
+use std::io::ErrorKind;
+use std::pin::{pin, Pin};
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Sends the one file accepted in `response` from `reader`,
+/// instead of from the file system.
+///
+/// - `offer` is the [`FileOfferMsg`] you sent to your peer.
+///   Its accepted [`FileMeta::len`] must be the number of bytes
+///   that will be read from `reader`, since the length is declared up front.
+/// - `response` is the [`FileResponseMsg`] received from your peer.
+///   It must accept exactly one file, from its start.
+/// - `writer` is the IO stream on which the file will be sent.
+/// - `progress_callback` is a function that gets frequently
+///   called with [`TransferReport`] to report progress.
+///
+/// Returns [`Error::NotSingleFile`] if `response` doesn't accept exactly one file,
+/// and [`Error::UnexpectedFileLen`] if `reader` ends before the declared length.
+pub async fn send_file_from(
+    offer: &FileOfferMsg,
+    response: &FileResponseMsg,
+    reader: impl AsyncRead,
+    writer: impl AsyncWrite,
+    progress_callback: impl FnMut(&TransferReport),
+) -> Result<(), Error> {
+    let (file_index, meta) = single_file(offer, response)?;
+    let mut reader = pin!(reader);
+    let mut writer = pin!(writer);
+    let mut progress = new_progress(meta, progress_callback);
+    let mut buf = vec![0; CHUNK_LEN];
+
+    if response.archive {
+        let header = archive::header(
+            &archive_path(&meta.short_path),
+            meta.len,
+            meta.mode,
+            meta.modified,
+        );
+        writer.write_all(&header).await?;
+        while progress.report.processed_bytes < meta.len {
+            let remaining = meta.len - progress.report.processed_bytes;
+            let data = next_data(&mut reader, &mut buf, remaining).await?;
+            writer.write_all(data).await?;
+            progress.add(data.len() as u64);
+        }
+        writer
+            .write_all(&[0; BLOCK_LEN][0..archive::padding(meta.len)])
+            .await?;
+        writer.write_all(&[0; 2 * BLOCK_LEN]).await?;
+    } else {
+        let mut chunks = ChunkWriter::new(writer.as_mut(), response.compression)?;
+        // even an empty file is sent as one chunk,
+        // so the receiver knows it arrived
+        loop {
+            let offset = progress.report.processed_bytes;
+            let data = next_data(&mut reader, &mut buf, meta.len - offset).await?;
+            chunks
+                .write_data(file_index, offset, data, meta.compressible)
+                .await?;
+            progress.add(data.len() as u64);
+            if progress.report.processed_bytes == meta.len {
+                break;
+            }
+        }
+    }
+
+    writer.flush().await?;
+    progress.finish_file();
+    Ok(())
+}
+
+/// Receives the one file accepted in `response`, and writes it to `writer`
+/// instead of saving it to the file system.
+///
+/// - `offer` is the [`FileOfferMsg`] offered by the peer.
+/// - `response` is the [`FileResponseMsg`] that you've sent in response.
+///   It must accept exactly one file, from its start.
+/// - `reader` is the IO stream on which the file will be received.
+/// - `progress_callback` is a function that gets frequently
+///   called with [`TransferReport`] to report progress.
+///
+/// Returns [`Error::NotSingleFile`] if `response` doesn't accept exactly one file.
+/// If the peer offered a hash, the file is verified once it arrived,
+/// returning [`Error::ChecksumMismatch`] if it doesn't match.
+/// By then, its contents were already written to `writer`.
+pub async fn receive_file_to(
+    offer: &FileOfferMsg,
+    response: &FileResponseMsg,
+    reader: impl AsyncBufRead,
+    writer: impl AsyncWrite,
+    progress_callback: impl FnMut(&TransferReport),
+) -> Result<(), Error> {
+    let (file_index, meta) = single_file(offer, response)?;
+    let mut reader = pin!(reader);
+    let mut output = Output {
+        writer: pin!(writer),
+        hasher: meta.hash.map(|_| blake3::Hasher::new()),
+        progress: new_progress(meta, progress_callback),
+    };
+    let mut buf = vec![0; CHUNK_LEN];
+
+    if response.archive {
+        // the entry must be the accepted file
+        let expected = archive::Entry {
+            path: archive_path(&meta.short_path),
+            len: meta.len,
+        };
+        if archive::read_header(&mut reader).await? != Some(expected) {
+            return Err(Error::InvalidArchive);
+        }
+        while output.progress.report.processed_bytes < meta.len {
+            let remaining = meta.len - output.progress.report.processed_bytes;
+            let data = &mut buf[0..std::cmp::min(remaining, CHUNK_LEN as u64) as usize];
+            reader.read_exact(data).await?;
+            output.write(data).await?;
+        }
+        let padding = &mut buf[0..archive::padding(meta.len)];
+        reader.read_exact(padding).await?;
+        if archive::read_header(&mut reader).await?.is_some() {
+            return Err(Error::InvalidArchive);
+        }
+    } else {
+        let mut decompressor = match response.compression {
+            Some(Compression::Zstd { .. }) => Some(zstd::bulk::Decompressor::new()?),
+            None => None,
+        };
+        let mut compressed = Vec::new();
+
+        loop {
+            let header = ChunkHeader::read(&mut reader).await?;
+            if header.kind == ChunkKind::Abort {
+                return Err(read_abort(&mut reader, header.len).await);
+            }
+            let remaining = meta.len - output.progress.report.processed_bytes;
+            if header.file_index != file_index
+                || header.offset != output.progress.report.processed_bytes
+            {
+                return Err(Error::InvalidChunk);
+            }
+
+            let data = match header.kind {
+                ChunkKind::Raw => {
+                    if u64::from(header.len) > remaining {
+                        return Err(Error::InvalidChunk);
+                    }
+                    let data = &mut buf[0..header.len as usize];
+                    reader.read_exact(data).await?;
+                    data
+                }
+                ChunkKind::Zstd => {
+                    let Some(decompressor) = &mut decompressor else {
+                        return Err(Error::InvalidChunk);
+                    };
+                    compressed.resize(header.len as usize, 0);
+                    reader.read_exact(&mut compressed).await?;
+                    // a chunk that decompresses past the end of the file is invalid
+                    let max_len = std::cmp::min(remaining, CHUNK_LEN as u64) as usize;
+                    let len = decompressor
+                        .decompress_to_buffer(&compressed[..], &mut buf[0..max_len])
+                        .map_err(|_| Error::InvalidChunk)?;
+                    &buf[0..len]
+                }
+                // there's no old version to copy from
+                ChunkKind::Copy => return Err(Error::InvalidChunk),
+                ChunkKind::Abort => unreachable!("Handled above."),
+            };
+            output.write(data).await?;
+
+            if output.progress.report.processed_bytes == meta.len {
+                break;
+            }
+        }
+    }
+
+    output.writer.flush().await?;
+    if let (Some(hasher), Some(expected)) = (output.hasher, meta.hash) {
+        if *hasher.finalize().as_bytes() != expected {
+            return Err(Error::ChecksumMismatch(meta.short_path.clone()));
+        }
+    }
+    output.progress.finish_file();
+    Ok(())
+}
+
+/// Writes the received file, and reports progress.
+struct Output<'a, W, F> {
+    writer: Pin<&'a mut W>,
+    /// Verifies the file as it's received, if the peer offered a hash
+    hasher: Option<blake3::Hasher>,
+    progress: Progress<F>,
+}
+
+impl<W: AsyncWrite, F: FnMut(&TransferReport)> Output<'_, W, F> {
+    /// Writes `data`, the next bytes of the file.
+    async fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.writer.write_all(data).await?;
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(data);
+        }
+        self.progress.add(data.len() as u64);
+        Ok(())
+    }
+}
+
+/// Returns the index and metadata of the one file accepted in `response`.
+///
+/// Returns [`Error::NotSingleFile`] if it doesn't accept exactly one,
+/// and an error if it's not accepted from its start, or as a delta,
+/// since streams can't seek.
+fn single_file<'a>(
+    offer: &'a FileOfferMsg,
+    response: &FileResponseMsg,
+) -> Result<(u32, &'a FileMeta), Error> {
+    if offer.files.len() != response.response.len() {
+        return Err(Error::InvalidResponseLength);
+    }
+    let mut accepted = response
+        .response
+        .iter()
+        .enumerate()
+        .filter(|(_, start)| start.is_some());
+    let (Some((index, start)), None) = (accepted.next(), accepted.next()) else {
+        return Err(Error::NotSingleFile);
+    };
+    if *start != Some(0) {
+        return Err(Error::InvalidStartIndex);
+    }
+    if !response.deltas.is_empty() {
+        return Err(Error::InvalidSignature);
+    }
+    // offers have fewer than 2^32 files, since they're shorter than 2^32 bytes
+    Ok((index as u32, &offer.files[index]))
+}
+
+/// Returns the progress of a transfer of `meta` that hasn't started yet,
+/// reported to `progress_callback`.
+fn new_progress<F: FnMut(&TransferReport)>(meta: &FileMeta, progress_callback: F) -> Progress<F> {
+    let mut progress = Progress::new(meta.len, 1, progress_callback);
+    progress.report.start_file(&meta.short_path, meta.len, 0);
+    progress
+}
+
+/// Reads the next at most [`CHUNK_LEN`] of `remaining` bytes
+/// from `reader` into `buf`, and returns them.
+async fn next_data<'a>(
+    reader: &mut (impl AsyncRead + Unpin),
+    buf: &'a mut [u8],
+    remaining: u64,
+) -> Result<&'a [u8], Error> {
+    let data = &mut buf[0..std::cmp::min(remaining, CHUNK_LEN as u64) as usize];
+    reader
+        .read_exact(data)
+        .await
+        .map_err(|err| match err.kind() {
+            ErrorKind::UnexpectedEof => Error::UnexpectedFileLen,
+            _ => err.into(),
+        })?;
+    Ok(data)
+}
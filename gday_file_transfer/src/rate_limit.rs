@@ -0,0 +1,204 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{Instant, Sleep};
+
+/// Limits how fast a [`RateLimited`] stream may transfer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Average number of bytes per second.
+    pub bytes_per_sec: u64,
+    /// Number of bytes that may be transferred at once
+    /// after the stream was idle for a while.
+    pub burst: u64,
+}
+
+impl RateLimit {
+    /// Limits to `bytes_per_sec`, with a burst of about
+    /// a tenth of a second's worth of bytes.
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec,
+            burst: (bytes_per_sec / 10).max(0x4000),
+        }
+    }
+}
+
+/// Wraps a stream, limiting how fast it's read from and written to.
+///
+/// Pass it to [`crate::send_files()`] or [`crate::receive_files()`]
+/// so that a transfer doesn't use up a slow uplink needed for
+/// other traffic. Reading and writing share one limit.
+///
+/// Uses a token bucket: the stream may transfer up to
+/// [`RateLimit::burst`] bytes at once, and earns
+/// [`RateLimit::bytes_per_sec`] more every second.
+#[derive(Debug)]
+pub struct RateLimited<T> {
+    inner: T,
+    /// `None` if there's no limit
+    limit: Option<RateLimit>,
+    /// Bytes that may be transferred right now
+    tokens: f64,
+    /// When `tokens` was last refilled
+    refilled: Instant,
+    /// Wakes the task once more tokens are available
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl<T> RateLimited<T> {
+    /// Wraps `inner`, limiting it to `limit`,
+    /// or not at all if `limit` is `None`.
+    pub fn new(inner: T, limit: Option<RateLimit>) -> Self {
+        Self {
+            inner,
+            limit,
+            tokens: limit.map_or(0.0, |limit| limit.burst as f64),
+            refilled: Instant::now(),
+            sleep: None,
+        }
+    }
+
+    /// Returns the wrapped stream.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// Returns how many of `wanted` bytes may be transferred now,
+    /// or `Pending` if none may be yet.
+    fn poll_allowance(&mut self, cx: &mut Context<'_>, wanted: usize) -> Poll<usize> {
+        let Some(limit) = self.limit else {
+            return Poll::Ready(wanted);
+        };
+        let rate = limit.bytes_per_sec.max(1) as f64;
+        let burst = limit.burst.max(1) as f64;
+
+        loop {
+            let now = Instant::now();
+            let earned = now.duration_since(self.refilled).as_secs_f64() * rate;
+            self.tokens = (self.tokens + earned).min(burst);
+            self.refilled = now;
+
+            if self.tokens >= 1.0 || wanted == 0 {
+                self.sleep = None;
+                return Poll::Ready(std::cmp::min(wanted, self.tokens as usize));
+            }
+
+            // wait until enough tokens for a reasonably sized transfer
+            let needed = (wanted as f64).min(burst) - self.tokens;
+            let wake_at = now + Duration::from_secs_f64(needed / rate);
+            match &mut self.sleep {
+                Some(sleep) => sleep.as_mut().reset(wake_at),
+                None => self.sleep = Some(Box::pin(tokio::time::sleep_until(wake_at))),
+            }
+            let sleep = self.sleep.as_mut().expect("set above");
+            ready!(sleep.as_mut().poll(cx));
+        }
+    }
+
+    /// Spends tokens on `len` transferred bytes.
+    fn spend(&mut self, len: usize) {
+        if self.limit.is_some() {
+            self.tokens -= len as f64;
+        }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for RateLimited<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        let allowed = ready!(this.poll_allowance(cx, buf.remaining()));
+
+        let mut limited = ReadBuf::new(buf.initialize_unfilled_to(allowed));
+        ready!(Pin::new(&mut this.inner).poll_read(cx, &mut limited))?;
+        let read = limited.filled().len();
+        buf.advance(read);
+        this.spend(read);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T: AsyncBufRead + Unpin> AsyncBufRead for RateLimited<T> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<&[u8]>> {
+        let this = self.get_mut();
+        let allowed = ready!(this.poll_allowance(cx, usize::MAX));
+        let buf = ready!(Pin::new(&mut this.inner).poll_fill_buf(cx))?;
+        Poll::Ready(Ok(&buf[0..std::cmp::min(allowed, buf.len())]))
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let this = self.get_mut();
+        this.spend(amt);
+        Pin::new(&mut this.inner).consume(amt);
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for RateLimited<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        let allowed = ready!(this.poll_allowance(cx, buf.len()));
+        let written = ready!(Pin::new(&mut this.inner).poll_write(cx, &buf[0..allowed]))?;
+        this.spend(written);
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RateLimit, RateLimited};
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::time::Instant;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rate_limit() {
+        let limit = RateLimit {
+            bytes_per_sec: 10_000,
+            burst: 1_000,
+        };
+
+        // writing 50 KB takes about 5 seconds
+        let start = Instant::now();
+        let mut writer = RateLimited::new(Vec::new(), Some(limit));
+        writer.write_all(&[1; 50_000]).await.unwrap();
+        let elapsed = start.elapsed();
+        assert!(elapsed > Duration::from_millis(4800), "{elapsed:?}");
+        assert!(elapsed < Duration::from_millis(5200), "{elapsed:?}");
+        assert_eq!(writer.into_inner(), [1; 50_000]);
+
+        // so does reading
+        let start = Instant::now();
+        let data = [2; 50_000];
+        let mut reader = RateLimited::new(&data[..], Some(limit));
+        let mut received = Vec::new();
+        reader.read_to_end(&mut received).await.unwrap();
+        let elapsed = start.elapsed();
+        assert!(elapsed > Duration::from_millis(4800), "{elapsed:?}");
+        assert!(elapsed < Duration::from_millis(5200), "{elapsed:?}");
+        assert_eq!(received, data);
+
+        // no limit
+        let start = Instant::now();
+        let mut writer = RateLimited::new(Vec::new(), None);
+        writer.write_all(&[1; 50_000]).await.unwrap();
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+}
@@ -0,0 +1,96 @@
+use crate::{read_from_async, write_to_async, Error};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::time::Instant;
+
+/// How often [`wait_for_start()`] sends a keepalive to the peer,
+/// so that idle NAT mappings and firewalls don't drop the connection.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How often [`wait_for_start()`] reports the remaining time.
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Sent by both peers during [`wait_for_start()`].
+///
+/// The first one holds how long the peer wants to wait.
+/// Later ones are keepalives, and one with `wait_millis == 0`
+/// means the peer is ready to start.
+#[derive(Serialize, Deserialize, Debug)]
+struct WaitMsg {
+    wait_millis: u64,
+}
+
+/// Waits until both peers are ready to start the transfer,
+/// while keeping the connection alive.
+///
+/// Both peers must call this function at the same time.
+/// Each passes how long it wants to `delay` the start
+/// (such as [`Duration::ZERO`] to start right away), and both wait
+/// for the longer of the two delays.
+///
+/// Calls `on_tick` about once per second with the remaining time.
+pub async fn wait_for_start(
+    stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+    delay: Duration,
+    mut on_tick: impl FnMut(Duration),
+) -> Result<(), Error> {
+    // agree on how long to wait
+    let millis = u64::try_from(delay.as_millis()).unwrap_or(u64::MAX);
+    write_to_async(
+        WaitMsg {
+            wait_millis: millis,
+        },
+        stream,
+    )
+    .await?;
+    let peer_msg: WaitMsg = read_from_async(stream).await?;
+    let delay = delay.max(Duration::from_millis(peer_msg.wait_millis));
+    let start = Instant::now() + delay;
+
+    let (mut reader, mut writer) = tokio::io::split(stream);
+
+    // keep reading the peer's keepalives, so they don't pile up
+    let peer_ready = async {
+        loop {
+            let msg: WaitMsg = read_from_async(&mut reader).await?;
+            if msg.wait_millis == 0 {
+                return Ok::<(), Error>(());
+            }
+        }
+    };
+
+    let ready = async {
+        let mut last_keepalive = Instant::now();
+        loop {
+            let now = Instant::now();
+            if now >= start {
+                break;
+            }
+            on_tick(start - now);
+
+            if now - last_keepalive >= KEEPALIVE_INTERVAL {
+                write_to_async(WaitMsg::keepalive(start - now), &mut writer).await?;
+                last_keepalive = now;
+            }
+
+            tokio::time::sleep_until(start.min(now + TICK_INTERVAL)).await;
+        }
+        on_tick(Duration::ZERO);
+        write_to_async(WaitMsg { wait_millis: 0 }, &mut writer).await
+    };
+
+    tokio::try_join!(peer_ready, ready)?;
+    Ok(())
+}
+
+impl WaitMsg {
+    /// Creates a keepalive with the `remaining` time,
+    /// rounded up so it isn't mistaken for being ready.
+    fn keepalive(remaining: Duration) -> Self {
+        let millis = u64::try_from(remaining.as_millis()).unwrap_or(u64::MAX);
+        Self {
+            wait_millis: millis.max(1),
+        }
+    }
+}
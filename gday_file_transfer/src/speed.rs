@@ -0,0 +1,107 @@
+use crate::TransferReport;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// How often throughput is measured.
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Roughly how far back the smoothed throughput, used for the ETA, looks.
+/// Long enough that the ETA doesn't jump around, but
+/// short enough that it follows a connection that speeds up or slows down.
+const SMOOTHING_WINDOW: Duration = Duration::from_secs(5);
+
+/// Measures the throughput of a transfer, and estimates when it will finish,
+/// filling in [`TransferReport::bytes_per_sec`] and [`TransferReport::eta`].
+pub(crate) struct Speedometer {
+    /// When the last sample was taken
+    last_sample: Instant,
+    /// [`TransferReport::processed_bytes`] at the last sample
+    last_bytes: u64,
+    /// Exponential moving average of the throughput,
+    /// or `None` before the first sample
+    smoothed: Option<f64>,
+}
+
+impl Speedometer {
+    pub fn new() -> Self {
+        Self {
+            last_sample: Instant::now(),
+            last_bytes: 0,
+            smoothed: None,
+        }
+    }
+
+    /// Updates the speed and ETA in `report`, if enough time passed
+    /// since the last sample, or if the transfer is done.
+    pub fn update(&mut self, report: &mut TransferReport) {
+        if report.processed_bytes >= report.total_bytes {
+            report.eta = Some(Duration::ZERO);
+            return;
+        }
+
+        let now = Instant::now();
+        let elapsed = now - self.last_sample;
+        if elapsed < SAMPLE_INTERVAL {
+            return;
+        }
+        let bytes = report.processed_bytes.saturating_sub(self.last_bytes);
+        let speed = bytes as f64 / elapsed.as_secs_f64();
+        self.last_sample = now;
+        self.last_bytes = report.processed_bytes;
+
+        // weigh the sample by how long it took, so that
+        // samples taken late don't count for less
+        let weight = 1.0 - (-elapsed.as_secs_f64() / SMOOTHING_WINDOW.as_secs_f64()).exp();
+        let smoothed = match self.smoothed {
+            Some(smoothed) => smoothed + weight * (speed - smoothed),
+            None => speed,
+        };
+        self.smoothed = Some(smoothed);
+
+        report.bytes_per_sec = speed;
+        let remaining = (report.total_bytes - report.processed_bytes) as f64;
+        report.eta = (smoothed > 0.0)
+            .then(|| Duration::try_from_secs_f64(remaining / smoothed).ok())
+            .flatten();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Speedometer;
+    use crate::TransferReport;
+    use std::time::Duration;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_speedometer() {
+        let mut speedometer = Speedometer::new();
+        let mut report = TransferReport::new(10_000, 1);
+
+        // too soon to tell
+        report.processed_bytes = 100;
+        speedometer.update(&mut report);
+        assert_eq!(report.bytes_per_sec, 0.0);
+        assert_eq!(report.eta, None);
+
+        // 1000 bytes per second
+        tokio::time::advance(Duration::from_secs(1)).await;
+        report.processed_bytes = 1_000;
+        speedometer.update(&mut report);
+        assert_eq!(report.bytes_per_sec, 1_000.0);
+        assert_eq!(report.eta, Some(Duration::from_secs(9)));
+
+        // a sudden burst changes the throughput,
+        // but the ETA only a bit
+        tokio::time::advance(Duration::from_secs(1)).await;
+        report.processed_bytes = 5_000;
+        speedometer.update(&mut report);
+        assert_eq!(report.bytes_per_sec, 4_000.0);
+        let eta = report.eta.unwrap();
+        assert!(eta > Duration::from_secs(2) && eta < Duration::from_secs(5));
+
+        // done
+        report.processed_bytes = 10_000;
+        speedometer.update(&mut report);
+        assert_eq!(report.eta, Some(Duration::ZERO));
+    }
+}
@@ -0,0 +1,129 @@
+use crate::{read_from_async, write_to_async, Error};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Number of round trips used to measure latency.
+const NUM_PINGS: u32 = 10;
+
+/// Size of each block of benchmark data sent.
+const BLOCK_SIZE: usize = 64_000;
+
+/// Results of [`run_speedtest()`], as seen by this peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpeedtestReport {
+    /// Average round-trip time of a small message.
+    pub round_trip: Duration,
+
+    /// Bytes per second this peer sent to the other peer.
+    pub upload_speed: u64,
+
+    /// Bytes per second the other peer sent to this peer.
+    pub download_speed: u64,
+}
+
+/// Runs a short latency and throughput benchmark with the other peer over `stream`.
+///
+/// Both peers must call this function at the same time,
+/// with exactly one of them setting `is_leader` to `true`.
+/// Each peer sends data for about `duration` in each direction.
+///
+/// The peers exchange their measurements at the end,
+/// so both receive reports that agree with each other.
+pub async fn run_speedtest(
+    stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+    is_leader: bool,
+    duration: Duration,
+) -> Result<SpeedtestReport, Error> {
+    let (round_trip, upload_speed) = if is_leader {
+        let measurements = measure(stream, duration).await?;
+        respond(stream).await?;
+        measurements
+    } else {
+        respond(stream).await?;
+        measure(stream, duration).await?
+    };
+
+    // exchange upload speeds, leader first
+    let download_speed = if is_leader {
+        write_to_async(upload_speed, stream).await?;
+        read_from_async(stream).await?
+    } else {
+        let download_speed = read_from_async(stream).await?;
+        write_to_async(upload_speed, stream).await?;
+        download_speed
+    };
+
+    Ok(SpeedtestReport {
+        round_trip,
+        upload_speed,
+        download_speed,
+    })
+}
+
+/// Measures the round-trip time and upload speed,
+/// while the other peer runs [`respond()`].
+async fn measure(
+    stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+    duration: Duration,
+) -> Result<(Duration, u64), Error> {
+    // measure latency
+    let start = Instant::now();
+    for _ in 0..NUM_PINGS {
+        stream.write_u8(0).await?;
+        stream.flush().await?;
+        stream.read_u8().await?;
+    }
+    let round_trip = start.elapsed() / NUM_PINGS;
+
+    // send blocks of data until `duration` passes
+    let block = vec![0; BLOCK_SIZE];
+    let mut bytes_sent = 0;
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        stream.write_u32(BLOCK_SIZE as u32).await?;
+        stream.write_all(&block).await?;
+        bytes_sent += BLOCK_SIZE as u64;
+    }
+
+    // a zero-length block marks the end of the data
+    stream.write_u32(0).await?;
+    stream.flush().await?;
+
+    // wait for the other peer to confirm it received everything
+    stream.read_u8().await?;
+    let upload_speed = (bytes_sent as f64 / start.elapsed().as_secs_f64()) as u64;
+
+    Ok((round_trip, upload_speed))
+}
+
+/// Answers the messages sent by the other peer's [`measure()`].
+async fn respond(stream: &mut (impl AsyncRead + AsyncWrite + Unpin)) -> Result<(), Error> {
+    // echo pings
+    for _ in 0..NUM_PINGS {
+        let ping = stream.read_u8().await?;
+        stream.write_u8(ping).await?;
+        stream.flush().await?;
+    }
+
+    // discard received blocks until the zero-length block
+    let mut buf = vec![0; BLOCK_SIZE];
+    loop {
+        let len = stream.read_u32().await? as usize;
+        if len == 0 {
+            break;
+        }
+        if len > BLOCK_SIZE {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Speedtest block longer than expected.",
+            )
+            .into());
+        }
+        stream.read_exact(&mut buf[..len]).await?;
+    }
+
+    // confirm all data was received
+    stream.write_u8(0).await?;
+    stream.flush().await?;
+    Ok(())
+}
@@ -0,0 +1,189 @@
+//! Summaries of received files, and the manifests
+//! that can be saved alongside them.
+use crate::{Error, FileMeta, FileOfferMsg, FileResponseMsg};
+use serde::{Serialize, Serializer};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// What happened to each offered file in a transfer,
+/// returned by [`crate::receive_files()`].
+///
+/// If [`FileResponseMsg::write_manifest`] is set, it's also saved
+/// as JSON in the save directory, so users can audit what arrived.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TransferSummary {
+    /// One summary per file in [`FileOfferMsg::files`], at the same index
+    pub files: Vec<FileSummary>,
+    /// How long the whole transfer took
+    #[serde(rename = "elapsed_secs", serialize_with = "as_secs")]
+    pub elapsed: Duration,
+    /// Where the manifest was saved, if it was
+    #[serde(skip)]
+    pub manifest_path: Option<PathBuf>,
+}
+
+/// What happened to one offered file in a [`TransferSummary`].
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FileSummary {
+    /// The file's path, as offered
+    pub short_path: PathBuf,
+    /// Whether the file arrived
+    pub status: FileStatus,
+    /// Where the file was saved, if it arrived
+    pub saved_path: Option<PathBuf>,
+    /// Number of bytes received in this transfer,
+    /// not counting any resumed part
+    pub bytes: u64,
+    /// Whether the file was verified against a hash sent by the peer
+    pub hash_verified: bool,
+    /// How long receiving the file took
+    #[serde(rename = "elapsed_secs", serialize_with = "as_secs")]
+    pub elapsed: Duration,
+    /// Average number of bytes received per second
+    pub average_speed: f64,
+}
+
+/// Whether a file in a [`TransferSummary`] arrived.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FileStatus {
+    /// Received from the start
+    Received,
+    /// Received after resuming a partial download
+    Resumed,
+    /// Copied from another received file with the same contents,
+    /// as asked for by [`FileResponseMsg::copies`]
+    Copied,
+    /// Rejected in the [`FileResponseMsg`]
+    Skipped,
+    /// Accepted, but the transfer ended before it arrived
+    Failed,
+}
+
+impl TransferSummary {
+    /// Returns a summary of a transfer that hasn't started yet,
+    /// where every file accepted in `response` is [`FileStatus::Failed`]
+    /// until it arrives.
+    pub(crate) fn new(offer: &FileOfferMsg, response: &FileResponseMsg) -> Self {
+        let files = offer
+            .files
+            .iter()
+            .zip(&response.response)
+            .map(|(file, start)| {
+                let status = if start.is_some() {
+                    FileStatus::Failed
+                } else {
+                    FileStatus::Skipped
+                };
+                FileSummary::not_received(file, status)
+            })
+            .collect();
+        Self {
+            files,
+            elapsed: Duration::ZERO,
+            manifest_path: None,
+        }
+    }
+
+    /// Records that a transfer that ended with `result` took `elapsed`,
+    /// and saves the manifest in `save_path` if `response` asks for one.
+    ///
+    /// The manifest is saved even if the transfer failed,
+    /// but then `result`'s error is returned.
+    pub(crate) fn finish(
+        mut self,
+        result: Result<(), Error>,
+        elapsed: Duration,
+        response: &FileResponseMsg,
+        save_path: &Path,
+    ) -> Result<Self, Error> {
+        self.elapsed = elapsed;
+        if response.write_manifest {
+            let manifest_path = self.write_manifest(save_path);
+            // the error that ended the transfer matters more
+            result?;
+            self.manifest_path = Some(manifest_path?);
+        } else {
+            result?;
+        }
+        Ok(self)
+    }
+
+    /// Saves this summary as JSON in `save_dir`, in a new file named
+    /// `gday_manifest_{unix time}.json`, and returns its path.
+    pub fn write_manifest(&self, save_dir: &Path) -> Result<PathBuf, Error> {
+        let json = serde_json::to_vec_pretty(self)?;
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        // don't overwrite the manifest of another transfer
+        for i in 0..100 {
+            let name = if i == 0 {
+                format!("gday_manifest_{timestamp}.json")
+            } else {
+                format!("gday_manifest_{timestamp} ({i}).json")
+            };
+            let path = save_dir.join(name);
+            match std::fs::File::create_new(&path) {
+                Ok(mut file) => {
+                    file.write_all(&json)?;
+                    return Ok(path);
+                }
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Err(Error::FilenameOccupied(save_dir.join("gday_manifest.json")))
+    }
+}
+
+impl FileSummary {
+    /// Returns the summary of `file` that wasn't received.
+    pub(crate) fn not_received(file: &FileMeta, status: FileStatus) -> Self {
+        Self {
+            short_path: file.short_path.clone(),
+            status,
+            saved_path: None,
+            bytes: 0,
+            hash_verified: false,
+            elapsed: Duration::ZERO,
+            average_speed: 0.0,
+        }
+    }
+
+    /// Returns the summary of `file` that arrived at `saved_path`
+    /// after receiving it from byte `start` for `elapsed`.
+    pub(crate) fn received(
+        file: &FileMeta,
+        start: u64,
+        saved_path: PathBuf,
+        elapsed: Duration,
+    ) -> Self {
+        let bytes = file.len - start;
+        Self {
+            short_path: file.short_path.clone(),
+            status: if start == 0 {
+                FileStatus::Received
+            } else {
+                FileStatus::Resumed
+            },
+            saved_path: Some(saved_path),
+            bytes,
+            hash_verified: file.hash.is_some(),
+            elapsed,
+            average_speed: if elapsed.is_zero() {
+                0.0
+            } else {
+                bytes as f64 / elapsed.as_secs_f64()
+            },
+        }
+    }
+}
+
+/// Serializes `duration` as a number of seconds.
+fn as_secs<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
@@ -0,0 +1,213 @@
+//! Keeps sending the offered files to the peer as they change,
+//! turning a session into a simple one-way folder sync.
+//!
+//! The session is a series of rounds on the same stream:
+//! 1. The sender finds files that are new or changed since the last round,
+//!    and offers just those in a [`FileOfferMsg`].
+//! 2. The receiver replies with a [`FileResponseMsg`],
+//!    and the accepted files are sent, just like [`send_files()`].
+//!
+//! Between rounds, the sender sends heartbeats.
+//! The session ends when either peer closes the stream.
+
+use crate::heartbeat::write_heartbeat;
+use crate::{
+    get_file_tree, read_from_async, receive_files, send_files, write_to_async, Compression, Error,
+    FileMetaLocal, FileOfferMsg, FileResponseMsg, OfferOptions, TransferReport,
+};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite};
+
+/// How [`sync_session()`] watches and offers the files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncOptions {
+    /// Which entries within the given directories are watched
+    pub offer: OfferOptions,
+    /// How often to look for changed files.
+    ///
+    /// A changed file is only offered once it stays the same
+    /// for this long, so files aren't sent while they're still being written.
+    pub poll_interval: Duration,
+    /// Compression to propose in each offer
+    pub compression: Option<Compression>,
+    /// Whether to send a hash of each file,
+    /// so the receiver can verify it arrived intact
+    pub checksum: bool,
+}
+
+impl Default for SyncOptions {
+    fn default() -> Self {
+        Self {
+            offer: OfferOptions::default(),
+            poll_interval: Duration::from_secs(1),
+            compression: None,
+            checksum: false,
+        }
+    }
+}
+
+/// What identifies a version of a file between polls.
+type Stamp = (u64, Option<SystemTime>);
+
+/// Watches `paths`, and sends every file that is new or changed
+/// to the peer over `stream`, until the peer closes it.
+///
+/// The peer must call [`receive_sync_session()`] at the same time.
+/// Every file is offered once at the start, then again whenever
+/// its length or modification time changes. The first round waits
+/// for one [`SyncOptions::poll_interval`], like any other change.
+/// Deleted files aren't deleted on the peer.
+///
+/// The files are found by polling, since they're usually on a local disk
+/// that is cheap to scan. `progress_callback` is frequently called with the
+/// [`TransferReport`] of the current round.
+///
+/// Returns `Ok(())` once the peer closes the stream, or
+/// [`Error::PeerCancelled`] if it cancels the session.
+pub async fn sync_session(
+    paths: &[PathBuf],
+    options: &SyncOptions,
+    stream: &mut (impl AsyncBufRead + AsyncWrite + Unpin),
+    mut progress_callback: impl FnMut(&TransferReport),
+) -> Result<(), Error> {
+    // the version of each file at the last poll
+    let mut polled: HashMap<PathBuf, Stamp> = HashMap::new();
+    // the version of each file last offered to the peer
+    let mut offered: HashMap<PathBuf, Stamp> = HashMap::new();
+    let mut heartbeat = 0;
+
+    loop {
+        // wait for the next poll, or for the peer to stop the session
+        tokio::select! {
+            () = tokio::time::sleep(options.poll_interval) => (),
+            closed = peer_closed(stream) => return closed,
+        }
+
+        let tree = get_file_tree(paths, &options.offer)?;
+        let mut changed = Vec::new();
+        let mut current = HashMap::new();
+        for file in tree.files {
+            let stamp = (file.len, file.modified);
+            let settled = polled.get(&file.short_path) == Some(&stamp);
+            if settled && offered.get(&file.short_path) != Some(&stamp) {
+                changed.push(file.clone());
+            }
+            current.insert(file.short_path, stamp);
+        }
+        polled = current;
+
+        if changed.is_empty() {
+            // keeps the connection alive, and notices if the peer vanished
+            if let Err(err) = write_heartbeat(heartbeat, stream).await {
+                return closed_or(err);
+            }
+            heartbeat += 1;
+            continue;
+        }
+
+        match send_round(&mut changed, options, stream, &mut progress_callback).await {
+            Ok(()) => (),
+            Err(err) => return closed_or(err),
+        }
+        for file in changed {
+            offered.insert(file.short_path, (file.len, file.modified));
+        }
+    }
+}
+
+/// Receives files from a peer running [`sync_session()`] over `stream`,
+/// saving them in `save_path`, until the peer closes the stream.
+///
+/// `respond` is called with each round's offer, and decides which files to accept.
+/// Since offered files are usually newer versions of ones already received,
+/// it will typically accept them all and overwrite the old versions, with
+/// [`FileResponseMsg::with_overwrite_policy()`] and [`crate::OverwritePolicy::Overwrite`].
+///
+/// `progress_callback` is frequently called with the
+/// [`TransferReport`] of the current round.
+/// Returns `Ok(())` once the peer closes the stream.
+/// To stop the session, drop this future and close the stream.
+pub async fn receive_sync_session(
+    save_path: &Path,
+    stream: &mut (impl AsyncBufRead + AsyncWrite + Unpin),
+    mut respond: impl FnMut(&FileOfferMsg) -> Result<FileResponseMsg, Error>,
+    mut progress_callback: impl FnMut(&TransferReport),
+) -> Result<(), Error> {
+    loop {
+        let offer: FileOfferMsg = match read_from_async(stream).await {
+            Ok(offer) => offer,
+            Err(err) => return closed_or(err),
+        };
+        let response = respond(&offer)?;
+        write_to_async(&response, stream).await?;
+        receive_files(
+            &offer,
+            &response,
+            save_path,
+            &mut *stream,
+            &mut progress_callback,
+        )
+        .await?;
+    }
+}
+
+/// Offers the `changed` files to the peer, and sends the ones it accepts.
+async fn send_round(
+    changed: &mut [FileMetaLocal],
+    options: &SyncOptions,
+    stream: &mut (impl AsyncBufRead + AsyncWrite + Unpin),
+    progress_callback: impl FnMut(&TransferReport),
+) -> Result<(), Error> {
+    for file in changed.iter_mut() {
+        file.compute_head_hash()?;
+        if options.checksum {
+            file.compute_hash()?;
+        }
+    }
+    let mut offer = FileOfferMsg::from(changed.to_vec());
+    offer.compression = options.compression;
+    write_to_async(&offer, stream).await?;
+
+    let response: FileResponseMsg = read_from_async(stream).await?;
+    if response.response.len() != offer.files.len() {
+        return Err(Error::InvalidResponseLength);
+    }
+    send_files(changed, &response, &mut *stream, progress_callback).await
+}
+
+/// Resolves once the peer closes `stream` or sends it a message,
+/// which the receiver only does to cancel the session.
+async fn peer_closed(stream: &mut (impl AsyncBufRead + AsyncWrite + Unpin)) -> Result<(), Error> {
+    if stream.fill_buf().await?.is_empty() {
+        return Ok(());
+    }
+    match read_from_async::<()>(stream).await {
+        Ok(()) => Err(Error::IO(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "Unexpected message from the peer during a sync session.",
+        ))),
+        Err(err) => closed_or(err),
+    }
+}
+
+/// Returns `Ok(())` if `err` means the peer closed the connection,
+/// which ends a sync session normally, or `err` otherwise.
+fn closed_or(err: Error) -> Result<(), Error> {
+    use std::io::ErrorKind;
+    match &err {
+        Error::IO(io)
+            if matches!(
+                io.kind(),
+                ErrorKind::UnexpectedEof
+                    | ErrorKind::BrokenPipe
+                    | ErrorKind::ConnectionReset
+                    | ErrorKind::ConnectionAborted
+            ) =>
+        {
+            Ok(())
+        }
+        _ => Err(err),
+    }
+}
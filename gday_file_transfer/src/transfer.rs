@@ -1,10 +1,28 @@
-use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
-use crate::{Error, FileMeta, FileMetaLocal, FileOfferMsg, FileResponseMsg};
-use std::io::{ErrorKind, Seek, SeekFrom};
-use std::path::Path;
+use crate::archive::{self, archive_path, BLOCK_LEN};
+use crate::chunk::{
+    abort_payload, max_payload_len, read_abort, ChunkHeader, ChunkKind, CHUNK_LEN, COPY_PAYLOAD_LEN,
+};
+use crate::dedup::{check_copies, save_copies};
+use crate::delta::{DeltaEncoder, DeltaOp};
+use crate::partial_download::TmpInfoFile;
+use crate::path_policy::check_safe_path;
+use crate::speed::Speedometer;
+use crate::{
+    ensure_disk_space, CancelReason, CancelToken, Compression, Error, FileMeta, FileMetaLocal,
+    FileOfferMsg, FileResponseMsg, FileSummary, OverwritePolicy, TransferSummary,
+};
+use std::collections::BTreeSet;
+use std::future::Future;
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
 use std::pin::{pin, Pin};
-use std::task::{ready, Context, Poll};
+use std::time::{Duration, Instant};
+
+/// Number of bytes of a file received between syncing it to disk
+/// and recording in its info file that they can be resumed.
+const CHECKPOINT_LEN: u64 = 0x100_0000;
 
 /// Holds the status of a file transfer
 #[derive(Debug, Clone)]
@@ -14,6 +32,43 @@ pub struct TransferReport {
     pub processed_files: u64,
     pub total_files: u64,
     pub current_file: std::path::PathBuf,
+    /// Bytes of [`Self::current_file`] done so far,
+    /// including any part that was resumed
+    pub current_file_processed: u64,
+    /// Length of [`Self::current_file`]
+    pub current_file_len: u64,
+    /// Throughput over the last fraction of a second, in bytes per second
+    pub bytes_per_sec: f64,
+    /// Estimated time until the transfer finishes,
+    /// based on the throughput of the last few seconds.
+    /// `None` until the throughput was measured.
+    pub eta: Option<Duration>,
+}
+
+impl TransferReport {
+    /// Returns the report of a transfer that hasn't started yet.
+    pub(crate) fn new(total_bytes: u64, total_files: u64) -> Self {
+        Self {
+            processed_bytes: 0,
+            total_bytes,
+            processed_files: 0,
+            total_files,
+            current_file: PathBuf::new(),
+            current_file_processed: 0,
+            current_file_len: 0,
+            bytes_per_sec: 0.0,
+            eta: None,
+        }
+    }
+
+    /// Records that the file with `short_path` and `len` bytes
+    /// is now being transferred, starting at byte `start`.
+    pub(crate) fn start_file(&mut self, short_path: &Path, len: u64, start: u64) {
+        self.current_file.clear();
+        self.current_file.push(short_path);
+        self.current_file_len = len;
+        self.current_file_processed = start;
+    }
 }
 
 /// Transfers the requested files to `writer`.
@@ -24,62 +79,365 @@ pub struct TransferReport {
 /// - `progress_callback` is a function that gets frequently
 ///   called with [`TransferReport`] to report progress.
 ///
-/// Transfers the accepted files in order, sequentially, back-to-back.
+/// Transfers the accepted files in order, sequentially,
+/// each split into chunks that say which file and offset they belong to.
+/// Compresses the chunks if [`FileResponseMsg::compression`] is set,
+/// or sends all the files as one uncompressed tar archive
+/// if [`FileResponseMsg::archive`] is set.
+/// Skips the files in [`FileResponseMsg::copies`],
+/// which the receiver copies from identical files instead.
+///
+/// To limit its bandwidth, wrap `writer` in a [`crate::RateLimited`].
 pub async fn send_files(
     offer: &[FileMetaLocal],
     response: &FileResponseMsg,
     writer: impl AsyncWrite,
     progress_callback: impl FnMut(&TransferReport),
 ) -> Result<(), Error> {
-    let writer = pin!(writer);
-    let files: Vec<(&FileMetaLocal, u64)> = offer
+    let cancel = CancelToken::new();
+    send_files_cancellable(offer, response, writer, progress_callback, &cancel).await
+}
+
+/// Like [`send_files()`], but stops once `cancel` is cancelled,
+/// returning [`Error::Cancelled`].
+///
+/// Before stopping, tells the receiver why with an abort chunk in place of
+/// the next chunk, so that the receiver's [`receive_files()`] saves what
+/// arrived for resuming later, and returns [`Error::PeerCancelled`].
+/// The receiver is told the same way if sending fails on this side,
+/// such as when a file can't be read.
+/// If [`FileResponseMsg::archive`] is set,
+/// there's no room for this in the archive, so it only stops.
+pub async fn send_files_cancellable(
+    offer: &[FileMetaLocal],
+    response: &FileResponseMsg,
+    writer: impl AsyncWrite,
+    progress_callback: impl FnMut(&TransferReport),
+    cancel: &CancelToken,
+) -> Result<(), Error> {
+    let lens: Vec<u64> = offer.iter().map(|file| file.len).collect();
+    check_copies(response, &lens)?;
+
+    // offers have fewer than 2^32 files, since they're shorter than 2^32 bytes
+    let files: Vec<(u32, &FileMetaLocal, u64)> = offer
         .iter()
         .zip(&response.response)
-        .filter_map(|(file, response)| response.map(|response| (file, response)))
+        .enumerate()
+        .filter(|(index, _)| !response.copies.contains_key(index))
+        .filter_map(|(index, (file, start))| start.map(|start| (index as u32, file, start)))
         .collect();
 
+    send_some_files(&files, response, writer, progress_callback, cancel).await
+}
+
+/// Like [`send_files_cancellable()`], but sends `files`, each given as
+/// its index in the offer, its metadata, and the byte to start at.
+pub(crate) async fn send_some_files(
+    files: &[(u32, &FileMetaLocal, u64)],
+    response: &FileResponseMsg,
+    writer: impl AsyncWrite,
+    progress_callback: impl FnMut(&TransferReport),
+    cancel: &CancelToken,
+) -> Result<(), Error> {
+    let writer = pin!(writer);
+
     // sum up total transfer size
     let mut total_bytes = 0;
-    for (file, start) in &files {
+    for (_, file, start) in files {
         total_bytes += file
             .len
             .checked_sub(*start)
             .ok_or(Error::InvalidStartIndex)?;
     }
 
-    // Wrap the writer to report progress over `progress_tx`
-    let mut writer =
-        ProgressWrapper::new(writer, total_bytes, files.len() as u64, progress_callback);
+    let mut progress = Progress::new(total_bytes, files.len() as u64, progress_callback);
+
+    if response.archive {
+        return send_archive(files, response, writer, &mut progress, cancel).await;
+    }
+
+    let mut chunks = ChunkWriter::new(writer, response.compression)?.with_cancel(cancel);
+    if let Err(err) = send_chunks(files, response, &mut chunks, &mut progress).await {
+        // a cancellation already sent its abort chunk.
+        // the error that stopped the transfer matters more.
+        if !matches!(err, Error::Cancelled(_)) {
+            let _ = chunks.write_abort(&CancelReason::from_error(&err)).await;
+        }
+        return Err(err);
+    }
+
+    let mut writer = chunks.writer;
+    writer.flush().await?;
 
-    // 64 KiB copy buffer
-    let mut buf = vec![0; 0x10000];
+    Ok(())
+}
+
+/// Sends the contents of `files` with `chunks`.
+async fn send_chunks<W: AsyncWrite>(
+    files: &[(u32, &FileMetaLocal, u64)],
+    response: &FileResponseMsg,
+    chunks: &mut ChunkWriter<'_, W>,
+    progress: &mut Progress<impl FnMut(&TransferReport)>,
+) -> Result<(), Error> {
+    let mut buf = vec![0; CHUNK_LEN];
 
     // iterate over all the files
-    for (offer, start) in files {
+    for &(file_index, meta, start) in files {
         // report the file path
-        writer.progress.current_file.clone_from(&offer.short_path);
+        progress
+            .report
+            .start_file(&meta.short_path, meta.len, start);
 
-        let mut file = std::fs::File::open(&offer.local_path)?;
+        let mut file = std::fs::File::open(&meta.local_path)?;
 
         // confirm file length matches metadata length
-        if file.metadata()?.len() != offer.len {
+        if file.metadata()?.len() != meta.len {
             return Err(Error::UnexpectedFileLen);
         }
-
-        // copy the file into the writer
         file.seek(SeekFrom::Start(start))?;
 
-        file_to_net(&mut file, &mut writer, offer.len - start, &mut buf).await?;
+        let compress = meta.compressible;
+        let mut offset = start;
+
+        if let Some(signature) = response.deltas.get(&(file_index as usize)) {
+            if start != 0 {
+                return Err(Error::InvalidSignature);
+            }
+            let mut encoder = DeltaEncoder::new(&mut file, signature)?;
+            while let Some(op) = encoder.next_op()? {
+                let len = match op {
+                    DeltaOp::Literal(data) => {
+                        chunks
+                            .write_data(file_index, offset, data, compress)
+                            .await?;
+                        data.len() as u64
+                    }
+                    DeltaOp::Copy { offset: from, len } => {
+                        chunks.write_copy(file_index, offset, from, len).await?;
+                        u64::from(len)
+                    }
+                };
+                offset += len;
+                progress.add(len);
+            }
+            if offset != meta.len {
+                return Err(Error::UnexpectedFileLen);
+            }
+            // so the receiver knows an empty file arrived
+            if meta.len == 0 {
+                chunks.write_data(file_index, 0, &[], false).await?;
+            }
+        } else {
+            // even an empty file is sent as one chunk,
+            // so the receiver knows it arrived
+            loop {
+                let len = std::cmp::min(meta.len - offset, CHUNK_LEN as u64) as usize;
+                let data = &mut buf[0..len];
+                file.read_exact(data).map_err(|err| match err.kind() {
+                    ErrorKind::UnexpectedEof => Error::UnexpectedFileLen,
+                    _ => err.into(),
+                })?;
+                chunks
+                    .write_data(file_index, offset, data, compress)
+                    .await?;
+
+                offset += len as u64;
+                progress.add(len as u64);
+                if offset == meta.len {
+                    break;
+                }
+            }
+        }
 
         // report the number of processed files
-        writer.progress.processed_files += 1;
+        progress.report.processed_files += 1;
     }
 
-    writer.flush().await?;
+    Ok(())
+}
+
+/// Sends `files` as one tar archive, as asked for by [`FileResponseMsg::archive`].
+///
+/// A file accepted from a byte other than 0 is sent as an entry
+/// holding only the rest of it.
+async fn send_archive<W: AsyncWrite>(
+    files: &[(u32, &FileMetaLocal, u64)],
+    response: &FileResponseMsg,
+    mut writer: Pin<&mut W>,
+    progress: &mut Progress<impl FnMut(&TransferReport)>,
+    cancel: &CancelToken,
+) -> Result<(), Error> {
+    if !response.deltas.is_empty() {
+        return Err(Error::InvalidSignature);
+    }
 
+    let mut buf = vec![0; CHUNK_LEN];
+    let check_cancelled = || match cancel.reason() {
+        Some(reason) => Err(Error::Cancelled(reason)),
+        None => Ok(()),
+    };
+    for &(_, meta, start) in files {
+        progress
+            .report
+            .start_file(&meta.short_path, meta.len, start);
+
+        let mut file = std::fs::File::open(&meta.local_path)?;
+        if file.metadata()?.len() != meta.len {
+            return Err(Error::UnexpectedFileLen);
+        }
+        file.seek(SeekFrom::Start(start))?;
+
+        let len = meta.len - start;
+        let header = archive::header(
+            &archive_path(&meta.short_path),
+            len,
+            meta.mode,
+            meta.modified,
+        );
+        writer.write_all(&header).await?;
+
+        let mut remaining = len;
+        while remaining > 0 {
+            check_cancelled()?;
+            let data = &mut buf[0..std::cmp::min(remaining, CHUNK_LEN as u64) as usize];
+            file.read_exact(data).map_err(|err| match err.kind() {
+                ErrorKind::UnexpectedEof => Error::UnexpectedFileLen,
+                _ => err.into(),
+            })?;
+            writer.write_all(data).await?;
+            remaining -= data.len() as u64;
+            progress.add(data.len() as u64);
+        }
+        writer
+            .write_all(&[0; BLOCK_LEN][0..archive::padding(len)])
+            .await?;
+
+        progress.report.processed_files += 1;
+    }
+
+    writer.write_all(&[0; 2 * BLOCK_LEN]).await?;
+    writer.flush().await?;
     Ok(())
 }
 
+/// Writes chunks of file contents.
+pub(crate) struct ChunkWriter<'a, W> {
+    pub writer: Pin<&'a mut W>,
+    /// Set if chunks may be compressed
+    compressor: Option<zstd::bulk::Compressor<'static>>,
+    /// Payload of the current compressed chunk
+    compressed: Vec<u8>,
+    /// Checked before each chunk
+    cancel: Option<&'a CancelToken>,
+}
+
+impl<'a, W: AsyncWrite> ChunkWriter<'a, W> {
+    /// Writes chunks to `writer`, compressed with `compression`, if set.
+    pub fn new(writer: Pin<&'a mut W>, compression: Option<Compression>) -> Result<Self, Error> {
+        Ok(Self {
+            writer,
+            compressor: match compression {
+                Some(Compression::Zstd { level }) => Some(zstd::bulk::Compressor::new(level)?),
+                None => None,
+            },
+            compressed: Vec::with_capacity(max_payload_len(ChunkKind::Zstd)),
+            cancel: None,
+        })
+    }
+
+    /// Makes every chunk written after `cancel` is cancelled an abort chunk,
+    /// which fails with [`Error::Cancelled`] once written.
+    pub fn with_cancel(mut self, cancel: &'a CancelToken) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    /// If cancelled, writes an abort chunk,
+    /// and returns [`Error::Cancelled`].
+    async fn check_cancelled(&mut self) -> Result<(), Error> {
+        let Some(reason) = self.cancel.and_then(CancelToken::reason) else {
+            return Ok(());
+        };
+        self.write_abort(&reason).await?;
+        Err(Error::Cancelled(reason))
+    }
+
+    /// Writes an abort chunk, telling the receiver
+    /// the transfer stopped because of `reason`.
+    async fn write_abort(&mut self, reason: &CancelReason) -> Result<(), Error> {
+        let payload = abort_payload(reason);
+        let header = ChunkHeader {
+            file_index: 0,
+            offset: 0,
+            kind: ChunkKind::Abort,
+            len: payload.len() as u32,
+        };
+        self.writer.write_all(&header.to_bytes()).await?;
+        self.writer.write_all(&payload).await?;
+        self.writer.flush().await?;
+        Ok(())
+    }
+
+    /// Writes `data` as one chunk, which starts at `offset` in the file at `file_index`.
+    ///
+    /// Compresses it if `compress` is set, unless that doesn't make it shorter.
+    pub async fn write_data(
+        &mut self,
+        file_index: u32,
+        offset: u64,
+        data: &[u8],
+        compress: bool,
+    ) -> Result<(), Error> {
+        self.check_cancelled().await?;
+        let (kind, payload) = match &mut self.compressor {
+            Some(compressor) if compress && !data.is_empty() => {
+                self.compressed.clear();
+                compressor.compress_to_buffer(data, &mut self.compressed)?;
+                if self.compressed.len() < data.len() {
+                    (ChunkKind::Zstd, &self.compressed[..])
+                } else {
+                    (ChunkKind::Raw, data)
+                }
+            }
+            _ => (ChunkKind::Raw, data),
+        };
+
+        let header = ChunkHeader {
+            file_index,
+            offset,
+            kind,
+            len: payload.len() as u32,
+        };
+        self.writer.write_all(&header.to_bytes()).await?;
+        self.writer.write_all(payload).await?;
+        Ok(())
+    }
+
+    /// Writes a chunk telling the receiver to copy `len` bytes from `from`
+    /// in its old version of the file at `file_index`, to `offset`.
+    async fn write_copy(
+        &mut self,
+        file_index: u32,
+        offset: u64,
+        from: u64,
+        len: u32,
+    ) -> Result<(), Error> {
+        self.check_cancelled().await?;
+        let header = ChunkHeader {
+            file_index,
+            offset,
+            kind: ChunkKind::Copy,
+            len: COPY_PAYLOAD_LEN as u32,
+        };
+        let mut payload = [0; COPY_PAYLOAD_LEN];
+        payload[0..8].copy_from_slice(&from.to_be_bytes());
+        payload[8..12].copy_from_slice(&len.to_be_bytes());
+        self.writer.write_all(&header.to_bytes()).await?;
+        self.writer.write_all(&payload).await?;
+        Ok(())
+    }
+}
+
 /// Receives the requested files from `reader`.
 ///
 /// - `offer` is the [`FileOfferMsg`] offered by the peer.
@@ -89,213 +447,748 @@ pub async fn send_files(
 /// - `progress_callback` is an function that gets frequently
 ///   called with [`TransferReport`] to report progress.
 ///
-/// The accepted files must be sent in order, sequentially, back-to-back.
+/// The accepted files must be sent in order, sequentially, by [`send_files()`].
+/// Every few megabytes, the file being received is synced to disk,
+/// so that after a crash, it's only resumed from what was surely written.
+///
+/// Before receiving anything, returns [`Error::InsufficientDiskSpace`]
+/// if the accepted files won't fit on the save path's file system.
+/// Aborts if the disk fills up anyway. Use [`receive_files_with_recovery()`]
+/// to handle that instead.
+///
+/// Returns a [`TransferSummary`] of what happened to each offered file.
+/// If [`FileResponseMsg::write_manifest`] is set, also saves it in `save_path`,
+/// even if the transfer fails.
+///
+/// To limit its bandwidth, wrap `reader` in a [`crate::RateLimited`].
 pub async fn receive_files(
     offer: &FileOfferMsg,
     response: &FileResponseMsg,
     save_path: &Path,
     reader: impl AsyncBufRead,
     progress_callback: impl FnMut(&TransferReport),
-) -> Result<(), Error> {
-    let reader = pin!(reader);
-    let files: Vec<(&FileMeta, u64)> = offer
+) -> Result<TransferSummary, Error> {
+    ensure_disk_space(offer, response, save_path)?;
+    receive_files_with_recovery(
+        offer,
+        response,
+        save_path,
+        reader,
+        progress_callback,
+        |_| std::future::ready(StorageFullAction::Abort),
+    )
+    .await
+}
+
+/// What [`receive_files_with_recovery()`] should do
+/// when the disk runs out of space.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum StorageFullAction {
+    /// Try writing again, presumably after space was freed.
+    Retry,
+
+    /// Move the file being downloaded into this directory,
+    /// and save it and all the remaining files there instead.
+    MoveTo(PathBuf),
+
+    /// Stop the transfer, returning the out of space error.
+    Abort,
+}
+
+/// Like [`receive_files()`], but calls `on_storage_full`
+/// when the disk runs out of space, instead of aborting.
+///
+/// `on_storage_full` is given the directory where files are
+/// currently being saved, and returns a future that resolves to what to do next.
+/// It may take as long as it needs, for example to prompt the user.
+/// Meanwhile, the peer is paused by TCP flow control,
+/// since nothing is read from `reader`.
+pub async fn receive_files_with_recovery<P: Future<Output = StorageFullAction>>(
+    offer: &FileOfferMsg,
+    response: &FileResponseMsg,
+    save_path: &Path,
+    reader: impl AsyncBufRead,
+    progress_callback: impl FnMut(&TransferReport),
+    on_storage_full: impl FnMut(&Path) -> P,
+) -> Result<TransferSummary, Error> {
+    let cancel = CancelToken::new();
+    receive_files_cancellable(
+        offer,
+        response,
+        save_path,
+        reader,
+        progress_callback,
+        on_storage_full,
+        &cancel,
+    )
+    .await
+}
+
+/// Like [`receive_files_with_recovery()`], but stops once `cancel` is cancelled,
+/// returning [`Error::Cancelled`].
+///
+/// What arrived of the current file is saved as a partial download,
+/// so a later transfer can resume it. Tell the peer with
+/// [`crate::write_cancel_async()`], since nothing is sent to it.
+pub async fn receive_files_cancellable<P: Future<Output = StorageFullAction>>(
+    offer: &FileOfferMsg,
+    response: &FileResponseMsg,
+    save_path: &Path,
+    reader: impl AsyncBufRead,
+    progress_callback: impl FnMut(&TransferReport),
+    on_storage_full: impl FnMut(&Path) -> P,
+    cancel: &CancelToken,
+) -> Result<TransferSummary, Error> {
+    let started = Instant::now();
+    let lens: Vec<u64> = offer.files.iter().map(|file| file.len).collect();
+    check_copies(response, &lens)?;
+    let mut summary = TransferSummary::new(offer, response);
+    let files: Vec<(u32, &FileMeta, u64)> = offer
         .files
         .iter()
         .zip(&response.response)
-        .filter_map(|(file, response)| response.map(|response| (file, response)))
+        .enumerate()
+        .filter(|(index, _)| !response.copies.contains_key(index))
+        .filter_map(|(index, (file, start))| start.map(|start| (index as u32, file, start)))
         .collect();
 
+    let result = receive_some_files(
+        &files,
+        response,
+        save_path,
+        reader,
+        progress_callback,
+        on_storage_full,
+        |index, file| summary.files[index as usize] = file,
+        cancel,
+    )
+    .await
+    .and_then(|()| save_copies(offer, response, save_path, &mut summary));
+    summary.finish(result, started.elapsed(), response, save_path)
+}
+
+/// Like [`receive_files_cancellable()`], but receives `files`, each given as
+/// its index in the offer, its metadata, and the byte to start at.
+///
+/// Calls `on_received` with the index and summary of each file that arrives.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn receive_some_files<P: Future<Output = StorageFullAction>>(
+    files: &[(u32, &FileMeta, u64)],
+    response: &FileResponseMsg,
+    save_path: &Path,
+    reader: impl AsyncBufRead,
+    progress_callback: impl FnMut(&TransferReport),
+    on_storage_full: impl FnMut(&Path) -> P,
+    mut on_received: impl FnMut(u32, FileSummary),
+    cancel: &CancelToken,
+) -> Result<(), Error> {
     // sum up total transfer size
     let mut total_bytes = 0;
-    for (file, start) in &files {
+    for (_, file, start) in files {
+        check_safe_path(&file.short_path)?;
         total_bytes += file
             .len
             .checked_sub(*start)
             .ok_or(Error::InvalidStartIndex)?;
     }
 
-    // Wrap the reader to report progress over `progress_tx`
-    let mut reader =
-        ProgressWrapper::new(reader, total_bytes, files.len() as u64, progress_callback);
+    let decompressor = match response.compression {
+        Some(Compression::Zstd { .. }) => Some(zstd::bulk::Decompressor::new()?),
+        None => None,
+    };
+
+    let reader = pin!(reader);
+    let mut receiver = Receiver {
+        reader,
+        progress: Progress::new(total_bytes, files.len() as u64, progress_callback),
+        save_path: save_path.to_path_buf(),
+        on_storage_full,
+        preserve_metadata: response.preserve_metadata,
+        overwrite: response.overwrite,
+        deltas: response.deltas.keys().copied().collect(),
+        decompressor,
+        compressed: Vec::new(),
+        decompressed: vec![0; CHUNK_LEN],
+        cancel,
+    };
+
+    if response.archive {
+        return receiver.receive_archive(files, on_received).await;
+    }
 
     // iterate over all the files
-    for (offer, start) in files {
+    for &(file_index, meta, start) in files {
         // set progress bar message to file path
-        reader.progress.current_file.clone_from(&offer.short_path);
+        receiver
+            .progress
+            .report
+            .start_file(&meta.short_path, meta.len, start);
+        let summary = receiver.receive_file(file_index, meta, start).await?;
+        on_received(file_index, summary);
+        receiver.progress.report.processed_files += 1;
+    }
+
+    Ok(())
+}
+
+/// Receives files from `reader`, one chunk at a time.
+struct Receiver<'a, R, F, S> {
+    reader: Pin<&'a mut R>,
+    progress: Progress<F>,
+    /// Where files are saved. May change if the disk fills up.
+    save_path: PathBuf,
+    on_storage_full: S,
+    preserve_metadata: bool,
+    overwrite: OverwritePolicy,
+    /// Indices of the files sent as deltas
+    deltas: BTreeSet<usize>,
+    /// Set if the peer may send compressed chunks
+    decompressor: Option<zstd::bulk::Decompressor<'static>>,
+    /// Payload of the current compressed chunk
+    compressed: Vec<u8>,
+    /// Decompressed contents of the current chunk
+    decompressed: Vec<u8>,
+    /// Stops the transfer once cancelled
+    cancel: &'a CancelToken,
+}
+
+impl<R, F, S, P> Receiver<'_, R, F, S>
+where
+    R: AsyncBufRead,
+    F: FnMut(&TransferReport),
+    S: FnMut(&Path) -> P,
+    P: Future<Output = StorageFullAction>,
+{
+    /// Receives the file at `file_index` in the offer,
+    /// starting at byte `start`, and saves it.
+    async fn receive_file(
+        &mut self,
+        file_index: u32,
+        meta: &FileMeta,
+        start: u64,
+    ) -> Result<FileSummary, Error> {
+        let started = Instant::now();
+        let delta = self.deltas.contains(&(file_index as usize));
+        if delta && start != 0 {
+            return Err(Error::InvalidSignature);
+        }
+        let mut download = Download::start(meta, start, &self.save_path, delta)?;
+
+        let cancel = self.cancel;
+        let result = tokio::select! {
+            result = self.receive_contents(file_index, &mut download) => result,
+            reason = cancel.cancelled() => Err(Error::Cancelled(reason)),
+        };
+        if let Err(err) = result {
+            // so that a retry resumes from here.
+            // the error that ended the transfer matters more.
+            let _ = download.checkpoint(&self.save_path);
+            return Err(err);
+        }
+
+        let saved_path =
+            download.finish(&self.save_path, self.preserve_metadata, self.overwrite)?;
+        Ok(FileSummary::received(
+            meta,
+            start,
+            saved_path,
+            started.elapsed(),
+        ))
+    }
+
+    /// Receives `files` as one tar archive, as asked for by [`FileResponseMsg::archive`],
+    /// and calls `on_received` with the index and summary of each one.
+    async fn receive_archive(
+        &mut self,
+        files: &[(u32, &FileMeta, u64)],
+        mut on_received: impl FnMut(u32, FileSummary),
+    ) -> Result<(), Error> {
+        if !self.deltas.is_empty() {
+            return Err(Error::InvalidSignature);
+        }
+
+        for &(file_index, meta, start) in files {
+            self.progress
+                .report
+                .start_file(&meta.short_path, meta.len, start);
+            let started = Instant::now();
+
+            // the entry must be the next accepted file
+            let expected = archive::Entry {
+                path: archive_path(&meta.short_path),
+                len: meta.len - start,
+            };
+            if archive::read_header(&mut self.reader).await? != Some(expected) {
+                return Err(Error::InvalidArchive);
+            }
+
+            let mut download = Download::start(meta, start, &self.save_path, false)?;
+            while download.written < meta.len {
+                let len = std::cmp::min(meta.len - download.written, CHECKPOINT_LEN);
+                let cancel = self.cancel;
+                let result = tokio::select! {
+                    result = self.receive_bytes(len, &mut download) => result,
+                    reason = cancel.cancelled() => Err(Error::Cancelled(reason)),
+                };
+                if let Err(err) = result {
+                    // so that a retry resumes from here
+                    let _ = download.checkpoint(&self.save_path);
+                    return Err(err);
+                }
+                if download.written - download.synced >= CHECKPOINT_LEN {
+                    download.checkpoint(&self.save_path)?;
+                }
+            }
+            let mut padding = [0; BLOCK_LEN];
+            let padding = &mut padding[0..archive::padding(meta.len - start)];
+            self.reader.read_exact(padding).await?;
+
+            let saved_path =
+                download.finish(&self.save_path, self.preserve_metadata, self.overwrite)?;
+            on_received(
+                file_index,
+                FileSummary::received(meta, start, saved_path, started.elapsed()),
+            );
+            self.progress.report.processed_files += 1;
+        }
+
+        if archive::read_header(&mut self.reader).await?.is_some() {
+            return Err(Error::InvalidArchive);
+        }
+        Ok(())
+    }
+
+    /// Receives chunks into `download` until its file is complete.
+    async fn receive_contents(
+        &mut self,
+        file_index: u32,
+        download: &mut Download<'_>,
+    ) -> Result<(), Error> {
+        loop {
+            let header = ChunkHeader::read(&mut self.reader).await?;
+            if header.kind == ChunkKind::Abort {
+                return Err(read_abort(&mut self.reader, header.len).await);
+            }
+            if header.file_index != file_index || header.offset != download.written {
+                return Err(Error::InvalidChunk);
+            }
+
+            match header.kind {
+                ChunkKind::Raw => self.receive_raw(header.len, download).await?,
+                ChunkKind::Zstd => self.receive_zstd(header.len, download).await?,
+                ChunkKind::Copy => self.receive_copy(header.len, download).await?,
+                ChunkKind::Abort => unreachable!("Handled above."),
+            }
+
+            if download.written - download.synced >= CHECKPOINT_LEN {
+                download.checkpoint(&self.save_path)?;
+            }
+            if download.written == download.meta.len {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Copies a payload of `len` bytes from the reader into `download`.
+    ///
+    /// If the disk fills up, leaves the unwritten data in the reader,
+    /// so the copy can be resumed.
+    async fn receive_raw(&mut self, len: u32, download: &mut Download<'_>) -> Result<(), Error> {
+        if u64::from(len) > download.meta.len - download.written {
+            return Err(Error::InvalidChunk);
+        }
+        self.receive_bytes(u64::from(len), download).await
+    }
+
+    /// Copies `len` bytes from the reader into `download`.
+    ///
+    /// If the disk fills up, leaves the unwritten data in the reader,
+    /// so the copy can be resumed.
+    async fn receive_bytes(&mut self, len: u64, download: &mut Download<'_>) -> Result<(), Error> {
+        let mut remaining = len;
+        while remaining > 0 {
+            let buf = self.reader.fill_buf().await?;
+            if buf.is_empty() {
+                return Err(std::io::Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "Peer interrupted transfer.",
+                )
+                .into());
+            }
+            let to_write = std::cmp::min(remaining, buf.len() as u64) as usize;
+            let written = download
+                .write(
+                    &buf[0..to_write],
+                    &mut self.save_path,
+                    &mut self.on_storage_full,
+                )
+                .await?;
+            self.reader.as_mut().consume(written);
+            remaining -= written as u64;
+            self.progress.add(written as u64);
+        }
+        Ok(())
+    }
+
+    /// Decompresses a payload of `len` bytes from the reader into `download`.
+    async fn receive_zstd(&mut self, len: u32, download: &mut Download<'_>) -> Result<(), Error> {
+        let Some(decompressor) = &mut self.decompressor else {
+            return Err(Error::InvalidChunk);
+        };
+
+        self.compressed.resize(len as usize, 0);
+        self.reader.read_exact(&mut self.compressed).await?;
+
+        // a chunk that decompresses past the end of the file is invalid
+        let max_len = std::cmp::min(download.meta.len - download.written, CHUNK_LEN as u64);
+        let decompressed = &mut self.decompressed[0..max_len as usize];
+        let decompressed_len = decompressor
+            .decompress_to_buffer(&self.compressed[..], decompressed)
+            .map_err(|_| Error::InvalidChunk)?;
+
+        let mut data = &decompressed[0..decompressed_len];
+        while !data.is_empty() {
+            let written = download
+                .write(data, &mut self.save_path, &mut self.on_storage_full)
+                .await?;
+            data = &data[written..];
+            self.progress.add(written as u64);
+        }
+        Ok(())
+    }
+
+    /// Reads a [`ChunkKind::Copy`] payload of `len` bytes,
+    /// and copies the bytes it describes from
+    /// the old version of the file into `download`.
+    async fn receive_copy(&mut self, len: u32, download: &mut Download<'_>) -> Result<(), Error> {
+        if len as usize != COPY_PAYLOAD_LEN {
+            return Err(Error::InvalidChunk);
+        }
+        let mut payload = [0; COPY_PAYLOAD_LEN];
+        self.reader.read_exact(&mut payload).await?;
+        let mut from = u64::from_be_bytes(payload[0..8].try_into().unwrap());
+        let mut remaining = u64::from(u32::from_be_bytes(payload[8..12].try_into().unwrap()));
+        if remaining > download.meta.len - download.written {
+            return Err(Error::InvalidChunk);
+        }
+
+        while remaining > 0 {
+            let len = std::cmp::min(remaining, CHUNK_LEN as u64) as usize;
+            let data = &mut self.decompressed[0..len];
+            download.read_basis(from, data)?;
+
+            let mut data = &data[..];
+            while !data.is_empty() {
+                let written = download
+                    .write(data, &mut self.save_path, &mut self.on_storage_full)
+                    .await?;
+                data = &data[written..];
+                self.progress.add(written as u64);
+            }
+            from += len as u64;
+            remaining -= len as u64;
+        }
+        Ok(())
+    }
+}
+
+/// A file being downloaded to its partial download path.
+struct Download<'a> {
+    meta: &'a FileMeta,
+    file: std::fs::File,
+    tmp_path: PathBuf,
+    /// Verifies the file as it's downloaded, if the peer offered a hash
+    hasher: Option<blake3::Hasher>,
+    /// Number of bytes of the file written so far
+    written: u64,
+    /// Number of bytes of the file that were synced to disk,
+    /// as last recorded in its info file
+    synced: u64,
+    /// The old version of the file, if it's sent as a delta
+    basis: Option<std::fs::File>,
+}
+
+impl<'a> Download<'a> {
+    /// Starts downloading `meta` into `save_path`,
+    /// resuming its partial download if `start` isn't 0.
+    ///
+    /// If `delta` is set, the file is sent as a delta
+    /// against the old version at its save path.
+    fn start(meta: &'a FileMeta, start: u64, save_path: &Path, delta: bool) -> Result<Self, Error> {
+        let basis = if delta {
+            Some(std::fs::File::open(meta.get_save_path(save_path))?)
+        } else {
+            None
+        };
 
         // get the partial download path
-        let tmp_path = offer.get_partial_download_path(save_path)?;
+        let tmp_path = meta.get_partial_download_path(save_path)?;
 
         // download whole file
         if start == 0 {
-            // create a directory and TMP file
+            // create a directory for the TMP file
             if let Some(parent) = tmp_path.parent() {
                 std::fs::create_dir_all(parent)?;
             }
-            let mut file = std::fs::File::create(&tmp_path)?;
-
-            // copy from the reader into the file
-            net_to_file(&mut reader, &mut file, offer.len).await?;
+        }
+        // not truncated until it's locked,
+        // in case another transfer is writing it
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(start == 0)
+            .truncate(false)
+            .open(&tmp_path)?;
+        lock_partial_download(&file, &tmp_path)?;
 
         // resume interrupted download
+        let len = file.metadata()?.len();
+        if len < start {
+            return Err(Error::UnexpectedFileLen);
+        }
+        // anything after `start` may be garbage left by a crash
+        if len != start {
+            file.set_len(start)?;
+        }
+
+        // verify the file as it's downloaded, starting with any resumed prefix.
+        // read through the locked handle, since on some platforms
+        // locks also keep other handles from reading.
+        let hasher = if meta.hash.is_some() {
+            let mut hasher = blake3::Hasher::new();
+            if start != 0 {
+                file.seek(SeekFrom::Start(0))?;
+                hasher.update_reader((&file).take(start))?;
+            }
+            Some(hasher)
         } else {
-            // open the partially downloaded file in append mode
-            let mut file = std::fs::OpenOptions::new().append(true).open(&tmp_path)?;
-            if file.metadata()?.len() != start {
-                return Err(Error::UnexpectedFileLen);
+            None
+        };
+        file.seek(SeekFrom::Start(start))?;
+
+        // describe the partial download, in case it's interrupted.
+        // also upgrades partial downloads from before info files existed.
+        TmpInfoFile::new(meta, start).write(&meta.get_partial_info_path(save_path)?)?;
+
+        Ok(Self {
+            meta,
+            file,
+            tmp_path,
+            hasher,
+            written: start,
+            synced: start,
+            basis,
+        })
+    }
+
+    /// Writes some of `data` to the file, and returns how many bytes were written.
+    ///
+    /// If the disk is full, does what `on_storage_full` says,
+    /// and returns 0 unless it says to abort.
+    async fn write<P: Future<Output = StorageFullAction>>(
+        &mut self,
+        data: &[u8],
+        save_path: &mut PathBuf,
+        on_storage_full: &mut impl FnMut(&Path) -> P,
+    ) -> Result<usize, Error> {
+        let err = match self.file.write(data) {
+            Ok(written) => {
+                if let Some(hasher) = &mut self.hasher {
+                    hasher.update(&data[0..written]);
+                }
+                self.written += written as u64;
+                return Ok(written);
             }
+            Err(err) if err.kind() == ErrorKind::StorageFull => err,
+            Err(err) => return Err(err.into()),
+        };
 
-            net_to_file(&mut reader, &mut file, offer.len - start).await?;
+        match on_storage_full(save_path).await {
+            StorageFullAction::Retry => (),
+            StorageFullAction::MoveTo(new_save_path) => self.move_to(save_path, new_save_path)?,
+            StorageFullAction::Abort => return Err(err.into()),
         }
-        reader.progress.processed_files += 1;
-        std::fs::rename(tmp_path, offer.get_unoccupied_save_path(save_path)?)?;
+        Ok(0)
     }
 
-    Ok(())
-}
+    /// Moves the partial download from `save_path` into `new_save_path`,
+    /// and changes `save_path` to it.
+    fn move_to(&mut self, save_path: &mut PathBuf, new_save_path: PathBuf) -> Result<(), Error> {
+        let new_tmp_path = self.meta.get_partial_download_path(&new_save_path)?;
+        move_file(&self.tmp_path, &new_tmp_path)?;
+        // the moved file may be the same one, which the old handle still locks
+        self.file.unlock()?;
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&new_tmp_path)?;
+        lock_partial_download(&file, &new_tmp_path)?;
+        self.file = file;
+        self.file.seek(SeekFrom::Start(self.written))?;
 
-/// We're using this instead of [`tokio::io::copy()`].
-///
-/// [`tokio::io::copy()`] spawns a task on a thread
-/// during every file read/write. This occurs 1000s of times,
-/// introducing unnecessary overhead.
-///
-/// This function is similar, but uses standard blocking
-/// reads from `src`. This is made on the assumption that each read
-/// won't block everything for too long, so this
-/// function should still be cancellable.
-async fn file_to_net(
-    mut src: impl std::io::Read,
-    mut dst: impl tokio::io::AsyncWrite + Unpin,
-    mut amt: u64,
-    buf: &mut [u8],
-) -> std::io::Result<()> {
-    while amt > 0 {
-        let to_read = std::cmp::min(amt, buf.len() as u64) as usize;
-        let bytes_read = src.read(&mut buf[0..to_read])?;
-        if bytes_read == 0 {
-            return Err(std::io::Error::new(
-                ErrorKind::UnexpectedEof,
-                "Peer interrupted transfer.",
-            ));
-        }
-        amt -= bytes_read as u64;
-        dst.write_all(&buf[0..to_read]).await?;
+        TmpInfoFile::new(self.meta, self.synced)
+            .write(&self.meta.get_partial_info_path(&new_save_path)?)?;
+        remove_if_exists(&self.meta.get_partial_info_path(save_path)?)?;
+        self.tmp_path = new_tmp_path;
+        *save_path = new_save_path;
+        Ok(())
     }
-    Ok(())
-}
 
-/// We're using this instead of [`tokio::io::copy_buf()`].
-///
-/// [`tokio::io::copy_buf()`] spawns a task on a thread
-/// during every file read/write. This occurs 1000s of times,
-/// introducing unnecessary overhead.
-///
-/// This function is similar, but uses standard blocking
-/// writes to `dst`. This is made on the assumption that each write
-/// won't block everything for too long, so this
-/// function should still be cancellable.
-async fn net_to_file(
-    mut src: impl tokio::io::AsyncBufRead + Unpin,
-    mut dst: impl std::io::Write,
-    mut amt: u64,
-) -> std::io::Result<()> {
-    while amt > 0 {
-        let buf = src.fill_buf().await?;
-        if buf.is_empty() {
-            return Err(std::io::Error::new(
-                ErrorKind::UnexpectedEof,
-                "Peer interrupted transfer.",
-            ));
+    /// Fills `buf` with the bytes at `offset` in the old version of the file.
+    ///
+    /// Returns [`Error::InvalidChunk`] if the file isn't sent as a delta,
+    /// or the old version is too short.
+    fn read_basis(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), Error> {
+        let Some(basis) = &mut self.basis else {
+            return Err(Error::InvalidChunk);
+        };
+        basis.seek(SeekFrom::Start(offset))?;
+        basis.read_exact(buf).map_err(|err| match err.kind() {
+            ErrorKind::UnexpectedEof => Error::InvalidChunk,
+            _ => err.into(),
+        })
+    }
+
+    /// Syncs the file to disk, and records in its info file
+    /// that everything written so far can be resumed.
+    fn checkpoint(&mut self, save_path: &Path) -> Result<(), Error> {
+        self.file.sync_data()?;
+        TmpInfoFile::new(self.meta, self.written)
+            .write(&self.meta.get_partial_info_path(save_path)?)?;
+        self.synced = self.written;
+        Ok(())
+    }
+
+    /// Verifies the downloaded file, moves it to its place in `save_path`,
+    /// and returns where it was saved.
+    ///
+    /// A file sent as a delta replaces its old version.
+    fn finish(
+        self,
+        save_path: &Path,
+        preserve_metadata: bool,
+        overwrite: OverwritePolicy,
+    ) -> Result<PathBuf, Error> {
+        drop(self.file);
+        let replace = self.basis.is_some();
+        drop(self.basis);
+        let info_path = self.meta.get_partial_info_path(save_path)?;
+
+        if let (Some(hasher), Some(expected)) = (self.hasher, self.meta.hash) {
+            if *hasher.finalize().as_bytes() != expected {
+                std::fs::remove_file(&self.tmp_path)?;
+                remove_if_exists(&info_path)?;
+                return Err(Error::ChecksumMismatch(self.meta.short_path.clone()));
+            }
         }
-        let to_write = std::cmp::min(amt, buf.len() as u64) as usize;
-        let written = dst.write(&buf[0..to_write])?;
-        src.consume(written);
-        amt -= written as u64;
+
+        let final_path = if replace {
+            self.meta.get_save_path(save_path)
+        } else {
+            // the file may have appeared during the transfer
+            self.meta
+                .get_save_path_with_policy(save_path, overwrite)?
+                .ok_or_else(|| Error::FileExists(self.meta.get_save_path(save_path)))?
+        };
+        std::fs::rename(&self.tmp_path, &final_path)?;
+        remove_if_exists(&info_path)?;
+        if preserve_metadata {
+            restore_metadata(self.meta, &final_path)?;
+        }
+        Ok(final_path)
     }
-    Ok(())
 }
 
-/// Wraps an IO stream. Calls `progress_callback` on each
-/// read/write to report progress.
-#[pin_project::pin_project]
-struct ProgressWrapper<T, F: FnMut(&TransferReport)> {
-    /// The callback function called to report progress
+/// Reports the progress of a transfer to a callback.
+pub(crate) struct Progress<F> {
+    pub report: TransferReport,
+    speedometer: Speedometer,
     progress_callback: F,
-
-    /// The inner IO stream
-    #[pin]
-    inner_io: T,
-
-    /// The current progress of the file transfer.
-    progress: TransferReport,
 }
 
-impl<T, F: FnMut(&TransferReport)> ProgressWrapper<T, F> {
-    fn new(inner_io: T, total_bytes: u64, total_files: u64, progress_callback: F) -> Self {
+impl<F: FnMut(&TransferReport)> Progress<F> {
+    pub fn new(total_bytes: u64, total_files: u64, progress_callback: F) -> Self {
         Self {
+            report: TransferReport::new(total_bytes, total_files),
+            speedometer: Speedometer::new(),
             progress_callback,
-            inner_io,
-            progress: TransferReport {
-                processed_bytes: 0,
-                total_bytes,
-                processed_files: 0,
-                total_files,
-                current_file: "".into(),
-            },
         }
     }
+
+    /// Adds `bytes` to the processed bytes of the transfer
+    /// and the current file, and reports progress.
+    pub fn add(&mut self, bytes: u64) {
+        self.report.processed_bytes += bytes;
+        self.report.current_file_processed += bytes;
+        self.speedometer.update(&mut self.report);
+        (self.progress_callback)(&self.report);
+    }
+
+    /// Counts one more file as processed, and reports progress.
+    pub fn finish_file(&mut self) {
+        self.report.processed_files += 1;
+        (self.progress_callback)(&self.report);
+    }
 }
 
-impl<T: AsyncWrite, F: FnMut(&TransferReport)> AsyncWrite for ProgressWrapper<T, F> {
-    fn poll_write(
-        self: Pin<&mut Self>,
-        cx: &mut Context<'_>,
-        buf: &[u8],
-    ) -> Poll<Result<usize, std::io::Error>> {
-        let me = self.project();
-        let amt = ready!(me.inner_io.poll_write(cx, buf))?;
-        me.progress.processed_bytes += amt as u64;
-        (me.progress_callback)(me.progress);
-        Poll::Ready(Ok(amt))
+/// Gives the file at `path` the permissions and modification time in `meta`.
+///
+/// Only the read, write, and execute bits are restored,
+/// so a peer can't create setuid files.
+pub(crate) fn restore_metadata(meta: &FileMeta, path: &Path) -> std::io::Result<()> {
+    // before the permissions, which may remove write access
+    if let Some(modified) = meta.modified {
+        std::fs::File::options()
+            .write(true)
+            .open(path)?
+            .set_modified(modified)?;
     }
 
-    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), std::io::Error>> {
-        self.project().inner_io.poll_flush(cx)
+    #[cfg(unix)]
+    if let Some(mode) = meta.mode {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode & 0o777))?;
     }
 
-    fn poll_shutdown(
-        self: Pin<&mut Self>,
-        cx: &mut Context<'_>,
-    ) -> Poll<Result<(), std::io::Error>> {
-        self.project().inner_io.poll_shutdown(cx)
+    Ok(())
+}
+
+/// Locks the partial download `file` at `path` until it's closed,
+/// so that another transfer into the same directory can't write it.
+///
+/// Returns [`Error::PartialDownloadInUse`] if another transfer holds the lock.
+fn lock_partial_download(file: &std::fs::File, path: &Path) -> Result<(), Error> {
+    match file.try_lock() {
+        Ok(()) => Ok(()),
+        Err(std::fs::TryLockError::WouldBlock) => {
+            Err(Error::PartialDownloadInUse(path.to_path_buf()))
+        }
+        Err(std::fs::TryLockError::Error(err)) => Err(err.into()),
     }
 }
 
-impl<T: AsyncRead, F: FnMut(&TransferReport)> AsyncRead for ProgressWrapper<T, F> {
-    fn poll_read(
-        self: Pin<&mut Self>,
-        cx: &mut Context<'_>,
-        buf: &mut tokio::io::ReadBuf<'_>,
-    ) -> Poll<std::io::Result<()>> {
-        let me = self.project();
-        let filled = buf.filled().len();
-        ready!(me.inner_io.poll_read(cx, buf))?;
-        me.progress.processed_bytes += (buf.filled().len() - filled) as u64;
-        (me.progress_callback)(me.progress);
-        Poll::Ready(Ok(()))
+/// Removes the file at `path`, if there is one.
+fn remove_if_exists(path: &Path) -> std::io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(err) if err.kind() != ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
     }
 }
 
-impl<T: AsyncBufRead, F: FnMut(&TransferReport)> AsyncBufRead for ProgressWrapper<T, F> {
-    fn consume(self: Pin<&mut Self>, amt: usize) {
-        let me = self.project();
-        me.inner_io.consume(amt);
-        me.progress.processed_bytes += amt as u64;
-        (me.progress_callback)(me.progress);
+/// Moves the file at `from` to `to`, creating
+/// any missing parent directories of `to`.
+///
+/// Works even if `from` and `to` are on different file systems.
+fn move_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)?;
     }
 
-    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<&[u8]>> {
-        self.project().inner_io.poll_fill_buf(cx)
+    // renaming fails across file systems
+    if std::fs::rename(from, to).is_err() {
+        std::fs::copy(from, to)?;
+        std::fs::remove_file(from)?;
     }
+    Ok(())
 }
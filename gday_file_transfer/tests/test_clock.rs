@@ -0,0 +1,86 @@
+#![forbid(unsafe_code)]
+#![warn(clippy::all)]
+use gday_file_transfer::{estimate_clock_skew, ClockSkew, FileMeta, FileOfferMsg};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+/// Confirm that both peers of [`estimate_clock_skew()`]
+/// get matching estimates.
+#[tokio::test]
+async fn test_clock_skew() {
+    let (mut stream_a, mut stream_b) = tokio::io::duplex(1000);
+
+    let (skew_a, skew_b) = tokio::join!(
+        estimate_clock_skew(&mut stream_a, true),
+        estimate_clock_skew(&mut stream_b, false)
+    );
+    let skew_a = skew_a.unwrap();
+    let skew_b = skew_b.unwrap();
+
+    // both peers share the same clock here
+    assert!(skew_a.is_within(Duration::from_secs(1)));
+    assert_eq!(skew_a.offset_millis, -skew_b.offset_millis);
+    assert_eq!(skew_a.round_trip_millis, skew_b.round_trip_millis);
+}
+
+/// Confirm that [`ClockSkew`] converts timestamps between clocks.
+#[test]
+fn test_clock_skew_conversion() {
+    let skew = ClockSkew {
+        offset_millis: -5000,
+        round_trip_millis: 100,
+    };
+    let local = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+    let peer = local - Duration::from_secs(5);
+
+    assert_eq!(skew.local_to_peer(local), Some(peer));
+    assert_eq!(skew.peer_to_local(peer), Some(local));
+
+    assert!(skew.is_within(Duration::from_millis(5050)));
+    assert!(!skew.is_within(Duration::from_millis(5049)));
+}
+
+/// Confirm that an extreme offset from a malicious peer
+/// doesn't panic when converting timestamps.
+#[test]
+fn test_clock_skew_overflow() {
+    let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+    for offset_millis in [i64::MAX, i64::MIN, -i64::MAX] {
+        let skew = ClockSkew {
+            offset_millis,
+            round_trip_millis: u64::MAX,
+        };
+        // may not be representable, but mustn't panic
+        let _ = skew.peer_to_local(time);
+        let _ = skew.local_to_peer(time);
+        assert!(!skew.is_within(Duration::from_secs(1)));
+    }
+
+    let skew = ClockSkew {
+        offset_millis: i64::MIN,
+        round_trip_millis: 0,
+    };
+    assert_eq!(skew.peer_to_local(time), None);
+
+    // the modification time is dropped
+    let mut offer = FileOfferMsg {
+        files: vec![FileMeta {
+            short_path: PathBuf::from("file.txt"),
+            len: 1,
+            hash: None,
+            head_hash: None,
+            compressible: false,
+            mode: None,
+            modified: Some(time),
+        }],
+        compression: None,
+        symlinks: Vec::new(),
+        empty_dirs: Vec::new(),
+        archive: false,
+        streams: None,
+        duplicates: BTreeMap::new(),
+    };
+    offer.adjust_for_clock_skew(&skew);
+    assert_eq!(offer.files[0].modified, None);
+}
@@ -0,0 +1,110 @@
+#![forbid(unsafe_code)]
+#![warn(clippy::all)]
+use gday_file_transfer::{
+    exchange_files, get_file_metas, Direction, FileOfferMsg, FileResponseMsg,
+};
+use std::fs;
+use tokio::io::BufReader;
+
+/// Confirm that both peers can send and receive files in one session,
+/// and that a peer may offer nothing.
+#[tokio::test]
+async fn test_exchange_files() {
+    let dir_a = tempfile::tempdir().unwrap();
+    let dir_b = tempfile::tempdir().unwrap();
+    fs::write(dir_a.path().join("from_a.txt"), "Peer A's file").unwrap();
+    fs::write(
+        dir_b.path().join("from_b.txt"),
+        "Peer B's file".repeat(1000),
+    )
+    .unwrap();
+    fs::write(dir_b.path().join("unwanted.txt"), "Rejected").unwrap();
+
+    let files_a = get_file_metas(&[dir_a.path().join("from_a.txt")]).unwrap();
+    let files_b = get_file_metas(&[
+        dir_b.path().join("from_b.txt"),
+        dir_b.path().join("unwanted.txt"),
+    ])
+    .unwrap();
+    let offer_a = FileOfferMsg::from(files_a.clone());
+    let offer_b = FileOfferMsg::from(files_b.clone());
+
+    let save_a = tempfile::tempdir().unwrap();
+    let save_b = tempfile::tempdir().unwrap();
+    let (stream_a, stream_b) = tokio::io::duplex(1000);
+    let mut stream_a = BufReader::new(stream_a);
+    let mut stream_b = BufReader::new(stream_b);
+
+    let mut directions_a = Vec::new();
+    let (summary_a, summary_b) = tokio::join!(
+        exchange_files(
+            &mut stream_a,
+            true,
+            &files_a,
+            &offer_a,
+            save_a.path(),
+            |offer| {
+                let mut response = FileResponseMsg::accept_all_files(offer);
+                response.response[1] = None;
+                Ok(response)
+            },
+            |direction, _| directions_a.push(direction),
+        ),
+        exchange_files(
+            &mut stream_b,
+            false,
+            &files_b,
+            &offer_b,
+            save_b.path(),
+            |offer| Ok(FileResponseMsg::accept_all_files(offer)),
+            |_, _| (),
+        )
+    );
+    let summary_a = summary_a.unwrap();
+    let summary_b = summary_b.unwrap();
+
+    assert_eq!(summary_a.peer_offer, offer_b);
+    assert_eq!(summary_a.response, summary_b.peer_response);
+    assert_eq!(summary_b.response, summary_a.peer_response);
+
+    // the leader's files are sent first
+    assert_eq!(directions_a.first(), Some(&Direction::Sending));
+    assert_eq!(directions_a.last(), Some(&Direction::Receiving));
+
+    assert_eq!(
+        fs::read(save_a.path().join("from_b.txt")).unwrap(),
+        fs::read(dir_b.path().join("from_b.txt")).unwrap()
+    );
+    assert!(!save_a.path().join("unwanted.txt").exists());
+    assert_eq!(
+        fs::read(save_b.path().join("from_a.txt")).unwrap(),
+        b"Peer A's file"
+    );
+
+    // peer B has nothing to offer
+    let save_b = tempfile::tempdir().unwrap();
+    let empty_offer = FileOfferMsg::from(Vec::new());
+    let (summary_a, summary_b) = tokio::join!(
+        exchange_files(
+            &mut stream_a,
+            true,
+            &files_a,
+            &offer_a,
+            save_a.path(),
+            |offer| Ok(FileResponseMsg::accept_all_files(offer)),
+            |_, _| (),
+        ),
+        exchange_files(
+            &mut stream_b,
+            false,
+            &[],
+            &empty_offer,
+            save_b.path(),
+            |offer| Ok(FileResponseMsg::accept_all_files(offer)),
+            |_, _| (),
+        )
+    );
+    assert!(summary_a.unwrap().peer_offer.files.is_empty());
+    assert_eq!(summary_b.unwrap().peer_offer, offer_a);
+    assert!(save_b.path().join("from_a.txt").exists());
+}
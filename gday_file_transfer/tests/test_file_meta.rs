@@ -22,6 +22,11 @@ async fn test_file_meta_1() {
     let file_meta = FileMeta {
         short_path: PathBuf::from("fol der/file.tar.gz"),
         len: 5,
+        hash: None,
+        head_hash: None,
+        compressible: true,
+        mode: None,
+        modified: None,
     };
 
     // save path is the save directory joined with the short path
@@ -72,6 +77,11 @@ async fn test_file_meta_2() {
     let file_meta = FileMeta {
         short_path: PathBuf::from("fol der/file.tar.gz"),
         len: 5,
+        hash: None,
+        head_hash: None,
+        compressible: true,
+        mode: None,
+        modified: None,
     };
 
     // save path is the save directory joined with the short path
@@ -112,6 +122,11 @@ async fn test_file_meta_empty() {
     let file_meta = FileMeta {
         short_path: PathBuf::from("fol der/file.tar.gz"),
         len: 5,
+        hash: None,
+        head_hash: None,
+        compressible: true,
+        mode: None,
+        modified: None,
     };
 
     // save path is the save directory joined with the short path
@@ -0,0 +1,57 @@
+#![forbid(unsafe_code)]
+#![warn(clippy::all)]
+use gday_file_transfer::{HumanFormat, SizeUnits};
+use std::time::Duration;
+
+/// Confirm that sizes are shown with 3 significant digits.
+#[test]
+fn test_size() {
+    let binary = HumanFormat::default();
+    assert_eq!(binary.size(0), "0 B");
+    assert_eq!(binary.size(1023), "1023 B");
+    assert_eq!(binary.size(1024), "1.00 KiB");
+    assert_eq!(binary.size(15 * 1024 + 300), "15.3 KiB");
+    assert_eq!(binary.size(153 * 1024 * 1024), "153 MiB");
+    assert_eq!(binary.size(1024 * 1024 - 1), "1.00 MiB");
+    assert_eq!(binary.size(u64::MAX), "16.0 EiB");
+
+    let decimal = HumanFormat {
+        units: SizeUnits::Decimal,
+        ..HumanFormat::default()
+    };
+    assert_eq!(decimal.size(999), "999 B");
+    assert_eq!(decimal.size(1000), "1.00 kB");
+    assert_eq!(decimal.size(999_999), "1.00 MB");
+    assert_eq!(decimal.size(2_345_678_901), "2.35 GB");
+}
+
+/// Confirm that rates and durations are formatted.
+#[test]
+fn test_rate_and_duration() {
+    let format = HumanFormat::default();
+    assert_eq!(format.rate(1_048_576.0), "1.00 MiB/s");
+    assert_eq!(format.rate(-5.0), "0 B/s");
+
+    assert_eq!(format.duration(Duration::from_millis(250)), "250 ms");
+    assert_eq!(format.duration(Duration::from_secs(42)), "42s");
+    assert_eq!(format.duration(Duration::from_secs(3 * 60 + 5)), "3m 05s");
+    assert_eq!(
+        format.duration(Duration::from_secs(3600 + 2 * 60)),
+        "1h 02m"
+    );
+    assert_eq!(
+        format.duration(Duration::from_secs(2 * 86400 + 3 * 3600)),
+        "2d 03h"
+    );
+}
+
+/// Confirm that the decimal separator is used.
+#[test]
+fn test_decimal_separator() {
+    let format = HumanFormat {
+        units: SizeUnits::Binary,
+        decimal_separator: ',',
+    };
+    assert_eq!(format.size(1536), "1,50 KiB");
+    assert_eq!(format.size(100), "100 B");
+}
@@ -0,0 +1,58 @@
+use gday_file_transfer::{Error, Glob};
+use std::path::Path;
+
+fn matches(pattern: &str, path: &str) -> bool {
+    pattern.parse::<Glob>().unwrap().matches(Path::new(path))
+}
+
+#[test]
+fn test_wildcards() {
+    assert!(matches("*.rs", "main.rs"));
+    assert!(matches("*.rs", "src/main.rs"));
+    assert!(!matches("*.rs", "main.rs.bak"));
+    assert!(!matches("*.rs", "src/main.txt"));
+
+    assert!(matches("file?.txt", "dir/file1.txt"));
+    assert!(!matches("file?.txt", "dir/file10.txt"));
+
+    // `*` and `?` don't match across directories
+    assert!(!matches("src*", "src/main.rs"));
+    assert!(!matches("a?b", "a/b"));
+}
+
+#[test]
+fn test_classes() {
+    assert!(matches("file[0-9].txt", "file7.txt"));
+    assert!(!matches("file[0-9].txt", "filex.txt"));
+    assert!(matches("file[!0-9].txt", "filex.txt"));
+    assert!(!matches("file[^0-9].txt", "file7.txt"));
+    assert!(matches("[]a]", "]"));
+    assert!(matches("[ab-]", "-"));
+}
+
+#[test]
+fn test_components() {
+    // matches the last components of the path
+    assert!(matches("target", "project/target"));
+    assert!(matches("target/", "project/target"));
+    assert!(matches("src/*.rs", "project/src/main.rs"));
+    assert!(!matches("src/*.rs", "project/src/bin/main.rs"));
+    assert!(!matches("target", "project/target/debug"));
+    assert!(!matches("arget", "project/target"));
+
+    // `**` matches any number of directories
+    assert!(matches("src/**/*.rs", "project/src/main.rs"));
+    assert!(matches("src/**/*.rs", "project/src/bin/tool/main.rs"));
+    assert!(matches("project/**", "project/src/main.rs"));
+    assert!(!matches("src/**/*.rs", "project/tests/main.rs"));
+}
+
+#[test]
+fn test_invalid() {
+    assert!(matches!("".parse::<Glob>(), Err(Error::InvalidGlob(_))));
+    assert!(matches!("/".parse::<Glob>(), Err(Error::InvalidGlob(_))));
+    assert!(matches!(
+        "file[0-9".parse::<Glob>(),
+        Err(Error::InvalidGlob(_))
+    ));
+}
@@ -2,11 +2,16 @@
 #![warn(clippy::all)]
 use gday_file_transfer::{
     get_file_metas, read_from_async, receive_files, send_files, write_to_async, FileMetaLocal,
-    FileOfferMsg, FileResponseMsg,
+    FileOfferMsg, FileResponseMsg, OverwritePolicy,
 };
+use std::collections::BTreeMap;
 use std::fs::{self, create_dir_all};
 use std::io::Write;
-use std::{fs::File, path::PathBuf};
+use std::time::{Duration, SystemTime};
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+};
 
 /// Returns a temporary directory
 /// with the following contents:
@@ -53,6 +58,38 @@ fn make_test_dir() -> tempfile::TempDir {
     temp_dir
 }
 
+/// Returns the unix permission bits of the file at `path`,
+/// like [`get_file_metas()`] does.
+fn unix_mode(path: &Path) -> Option<u32> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        Some(path.metadata().unwrap().permissions().mode() & 0o777)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        None
+    }
+}
+
+/// Returns the modification time of the file at `path`.
+fn modified(path: &Path) -> Option<SystemTime> {
+    path.metadata().unwrap().modified().ok()
+}
+
+/// Returns `data` as a single uncompressed chunk of the file
+/// at `file_index` in the offer, starting at `offset`,
+/// framed like [`send_files()`] sends it.
+fn raw_chunk(file_index: u32, offset: u64, data: &[u8]) -> Vec<u8> {
+    let mut chunk = file_index.to_be_bytes().to_vec();
+    chunk.extend_from_slice(&offset.to_be_bytes());
+    chunk.push(0);
+    chunk.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    chunk.extend_from_slice(data);
+    chunk
+}
+
 /// Confirm that [`get_file_metas()`] returns errors
 /// when it should.
 #[tokio::test]
@@ -112,26 +149,51 @@ async fn test_get_file_metas_1() {
             short_path: dir_name.join("file1"),
             local_path: dir_path.join("file1"),
             len: dir_path.join("file1").metadata().unwrap().len(),
+            hash: None,
+            head_hash: None,
+            compressible: true,
+            mode: unix_mode(&dir_path.join("file1")),
+            modified: modified(&dir_path.join("file1")),
         },
         FileMetaLocal {
             short_path: dir_name.join("file2.txt"),
             local_path: dir_path.join("file2.txt"),
             len: dir_path.join("file2.txt").metadata().unwrap().len(),
+            hash: None,
+            head_hash: None,
+            compressible: true,
+            mode: unix_mode(&dir_path.join("file2.txt")),
+            modified: modified(&dir_path.join("file2.txt")),
         },
         FileMetaLocal {
             short_path: dir_name.join("dir/file1"),
             local_path: dir_path.join("dir/file1"),
             len: dir_path.join("dir/file1").metadata().unwrap().len(),
+            hash: None,
+            head_hash: None,
+            compressible: true,
+            mode: unix_mode(&dir_path.join("dir/file1")),
+            modified: modified(&dir_path.join("dir/file1")),
         },
         FileMetaLocal {
             short_path: dir_name.join("dir/file2.txt"),
             local_path: dir_path.join("dir/file2.txt"),
             len: dir_path.join("dir/file2.txt").metadata().unwrap().len(),
+            hash: None,
+            head_hash: None,
+            compressible: true,
+            mode: unix_mode(&dir_path.join("dir/file2.txt")),
+            modified: modified(&dir_path.join("dir/file2.txt")),
         },
         FileMetaLocal {
             short_path: dir_name.join("dir/subdir1/file1"),
             local_path: dir_path.join("dir/subdir1/file1"),
             len: dir_path.join("dir/subdir1/file1").metadata().unwrap().len(),
+            hash: None,
+            head_hash: None,
+            compressible: true,
+            mode: unix_mode(&dir_path.join("dir/subdir1/file1")),
+            modified: modified(&dir_path.join("dir/subdir1/file1")),
         },
         FileMetaLocal {
             short_path: dir_name.join("dir/subdir1/file2.txt"),
@@ -141,11 +203,21 @@ async fn test_get_file_metas_1() {
                 .metadata()
                 .unwrap()
                 .len(),
+            hash: None,
+            head_hash: None,
+            compressible: true,
+            mode: unix_mode(&dir_path.join("dir/subdir1/file2.txt")),
+            modified: modified(&dir_path.join("dir/subdir1/file2.txt")),
         },
         FileMetaLocal {
             short_path: dir_name.join("dir/subdir2/file1"),
             local_path: dir_path.join("dir/subdir2/file1"),
             len: dir_path.join("dir/subdir2/file1").metadata().unwrap().len(),
+            hash: None,
+            head_hash: None,
+            compressible: true,
+            mode: unix_mode(&dir_path.join("dir/subdir2/file1")),
+            modified: modified(&dir_path.join("dir/subdir2/file1")),
         },
         FileMetaLocal {
             short_path: dir_name.join("dir/subdir2/file2.tar.gz"),
@@ -155,6 +227,11 @@ async fn test_get_file_metas_1() {
                 .metadata()
                 .unwrap()
                 .len(),
+            hash: None,
+            head_hash: None,
+            compressible: false,
+            mode: unix_mode(&dir_path.join("dir/subdir2/file2.tar.gz")),
+            modified: modified(&dir_path.join("dir/subdir2/file2.tar.gz")),
         },
     ];
 
@@ -183,6 +260,11 @@ async fn test_get_file_metas_2() {
             short_path: PathBuf::from("subdir1/file1"),
             local_path: dir_path.join("dir/subdir1/file1"),
             len: dir_path.join("dir/subdir1/file1").metadata().unwrap().len(),
+            hash: None,
+            head_hash: None,
+            compressible: true,
+            mode: unix_mode(&dir_path.join("dir/subdir1/file1")),
+            modified: modified(&dir_path.join("dir/subdir1/file1")),
         },
         FileMetaLocal {
             short_path: PathBuf::from("subdir1/file2.txt"),
@@ -192,11 +274,21 @@ async fn test_get_file_metas_2() {
                 .metadata()
                 .unwrap()
                 .len(),
+            hash: None,
+            head_hash: None,
+            compressible: true,
+            mode: unix_mode(&dir_path.join("dir/subdir1/file2.txt")),
+            modified: modified(&dir_path.join("dir/subdir1/file2.txt")),
         },
         FileMetaLocal {
             short_path: PathBuf::from("file1"),
             local_path: dir_path.join("dir/subdir2/file1"),
             len: dir_path.join("dir/subdir2/file1").metadata().unwrap().len(),
+            hash: None,
+            head_hash: None,
+            compressible: true,
+            mode: unix_mode(&dir_path.join("dir/subdir2/file1")),
+            modified: modified(&dir_path.join("dir/subdir2/file1")),
         },
         FileMetaLocal {
             short_path: PathBuf::from("file2.tar.gz"),
@@ -206,6 +298,11 @@ async fn test_get_file_metas_2() {
                 .metadata()
                 .unwrap()
                 .len(),
+            hash: None,
+            head_hash: None,
+            compressible: false,
+            mode: unix_mode(&dir_path.join("dir/subdir2/file2.tar.gz")),
+            modified: modified(&dir_path.join("dir/subdir2/file2.tar.gz")),
         },
     ];
 
@@ -330,3 +427,1559 @@ async fn file_transfer() {
     assert!(fs::read(dir_b_path.join("dir/subdir2/file1")).is_err());
     assert!(fs::read(dir_b_path.join("dir/subdir2/file2.txt")).is_err());
 }
+
+/// Confirm that [`gday_file_transfer::receive_files_with_recovery()`]
+/// calls its handler when the disk is full, and obeys its choice.
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_storage_full() {
+    use gday_file_transfer::{FileMeta, StorageFullAction};
+
+    let save_dir = tempfile::tempdir().unwrap();
+
+    let offer = FileOfferMsg {
+        files: vec![FileMeta {
+            short_path: PathBuf::from("file.txt"),
+            len: 5,
+            hash: None,
+            head_hash: None,
+            compressible: true,
+            mode: None,
+            modified: None,
+        }],
+        compression: None,
+        symlinks: Vec::new(),
+        empty_dirs: Vec::new(),
+        archive: false,
+        streams: None,
+        duplicates: BTreeMap::new(),
+    };
+    let response = FileResponseMsg::accept_all_files(&offer);
+
+    // writing to /dev/full always fails with `StorageFull`
+    let tmp_path = offer.files[0]
+        .get_partial_download_path(save_dir.path())
+        .unwrap();
+    std::os::unix::fs::symlink("/dev/full", &tmp_path).unwrap();
+
+    let mut calls = 0;
+    let result = gday_file_transfer::receive_files_with_recovery(
+        &offer,
+        &response,
+        save_dir.path(),
+        &raw_chunk(0, 0, b"hello")[..],
+        |_| (),
+        |path| {
+            assert_eq!(path, save_dir.path());
+            calls += 1;
+            std::future::ready(if calls == 1 {
+                StorageFullAction::Retry
+            } else {
+                StorageFullAction::Abort
+            })
+        },
+    )
+    .await;
+
+    assert_eq!(calls, 2);
+    let Err(gday_file_transfer::Error::IO(err)) = result else {
+        panic!("Expected an IO error.");
+    };
+    assert_eq!(err.kind(), std::io::ErrorKind::StorageFull);
+}
+
+/// Confirm that when the disk is full, [`gday_file_transfer::receive_files_with_recovery()`]
+/// can move the partial download elsewhere, where it stays locked against other transfers.
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_storage_full_move() {
+    use gday_file_transfer::{FileMeta, StorageFullAction};
+
+    let save_dir = tempfile::tempdir().unwrap();
+    let new_dir = tempfile::tempdir().unwrap();
+
+    let offer = FileOfferMsg {
+        files: vec![FileMeta {
+            short_path: PathBuf::from("file.txt"),
+            len: 5,
+            hash: None,
+            head_hash: None,
+            compressible: true,
+            mode: None,
+            modified: None,
+        }],
+        compression: None,
+        symlinks: Vec::new(),
+        empty_dirs: Vec::new(),
+        archive: false,
+        streams: None,
+        duplicates: BTreeMap::new(),
+    };
+    let response = FileResponseMsg::accept_all_files(&offer);
+
+    // writing to /dev/full always fails with `StorageFull`
+    let tmp_path = offer.files[0]
+        .get_partial_download_path(save_dir.path())
+        .unwrap();
+    std::os::unix::fs::symlink("/dev/full", &tmp_path).unwrap();
+    let new_tmp_path = offer.files[0]
+        .get_partial_download_path(new_dir.path())
+        .unwrap();
+
+    let mut locked = false;
+    gday_file_transfer::receive_files_with_recovery(
+        &offer,
+        &response,
+        save_dir.path(),
+        &raw_chunk(0, 0, b"hello")[..],
+        |report| {
+            // another transfer can't take over the moved partial download
+            if report.processed_bytes != 0 && new_tmp_path.exists() {
+                let other = File::open(&new_tmp_path).unwrap();
+                locked = matches!(other.try_lock(), Err(std::fs::TryLockError::WouldBlock));
+            }
+        },
+        |_| {
+            // what's moved is an empty file, rather than the full device
+            fs::remove_file(&tmp_path).unwrap();
+            File::create(&tmp_path).unwrap();
+            std::future::ready(StorageFullAction::MoveTo(new_dir.path().to_path_buf()))
+        },
+    )
+    .await
+    .unwrap();
+
+    assert!(locked);
+    assert_eq!(
+        fs::read_to_string(new_dir.path().join("file.txt")).unwrap(),
+        "hello"
+    );
+    assert!(!save_dir.path().join("file.txt").exists());
+}
+
+/// Confirm that received files are verified
+/// against the hash offered by the sender.
+#[tokio::test]
+async fn test_checksum() {
+    use gday_file_transfer::FileMeta;
+
+    let save_dir = tempfile::tempdir().unwrap();
+    let hash = *blake3::hash(b"hello").as_bytes();
+
+    let offer = FileOfferMsg {
+        files: vec![FileMeta {
+            short_path: PathBuf::from("file.txt"),
+            len: 5,
+            hash: Some(hash),
+            head_hash: None,
+            compressible: true,
+            mode: None,
+            modified: None,
+        }],
+        compression: None,
+        symlinks: Vec::new(),
+        empty_dirs: Vec::new(),
+        archive: false,
+        streams: None,
+        duplicates: BTreeMap::new(),
+    };
+    let response = FileResponseMsg::accept_all_files(&offer);
+
+    // matching contents are saved
+    receive_files(
+        &offer,
+        &response,
+        save_dir.path(),
+        &raw_chunk(0, 0, b"hello")[..],
+        |_| (),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        fs::read(save_dir.path().join("file.txt")).unwrap(),
+        b"hello"
+    );
+
+    // corrupted contents are rejected and deleted
+    let chunk = raw_chunk(0, 0, b"hellO");
+    let result = receive_files(&offer, &response, save_dir.path(), &chunk[..], |_| ()).await;
+    let Err(gday_file_transfer::Error::ChecksumMismatch(path)) = result else {
+        panic!("Expected a checksum mismatch.");
+    };
+    assert_eq!(path, PathBuf::from("file.txt"));
+    let tmp_path = offer.files[0]
+        .get_partial_download_path(save_dir.path())
+        .unwrap();
+    assert!(!tmp_path.exists());
+    assert!(!save_dir.path().join("file (1).txt").exists());
+
+    // the already downloaded prefix of a resumed file is verified too
+    fs::write(&tmp_path, b"jel").unwrap();
+    let response = FileResponseMsg {
+        response: vec![Some(3)],
+        compression: None,
+        preserve_metadata: false,
+        deltas: BTreeMap::new(),
+        write_manifest: false,
+        overwrite: OverwritePolicy::default(),
+        archive: false,
+        streams: None,
+        copies: BTreeMap::new(),
+    };
+    let chunk = raw_chunk(0, 3, b"lo");
+    let result = receive_files(&offer, &response, save_dir.path(), &chunk[..], |_| ()).await;
+    assert!(matches!(
+        result,
+        Err(gday_file_transfer::Error::ChecksumMismatch(_))
+    ));
+
+    fs::write(&tmp_path, b"hel").unwrap();
+    receive_files(&offer, &response, save_dir.path(), &chunk[..], |_| ())
+        .await
+        .unwrap();
+    assert_eq!(
+        fs::read(save_dir.path().join("file (1).txt")).unwrap(),
+        b"hello"
+    );
+}
+
+/// Confirm that [`FileMetaLocal::compute_hash()`] hashes the file's contents.
+#[test]
+fn test_compute_hash() {
+    let test_dir = make_test_dir();
+    let mut files = get_file_metas(&[test_dir.path().join("file1")]).unwrap();
+    assert_eq!(files[0].hash, None);
+
+    files[0].compute_hash().unwrap();
+    assert_eq!(
+        files[0].hash,
+        Some(*blake3::hash(b"This is file1").as_bytes())
+    );
+
+    // the hash is offered to the peer
+    let offer = FileOfferMsg::from(files);
+    assert!(offer.files[0].hash.is_some());
+}
+
+/// Confirm that interrupted downloads are only resumed
+/// if their info file describes the offered file.
+#[tokio::test]
+async fn test_partial_download_info() {
+    use gday_file_transfer::FileMeta;
+
+    let save_dir = tempfile::tempdir().unwrap();
+    let file = FileMeta {
+        short_path: PathBuf::from("file.txt"),
+        len: 5,
+        hash: Some(*blake3::hash(b"hello").as_bytes()),
+        head_hash: None,
+        compressible: true,
+        mode: None,
+        modified: None,
+    };
+    let offer = FileOfferMsg {
+        files: vec![file.clone()],
+        compression: None,
+        symlinks: Vec::new(),
+        empty_dirs: Vec::new(),
+        archive: false,
+        streams: None,
+        duplicates: BTreeMap::new(),
+    };
+    let response = FileResponseMsg::accept_all_files(&offer);
+    let info_path = file.get_partial_info_path(save_dir.path()).unwrap();
+    assert_eq!(info_path, save_dir.path().join("file.txt.part5.info"),);
+
+    // interrupt the download
+    // the chunk is cut off after 3 bytes
+    let chunk = raw_chunk(0, 0, b"hello");
+    let result = receive_files(&offer, &response, save_dir.path(), &chunk[..20], |_| ()).await;
+    assert!(result.is_err());
+    assert!(info_path.exists());
+    assert_eq!(
+        file.partial_download_exists(save_dir.path()).unwrap(),
+        Some(3)
+    );
+
+    // a different file with the same name and length isn't resumed
+    let other = FileMeta {
+        hash: Some(*blake3::hash(b"world").as_bytes()),
+        ..file.clone()
+    };
+    assert_eq!(
+        other.partial_download_exists(save_dir.path()).unwrap(),
+        None
+    );
+
+    // neither is a download with a corrupted info file
+    let info = fs::read(&info_path).unwrap();
+    fs::write(&info_path, &info[..info.len() - 1]).unwrap();
+    assert_eq!(file.partial_download_exists(save_dir.path()).unwrap(), None);
+
+    // partial downloads from before info files existed are resumed
+    fs::remove_file(&info_path).unwrap();
+    assert_eq!(
+        file.partial_download_exists(save_dir.path()).unwrap(),
+        Some(3)
+    );
+
+    // finishing the download removes the info file
+    let response = FileResponseMsg {
+        response: vec![Some(3)],
+        compression: None,
+        preserve_metadata: false,
+        deltas: BTreeMap::new(),
+        write_manifest: false,
+        overwrite: OverwritePolicy::default(),
+        archive: false,
+        streams: None,
+        copies: BTreeMap::new(),
+    };
+    receive_files(
+        &offer,
+        &response,
+        save_dir.path(),
+        &raw_chunk(0, 3, b"lo")[..],
+        |_| (),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        fs::read(save_dir.path().join("file.txt")).unwrap(),
+        b"hello"
+    );
+    assert!(!info_path.exists());
+}
+
+/// Confirm that after a crash, a partial download is only resumed
+/// from the bytes its info file says were written,
+/// and that chunks for the wrong file or offset are rejected.
+#[tokio::test]
+async fn test_chunk_resume() {
+    use gday_file_transfer::{Error, FileMeta};
+
+    let save_dir = tempfile::tempdir().unwrap();
+    let file = FileMeta {
+        short_path: PathBuf::from("file.txt"),
+        len: 10,
+        hash: Some(*blake3::hash(b"0123456789").as_bytes()),
+        head_hash: None,
+        compressible: true,
+        mode: None,
+        modified: None,
+    };
+    let offer = FileOfferMsg {
+        files: vec![file.clone()],
+        compression: None,
+        symlinks: Vec::new(),
+        empty_dirs: Vec::new(),
+        archive: false,
+        streams: None,
+        duplicates: BTreeMap::new(),
+    };
+    let response = FileResponseMsg::accept_all_files(&offer);
+
+    // interrupted after 4 bytes
+    let mut stream = raw_chunk(0, 0, b"0123");
+    stream.extend_from_slice(&raw_chunk(0, 4, b"456789")[..19]);
+    let result = receive_files(&offer, &response, save_dir.path(), &stream[..], |_| ()).await;
+    assert!(matches!(result, Err(Error::IO(_))));
+
+    // garbage that a crash left at the end of the partial download
+    let tmp_path = file.get_partial_download_path(save_dir.path()).unwrap();
+    assert_eq!(fs::read(&tmp_path).unwrap(), b"012345");
+    fs::write(&tmp_path, b"012345\0\0").unwrap();
+    assert_eq!(
+        file.partial_download_exists(save_dir.path()).unwrap(),
+        Some(6)
+    );
+
+    let response =
+        FileResponseMsg::accept_only_new_and_interrupted(&offer, save_dir.path()).unwrap();
+    assert_eq!(response.response, [Some(6)]);
+
+    // a chunk at the wrong offset or for a different file
+    for chunk in [raw_chunk(0, 5, b"56789"), raw_chunk(1, 6, b"6789")] {
+        let result = receive_files(&offer, &response, save_dir.path(), &chunk[..], |_| ()).await;
+        assert!(matches!(result, Err(Error::InvalidChunk)));
+    }
+
+    // a chunk past the end of the file
+    let chunk = raw_chunk(0, 6, b"6789A");
+    let result = receive_files(&offer, &response, save_dir.path(), &chunk[..], |_| ()).await;
+    assert!(matches!(result, Err(Error::InvalidChunk)));
+
+    let chunk = raw_chunk(0, 6, b"6789");
+    receive_files(&offer, &response, save_dir.path(), &chunk[..], |_| ())
+        .await
+        .unwrap();
+    assert_eq!(
+        fs::read(save_dir.path().join("file.txt")).unwrap(),
+        b"0123456789"
+    );
+}
+
+/// Confirm that files are compressed when both peers agree,
+/// except for those that are already compressed.
+#[tokio::test]
+async fn test_compression() {
+    use gday_file_transfer::Compression;
+
+    let send_dir = tempfile::tempdir().unwrap();
+    let text = "All work and no play makes Jack a dull boy.\n".repeat(10_000);
+    fs::write(send_dir.path().join("text.txt"), &text).unwrap();
+    let archive: Vec<u8> = (0..50_000_u32).map(|i| (i * 7919 % 251) as u8).collect();
+    fs::write(send_dir.path().join("archive.zip"), &archive).unwrap();
+
+    let local_files = get_file_metas(&[
+        send_dir.path().join("text.txt"),
+        send_dir.path().join("archive.zip"),
+    ])
+    .unwrap();
+    assert!(local_files[0].compressible);
+    assert!(!local_files[1].compressible);
+
+    let mut offer = FileOfferMsg::from(local_files.clone());
+    offer.compression = Some(Compression::Zstd { level: 3 });
+    let response = FileResponseMsg::accept_all_files(&offer);
+    assert_eq!(response.compression, offer.compression);
+
+    // send into a buffer, to measure what goes over the network
+    let mut sent = Vec::new();
+    let mut last_sent_report = None;
+    send_files(&local_files, &response, &mut sent, |report| {
+        last_sent_report = Some(report.clone())
+    })
+    .await
+    .unwrap();
+    let total_len = (text.len() + archive.len()) as u64;
+    assert!((sent.len() as u64) < total_len / 2);
+    assert!((sent.len() as u64) > archive.len() as u64);
+    assert_eq!(last_sent_report.unwrap().processed_bytes, total_len);
+
+    // followed by unrelated data, which shouldn't be read
+    sent.extend_from_slice(b"rest");
+    let mut reader = &sent[..];
+
+    let save_dir = tempfile::tempdir().unwrap();
+    let mut last_received_report = None;
+    receive_files(&offer, &response, save_dir.path(), &mut reader, |report| {
+        last_received_report = Some(report.clone())
+    })
+    .await
+    .unwrap();
+    assert_eq!(reader, b"rest");
+    assert_eq!(last_received_report.unwrap().processed_bytes, total_len);
+    assert_eq!(
+        fs::read(save_dir.path().join("text.txt")).unwrap(),
+        text.as_bytes()
+    );
+    assert_eq!(
+        fs::read(save_dir.path().join("archive.zip")).unwrap(),
+        archive
+    );
+
+    // a receiver that declines compression gets the files uncompressed
+    let response = FileResponseMsg {
+        compression: None,
+        ..response
+    };
+    let mut sent = Vec::new();
+    send_files(&local_files, &response, &mut sent, |_| ())
+        .await
+        .unwrap();
+    // plus a 17 byte header for each chunk of up to 64 KiB
+    let num_chunks =
+        (text.len() as u64).div_ceil(0x10000) + (archive.len() as u64).div_ceil(0x10000);
+    assert_eq!(sent.len() as u64, total_len + 17 * num_chunks);
+}
+
+/// Confirm that a file the receiver has an older version of
+/// is sent as a delta, which replaces the older version.
+#[tokio::test]
+async fn test_delta_sync() {
+    use gday_file_transfer::{Compression, Error};
+
+    let old: Vec<u8> = (0..200_000_u32)
+        .map(|i| (i.wrapping_mul(2_654_435_761) >> 11) as u8)
+        .collect();
+    let mut new = old.clone();
+    new[50_000..50_100].fill(0);
+    new.splice(120_000..120_000, *b"inserted");
+
+    let send_dir = tempfile::tempdir().unwrap();
+    fs::write(send_dir.path().join("file.bin"), &new).unwrap();
+    fs::write(send_dir.path().join("other.txt"), "Not in the save dir").unwrap();
+    let local_files = get_file_metas(&[
+        send_dir.path().join("file.bin"),
+        send_dir.path().join("other.txt"),
+    ])
+    .unwrap();
+    let mut offer = FileOfferMsg::from(local_files.clone());
+    offer.compression = Some(Compression::Zstd { level: 3 });
+
+    let save_dir = tempfile::tempdir().unwrap();
+    fs::write(save_dir.path().join("file.bin"), &old).unwrap();
+
+    let response = FileResponseMsg::accept_all_files(&offer)
+        .request_deltas(&offer, save_dir.path())
+        .unwrap();
+    assert_eq!(response.deltas.keys().collect::<Vec<_>>(), [&0]);
+
+    let mut sent = Vec::new();
+    send_files(&local_files, &response, &mut sent, |_| ())
+        .await
+        .unwrap();
+    assert!(sent.len() < 20_000);
+
+    let mut last_report = None;
+    receive_files(&offer, &response, save_dir.path(), &sent[..], |report| {
+        last_report = Some(report.clone())
+    })
+    .await
+    .unwrap();
+    assert_eq!(
+        last_report.unwrap().processed_bytes,
+        offer.get_transfer_size(&response).unwrap()
+    );
+
+    // replaced in place
+    assert_eq!(fs::read(save_dir.path().join("file.bin")).unwrap(), new);
+    assert!(!save_dir.path().join("file (1).bin").exists());
+    assert_eq!(
+        fs::read(save_dir.path().join("other.txt")).unwrap(),
+        b"Not in the save dir"
+    );
+
+    // deltas can't be resumed
+    let mut response = response;
+    response.response[0] = Some(10);
+    let result = send_files(&local_files, &response, &mut Vec::new(), |_| ()).await;
+    assert!(matches!(result, Err(Error::InvalidSignature)));
+}
+
+/// Confirm that permissions and modification times are only
+/// restored when the receiver asks for it.
+#[tokio::test]
+async fn test_preserve_metadata() {
+    use gday_file_transfer::ClockSkew;
+
+    let send_dir = tempfile::tempdir().unwrap();
+    let path = send_dir.path().join("script.sh");
+    fs::write(&path, "echo hello").unwrap();
+    let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(modified)
+        .unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o4750)).unwrap();
+    }
+
+    let local_files = get_file_metas(&[path]).unwrap();
+    assert_eq!(local_files[0].modified, Some(modified));
+    #[cfg(unix)]
+    assert_eq!(local_files[0].mode, Some(0o750));
+
+    let offer = FileOfferMsg::from(local_files.clone());
+    let mut response = FileResponseMsg::accept_all_files(&offer);
+    assert!(!response.preserve_metadata);
+    let mut sent = Vec::new();
+    send_files(&local_files, &response, &mut sent, |_| ())
+        .await
+        .unwrap();
+
+    // not restored by default
+    let save_dir = tempfile::tempdir().unwrap();
+    receive_files(&offer, &response, save_dir.path(), &sent[..], |_| ())
+        .await
+        .unwrap();
+    let received = save_dir.path().join("script.sh");
+    assert_ne!(self::modified(&received), Some(modified));
+
+    // restored when asked for, except for the setuid bit
+    response.preserve_metadata = true;
+    receive_files(&offer, &response, save_dir.path(), &sent[..], |_| ())
+        .await
+        .unwrap();
+    let received = save_dir.path().join("script (1).sh");
+    assert_eq!(fs::read(&received).unwrap(), b"echo hello");
+    assert_eq!(self::modified(&received), Some(modified));
+    #[cfg(unix)]
+    assert_eq!(unix_mode(&received), Some(0o750));
+
+    // modification times are converted to the local clock
+    let mut offer = offer;
+    offer.adjust_for_clock_skew(&ClockSkew {
+        offset_millis: 60_000,
+        round_trip_millis: 10,
+    });
+    assert_eq!(
+        offer.files[0].modified,
+        Some(modified - Duration::from_secs(60))
+    );
+}
+
+/// Confirm that [`gday_file_transfer::get_file_tree()`] finds
+/// symlinks and empty directories.
+#[cfg(unix)]
+#[test]
+fn test_get_file_tree() {
+    use gday_file_transfer::{get_file_tree, OfferOptions, SymlinkMeta};
+    use std::os::unix::fs::symlink;
+
+    let test_dir = make_test_dir();
+    let dir_path = test_dir.path().canonicalize().unwrap();
+    create_dir_all(dir_path.join("dir/empty")).unwrap();
+    create_dir_all(dir_path.join("dir/nested/empty")).unwrap();
+    symlink("subdir1/file1", dir_path.join("dir/link")).unwrap();
+    symlink("subdir2", dir_path.join("dir/dir_link")).unwrap();
+    symlink("missing", dir_path.join("dir/broken")).unwrap();
+
+    let mut tree = get_file_tree(&[dir_path.join("dir")], &OfferOptions::default()).unwrap();
+    tree.symlinks.sort_unstable();
+    tree.empty_dirs.sort_unstable();
+    assert_eq!(tree.files.len(), 6);
+    assert_eq!(
+        tree.symlinks,
+        [
+            SymlinkMeta {
+                short_path: PathBuf::from("dir/broken"),
+                target: PathBuf::from("missing"),
+                target_is_dir: false,
+            },
+            SymlinkMeta {
+                short_path: PathBuf::from("dir/dir_link"),
+                target: PathBuf::from("subdir2"),
+                target_is_dir: true,
+            },
+            SymlinkMeta {
+                short_path: PathBuf::from("dir/link"),
+                target: PathBuf::from("subdir1/file1"),
+                target_is_dir: false,
+            },
+        ]
+    );
+    assert_eq!(
+        tree.empty_dirs,
+        [
+            PathBuf::from("dir/empty"),
+            PathBuf::from("dir/nested/empty")
+        ]
+    );
+
+    // following symlinks offers their targets as files instead,
+    // and skips broken ones
+    let options = OfferOptions {
+        follow_symlinks: true,
+        ..OfferOptions::default()
+    };
+    let tree = get_file_tree(&[dir_path.join("dir")], &options).unwrap();
+    assert_eq!(tree.files.len(), 6 + 1 + 2);
+    assert!(tree.symlinks.is_empty());
+    assert_eq!(tree.empty_dirs.len(), 2);
+
+    // which is what `get_file_metas()` does
+    assert_eq!(get_file_metas(&[dir_path.join("dir")]).unwrap(), tree.files);
+}
+
+/// Confirm that [`gday_file_transfer::create_entries()`]
+/// follows its policy.
+#[cfg(unix)]
+#[test]
+fn test_create_entries() {
+    use gday_file_transfer::{check_entries, create_entries, EntryPolicy, Error, SymlinkMeta};
+
+    let link = |short_path: &str, target: &str| SymlinkMeta {
+        short_path: PathBuf::from(short_path),
+        target: PathBuf::from(target),
+        target_is_dir: false,
+    };
+    let mut offer = FileOfferMsg::from(Vec::new());
+    offer.symlinks = vec![link("dir/link", "../file.txt")];
+    offer.empty_dirs = vec![PathBuf::from("dir/empty")];
+
+    let save_dir = tempfile::tempdir().unwrap();
+
+    // skipped
+    create_entries(&offer, save_dir.path(), EntryPolicy::Skip).unwrap();
+    assert_eq!(fs::read_dir(save_dir.path()).unwrap().count(), 0);
+
+    // refused
+    assert!(matches!(
+        check_entries(&offer, EntryPolicy::Error),
+        Err(Error::EntriesNotAllowed)
+    ));
+    check_entries(&FileOfferMsg::from(Vec::new()), EntryPolicy::Error).unwrap();
+
+    // recreated
+    create_entries(&offer, save_dir.path(), EntryPolicy::Recreate).unwrap();
+    assert!(save_dir.path().join("dir/empty").is_dir());
+    assert_eq!(
+        fs::read_link(save_dir.path().join("dir/link")).unwrap(),
+        PathBuf::from("../file.txt")
+    );
+
+    // identical symlinks are left alone, and others are renamed
+    offer.symlinks.push(link("dir/link", "other.txt"));
+    create_entries(&offer, save_dir.path(), EntryPolicy::Recreate).unwrap();
+    assert_eq!(
+        fs::read_dir(save_dir.path().join("dir")).unwrap().count(),
+        3
+    );
+    assert_eq!(
+        fs::read_link(save_dir.path().join("dir/link (1)")).unwrap(),
+        PathBuf::from("other.txt")
+    );
+
+    // symlinks pointing outside the save directory are refused
+    for target in ["../../file.txt", "/etc/passwd", "a/../../.."] {
+        offer.symlinks = vec![link("dir/escape", target)];
+        let result = create_entries(&offer, save_dir.path(), EntryPolicy::Recreate);
+        assert!(matches!(result, Err(Error::SymlinkEscapes(_))), "{target}");
+    }
+    assert!(save_dir
+        .path()
+        .join("dir/escape")
+        .symlink_metadata()
+        .is_err());
+}
+
+/// Confirm that [`gday_file_transfer::get_file_tree()`]
+/// only offers what its [`gday_file_transfer::OfferOptions`] allow.
+#[test]
+fn test_offer_options() {
+    use gday_file_transfer::{get_file_tree, OfferOptions};
+
+    let test_dir = make_test_dir();
+    let dir_path = test_dir.path().canonicalize().unwrap();
+    create_dir_all(dir_path.join("dir/.git/objects")).unwrap();
+    fs::write(dir_path.join("dir/.git/HEAD"), "ref").unwrap();
+    fs::write(dir_path.join("dir/.hidden.txt"), "hidden").unwrap();
+    create_dir_all(dir_path.join("dir/subdir1/empty")).unwrap();
+
+    let short_paths = |options: &OfferOptions, paths: &[PathBuf]| {
+        let tree = get_file_tree(paths, options).unwrap();
+        let mut short_paths: Vec<PathBuf> = tree
+            .files
+            .into_iter()
+            .map(|f| f.short_path)
+            .chain(tree.empty_dirs)
+            .collect();
+        short_paths.sort_unstable();
+        short_paths
+    };
+    let dir = [dir_path.join("dir")];
+
+    // everything by default
+    assert_eq!(short_paths(&OfferOptions::default(), &dir).len(), 10);
+
+    // without hidden files
+    let options = OfferOptions {
+        include_hidden: false,
+        ..OfferOptions::default()
+    };
+    assert_eq!(short_paths(&options, &dir).len(), 7);
+
+    // without excluded directories and files
+    let options = OfferOptions {
+        exclude: vec![".git".parse().unwrap(), "subdir*/*.txt".parse().unwrap()],
+        ..OfferOptions::default()
+    };
+    assert_eq!(
+        short_paths(&options, &dir),
+        [
+            "dir/.hidden.txt",
+            "dir/file1",
+            "dir/file2.txt",
+            "dir/subdir1/empty",
+            "dir/subdir1/file1",
+            "dir/subdir2/file1",
+            "dir/subdir2/file2.tar.gz",
+        ]
+        .map(PathBuf::from)
+    );
+
+    // only included files, or those in included directories
+    let options = OfferOptions {
+        include: vec!["*.txt".parse().unwrap(), "subdir2".parse().unwrap()],
+        include_hidden: false,
+        ..OfferOptions::default()
+    };
+    assert_eq!(
+        short_paths(&options, &dir),
+        [
+            "dir/file2.txt",
+            "dir/subdir1/file2.txt",
+            "dir/subdir2/file1",
+            "dir/subdir2/file2.tar.gz",
+        ]
+        .map(PathBuf::from)
+    );
+
+    // paths given directly are always offered
+    let options = OfferOptions {
+        exclude: vec!["file1".parse().unwrap()],
+        ..OfferOptions::default()
+    };
+    assert_eq!(
+        short_paths(&options, &[dir_path.join("file1")]),
+        [PathBuf::from("file1")]
+    );
+}
+
+/// Confirm that [`receive_files()`] summarizes what happened to each file,
+/// and saves a manifest if asked to, even if the transfer fails.
+#[tokio::test]
+async fn test_transfer_summary() {
+    use gday_file_transfer::{FileMeta, FileStatus};
+
+    let save_dir = tempfile::tempdir().unwrap();
+    let file = |name: &str, hash: Option<[u8; 32]>| FileMeta {
+        short_path: PathBuf::from(name),
+        len: 5,
+        hash,
+        head_hash: None,
+        compressible: true,
+        mode: None,
+        modified: None,
+    };
+    let offer = FileOfferMsg {
+        files: vec![
+            file("a.txt", Some(*blake3::hash(b"hello").as_bytes())),
+            file("b.txt", None),
+            file("c.txt", None),
+            file("d.txt", None),
+        ],
+        compression: None,
+        symlinks: Vec::new(),
+        empty_dirs: Vec::new(),
+        archive: false,
+        streams: None,
+        duplicates: BTreeMap::new(),
+    };
+
+    // b.txt is resumed, and c.txt is skipped
+    fs::write(
+        offer.files[1]
+            .get_partial_download_path(save_dir.path())
+            .unwrap(),
+        b"wor",
+    )
+    .unwrap();
+    let mut response = FileResponseMsg {
+        response: vec![Some(0), Some(3), None, Some(0)],
+        compression: None,
+        preserve_metadata: false,
+        deltas: BTreeMap::new(),
+        write_manifest: true,
+        archive: false,
+        streams: None,
+        copies: BTreeMap::new(),
+        overwrite: OverwritePolicy::default(),
+    };
+
+    let mut sent = raw_chunk(0, 0, b"hello");
+    sent.extend(raw_chunk(1, 3, b"ld"));
+    sent.extend(raw_chunk(3, 0, b"12345"));
+    let summary = receive_files(&offer, &response, save_dir.path(), &sent[..], |_| ())
+        .await
+        .unwrap();
+
+    let statuses: Vec<_> = summary.files.iter().map(|file| file.status).collect();
+    assert_eq!(
+        statuses,
+        [
+            FileStatus::Received,
+            FileStatus::Resumed,
+            FileStatus::Skipped,
+            FileStatus::Received
+        ]
+    );
+    assert!(summary.files[0].hash_verified);
+    assert!(!summary.files[3].hash_verified);
+    assert_eq!(summary.files[1].bytes, 2);
+    assert_eq!(
+        summary.files[1].saved_path,
+        Some(save_dir.path().join("b.txt"))
+    );
+    assert_eq!(summary.files[2].saved_path, None);
+
+    let manifest_path = summary.manifest_path.unwrap();
+    let manifest: serde_json::Value =
+        serde_json::from_slice(&fs::read(&manifest_path).unwrap()).unwrap();
+    assert_eq!(manifest["files"][1]["status"], "resumed");
+    assert_eq!(manifest["files"][2]["status"], "skipped");
+
+    // a file cut short fails, and so does every file after it
+    response.response = vec![Some(0), None, None, Some(0)];
+    let sent = raw_chunk(0, 0, b"hel");
+    let result = receive_files(&offer, &response, save_dir.path(), &sent[..], |_| ()).await;
+    assert!(result.is_err());
+
+    let mut manifests: Vec<_> = fs::read_dir(save_dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.to_string_lossy().contains("gday_manifest_") && *path != manifest_path)
+        .collect();
+    assert_eq!(manifests.len(), 1);
+    let manifest: serde_json::Value =
+        serde_json::from_slice(&fs::read(manifests.pop().unwrap()).unwrap()).unwrap();
+    assert_eq!(manifest["files"][0]["status"], "failed");
+    assert_eq!(manifest["files"][1]["status"], "skipped");
+    assert_eq!(manifest["files"][3]["status"], "failed");
+}
+
+/// Confirm that files can be sent as one tar archive,
+/// and that the receiver rejects archives that don't match the offer.
+#[tokio::test]
+async fn test_archive() {
+    let test_dir = make_test_dir();
+    let dir_path = test_dir.path().canonicalize().unwrap();
+    let local_files = get_file_metas(&[dir_path.join("file1"), dir_path.join("dir")]).unwrap();
+    let mut offer = FileOfferMsg::from(local_files.clone());
+    offer.archive = true;
+
+    // the second file was partially downloaded before
+    let save_dir = tempfile::tempdir().unwrap();
+    let partial = &offer.files[1];
+    let contents = fs::read(&local_files[1].local_path).unwrap();
+    let partial_path = partial.get_partial_download_path(save_dir.path()).unwrap();
+    create_dir_all(partial_path.parent().unwrap()).unwrap();
+    fs::write(partial_path, &contents[0..4]).unwrap();
+    let response =
+        FileResponseMsg::accept_only_new_and_interrupted(&offer, save_dir.path()).unwrap();
+    assert!(response.archive);
+    assert_eq!(response.response[1], Some(4));
+
+    let mut sent = Vec::new();
+    send_files(&local_files, &response, &mut sent, |_| ())
+        .await
+        .unwrap();
+    // a real ustar archive
+    assert_eq!(&sent[257..263], b"ustar\0");
+    assert_eq!(sent.len() % 512, 0);
+
+    sent.extend_from_slice(b"rest");
+    let mut reader = &sent[..];
+    receive_files(&offer, &response, save_dir.path(), &mut reader, |_| ())
+        .await
+        .unwrap();
+    assert_eq!(reader, b"rest");
+    for file in &local_files {
+        assert_eq!(
+            fs::read(save_dir.path().join(&file.short_path)).unwrap(),
+            fs::read(&file.local_path).unwrap()
+        );
+    }
+
+    // an entry that isn't the accepted file
+    let response = FileResponseMsg::accept_all_files(&offer);
+    let mut sent = Vec::new();
+    send_files(&local_files, &response, &mut sent, |_| ())
+        .await
+        .unwrap();
+    let mut renamed = offer.clone();
+    renamed.files[0].short_path = PathBuf::from("other");
+    let save_dir = tempfile::tempdir().unwrap();
+    let result = receive_files(&renamed, &response, save_dir.path(), &sent[..], |_| ()).await;
+    assert!(matches!(
+        result,
+        Err(gday_file_transfer::Error::InvalidArchive)
+    ));
+}
+
+/// Tests streaming a single file to and from
+/// a pipe instead of the file system.
+#[tokio::test]
+async fn test_pipe() {
+    use gday_file_transfer::{receive_file_to, send_file_from, Compression, Error, FileMeta};
+
+    let send_dir = tempfile::tempdir().unwrap();
+    let text = "All work and no play makes Jack a dull boy.\n".repeat(10_000);
+    fs::write(send_dir.path().join("text.txt"), &text).unwrap();
+    let mut local_files = get_file_metas(&[send_dir.path().join("text.txt")]).unwrap();
+    local_files[0].compute_hash().unwrap();
+
+    // a file sent from disk can be received to a pipe,
+    // with and without compression or archiving
+    for (compression, archive) in [
+        (None, false),
+        (Some(Compression::Zstd { level: 3 }), false),
+        (None, true),
+    ] {
+        let mut offer = FileOfferMsg::from(local_files.clone());
+        offer.compression = compression;
+        offer.archive = archive;
+        let response = FileResponseMsg::accept_all_files(&offer);
+
+        let mut sent = Vec::new();
+        send_files(&local_files, &response, &mut sent, |_| ())
+            .await
+            .unwrap();
+        sent.extend_from_slice(b"rest");
+
+        let mut reader = &sent[..];
+        let mut received = Vec::new();
+        let mut last_report = None;
+        receive_file_to(&offer, &response, &mut reader, &mut received, |report| {
+            last_report = Some(report.clone())
+        })
+        .await
+        .unwrap();
+        assert_eq!(received, text.as_bytes());
+        assert_eq!(reader, b"rest");
+        let last_report = last_report.unwrap();
+        assert_eq!(last_report.processed_bytes, text.len() as u64);
+        assert_eq!(last_report.processed_files, 1);
+        assert_eq!(last_report.current_file_processed, text.len() as u64);
+        assert_eq!(last_report.current_file_len, text.len() as u64);
+        assert_eq!(last_report.eta, Some(Duration::ZERO));
+
+        // a corrupted file doesn't match its hash
+        let mut corrupted = offer.clone();
+        corrupted.files[0].hash = Some([0; 32]);
+        let result = receive_file_to(&corrupted, &response, &sent[..], Vec::new(), |_| ()).await;
+        assert!(matches!(result, Err(Error::ChecksumMismatch(_))));
+    }
+
+    // a file piped in with a declared length can be received to disk
+    let offer = FileOfferMsg {
+        files: vec![FileMeta {
+            short_path: PathBuf::from("piped.txt"),
+            len: text.len() as u64,
+            hash: None,
+            head_hash: None,
+            compressible: true,
+            mode: None,
+            modified: None,
+        }],
+        compression: Some(Compression::Zstd { level: 3 }),
+        symlinks: Vec::new(),
+        empty_dirs: Vec::new(),
+        archive: false,
+        streams: None,
+        duplicates: BTreeMap::new(),
+    };
+    let response = FileResponseMsg::accept_all_files(&offer);
+    let mut sent = Vec::new();
+    send_file_from(&offer, &response, text.as_bytes(), &mut sent, |_| ())
+        .await
+        .unwrap();
+    assert!(sent.len() < text.len() / 2);
+    let save_dir = tempfile::tempdir().unwrap();
+    receive_files(&offer, &response, save_dir.path(), &sent[..], |_| ())
+        .await
+        .unwrap();
+    assert_eq!(
+        fs::read_to_string(save_dir.path().join("piped.txt")).unwrap(),
+        text
+    );
+
+    // a pipe that ends early
+    let result = send_file_from(&offer, &response, &b"short"[..], Vec::new(), |_| ()).await;
+    assert!(matches!(result, Err(Error::UnexpectedFileLen)));
+
+    // only a single file can be streamed
+    let multiple = get_file_metas(&[make_test_dir().path().to_path_buf()]).unwrap();
+    let offer = FileOfferMsg::from(multiple);
+    let response = FileResponseMsg::accept_all_files(&offer);
+    let result = receive_file_to(&offer, &response, &b""[..], Vec::new(), |_| ()).await;
+    assert!(matches!(result, Err(Error::NotSingleFile)));
+}
+
+/// Tests checking that offered files fit on the disk.
+#[cfg(unix)]
+#[tokio::test]
+async fn test_disk_space() {
+    use gday_file_transfer::{ensure_disk_space, DiskSpace, Error, FileMeta};
+
+    let test_dir = make_test_dir();
+    let local_files = get_file_metas(&[test_dir.path().join("file1")]).unwrap();
+    let offer = FileOfferMsg::from(local_files);
+    // the save directory doesn't exist yet
+    let save_dir = tempfile::tempdir().unwrap();
+    let save_path = save_dir.path().join("new_dir");
+
+    let space = DiskSpace::check(&offer, &save_path).unwrap().unwrap();
+    assert_eq!(space.offered, offer.get_total_offered_size());
+    assert!(space.available > 0);
+    assert_eq!(space.shortfall(), None);
+    let response = FileResponseMsg::accept_all_files(&offer);
+    ensure_disk_space(&offer, &response, &save_path).unwrap();
+
+    // a file that can't fit
+    let huge = FileOfferMsg {
+        files: vec![FileMeta {
+            short_path: PathBuf::from("huge"),
+            len: u64::MAX / 2,
+            hash: None,
+            head_hash: None,
+            compressible: false,
+            mode: None,
+            modified: None,
+        }],
+        compression: None,
+        symlinks: Vec::new(),
+        empty_dirs: Vec::new(),
+        archive: false,
+        streams: None,
+        duplicates: BTreeMap::new(),
+    };
+    let space = DiskSpace::check(&huge, &save_path).unwrap().unwrap();
+    assert_eq!(space.shortfall(), Some(u64::MAX / 2 - space.available));
+
+    // receiving fails before anything is read or saved
+    let response = FileResponseMsg::accept_all_files(&huge);
+    let result = receive_files(&huge, &response, &save_path, &b""[..], |_| ()).await;
+    assert!(matches!(
+        result,
+        Err(Error::InsufficientDiskSpace { needed, .. }) if needed == u64::MAX / 2
+    ));
+    assert!(!save_path.exists());
+
+    // unless it's rejected
+    let response = FileResponseMsg::reject_all_files(&huge);
+    ensure_disk_space(&huge, &response, &save_path).unwrap();
+}
+
+/// Tests the policies for files whose save path is taken.
+#[tokio::test]
+async fn test_overwrite_policy() {
+    use gday_file_transfer::Error;
+
+    let test_dir = make_test_dir();
+    let dir_path = test_dir.path();
+    let local_files =
+        get_file_metas(&[dir_path.join("file1"), dir_path.join("file2.txt")]).unwrap();
+    let offer = FileOfferMsg::from(local_files.clone());
+
+    // an older, different version of file1 is already saved
+    let save_dir = tempfile::tempdir().unwrap();
+    let existing = save_dir.path().join("file1");
+    fs::write(&existing, "old").unwrap();
+
+    let receive = |policy| {
+        let (offer, local_files) = (offer.clone(), local_files.clone());
+        let save_dir = save_dir.path().to_path_buf();
+        async move {
+            let response = FileResponseMsg::accept_all_files(&offer)
+                .with_overwrite_policy(&offer, &save_dir, policy)?;
+            let mut sent = Vec::new();
+            send_files(&local_files, &response, &mut sent, |_| ()).await?;
+            receive_files(&offer, &response, &save_dir, &sent[..], |_| ()).await?;
+            Ok::<_, Error>(response)
+        }
+    };
+
+    // refusing to overwrite fails before anything is sent
+    let result = receive(OverwritePolicy::FailIfExists).await;
+    assert!(matches!(result, Err(Error::FileExists(path)) if path == existing));
+    assert!(!save_dir.path().join("file2.txt").exists());
+
+    // skipping rejects the existing file
+    let response = receive(OverwritePolicy::Skip).await.unwrap();
+    assert_eq!(response.response, [None, Some(0)]);
+    assert_eq!(fs::read_to_string(&existing).unwrap(), "old");
+    assert!(save_dir.path().join("file2.txt").exists());
+
+    // renaming keeps both
+    receive(OverwritePolicy::RenameSuffix).await.unwrap();
+    assert_eq!(fs::read_to_string(&existing).unwrap(), "old");
+    assert_eq!(
+        fs::read_to_string(save_dir.path().join("file1 (1)")).unwrap(),
+        "This is file1"
+    );
+    assert!(save_dir.path().join("file2 (1).txt").exists());
+
+    // overwriting replaces it
+    receive(OverwritePolicy::Overwrite).await.unwrap();
+    assert_eq!(fs::read_to_string(&existing).unwrap(), "This is file1");
+    assert!(!save_dir.path().join("file1 (2)").exists());
+
+    // a file that appears during the transfer isn't overwritten,
+    // and is left as a partial download
+    let save_dir = tempfile::tempdir().unwrap();
+    let response = FileResponseMsg::accept_all_files(&offer)
+        .with_overwrite_policy(&offer, save_dir.path(), OverwritePolicy::Skip)
+        .unwrap();
+    assert_eq!(response.response, [Some(0), Some(0)]);
+    fs::write(save_dir.path().join("file1"), "new").unwrap();
+    let mut sent = Vec::new();
+    send_files(&local_files, &response, &mut sent, |_| ())
+        .await
+        .unwrap();
+    let result = receive_files(&offer, &response, save_dir.path(), &sent[..], |_| ()).await;
+    assert!(matches!(result, Err(Error::FileExists(_))));
+    assert_eq!(
+        fs::read_to_string(save_dir.path().join("file1")).unwrap(),
+        "new"
+    );
+    assert!(offer.files[0]
+        .partial_download_exists(save_dir.path())
+        .unwrap()
+        .is_some());
+}
+
+/// Tests that two transfers into the same directory
+/// don't write the same partial download.
+#[tokio::test]
+async fn test_partial_download_in_use() {
+    use gday_file_transfer::Error;
+
+    let test_dir = make_test_dir();
+    let local_files = get_file_metas(&[test_dir.path().join("file1")]).unwrap();
+    let offer = FileOfferMsg::from(local_files.clone());
+    let response = FileResponseMsg::accept_all_files(&offer);
+    let mut sent = Vec::new();
+    send_files(&local_files, &response, &mut sent, |_| ())
+        .await
+        .unwrap();
+
+    // another transfer is writing the partial download
+    let save_dir = tempfile::tempdir().unwrap();
+    let partial_path = offer.files[0]
+        .get_partial_download_path(save_dir.path())
+        .unwrap();
+    fs::write(&partial_path, "This").unwrap();
+    let other = File::open(&partial_path).unwrap();
+    other.lock().unwrap();
+
+    let result = receive_files(&offer, &response, save_dir.path(), &sent[..], |_| ()).await;
+    assert!(matches!(result, Err(Error::PartialDownloadInUse(path)) if path == partial_path));
+    // and it's left as it was
+    assert_eq!(fs::read_to_string(&partial_path).unwrap(), "This");
+
+    // once it's done, the file can be received
+    drop(other);
+    receive_files(&offer, &response, save_dir.path(), &sent[..], |_| ())
+        .await
+        .unwrap();
+    assert_eq!(
+        fs::read_to_string(save_dir.path().join("file1")).unwrap(),
+        "This is file1"
+    );
+}
+
+/// Tests resuming partial downloads after the sender
+/// renamed the folder they're in.
+#[tokio::test]
+async fn test_relocate_partial_downloads() {
+    use gday_file_transfer::{relocate_partial_downloads, HEAD_LEN};
+
+    let send_dir = tempfile::tempdir().unwrap();
+    let contents: Vec<u8> = (0..3 * HEAD_LEN).map(|i| (i * 7919 % 251) as u8).collect();
+    create_dir_all(send_dir.path().join("old_name")).unwrap();
+    fs::write(send_dir.path().join("old_name/big"), &contents).unwrap();
+    fs::write(send_dir.path().join("old_name/other"), &contents).unwrap();
+
+    // the first transfer was interrupted
+    let mut local_files = get_file_metas(&[send_dir.path().join("old_name")]).unwrap();
+    for file in &mut local_files {
+        file.compute_head_hash().unwrap();
+    }
+    let offer = FileOfferMsg::from(local_files.clone());
+    let save_dir = tempfile::tempdir().unwrap();
+    let old_partial = offer.files[0]
+        .get_partial_download_path(save_dir.path())
+        .unwrap();
+    create_dir_all(old_partial.parent().unwrap()).unwrap();
+    fs::write(&old_partial, &contents[0..2 * HEAD_LEN as usize]).unwrap();
+    // a partial download with different contents
+    let mut garbage = contents.clone();
+    garbage[0] ^= 1;
+    let other_partial = offer.files[1]
+        .get_partial_download_path(save_dir.path())
+        .unwrap();
+    fs::write(&other_partial, &garbage[0..2 * HEAD_LEN as usize]).unwrap();
+
+    // then the sender renamed the folder
+    fs::rename(
+        send_dir.path().join("old_name"),
+        send_dir.path().join("new_name"),
+    )
+    .unwrap();
+    let mut local_files = get_file_metas(&[send_dir.path().join("new_name")]).unwrap();
+    for file in &mut local_files {
+        file.compute_head_hash().unwrap();
+    }
+    let offer = FileOfferMsg::from(local_files.clone());
+
+    // a file whose path leaves the save directory is rejected
+    let mut unsafe_offer = offer.clone();
+    unsafe_offer.files[0].short_path = PathBuf::from("../escaped/big");
+    assert!(matches!(
+        relocate_partial_downloads(&unsafe_offer, save_dir.path()),
+        Err(gday_file_transfer::Error::UnsafePath(_))
+    ));
+    assert!(old_partial.exists());
+
+    let moved = relocate_partial_downloads(&offer, save_dir.path()).unwrap();
+    let new_partial = offer.files[0]
+        .get_partial_download_path(save_dir.path())
+        .unwrap();
+    assert_eq!(moved, [(old_partial, new_partial)]);
+    assert!(other_partial.exists());
+    // nothing else to move
+    assert!(relocate_partial_downloads(&offer, save_dir.path())
+        .unwrap()
+        .is_empty());
+
+    let response =
+        FileResponseMsg::accept_only_new_and_interrupted(&offer, save_dir.path()).unwrap();
+    assert_eq!(response.response, [Some(2 * HEAD_LEN), Some(0)]);
+    let mut sent = Vec::new();
+    send_files(&local_files, &response, &mut sent, |_| ())
+        .await
+        .unwrap();
+    receive_files(&offer, &response, save_dir.path(), &sent[..], |_| ())
+        .await
+        .unwrap();
+    for file in &offer.files {
+        assert_eq!(
+            fs::read(save_dir.path().join(&file.short_path)).unwrap(),
+            contents
+        );
+    }
+}
+
+/// Tests that a sync session sends new and changed files,
+/// and ends when the receiver closes the connection.
+#[tokio::test]
+async fn test_sync_session() {
+    use gday_file_transfer::{receive_sync_session, sync_session, SyncOptions};
+
+    let src_dir = tempfile::tempdir().unwrap();
+    let watched = src_dir.path().join("watched");
+    create_dir_all(&watched).unwrap();
+    fs::write(watched.join("a.txt"), "first").unwrap();
+    let save_dir = tempfile::tempdir().unwrap();
+    let save_path = save_dir.path().to_path_buf();
+
+    let (sender_stream, receiver_stream) = tokio::io::duplex(0x10000);
+    let options = SyncOptions {
+        poll_interval: Duration::from_millis(20),
+        ..SyncOptions::default()
+    };
+    let sender = tokio::spawn(async move {
+        let mut stream = tokio::io::BufReader::new(sender_stream);
+        sync_session(&[watched], &options, &mut stream, |_| ()).await
+    });
+    let receiver = tokio::spawn(async move {
+        let mut stream = tokio::io::BufReader::new(receiver_stream);
+        let respond = |offer: &FileOfferMsg| {
+            FileResponseMsg::accept_all_files(offer).with_overwrite_policy(
+                offer,
+                &save_path,
+                OverwritePolicy::Overwrite,
+            )
+        };
+        receive_sync_session(&save_path, &mut stream, respond, |_| ()).await
+    });
+
+    // waits until `path` is saved with `contents`
+    let synced = |path: PathBuf, contents: &'static str| async move {
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while fs::read_to_string(&path).ok().as_deref() != Some(contents) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("File wasn't synced in time.");
+    };
+
+    // existing files are sent at the start
+    let saved = save_dir.path().join("watched");
+    synced(saved.join("a.txt"), "first").await;
+
+    // then changed and new files
+    fs::write(src_dir.path().join("watched/a.txt"), "second version").unwrap();
+    fs::write(src_dir.path().join("watched/b.txt"), "new file").unwrap();
+    synced(saved.join("a.txt"), "second version").await;
+    synced(saved.join("b.txt"), "new file").await;
+    assert!(!saved.join("a (1).txt").exists());
+
+    // closing the receiver ends the session
+    receiver.abort();
+    assert!(receiver.await.unwrap_err().is_cancelled());
+    tokio::time::timeout(Duration::from_secs(10), sender)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+}
+
+/// Tests that identical files are sent once,
+/// and copied by the receiver.
+#[tokio::test]
+async fn test_duplicates() {
+    use gday_file_transfer::{find_duplicates, Error, FileStatus};
+
+    let src_dir = tempfile::tempdir().unwrap();
+    let src = src_dir.path();
+    fs::write(src.join("a.txt"), "same contents").unwrap();
+    fs::write(src.join("b.txt"), "other content").unwrap();
+    fs::write(src.join("c.txt"), "same contents").unwrap();
+    fs::write(src.join("d.txt"), "short").unwrap();
+
+    let paths = ["a.txt", "b.txt", "c.txt", "d.txt"].map(|name| src.join(name));
+    let mut local_files = get_file_metas(&paths).unwrap();
+    let index_of = |files: &[FileMetaLocal], name: &str| {
+        files
+            .iter()
+            .position(|file| file.short_path == Path::new(name))
+            .unwrap()
+    };
+    let (a, c, d) = (
+        index_of(&local_files, "a.txt"),
+        index_of(&local_files, "c.txt"),
+        index_of(&local_files, "d.txt"),
+    );
+    let (original, duplicate) = (a.min(c), a.max(c));
+
+    // only files of the same length are hashed
+    let duplicates = find_duplicates(&mut local_files).unwrap();
+    assert_eq!(duplicates, BTreeMap::from([(duplicate, original)]));
+    assert!(local_files[d].hash.is_none());
+
+    let mut offer = FileOfferMsg::from(local_files.clone());
+    offer.duplicates = duplicates;
+
+    // the duplicate isn't transferred
+    let response = FileResponseMsg::accept_all_files(&offer).copy_duplicates(&offer);
+    assert_eq!(response.copies, offer.duplicates);
+    assert_eq!(
+        offer.get_transfer_size(&response).unwrap(),
+        offer.get_total_offered_size() - 13
+    );
+
+    let save_dir = tempfile::tempdir().unwrap();
+    let mut sent = Vec::new();
+    send_files(&local_files, &response, &mut sent, |_| ())
+        .await
+        .unwrap();
+    let summary = receive_files(&offer, &response, save_dir.path(), &sent[..], |_| ())
+        .await
+        .unwrap();
+    for name in ["a.txt", "c.txt"] {
+        assert_eq!(
+            fs::read_to_string(save_dir.path().join(name)).unwrap(),
+            "same contents"
+        );
+    }
+    assert_eq!(summary.files[duplicate].status, FileStatus::Copied);
+    assert_eq!(summary.files[original].status, FileStatus::Received);
+
+    // a duplicate of a rejected file is received as usual
+    let mut response = FileResponseMsg::accept_all_files(&offer);
+    response.response[original] = None;
+    let response = response.copy_duplicates(&offer);
+    assert!(response.copies.is_empty());
+
+    // files can only be copied from accepted files of the same length
+    let mut response = FileResponseMsg::accept_all_files(&offer);
+    response.copies.insert(d, a);
+    let mut sent = Vec::new();
+    let result = send_files(&local_files, &response, &mut sent, |_| ()).await;
+    assert!(matches!(result, Err(Error::InvalidCopy(i)) if i == d));
+}
+
+/// Tests that either peer can cancel a transfer,
+/// and what arrived can be resumed.
+#[tokio::test]
+async fn test_cancel() {
+    use gday_file_transfer::{
+        receive_files_cancellable, send_files_cancellable, CancelReason, CancelToken, Error,
+        StorageFullAction,
+    };
+    use tokio::io::AsyncWriteExt;
+
+    let src_dir = tempfile::tempdir().unwrap();
+    let contents: Vec<u8> = (0..200_000_u32).map(|i| (i % 251) as u8).collect();
+    fs::write(src_dir.path().join("big"), &contents).unwrap();
+    let local_files = get_file_metas(&[src_dir.path().join("big")]).unwrap();
+    let offer = FileOfferMsg::from(local_files.clone());
+    let response = FileResponseMsg::accept_all_files(&offer);
+
+    // the sender cancels after its first chunk
+    let cancel = CancelToken::new();
+    let mut sent = Vec::new();
+    let result = send_files_cancellable(
+        &local_files,
+        &response,
+        &mut sent,
+        |_| cancel.cancel(CancelReason::UserCancelled),
+        &cancel,
+    )
+    .await;
+    assert!(matches!(
+        result,
+        Err(Error::Cancelled(CancelReason::UserCancelled))
+    ));
+
+    // so the receiver is told, and keeps what arrived
+    let save_dir = tempfile::tempdir().unwrap();
+    let result = receive_files(&offer, &response, save_dir.path(), &sent[..], |_| ()).await;
+    assert!(matches!(
+        result,
+        Err(Error::PeerCancelled(CancelReason::UserCancelled))
+    ));
+    assert_eq!(
+        offer.files[0]
+            .partial_download_exists(save_dir.path())
+            .unwrap(),
+        Some(0x10000)
+    );
+
+    // the receiver cancels while waiting for more
+    let save_dir = tempfile::tempdir().unwrap();
+    let (mut writer, reader) = tokio::io::duplex(0x100000);
+    writer
+        .write_all(&raw_chunk(0, 0, &contents[0..1000]))
+        .await
+        .unwrap();
+    let cancel = CancelToken::new();
+    let cancel_soon = async {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        cancel.cancel(CancelReason::StorageFull);
+    };
+    let (result, ()) = tokio::join!(
+        receive_files_cancellable(
+            &offer,
+            &response,
+            save_dir.path(),
+            tokio::io::BufReader::new(reader),
+            |_| (),
+            |_| std::future::ready(StorageFullAction::Abort),
+            &cancel,
+        ),
+        cancel_soon
+    );
+    assert!(matches!(
+        result,
+        Err(Error::Cancelled(CancelReason::StorageFull))
+    ));
+    assert_eq!(
+        offer.files[0]
+            .partial_download_exists(save_dir.path())
+            .unwrap(),
+        Some(1000)
+    );
+    // the sender can't read its file
+    fs::write(src_dir.path().join("big"), b"shorter").unwrap();
+    let mut sent = Vec::new();
+    let result = send_files(&local_files, &response, &mut sent, |_| ()).await;
+    assert!(matches!(result, Err(Error::UnexpectedFileLen)));
+
+    // so the receiver is told why
+    let save_dir = tempfile::tempdir().unwrap();
+    let result = receive_files(&offer, &response, save_dir.path(), &sent[..], |_| ()).await;
+    assert!(matches!(
+        result,
+        Err(Error::PeerCancelled(CancelReason::Error(msg))) if msg == Error::UnexpectedFileLen.to_string()
+    ));
+}
@@ -0,0 +1,131 @@
+#![forbid(unsafe_code)]
+#![warn(clippy::all)]
+use gday_file_transfer::{
+    get_file_metas, receive_files_multi, send_files_multi, Compression, Error, FileOfferMsg,
+    FileResponseMsg,
+};
+use std::collections::BTreeSet;
+use std::fs;
+use tokio::io::{AsyncWriteExt, BufReader, DuplexStream};
+
+/// Returns `n` connected pairs of streams,
+/// with the receiving ends buffered.
+fn stream_pairs(n: usize) -> (Vec<DuplexStream>, Vec<BufReader<DuplexStream>>) {
+    (0..n)
+        .map(|_| {
+            let (a, b) = tokio::io::duplex(1000);
+            (a, BufReader::new(b))
+        })
+        .unzip()
+}
+
+/// Confirm that files of various sizes are split across
+/// several streams and all arrive intact.
+#[tokio::test]
+async fn test_multi_stream_transfer() {
+    let send_dir = tempfile::tempdir().unwrap();
+    let mut paths = Vec::new();
+    for (i, len) in [0, 10, 5_000, 100_000, 30_000].into_iter().enumerate() {
+        let path = send_dir.path().join(format!("file{i}"));
+        let contents: Vec<u8> = (0..len).map(|j: u32| (j * 31 + i as u32) as u8).collect();
+        fs::write(&path, contents).unwrap();
+        paths.push(path);
+    }
+    let long_text = "Multiple streams of text.\n".repeat(5_000);
+    fs::write(send_dir.path().join("text.txt"), &long_text).unwrap();
+    paths.push(send_dir.path().join("text.txt"));
+
+    let local_files = get_file_metas(&paths).unwrap();
+    let mut offer = FileOfferMsg::from(local_files.clone());
+    offer.compression = Some(Compression::Zstd { level: 3 });
+    let mut response = FileResponseMsg::accept_all_files(&offer);
+    // reject one file
+    response.response[1] = None;
+
+    let total_bytes = offer.get_transfer_size(&response).unwrap();
+    let save_dir = tempfile::tempdir().unwrap();
+    let (mut senders, mut receivers) = stream_pairs(3);
+
+    let mut last_sent = None;
+    let mut last_received = None;
+    let mut sending_streams = BTreeSet::new();
+    let mut receiving_streams = BTreeSet::new();
+    let (sent, received) = tokio::join!(
+        send_files_multi(&local_files, &response, &mut senders, |progress| {
+            assert!(progress.stream_report.processed_bytes <= progress.total.processed_bytes);
+            sending_streams.insert(progress.stream);
+            last_sent = Some(progress.total.clone());
+        }),
+        receive_files_multi(
+            &offer,
+            &response,
+            save_dir.path(),
+            &mut receivers,
+            |progress| {
+                assert!(progress.stream_report.processed_bytes <= progress.total.processed_bytes);
+                receiving_streams.insert(progress.stream);
+                last_received = Some(progress.total.clone());
+            }
+        )
+    );
+    sent.unwrap();
+    received.unwrap();
+
+    // the files were split across the streams
+    for streams in [sending_streams, receiving_streams] {
+        assert!(streams.len() > 1);
+        assert!(streams.iter().all(|&stream| stream < 3));
+    }
+
+    for report in [last_sent.unwrap(), last_received.unwrap()] {
+        assert_eq!(report.processed_bytes, total_bytes);
+        assert_eq!(report.total_bytes, total_bytes);
+        assert_eq!(report.processed_files, 5);
+        assert_eq!(report.total_files, 5);
+    }
+
+    for (i, path) in paths.iter().enumerate() {
+        let received_path = save_dir.path().join(path.file_name().unwrap());
+        if i == 1 {
+            assert!(!received_path.exists());
+        } else {
+            assert_eq!(fs::read(received_path).unwrap(), fs::read(path).unwrap());
+        }
+    }
+}
+
+/// Confirm that the receiver notices files that are
+/// missing, unrequested, or sent twice.
+#[tokio::test]
+async fn test_multi_stream_errors() {
+    let send_dir = tempfile::tempdir().unwrap();
+    fs::write(send_dir.path().join("file"), "Some contents").unwrap();
+    let local_files = get_file_metas(&[send_dir.path().join("file")]).unwrap();
+    let offer = FileOfferMsg::from(local_files);
+    let response = FileResponseMsg::accept_all_files(&offer);
+
+    // every stream ends without sending the file
+    let save_dir = tempfile::tempdir().unwrap();
+    let (mut senders, mut receivers) = stream_pairs(2);
+    for sender in &mut senders {
+        sender.write_u32(u32::MAX).await.unwrap();
+    }
+    let result =
+        receive_files_multi(&offer, &response, save_dir.path(), &mut receivers, |_| ()).await;
+    assert!(matches!(result, Err(Error::IncompleteTransfer)));
+
+    // an index that isn't in the offer
+    let (mut senders, mut receivers) = stream_pairs(1);
+    senders[0].write_u32(1).await.unwrap();
+    let result =
+        receive_files_multi(&offer, &response, save_dir.path(), &mut receivers, |_| ()).await;
+    assert!(matches!(result, Err(Error::InvalidFileIndex(1))));
+
+    // a file that was rejected
+    let rejected = FileResponseMsg::reject_all_files(&offer);
+    let (mut senders, mut receivers) = stream_pairs(1);
+    senders[0].write_u32(0).await.unwrap();
+    let result =
+        receive_files_multi(&offer, &rejected, save_dir.path(), &mut receivers, |_| ()).await;
+    assert!(matches!(result, Err(Error::InvalidFileIndex(0))));
+}
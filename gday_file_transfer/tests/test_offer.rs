@@ -1,4 +1,8 @@
-use gday_file_transfer::{FileMetaLocal, FileOfferMsg, FileResponseMsg};
+use gday_file_transfer::{
+    read_from, read_from_async, write_cancel, write_cancel_async, CancelReason, Error,
+    FileMetaLocal, FileOfferMsg, FileResponseMsg,
+};
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::Write;
 use std::path::PathBuf;
@@ -40,31 +44,61 @@ async fn test_file_offer() {
             short_path: PathBuf::from("completely_exists.tar.gz"),
             local_path: sender_path.join("completely_exists.tar.gz"),
             len: 3,
+            hash: None,
+            head_hash: None,
+            compressible: true,
+            mode: None,
+            modified: None,
         },
         FileMetaLocal {
             short_path: PathBuf::from("wrong_size_exists.tar.gz"),
             local_path: sender_path.join("wrong_size_exists.tar.gz"),
             len: 2,
+            hash: None,
+            head_hash: None,
+            compressible: true,
+            mode: None,
+            modified: None,
         },
         FileMetaLocal {
             short_path: PathBuf::from("just_partial.tar.gz"),
             local_path: sender_path.join("just_partial.tar.gz"),
             len: 9,
+            hash: None,
+            head_hash: None,
+            compressible: true,
+            mode: None,
+            modified: None,
         },
         FileMetaLocal {
             short_path: PathBuf::from("partial_wrong_size.tar.gz"),
             local_path: sender_path.join("partial_wrong_size.tar.gz"),
             len: 10,
+            hash: None,
+            head_hash: None,
+            compressible: true,
+            mode: None,
+            modified: None,
         },
         FileMetaLocal {
             short_path: PathBuf::from("exists_and_has_partial.tar.gz"),
             local_path: sender_path.join("exists_and_has_partial.tar.gz"),
             len: 4,
+            hash: None,
+            head_hash: None,
+            compressible: true,
+            mode: None,
+            modified: None,
         },
         FileMetaLocal {
             short_path: PathBuf::from("completely_unseen_file.tar.gz"),
             local_path: sender_path.join("completely_unseen_file.tar.gz"),
             len: 2,
+            hash: None,
+            head_hash: None,
+            compressible: true,
+            mode: None,
+            modified: None,
         },
     ];
 
@@ -116,4 +150,257 @@ async fn test_file_offer() {
         offer.get_transfer_size(&only_new_and_interrupted).unwrap(),
         22
     );
+
+    let only_interrupted = FileResponseMsg::accept_only_interrupted(&offer, dir_path).unwrap();
+    assert_eq!(
+        only_interrupted.response,
+        vec![None, None, Some(4), None, Some(1), None]
+    );
+    assert_eq!(only_interrupted.get_num_fully_accepted(), 0);
+    assert_eq!(only_interrupted.get_num_partially_accepted(), 2);
+    assert_eq!(offer.get_transfer_size(&only_interrupted).unwrap(), 8);
+
+    let small_only = only_new_and_interrupted.reject_larger_than(&offer, 4);
+    assert_eq!(
+        small_only.response,
+        vec![None, Some(0), None, None, Some(1), Some(0)]
+    );
+    assert_eq!(offer.get_transfer_size(&small_only).unwrap(), 7);
+
+    let small_only = FileResponseMsg::accept_all_files(&offer).reject_larger_than(&offer, 2);
+    assert_eq!(
+        small_only.response,
+        vec![None, Some(0), None, None, None, Some(0)]
+    );
+}
+
+/// Confirm that a cancellation is received as [`gday_file_transfer::Error::PeerCancelled`]
+/// in place of the expected message.
+#[tokio::test]
+async fn test_cancel() {
+    // synchronous
+    let mut buf = Vec::new();
+    write_cancel(CancelReason::StorageFull, &mut buf).unwrap();
+    let result: Result<FileResponseMsg, _> = read_from(&mut &buf[..]);
+    assert!(matches!(
+        result,
+        Err(Error::PeerCancelled(CancelReason::StorageFull))
+    ));
+
+    // asynchronous
+    let (mut stream1, mut stream2) = tokio::io::duplex(1000);
+    write_cancel_async(CancelReason::Error("oops".to_string()), &mut stream1)
+        .await
+        .unwrap();
+    let result: Result<FileOfferMsg, _> = read_from_async(&mut stream2).await;
+    let Err(Error::PeerCancelled(reason)) = result else {
+        panic!("Expected a cancellation.");
+    };
+    assert_eq!(reason, CancelReason::Error("oops".to_string()));
+}
+
+/// Confirm that offered paths differing only in case are renamed apart.
+#[test]
+fn test_rename_case_collisions() {
+    let file = |path: &str| gday_file_transfer::FileMeta {
+        short_path: PathBuf::from(path),
+        len: 5,
+        hash: None,
+        head_hash: None,
+        compressible: true,
+        mode: None,
+        modified: None,
+    };
+    let mut offer = FileOfferMsg {
+        files: vec![
+            file("dir/Readme.md"),
+            file("dir/README.md"),
+            file("dir/readme (1).md"),
+            file("DIR/readme.md"),
+            file("dir/other.md"),
+        ],
+        compression: None,
+        symlinks: Vec::new(),
+        empty_dirs: Vec::new(),
+        archive: false,
+        streams: None,
+        duplicates: BTreeMap::new(),
+    };
+
+    let renamed = offer.rename_case_collisions();
+    assert_eq!(
+        renamed,
+        [
+            ("dir/README.md".into(), "dir/README (2).md".into()),
+            ("DIR/readme.md".into(), "DIR/readme (3).md".into()),
+        ]
+    );
+    let paths: Vec<_> = offer.files.iter().map(|f| f.short_path.clone()).collect();
+    assert_eq!(
+        paths,
+        [
+            PathBuf::from("dir/Readme.md"),
+            PathBuf::from("dir/README (2).md"),
+            PathBuf::from("dir/readme (1).md"),
+            PathBuf::from("DIR/readme (3).md"),
+            PathBuf::from("dir/other.md"),
+        ]
+    );
+
+    // nothing left to rename
+    assert!(offer.rename_case_collisions().is_empty());
+}
+
+#[tokio::test]
+async fn test_apply_path_policy() {
+    use gday_file_transfer::PathPolicy;
+
+    let file = |path: &str| gday_file_transfer::FileMeta {
+        short_path: PathBuf::from(path),
+        len: 5,
+        hash: None,
+        head_hash: None,
+        compressible: true,
+        mode: None,
+        modified: None,
+    };
+    let mut offer = FileOfferMsg {
+        files: vec![
+            file("dir/a:b.txt"),
+            file("dir/a_b.txt"),
+            file("aux/fine.txt"),
+        ],
+        compression: None,
+        symlinks: Vec::new(),
+        empty_dirs: vec![PathBuf::from("empty?")],
+        archive: true,
+        streams: None,
+        duplicates: BTreeMap::new(),
+    };
+    let policy = PathPolicy {
+        windows_names: true,
+        case_collisions: false,
+        ..PathPolicy::default()
+    };
+
+    let renamed = offer.apply_path_policy(&policy).unwrap();
+    assert_eq!(
+        renamed,
+        [
+            ("empty?".into(), "empty_".into()),
+            ("dir/a:b.txt".into(), "dir/a_b.txt".into()),
+            ("dir/a_b.txt".into(), "dir/a_b (1).txt".into()),
+            ("aux/fine.txt".into(), "aux_/fine.txt".into()),
+        ]
+    );
+    // the archive would hold the original paths
+    assert!(!offer.archive);
+
+    // nothing left to rename
+    assert!(offer.apply_path_policy(&policy).unwrap().is_empty());
+
+    // paths outside the save directory are rejected,
+    // even if the policy isn't applied
+    offer.files.push(file("../outside.txt"));
+    assert!(matches!(
+        offer.apply_path_policy(&policy),
+        Err(Error::UnsafePath(_))
+    ));
+    let response = FileResponseMsg::accept_all_files(&offer);
+    let save_dir = tempfile::tempdir().unwrap();
+    let result =
+        gday_file_transfer::receive_files(&offer, &response, save_dir.path(), &[][..], |_| ())
+            .await;
+    assert!(matches!(result, Err(Error::UnsafePath(_))));
+}
+
+/// Confirm that [`gday_file_transfer::is_case_insensitive()`]
+/// matches how the file system treats names, and leaves no files behind.
+#[test]
+fn test_is_case_insensitive() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    File::create_new(temp_dir.path().join("file")).unwrap();
+    let expected = temp_dir.path().join("FILE").exists();
+
+    assert_eq!(
+        gday_file_transfer::is_case_insensitive(temp_dir.path()).unwrap(),
+        expected
+    );
+    assert_eq!(std::fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn test_overview() {
+    use gday_file_transfer::{ExtensionStats, FileMeta, OfferOverview};
+
+    let file = |path: &str, len: u64| FileMeta {
+        short_path: PathBuf::from(path),
+        len,
+        hash: None,
+        head_hash: None,
+        compressible: true,
+        mode: None,
+        modified: None,
+    };
+    let offer = FileOfferMsg {
+        files: vec![
+            file("photos/a.JPG", 30),
+            file("photos/2024/june/b.jpg", 50),
+            file("notes.txt", 5),
+            file("README", 1),
+            file("backup.tar.gz", 40),
+        ],
+        compression: None,
+        symlinks: Vec::new(),
+        empty_dirs: vec![PathBuf::from("photos/2024/empty")],
+        archive: false,
+        streams: None,
+        duplicates: BTreeMap::new(),
+    };
+
+    let overview = OfferOverview::of(&offer, 2);
+    assert_eq!(
+        overview.by_extension,
+        [
+            ExtensionStats {
+                extension: Some("jpg".to_string()),
+                count: 2,
+                bytes: 80,
+            },
+            ExtensionStats {
+                extension: Some("gz".to_string()),
+                count: 1,
+                bytes: 40,
+            },
+            ExtensionStats {
+                extension: Some("txt".to_string()),
+                count: 1,
+                bytes: 5,
+            },
+            ExtensionStats {
+                extension: None,
+                count: 1,
+                bytes: 1,
+            },
+        ]
+    );
+    assert_eq!(overview.largest, [1, 4]);
+    assert_eq!(
+        overview.deepest_dir,
+        Some((PathBuf::from("photos/2024/june"), 3))
+    );
+
+    let empty = FileOfferMsg {
+        files: Vec::new(),
+        compression: None,
+        symlinks: Vec::new(),
+        empty_dirs: Vec::new(),
+        archive: false,
+        streams: None,
+        duplicates: BTreeMap::new(),
+    };
+    let overview = OfferOverview::of(&empty, 2);
+    assert!(overview.by_extension.is_empty());
+    assert!(overview.largest.is_empty());
+    assert_eq!(overview.deepest_dir, None);
 }
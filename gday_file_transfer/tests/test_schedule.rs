@@ -0,0 +1,41 @@
+#![forbid(unsafe_code)]
+#![warn(clippy::all)]
+use gday_file_transfer::wait_for_start;
+use std::time::{Duration, Instant};
+
+/// Confirm that both peers of [`wait_for_start()`]
+/// wait for the longer of their delays.
+#[tokio::test]
+async fn test_wait_for_start() {
+    let (mut stream_a, mut stream_b) = tokio::io::duplex(1000);
+    let mut ticks_a = Vec::new();
+
+    let begin = Instant::now();
+    let (result_a, result_b) = tokio::join!(
+        wait_for_start(&mut stream_a, Duration::ZERO, |left| ticks_a.push(left)),
+        wait_for_start(&mut stream_b, Duration::from_millis(300), |_| ())
+    );
+    result_a.unwrap();
+    result_b.unwrap();
+
+    assert!(begin.elapsed() >= Duration::from_millis(300));
+    assert!(ticks_a[0] > Duration::from_millis(200));
+    assert_eq!(ticks_a.last(), Some(&Duration::ZERO));
+}
+
+/// Confirm that [`wait_for_start()`] returns right away
+/// when neither peer wants to wait.
+#[tokio::test]
+async fn test_wait_for_start_immediately() {
+    let (mut stream_a, mut stream_b) = tokio::io::duplex(1000);
+
+    let begin = Instant::now();
+    let (result_a, result_b) = tokio::join!(
+        wait_for_start(&mut stream_a, Duration::ZERO, |_| ()),
+        wait_for_start(&mut stream_b, Duration::ZERO, |_| ())
+    );
+    result_a.unwrap();
+    result_b.unwrap();
+
+    assert!(begin.elapsed() < Duration::from_millis(100));
+}
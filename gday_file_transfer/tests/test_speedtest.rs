@@ -0,0 +1,24 @@
+#![forbid(unsafe_code)]
+#![warn(clippy::all)]
+use gday_file_transfer::run_speedtest;
+use std::time::Duration;
+
+/// Confirm that both peers of [`run_speedtest()`]
+/// get matching reports.
+#[tokio::test]
+async fn test_speedtest() {
+    let (mut stream_a, mut stream_b) = tokio::io::duplex(10_000);
+    let duration = Duration::from_millis(100);
+
+    let (report_a, report_b) = tokio::join!(
+        run_speedtest(&mut stream_a, true, duration),
+        run_speedtest(&mut stream_b, false, duration)
+    );
+    let report_a = report_a.unwrap();
+    let report_b = report_b.unwrap();
+
+    assert!(report_a.upload_speed > 0);
+    assert!(report_b.upload_speed > 0);
+    assert_eq!(report_a.upload_speed, report_b.download_speed);
+    assert_eq!(report_a.download_speed, report_b.upload_speed);
+}
@@ -0,0 +1,127 @@
+use crate::{Error, Secret};
+use log::debug;
+use sha2::Digest;
+use spake2::{Ed25519Group, Identity, Password, Spake2};
+use std::future::Future;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+};
+
+/// Mutually authenticates the peer on the other end of a
+/// hole-punched connection, and derives whatever the connection
+/// should be used with, such as a shared key.
+///
+/// [`Spake2Authenticator`] is used by default. Implement this
+/// to plug in an existing identity system instead,
+/// such as pre-shared strong keys or TLS client certificates, and pass it to
+/// [`crate::try_connect_to_peer_with()`].
+///
+/// Both peers must use the same kind of authenticator.
+pub trait PeerAuthenticator: Send + Sync + 'static {
+    /// What a successful authentication returns, for example
+    /// the stream and a key shared with the peer.
+    type Output: Send + 'static;
+
+    /// Authenticates the peer connected by `stream`.
+    ///
+    /// Should return an error, such as [`Error::PeerAuthenticationFailed`],
+    /// unless the peer proved its identity.
+    fn authenticate(
+        &self,
+        stream: TcpStream,
+    ) -> impl Future<Output = Result<Self::Output, Error>> + Send;
+}
+
+/// The default [`PeerAuthenticator`].
+///
+/// Uses [SPAKE2](https://docs.rs/spake2/) to derive a cryptographically
+/// secure shared key from a weak `shared_secret` that both peers know.
+/// Then verifies that the peer derived the same key.
+#[derive(Debug, Clone)]
+pub struct Spake2Authenticator {
+    shared_secret: Secret<Vec<u8>>,
+}
+
+impl Spake2Authenticator {
+    /// Creates a [`Spake2Authenticator`] for peers that know `shared_secret`.
+    pub fn new(shared_secret: &Secret<impl AsRef<[u8]>>) -> Self {
+        Self {
+            shared_secret: Secret::new(shared_secret.expose_secret().as_ref().to_vec()),
+        }
+    }
+}
+
+impl PeerAuthenticator for Spake2Authenticator {
+    /// The authenticated stream, and the derived 32-byte shared key
+    type Output = (TcpStream, Secret<[u8; 32]>);
+
+    async fn authenticate(&self, mut stream: TcpStream) -> Result<Self::Output, Error> {
+        let shared_key = self.derive_key(&mut stream).await?;
+        Ok((stream, shared_key))
+    }
+}
+
+impl Spake2Authenticator {
+    /// Derives a key shared with the peer on the other end of `stream`,
+    /// and verifies that the peer derived the same one.
+    pub(crate) async fn derive_key(
+        &self,
+        stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+    ) -> Result<Secret<[u8; 32]>, Error> {
+        //// Password authenticated key exchange ////
+        let (spake, outbound_msg) = Spake2::<Ed25519Group>::start_symmetric(
+            &Password::new(self.shared_secret.expose_secret()),
+            &Identity::new(b"gday mates"),
+        );
+
+        stream.write_all(&outbound_msg).await?;
+        stream.flush().await?;
+
+        let mut inbound_msg = [0; 33];
+        stream.read_exact(&mut inbound_msg).await?;
+
+        let shared_key: [u8; 32] = spake
+            .finish(&inbound_msg)?
+            .try_into()
+            .expect("Unreachable: Key is always 32 bytes long.");
+
+        debug!("Derived a strong key with the peer. Will now verify we both have the same key.");
+
+        //// Mutually verify that we have the same `shared_key` ////
+
+        // send a random challenge to the peer
+        let my_challenge: [u8; 32] = rand::random();
+        stream.write_all(&my_challenge).await?;
+        stream.flush().await?;
+
+        // receive the peer's random challenge
+        let mut peer_challenge = [0; 32];
+        stream.read_exact(&mut peer_challenge).await?;
+
+        // reply with the solution hash to the peer's challenge
+        let mut hasher = sha2::Sha256::new();
+        hasher.update(shared_key);
+        hasher.update(peer_challenge);
+        let my_hash = hasher.finalize();
+        stream.write_all(&my_hash).await?;
+        stream.flush().await?;
+
+        // receive peer's hash to my challenge
+        let mut peer_hash = [0; 32];
+        stream.read_exact(&mut peer_hash).await?;
+
+        // confirm peer's hash to my challenge
+        let mut hasher = sha2::Sha256::new();
+        hasher.update(shared_key);
+        hasher.update(my_challenge);
+        let expected = hasher.finalize();
+
+        // Peer authentication failed
+        if expected != peer_hash.into() {
+            return Err(Error::PeerAuthenticationFailed);
+        }
+
+        Ok(Secret::new(shared_key))
+    }
+}
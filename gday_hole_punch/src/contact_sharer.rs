@@ -1,4 +1,4 @@
-use crate::{server_connector::ServerConnection, Error};
+use crate::{server_connector::ServerConnection, Error, Secret};
 use gday_contact_exchange_protocol::{
     read_from_async, write_to_async, ClientMsg, FullContact, ServerMsg,
 };
@@ -8,6 +8,8 @@ use std::future::Future;
 /// Shares contacts on `room_code` in the gday server
 /// that `server_connection` is connected to.
 ///
+/// Only a hash of `room_code` is sent to the server.
+///
 /// If `is_creator`, tries creating the room, otherwise tries joining it.
 ///
 /// Returns
@@ -17,7 +19,7 @@ use std::future::Future;
 ///   the peer's [`FullContact`].
 pub async fn share_contacts<'a>(
     server_connection: &'a mut ServerConnection,
-    room_code: &[u8],
+    room_code: &Secret<impl AsRef<[u8]>>,
     is_creator: bool,
 ) -> Result<
     (
@@ -26,25 +28,14 @@ pub async fn share_contacts<'a>(
     ),
     Error,
 > {
-    // Hash the `room_code` to get a 32-bit long code
-    let mut hasher = sha2::Sha256::new();
-    hasher.update(room_code);
-    let room_code: [u8; 32] = hasher.finalize().into();
+    let room_code = hash_room_code(room_code);
 
     // set reuse addr and reuse port, so that these sockets
     // can be later reused for hole punching
     server_connection.enable_reuse()?;
 
     if is_creator {
-        // choose a stream to talk to the server with
-        let messenger = &mut server_connection.streams()[0];
-
-        // try creating a room in the server
-        write_to_async(ClientMsg::CreateRoom { room_code }, messenger).await?;
-        let response: ServerMsg = read_from_async(messenger).await?;
-        if response != ServerMsg::RoomCreated {
-            return Err(Error::UnexpectedServerReply(response));
-        }
+        create_hashed_room(server_connection, room_code).await?;
     }
 
     // send personal socket addresses to the server
@@ -53,6 +44,45 @@ pub async fn share_contacts<'a>(
     Ok((my_contact, get_peer_contact(server_connection)))
 }
 
+/// Creates a room with `room_code` in the gday server
+/// that `server_connection` is connected to, without sharing contacts in it.
+///
+/// Only a hash of `room_code` is sent to the server.
+///
+/// The server only relays peers that met in a room, so the creator should call this
+/// before [`crate::connect_through_relay()`], unless it called [`share_contacts()`].
+pub async fn create_room(
+    server_connection: &mut ServerConnection,
+    room_code: &Secret<impl AsRef<[u8]>>,
+) -> Result<(), Error> {
+    create_hashed_room(server_connection, hash_room_code(room_code)).await
+}
+
+/// Hashes `room_code` to get the 32-byte code sent to the server.
+pub(crate) fn hash_room_code(room_code: &Secret<impl AsRef<[u8]>>) -> [u8; 32] {
+    let mut hasher = sha2::Sha256::new();
+    hasher.update(room_code.expose_secret());
+    hasher.finalize().into()
+}
+
+/// Private helper function.
+/// Creates a room with the hashed `room_code` in the server.
+async fn create_hashed_room(
+    server_connection: &mut ServerConnection,
+    room_code: [u8; 32],
+) -> Result<(), Error> {
+    // choose a stream to talk to the server with
+    let messenger = &mut server_connection.streams()[0];
+
+    // try creating a room in the server
+    write_to_async(ClientMsg::CreateRoom { room_code }, messenger).await?;
+    let response: ServerMsg = read_from_async(messenger).await?;
+    if response != ServerMsg::RoomCreated {
+        return Err(Error::UnexpectedServerReply(response));
+    }
+    Ok(())
+}
+
 /// Private helper function.
 /// Sends personal contact information the the server, and
 /// returns its response.
@@ -101,7 +131,11 @@ async fn share_contact(
 /// Blocks until the Gday server sends the contact information the
 /// other peer submitted. Returns the peer's [`FullContact`], as
 /// determined by the server.
-async fn get_peer_contact(connection: &mut ServerConnection) -> Result<FullContact, Error> {
+///
+/// The future returned by [`share_contacts()`] does the same.
+/// Call this instead if that future was dropped before it finished, such as to
+/// store `connection` somewhere while waiting for the peer.
+pub async fn get_peer_contact(connection: &mut ServerConnection) -> Result<FullContact, Error> {
     // This is the same stream we used to send DoneSending,
     // so the server should respond on it,
     // once the other peer is also done.
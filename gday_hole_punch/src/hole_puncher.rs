@@ -1,21 +1,111 @@
-use crate::Error;
-use gday_contact_exchange_protocol::{Contact, FullContact};
-use log::{debug, trace};
-use sha2::Digest;
+use crate::{
+    ipv6_addrs::{self, AddrStatus},
+    Error, PeerAuthenticator, Secret, Spake2Authenticator,
+};
+use gday_contact_exchange_protocol::{Contact, FullContact, PortMapping};
+use log::{debug, trace, warn};
 use socket2::{SockRef, TcpKeepalive};
-use spake2::{Ed25519Group, Identity, Password, Spake2};
-use std::{net::SocketAddr, time::Duration};
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpSocket,
+use std::{
+    fmt::Display,
+    net::{SocketAddr, SocketAddrV6},
+    sync::Arc,
+    time::{Duration, Instant},
 };
+use tokio::net::TcpSocket;
 
 /// Alias to the return type of [`try_connect_to_peer()`].
-type PeerConnection = (tokio::net::TcpStream, [u8; 32]);
+type PeerConnection = (tokio::net::TcpStream, Secret<[u8; 32]>, ConnectionInfo);
+
+/// How [`try_connect_to_peer()`] connected to the peer.
+///
+/// Useful for showing the user how they're connected,
+/// or debugging hole punching through their NAT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    /// This peer's socket address of the connection.
+    pub local_addr: SocketAddr,
+    /// The other peer's socket address of the connection.
+    pub peer_addr: SocketAddr,
+    /// Which of the other peer's addresses [`Self::peer_addr`] is.
+    pub candidate: Candidate,
+    /// `true` if this peer accepted the connection,
+    /// `false` if it connected to the other peer.
+    pub accepted: bool,
+    /// How long the [`PeerAuthenticator`] took to authenticate the peer.
+    ///
+    /// Authenticating takes a few round trips,
+    /// so this is roughly a multiple of the round-trip time to the peer.
+    pub handshake_time: Duration,
+}
+
+impl ConnectionInfo {
+    /// Returns `true` if the connection is over IPv6.
+    pub fn is_ipv6(&self) -> bool {
+        self.peer_addr.is_ipv6()
+    }
+}
+
+/// Which of the addresses in the peer's [`FullContact`]
+/// a connection is with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Candidate {
+    /// The peer's [`FullContact::public`] address,
+    /// or a port after it that its NAT likely mapped.
+    Public,
+    /// The peer's [`FullContact::local`] address,
+    /// for example because both peers are on the same network.
+    Local,
+    /// Neither, for example because the connection
+    /// was accepted through an unexpected NAT mapping.
+    Unknown,
+}
+
+impl Candidate {
+    /// Returns which of the addresses in `peer_contact` is `peer_addr`.
+    ///
+    /// Compares whole socket addresses, since a peer
+    /// that isn't behind a NAT has the same public and local IP.
+    fn of(peer_addr: SocketAddr, peer_contact: &FullContact) -> Self {
+        let has_addr = |contact: &Contact| {
+            contact.v4.map(SocketAddr::V4) == Some(peer_addr)
+                || contact.v6.map(SocketAddr::V6) == Some(peer_addr)
+        };
+        // one of the ports tried after the public IPv4 port
+        let is_predicted = match (peer_contact.public.v4, peer_addr) {
+            (Some(public), SocketAddr::V4(addr)) => {
+                addr.ip() == public.ip()
+                    && (1..=PREDICTED_PORTS)
+                        .any(|offset| public.port().checked_add(offset) == Some(addr.port()))
+            }
+            _ => false,
+        };
+        if has_addr(&peer_contact.public) || is_predicted {
+            Self::Public
+        } else if has_addr(&peer_contact.local) {
+            Self::Local
+        } else {
+            Self::Unknown
+        }
+    }
+}
+
+impl Display for Candidate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Public => write!(f, "public"),
+            Self::Local => write!(f, "local"),
+            Self::Unknown => write!(f, "unknown"),
+        }
+    }
+}
 
 /// How often a connection attempt is made during hole punching.
 const RETRY_INTERVAL: Duration = Duration::from_millis(200);
 
+/// Number of ports after the peer's public IPv4 port to also try
+/// connecting to, if its NAT maps each connection to a new port.
+const PREDICTED_PORTS: u16 = 8;
+
 /// Tries to connect to the other peer using
 /// [TCP hole punching](https://en.wikipedia.org/wiki/TCP_hole_punching).
 ///
@@ -29,17 +119,39 @@ const RETRY_INTERVAL: Duration = Duration::from_millis(200);
 ///   It will be used to verify the peer's identity, and derive a stronger shared key
 ///   using [SPAKE2](https://docs.rs/spake2/).
 ///
+/// If the server reported in [`FullContact::nat`] that the peer's NAT
+/// maps each connection to a new port, also tries the next few ports.
+///
 /// Returns:
 /// - An authenticated [`std::net::TcpStream`] connected to the other peer.
-/// - A `[u8; 32]` shared key that was derived using
-///     [SPAKE2](https://docs.rs/spake2/) from the weaker `shared_secret`.
+/// - A 32-byte shared key that was derived using
+///   [SPAKE2](https://docs.rs/spake2/) from the weaker `shared_secret`.
+/// - [`ConnectionInfo`] about how the peers connected.
 pub async fn try_connect_to_peer(
     local_contact: Contact,
     peer_contact: FullContact,
-    shared_secret: &[u8],
+    shared_secret: &Secret<impl AsRef<[u8]>>,
 ) -> Result<PeerConnection, Error> {
+    let authenticator = Spake2Authenticator::new(shared_secret);
+    let ((stream, shared_key), info) =
+        try_connect_to_peer_with(local_contact, peer_contact, authenticator).await?;
+    Ok((stream, shared_key, info))
+}
+
+/// Like [`try_connect_to_peer()`], but verifies the peer's identity
+/// with a custom `authenticator` instead of
+/// [SPAKE2](https://docs.rs/spake2/).
+///
+/// Returns the [`PeerAuthenticator::Output`] of the first
+/// connection that `authenticator` authenticated,
+/// and [`ConnectionInfo`] about that connection.
+pub async fn try_connect_to_peer_with<A: PeerAuthenticator>(
+    local_contact: Contact,
+    peer_contact: FullContact,
+    authenticator: A,
+) -> Result<(A::Output, ConnectionInfo), Error> {
     // shorten the variable name for brevity
-    let p = shared_secret;
+    let p = Arc::new(authenticator);
 
     // A set of tasks that will run concurrently,
     // trying to establish a connection to the peer.
@@ -48,32 +160,49 @@ pub async fn try_connect_to_peer(
     // If we have an IPv4 socket address
     if let Some(local) = local_contact.v4 {
         // listen to connections from the peer
-        tasks.spawn(try_accept(local, p.to_vec()));
+        tasks.spawn(try_accept(local, p.clone()));
 
         // try connecting to the peer's private socket address
         if let Some(peer) = peer_contact.local.v4 {
-            tasks.spawn(try_connect(local, peer, p.to_vec()));
+            tasks.spawn(try_connect(local, peer, p.clone()));
         }
 
         // try connecting to the peer's public socket address
         if let Some(peer) = peer_contact.public.v4 {
-            tasks.spawn(try_connect(local, peer, p.to_vec()));
+            tasks.spawn(try_connect(local, peer, p.clone()));
+
+            // the peer's NAT probably gave its connection to us a new port,
+            // likely one of the next few
+            if peer_contact.nat.port_mapping == PortMapping::Varies {
+                debug!(
+                    "Peer's NAT seems to map each connection to a new port, \
+                    so also trying the {PREDICTED_PORTS} ports after {peer}."
+                );
+                for offset in 1..=PREDICTED_PORTS {
+                    let Some(port) = peer.port().checked_add(offset) else {
+                        break;
+                    };
+                    let mut predicted = peer;
+                    predicted.set_port(port);
+                    tasks.spawn(try_connect(local, predicted, p.clone()));
+                }
+            }
         }
     }
 
-    // If we have an IPv6 socket address
-    if let Some(local) = local_contact.v6 {
+    // If we have an IPv6 socket address that still works
+    if let Some(local) = local_contact.v6.filter(is_still_assigned) {
         // listen to connections from the peer
-        tasks.spawn(try_accept(local, p.to_vec()));
+        tasks.spawn(try_accept(local, p.clone()));
 
         // try connecting to the peer's private socket address
         if let Some(peer) = peer_contact.local.v6 {
-            tasks.spawn(try_connect(local, peer, p.to_vec()));
+            tasks.spawn(try_connect(local, peer, p.clone()));
         }
 
         // try connecting to the peer's public socket address
         if let Some(peer) = peer_contact.public.v6 {
-            tasks.spawn(try_connect(local, peer, p.to_vec()));
+            tasks.spawn(try_connect(local, peer, p.clone()));
         }
     }
 
@@ -84,7 +213,15 @@ pub async fn try_connect_to_peer(
     // wrong. Otherwise they'll keep trying.
     match tasks.join_next().await {
         // A task finished
-        Some(Ok(result)) => result,
+        Some(Ok(result)) => {
+            let (output, mut info) = result?;
+            info.candidate = Candidate::of(info.peer_addr, &peer_contact);
+            debug!(
+                "Connected to the peer's {} address {} from {}.",
+                info.candidate, info.peer_addr, info.local_addr
+            );
+            Ok((output, info))
+        }
 
         // Couldn't join the task
         Some(Err(..)) => panic!("Tokio join error."),
@@ -94,13 +231,42 @@ pub async fn try_connect_to_peer(
     }
 }
 
+/// Returns `false` if the local IPv6 address `local` has been removed from
+/// this host, for example because it was a temporary privacy address.
+///
+/// Hole punching from a removed address would fail, so it's better to only
+/// use IPv4. To get a fresh contact, reconnect to the server
+/// and share contacts again.
+fn is_still_assigned(local: &SocketAddrV6) -> bool {
+    match ipv6_addrs::addr_status(*local.ip()) {
+        AddrStatus::Missing => {
+            warn!(
+                "Not hole punching over IPv6, since local address {} \
+                is no longer assigned to this host.",
+                local.ip()
+            );
+            false
+        }
+        AddrStatus::Deprecated => {
+            warn!(
+                "Local IPv6 address {} is deprecated, so hole punching over IPv6 may fail.",
+                local.ip()
+            );
+            true
+        }
+        AddrStatus::Stable | AddrStatus::Temporary | AddrStatus::Unknown => true,
+    }
+}
+
 /// Tries to TCP connect from `local` to `peer`,
-/// and authenticate using `shared_secret`.
-async fn try_connect<T: Into<SocketAddr>>(
+/// and authenticate using `authenticator`.
+///
+/// The returned [`ConnectionInfo::candidate`] is always [`Candidate::Unknown`].
+async fn try_connect<T: Into<SocketAddr>, A: PeerAuthenticator>(
     local: T,
     peer: T,
-    shared_secret: Vec<u8>,
-) -> Result<PeerConnection, Error> {
+    authenticator: Arc<A>,
+) -> Result<(A::Output, ConnectionInfo), Error> {
     let local = local.into();
     let peer = peer.into();
     let mut interval = tokio::time::interval(RETRY_INTERVAL);
@@ -116,15 +282,17 @@ async fn try_connect<T: Into<SocketAddr>>(
     };
 
     debug!("Connected from {local} to {peer}. Will try to authenticate.");
-    verify_peer(&shared_secret, stream).await
+    authenticate(stream, local, peer, false, &*authenticator).await
 }
 
 /// Tries to accept a peer TCP connection on `local`,
-/// and authenticate using `shared_secret`.
-async fn try_accept(
+/// and authenticate using `authenticator`.
+///
+/// The returned [`ConnectionInfo::candidate`] is always [`Candidate::Unknown`].
+async fn try_accept<A: PeerAuthenticator>(
     local: impl Into<SocketAddr>,
-    shared_secret: Vec<u8>,
-) -> Result<PeerConnection, Error> {
+    authenticator: Arc<A>,
+) -> Result<(A::Output, ConnectionInfo), Error> {
     let local = local.into();
     let mut interval = tokio::time::interval(RETRY_INTERVAL);
     trace!("Waiting to accept connections on {local}.");
@@ -141,72 +309,28 @@ async fn try_accept(
     };
 
     debug!("Received connection on {local} from {addr}. Will try to authenticate.");
-    verify_peer(&shared_secret, stream).await
+    authenticate(stream, local, addr, true, &*authenticator).await
 }
 
-/// Uses [SPAKE 2](https://docs.rs/spake2/latest/spake2/)
-/// to derive a cryptographically secure secret from
-/// a `weak_secret`.
-/// Verifies that the other peer derived the same secret.
-/// If successful, returns a [`PeerConnection`].
-async fn verify_peer(
-    weak_secret: &[u8],
-    mut stream: tokio::net::TcpStream,
-) -> Result<PeerConnection, Error> {
-    //// Password authenticated key exchange ////
-    let (spake, outbound_msg) = Spake2::<Ed25519Group>::start_symmetric(
-        &Password::new(weak_secret),
-        &Identity::new(b"gday mates"),
-    );
-
-    stream.write_all(&outbound_msg).await?;
-    stream.flush().await?;
-
-    let mut inbound_msg = [0; 33];
-    stream.read_exact(&mut inbound_msg).await?;
-
-    let shared_key: [u8; 32] = spake
-        .finish(&inbound_msg)?
-        .try_into()
-        .expect("Unreachable: Key is always 32 bytes long.");
-
-    debug!("Derived a strong key with the peer. Will now verify we both have the same key.");
-
-    //// Mutually verify that we have the same `shared_key` ////
-
-    // send a random challenge to the peer
-    let my_challenge: [u8; 32] = rand::random();
-    stream.write_all(&my_challenge).await?;
-    stream.flush().await?;
-
-    // receive the peer's random challenge
-    let mut peer_challenge = [0; 32];
-    stream.read_exact(&mut peer_challenge).await?;
-
-    // reply with the solution hash to the peer's challenge
-    let mut hasher = sha2::Sha256::new();
-    hasher.update(shared_key);
-    hasher.update(peer_challenge);
-    let my_hash = hasher.finalize();
-    stream.write_all(&my_hash).await?;
-    stream.flush().await?;
-
-    // receive peer's hash to my challenge
-    let mut peer_hash = [0; 32];
-    stream.read_exact(&mut peer_hash).await?;
-
-    // confirm peer's hash to my challenge
-    let mut hasher = sha2::Sha256::new();
-    hasher.update(shared_key);
-    hasher.update(my_challenge);
-    let expected = hasher.finalize();
-
-    // Peer authentication failed
-    if expected != peer_hash.into() {
-        return Err(Error::PeerAuthenticationFailed);
-    }
-
-    Ok((stream, shared_key))
+/// Authenticates the peer on `stream` with `authenticator`,
+/// timing how long that takes.
+async fn authenticate<A: PeerAuthenticator>(
+    stream: tokio::net::TcpStream,
+    local_addr: SocketAddr,
+    peer_addr: SocketAddr,
+    accepted: bool,
+    authenticator: &A,
+) -> Result<(A::Output, ConnectionInfo), Error> {
+    let start = Instant::now();
+    let output = authenticator.authenticate(stream).await?;
+    let info = ConnectionInfo {
+        local_addr,
+        peer_addr,
+        candidate: Candidate::Unknown,
+        accepted,
+        handshake_time: start.elapsed(),
+    };
+    Ok((output, info))
 }
 
 /// Makes a new socket with this address.
@@ -235,3 +359,46 @@ fn get_local_socket(local_addr: SocketAddr) -> std::io::Result<TcpSocket> {
     socket.bind(local_addr)?;
     Ok(socket)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Candidate;
+    use gday_contact_exchange_protocol::{Contact, FullContact};
+
+    #[test]
+    fn test_candidate() {
+        // a peer that isn't behind a NAT
+        let addr = "203.0.113.5:5000".parse().unwrap();
+        let contact = FullContact {
+            local: Contact {
+                v4: Some(addr),
+                v6: None,
+            },
+            public: Contact {
+                v4: Some(addr),
+                v6: None,
+            },
+            nat: Default::default(),
+        };
+        assert_eq!(Candidate::of(addr.into(), &contact), Candidate::Public);
+        // the same IP, but a port neither the peer nor its server reported
+        let other = "203.0.113.5:6000".parse().unwrap();
+        assert_eq!(Candidate::of(other, &contact), Candidate::Unknown);
+        // a port after the public one, as tried for NATs that vary ports
+        let predicted = "203.0.113.5:5003".parse().unwrap();
+        assert_eq!(Candidate::of(predicted, &contact), Candidate::Public);
+
+        // a peer behind a NAT
+        let contact = FullContact {
+            local: Contact {
+                v4: Some("192.168.1.2:5000".parse().unwrap()),
+                v6: None,
+            },
+            ..contact
+        };
+        let local = "192.168.1.2:5000".parse().unwrap();
+        assert_eq!(Candidate::of(local, &contact), Candidate::Local);
+        let local_other = "192.168.1.2:5001".parse().unwrap();
+        assert_eq!(Candidate::of(local_other, &contact), Candidate::Unknown);
+    }
+}
@@ -0,0 +1,203 @@
+//! Tells stable IPv6 addresses apart from temporary ones.
+//!
+//! Hosts with IPv6 privacy extensions regularly create new temporary
+//! addresses and deprecate old ones. A contact that uses a temporary address
+//! may stop working partway through a session, so stable addresses are
+//! preferred when connecting to the server.
+//!
+//! Only implemented on Linux. Elsewhere, every address is
+//! [`AddrStatus::Unknown`], and the operating system's choice is kept.
+
+use std::net::{Ipv6Addr, SocketAddr};
+
+/// `IFA_F_TEMPORARY` flag of an address in `/proc/net/if_inet6`
+const FLAG_TEMPORARY: u8 = 0x01;
+/// `IFA_F_DADFAILED` flag of an address in `/proc/net/if_inet6`
+const FLAG_DAD_FAILED: u8 = 0x08;
+/// `IFA_F_DEPRECATED` flag of an address in `/proc/net/if_inet6`
+const FLAG_DEPRECATED: u8 = 0x20;
+/// `IFA_F_TENTATIVE` flag of an address in `/proc/net/if_inet6`
+const FLAG_TENTATIVE: u8 = 0x40;
+
+/// How long an IPv6 address of this host is expected to keep working.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddrStatus {
+    /// A usable address that isn't going away
+    Stable,
+    /// A privacy address that will be deprecated after a while
+    Temporary,
+    /// An address that still works for existing connections,
+    /// but shouldn't be used for new ones
+    Deprecated,
+    /// The address isn't assigned to this host (anymore)
+    Missing,
+    /// Couldn't find out
+    Unknown,
+}
+
+/// An IPv6 address assigned to one of this host's interfaces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct InterfaceAddr {
+    ip: Ipv6Addr,
+    /// Index of the interface with this address
+    interface: u32,
+    /// Scope of the address. `0` is global.
+    scope: u8,
+    /// `IFA_F_*` flags of the address
+    flags: u8,
+}
+
+impl InterfaceAddr {
+    fn status(&self) -> AddrStatus {
+        if self.flags & FLAG_DEPRECATED != 0 {
+            AddrStatus::Deprecated
+        } else if self.flags & FLAG_TEMPORARY != 0 {
+            AddrStatus::Temporary
+        } else {
+            AddrStatus::Stable
+        }
+    }
+
+    /// Whether this address can be used for new connections
+    /// to the internet.
+    fn is_usable_global(&self) -> bool {
+        self.scope == 0 && self.flags & (FLAG_TENTATIVE | FLAG_DAD_FAILED) == 0
+    }
+}
+
+/// Returns the [`AddrStatus`] of `ip`, one of this host's addresses.
+pub fn addr_status(ip: Ipv6Addr) -> AddrStatus {
+    match interface_addrs() {
+        Some(addrs) => status_in(&addrs, ip),
+        None => AddrStatus::Unknown,
+    }
+}
+
+/// Returns a stable address to connect to `remote` from, if the one the
+/// operating system would choose is temporary or deprecated.
+///
+/// Returns `None` if the operating system's choice should be kept.
+pub fn stable_source_for(remote: SocketAddr) -> Option<Ipv6Addr> {
+    // Ask the operating system which address it would use.
+    // Connecting a UDP socket doesn't send anything.
+    let probe = std::net::UdpSocket::bind("[::]:0").ok()?;
+    probe.connect(remote).ok()?;
+    let SocketAddr::V6(source) = probe.local_addr().ok()? else {
+        return None;
+    };
+
+    let addrs = interface_addrs()?;
+    stable_alternative(&addrs, *source.ip())
+}
+
+/// Returns the [`AddrStatus`] of `ip` among `addrs`.
+fn status_in(addrs: &[InterfaceAddr], ip: Ipv6Addr) -> AddrStatus {
+    addrs
+        .iter()
+        .find(|addr| addr.ip == ip)
+        .map_or(AddrStatus::Missing, InterfaceAddr::status)
+}
+
+/// If `ip` is temporary or deprecated, returns a stable global
+/// address on the same interface.
+fn stable_alternative(addrs: &[InterfaceAddr], ip: Ipv6Addr) -> Option<Ipv6Addr> {
+    let current = addrs.iter().find(|addr| addr.ip == ip)?;
+    if current.status() == AddrStatus::Stable {
+        return None;
+    }
+
+    addrs
+        .iter()
+        .find(|addr| {
+            addr.interface == current.interface
+                && addr.is_usable_global()
+                && addr.status() == AddrStatus::Stable
+        })
+        .map(|addr| addr.ip)
+}
+
+/// Returns this host's IPv6 addresses, or `None` if they couldn't be listed.
+fn interface_addrs() -> Option<Vec<InterfaceAddr>> {
+    #[cfg(target_os = "linux")]
+    {
+        let contents = std::fs::read_to_string("/proc/net/if_inet6").ok()?;
+        Some(parse_if_inet6(&contents))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        None
+    }
+}
+
+/// Parses the contents of Linux's `/proc/net/if_inet6`.
+///
+/// Each line holds an address in hex, then the interface index,
+/// prefix length, scope and flags (also in hex), then the interface name.
+/// Skips lines that can't be parsed.
+#[cfg_attr(not(any(target_os = "linux", test)), allow(dead_code))]
+fn parse_if_inet6(contents: &str) -> Vec<InterfaceAddr> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [ip, interface, _prefix_len, scope, flags, _name] = fields[..] else {
+                return None;
+            };
+            Some(InterfaceAddr {
+                ip: Ipv6Addr::from(u128::from_str_radix(ip, 16).ok()?),
+                interface: u32::from_str_radix(interface, 16).ok()?,
+                scope: u8::from_str_radix(scope, 16).ok()?,
+                flags: u8::from_str_radix(flags, 16).ok()?,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_if_inet6, stable_alternative, status_in, AddrStatus};
+
+    const IF_INET6: &str = "\
+20010db8000000000000000000000001 02 40 00 80     eth0
+20010db80000000012345678abcdef01 02 40 00 01     eth0
+20010db800000000aaaaaaaaaaaaaaaa 02 40 00 21     eth0
+20010db8000000010000000000000001 03 40 00 80    wlan0
+fe800000000000000000000000000001 02 40 20 80     eth0
+00000000000000000000000000000001 01 80 10 80       lo
+";
+
+    #[test]
+    fn test_addr_status() {
+        let addrs = parse_if_inet6(IF_INET6);
+        assert_eq!(addrs.len(), 6);
+
+        let status = |ip: &str| status_in(&addrs, ip.parse().unwrap());
+        assert_eq!(status("2001:db8::1"), AddrStatus::Stable);
+        assert_eq!(
+            status("2001:db8::1234:5678:abcd:ef01"),
+            AddrStatus::Temporary
+        );
+        assert_eq!(
+            status("2001:db8::aaaa:aaaa:aaaa:aaaa"),
+            AddrStatus::Deprecated
+        );
+        assert_eq!(status("2001:db8::99"), AddrStatus::Missing);
+    }
+
+    #[test]
+    fn test_stable_alternative() {
+        let addrs = parse_if_inet6(IF_INET6);
+        let alternative = |ip: &str| stable_alternative(&addrs, ip.parse().unwrap());
+
+        // temporary and deprecated addresses are replaced with
+        // the stable global address on the same interface
+        let expected = Some("2001:db8::1".parse().unwrap());
+        assert_eq!(alternative("2001:db8::1234:5678:abcd:ef01"), expected);
+        assert_eq!(alternative("2001:db8::aaaa:aaaa:aaaa:aaaa"), expected);
+
+        // stable and unknown addresses are kept
+        assert_eq!(alternative("2001:db8::1"), None);
+        assert_eq!(alternative("2001:db8::99"), None);
+    }
+}
@@ -9,6 +9,7 @@
 //! # use gday_hole_punch::server_connector;
 //! # use gday_hole_punch::try_connect_to_peer;
 //! # use gday_hole_punch::PeerCode;
+//! # use gday_hole_punch::Secret;
 //! # use gday_hole_punch::share_contacts;
 //! # use std::str::FromStr;
 //! #
@@ -28,15 +29,15 @@
 //! // over an existing channel like email.
 //! let peer_code = PeerCode {
 //!     server_id,
-//!     room_code: "roomcode".to_string(),
-//!     shared_secret: "shared_secret".to_string()
+//!     room_code: Secret::new("roomcode".to_string()),
+//!     shared_secret: Secret::new("shared_secret".to_string()),
 //! };
 //! let code_to_share = String::try_from(&peer_code)?;
 //!
 //! // Create a room in the server, and get my contact from it
 //! let (my_contact, peer_contact_future) = share_contacts(
 //!     &mut server_connection,
-//!     peer_code.room_code.as_bytes(),
+//!     &peer_code.room_code,
 //!     true,
 //! ).await?;
 //!
@@ -46,10 +47,10 @@
 //! // Use TCP hole-punching to connect to the peer,
 //! // verify their identity with the shared_secret,
 //! // and get a cryptographically-secure shared key
-//! let (tcp_stream, strong_key) = try_connect_to_peer(
+//! let (tcp_stream, strong_key, connection_info) = try_connect_to_peer(
 //!     my_contact.local,
 //!     peer_contact,
-//!     peer_code.shared_secret.as_bytes(),
+//!     &peer_code.shared_secret,
 //! ).await?;
 //!
 //! //////// Peer 2 (on a different computer) ////////
@@ -68,16 +69,16 @@
 //! // Join the same room in the server, and get my local contact
 //! let (my_contact, peer_contact_future) = share_contacts(
 //!     &mut server_connection,
-//!     peer_code.room_code.as_bytes(),
+//!     &peer_code.room_code,
 //!     false,
 //! ).await?;
 //!
 //! let peer_contact = peer_contact_future.await?;
 //!
-//! let (tcp_stream, strong_key) = try_connect_to_peer(
+//! let (tcp_stream, strong_key, connection_info) = try_connect_to_peer(
 //!     my_contact.local,
 //!     peer_contact,
-//!     peer_code.shared_secret.as_bytes(),
+//!     &peer_code.shared_secret,
 //! ).await?;
 //!
 //! # Ok::<(), Box<dyn std::error::Error>>(())
@@ -87,15 +88,25 @@
 #![forbid(unsafe_code)]
 #![warn(clippy::all)]
 
+mod authenticator;
 mod contact_sharer;
 mod hole_puncher;
+mod ipv6_addrs;
+mod local_network;
 mod peer_code;
+mod proxy;
+mod relay;
+mod secret;
 pub mod server_connector;
 
-pub use contact_sharer::share_contacts;
+pub use authenticator::{PeerAuthenticator, Spake2Authenticator};
+pub use contact_sharer::{create_room, get_peer_contact, share_contacts};
 use gday_contact_exchange_protocol::ServerMsg;
-pub use hole_puncher::try_connect_to_peer;
+pub use hole_puncher::{try_connect_to_peer, try_connect_to_peer_with, Candidate, ConnectionInfo};
+pub use local_network::{connect_on_local_network, connect_on_local_network_with};
 pub use peer_code::PeerCode;
+pub use relay::connect_through_relay;
+pub use secret::Secret;
 
 /// `gday_hole_punch` error
 #[derive(thiserror::Error, Debug)]
@@ -145,6 +156,17 @@ pub enum Error {
     #[error("No contact exchange server with ID '{0}' exists in server list.")]
     ServerIDNotFound(u64),
 
+    /// A [`server_connector::Proxy`] URL wasn't of form `"socks5://host:port"` or `"http://host:port"`.
+    #[error(
+        "Invalid proxy URL '{0}'. \
+    Use a URL like \"socks5://127.0.0.1:9050\" or \"http://proxy.example.com:8080\"."
+    )]
+    InvalidProxyUrl(String),
+
+    /// Couldn't connect to the contact exchange server through a [`server_connector::Proxy`].
+    #[error("Couldn't connect to the server through the proxy: {0}.")]
+    ProxyFailed(String),
+
     /// Couldn't connect to any of the contact exchange servers listed
     #[error("Couldn't connect to any of the contact exchange servers in the list.")]
     CouldntConnectToServers,
@@ -159,11 +181,26 @@ pub enum Error {
         "Timed out while trying to connect to peer, likely due to an uncooperative \
     NAT (network address translator). \
     Enable IPv6 or try from a different network. \
-    Or use a tool such as magic-wormhole that transfers \
-    over a relay to evade NATs."
+    Or, if your server relays traffic, connect through \
+    its relay with gday's --allow-relay."
     )]
     HolePunchTimeout,
 
+    /// Timed out while waiting for the peer to join the server's relay.
+    #[error(
+        "Timed out while waiting for your mate to connect through the server's relay. \
+    Both of you must allow relaying."
+    )]
+    RelayPeerTimedOut,
+
+    /// Timed out while looking for the peer on the local network.
+    #[error(
+        "Couldn't find your mate on the local network. \
+    Check that you're both on the same network, \
+    and that its firewall allows mDNS."
+    )]
+    LocalPeerNotFound,
+
     /// Couldn't parse server ID of [`PeerCode`]
     #[error("Couldn't parse the server ID in your code: {0}. Check it for typos!")]
     CouldntParseServerID(#[from] std::num::ParseIntError),
@@ -179,4 +216,11 @@ pub enum Error {
     /// Wrong number of settings in [`PeerCode`].
     #[error("Wrong number of segments in your code. Check it for typos!")]
     WrongNumberOfSegmentsPeerCode,
+
+    /// A [`PeerCode`] URI wasn't of form `"gday://server_id/room_code#shared_secret"`.
+    #[error(
+        "Your link isn't of form \"gday://server_id/room_code#shared_secret\". \
+    Check it for typos!"
+    )]
+    InvalidPeerCodeUri,
 }
@@ -0,0 +1,364 @@
+//! Connects to a peer on the same local network, without a contact exchange server.
+//!
+//! The peer that created the room advertises it as a
+//! [DNS-SD](https://en.wikipedia.org/wiki/Zero-configuration_networking#DNS-SD)
+//! service over [mDNS](https://en.wikipedia.org/wiki/Multicast_DNS),
+//! named after a hash of the room code. The other peer asks for that name,
+//! and connects to the port in the answer.
+//!
+//! Only the few DNS messages this needs are implemented, over IPv4.
+use crate::{Error, PeerAuthenticator, Secret, Spake2Authenticator};
+use log::{debug, trace};
+use sha2::{Digest, Sha256};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    time::Duration,
+};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+
+/// Multicast group and port of mDNS.
+const MDNS_ADDR: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(224, 0, 0, 251), 5353);
+
+/// DNS-SD service type that gday peers advertise rooms as.
+const SERVICE_TYPE: &str = "_gday._tcp.local";
+
+/// How often the peer that joins a room asks for it.
+const QUERY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long answers may be cached, in seconds.
+const ANSWER_TTL: u32 = 120;
+
+/// DNS record type of a service's port.
+const TYPE_SRV: u16 = 33;
+
+/// DNS query type that asks for every record type.
+const TYPE_ANY: u16 = 255;
+
+/// DNS class of internet records.
+const CLASS_IN: u16 = 1;
+
+/// Largest mDNS message to read.
+const MAX_MESSAGE_LEN: usize = 9000;
+
+/// Connects to the peer on the local network that uses the same `room_code`,
+/// without a contact exchange server.
+///
+/// The peer that would have created the room should set `is_creator` to `true`,
+/// and the other one to `false`. Keeps looking for the peer until it's found,
+/// so wrap this in a timeout.
+///
+/// Like [`crate::try_connect_to_peer()`], verifies the peer's identity with
+/// `shared_secret` using [SPAKE2](https://docs.rs/spake2/).
+///
+/// Returns:
+/// - An authenticated [`std::net::TcpStream`] connected to the other peer.
+/// - A 32-byte shared key that was derived using
+///   [SPAKE2](https://docs.rs/spake2/) from the weaker `shared_secret`.
+pub async fn connect_on_local_network(
+    room_code: &Secret<impl AsRef<[u8]>>,
+    shared_secret: &Secret<impl AsRef<[u8]>>,
+    is_creator: bool,
+) -> Result<(TcpStream, Secret<[u8; 32]>), Error> {
+    let authenticator = Spake2Authenticator::new(shared_secret);
+    connect_on_local_network_with(room_code, is_creator, authenticator).await
+}
+
+/// Like [`connect_on_local_network()`], but verifies the peer's identity
+/// with a custom `authenticator` instead of
+/// [SPAKE2](https://docs.rs/spake2/).
+pub async fn connect_on_local_network_with<A: PeerAuthenticator>(
+    room_code: &Secret<impl AsRef<[u8]>>,
+    is_creator: bool,
+    authenticator: A,
+) -> Result<A::Output, Error> {
+    let name = instance_name(room_code.expose_secret().as_ref());
+    let stream = if is_creator {
+        advertise(&name).await?
+    } else {
+        discover(&name).await?
+    };
+    authenticator.authenticate(stream).await
+}
+
+/// Returns the DNS-SD instance name of the room with `room_code`.
+///
+/// Only a hash of the room code is advertised,
+/// since anyone on the network can see it.
+fn instance_name(room_code: &[u8]) -> String {
+    let hash = Sha256::new()
+        .chain_update(b"gday local room")
+        .chain_update(room_code)
+        .finalize();
+    let label: String = hash[..8].iter().map(|byte| format!("{byte:02x}")).collect();
+    format!("{label}.{SERVICE_TYPE}")
+}
+
+/// Answers mDNS queries for `name` with the port of a new listener,
+/// until a peer connects to it.
+async fn advertise(name: &str) -> Result<TcpStream, Error> {
+    let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    let port = listener.local_addr()?.port();
+    let mdns = mdns_socket()?;
+    debug!("Advertising {name} with port {port} on the local network.");
+
+    let mut buf = vec![0; MAX_MESSAGE_LEN];
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (stream, addr) = accepted?;
+                debug!("Received connection from {addr}. Will try to authenticate.");
+                return Ok(stream);
+            }
+            received = mdns.recv_from(&mut buf) => {
+                let (len, from) = received?;
+                if let Some(id) = parse_query(&buf[..len], name) {
+                    trace!("Answering query for {name} from {from}.");
+                    // queries that weren't sent from the mDNS port expect a direct answer
+                    let to = if from.port() == MDNS_ADDR.port() {
+                        SocketAddr::V4(MDNS_ADDR)
+                    } else {
+                        from
+                    };
+                    mdns.send_to(&response(id, name, port), to).await?;
+                }
+            }
+        }
+    }
+}
+
+/// Asks for `name` over mDNS every [`QUERY_INTERVAL`],
+/// until it can connect to a peer that answers.
+async fn discover(name: &str) -> Result<TcpStream, Error> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    socket.set_multicast_ttl_v4(255)?;
+    let query = query(rand::random(), name);
+    debug!("Looking for {name} on the local network.");
+
+    let mut buf = vec![0; MAX_MESSAGE_LEN];
+    loop {
+        socket.send_to(&query, MDNS_ADDR).await?;
+        let deadline = tokio::time::Instant::now() + QUERY_INTERVAL;
+        while let Ok(received) = tokio::time::timeout_at(deadline, socket.recv_from(&mut buf)).await
+        {
+            let (len, from) = received?;
+            let Some(port) = parse_response(&buf[..len], name) else {
+                continue;
+            };
+            // the answer doesn't include an address record, so use the sender's
+            let peer = SocketAddr::new(from.ip(), port);
+            match TcpStream::connect(peer).await {
+                Ok(stream) => {
+                    debug!("Connected to {peer}. Will try to authenticate.");
+                    return Ok(stream);
+                }
+                Err(err) => debug!("Couldn't connect to {peer}: {err}"),
+            }
+        }
+    }
+}
+
+/// Makes a socket that receives the mDNS messages of the local network.
+///
+/// Enables `SO_REUSEADDR` and `SO_REUSEPORT` to share the mDNS port
+/// with other responders on this host.
+fn mdns_socket() -> std::io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+
+    // socket2 only supports this method on these systems
+    #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+    socket.set_reuse_port(true)?;
+
+    let local = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, MDNS_ADDR.port());
+    socket.bind(&local.into())?;
+    socket.join_multicast_v4(MDNS_ADDR.ip(), &Ipv4Addr::UNSPECIFIED)?;
+    socket.set_multicast_ttl_v4(255)?;
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(socket.into())
+}
+
+/// Returns a DNS query with `id` for the port of the service `name`.
+fn query(id: u16, name: &str) -> Vec<u8> {
+    let mut packet = Vec::new();
+    // header with 1 question
+    for field in [id, 0, 1, 0, 0, 0] {
+        packet.extend_from_slice(&field.to_be_bytes());
+    }
+    write_question(&mut packet, name);
+    packet
+}
+
+/// Returns a DNS response to the query with `id`,
+/// saying that the service `name` listens on `port`.
+fn response(id: u16, name: &str, port: u16) -> Vec<u8> {
+    let mut packet = Vec::new();
+    // header of an authoritative answer, with 1 question and 1 answer
+    for field in [id, 0x8400, 1, 1, 0, 0] {
+        packet.extend_from_slice(&field.to_be_bytes());
+    }
+    // direct answers must repeat the question
+    write_question(&mut packet, name);
+
+    // priority and weight, port, and target host
+    let mut data = vec![0; 4];
+    data.extend_from_slice(&port.to_be_bytes());
+    write_name(&mut data, name);
+
+    write_name(&mut packet, name);
+    packet.extend_from_slice(&TYPE_SRV.to_be_bytes());
+    packet.extend_from_slice(&CLASS_IN.to_be_bytes());
+    packet.extend_from_slice(&ANSWER_TTL.to_be_bytes());
+    let data_len = u16::try_from(data.len()).expect("unreachable: names are short");
+    packet.extend_from_slice(&data_len.to_be_bytes());
+    packet.extend_from_slice(&data);
+    packet
+}
+
+/// Appends a question for the port of the service `name` to `packet`.
+fn write_question(packet: &mut Vec<u8>, name: &str) {
+    write_name(packet, name);
+    packet.extend_from_slice(&TYPE_SRV.to_be_bytes());
+    packet.extend_from_slice(&CLASS_IN.to_be_bytes());
+}
+
+/// Appends `name` to `packet`, as length-prefixed labels.
+fn write_name(packet: &mut Vec<u8>, name: &str) {
+    for label in name.split('.') {
+        let len = u8::try_from(label.len()).expect("unreachable: labels are short");
+        packet.push(len);
+        packet.extend_from_slice(label.as_bytes());
+    }
+    packet.push(0);
+}
+
+/// If `packet` is a query that asks for the port of `name`, returns its ID.
+fn parse_query(packet: &[u8], name: &str) -> Option<u16> {
+    let id = read_u16(packet, 0)?;
+    // skip responses
+    if read_u16(packet, 2)? & 0x8000 != 0 {
+        return None;
+    }
+    let questions = read_u16(packet, 4)?;
+    let mut pos = 12;
+    for _ in 0..questions {
+        let (question, next) = read_name(packet, pos)?;
+        let question_type = read_u16(packet, next)?;
+        if question == name && (question_type == TYPE_SRV || question_type == TYPE_ANY) {
+            return Some(id);
+        }
+        pos = next + 4;
+    }
+    None
+}
+
+/// If `packet` is a response with the port of `name`, returns that port.
+fn parse_response(packet: &[u8], name: &str) -> Option<u16> {
+    // skip queries
+    if read_u16(packet, 2)? & 0x8000 == 0 {
+        return None;
+    }
+    let questions = read_u16(packet, 4)?;
+    // answers, authority records, and additional records
+    let records: u32 = [6, 8, 10]
+        .into_iter()
+        .map(|pos| read_u16(packet, pos).map(u32::from))
+        .sum::<Option<u32>>()?;
+
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = read_name(packet, pos)?.1 + 4;
+    }
+    for _ in 0..records {
+        let (record, next) = read_name(packet, pos)?;
+        let record_type = read_u16(packet, next)?;
+        let data_len = usize::from(read_u16(packet, next + 8)?);
+        let data = next + 10;
+        if record == name && record_type == TYPE_SRV {
+            return read_u16(packet, data + 4);
+        }
+        pos = data + data_len;
+    }
+    None
+}
+
+/// Reads the name at `pos` in `packet`, following compression pointers.
+///
+/// Returns it in lowercase, and the position after it.
+fn read_name(packet: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut name = String::new();
+    let mut end = None;
+    // every step reads a label or follows a pointer, which bounds pointer loops
+    for _ in 0..packet.len() {
+        let len = usize::from(*packet.get(pos)?);
+        if len == 0 {
+            return Some((name, end.unwrap_or(pos + 1)));
+        } else if len & 0xC0 == 0xC0 {
+            let low = usize::from(*packet.get(pos + 1)?);
+            end.get_or_insert(pos + 2);
+            pos = (len & 0x3F) << 8 | low;
+        } else {
+            let label = packet.get(pos + 1..pos + 1 + len)?;
+            if !name.is_empty() {
+                name.push('.');
+            }
+            name.push_str(&String::from_utf8_lossy(label).to_ascii_lowercase());
+            pos += 1 + len;
+        }
+    }
+    None
+}
+
+/// Reads the big-endian [`u16`] at `pos` in `packet`.
+fn read_u16(packet: &[u8], pos: usize) -> Option<u16> {
+    let bytes = packet.get(pos..pos + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test that queries and responses for a room are understood.
+    #[test]
+    fn test_messages() {
+        let name = instance_name(b"room");
+        let other = instance_name(b"other room");
+        assert_ne!(name, other);
+        assert!(name.ends_with("._gday._tcp.local"));
+
+        let query = query(1234, &name);
+        assert_eq!(parse_query(&query, &name), Some(1234));
+        assert_eq!(parse_query(&query, &other), None);
+        assert_eq!(parse_response(&query, &name), None);
+
+        let response = response(1234, &name, 5678);
+        assert_eq!(parse_response(&response, &name), Some(5678));
+        assert_eq!(parse_response(&response, &other), None);
+        assert_eq!(parse_query(&response, &name), None);
+
+        // truncated messages don't give a wrong port
+        for len in 0..response.len() {
+            let port = parse_response(&response[..len], &name);
+            assert!(port.is_none() || port == Some(5678));
+        }
+    }
+
+    /// Test reading names that point to earlier names.
+    #[test]
+    fn test_compressed_name() {
+        let mut packet = vec![0; 12];
+        write_name(&mut packet, "_gday._tcp.local");
+        packet.push(4);
+        packet.extend_from_slice(b"ROOM");
+        packet.extend_from_slice(&[0xC0, 12]);
+
+        let (name, end) = read_name(&packet, 30).unwrap();
+        assert_eq!(name, "room._gday._tcp.local");
+        assert_eq!(end, packet.len());
+
+        // pointer loops are rejected
+        let looping = [0xC0, 0];
+        assert_eq!(read_name(&looping, 0), None);
+    }
+}
@@ -1,12 +1,17 @@
-use crate::Error;
+use crate::{Error, Secret};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::str::FromStr;
 
 /// Info that 2 peers must share before they can exchange contacts.
 ///
 /// Use [`String::try_from()`] and [`PeerCode::from_str()`]
 /// to convert to and from a short human-readable code.
+///
+/// Use [`PeerCode::to_uri()`] to get a URI of form
+/// `"gday://server_id/room_code#shared_secret"` instead,
+/// which can be shared as a link. [`PeerCode::from_str()`] accepts both forms.
 #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
 pub struct PeerCode {
     /// The ID of the gday contact exchange server
@@ -23,7 +28,7 @@ pub struct PeerCode {
     ///
     /// Both peers pass this value to [`crate::share_contacts()`]
     /// to specify which room to exchange contacts in.
-    pub room_code: String,
+    pub room_code: Secret<String>,
 
     /// The shared secret that the peers will use to confirm
     /// each other's identity, and derive a stronger key from.
@@ -32,9 +37,12 @@ pub struct PeerCode {
     ///
     /// Both peers pass this value to [`crate::try_connect_to_peer()`]
     /// to authenticate the other peer when hole-punching.
-    pub shared_secret: String,
+    pub shared_secret: Secret<String>,
 }
 
+/// Scheme of the URIs returned by [`PeerCode::to_uri()`].
+const URI_SCHEME: &str = "gday://";
+
 impl PeerCode {
     /// Returns a [`PeerCode`] with this `server_id`
     /// and a random `room_code` and `shared_secret`,
@@ -56,23 +64,125 @@ impl PeerCode {
 
         Self {
             server_id,
-            room_code,
-            shared_secret,
+            room_code: Secret::new(room_code),
+            shared_secret: Secret::new(shared_secret),
+        }
+    }
+
+    /// Returns a [`PeerCode`] with this `server_id`
+    /// and a `room_code` and `shared_secret` derived from `seed`.
+    ///
+    /// Lets two machines that already share a secret `seed`
+    /// compute the same [`PeerCode`] independently, without sending it.
+    ///
+    /// # Security
+    /// Anyone who knows `seed` can join the room and authenticate as a peer.
+    /// The `room_code` and `shared_secret` are derived with SHA-256 under
+    /// distinct labels, so neither reveals the other.
+    /// However, the server learns the `room_code`, so a malicious server
+    /// could brute-force a guessable `seed` offline
+    /// and recover the `shared_secret`.
+    /// Only use seeds with plenty of entropy, such as a long random string.
+    ///
+    /// The same `seed` always maps to the same room,
+    /// so two sessions with the same `seed` can't run at once.
+    pub fn from_seed(server_id: u64, seed: &str) -> Self {
+        Self {
+            server_id,
+            room_code: Secret::new(derive_from_seed(b"gday room code", seed)),
+            shared_secret: Secret::new(derive_from_seed(b"gday shared secret", seed)),
+        }
+    }
+
+    /// Returns this code as a URI of form `"gday://server_id/room_code#shared_secret"`.
+    ///
+    /// Characters of `room_code` and `shared_secret` other than letters,
+    /// digits, and `-._~` are percent-encoded.
+    pub fn to_uri(&self) -> String {
+        format!(
+            "{URI_SCHEME}{}/{}#{}",
+            self.server_id,
+            percent_encode(self.room_code.expose_secret()),
+            percent_encode(self.shared_secret.expose_secret())
+        )
+    }
+
+    /// Parses a URI returned by [`PeerCode::to_uri()`].
+    fn from_uri(uri: &str) -> Result<Self, Error> {
+        let rest = uri
+            .get(..URI_SCHEME.len())
+            .filter(|scheme| scheme.eq_ignore_ascii_case(URI_SCHEME))
+            .map(|_| &uri[URI_SCHEME.len()..])
+            .ok_or(Error::InvalidPeerCodeUri)?;
+        let (server_id, rest) = rest.split_once('/').ok_or(Error::InvalidPeerCodeUri)?;
+        let (room_code, shared_secret) = rest.split_once('#').ok_or(Error::InvalidPeerCodeUri)?;
+
+        Ok(PeerCode {
+            server_id: server_id.parse()?,
+            room_code: Secret::new(percent_decode(room_code)?),
+            shared_secret: Secret::new(percent_decode(shared_secret)?),
+        })
+    }
+}
+
+/// Percent-encodes the bytes of `str` other than
+/// ASCII letters, digits, and `-._~`.
+fn percent_encode(str: &str) -> String {
+    str.bytes()
+        .map(|byte| {
+            if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+                (byte as char).to_string()
+            } else {
+                format!("%{byte:02X}")
+            }
+        })
+        .collect()
+}
+
+/// Decodes the percent-encoded bytes of `str`.
+fn percent_decode(str: &str) -> Result<String, Error> {
+    let mut bytes = Vec::with_capacity(str.len());
+    let mut rest = str.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = tail.get(..2).ok_or(Error::InvalidPeerCodeUri)?;
+            let hex = std::str::from_utf8(hex).map_err(|_| Error::InvalidPeerCodeUri)?;
+            bytes.push(u8::from_str_radix(hex, 16).map_err(|_| Error::InvalidPeerCodeUri)?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
         }
     }
+    String::from_utf8(bytes).map_err(|_| Error::InvalidPeerCodeUri)
+}
+
+/// Derives a hex string from `seed`, hashed together with `label`.
+fn derive_from_seed(label: &[u8], seed: &str) -> String {
+    let mut hasher = Sha256::new();
+    // prefix the label with its length, so no `label` and `seed`
+    // pair can produce the same input as another
+    hasher.update((label.len() as u64).to_be_bytes());
+    hasher.update(label);
+    hasher.update(seed.as_bytes());
+
+    // 128 bits is plenty
+    hasher.finalize()[..16]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
 }
 
 impl TryFrom<&PeerCode> for String {
     type Error = Error;
 
     fn try_from(value: &PeerCode) -> Result<Self, Self::Error> {
-        if value.room_code.contains('.') || value.shared_secret.contains('.') {
+        let room_code = value.room_code.expose_secret();
+        let shared_secret = value.shared_secret.expose_secret();
+        if room_code.contains('.') || shared_secret.contains('.') {
             Err(Error::PeerCodeContainedPeriod)
         } else {
-            Ok(format!(
-                "{}.{}.{}",
-                value.server_id, value.room_code, value.shared_secret,
-            ))
+            Ok(format!("{}.{room_code}.{shared_secret}", value.server_id,))
         }
     }
 }
@@ -82,7 +192,13 @@ impl std::str::FromStr for PeerCode {
 
     /// Converts `str` of hexadecimal form:
     /// `"server_id.room_code.shared_secret"` into a [`PeerCode`].
+    ///
+    /// Also accepts a URI returned by [`PeerCode::to_uri()`].
     fn from_str(str: &str) -> Result<Self, Error> {
+        if str.contains("://") {
+            return Self::from_uri(str);
+        }
+
         // split `str` into period-separated substrings
         let substrings: Vec<&str> = str.split('.').collect();
 
@@ -93,8 +209,8 @@ impl std::str::FromStr for PeerCode {
         // set fields to segments
         Ok(PeerCode {
             server_id: substrings[0].parse()?,
-            room_code: substrings[1].to_owned(),
-            shared_secret: substrings[2].to_owned(),
+            room_code: Secret::new(substrings[1].to_owned()),
+            shared_secret: Secret::new(substrings[2].to_owned()),
         })
     }
 }
@@ -112,15 +228,15 @@ impl TryFrom<&str> for PeerCode {
 mod tests {
     use std::str::FromStr;
 
-    use crate::{Error, PeerCode};
+    use crate::{Error, PeerCode, Secret};
 
     /// Test encoding a message.
     #[test]
     fn test_encode() {
         let peer_code = PeerCode {
             server_id: 27,
-            room_code: " hel lo123".to_string(),
-            shared_secret: "coded ".to_string(),
+            room_code: Secret::new(" hel lo123".to_string()),
+            shared_secret: Secret::new("coded ".to_string()),
         };
 
         let message = String::try_from(&peer_code).unwrap();
@@ -137,8 +253,8 @@ mod tests {
 
         let expected = PeerCode {
             server_id: 83221,
-            room_code: "room codefoo".to_string(),
-            shared_secret: "secret123  ".to_string(),
+            room_code: Secret::new("room codefoo".to_string()),
+            shared_secret: Secret::new("secret123  ".to_string()),
         };
 
         assert_eq!(received1, expected);
@@ -171,8 +287,8 @@ mod tests {
     fn invalid_encodes() {
         let peer_code = PeerCode {
             server_id: 0,
-            room_code: "hi.there".to_string(),
-            shared_secret: "what.".to_string(),
+            room_code: Secret::new("hi.there".to_string()),
+            shared_secret: Secret::new("what.".to_string()),
         };
 
         let result = String::try_from(&peer_code);
@@ -180,12 +296,47 @@ mod tests {
         assert!(matches!(result, Err(Error::PeerCodeContainedPeriod)))
     }
 
+    #[test]
+    fn test_uri() {
+        let peer_code = PeerCode {
+            server_id: 27,
+            room_code: Secret::new(" hel.lo123".to_string()),
+            shared_secret: Secret::new("c#d/é%".to_string()),
+        };
+
+        let uri = peer_code.to_uri();
+        assert_eq!(uri, "gday://27/%20hel.lo123#c%23d%2F%C3%A9%25");
+        assert_eq!(PeerCode::from_str(&uri).unwrap(), peer_code);
+        assert_eq!(
+            PeerCode::from_str("GDAY://3/abc#def").unwrap(),
+            PeerCode::from_str("3.abc.def").unwrap()
+        );
+
+        for invalid in [
+            "http://3/abc#def",
+            "gday://3/abcdef",
+            "gday://3abc#def",
+            "gday://3/abc#de%2",
+            "gday://3/abc#de%zz",
+            "gday://3/abc#%FF",
+        ] {
+            assert!(matches!(
+                PeerCode::from_str(invalid),
+                Err(Error::InvalidPeerCodeUri)
+            ));
+        }
+        assert!(matches!(
+            PeerCode::from_str("gday://x/abc#def"),
+            Err(Error::CouldntParseServerID(..))
+        ));
+    }
+
     #[test]
     fn test_zeros() {
         let peer_code = PeerCode {
             server_id: 0,
-            room_code: "".to_string(),
-            shared_secret: "".to_string(),
+            room_code: Secret::new("".to_string()),
+            shared_secret: Secret::new("".to_string()),
         };
 
         let str = String::try_from(&peer_code).unwrap();
@@ -193,12 +344,32 @@ mod tests {
         assert_eq!(peer_code, received);
     }
 
+    #[test]
+    fn test_from_seed() {
+        let peer_code = PeerCode::from_seed(3, "correct horse battery staple");
+        assert_eq!(
+            peer_code,
+            PeerCode::from_seed(3, "correct horse battery staple")
+        );
+        assert_eq!(peer_code.server_id, 3);
+        assert_eq!(peer_code.room_code.expose_secret().len(), 32);
+        assert_ne!(peer_code.room_code, peer_code.shared_secret);
+
+        let other = PeerCode::from_seed(3, "correct horse battery stapler");
+        assert_ne!(peer_code.room_code, other.room_code);
+        assert_ne!(peer_code.shared_secret, other.shared_secret);
+
+        // can be shared like any other code
+        let str = String::try_from(&peer_code).unwrap();
+        assert_eq!(PeerCode::from_str(&str).unwrap(), peer_code);
+    }
+
     #[test]
     fn test_large() {
         let peer_code = PeerCode {
             server_id: u64::MAX,
-            room_code: " j fisd;af  ljks da; ".to_string(),
-            shared_secret: "r f98032 fsf 02f a".to_string(),
+            room_code: Secret::new(" j fisd;af  ljks da; ".to_string()),
+            shared_secret: Secret::new("r f98032 fsf 02f a".to_string()),
         };
 
         let str = String::try_from(&peer_code).unwrap();
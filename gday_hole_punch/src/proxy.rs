@@ -0,0 +1,354 @@
+//! Connects to a gday server through a SOCKS5 or HTTP proxy.
+use crate::server_connector::{upgrade_to_tls, ConnectStrategy, ServerConnection, ServerStream};
+use crate::{Error, Secret};
+use log::debug;
+use std::str::FromStr;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::Instant;
+
+/// Longest HTTP response header accepted from a proxy.
+const MAX_HTTP_HEADER_LEN: usize = 16 * 1024;
+
+/// A proxy to connect to gday servers through,
+/// such as for networks that only allow connections through a proxy,
+/// or to hide the contact exchange behind Tor.
+///
+/// Only the connection to the server goes through the proxy.
+/// The server then sees the proxy's address instead of yours,
+/// so hole punching can only use your local address.
+///
+/// Parsed from URLs of form `"socks5://[user:password@]host:port"`
+/// or `"http://[user:password@]host:port"`.
+/// The server's domain name is resolved by the proxy, so it doesn't leak to your DNS server.
+///
+/// ```
+/// # use gday_hole_punch::server_connector::Proxy;
+/// let proxy: Proxy = "socks5://127.0.0.1:9050".parse()?;
+/// # Ok::<(), gday_hole_punch::Error>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Proxy {
+    /// The protocol spoken with the proxy
+    kind: ProxyKind,
+    /// The proxy's `host:port`
+    addr: String,
+    /// User name and password to log into the proxy with
+    credentials: Option<(String, Secret<String>)>,
+}
+
+/// The protocol spoken with a [`Proxy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ProxyKind {
+    /// [SOCKS5](https://www.rfc-editor.org/rfc/rfc1928)
+    Socks5,
+    /// HTTP `CONNECT`
+    Http,
+}
+
+impl FromStr for Proxy {
+    type Err = Error;
+
+    fn from_str(url: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidProxyUrl(url.to_string());
+
+        let (scheme, rest) = url.split_once("://").ok_or_else(invalid)?;
+        let kind = match scheme.to_ascii_lowercase().as_str() {
+            "socks5" | "socks5h" => ProxyKind::Socks5,
+            "http" => ProxyKind::Http,
+            _ => return Err(invalid()),
+        };
+        let rest = rest.strip_suffix('/').unwrap_or(rest);
+        let (credentials, addr) = match rest.rsplit_once('@') {
+            Some((credentials, addr)) => {
+                let (user, password) = credentials.split_once(':').ok_or_else(invalid)?;
+                (
+                    Some((user.to_string(), Secret::new(password.to_string()))),
+                    addr,
+                )
+            }
+            None => (None, rest),
+        };
+
+        // the port is required, and the address can't have a path
+        let (host, port) = addr.rsplit_once(':').ok_or_else(invalid)?;
+        if host.is_empty() || addr.contains('/') || port.parse::<u16>().is_err() {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            kind,
+            addr: addr.to_string(),
+            credentials,
+        })
+    }
+}
+
+impl Proxy {
+    /// Like [`crate::server_connector::connect_tcp()`],
+    /// but connects to `domain_name` on `port` through this proxy.
+    ///
+    /// The [`ServerConnection`] holds a single stream,
+    /// of the IP family used to reach the proxy.
+    pub async fn connect_tcp(
+        &self,
+        domain_name: &str,
+        port: u16,
+        strategy: impl Into<ConnectStrategy>,
+    ) -> Result<ServerConnection, Error> {
+        let strategy = strategy.into();
+        let deadline = Instant::now() + strategy.overall_timeout;
+        self.connect_tcp_until(domain_name, port, strategy, deadline)
+            .await
+    }
+
+    /// Like [`crate::server_connector::connect_tls()`],
+    /// but connects to `domain_name` on `port` through this proxy.
+    ///
+    /// TLS is negotiated with the server, so the proxy can't read the connection.
+    pub async fn connect_tls(
+        &self,
+        domain_name: String,
+        port: u16,
+        strategy: impl Into<ConnectStrategy>,
+    ) -> Result<ServerConnection, Error> {
+        let strategy = strategy.into();
+        let deadline = Instant::now() + strategy.overall_timeout;
+        let connection = self
+            .connect_tcp_until(&domain_name, port, strategy, deadline)
+            .await?;
+        upgrade_to_tls(connection, domain_name, deadline).await
+    }
+
+    /// Connects to `domain_name` on `port` through this proxy,
+    /// timing out at `deadline`.
+    async fn connect_tcp_until(
+        &self,
+        domain_name: &str,
+        port: u16,
+        strategy: ConnectStrategy,
+        deadline: Instant,
+    ) -> Result<ServerConnection, Error> {
+        debug!(
+            "Connecting to server '{domain_name}:{port}' through proxy '{}'",
+            self.addr
+        );
+        let mut connection =
+            crate::server_connector::connect_tcp(self.addr.as_str(), strategy).await?;
+
+        // one tunnel is enough, since the server only sees the proxy's address
+        let stream = if let Some(stream) = connection.v4.as_mut() {
+            connection.v6 = None;
+            stream
+        } else {
+            connection
+                .v6
+                .as_mut()
+                .expect("unreachable: connected over IPv4 or IPv6")
+        };
+        let ServerStream::TCP(tcp) = stream else {
+            unreachable!("connect_tcp() returns TCP streams")
+        };
+
+        let tunnel = async {
+            match self.kind {
+                ProxyKind::Socks5 => self.socks5_connect(tcp, domain_name, port).await,
+                ProxyKind::Http => self.http_connect(tcp, domain_name, port).await,
+            }
+        };
+        tokio::time::timeout_at(deadline, tunnel)
+            .await
+            .map_err(|_| Error::ProxyFailed("Timed out".to_string()))??;
+        Ok(connection)
+    }
+
+    /// Asks the SOCKS5 proxy on the other end of `stream`
+    /// to connect it to `domain_name` on `port`.
+    async fn socks5_connect(
+        &self,
+        stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+        domain_name: &str,
+        port: u16,
+    ) -> Result<(), Error> {
+        // offer no authentication, or a user name and password
+        let method: u8 = if self.credentials.is_some() { 2 } else { 0 };
+        stream.write_all(&[5, 1, method]).await?;
+        let mut reply = [0; 2];
+        stream.read_exact(&mut reply).await?;
+        if reply != [5, method] {
+            return Err(Error::ProxyFailed(
+                "The SOCKS5 proxy didn't accept the authentication method".to_string(),
+            ));
+        }
+
+        if let Some((user, password)) = &self.credentials {
+            let mut login = vec![1];
+            for field in [user.as_bytes(), password.expose_secret().as_bytes()] {
+                login.push(u8::try_from(field.len()).map_err(|_| {
+                    Error::ProxyFailed("The proxy user name or password is too long".to_string())
+                })?);
+                login.extend_from_slice(field);
+            }
+            stream.write_all(&login).await?;
+            stream.read_exact(&mut reply).await?;
+            if reply[1] != 0 {
+                return Err(Error::ProxyFailed(
+                    "The SOCKS5 proxy rejected the user name or password".to_string(),
+                ));
+            }
+        }
+
+        // connect to the domain name, which the proxy resolves
+        let name_len = u8::try_from(domain_name.len())
+            .map_err(|_| Error::ProxyFailed("The server domain name is too long".to_string()))?;
+        let mut request = vec![5, 1, 0, 3, name_len];
+        request.extend_from_slice(domain_name.as_bytes());
+        request.extend_from_slice(&port.to_be_bytes());
+        stream.write_all(&request).await?;
+
+        let mut reply = [0; 4];
+        stream.read_exact(&mut reply).await?;
+        if reply[1] != 0 {
+            let reason = match reply[1] {
+                1 => "general failure",
+                2 => "connection not allowed by ruleset",
+                3 => "network unreachable",
+                4 => "host unreachable",
+                5 => "connection refused",
+                6 => "TTL expired",
+                7 => "command not supported",
+                8 => "address type not supported",
+                _ => "unknown error",
+            };
+            return Err(Error::ProxyFailed(format!(
+                "The SOCKS5 proxy replied: {reason}"
+            )));
+        }
+
+        // skip the address the proxy connected from
+        let addr_len = match reply[3] {
+            1 => 4,
+            4 => 16,
+            3 => usize::from(stream.read_u8().await?),
+            _ => {
+                return Err(Error::ProxyFailed(
+                    "The SOCKS5 proxy replied with an unknown address type".to_string(),
+                ))
+            }
+        };
+        let mut bound = vec![0; addr_len + 2];
+        stream.read_exact(&mut bound).await?;
+        Ok(())
+    }
+
+    /// Asks the HTTP proxy on the other end of `stream`
+    /// to connect it to `domain_name` on `port`.
+    async fn http_connect(
+        &self,
+        stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+        domain_name: &str,
+        port: u16,
+    ) -> Result<(), Error> {
+        let target = format!("{domain_name}:{port}");
+        let mut request = format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n");
+        if let Some((user, password)) = &self.credentials {
+            let login = format!("{user}:{}", password.expose_secret());
+            request.push_str(&format!(
+                "Proxy-Authorization: Basic {}\r\n",
+                base64(login.as_bytes())
+            ));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await?;
+
+        // read byte by byte, so that none of the server's bytes are consumed
+        let mut header = Vec::new();
+        while !header.ends_with(b"\r\n\r\n") {
+            if header.len() >= MAX_HTTP_HEADER_LEN {
+                return Err(Error::ProxyFailed(
+                    "The HTTP proxy's response is too long".to_string(),
+                ));
+            }
+            header.push(stream.read_u8().await?);
+        }
+
+        let header = String::from_utf8_lossy(&header);
+        let status_line = header.lines().next().unwrap_or_default();
+        let status = status_line.split_whitespace().nth(1);
+        if status.is_some_and(|status| status.starts_with('2')) {
+            Ok(())
+        } else {
+            Err(Error::ProxyFailed(format!(
+                "The HTTP proxy replied \"{status_line}\""
+            )))
+        }
+    }
+}
+
+/// Returns `data` in standard base64, for HTTP basic authentication.
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let bits = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test parsing proxy URLs.
+    #[test]
+    fn test_parse() {
+        let proxy: Proxy = "socks5://127.0.0.1:9050".parse().unwrap();
+        assert_eq!(proxy.kind, ProxyKind::Socks5);
+        assert_eq!(proxy.addr, "127.0.0.1:9050");
+        assert_eq!(proxy.credentials, None);
+
+        let proxy: Proxy = "HTTP://me:pass:word@proxy.example.com:8080/"
+            .parse()
+            .unwrap();
+        assert_eq!(proxy.kind, ProxyKind::Http);
+        assert_eq!(proxy.addr, "proxy.example.com:8080");
+        let (user, password) = proxy.credentials.unwrap();
+        assert_eq!(user, "me");
+        assert_eq!(password.expose_secret(), "pass:word");
+
+        let proxy: Proxy = "socks5h://[::1]:1080".parse().unwrap();
+        assert_eq!(proxy.addr, "[::1]:1080");
+
+        for invalid in [
+            "127.0.0.1:9050",
+            "ftp://127.0.0.1:21",
+            "socks5://127.0.0.1",
+            "socks5://:1080",
+            "http://proxy:8080/path",
+            "http://user@proxy:8080",
+        ] {
+            assert!(invalid.parse::<Proxy>().is_err(), "{invalid}");
+        }
+    }
+
+    /// Test base64 against known encodings.
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"user:pass"), "dXNlcjpwYXNz");
+    }
+}
@@ -0,0 +1,72 @@
+//! Connects to a peer through a gday server's relay,
+//! for when hole punching fails.
+use crate::{
+    contact_sharer::hash_room_code,
+    server_connector::{ServerConnection, ServerStream},
+    Error, Secret, Spake2Authenticator,
+};
+use gday_contact_exchange_protocol::{read_from_async, write_to_async, ClientMsg, ServerMsg};
+use log::debug;
+
+/// Connects to the peer through the relay of the gday server
+/// that `server_connection` is connected to, in the room with `room_code`.
+///
+/// Only a hash of `room_code` is sent to the server.
+/// The peer must call this with the same `room_code`,
+/// and the opposite `is_creator`.
+///
+/// The server only relays peers that met in a room.
+/// So the peers must have shared contacts in this room with [`crate::share_contacts()`],
+/// or the creator must have created it with [`crate::create_room()`].
+/// Otherwise the server replies with [`ServerMsg::ErrorNoSuchRoomCode`].
+///
+/// Only some servers relay traffic. Others reply with
+/// [`ServerMsg::ErrorRelayDisabled`], returned as [`Error::UnexpectedServerReply`].
+///
+/// Relayed traffic passes through the server, so it may be slower than a
+/// direct connection, and the server learns how much you send.
+/// Like [`crate::try_connect_to_peer()`], the peer is authenticated
+/// with [SPAKE2](https://docs.rs/spake2/) using `shared_secret`,
+/// so encrypt the traffic with the returned key to keep the server from reading it.
+///
+/// Returns:
+/// - The server stream, which from now on carries the peer's traffic.
+/// - A 32-byte key shared with the peer.
+pub async fn connect_through_relay(
+    mut server_connection: ServerConnection,
+    room_code: &Secret<impl AsRef<[u8]>>,
+    shared_secret: &Secret<impl AsRef<[u8]>>,
+    is_creator: bool,
+) -> Result<(ServerStream, Secret<[u8; 32]>), Error> {
+    let room_code = hash_room_code(room_code);
+
+    // the server only relays one stream
+    let mut stream = server_connection
+        .v6
+        .take()
+        .or(server_connection.v4.take())
+        .ok_or(Error::ServerConnectionEmpty)?;
+
+    write_to_async(
+        ClientMsg::RelayConnect {
+            room_code,
+            is_creator,
+        },
+        &mut stream,
+    )
+    .await?;
+
+    // the server replies once the peer has joined too
+    let reply: ServerMsg = read_from_async(&mut stream).await?;
+    match reply {
+        ServerMsg::RelayStarted => (),
+        ServerMsg::ErrorPeerTimedOut => return Err(Error::RelayPeerTimedOut),
+        reply => return Err(Error::UnexpectedServerReply(reply)),
+    }
+    debug!("The server started relaying. Will now authenticate the peer.");
+
+    let shared_key = Spake2Authenticator::new(shared_secret)
+        .derive_key(&mut stream)
+        .await?;
+    Ok((stream, shared_key))
+}
@@ -0,0 +1,88 @@
+use serde::{Deserialize, Serialize};
+
+/// Wraps a value that must never end up in logs,
+/// such as a room code, shared secret, or derived key.
+///
+/// Its [`Debug`] output is redacted, and it doesn't implement
+/// [`std::fmt::Display`] or [`std::ops::Deref`], so the value can only be
+/// read by explicitly calling [`Secret::expose_secret()`].
+///
+/// ```
+/// # use gday_hole_punch::Secret;
+/// let secret = Secret::new("hunter2".to_string());
+/// assert_eq!(format!("{secret:?}"), "Secret([REDACTED])");
+/// assert_eq!(secret.expose_secret(), "hunter2");
+/// ```
+///
+/// Formatting the value by accident doesn't compile:
+///
+/// ```compile_fail
+/// # use gday_hole_punch::Secret;
+/// let secret = Secret::new("hunter2".to_string());
+/// println!("{secret}");
+/// ```
+///
+/// It's serialized as the plain value, since it's usually
+/// serialized to be shared with the peer.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+    /// Wraps `value`.
+    pub const fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    /// Returns the secret value.
+    ///
+    /// Take care not to log what this returns.
+    pub fn expose_secret(&self) -> &T {
+        &self.0
+    }
+
+    /// Unwraps the secret value.
+    ///
+    /// Take care not to log what this returns.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> From<T> for Secret<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T> std::fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Secret([REDACTED])")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{PeerCode, Secret, Spake2Authenticator};
+
+    #[test]
+    fn test_redacted() {
+        let peer_code = PeerCode {
+            server_id: 5,
+            room_code: Secret::new("roomcode".to_string()),
+            shared_secret: Secret::new("sharedsecret".to_string()),
+        };
+        let debug = format!("{peer_code:?} {peer_code:#?}");
+        assert!(!debug.contains("roomcode"));
+        assert!(!debug.contains("sharedsecret"));
+        assert!(debug.contains("server_id: 5"));
+
+        let authenticator = Spake2Authenticator::new(&peer_code.shared_secret);
+        let debug = format!("{authenticator:?}");
+        assert!(!debug.contains("115, 104"));
+        assert!(debug.contains("[REDACTED]"));
+
+        let key = Secret::new([7_u8; 32]);
+        assert_eq!(format!("{key:?}"), "Secret([REDACTED])");
+    }
+}
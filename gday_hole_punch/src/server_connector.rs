@@ -1,8 +1,11 @@
 //! Functions for connecting to a Gday server.
 use crate::Error;
-use gday_contact_exchange_protocol::Contact;
+use gday_contact_exchange_protocol::{
+    read_from_async, write_to_async, ClientMsg, Contact, ServerMsg,
+};
 use log::{debug, error, warn};
 use rand::seq::SliceRandom;
+use sha2::Digest;
 use socket2::SockRef;
 use std::fmt::Debug;
 use std::io::ErrorKind;
@@ -10,7 +13,9 @@ use std::net::SocketAddr::{V4, V6};
 use std::{net::SocketAddr, sync::Arc, time::Duration};
 use tokio::io::AsyncWriteExt;
 use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::time::Instant;
 
+pub use crate::proxy::Proxy;
 pub use gday_contact_exchange_protocol::DEFAULT_PORT;
 
 /// List of default public Gday servers.
@@ -211,6 +216,23 @@ impl ServerConnection {
         Ok(contact)
     }
 
+    /// Authenticates each stream as the server's tenant with `api_key`,
+    /// for servers shared by several apps or groups of users.
+    ///
+    /// Call before sharing contacts, if the server requires it.
+    pub async fn authenticate(&mut self, api_key: &str) -> Result<(), Error> {
+        let token: [u8; 32] = sha2::Sha256::digest(api_key.as_bytes()).into();
+
+        for stream in self.streams() {
+            write_to_async(ClientMsg::Authenticate { token }, stream).await?;
+            let reply: ServerMsg = read_from_async(stream).await?;
+            if reply != ServerMsg::Authenticated {
+                return Err(Error::UnexpectedServerReply(reply));
+            }
+        }
+        Ok(())
+    }
+
     /// Calls shutdown on the underlying streams to gracefully
     /// close the connection.
     pub async fn shutdown(&mut self) -> std::io::Result<()> {
@@ -224,13 +246,58 @@ impl ServerConnection {
     }
 }
 
+/// How to time attempts to connect to a server's addresses.
+///
+/// A host name often resolves to several addresses, some of which
+/// may be unreachable. The addresses of each IP family are tried
+/// like "Happy Eyeballs" ([RFC 8305](https://www.rfc-editor.org/rfc/rfc8305)):
+/// a new attempt starts every `stagger`, or as soon as an earlier one fails,
+/// and the first stream to connect is kept.
+///
+/// A [`Duration`] converts into the default strategy with that duration
+/// as both `per_attempt_timeout` and `overall_timeout`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectStrategy {
+    /// How long to wait for an attempt before starting the next one.
+    pub stagger: Duration,
+    /// How long to wait for a single address before giving up on it.
+    pub per_attempt_timeout: Duration,
+    /// How long to wait for the whole connection, including
+    /// resolving the host name and TLS handshakes, before giving up.
+    pub overall_timeout: Duration,
+    /// Maximum number of attempts in progress per IP family at once.
+    /// `0` is treated as `1`.
+    pub parallelism: usize,
+}
+
+impl Default for ConnectStrategy {
+    fn default() -> Self {
+        Self {
+            stagger: Duration::from_millis(500),
+            per_attempt_timeout: Duration::from_secs(5),
+            overall_timeout: Duration::from_secs(10),
+            parallelism: 2,
+        }
+    }
+}
+
+impl From<Duration> for ConnectStrategy {
+    fn from(timeout: Duration) -> Self {
+        Self {
+            per_attempt_timeout: timeout,
+            overall_timeout: timeout,
+            ..Self::default()
+        }
+    }
+}
+
 /// In random order, sequentially try connecting to `servers`.
 ///
 /// You may pass [`DEFAULT_SERVERS`] as `servers`.
 ///
 /// Ignores servers that don't have `prefer == true`.
 /// Connects to port [`DEFAULT_PORT`] via TLS.
-/// Tries the next server after `timeout` time.
+/// Tries the next server after the `overall_timeout` of `strategy`.
 ///
 /// Returns
 /// - The [`ServerConnection`] of the first successful connection.
@@ -239,7 +306,7 @@ impl ServerConnection {
 /// Returns an error if all connection attempts failed.
 pub async fn connect_to_random_server(
     servers: &[ServerInfo],
-    timeout: Duration,
+    strategy: impl Into<ConnectStrategy>,
 ) -> Result<(ServerConnection, u64), Error> {
     // Filter out non-preferred servers
     let preferred: Vec<&ServerInfo> = servers.iter().filter(|s| s.prefer).collect();
@@ -248,7 +315,7 @@ pub async fn connect_to_random_server(
     let preferred_names: Vec<&str> = preferred.iter().map(|s| s.domain_name).collect();
 
     // Try connecting to the them in a random order
-    let (conn, i) = connect_to_random_domain_name(&preferred_names, timeout).await?;
+    let (conn, i) = connect_to_random_domain_name(&preferred_names, strategy).await?;
     Ok((conn, preferred[i].id))
 }
 
@@ -257,25 +324,25 @@ pub async fn connect_to_random_server(
 /// You may pass [`DEFAULT_SERVERS`] as `servers`.
 ///
 /// Connects to port [`DEFAULT_PORT`] via TLS.
-/// Gives up after `timeout` time.
+/// Gives up after the `overall_timeout` of `strategy`.
 ///
 /// Returns an error if `servers` contains no server with id `server_id` or connecting
 /// to the server fails.
 pub async fn connect_to_server_id(
     servers: &[ServerInfo],
     server_id: u64,
-    timeout: Duration,
+    strategy: impl Into<ConnectStrategy>,
 ) -> Result<ServerConnection, Error> {
     let Some(server) = servers.iter().find(|server| server.id == server_id) else {
         return Err(Error::ServerIDNotFound(server_id));
     };
-    connect_tls(server.domain_name.to_string(), DEFAULT_PORT, timeout).await
+    connect_tls(server.domain_name.to_string(), DEFAULT_PORT, strategy).await
 }
 
 /// In random order, sequentially tries connecting to the given `domain_names`.
 ///
 /// Connects to port [`DEFAULT_PORT`] via TLS.
-/// Tries the next connection after `timeout` time.
+/// Tries the next connection after the `overall_timeout` of `strategy`.
 ///
 /// Returns
 /// - The [`ServerConnection`] of the first successful connection.
@@ -284,8 +351,9 @@ pub async fn connect_to_server_id(
 /// Returns an error only if all connection attempts failed.
 pub async fn connect_to_random_domain_name(
     domain_names: &[&str],
-    timeout: Duration,
+    strategy: impl Into<ConnectStrategy>,
 ) -> Result<(ServerConnection, usize), Error> {
+    let strategy = strategy.into();
     let mut indices: Vec<usize> = (0..domain_names.len()).collect();
     indices.shuffle(&mut rand::thread_rng());
 
@@ -293,7 +361,7 @@ pub async fn connect_to_random_domain_name(
 
     for i in indices {
         let server = domain_names[i];
-        match connect_tls(server.to_string(), DEFAULT_PORT, timeout).await {
+        match connect_tls(server.to_string(), DEFAULT_PORT, strategy).await {
             Ok(streams) => return Ok((streams, i)),
             Err(err) => {
                 recent_error = err;
@@ -312,20 +380,30 @@ pub async fn connect_to_random_domain_name(
 /// Tries to TLS connect to `domain_name` over both IPv4 and IPv6.
 ///
 /// - Returns a [`ServerConnection`] with all the successful TLS streams.
-/// - Gives up connecting to each TCP address after `timeout` time.
+/// - Times the attempts to each address as described by `strategy`.
 /// - Returns an error if couldn't connect to any of IPv4 and IPv6.
 /// - Returns an error for any issues with TLS.
 pub async fn connect_tls(
     domain_name: String,
     port: u16,
-    timeout: Duration,
+    strategy: impl Into<ConnectStrategy>,
 ) -> Result<ServerConnection, Error> {
+    let strategy = strategy.into();
+    let deadline = Instant::now() + strategy.overall_timeout;
     debug!("Connecting to server '{domain_name}:{port}'");
 
     // Connect to the server over TCP
-    let mut connection: ServerConnection =
-        connect_tcp((domain_name.as_str(), port), timeout).await?;
+    let connection: ServerConnection = connect_tcp((domain_name.as_str(), port), strategy).await?;
+    upgrade_to_tls(connection, domain_name, deadline).await
+}
 
+/// Does a TLS handshake with `domain_name` over each TCP stream of `connection`,
+/// giving up at `deadline`.
+pub(crate) async fn upgrade_to_tls(
+    mut connection: ServerConnection,
+    domain_name: String,
+    deadline: Instant,
+) -> Result<ServerConnection, Error> {
     // wrap the DNS name of the server
     let name = tokio_rustls::rustls::pki_types::ServerName::try_from(domain_name)?;
 
@@ -334,22 +412,31 @@ pub async fn connect_tls(
 
     let connector = tokio_rustls::TlsConnector::from(tls_config);
 
+    let handshake = |tcp| {
+        let connect = connector.connect(name.clone(), tcp);
+        async move {
+            match tokio::time::timeout_at(deadline, connect).await {
+                Ok(result) => result,
+                Err(_) => Err(std::io::Error::new(
+                    ErrorKind::TimedOut,
+                    "Timed out during the TLS handshake with the server.",
+                )),
+            }
+        }
+    };
+
     if let Some(tcp_v4) = connection.v4 {
         let ServerStream::TCP(tcp_v4) = tcp_v4 else {
             unreachable!()
         };
-        connection.v4 = Some(ServerStream::TLS(
-            connector.connect(name.clone(), tcp_v4).await?,
-        ));
+        connection.v4 = Some(ServerStream::TLS(handshake(tcp_v4).await?));
     }
 
     if let Some(tcp_v6) = connection.v6 {
         let ServerStream::TCP(tcp_v6) = tcp_v6 else {
             unreachable!()
         };
-        connection.v6 = Some(ServerStream::TLS(
-            connector.connect(name.clone(), tcp_v6).await?,
-        ));
+        connection.v6 = Some(ServerStream::TLS(handshake(tcp_v6).await?));
     }
 
     Ok(connection)
@@ -358,52 +445,34 @@ pub async fn connect_tls(
 /// Tries to TCP connect to `addrs` over both IPv4 and IPv6.
 ///
 /// - Returns a [`ServerConnection`] with all the successful TCP streams.
-/// - Gives up connecting to each TCP address after `timeout` time.
+/// - Tries every resolved address, timed as described by `strategy`.
+///   IPv4 and IPv6 are tried at the same time.
 /// - Returns an error if couldn't connect to any of IPv4 and IPv6.
 pub async fn connect_tcp(
     addrs: impl ToSocketAddrs + Debug,
-    timeout: Duration,
+    strategy: impl Into<ConnectStrategy>,
 ) -> std::io::Result<ServerConnection> {
-    // Try to get an IPv4 and IPv6 socket address.
-    let mut addr_v4 = None;
-    let mut addr_v6 = None;
-    for addr in tokio::net::lookup_host(&addrs).await? {
-        if addr.is_ipv6() && addr_v6.is_none() {
-            addr_v6 = Some(addr);
-        } else if addr.is_ipv4() && addr_v4.is_none() {
-            addr_v4 = Some(addr);
-        } else if addr_v4.is_some() && addr_v6.is_some() {
-            break;
-        }
-    }
-
-    // try connecting to the first IPv4 address
-    let tcp_v4 = if let Some(addr) = addr_v4 {
-        if let Ok(result) = tokio::time::timeout(timeout, TcpStream::connect(addr)).await {
-            Some(result)
-        } else {
-            Some(Err(std::io::Error::new(
-                ErrorKind::TimedOut,
-                format!("Timed out while trying to connect to server {addrs:?}."),
-            )))
-        }
-    } else {
-        None
+    let strategy = strategy.into();
+    let deadline = Instant::now() + strategy.overall_timeout;
+    let timed_out = || {
+        std::io::Error::new(
+            ErrorKind::TimedOut,
+            format!("Timed out while trying to connect to server {addrs:?}."),
+        )
     };
 
-    // try connecting to the first IPv6 addresss
-    let tcp_v6 = if let Some(addr) = addr_v6 {
-        if let Ok(result) = tokio::time::timeout(timeout, TcpStream::connect(addr)).await {
-            Some(result)
-        } else {
-            Some(Err(std::io::Error::new(
-                ErrorKind::TimedOut,
-                format!("Timed out while trying to connect to server {addrs:?}."),
-            )))
-        }
-    } else {
-        None
+    // Split the resolved addresses by IP family
+    let resolved = tokio::time::timeout_at(deadline, tokio::net::lookup_host(&addrs))
+        .await
+        .map_err(|_| timed_out())??;
+    let (addrs_v6, addrs_v4): (Vec<SocketAddr>, Vec<SocketAddr>) =
+        resolved.partition(|addr| addr.is_ipv6());
+
+    let connect = |family| async move {
+        let result = tokio::time::timeout_at(deadline, connect_family(family, &strategy)).await;
+        result.unwrap_or_else(|_| Some(Err(timed_out())))
     };
+    let (tcp_v4, tcp_v6) = tokio::join!(connect(&addrs_v4), connect(&addrs_v6));
 
     // return an error if couldn't establish any connections
     if !matches!(tcp_v4, Some(Ok(_))) && !matches!(tcp_v6, Some(Ok(_))) {
@@ -435,6 +504,63 @@ pub async fn connect_tcp(
     Ok(server_connection)
 }
 
+/// Tries connecting to `addrs`, which should all be of the same IP family,
+/// in order, with attempts timed as described by `strategy`.
+///
+/// Returns the first stream that connects, the last error
+/// if none did, or `None` if `addrs` is empty.
+async fn connect_family(
+    addrs: &[SocketAddr],
+    strategy: &ConnectStrategy,
+) -> Option<std::io::Result<TcpStream>> {
+    let mut pending = addrs.iter().copied().peekable();
+    let mut attempts = tokio::task::JoinSet::new();
+    let mut last_error = None;
+
+    loop {
+        if attempts.len() < strategy.parallelism.max(1) {
+            if let Some(addr) = pending.next() {
+                let timeout = strategy.per_attempt_timeout;
+                attempts.spawn(connect_with_timeout(addr, timeout));
+            }
+        }
+
+        if attempts.is_empty() {
+            return last_error.map(Err);
+        }
+
+        let can_start_more =
+            pending.peek().is_some() && attempts.len() < strategy.parallelism.max(1);
+
+        tokio::select! {
+            Some(result) = attempts.join_next() => match result {
+                Ok(Ok(stream)) => return Some(Ok(stream)),
+                Ok(Err(err)) => last_error = Some(err),
+                Err(err) => last_error = Some(err.into()),
+            },
+            _ = tokio::time::sleep(strategy.stagger), if can_start_more => (),
+        }
+    }
+}
+
+/// TCP connects to `addr`, giving up after `timeout`.
+async fn connect_with_timeout(addr: SocketAddr, timeout: Duration) -> std::io::Result<TcpStream> {
+    let connect = async {
+        if addr.is_ipv6() {
+            connect_from_stable_v6(addr).await
+        } else {
+            TcpStream::connect(addr).await
+        }
+    };
+    match tokio::time::timeout(timeout, connect).await {
+        Ok(result) => result,
+        Err(_) => Err(std::io::Error::new(
+            ErrorKind::TimedOut,
+            format!("Timed out while trying to connect to {addr}."),
+        )),
+    }
+}
+
 /// Get default TLS config
 fn get_tls_config() -> Arc<tokio_rustls::rustls::ClientConfig> {
     let root_store = tokio_rustls::rustls::RootCertStore::from_iter(
@@ -447,3 +573,19 @@ fn get_tls_config() -> Arc<tokio_rustls::rustls::ClientConfig> {
             .with_no_client_auth(),
     )
 }
+
+/// TCP connects to the IPv6 address `addr`.
+///
+/// If the operating system would connect from a temporary
+/// IPv6 address, connects from a stable one instead, if there is one.
+/// Otherwise the local contact shared with the peer could expire mid-session.
+async fn connect_from_stable_v6(addr: SocketAddr) -> std::io::Result<TcpStream> {
+    let Some(source) = crate::ipv6_addrs::stable_source_for(addr) else {
+        return TcpStream::connect(addr).await;
+    };
+
+    debug!("Connecting to {addr} from stable IPv6 address {source}.");
+    let socket = tokio::net::TcpSocket::new_v6()?;
+    socket.bind(SocketAddr::new(source.into(), 0))?;
+    socket.connect(addr).await
+}
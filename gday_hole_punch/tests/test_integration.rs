@@ -1,23 +1,32 @@
 #![forbid(unsafe_code)]
 #![warn(clippy::all)]
 
-use gday_hole_punch::{server_connector, share_contacts, try_connect_to_peer, PeerCode};
-use std::str::FromStr;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use gday_contact_exchange_protocol::{Contact, FullContact, NatBehavior};
+use gday_hole_punch::{
+    connect_on_local_network, connect_through_relay, create_room, server_connector, share_contacts,
+    try_connect_to_peer, try_connect_to_peer_with, Candidate, Error, PeerAuthenticator, PeerCode,
+    Secret,
+};
+use gday_server::Server;
+use sha2::Digest;
+use std::{net::SocketAddrV4, str::FromStr, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
 
 #[tokio::test]
 async fn test_integration() {
     // start the server in the background
-    let args = gday_server::Args {
-        key: None,
-        certificate: None,
-        unencrypted: true,
-        addresses: vec!["127.0.0.1:0".parse().unwrap(), "[::1]:0".parse().unwrap()],
-        timeout: 3600,
-        request_limit: 10,
-        verbosity: log::LevelFilter::Off,
-    };
-    let (server_addrs, _joinset) = gday_server::start_server(args).unwrap();
+    let server = Server::builder()
+        .unencrypted()
+        .addresses(["127.0.0.1:0".parse().unwrap(), "[::1]:0".parse().unwrap()])
+        .room_timeout(Duration::from_secs(3600))
+        .request_limit(10, 10)
+        .connection_limit(100, 1000)
+        .start()
+        .unwrap();
+    let server_addrs = server.addresses();
 
     let server_addr_1 = server_addrs[0];
 
@@ -31,8 +40,8 @@ async fn test_integration() {
         // Rendezvous settings
         let peer_code = PeerCode {
             server_id: 0,
-            room_code: "123".to_string(),
-            shared_secret: "456".to_string(),
+            room_code: Secret::new("123".to_string()),
+            shared_secret: Secret::new("456".to_string()),
         };
 
         // Connect to the server
@@ -42,7 +51,7 @@ async fn test_integration() {
 
         // Create a room in the server, and get my contact from it
         let (my_contact, peer_contact_fut) =
-            share_contacts(&mut server_connection, peer_code.room_code.as_bytes(), true)
+            share_contacts(&mut server_connection, &peer_code.room_code, true)
                 .await
                 .unwrap();
 
@@ -56,18 +65,18 @@ async fn test_integration() {
         // Use TCP hole-punching to connect to the peer,
         // verify their identity with the shared_secret,
         // and get a cryptographically-secure shared key
-        let (mut tcp_stream, strong_key) = try_connect_to_peer(
-            my_contact.local,
-            peer_contact,
-            peer_code.shared_secret.as_bytes(),
-        )
-        .await
-        .unwrap();
+        let (mut tcp_stream, strong_key, _) =
+            try_connect_to_peer(my_contact.local, peer_contact, &peer_code.shared_secret)
+                .await
+                .unwrap();
 
         tcp_stream.write_all(b"Hello peer!").await.unwrap();
 
         // never send a secret outside of tests
-        tcp_stream.write_all(&strong_key).await.unwrap();
+        tcp_stream
+            .write_all(strong_key.expose_secret())
+            .await
+            .unwrap();
         tcp_stream.flush().await.unwrap();
     });
 
@@ -83,25 +92,25 @@ async fn test_integration() {
         .unwrap();
 
     // Join the same room in the server, and get my local contact
-    let (my_contact, peer_contact_fut) = share_contacts(
-        &mut server_connection,
-        peer_code.room_code.as_bytes(),
-        false,
-    )
-    .await
-    .unwrap();
+    let (my_contact, peer_contact_fut) =
+        share_contacts(&mut server_connection, &peer_code.room_code, false)
+            .await
+            .unwrap();
 
     // Get peer's contact
     let peer_contact = peer_contact_fut.await.unwrap();
 
     // Use hole-punching to connect to peer.
-    let (mut tcp_stream, strong_key) = try_connect_to_peer(
-        my_contact.local,
-        peer_contact,
-        peer_code.shared_secret.as_bytes(),
-    )
-    .await
-    .unwrap();
+    let (mut tcp_stream, strong_key, info) =
+        try_connect_to_peer(my_contact.local, peer_contact, &peer_code.shared_secret)
+            .await
+            .unwrap();
+
+    // the server saw the same loopback address as the peer's public one
+    assert_eq!(info.candidate, Candidate::Public);
+    assert!(!info.is_ipv6());
+    assert_eq!(info.local_addr, tcp_stream.local_addr().unwrap());
+    assert_eq!(info.peer_addr, tcp_stream.peer_addr().unwrap());
 
     // Ensure the direct connection works
     let mut received = [0_u8; 11];
@@ -111,7 +120,302 @@ async fn test_integration() {
     // Ensure the peer has the same strong key
     let mut received = [0_u8; 32];
     tcp_stream.read_exact(&mut received).await.unwrap();
-    assert_eq!(received, strong_key);
+    assert_eq!(&received, strong_key.expose_secret());
 
     handle.await.unwrap();
 }
+
+/// Authenticates peers that were given the same strong key
+/// ahead of time, instead of using SPAKE2.
+struct PresharedKey([u8; 32]);
+
+impl PresharedKey {
+    /// Returns proof of knowing the key, in response to `challenge`.
+    fn proof(&self, challenge: [u8; 32]) -> [u8; 32] {
+        sha2::Sha256::new()
+            .chain_update(self.0)
+            .chain_update(challenge)
+            .finalize()
+            .into()
+    }
+}
+
+impl PeerAuthenticator for PresharedKey {
+    type Output = TcpStream;
+
+    async fn authenticate(&self, mut stream: TcpStream) -> Result<TcpStream, Error> {
+        let my_challenge: [u8; 32] = rand::random();
+        stream.write_all(&my_challenge).await?;
+
+        let mut peer_challenge = [0; 32];
+        stream.read_exact(&mut peer_challenge).await?;
+        stream.write_all(&self.proof(peer_challenge)).await?;
+
+        let mut peer_proof = [0; 32];
+        stream.read_exact(&mut peer_proof).await?;
+        if peer_proof != self.proof(my_challenge) {
+            return Err(Error::PeerAuthenticationFailed);
+        }
+        Ok(stream)
+    }
+}
+
+#[tokio::test]
+async fn test_custom_authenticator() {
+    // find 2 free local ports
+    let free_addr = || {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let std::net::SocketAddr::V4(addr) = listener.local_addr().unwrap() else {
+            unreachable!();
+        };
+        addr
+    };
+    let addr_1 = free_addr();
+    let addr_2 = free_addr();
+
+    let contacts = |local: SocketAddrV4, peer: SocketAddrV4| {
+        let local = Contact {
+            v4: Some(local),
+            v6: None,
+        };
+        let peer = FullContact {
+            local: Contact {
+                v4: Some(peer),
+                v6: None,
+            },
+            public: Contact::default(),
+            nat: NatBehavior::default(),
+        };
+        (local, peer)
+    };
+
+    let (local_1, peer_1) = contacts(addr_1, addr_2);
+    let (local_2, peer_2) = contacts(addr_2, addr_1);
+
+    let key = [7; 32];
+    let (stream_1, stream_2) = tokio::join!(
+        try_connect_to_peer_with(local_1, peer_1, PresharedKey(key)),
+        try_connect_to_peer_with(local_2, peer_2, PresharedKey(key)),
+    );
+    let (mut stream_1, info_1) = stream_1.unwrap();
+    let (mut stream_2, info_2) = stream_2.unwrap();
+
+    // the peers only knew each other's local addresses
+    assert_eq!(info_1.candidate, Candidate::Local);
+    assert_eq!(info_2.candidate, Candidate::Local);
+    assert_eq!(info_1.local_addr, info_2.peer_addr);
+    assert_eq!(info_1.peer_addr, info_2.local_addr);
+
+    // ensure the direct connection works
+    stream_1.write_all(b"Hello peer!").await.unwrap();
+    let mut received = [0_u8; 11];
+    stream_2.read_exact(&mut received).await.unwrap();
+    assert_eq!(&received, b"Hello peer!");
+}
+
+/// Confirm that `connect_tcp` moves on from addresses that
+/// refuse connections, and fails if none of them work.
+#[tokio::test]
+async fn test_connect_strategy() {
+    use server_connector::ConnectStrategy;
+    use std::net::SocketAddr;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let reachable = listener.local_addr().unwrap();
+
+    // an address that refuses connections
+    let refused = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let refused_addr = refused.local_addr().unwrap();
+    drop(refused);
+
+    let strategy = ConnectStrategy {
+        stagger: Duration::from_millis(50),
+        per_attempt_timeout: Duration::from_secs(2),
+        overall_timeout: Duration::from_secs(5),
+        parallelism: 2,
+    };
+    let addrs: &[SocketAddr] = &[refused_addr, reachable];
+    let connection = server_connector::connect_tcp(addrs, strategy)
+        .await
+        .unwrap();
+    assert!(connection.v6.is_none());
+    let local = connection.v4.unwrap().local_addr().unwrap();
+    let (_, peer) = listener.accept().await.unwrap();
+    assert_eq!(local, peer);
+
+    // no address works
+    let addrs: &[SocketAddr] = &[refused_addr];
+    let result = server_connector::connect_tcp(addrs, strategy).await;
+    assert!(result.is_err());
+}
+
+/// Confirm that peers with the same room code find each other
+/// on the local network, and derive the same key.
+#[tokio::test]
+async fn test_local_network() {
+    let room_code = Secret::new("local room".to_string());
+    let shared_secret = Secret::new("local secret".to_string());
+    let timeout = Duration::from_secs(10);
+
+    let creator = tokio::time::timeout(
+        timeout,
+        connect_on_local_network(&room_code, &shared_secret, true),
+    );
+    let joiner = tokio::time::timeout(
+        timeout,
+        connect_on_local_network(&room_code, &shared_secret, false),
+    );
+    let (creator, joiner) = tokio::join!(creator, joiner);
+    let (mut creator_stream, creator_key) = creator.unwrap().unwrap();
+    let (mut joiner_stream, joiner_key) = joiner.unwrap().unwrap();
+    assert_eq!(creator_key.expose_secret(), joiner_key.expose_secret());
+
+    creator_stream.write_all(b"Hello peer!").await.unwrap();
+    let mut received = [0; 11];
+    joiner_stream.read_exact(&mut received).await.unwrap();
+    assert_eq!(&received, b"Hello peer!");
+}
+
+/// Accepts one connection on `listener` as a minimal SOCKS5 or HTTP proxy,
+/// and forwards it to the requested address.
+async fn run_fake_proxy(listener: tokio::net::TcpListener, socks5: bool) {
+    let (mut client, _) = listener.accept().await.unwrap();
+    let target = if socks5 {
+        let mut greeting = [0; 3];
+        client.read_exact(&mut greeting).await.unwrap();
+        assert_eq!(greeting, [5, 1, 0]);
+        client.write_all(&[5, 0]).await.unwrap();
+
+        let mut request = [0; 5];
+        client.read_exact(&mut request).await.unwrap();
+        assert_eq!(request[..4], [5, 1, 0, 3]);
+        let mut name = vec![0; usize::from(request[4])];
+        client.read_exact(&mut name).await.unwrap();
+        let port = client.read_u16().await.unwrap();
+        client
+            .write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0])
+            .await
+            .unwrap();
+        format!("{}:{port}", String::from_utf8(name).unwrap())
+    } else {
+        let mut header = Vec::new();
+        while !header.ends_with(b"\r\n\r\n") {
+            header.push(client.read_u8().await.unwrap());
+        }
+        let header = String::from_utf8(header).unwrap();
+        let target = header.split_whitespace().nth(1).unwrap().to_string();
+        assert!(header.starts_with("CONNECT "));
+        client
+            .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+            .await
+            .unwrap();
+        target
+    };
+    let mut server = TcpStream::connect(target).await.unwrap();
+    let _ = tokio::io::copy_bidirectional(&mut client, &mut server).await;
+}
+
+/// Confirm that contacts can be shared through SOCKS5 and HTTP proxies.
+#[tokio::test]
+async fn test_proxy() {
+    let server = Server::builder()
+        .unencrypted()
+        .addresses(["127.0.0.1:0".parse().unwrap()])
+        .start()
+        .unwrap();
+    let server_port = server.addresses()[0].port();
+
+    for socks5 in [true, false] {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = listener.local_addr().unwrap();
+        tokio::spawn(run_fake_proxy(listener, socks5));
+
+        let scheme = if socks5 { "socks5" } else { "http" };
+        let proxy: server_connector::Proxy = format!("{scheme}://{proxy_addr}").parse().unwrap();
+        let mut server_connection = proxy
+            .connect_tcp("127.0.0.1", server_port, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert!(server_connection.v6.is_none());
+
+        let room_code = Secret::new(format!("proxied room {scheme}"));
+        let (my_contact, _) = share_contacts(&mut server_connection, &room_code, true)
+            .await
+            .unwrap();
+        assert!(my_contact.local.v4.is_some());
+    }
+}
+
+#[tokio::test]
+async fn test_relay() {
+    // start a relaying server in the background
+    let server = Server::builder()
+        .unencrypted()
+        .addresses(["127.0.0.1:0".parse().unwrap()])
+        .room_timeout(Duration::from_secs(3600))
+        .request_limit(10, 10)
+        .connection_limit(100, 1000)
+        .relay(1_000_000, 1_000_000)
+        .start()
+        .unwrap();
+    let server_addr = server.addresses()[0];
+    let timeout = Duration::from_secs(5);
+    let room_code = Secret::new("room".to_string());
+
+    let peer = |shared_secret: &'static str, is_creator| {
+        let room_code = room_code.clone();
+        async move {
+            let mut server_connection = server_connector::connect_tcp(server_addr, timeout)
+                .await
+                .unwrap();
+            if is_creator {
+                create_room(&mut server_connection, &room_code)
+                    .await
+                    .unwrap();
+            } else {
+                // give the creator time to create the room
+                tokio::time::sleep(Duration::from_millis(200)).await;
+            }
+            connect_through_relay(
+                server_connection,
+                &room_code,
+                &Secret::new(shared_secret),
+                is_creator,
+            )
+            .await
+        }
+    };
+
+    // both peers derive the same key, and can talk through the relay
+    let ((mut stream_1, key_1), (mut stream_2, key_2)) =
+        tokio::try_join!(peer("secret", true), peer("secret", false)).unwrap();
+    assert_eq!(key_1.expose_secret(), key_2.expose_secret());
+
+    stream_1.write_all(b"Hello peer!").await.unwrap();
+    stream_1.flush().await.unwrap();
+    let mut buf = [0; 11];
+    stream_2.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"Hello peer!");
+
+    // a peer with the wrong secret isn't authenticated
+    let (result_1, result_2) = tokio::join!(peer("secret", true), peer("wrong", false));
+    assert!(matches!(result_1, Err(Error::PeerAuthenticationFailed)));
+    assert!(matches!(result_2, Err(Error::PeerAuthenticationFailed)));
+
+    // servers that don't relay say so
+    let server = Server::builder()
+        .unencrypted()
+        .addresses(["127.0.0.1:0".parse().unwrap()])
+        .start()
+        .unwrap();
+    let server_connection = server_connector::connect_tcp(server.addresses()[0], timeout)
+        .await
+        .unwrap();
+    let result = connect_through_relay(server_connection, &room_code, &room_code, true).await;
+    assert!(matches!(
+        result,
+        Err(Error::UnexpectedServerReply(
+            gday_contact_exchange_protocol::ServerMsg::ErrorRelayDisabled
+        ))
+    ));
+}
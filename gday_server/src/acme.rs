@@ -0,0 +1,83 @@
+//! Automatic provisioning and renewal of the server's TLS certificate
+//! over [ACME](https://en.wikipedia.org/wiki/Automatic_Certificate_Management_Environment),
+//! using the TLS-ALPN-01 challenge.
+use log::{error, info};
+use rustls_acme::{caches::DirCache, is_tls_alpn_challenge, AcmeConfig};
+use std::{path::PathBuf, sync::Arc};
+use tokio::{io::AsyncWriteExt, net::TcpStream};
+use tokio_rustls::{
+    rustls::{server::Acceptor, ServerConfig},
+    server::TlsStream,
+    LazyConfigAcceptor,
+};
+use tokio_stream::StreamExt;
+
+/// Accepts TLS connections using a certificate
+/// that is automatically obtained and renewed via ACME.
+#[derive(Clone)]
+pub struct AcmeAcceptor {
+    /// Config used to answer TLS-ALPN-01 challenges
+    /// from the certificate authority.
+    challenge_config: Arc<ServerConfig>,
+    /// Config used for all other connections.
+    default_config: Arc<ServerConfig>,
+}
+
+impl AcmeAcceptor {
+    /// Spawns a background task that obtains a certificate for `domain`
+    /// and renews it before it expires.
+    ///
+    /// - `email` is given to the certificate authority as a contact.
+    /// - `cache_dir` is where the account key and certificate are stored,
+    ///   so they aren't requested again after every restart.
+    /// - If `production` is false, uses the Let's Encrypt staging directory,
+    ///   whose certificates aren't trusted by clients.
+    ///
+    /// Must be called from a tokio async context.
+    pub fn new(domain: &str, email: Option<&str>, cache_dir: PathBuf, production: bool) -> Self {
+        let mut state = AcmeConfig::new([domain])
+            .contact(email.map(|email| format!("mailto:{email}")))
+            .cache(DirCache::new(cache_dir))
+            .directory_lets_encrypt(production)
+            .state();
+
+        let this = Self {
+            challenge_config: state.challenge_rustls_config(),
+            default_config: state.default_rustls_config(),
+        };
+
+        // the certificate is only obtained and renewed
+        // while this state is being polled
+        tokio::spawn(async move {
+            while let Some(event) = state.next().await {
+                match event {
+                    Ok(event) => info!("ACME: {event:?}"),
+                    Err(err) => error!("ACME error: {err}"),
+                }
+            }
+        });
+
+        this
+    }
+
+    /// Performs a TLS handshake on `tcp_stream`.
+    ///
+    /// Returns `None` if the connection was a TLS-ALPN-01 challenge
+    /// from the certificate authority, which has been answered and closed.
+    pub async fn accept(
+        &self,
+        tcp_stream: TcpStream,
+    ) -> std::io::Result<Option<TlsStream<TcpStream>>> {
+        let handshake = LazyConfigAcceptor::new(Acceptor::default(), tcp_stream).await?;
+
+        if is_tls_alpn_challenge(&handshake.client_hello()) {
+            info!("Answering ACME TLS-ALPN-01 challenge.");
+            let mut tls_stream = handshake.into_stream(self.challenge_config.clone()).await?;
+            tls_stream.shutdown().await?;
+            Ok(None)
+        } else {
+            let tls_stream = handshake.into_stream(self.default_config.clone()).await?;
+            Ok(Some(tls_stream))
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use crate::{event_log::EventLog, tenants::Tenants, Error};
+use log::{debug, info, warn};
+use std::{io::ErrorKind, net::IpAddr, path::Path, time::Instant};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+};
+
+/// Binds the admin interface to a unix socket at `path`,
+/// replacing any stale socket file left there.
+///
+/// Only local users allowed to open `path` can connect.
+pub fn bind(path: &Path) -> Result<UnixListener, Error> {
+    match std::fs::remove_file(path) {
+        Err(err) if err.kind() != ErrorKind::NotFound => {
+            return Err(Error {
+                msg: format!("Couldn't remove old admin socket {path:?}."),
+                source: err,
+            })
+        }
+        _ => (),
+    }
+
+    UnixListener::bind(path).map_err(|source| Error {
+        msg: format!("Couldn't bind admin socket {path:?}."),
+        source,
+    })
+}
+
+/// What admin commands can inspect and change.
+#[derive(Clone)]
+struct Server {
+    event_log: EventLog,
+    tenants: Tenants,
+    /// When the server started
+    start: Instant,
+}
+
+/// Answers admin commands sent to `listener`.
+///
+/// Each command is one line, and gets a one-line JSON reply.
+/// Supported commands:
+/// - `status`: uptime, and the number of open rooms of each tenant
+/// - `requests`: number of recent requests from each IP address
+/// - `events`: the recent room events
+/// - `events <FILTER>`: the recent room events whose origin IP address
+///   or room hash is `FILTER`
+/// - `expire <ROOM HASH>`: deletes the room with this hash right away
+pub async fn serve(listener: UnixListener, event_log: EventLog, tenants: Tenants) {
+    let server = Server {
+        event_log,
+        tenants,
+        start: Instant::now(),
+    };
+
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                warn!("Error accepting admin connection: {err}");
+                continue;
+            }
+        };
+
+        let server = server.clone();
+        tokio::spawn(async move {
+            if let Err(err) = handle_admin_connection(stream, &server).await {
+                debug!("Admin connection closed with error: {err}");
+            }
+        });
+    }
+}
+
+/// Answers each command sent on `stream` until it closes.
+async fn handle_admin_connection(stream: UnixStream, server: &Server) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        let reply = respond(&line, server);
+        writer.write_all(format!("{reply}\n").as_bytes()).await?;
+    }
+    Ok(())
+}
+
+/// Returns the JSON reply to the admin `command`.
+fn respond(command: &str, server: &Server) -> serde_json::Value {
+    let mut words = command.split_whitespace();
+    let event_log = &server.event_log;
+    let tenants = &server.tenants;
+
+    match (words.next(), words.next(), words.next()) {
+        (Some("status"), None, None) => {
+            let tenant_rooms: serde_json::Map<_, _> = tenants
+                .iter()
+                .map(|tenant| (tenant.name.to_string(), tenant.state.room_count().into()))
+                .collect();
+            serde_json::json!({
+                "uptime_secs": server.start.elapsed().as_secs(),
+                "rooms": tenants.default_state().room_count(),
+                "tenant_rooms": tenant_rooms,
+            })
+        }
+        (Some("requests"), None, None) => {
+            let states = std::iter::once((None, tenants.default_state()))
+                .chain(tenants.iter().map(|t| (Some(&*t.name), &t.state)));
+            let mut counts: Vec<serde_json::Value> = Vec::new();
+            for (tenant, state) in states {
+                for (ip, requests) in state.request_counts() {
+                    counts.push(serde_json::json!({
+                        "ip": ip,
+                        "tenant": tenant,
+                        "requests": requests,
+                    }));
+                }
+            }
+            serde_json::json!(counts)
+        }
+        (Some("events"), None, None) => serde_json::json!(event_log.events(|_| true)),
+        (Some("events"), Some(filter), None) => {
+            let ip = filter.parse::<IpAddr>().ok();
+            serde_json::json!(
+                event_log.events(|event| Some(event.origin) == ip || event.room == filter)
+            )
+        }
+        (Some("expire"), Some(room), None) => {
+            // room hashes may collide between tenants, so check them all
+            let mut expired = tenants.default_state().expire_room(room);
+            for tenant in tenants.iter() {
+                expired |= tenant.state.expire_room(room);
+            }
+            if expired {
+                info!(event = "room_expired", room = room; "Deleted room {room} on admin request.");
+            }
+            serde_json::json!({ "expired": expired })
+        }
+        _ => serde_json::json!({ "error": format!("Unknown command: {command:?}") }),
+    }
+}
@@ -0,0 +1,222 @@
+use crate::{Args, Error, LogFormat, TenantConfig};
+use clap::{parser::ValueSource, ArgMatches, CommandFactory, FromArgMatches, Parser};
+use ipnet::IpNet;
+use serde::Deserialize;
+use std::{ffi::OsString, io::ErrorKind, net::SocketAddr, path::PathBuf};
+
+/// Options that can be set in a `--config` TOML file.
+///
+/// Each key has the same name and meaning as the
+/// corresponding command line flag (in snake_case).
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    key: Option<PathBuf>,
+    certificate: Option<PathBuf>,
+    unencrypted: Option<bool>,
+    acme_domain: Option<String>,
+    acme_email: Option<String>,
+    acme_cache: Option<PathBuf>,
+    acme_staging: Option<bool>,
+    addresses: Option<Vec<SocketAddr>>,
+    timeout: Option<u64>,
+    request_limit: Option<u32>,
+    request_burst: Option<u32>,
+    max_connections_per_ip: Option<u32>,
+    max_connections: Option<u32>,
+    deny_cidr: Option<Vec<IpNet>>,
+    allow_cidr: Option<Vec<IpNet>>,
+    state_file: Option<PathBuf>,
+    relay: Option<bool>,
+    relay_bandwidth: Option<u64>,
+    relay_byte_limit: Option<u64>,
+    event_log_size: Option<usize>,
+    admin_socket: Option<PathBuf>,
+    proxy_protocol: Option<bool>,
+    require_tenant: Option<bool>,
+    tenants: Option<Vec<TenantConfig>>,
+    verbosity: Option<log::LevelFilter>,
+    log_format: Option<LogFormat>,
+}
+
+impl Default for Args {
+    /// Returns the defaults of the command line flags,
+    /// without a TLS mode chosen.
+    fn default() -> Self {
+        // `--unencrypted` satisfies the required TLS flags,
+        // so every other option gets its default
+        let mut args = Self::parse_from(["gday_server", "--unencrypted"]);
+        args.unencrypted = false;
+        args
+    }
+}
+
+impl Args {
+    /// Parses the command line arguments, and fills in
+    /// any options not passed on the command line from
+    /// the `--config` file, if one was given.
+    ///
+    /// Like [`clap::Parser::parse()`], exits the process
+    /// if the command line arguments are invalid.
+    pub fn parse_with_config() -> Result<Self, Error> {
+        Self::with_config_file(Self::command().get_matches())
+    }
+
+    /// Creates [`Args`] from the TOML config file at `path`.
+    ///
+    /// Options missing from the file get their usual default values.
+    pub fn from_config_file(path: impl Into<PathBuf>) -> Result<Self, Error> {
+        let path: PathBuf = path.into();
+        Self::parse_with_config_from([
+            OsString::from("gday_server"),
+            "--config".into(),
+            path.into(),
+        ])
+    }
+
+    /// Like [`Args::parse_with_config()`], but parses
+    /// the command line arguments from `itr`.
+    ///
+    /// Returns an [`Error`] instead of exiting
+    /// if the arguments are invalid, or ask for `--help`.
+    pub fn parse_with_config_from<I, T>(itr: I) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let matches = Self::command()
+            .try_get_matches_from(itr)
+            .map_err(invalid_arguments)?;
+        Self::with_config_file(matches)
+    }
+
+    /// Creates [`Args`] from the parsed command line arguments in `matches`,
+    /// filling in options not passed on the command line from the `--config` file.
+    fn with_config_file(matches: ArgMatches) -> Result<Self, Error> {
+        let mut args = Self::from_arg_matches(&matches).map_err(invalid_arguments)?;
+
+        let Some(path) = &args.config else {
+            return Ok(args);
+        };
+
+        let contents = std::fs::read_to_string(path).map_err(|source| Error {
+            msg: format!("Couldn't read config file {path:?}."),
+            source,
+        })?;
+
+        let file: ConfigFile = toml::from_str(&contents).map_err(|source| Error {
+            msg: format!("Couldn't parse config file {path:?}."),
+            source: std::io::Error::new(ErrorKind::InvalidData, source),
+        })?;
+
+        // flags passed on the command line take precedence over the file
+        let from_cli = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+
+        macro_rules! merge {
+            ($($field:ident),*) => {
+                $(
+                    if let Some(value) = file.$field {
+                        if !from_cli(stringify!($field)) {
+                            args.$field = value.into();
+                        }
+                    }
+                )*
+            };
+        }
+
+        // choosing any TLS mode on the command line
+        // replaces the TLS mode chosen in the file
+        let tls_mode_from_cli = ["key", "certificate", "unencrypted", "acme_domain"]
+            .into_iter()
+            .any(from_cli);
+
+        if !tls_mode_from_cli {
+            merge!(key, certificate, unencrypted, acme_domain);
+        }
+
+        merge!(
+            acme_email,
+            acme_cache,
+            acme_staging,
+            addresses,
+            timeout,
+            request_limit,
+            request_burst,
+            max_connections_per_ip,
+            max_connections,
+            deny_cidr,
+            allow_cidr,
+            state_file,
+            relay,
+            relay_bandwidth,
+            relay_byte_limit,
+            event_log_size,
+            admin_socket,
+            proxy_protocol,
+            require_tenant,
+            verbosity,
+            log_format
+        );
+
+        // tenants can't be passed on the command line
+        if let Some(tenants) = file.tenants {
+            args.tenants = tenants;
+        }
+
+        Ok(args)
+    }
+
+    /// Checks for combinations of options that conflict
+    /// or are missing.
+    ///
+    /// Clap already enforces these for command line arguments,
+    /// but options may also come from a config file or be set directly.
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        let invalid = |msg: &str| Error {
+            msg: msg.to_string(),
+            source: ErrorKind::InvalidInput.into(),
+        };
+
+        let has_static_tls = self.key.is_some() || self.certificate.is_some();
+
+        if self.acme_domain.is_some() && (self.unencrypted || has_static_tls) {
+            return Err(invalid(
+                "acme_domain can't be combined with key, certificate, or unencrypted.",
+            ));
+        }
+
+        if self.unencrypted && has_static_tls {
+            return Err(invalid(
+                "unencrypted can't be combined with key or certificate.",
+            ));
+        }
+
+        if !self.unencrypted
+            && self.acme_domain.is_none()
+            && (self.key.is_none() || self.certificate.is_none())
+        {
+            return Err(invalid(
+                "Both key and certificate are required, unless unencrypted or acme_domain is set.",
+            ));
+        }
+
+        if self.require_tenant && self.tenants.is_empty() {
+            return Err(invalid("require_tenant needs at least one tenant."));
+        }
+
+        let mut api_keys = std::collections::HashSet::new();
+        if !self.tenants.iter().all(|t| api_keys.insert(&t.api_key)) {
+            return Err(invalid("Each tenant needs a different api_key."));
+        }
+
+        Ok(())
+    }
+}
+
+/// Turns a command line parsing error from clap into an [`Error`].
+fn invalid_arguments(err: clap::Error) -> Error {
+    Error {
+        msg: "Invalid command line arguments.".to_string(),
+        source: std::io::Error::new(ErrorKind::InvalidInput, err.to_string()),
+    }
+}
@@ -1,102 +1,295 @@
-use crate::state::{self, State};
+use crate::{
+    acme::AcmeAcceptor,
+    event_log::{EventLog, RoomEvent, RoomEventKind},
+    logging::room_hash,
+    relay::{self, Relay},
+    state::{self, State},
+    tenants::Tenants,
+};
 use gday_contact_exchange_protocol::{read_from_async, write_to_async, ClientMsg, ServerMsg};
-use log::{error, info, warn};
-use std::net::SocketAddr;
+use log::{debug, info, warn};
+use std::{
+    net::SocketAddr,
+    ops::ControlFlow,
+    time::{Instant, SystemTime},
+};
 use tokio::{
     io::{AsyncRead, AsyncWrite, AsyncWriteExt},
     net::TcpStream,
 };
 use tokio_rustls::TlsAcceptor;
 
+/// How incoming connections are secured.
+#[derive(Clone)]
+pub enum TlsMode {
+    /// Plain unencrypted TCP.
+    Unencrypted,
+    /// TLS with a certificate loaded from files.
+    Static(TlsAcceptor),
+    /// TLS with a certificate obtained and renewed via ACME.
+    Acme(AcmeAcceptor),
+}
+
+impl TlsMode {
+    /// Returns `true` unless this is [`TlsMode::Unencrypted`].
+    pub fn is_encrypted(&self) -> bool {
+        !matches!(self, Self::Unencrypted)
+    }
+}
+
 /// Handle this incoming `tcp_stream`.
-/// Establishes a TLS connection if `tls_acceptor.is_some()`
+/// Establishes a TLS connection according to `tls_mode`.
 /// Handles all incoming requests.
 /// Logs information and errors with [`log`].
 pub async fn handle_connection(
+    tcp_stream: TcpStream,
+    origin: SocketAddr,
+    tls_mode: TlsMode,
+    tenants: Tenants,
+    relay: Option<Relay>,
+    event_log: EventLog,
+) {
+    let start = Instant::now();
+    serve_connection(tcp_stream, origin, tls_mode, tenants, relay, event_log).await;
+    let duration = start.elapsed();
+    debug!(
+        event = "connection_closed", origin:% = origin.ip(), duration_ms = duration.as_millis() as u64;
+        "Closed connection with '{origin}' after {duration:?}."
+    );
+}
+
+/// Does the work of [`handle_connection()`].
+async fn serve_connection(
     mut tcp_stream: TcpStream,
     origin: SocketAddr,
-    tls_acceptor: Option<TlsAcceptor>,
-    state: State,
+    tls_mode: TlsMode,
+    tenants: Tenants,
+    relay: Option<Relay>,
+    event_log: EventLog,
 ) {
-    if let Some(tls_acceptor) = tls_acceptor {
-        let mut tls_stream = match tls_acceptor.accept(tcp_stream).await {
-            Ok(tls_stream) => tls_stream,
-            Err(err) => {
-                warn!("Error establishing TLS connection with '{origin}': {err}");
-                return;
-            }
-        };
-        let _ = handle_requests(&mut tls_stream, state, origin).await;
-        // Graceful TLS termination
-        let _ = tls_stream.shutdown().await;
-    } else {
-        let _ = handle_requests(&mut tcp_stream, state, origin).await;
-    }
+    let tls_stream = match tls_mode {
+        TlsMode::Unencrypted => {
+            let _ = handle_requests(&mut tcp_stream, tenants, relay, event_log, origin).await;
+            return;
+        }
+        TlsMode::Static(tls_acceptor) => tls_acceptor.accept(tcp_stream).await,
+        TlsMode::Acme(acme_acceptor) => match acme_acceptor.accept(tcp_stream).await {
+            Ok(Some(tls_stream)) => Ok(tls_stream),
+            // this was an ACME challenge, which has already been answered
+            Ok(None) => return,
+            Err(err) => Err(err),
+        },
+    };
+
+    let mut tls_stream = match tls_stream {
+        Ok(tls_stream) => tls_stream,
+        Err(err) => {
+            warn!(
+                event = "tls_error", origin:% = origin.ip();
+                "Error establishing TLS connection with '{origin}': {err}"
+            );
+            return;
+        }
+    };
+    let _ = handle_requests(&mut tls_stream, tenants, relay, event_log, origin).await;
+    // Graceful TLS termination
+    let _ = tls_stream.shutdown().await;
 }
 
 /// Handles requests from this connection.
 /// Returns an error if any problem is encountered.
 async fn handle_requests(
     stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
-    mut state: State,
+    tenants: Tenants,
+    relay: Option<Relay>,
+    event_log: EventLog,
     origin: SocketAddr,
 ) -> Result<(), HandleMessageError> {
+    // state of the tenant this connection authenticated as,
+    // or `None` if it must authenticate first
+    let mut state = (!tenants.required()).then(|| tenants.default_state().clone());
+
     loop {
-        let result = handle_message(stream, &mut state, origin).await;
+        let result = handle_message(
+            stream,
+            &mut state,
+            &tenants,
+            relay.as_ref(),
+            &event_log,
+            origin,
+        )
+        .await;
         match result {
-            Ok(()) => (),
+            Ok(ControlFlow::Continue(())) => (),
+            // the connection was handed over to the relay, and is done
+            Ok(ControlFlow::Break(())) => return Ok(()),
             Err(HandleMessageError::State(state::Error::NoSuchRoomCode)) => {
-                warn!("Replying with ServerMsg::ErrorNoSuchRoomCode.");
+                warn!(event = "request_error", error = "no_such_room", origin:% = origin.ip(); "Replying with ServerMsg::ErrorNoSuchRoomCode.");
                 write_to_async(ServerMsg::ErrorNoSuchRoomCode, stream).await?;
             }
             Err(HandleMessageError::Receiver(_)) => {
-                warn!("Replying with ServerMsg::ErrorPeerTimedOut.");
+                warn!(event = "request_error", error = "peer_timed_out", origin:% = origin.ip(); "Replying with ServerMsg::ErrorPeerTimedOut.");
                 write_to_async(ServerMsg::ErrorPeerTimedOut, stream).await?;
             }
             Err(HandleMessageError::State(state::Error::RoomCodeTaken)) => {
-                warn!("Replying with ServerMsg::ErrorRoomTaken.");
+                warn!(event = "request_error", error = "room_taken", origin:% = origin.ip(); "Replying with ServerMsg::ErrorRoomTaken.");
                 write_to_async(ServerMsg::ErrorRoomTaken, stream).await?;
             }
             Err(HandleMessageError::State(state::Error::TooManyRequests)) => {
-                warn!("Replying with ServerMsg::ErrorTooManyRequests and disconnecting.");
+                warn!(event = "request_error", error = "too_many_requests", origin:% = origin.ip(); "Replying with ServerMsg::ErrorTooManyRequests and disconnecting.");
                 write_to_async(ServerMsg::ErrorTooManyRequests, stream).await?;
-                return result;
+                return result.map(|_| ());
             }
             Err(HandleMessageError::State(state::Error::CantUpdateDoneClient)) => {
-                warn!("Replying with ServerMsg::ErrorUnexpectedMsg.");
+                warn!(event = "request_error", error = "unexpected_msg", origin:% = origin.ip(); "Replying with ServerMsg::ErrorUnexpectedMsg.");
                 write_to_async(ServerMsg::ErrorUnexpectedMsg, stream).await?;
             }
+            Err(HandleMessageError::State(state::Error::TooManyRooms)) => {
+                warn!(event = "request_error", error = "too_many_rooms", origin:% = origin.ip(); "Replying with ServerMsg::ErrorTooManyRooms.");
+                write_to_async(ServerMsg::ErrorTooManyRooms, stream).await?;
+            }
+            Err(HandleMessageError::InvalidToken) => {
+                warn!(event = "request_error", error = "invalid_token", origin:% = origin.ip(); "Replying with ServerMsg::ErrorInvalidToken.");
+                write_to_async(ServerMsg::ErrorInvalidToken, stream).await?;
+            }
+            Err(HandleMessageError::AuthRequired) => {
+                warn!(event = "request_error", error = "auth_required", origin:% = origin.ip(); "Replying with ServerMsg::ErrorAuthRequired and disconnecting.");
+                write_to_async(ServerMsg::ErrorAuthRequired, stream).await?;
+                return result.map(|_| ());
+            }
+            Err(HandleMessageError::Relay(relay::Error::Disabled)) => {
+                warn!(event = "request_error", error = "relay_disabled", origin:% = origin.ip(); "Replying with ServerMsg::ErrorRelayDisabled.");
+                write_to_async(ServerMsg::ErrorRelayDisabled, stream).await?;
+            }
+            Err(HandleMessageError::Relay(relay::Error::RoleTaken)) => {
+                warn!(event = "request_error", error = "relay_role_taken", origin:% = origin.ip(); "Replying with ServerMsg::ErrorRoomTaken.");
+                write_to_async(ServerMsg::ErrorRoomTaken, stream).await?;
+            }
+            Err(HandleMessageError::Relay(relay::Error::PeerTimedOut)) => {
+                warn!(event = "request_error", error = "peer_timed_out", origin:% = origin.ip(); "Replying with ServerMsg::ErrorPeerTimedOut.");
+                write_to_async(ServerMsg::ErrorPeerTimedOut, stream).await?;
+            }
             Err(HandleMessageError::Protocol(ref err)) => {
-                warn!("Replying with ServerMsg::ErrorSyntax and disconnecting, because: {err}");
+                warn!(
+                    event = "request_error", error = "syntax", origin:% = origin.ip();
+                    "Replying with ServerMsg::ErrorSyntax and disconnecting, because: {err}"
+                );
                 write_to_async(ServerMsg::ErrorSyntax, stream).await?;
-                return result;
+                return result.map(|_| ());
             }
             Err(HandleMessageError::UnknownMessage(msg)) => {
-                warn!("Replying with ServerMsg::ErrorSyntax because received unknown message: {msg:?}");
+                warn!(
+                    event = "request_error", error = "unknown_msg", origin:% = origin.ip();
+                    "Replying with ServerMsg::ErrorSyntax because received unknown message: {msg:?}"
+                );
                 write_to_async(ServerMsg::ErrorSyntax, stream).await?;
-                return result;
+                return result.map(|_| ());
             }
             Err(HandleMessageError::IO(_)) => {
-                info!("'{origin}' disconnected.");
-                return result;
+                info!(event = "disconnected", origin:% = origin.ip(); "'{origin}' disconnected.");
+                return result.map(|_| ());
             }
         }
     }
 }
 
-/// Read and handle a single message
+/// Read and handle a single message.
+///
+/// Returns [`ControlFlow::Break`] if no more messages
+/// should be read from this connection.
 async fn handle_message(
     stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
-    state: &mut State,
+    state: &mut Option<State>,
+    tenants: &Tenants,
+    relay: Option<&Relay>,
+    event_log: &EventLog,
     origin: SocketAddr,
-) -> Result<(), HandleMessageError> {
+) -> Result<ControlFlow<()>, HandleMessageError> {
     // read the next message from the client
     let msg: ClientMsg = read_from_async(stream).await?;
 
+    if let ClientMsg::Authenticate { token } = msg {
+        let Some(tenant) = tenants.get(&token) else {
+            // count failed attempts, to slow down guessing
+            tenants
+                .default_state()
+                .increment_request_count(origin.ip())?;
+            return Err(HandleMessageError::InvalidToken);
+        };
+        *state = Some(tenant.state.clone());
+
+        info!(
+            event = "authenticated", tenant = &*tenant.name, origin:% = origin.ip();
+            "'{origin}' authenticated as tenant '{}'.", tenant.name
+        );
+
+        write_to_async(ServerMsg::Authenticated, stream).await?;
+        return Ok(ControlFlow::Continue(()));
+    }
+
+    let (room_code, is_creator) = match msg {
+        ClientMsg::CreateRoom { room_code } => (room_code, Some(true)),
+        ClientMsg::RecordPublicAddr {
+            room_code,
+            is_creator,
+        }
+        | ClientMsg::ReadyToShare {
+            room_code,
+            is_creator,
+            ..
+        }
+        | ClientMsg::RelayConnect {
+            room_code,
+            is_creator,
+        } => (room_code, Some(is_creator)),
+        unknown_msg => return Err(HandleMessageError::UnknownMessage(unknown_msg)),
+    };
+
+    let record = |kind| {
+        event_log.record(RoomEvent {
+            time: SystemTime::now(),
+            room: room_hash(&room_code),
+            origin: origin.ip(),
+            is_creator,
+            kind,
+        })
+    };
+
+    let result = match state {
+        Some(state) => respond_to_message(msg, stream, state, relay, &record, origin).await,
+        None => Err(HandleMessageError::AuthRequired),
+    };
+    if let Err(err) = &result {
+        if let Some(kind) = err.event_kind() {
+            record(kind);
+        }
+    }
+    result
+}
+
+/// Responds to `msg`, calling `record` with each
+/// event it causes, other than errors.
+///
+/// Returns [`ControlFlow::Break`] if no more messages
+/// should be read from this connection.
+async fn respond_to_message(
+    msg: ClientMsg,
+    stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+    state: &mut State,
+    relay: Option<&Relay>,
+    record: &impl Fn(RoomEventKind),
+    origin: SocketAddr,
+) -> Result<ControlFlow<()>, HandleMessageError> {
     match msg {
         ClientMsg::CreateRoom { room_code } => {
             // try to create a room
             state.create_room(room_code, origin.ip())?;
+            record(RoomEventKind::Created);
+
+            debug!(
+                event = "room_created", room = room_hash(&room_code), origin:% = origin.ip();
+                "Created a room for '{origin}'."
+            );
 
             // acknowledge that a room was created
             write_to_async(ServerMsg::RoomCreated, stream).await?;
@@ -108,6 +301,7 @@ async fn handle_message(
         } => {
             // record their public socket address from the connection
             state.update_client(room_code, is_creator, origin, true, origin.ip())?;
+            record(RoomEventKind::AddrRecorded);
 
             // acknowledge the receipt
             write_to_async(ServerMsg::ReceivedAddr, stream).await?;
@@ -142,20 +336,105 @@ async fn handle_message(
 
             // responds to the client with their own contact info
             write_to_async(ServerMsg::ClientContact(client_contact), stream).await?;
+            record(RoomEventKind::ContactSent);
 
-            info!("Sent client '{origin}' their contact of '{client_contact}'.");
+            info!(
+                event = "client_contact_sent", room = room_hash(&room_code), origin:% = origin.ip();
+                "Sent client '{origin}' their contact of '{client_contact}'."
+            );
 
             // wait for the peer to be done sending as well
             let peer_contact = rx.await?;
 
             // send the peer's contact info to this client
             write_to_async(ServerMsg::PeerContact(peer_contact), stream).await?;
+            record(RoomEventKind::PeerContactSent);
 
-            info!("Sent client '{origin}' their peer's contact of '{client_contact}'.");
+            info!(
+                event = "peer_contact_sent", room = room_hash(&room_code), origin:% = origin.ip();
+                "Sent client '{origin}' their peer's contact of '{peer_contact}'."
+            );
+        }
+
+        ClientMsg::RelayConnect {
+            room_code,
+            is_creator,
+        } => {
+            let relay = relay.ok_or(relay::Error::Disabled)?;
+            state.increment_request_count(origin.ip())?;
+
+            // only relay peers that met in a room of this tenant
+            state.check_relay_room(room_code, origin.ip())?;
+
+            // wait for the other peer to join
+            let pipe_receiver = relay.join(state.id(), room_code, is_creator)?;
+            let Ok(Ok(mut pipe)) =
+                tokio::time::timeout(relay.limits().timeout, pipe_receiver).await
+            else {
+                relay.forget_abandoned(state.id(), room_code);
+                return Err(relay::Error::PeerTimedOut.into());
+            };
+
+            // the peers met, so the room can be reopened, like after exchanging contacts
+            state.close_room(room_code);
+
+            write_to_async(ServerMsg::RelayStarted, stream).await?;
+            record(RoomEventKind::RelayStarted);
+
+            info!(
+                event = "relay_started",
+                room = room_hash(&room_code),
+                origin:% = origin.ip(),
+                active_relays = relay.metrics().active_rooms;
+                "Started relaying traffic of '{origin}'."
+            );
+
+            let start = Instant::now();
+            let teardown = relay.run(stream, &mut pipe).await;
+            let bytes = pipe.bytes_relayed();
+            let duration = start.elapsed();
+            record(RoomEventKind::RelayClosed);
+
+            info!(
+                event = "relay_closed",
+                room = room_hash(&room_code),
+                origin:% = origin.ip(),
+                reason = teardown.as_str(),
+                bytes = bytes,
+                duration_ms = duration.as_millis() as u64;
+                "Stopped relaying traffic of '{origin}' after {bytes} bytes and {duration:?}, because: {}",
+                teardown.as_str()
+            );
+
+            return Ok(ControlFlow::Break(()));
         }
         unknown_msg => return Err(HandleMessageError::UnknownMessage(unknown_msg)),
     }
-    Ok(())
+    Ok(ControlFlow::Continue(()))
+}
+
+impl HandleMessageError {
+    /// Returns the kind of [`RoomEvent`] this error should be recorded as,
+    /// or `None` if it shouldn't be recorded.
+    fn event_kind(&self) -> Option<RoomEventKind> {
+        match self {
+            Self::State(state::Error::NoSuchRoomCode) => Some(RoomEventKind::NoSuchRoom),
+            Self::State(state::Error::RoomCodeTaken) => Some(RoomEventKind::RoomTaken),
+            Self::State(state::Error::TooManyRequests) => Some(RoomEventKind::TooManyRequests),
+            Self::State(state::Error::CantUpdateDoneClient) => Some(RoomEventKind::UnexpectedMsg),
+            Self::State(state::Error::TooManyRooms) => Some(RoomEventKind::TooManyRooms),
+            Self::Receiver(_) | Self::Relay(relay::Error::PeerTimedOut) => {
+                Some(RoomEventKind::PeerTimedOut)
+            }
+            Self::Relay(relay::Error::Disabled) => Some(RoomEventKind::RelayDisabled),
+            Self::Relay(relay::Error::RoleTaken) => Some(RoomEventKind::RelayRoleTaken),
+            Self::Protocol(_)
+            | Self::IO(_)
+            | Self::UnknownMessage(_)
+            | Self::InvalidToken
+            | Self::AuthRequired => None,
+        }
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -169,6 +448,10 @@ enum HandleMessageError {
     #[error("Error updating server state: {0}")]
     State(#[from] state::Error),
 
+    /// Error joining a relay room
+    #[error("Error joining a relay room: {0}")]
+    Relay(#[from] relay::Error),
+
     /// Timed out while waiting for other peer to share contact
     #[error("Timed out while waiting for other peer to share contact: {0}")]
     Receiver(#[from] tokio::sync::oneshot::error::RecvError),
@@ -177,6 +460,14 @@ enum HandleMessageError {
     #[error("IO Error: {0}")]
     IO(#[from] std::io::Error),
 
+    /// No tenant has the token the client authenticated with
+    #[error("No tenant has the token the client authenticated with")]
+    InvalidToken,
+
+    /// Client sent a request without authenticating as a tenant
+    #[error("Client sent a request without authenticating as a tenant")]
+    AuthRequired,
+
     /// Received unknown message from client
     #[error("Received unknown message from client:\n{0:?}")]
     UnknownMessage(gday_contact_exchange_protocol::ClientMsg),
@@ -0,0 +1,124 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+
+/// Number of currently open connections.
+#[derive(Default, Debug)]
+struct ConnectionCounts {
+    /// Maps IP addresses to their number of open connections
+    per_ip: HashMap<IpAddr, u32>,
+    /// Number of open connections from all IP addresses
+    total: u32,
+}
+
+/// Limits the number of simultaneously open connections,
+/// both per IP address and in total.
+///
+/// Cloning returns a reference to the same limiter.
+#[derive(Clone, Debug)]
+pub struct ConnectionLimiter {
+    counts: Arc<Mutex<ConnectionCounts>>,
+
+    /// Max number of open connections from a single IP address
+    max_per_ip: u32,
+
+    /// Max number of open connections in total
+    max_total: u32,
+}
+
+impl ConnectionLimiter {
+    /// Creates a new [`ConnectionLimiter`] with the given limits.
+    pub fn new(max_per_ip: u32, max_total: u32) -> Self {
+        Self {
+            counts: Arc::default(),
+            max_per_ip,
+            max_total,
+        }
+    }
+
+    /// Returns a [`ConnectionPermit`] for a new connection from `ip`,
+    /// or `None` if that would exceed a limit.
+    ///
+    /// The connection counts as open until the permit is dropped.
+    pub fn try_acquire(&self, ip: IpAddr) -> Option<ConnectionPermit> {
+        let mut counts = self.counts.lock().expect("Couldn't acquire state lock.");
+
+        if counts.total >= self.max_total {
+            return None;
+        }
+
+        let ip_count = counts.per_ip.entry(ip).or_insert(0);
+        if *ip_count >= self.max_per_ip {
+            return None;
+        }
+        *ip_count += 1;
+        counts.total += 1;
+
+        Some(ConnectionPermit {
+            limiter: self.clone(),
+            ip,
+        })
+    }
+
+    /// Marks a connection from `ip` as closed.
+    fn release(&self, ip: IpAddr) {
+        let mut counts = self.counts.lock().expect("Couldn't acquire state lock.");
+
+        if let Some(ip_count) = counts.per_ip.get_mut(&ip) {
+            *ip_count -= 1;
+            if *ip_count == 0 {
+                counts.per_ip.remove(&ip);
+            }
+        }
+        counts.total -= 1;
+    }
+}
+
+/// Proof that a connection was allowed by a [`ConnectionLimiter`].
+///
+/// Releases the connection's slot when dropped.
+#[derive(Debug)]
+pub struct ConnectionPermit {
+    limiter: ConnectionLimiter,
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.limiter.release(self.ip);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ConnectionLimiter;
+    use std::net::IpAddr;
+
+    #[test]
+    fn test_connection_limits() {
+        let limiter = ConnectionLimiter::new(2, 3);
+        let ip1 = IpAddr::V4(123.into());
+        let ip2 = IpAddr::V6(456.into());
+
+        // per-IP limit
+        let permit1 = limiter.try_acquire(ip1).unwrap();
+        let permit2 = limiter.try_acquire(ip1).unwrap();
+        assert!(limiter.try_acquire(ip1).is_none());
+
+        // global limit
+        let permit3 = limiter.try_acquire(ip2).unwrap();
+        assert!(limiter.try_acquire(ip2).is_none());
+
+        // dropping a permit frees a slot
+        drop(permit1);
+        assert!(limiter.try_acquire(ip2).is_some());
+        let _permit4 = limiter.try_acquire(ip1).unwrap();
+        assert!(limiter.try_acquire(ip1).is_none());
+
+        drop(permit2);
+        drop(permit3);
+        assert!(limiter.try_acquire(ip2).is_some());
+    }
+}
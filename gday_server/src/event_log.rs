@@ -0,0 +1,138 @@
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+/// What happened in a [`RoomEvent`].
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RoomEventKind {
+    /// A client created the room
+    Created,
+    /// A client tried to create the room, but it was taken
+    RoomTaken,
+    /// A client sent a request for a room that doesn't exist
+    NoSuchRoom,
+    /// The server recorded a client's public address
+    AddrRecorded,
+    /// A client finished sending its addresses, and got its contact back
+    ContactSent,
+    /// A client got its peer's contact
+    PeerContactSent,
+    /// A client gave up waiting for its peer
+    PeerTimedOut,
+    /// A client sent another request after finishing
+    UnexpectedMsg,
+    /// A client's request was rejected for exceeding the request limit
+    TooManyRequests,
+    /// A client's room wasn't created, because its tenant
+    /// already had the max number of rooms open
+    TooManyRooms,
+    /// A client asked for a relay, but relaying is disabled
+    RelayDisabled,
+    /// A client asked for a relay as a role that's already waiting
+    RelayRoleTaken,
+    /// The server started relaying a client's traffic
+    RelayStarted,
+    /// The server stopped relaying a client's traffic
+    RelayClosed,
+}
+
+/// Something that happened in a room.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct RoomEvent {
+    /// When it happened
+    pub time: SystemTime,
+    /// Hash of the room code, from [`crate::logging::room_hash()`]
+    pub room: String,
+    /// IP address of the client involved
+    pub origin: IpAddr,
+    /// Whether the client involved created the room, if known
+    pub is_creator: Option<bool>,
+    /// What happened
+    pub kind: RoomEventKind,
+}
+
+/// Remembers the most recent [`RoomEvent`]s, forgetting the oldest
+/// once it's full.
+///
+/// Cloning returns a reference to the same log.
+#[derive(Clone, Debug)]
+pub struct EventLog {
+    events: Arc<Mutex<VecDeque<RoomEvent>>>,
+    /// Max number of events remembered
+    capacity: usize,
+}
+
+impl EventLog {
+    /// Creates an empty [`EventLog`] that remembers
+    /// up to `capacity` events.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            events: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    /// Adds `event` to the log, forgetting the oldest event if full.
+    pub fn record(&self, event: RoomEvent) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut events = self.events.lock().expect("Couldn't acquire state lock.");
+        if events.len() == self.capacity {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+
+    /// Returns the remembered events, oldest first,
+    /// that `filter` returns `true` for.
+    pub fn events(&self, filter: impl Fn(&RoomEvent) -> bool) -> Vec<RoomEvent> {
+        let events = self.events.lock().expect("Couldn't acquire state lock.");
+        events
+            .iter()
+            .filter(|event| filter(event))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EventLog, RoomEvent, RoomEventKind};
+    use std::{net::IpAddr, time::SystemTime};
+
+    fn event(room: &str, kind: RoomEventKind) -> RoomEvent {
+        RoomEvent {
+            time: SystemTime::now(),
+            room: room.to_string(),
+            origin: IpAddr::V4(123.into()),
+            is_creator: Some(true),
+            kind,
+        }
+    }
+
+    #[test]
+    fn test_event_log() {
+        let log = EventLog::new(2);
+        log.record(event("a", RoomEventKind::Created));
+        log.record(event("b", RoomEventKind::Created));
+        log.record(event("b", RoomEventKind::ContactSent));
+
+        // the oldest event was forgotten
+        let events = log.events(|_| true);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].kind, RoomEventKind::Created);
+        assert_eq!(events[1].kind, RoomEventKind::ContactSent);
+        assert!(log.events(|event| event.room == "a").is_empty());
+
+        // a log with no capacity remembers nothing
+        let log = EventLog::new(0);
+        log.record(event("a", RoomEventKind::Created));
+        assert!(log.events(|_| true).is_empty());
+    }
+}
@@ -0,0 +1,88 @@
+use ipnet::IpNet;
+use std::{
+    net::IpAddr,
+    sync::{Arc, RwLock},
+};
+
+/// Subnets to allow and deny connections from.
+#[derive(Default, Debug)]
+struct Lists {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+}
+
+/// Decides which IP addresses may connect to the server.
+///
+/// Cloning returns a reference to the same filter,
+/// so lists updated with [`IpFilter::set_lists()`]
+/// apply to all clones.
+#[derive(Clone, Debug, Default)]
+pub struct IpFilter {
+    lists: Arc<RwLock<Lists>>,
+}
+
+impl IpFilter {
+    /// Creates a new [`IpFilter`] with the given lists.
+    ///
+    /// See [`IpFilter::is_allowed()`] for how they're applied.
+    pub fn new(allow: Vec<IpNet>, deny: Vec<IpNet>) -> Self {
+        Self {
+            lists: Arc::new(RwLock::new(Lists { allow, deny })),
+        }
+    }
+
+    /// Replaces the allow and deny lists.
+    pub fn set_lists(&self, allow: Vec<IpNet>, deny: Vec<IpNet>) {
+        *self.lists.write().expect("Couldn't acquire state lock.") = Lists { allow, deny };
+    }
+
+    /// Returns whether `ip` may connect.
+    ///
+    /// `ip` is rejected if it's in any denied subnet.
+    /// Otherwise, it's accepted if the allow list is empty
+    /// or it's in an allowed subnet.
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        let lists = self.lists.read().expect("Couldn't acquire state lock.");
+
+        if lists.deny.iter().any(|net| net.contains(&ip)) {
+            return false;
+        }
+
+        lists.allow.is_empty() || lists.allow.iter().any(|net| net.contains(&ip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IpFilter;
+    use std::net::IpAddr;
+
+    #[test]
+    fn test_ip_filter() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+
+        // allows everyone by default
+        let filter = IpFilter::default();
+        assert!(filter.is_allowed(ip("1.2.3.4")));
+        assert!(filter.is_allowed(ip("::1")));
+
+        // deny list
+        filter.set_lists(Vec::new(), vec!["10.0.0.0/8".parse().unwrap()]);
+        assert!(!filter.is_allowed(ip("10.1.2.3")));
+        assert!(filter.is_allowed(ip("11.1.2.3")));
+
+        // allow list, with a denied subnet inside it
+        let filter = IpFilter::new(
+            vec![
+                "192.168.0.0/16".parse().unwrap(),
+                "fd00::/8".parse().unwrap(),
+            ],
+            vec!["192.168.5.0/24".parse().unwrap()],
+        );
+        assert!(filter.is_allowed(ip("192.168.1.1")));
+        assert!(filter.is_allowed(ip("fd12::1")));
+        assert!(!filter.is_allowed(ip("192.168.5.1")));
+        assert!(!filter.is_allowed(ip("8.8.8.8")));
+        assert!(!filter.is_allowed(ip("2001:db8::1")));
+    }
+}
@@ -3,14 +3,34 @@
 #![forbid(unsafe_code)]
 #![warn(clippy::all)]
 
+mod acme;
+#[cfg(unix)]
+mod admin;
+mod config;
 mod connection_handler;
+mod connection_limiter;
+mod event_log;
+mod ip_filter;
+mod logging;
+mod persistence;
+mod proxy_protocol;
+mod relay;
+mod server;
 mod state;
+mod tenants;
 
 use clap::Parser;
-use connection_handler::handle_connection;
+use connection_handler::{handle_connection, TlsMode};
+use connection_limiter::ConnectionLimiter;
+use event_log::EventLog;
+pub use event_log::{RoomEvent, RoomEventKind};
+use ip_filter::IpFilter;
+use ipnet::IpNet;
 use log::{debug, error, info, warn};
+pub use logging::{init_logger, LogFormat};
+use relay::Relay;
+pub use server::{Server, ServerBuilder, ShutdownHandle};
 use socket2::{Domain, Protocol, TcpKeepalive, Type};
-use state::State;
 use std::net::SocketAddr;
 use std::{
     io::{BufReader, ErrorKind},
@@ -18,7 +38,8 @@ use std::{
     sync::Arc,
     time::Duration,
 };
-use tokio::task::JoinSet;
+pub use tenants::TenantConfig;
+use tenants::Tenants;
 use tokio_rustls::{
     rustls::{self, pki_types::CertificateDer},
     TlsAcceptor,
@@ -27,18 +48,55 @@ use tokio_rustls::{
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
 pub struct Args {
+    /// TOML file with options to use when they aren't passed as flags
+    ///
+    /// Keys are the long flag names in snake_case,
+    /// for example `request_limit = 20`.
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+
     /// PEM file of private TLS server key
-    #[arg(short, long, required_unless_present("unencrypted"))]
+    #[arg(
+        short,
+        long,
+        required_unless_present_any(["unencrypted", "acme_domain", "config"])
+    )]
     pub key: Option<PathBuf>,
 
     /// PEM file of signed TLS server certificate
-    #[arg(short, long, required_unless_present("unencrypted"))]
+    #[arg(
+        short,
+        long,
+        required_unless_present_any(["unencrypted", "acme_domain", "config"])
+    )]
     pub certificate: Option<PathBuf>,
 
     /// Use unencrypted TCP instead of TLS
     #[arg(short, long, conflicts_with_all(["key", "certificate"]))]
     pub unencrypted: bool,
 
+    /// Automatically obtain and renew a TLS certificate for this domain from Let's Encrypt
+    ///
+    /// The certificate authority verifies the domain by connecting
+    /// on port 443, so one of the `--addresses` must be reachable there.
+    #[arg(long, conflicts_with_all(["key", "certificate", "unencrypted"]))]
+    pub acme_domain: Option<String>,
+
+    /// Contact email to register with Let's Encrypt
+    #[arg(long, requires("acme_domain"))]
+    pub acme_email: Option<String>,
+
+    /// Directory in which to cache the ACME account and certificate
+    #[arg(long, requires("acme_domain"), default_value = "acme_cache")]
+    pub acme_cache: PathBuf,
+
+    /// Use the Let's Encrypt staging environment instead of production
+    ///
+    /// Staging issues untrusted certificates, but has much looser rate limits.
+    /// Useful for testing.
+    #[arg(long, requires("acme_domain"))]
+    pub acme_staging: bool,
+
     /// Socket addresses on which to listen.
     #[arg(short, long, default_values = ["0.0.0.0:2311", "[::]:2311"])]
     pub addresses: Vec<SocketAddr>,
@@ -54,101 +112,235 @@ pub struct Args {
     #[arg(short, long, default_value = "10")]
     pub request_limit: u32,
 
+    /// Max number of the above requests an IP address
+    /// can send in a quick burst before they're rejected.
+    #[arg(long, default_value = "10")]
+    pub request_burst: u32,
+
+    /// Max number of connections an IP address can have open at once
+    #[arg(long, default_value = "20")]
+    pub max_connections_per_ip: u32,
+
+    /// Max number of connections the server can have open at once
+    #[arg(long, default_value = "1000")]
+    pub max_connections: u32,
+
+    /// Reject connections from this subnet (for example 203.0.113.0/24)
+    ///
+    /// Can be given multiple times. With `--config`, sending the server
+    /// SIGHUP reloads this list from the config file.
+    #[arg(long, value_name = "CIDR")]
+    pub deny_cidr: Vec<IpNet>,
+
+    /// Only accept connections from this subnet (for example 2001:db8::/32)
+    ///
+    /// Can be given multiple times. If not given, all subnets are accepted.
+    /// With `--config`, sending the server SIGHUP reloads this list
+    /// from the config file.
+    #[arg(long, value_name = "CIDR")]
+    pub allow_cidr: Vec<IpNet>,
+
+    /// File in which to save open rooms, so they survive a server restart
+    #[arg(long)]
+    pub state_file: Option<PathBuf>,
+
+    /// Relay traffic between peers that can't connect directly
+    ///
+    /// Relayed traffic passes through this server, so
+    /// enabling this may use a lot of bandwidth.
+    #[arg(long)]
+    pub relay: bool,
+
+    /// Max bytes per second relayed between a pair of peers
+    #[arg(long, default_value = "1000000")]
+    pub relay_bandwidth: u64,
+
+    /// Max bytes relayed between a pair of peers before they're disconnected
+    #[arg(long, default_value = "1000000000")]
+    pub relay_byte_limit: u64,
+
+    /// Number of recent room events to keep in memory for the admin socket
+    #[arg(long, default_value = "1000")]
+    pub event_log_size: usize,
+
+    /// Unix socket on which to answer admin commands (Unix only)
+    ///
+    /// Send `status` to get the uptime and number of open rooms,
+    /// `requests` to get recent request counts of each IP address,
+    /// `events` to get recent room events, or `events <IP>`
+    /// to get the ones from a client's IP address.
+    /// Send `expire <ROOM HASH>` to delete a stuck room right away.
+    #[arg(long)]
+    pub admin_socket: Option<PathBuf>,
+
+    /// Expect each connection to start with a PROXY protocol (v1 or v2) header
+    ///
+    /// Use when running behind a load balancer that sends this header,
+    /// so client addresses are recorded and rate limited correctly.
+    /// Only enable if the server is unreachable except through the
+    /// load balancer, since clients could otherwise fake their address.
+    #[arg(long)]
+    pub proxy_protocol: bool,
+
+    /// Only serve clients that authenticate as one of the `tenants`
+    #[arg(long)]
+    pub require_tenant: bool,
+
+    /// Tenants that clients can authenticate as, each with its own
+    /// rooms and request limits
+    ///
+    /// Can only be set in the config file, as `[[tenants]]` tables.
+    #[arg(skip)]
+    pub tenants: Vec<TenantConfig>,
+
     /// Log verbosity. (trace, debug, info, warn, error)
     #[arg(short, long, default_value = "debug")]
     pub verbosity: log::LevelFilter,
-}
-
-/// Spawns a tokio server in the background.
-///
-/// Returns the addresses that the server is listening on and
-/// a [`JoinSet`] of tasks, one for each address being listened on.
-///
-/// Must be called from a tokio async context.
-pub fn start_server(args: Args) -> Result<(Vec<SocketAddr>, JoinSet<()>), Error> {
-    // set the log level according to the command line argument
-    if let Err(err) = env_logger::builder()
-        .filter_level(args.verbosity)
-        .try_init()
-    {
-        error!("Non-fatal error. Couldn't initialize logger: {err}")
-    }
-
-    // get TCP listeners
-    let tcp_listeners: Result<Vec<tokio::net::TcpListener>, Error> =
-        args.addresses.into_iter().map(get_tcp_listener).collect();
-    let tcp_listeners = tcp_listeners?;
 
-    // get the addresses that we've actually bound to
-    let addresses: std::io::Result<Vec<SocketAddr>> =
-        tcp_listeners.iter().map(|l| l.local_addr()).collect();
-    let addresses = addresses.map_err(|source| Error {
-        msg: "Couldn't determine local address".to_string(),
-        source,
-    })?;
-
-    // get the TLS acceptor if applicable
-    let tls_acceptor = if let (Some(key), Some(cert)) = (args.key, args.certificate) {
-        Some(get_tls_acceptor(&key, &cert)?)
-    } else {
-        None
-    };
-
-    // create the shared global state object
-    let state = State::new(
-        args.request_limit,
-        std::time::Duration::from_secs(args.timeout),
-    );
-
-    // log the addresses being listened on
-    info!("Listening on these addresses: {addresses:?}");
-    info!("Is encrypted?: {}", tls_acceptor.is_some());
-    info!(
-        "Critical requests per minute per IP address limit: {}",
-        args.request_limit
-    );
-    info!(
-        "Number of seconds before a new room is deleted: {}",
-        args.timeout
-    );
-    info!("Server is now running.");
-
-    let mut joinset = JoinSet::new();
-
-    for tcp_listener in tcp_listeners {
-        joinset.spawn(run_single_server(
-            state.clone(),
-            tcp_listener,
-            tls_acceptor.clone(),
-        ));
-    }
+    /// Log format
+    #[arg(long, value_enum, default_value_t)]
+    pub log_format: LogFormat,
+}
 
-    Ok((addresses, joinset))
+/// Everything a listener needs to handle its connections.
+#[derive(Clone)]
+struct Listener {
+    tenants: Tenants,
+    tls_mode: TlsMode,
+    limiter: ConnectionLimiter,
+    ip_filter: IpFilter,
+    relay: Option<Relay>,
+    event_log: EventLog,
+    /// Whether connections start with a PROXY protocol header
+    proxy_protocol: bool,
 }
 
-async fn run_single_server(
-    state: State,
-    tcp_listener: tokio::net::TcpListener,
-    tls_acceptor: Option<TlsAcceptor>,
-) {
+/// Max time to wait for a load balancer's PROXY protocol header.
+const PROXY_HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+
+async fn run_single_server(tcp_listener: tokio::net::TcpListener, listener: Listener) {
     loop {
         // try to accept another connection
         let (stream, origin) = match tcp_listener.accept().await {
             Ok(ok) => ok,
             Err(err) => {
-                warn!("Error accepting incoming TCP connection: {err}.");
+                warn!(event = "accept_error"; "Error accepting incoming TCP connection: {err}.");
                 continue;
             }
         };
-        debug!("Accepted incoming TCP connection from {origin}.");
+
+        if listener.proxy_protocol {
+            // read the header in the background, so a slow
+            // load balancer doesn't hold up other connections
+            let listener = listener.clone();
+            tokio::spawn(async move {
+                let mut stream = stream;
+                if let Some(origin) = read_proxy_header(&mut stream, origin).await {
+                    listener.admit(stream, origin);
+                }
+            });
+        } else {
+            listener.admit(stream, origin);
+        }
+    }
+}
+
+/// Reads the PROXY protocol header that the load balancer at `lb_addr`
+/// sent on `stream`.
+///
+/// Returns the client's address, or `lb_addr` if the header doesn't say.
+/// Returns `None` if the header couldn't be read.
+async fn read_proxy_header(
+    stream: &mut tokio::net::TcpStream,
+    lb_addr: SocketAddr,
+) -> Option<SocketAddr> {
+    match tokio::time::timeout(PROXY_HEADER_TIMEOUT, proxy_protocol::read_header(stream)).await {
+        Ok(Ok(origin)) => Some(origin.unwrap_or(lb_addr)),
+        Ok(Err(err)) => {
+            warn!(
+                event = "proxy_header_error", origin:% = lb_addr.ip();
+                "Couldn't read PROXY protocol header from {lb_addr}: {err}"
+            );
+            None
+        }
+        Err(_) => {
+            warn!(
+                event = "proxy_header_error", origin:% = lb_addr.ip();
+                "Timed out reading PROXY protocol header from {lb_addr}."
+            );
+            None
+        }
+    }
+}
+
+impl Listener {
+    /// Spawns a task to handle the connection from `origin`,
+    /// unless it's blocked or would exceed a limit.
+    fn admit(&self, stream: tokio::net::TcpStream, origin: SocketAddr) {
+        // drop the connection if it comes from a blocked subnet
+        if !self.ip_filter.is_allowed(origin.ip()) {
+            debug!(
+                event = "connection_rejected", reason = "blocked_subnet", origin:% = origin.ip();
+                "Rejected TCP connection from blocked address {origin}."
+            );
+            return;
+        }
+
+        // drop the connection if it would exceed a limit
+        let Some(permit) = self.limiter.try_acquire(origin.ip()) else {
+            warn!(
+                event = "connection_rejected", reason = "too_many_connections", origin:% = origin.ip();
+                "Rejected TCP connection from {origin}, because of too many open connections."
+            );
+            return;
+        };
+        debug!(
+            event = "connection_accepted", origin:% = origin.ip();
+            "Accepted incoming TCP connection from {origin}."
+        );
 
         // spawn a thread to handle the connection
-        tokio::spawn(handle_connection(
+        let connection = handle_connection(
             stream,
             origin,
-            tls_acceptor.clone(),
-            state.clone(),
-        ));
+            self.tls_mode.clone(),
+            self.tenants.clone(),
+            self.relay.clone(),
+            self.event_log.clone(),
+        );
+        tokio::spawn(async move {
+            connection.await;
+            // free up this connection's slot
+            drop(permit);
+        });
+    }
+}
+
+/// Every time the server receives SIGHUP, re-reads the
+/// allow and deny lists of `ip_filter` from the config file at `config_path`.
+#[cfg(unix)]
+async fn reload_ip_filter_on_sighup(config_path: PathBuf, ip_filter: IpFilter) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sighup = match signal(SignalKind::hangup()) {
+        Ok(sighup) => sighup,
+        Err(err) => {
+            error!("Couldn't listen for SIGHUP, so won't reload subnet lists: {err}");
+            return;
+        }
+    };
+
+    while sighup.recv().await.is_some() {
+        match Args::from_config_file(&config_path) {
+            Ok(args) => {
+                info!(
+                    "Reloaded subnet lists from {config_path:?}: allow {:?}, deny {:?}",
+                    args.allow_cidr, args.deny_cidr
+                );
+                ip_filter.set_lists(args.allow_cidr, args.deny_cidr);
+            }
+            Err(err) => error!("Couldn't reload subnet lists: {err}"),
+        }
     }
 }
 
@@ -0,0 +1,71 @@
+use log::kv::{self, VisitSource};
+use serde::Deserialize;
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    io::Write,
+};
+
+/// Format in which the server writes its logs.
+#[derive(clap::ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human-readable lines
+    #[default]
+    Text,
+    /// One JSON object per line, with the structured fields of each event
+    Json,
+}
+
+/// Initializes the global logger.
+pub fn init_logger(
+    verbosity: log::LevelFilter,
+    format: LogFormat,
+) -> Result<(), log::SetLoggerError> {
+    let mut builder = env_logger::builder();
+    builder.filter_level(verbosity);
+
+    if format == LogFormat::Json {
+        builder.format(|buf, record| {
+            let mut fields = serde_json::Map::new();
+            fields.insert("timestamp".into(), buf.timestamp().to_string().into());
+            fields.insert("level".into(), record.level().as_str().into());
+            fields.insert("message".into(), record.args().to_string().into());
+
+            // add the event's structured fields
+            let _ = record.key_values().visit(&mut JsonVisitor(&mut fields));
+
+            writeln!(buf, "{}", serde_json::Value::Object(fields))
+        });
+    }
+
+    builder.try_init()
+}
+
+/// Inserts the key-value pairs of a log record into a JSON object.
+struct JsonVisitor<'a>(&'a mut serde_json::Map<String, serde_json::Value>);
+
+impl<'kvs> VisitSource<'kvs> for JsonVisitor<'_> {
+    fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        let value = if let Some(v) = value.to_u64() {
+            v.into()
+        } else if let Some(v) = value.to_i64() {
+            v.into()
+        } else if let Some(v) = value.to_bool() {
+            v.into()
+        } else {
+            value.to_string().into()
+        };
+        self.0.insert(key.to_string(), value);
+        Ok(())
+    }
+}
+
+/// Returns a short hex hash of `room_code`.
+///
+/// Lets logs correlate events in the same room,
+/// without revealing the room code to whoever reads them.
+pub fn room_hash(room_code: &[u8; 32]) -> String {
+    let mut hasher = DefaultHasher::new();
+    room_code.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
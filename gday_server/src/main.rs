@@ -3,22 +3,28 @@
 #![forbid(unsafe_code)]
 #![warn(clippy::all)]
 
-use clap::Parser;
-use gday_server::Args;
+use gday_server::{Args, ServerBuilder};
 use log::error;
 
 #[tokio::main]
 async fn main() {
-    // read command line arguments
-    let args = Args::parse();
+    // read command line arguments and config file
+    let args = match Args::parse_with_config() {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{err}");
+            return;
+        }
+    };
+
+    // set the log level according to the command line argument
+    if let Err(err) = gday_server::init_logger(args.verbosity, args.log_format) {
+        eprintln!("Non-fatal error. Couldn't initialize logger: {err}")
+    }
 
-    match gday_server::start_server(args) {
-        Ok((_addr, mut joinset)) => {
-            joinset
-                .join_next()
-                .await
-                .expect("No addresses provided.")
-                .expect("Server thread panicked.");
+    match ServerBuilder::from(args).start() {
+        Ok(mut server) => {
+            server.wait().await;
             error!("Server crashed.");
         }
         Err(err) => {
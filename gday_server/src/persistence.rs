@@ -0,0 +1,63 @@
+use crate::{
+    state::{RoomSnapshot, State},
+    Error,
+};
+use log::{error, trace};
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::time::MissedTickBehavior;
+
+/// How often open rooms are saved to the state file.
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Reads the rooms saved in the state file at `path`.
+///
+/// Returns an empty list if the file doesn't exist yet.
+pub fn load_rooms(path: &Path) -> Result<Vec<RoomSnapshot>, Error> {
+    let contents = match std::fs::read(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(source) => {
+            return Err(Error {
+                msg: format!("Couldn't read state file {path:?}."),
+                source,
+            })
+        }
+    };
+
+    serde_json::from_slice(&contents).map_err(|source| Error {
+        msg: format!("Couldn't parse state file {path:?}."),
+        source: source.into(),
+    })
+}
+
+/// Saves `rooms` to the state file at `path`.
+///
+/// Writes to a temporary file first, so a crash mid-write
+/// doesn't corrupt the previous snapshot.
+fn save_rooms(path: &Path, rooms: &[RoomSnapshot]) -> std::io::Result<()> {
+    let mut tmp_path = path.as_os_str().to_os_string();
+    tmp_path.push(".tmp");
+
+    std::fs::write(&tmp_path, serde_json::to_vec(rooms)?)?;
+    std::fs::rename(&tmp_path, path)
+}
+
+/// Saves the open rooms of `state` to the
+/// state file at `path` every few seconds.
+pub async fn save_rooms_periodically(path: PathBuf, state: State) {
+    let mut interval = tokio::time::interval(SNAPSHOT_INTERVAL);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+        let rooms = state.snapshot_rooms();
+        match save_rooms(&path, &rooms) {
+            Ok(()) => trace!("Saved {} open rooms to {path:?}.", rooms.len()),
+            Err(err) => error!("Couldn't save open rooms to {path:?}: {err}"),
+        }
+    }
+}
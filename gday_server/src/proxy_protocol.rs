@@ -0,0 +1,200 @@
+//! Parses the [PROXY protocol](https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt)
+//! header that load balancers send before the client's data.
+
+use std::{
+    io::{Error, ErrorKind},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// First bytes of a version 2 header.
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Max length of a version 1 header, including the final CRLF.
+const V1_MAX_LEN: usize = 107;
+
+/// Reads a PROXY protocol header (version 1 or 2) from `stream`,
+/// without reading any of the data after it.
+///
+/// Returns the address of the client that the load balancer is proxying,
+/// or `None` if the header doesn't say (such as for the load balancer's
+/// own health checks).
+pub async fn read_header(
+    stream: &mut (impl AsyncRead + Unpin),
+) -> Result<Option<SocketAddr>, Error> {
+    // both versions' headers are at least this long
+    let mut start = [0; 12];
+    stream.read_exact(&mut start).await?;
+
+    if start == V2_SIGNATURE {
+        read_v2(stream).await
+    } else if start.starts_with(b"PROXY ") {
+        read_v1(stream, &start).await
+    } else {
+        Err(invalid(
+            "Connection didn't start with a PROXY protocol header",
+        ))
+    }
+}
+
+/// Reads the rest of a version 1 header, which began with `start`.
+async fn read_v1(
+    stream: &mut (impl AsyncRead + Unpin),
+    start: &[u8],
+) -> Result<Option<SocketAddr>, Error> {
+    let mut header = start.to_vec();
+
+    // read one byte at a time, to not read past the header
+    while !header.ends_with(b"\r\n") {
+        if header.len() >= V1_MAX_LEN {
+            return Err(invalid("PROXY protocol v1 header is too long"));
+        }
+        header.push(stream.read_u8().await?);
+    }
+
+    let header = std::str::from_utf8(&header[..header.len() - 2])
+        .map_err(|_| invalid("PROXY protocol v1 header isn't ASCII"))?;
+    let fields: Vec<&str> = header.split(' ').collect();
+
+    match fields[..] {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", "TCP4" | "TCP6", src_ip, _dst_ip, src_port, _dst_port] => {
+            let ip = src_ip
+                .parse()
+                .map_err(|_| invalid("Invalid source IP in PROXY protocol v1 header"))?;
+            let port = src_port
+                .parse()
+                .map_err(|_| invalid("Invalid source port in PROXY protocol v1 header"))?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(invalid("Malformed PROXY protocol v1 header")),
+    }
+}
+
+/// Reads the rest of a version 2 header, after its signature.
+async fn read_v2(stream: &mut (impl AsyncRead + Unpin)) -> Result<Option<SocketAddr>, Error> {
+    let version_command = stream.read_u8().await?;
+    let family = stream.read_u8().await?;
+    let len = stream.read_u16().await?;
+
+    // read the addresses, and any extensions after them
+    let mut body = vec![0; usize::from(len)];
+    stream.read_exact(&mut body).await?;
+
+    if version_command >> 4 != 2 {
+        return Err(invalid("Unsupported PROXY protocol version"));
+    }
+
+    match version_command & 0x0F {
+        // LOCAL: the load balancer's own connection
+        0x0 => return Ok(None),
+        // PROXY: a proxied client's connection
+        0x1 => (),
+        _ => return Err(invalid("Unsupported PROXY protocol v2 command")),
+    }
+
+    // the high nibble is the address family,
+    // and the low nibble is the transport protocol
+    match family >> 4 {
+        // IPv4
+        0x1 => {
+            let Some(addrs) = body.get(..12) else {
+                return Err(invalid("PROXY protocol v2 header is too short"));
+            };
+            let ip = Ipv4Addr::from(<[u8; 4]>::try_from(&addrs[0..4]).unwrap());
+            let port = u16::from_be_bytes([addrs[8], addrs[9]]);
+            Ok(Some(SocketAddr::new(ip.into(), port)))
+        }
+        // IPv6
+        0x2 => {
+            let Some(addrs) = body.get(..36) else {
+                return Err(invalid("PROXY protocol v2 header is too short"));
+            };
+            let ip = Ipv6Addr::from(<[u8; 16]>::try_from(&addrs[0..16]).unwrap());
+            let port = u16::from_be_bytes([addrs[32], addrs[33]]);
+            Ok(Some(SocketAddr::new(ip.into(), port)))
+        }
+        // unspecified or unix socket
+        _ => Ok(None),
+    }
+}
+
+/// Returns an [`ErrorKind::InvalidData`] error with `msg`.
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::read_header;
+    use std::net::SocketAddr;
+    use tokio::io::AsyncReadExt;
+
+    /// Reads a header from `bytes`, and checks that
+    /// exactly the bytes after it are left over.
+    async fn parse(bytes: &[u8]) -> std::io::Result<Option<SocketAddr>> {
+        let input = [bytes, b"rest"].concat();
+        let mut reader = &input[..];
+        let addr = read_header(&mut reader).await?;
+
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(rest, b"rest");
+        Ok(addr)
+    }
+
+    #[tokio::test]
+    async fn test_v1() {
+        let addr = parse(b"PROXY TCP4 192.0.2.1 198.51.100.2 5000 2311\r\n").await;
+        assert_eq!(addr.unwrap(), Some("192.0.2.1:5000".parse().unwrap()));
+
+        let addr = parse(b"PROXY TCP6 2001:db8::1 2001:db8::2 5000 2311\r\n").await;
+        assert_eq!(addr.unwrap(), Some("[2001:db8::1]:5000".parse().unwrap()));
+
+        let addr = parse(b"PROXY UNKNOWN\r\n").await;
+        assert_eq!(addr.unwrap(), None);
+
+        assert!(parse(b"PROXY TCP4 nonsense\r\n").await.is_err());
+        assert!(parse(b"GET / HTTP/1.1\r\n\r\n").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_v2() {
+        let signature = b"\r\n\r\n\0\r\nQUIT\n";
+
+        // IPv4 PROXY
+        let mut header = signature.to_vec();
+        header.extend_from_slice(&[0x21, 0x11, 0, 12]);
+        header.extend_from_slice(&[192, 0, 2, 1, 198, 51, 100, 2]);
+        header.extend_from_slice(&5000_u16.to_be_bytes());
+        header.extend_from_slice(&2311_u16.to_be_bytes());
+        let addr = parse(&header).await.unwrap();
+        assert_eq!(addr, Some("192.0.2.1:5000".parse().unwrap()));
+
+        // IPv6 PROXY, followed by a TLV extension
+        let mut header = signature.to_vec();
+        header.extend_from_slice(&[0x21, 0x21, 0, 36 + 4]);
+        header.extend_from_slice(
+            &"2001:db8::1"
+                .parse::<std::net::Ipv6Addr>()
+                .unwrap()
+                .octets(),
+        );
+        header.extend_from_slice(&[0; 16]);
+        header.extend_from_slice(&5000_u16.to_be_bytes());
+        header.extend_from_slice(&2311_u16.to_be_bytes());
+        header.extend_from_slice(&[0x04, 0, 1, 0]);
+        let addr = parse(&header).await.unwrap();
+        assert_eq!(addr, Some("[2001:db8::1]:5000".parse().unwrap()));
+
+        // LOCAL
+        let mut header = signature.to_vec();
+        header.extend_from_slice(&[0x20, 0x00, 0, 0]);
+        assert_eq!(parse(&header).await.unwrap(), None);
+
+        // wrong version
+        let mut header = signature.to_vec();
+        header.extend_from_slice(&[0x31, 0x11, 0, 0]);
+        assert!(parse(&header).await.is_err());
+    }
+}
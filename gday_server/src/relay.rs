@@ -0,0 +1,533 @@
+use crate::state::TokenBucket;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use thiserror::Error;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    sync::{mpsc, oneshot},
+    time::Instant,
+};
+
+/// Max number of bytes read from a peer at once.
+const BUF_SIZE: usize = 16_384;
+
+/// Number of buffers that can be queued up for a peer
+/// before reading from the other peer pauses.
+const QUEUE_LEN: usize = 8;
+
+/// Limits that apply to each pair of relayed peers.
+#[derive(Debug, Clone, Copy)]
+pub struct RelayLimits {
+    /// Max bytes per second relayed between the peers,
+    /// in both directions combined
+    pub bytes_per_sec: u64,
+    /// Max bytes relayed between the peers before they're disconnected
+    pub max_bytes: u64,
+    /// How long to wait for the second peer to join,
+    /// and how long the peers may go without sending anything
+    /// before they're disconnected
+    pub timeout: Duration,
+}
+
+/// Totals describing what the relay has done since the server started.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RelayMetrics {
+    /// Number of pairs of peers currently being relayed
+    pub active_rooms: u64,
+    /// Number of pairs of peers ever relayed
+    pub total_rooms: u64,
+    /// Number of bytes ever relayed
+    pub bytes_relayed: u64,
+    /// Number of pairs of peers disconnected for
+    /// exceeding [`RelayLimits::max_bytes`]
+    pub rooms_over_limit: u64,
+}
+
+/// Atomic version of [`RelayMetrics`].
+#[derive(Debug, Default)]
+struct Counters {
+    active_rooms: AtomicU64,
+    total_rooms: AtomicU64,
+    bytes_relayed: AtomicU64,
+    rooms_over_limit: AtomicU64,
+}
+
+/// The ID of a tenant's [`State`](crate::state::State), and the code of one of its rooms.
+type RoomKey = (u64, [u8; 32]);
+
+/// A peer waiting for the other peer to join the relay.
+#[derive(Debug)]
+struct Waiting {
+    /// Whether this is the peer that created the room
+    is_creator: bool,
+    /// Sends this peer its [`Pipe`] once the other peer joins
+    pipe_sender: oneshot::Sender<Pipe>,
+}
+
+/// Bandwidth and byte budget shared by both peers in a relayed room.
+#[derive(Debug)]
+struct Budget {
+    /// Limits the rate at which bytes are relayed
+    bucket: Mutex<TokenBucket>,
+    /// Number of bytes relayed so far
+    relayed: AtomicU64,
+    /// Updated when bytes are relayed and when this room closes
+    counters: Arc<Counters>,
+}
+
+impl Drop for Budget {
+    fn drop(&mut self) {
+        // both peers are done with this room
+        self.counters.active_rooms.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// One peer's end of a relayed room.
+#[derive(Debug)]
+pub struct Pipe {
+    /// Sends bytes to the other peer
+    tx: mpsc::Sender<Vec<u8>>,
+    /// Receives bytes from the other peer
+    rx: mpsc::Receiver<Vec<u8>>,
+    /// Shared with the other peer's [`Pipe`]
+    budget: Arc<Budget>,
+}
+
+impl Pipe {
+    /// Returns the number of bytes relayed in this room so far,
+    /// in both directions combined.
+    pub fn bytes_relayed(&self) -> u64 {
+        self.budget.relayed.load(Ordering::Relaxed)
+    }
+}
+
+/// Why [`Relay::run()`] stopped relaying.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Teardown {
+    /// One of the peers disconnected
+    Disconnected,
+    /// The peers exceeded [`RelayLimits::max_bytes`]
+    ByteLimit,
+    /// Neither peer sent anything for [`RelayLimits::timeout`]
+    Idle,
+}
+
+impl Teardown {
+    /// Returns a short snake_case name for logging.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Disconnected => "disconnected",
+            Self::ByteLimit => "byte_limit",
+            Self::Idle => "idle",
+        }
+    }
+}
+
+/// Pairs up peers that can't connect directly,
+/// and relays bytes between them.
+///
+/// Cloning returns a reference to the same relay.
+#[derive(Clone, Debug)]
+pub struct Relay {
+    /// Maps rooms to the peer waiting in them
+    waiting: Arc<Mutex<HashMap<RoomKey, Waiting>>>,
+    limits: RelayLimits,
+    counters: Arc<Counters>,
+}
+
+impl Relay {
+    /// Creates a new [`Relay`] that applies `limits` to every room.
+    pub fn new(limits: RelayLimits) -> Self {
+        Self {
+            waiting: Arc::default(),
+            limits,
+            counters: Arc::default(),
+        }
+    }
+
+    /// Returns the limits applied to every room.
+    pub fn limits(&self) -> RelayLimits {
+        self.limits
+    }
+
+    /// Returns totals describing what this relay has done.
+    pub fn metrics(&self) -> RelayMetrics {
+        RelayMetrics {
+            active_rooms: self.counters.active_rooms.load(Ordering::Relaxed),
+            total_rooms: self.counters.total_rooms.load(Ordering::Relaxed),
+            bytes_relayed: self.counters.bytes_relayed.load(Ordering::Relaxed),
+            rooms_over_limit: self.counters.rooms_over_limit.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Joins the relay room with `room_code` of the tenant
+    /// whose [`State::id()`](crate::state::State::id) is `tenant`.
+    /// Peers of different tenants are never paired.
+    ///
+    /// Returns a [`oneshot::Receiver`] that will send this peer's [`Pipe`]
+    /// once the other peer has joined as well.
+    ///
+    /// - Returns [`Error::RoleTaken`] if a peer with the same
+    ///   `is_creator` is already waiting in this room.
+    pub fn join(
+        &self,
+        tenant: u64,
+        room_code: [u8; 32],
+        is_creator: bool,
+    ) -> Result<oneshot::Receiver<Pipe>, Error> {
+        let room_code = (tenant, room_code);
+        let mut waiting = self.waiting.lock().expect("Couldn't acquire state lock.");
+        let (pipe_sender, pipe_receiver) = oneshot::channel();
+
+        // forget a peer that gave up waiting
+        if waiting
+            .get(&room_code)
+            .is_some_and(|peer| peer.pipe_sender.is_closed())
+        {
+            waiting.remove(&room_code);
+        }
+
+        match waiting.remove(&room_code) {
+            // the other peer is already waiting
+            Some(peer) if peer.is_creator != is_creator => {
+                let (my_pipe, peer_pipe) = self.new_pipes();
+                if peer.pipe_sender.send(peer_pipe).is_ok() {
+                    let _ = pipe_sender.send(my_pipe);
+                    return Ok(pipe_receiver);
+                }
+                // the peer disconnected just now, so wait in its place
+            }
+            // someone is already waiting as this peer
+            Some(peer) => {
+                waiting.insert(room_code, peer);
+                return Err(Error::RoleTaken);
+            }
+            None => (),
+        }
+
+        waiting.insert(
+            room_code,
+            Waiting {
+                is_creator,
+                pipe_sender,
+            },
+        );
+        Ok(pipe_receiver)
+    }
+
+    /// Stops waiting in the room with `room_code` of `tenant`,
+    /// if the peer waiting there has given up.
+    pub fn forget_abandoned(&self, tenant: u64, room_code: [u8; 32]) {
+        let room_code = (tenant, room_code);
+        let mut waiting = self.waiting.lock().expect("Couldn't acquire state lock.");
+        if waiting
+            .get(&room_code)
+            .is_some_and(|peer| peer.pipe_sender.is_closed())
+        {
+            waiting.remove(&room_code);
+        }
+    }
+
+    /// Relays bytes between `stream` and the peer at the other end of `pipe`.
+    ///
+    /// Returns once either peer disconnects or a limit is reached.
+    /// Dropping `pipe` afterwards makes the other peer's call return as well.
+    pub async fn run(
+        &self,
+        stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+        pipe: &mut Pipe,
+    ) -> Teardown {
+        let (mut reader, mut writer) = tokio::io::split(stream);
+        let Pipe { tx, rx, budget } = pipe;
+        let last_active = Mutex::new(Instant::now());
+        let mark_active =
+            || *last_active.lock().expect("Couldn't acquire state lock.") = Instant::now();
+
+        // The directions run concurrently, so that a full queue
+        // in one direction doesn't stop the other one.
+
+        // relay bytes from this peer to the other one
+        let upload = async {
+            let mut buf = vec![0; BUF_SIZE];
+            loop {
+                let n = match reader.read(&mut buf).await {
+                    Ok(0) | Err(_) => return Teardown::Disconnected,
+                    Ok(n) => n,
+                };
+
+                if !self.spend(budget, n as u64).await {
+                    self.counters
+                        .rooms_over_limit
+                        .fetch_add(1, Ordering::Relaxed);
+                    return Teardown::ByteLimit;
+                }
+
+                if tx.send(buf[..n].to_vec()).await.is_err() {
+                    return Teardown::Disconnected;
+                }
+                mark_active();
+            }
+        };
+
+        // relay bytes from the other peer to this one
+        let download = async {
+            loop {
+                let Some(bytes) = rx.recv().await else {
+                    return Teardown::Disconnected;
+                };
+                if writer.write_all(&bytes).await.is_err() || writer.flush().await.is_err() {
+                    return Teardown::Disconnected;
+                }
+                mark_active();
+            }
+        };
+
+        // stop once neither direction relayed anything for a while
+        let idle = async {
+            loop {
+                let deadline = *last_active.lock().expect("Couldn't acquire state lock.")
+                    + self.limits.timeout;
+                if deadline <= Instant::now() {
+                    return Teardown::Idle;
+                }
+                tokio::time::sleep_until(deadline).await;
+            }
+        };
+
+        tokio::select! {
+            teardown = upload => teardown,
+            teardown = download => teardown,
+            teardown = idle => teardown,
+        }
+    }
+
+    /// Returns a connected pair of [`Pipe`]s sharing a new [`Budget`].
+    fn new_pipes(&self) -> (Pipe, Pipe) {
+        self.counters.active_rooms.fetch_add(1, Ordering::Relaxed);
+        self.counters.total_rooms.fetch_add(1, Ordering::Relaxed);
+
+        let budget = Arc::new(Budget {
+            bucket: Mutex::new(TokenBucket {
+                tokens: self.limits.bytes_per_sec as f64,
+                last_update: Instant::now(),
+            }),
+            relayed: AtomicU64::new(0),
+            counters: self.counters.clone(),
+        });
+
+        let (tx_a, rx_a) = mpsc::channel(QUEUE_LEN);
+        let (tx_b, rx_b) = mpsc::channel(QUEUE_LEN);
+
+        (
+            Pipe {
+                tx: tx_a,
+                rx: rx_b,
+                budget: budget.clone(),
+            },
+            Pipe {
+                tx: tx_b,
+                rx: rx_a,
+                budget,
+            },
+        )
+    }
+
+    /// Takes `n` bytes from `budget`, sleeping as long as
+    /// needed to stay under [`RelayLimits::bytes_per_sec`].
+    ///
+    /// Returns `false` if this would exceed [`RelayLimits::max_bytes`].
+    async fn spend(&self, budget: &Budget, n: u64) -> bool {
+        let relayed = budget.relayed.fetch_add(n, Ordering::Relaxed) + n;
+        if relayed > self.limits.max_bytes {
+            return false;
+        }
+        self.counters.bytes_relayed.fetch_add(n, Ordering::Relaxed);
+
+        // The bucket may go into debt, in which case
+        // the sender waits until it's paid off.
+        let rate = self.limits.bytes_per_sec as f64;
+        let debt = {
+            let mut bucket = budget.bucket.lock().expect("Couldn't acquire state lock.");
+            let now = Instant::now();
+            let tokens = bucket.tokens_at(now, rate, rate) - n as f64;
+            *bucket = TokenBucket {
+                tokens,
+                last_update: now,
+            };
+            -tokens
+        };
+
+        if debt > 0.0 {
+            tokio::time::sleep(Duration::from_secs_f64(debt / rate)).await;
+        }
+        true
+    }
+}
+
+/// Error while trying to join a relay room.
+#[derive(Error, Debug)]
+pub enum Error {
+    /// This server doesn't relay traffic.
+    #[error("This server doesn't relay traffic.")]
+    Disabled,
+
+    /// A peer with this role is already waiting in this room.
+    #[error("A peer with this role is already waiting in this room.")]
+    RoleTaken,
+
+    /// The other peer didn't join in time.
+    #[error("The other peer didn't join in time.")]
+    PeerTimedOut,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Error, Relay, RelayLimits, Teardown, BUF_SIZE, QUEUE_LEN};
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn limits() -> RelayLimits {
+        RelayLimits {
+            bytes_per_sec: 1_000_000,
+            max_bytes: 20,
+            timeout: Duration::from_secs(60),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_relay() {
+        let relay = Relay::new(limits());
+
+        let creator = relay.join(0, [1; 32], true).unwrap();
+        assert!(matches!(
+            relay.join(0, [1; 32], true),
+            Err(Error::RoleTaken)
+        ));
+        let joiner = relay.join(0, [1; 32], false).unwrap();
+
+        let mut creator_pipe = creator.await.unwrap();
+        let mut joiner_pipe = joiner.await.unwrap();
+        assert_eq!(relay.metrics().active_rooms, 1);
+
+        let (mut creator_stream, mut creator_peer) = tokio::io::duplex(1000);
+        let (mut joiner_stream, mut joiner_peer) = tokio::io::duplex(1000);
+
+        let relay_a = relay.clone();
+        let creator_task =
+            tokio::spawn(async move { relay_a.run(&mut creator_stream, &mut creator_pipe).await });
+        let relay_b = relay.clone();
+        let joiner_task =
+            tokio::spawn(async move { relay_b.run(&mut joiner_stream, &mut joiner_pipe).await });
+
+        // bytes go both ways
+        creator_peer.write_all(b"hello").await.unwrap();
+        let mut buf = [0; 5];
+        joiner_peer.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+
+        joiner_peer.write_all(b"there").await.unwrap();
+        creator_peer.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"there");
+
+        // going over the byte limit tears down both ends
+        creator_peer.write_all(&[0; 100]).await.unwrap();
+        assert_eq!(creator_task.await.unwrap(), Teardown::ByteLimit);
+        assert_eq!(joiner_task.await.unwrap(), Teardown::Disconnected);
+
+        let metrics = relay.metrics();
+        assert_eq!(metrics.active_rooms, 0);
+        assert_eq!(metrics.total_rooms, 1);
+        assert!((10..=20).contains(&metrics.bytes_relayed));
+        assert_eq!(metrics.rooms_over_limit, 1);
+    }
+
+    /// Both peers sending more than the relay queues hold
+    /// at the same time mustn't stall the relay.
+    #[tokio::test]
+    async fn test_relay_both_ways() {
+        let relay = Relay::new(RelayLimits {
+            bytes_per_sec: 1_000_000_000,
+            max_bytes: u64::MAX,
+            timeout: Duration::from_secs(60),
+        });
+
+        let creator = relay.join(0, [3; 32], true).unwrap();
+        let joiner = relay.join(0, [3; 32], false).unwrap();
+        let mut creator_pipe = creator.await.unwrap();
+        let mut joiner_pipe = joiner.await.unwrap();
+
+        let (mut creator_stream, creator_peer) = tokio::io::duplex(BUF_SIZE);
+        let (mut joiner_stream, joiner_peer) = tokio::io::duplex(BUF_SIZE);
+
+        let relay_a = relay.clone();
+        tokio::spawn(async move { relay_a.run(&mut creator_stream, &mut creator_pipe).await });
+        let relay_b = relay.clone();
+        tokio::spawn(async move { relay_b.run(&mut joiner_stream, &mut joiner_pipe).await });
+
+        const LEN: usize = 4 * QUEUE_LEN * BUF_SIZE;
+
+        // the creator sends and receives at the same time
+        let creator = async {
+            let (mut reader, mut writer) = tokio::io::split(creator_peer);
+            let write = async { writer.write_all(&[1; LEN]).await.unwrap() };
+            let read = async {
+                let mut received = vec![0; LEN];
+                reader.read_exact(&mut received).await.unwrap();
+                received
+            };
+            tokio::join!(write, read).1
+        };
+
+        // the joiner sends everything before receiving anything
+        let joiner = async {
+            let mut joiner_peer = joiner_peer;
+            joiner_peer.write_all(&[2; LEN]).await.unwrap();
+            let mut received = vec![0; LEN];
+            joiner_peer.read_exact(&mut received).await.unwrap();
+            received
+        };
+
+        let (from_joiner, from_creator) = tokio::time::timeout(Duration::from_secs(10), async {
+            tokio::join!(creator, joiner)
+        })
+        .await
+        .expect("The relay stalled.");
+
+        assert!(from_joiner.iter().all(|&b| b == 2));
+        assert!(from_creator.iter().all(|&b| b == 1));
+    }
+
+    #[tokio::test]
+    async fn test_abandoned_wait() {
+        let relay = Relay::new(limits());
+
+        // a peer that gave up waiting doesn't block its role
+        drop(relay.join(0, [2; 32], true).unwrap());
+        relay.forget_abandoned(0, [2; 32]);
+        let creator = relay.join(0, [2; 32], true).unwrap();
+        drop(creator);
+        let joiner = relay.join(0, [2; 32], false).unwrap();
+        let _creator = relay.join(0, [2; 32], true).unwrap();
+        assert!(joiner.await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_separate_tenants() {
+        let relay = Relay::new(limits());
+
+        // peers of different tenants aren't paired
+        let mut creator = relay.join(0, [4; 32], true).unwrap();
+        let mut joiner = relay.join(1, [4; 32], false).unwrap();
+        assert!(creator.try_recv().is_err());
+        assert!(joiner.try_recv().is_err());
+
+        let _joiner = relay.join(0, [4; 32], false).unwrap();
+        assert!(creator.await.is_ok());
+    }
+}
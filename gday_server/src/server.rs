@@ -0,0 +1,403 @@
+use crate::{
+    acme::AcmeAcceptor,
+    connection_handler::TlsMode,
+    connection_limiter::ConnectionLimiter,
+    event_log::{EventLog, RoomEvent},
+    get_tcp_listener, get_tls_acceptor,
+    ip_filter::IpFilter,
+    persistence,
+    relay::{Relay, RelayLimits},
+    run_single_server,
+    state::State,
+    tenants::Tenants,
+    Args, Error, Listener, TenantConfig,
+};
+use ipnet::IpNet;
+use log::info;
+use std::{net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+use tokio::task::{AbortHandle, JoinSet};
+
+/// Configures and starts a [`Server`].
+///
+/// Create one with [`Server::builder()`], or from
+/// command line [`Args`] with [`ServerBuilder::from()`].
+/// Options that aren't set keep the defaults of the command line flags.
+#[derive(Debug)]
+pub struct ServerBuilder {
+    args: Args,
+}
+
+impl From<Args> for ServerBuilder {
+    fn from(args: Args) -> Self {
+        Self { args }
+    }
+}
+
+impl ServerBuilder {
+    /// Uses unencrypted TCP instead of TLS.
+    pub fn unencrypted(mut self) -> Self {
+        self.args.unencrypted = true;
+        self
+    }
+
+    /// Uses TLS with the PEM-encoded private key and signed certificate at these paths.
+    pub fn tls(mut self, key: impl Into<PathBuf>, certificate: impl Into<PathBuf>) -> Self {
+        self.args.key = Some(key.into());
+        self.args.certificate = Some(certificate.into());
+        self
+    }
+
+    /// Obtains and renews a TLS certificate for `domain` from Let's Encrypt,
+    /// registering with the contact `email` if given.
+    pub fn acme(mut self, domain: impl Into<String>, email: Option<String>) -> Self {
+        self.args.acme_domain = Some(domain.into());
+        self.args.acme_email = email;
+        self
+    }
+
+    /// Listens on these socket addresses.
+    ///
+    /// Pass port `0` to let the operating system choose a free port,
+    /// then get it with [`Server::addresses()`].
+    pub fn addresses(mut self, addresses: impl IntoIterator<Item = SocketAddr>) -> Self {
+        self.args.addresses = addresses.into_iter().collect();
+        self
+    }
+
+    /// Deletes new rooms after `timeout`, rounded down to whole seconds.
+    pub fn room_timeout(mut self, timeout: Duration) -> Self {
+        self.args.timeout = timeout.as_secs();
+        self
+    }
+
+    /// Lets each IP address send `per_minute` critical requests
+    /// per minute, in bursts of up to `burst`.
+    pub fn request_limit(mut self, per_minute: u32, burst: u32) -> Self {
+        self.args.request_limit = per_minute;
+        self.args.request_burst = burst;
+        self
+    }
+
+    /// Lets each IP address have `per_ip` connections open at once,
+    /// and the server have `total`.
+    pub fn connection_limit(mut self, per_ip: u32, total: u32) -> Self {
+        self.args.max_connections_per_ip = per_ip;
+        self.args.max_connections = total;
+        self
+    }
+
+    /// Only accepts connections from the `allow` subnets (or any if empty),
+    /// and rejects connections from the `deny` subnets.
+    pub fn ip_filter(mut self, allow: Vec<IpNet>, deny: Vec<IpNet>) -> Self {
+        self.args.allow_cidr = allow;
+        self.args.deny_cidr = deny;
+        self
+    }
+
+    /// Saves open rooms in `path`, and restores them from it when starting.
+    pub fn state_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.args.state_file = Some(path.into());
+        self
+    }
+
+    /// Relays traffic between peers that can't connect directly,
+    /// up to `bytes_per_sec` and `max_bytes` per pair of peers.
+    pub fn relay(mut self, bytes_per_sec: u64, max_bytes: u64) -> Self {
+        self.args.relay = true;
+        self.args.relay_bandwidth = bytes_per_sec;
+        self.args.relay_byte_limit = max_bytes;
+        self
+    }
+
+    /// Remembers up to `size` recent room events.
+    pub fn event_log_size(mut self, size: usize) -> Self {
+        self.args.event_log_size = size;
+        self
+    }
+
+    /// Answers admin commands on a unix socket at `path` (Unix only).
+    pub fn admin_socket(mut self, path: impl Into<PathBuf>) -> Self {
+        self.args.admin_socket = Some(path.into());
+        self
+    }
+
+    /// Expects each connection to start with a PROXY protocol header,
+    /// as sent by some load balancers.
+    pub fn proxy_protocol(mut self) -> Self {
+        self.args.proxy_protocol = true;
+        self
+    }
+
+    /// Serves these tenants, each with its own rooms and request limits.
+    /// If `required`, clients must authenticate as one of them.
+    pub fn tenants(mut self, tenants: Vec<TenantConfig>, required: bool) -> Self {
+        self.args.tenants = tenants;
+        self.args.require_tenant = required;
+        self
+    }
+
+    /// Starts the server in the background.
+    ///
+    /// Unlike the `gday_server` executable, doesn't initialize a logger.
+    ///
+    /// Must be called from a tokio async context.
+    pub fn start(self) -> Result<Server, Error> {
+        let args = self.args;
+        args.validate()?;
+
+        // get TCP listeners
+        let tcp_listeners: Result<Vec<tokio::net::TcpListener>, Error> = args
+            .addresses
+            .iter()
+            .copied()
+            .map(get_tcp_listener)
+            .collect();
+        let tcp_listeners = tcp_listeners?;
+
+        // get the addresses that we've actually bound to
+        let addresses: std::io::Result<Vec<SocketAddr>> =
+            tcp_listeners.iter().map(|l| l.local_addr()).collect();
+        let addresses = addresses.map_err(|source| Error {
+            msg: "Couldn't determine local address".to_string(),
+            source,
+        })?;
+
+        // get the TLS acceptor if applicable
+        let tls_mode = if let Some(domain) = &args.acme_domain {
+            TlsMode::Acme(AcmeAcceptor::new(
+                domain,
+                args.acme_email.as_deref(),
+                args.acme_cache.clone(),
+                !args.acme_staging,
+            ))
+        } else if let (Some(key), Some(cert)) = (&args.key, &args.certificate) {
+            TlsMode::Static(get_tls_acceptor(key, cert)?)
+        } else {
+            TlsMode::Unencrypted
+        };
+
+        // tasks that run as long as the server,
+        // and handles to abort them on shutdown
+        let mut background = JoinSet::new();
+        let mut abort_handles = Vec::new();
+
+        // create the shared global state object
+        let mut state = State::new(
+            args.request_limit,
+            args.request_burst,
+            std::time::Duration::from_secs(args.timeout),
+        );
+
+        // restore rooms saved before the last restart, and keep saving them
+        if let Some(state_file) = &args.state_file {
+            let restored = state.restore_rooms(persistence::load_rooms(state_file)?);
+            info!("Restored {restored} open rooms from {state_file:?}.");
+            abort_handles.push(background.spawn(persistence::save_rooms_periodically(
+                state_file.clone(),
+                state.clone(),
+            )));
+        }
+
+        // separate rooms and limits for each tenant
+        let tenants = Tenants::new(&args, state);
+
+        // relay for peers that can't connect directly, if enabled
+        let relay = args.relay.then(|| {
+            Relay::new(RelayLimits {
+                bytes_per_sec: args.relay_bandwidth,
+                max_bytes: args.relay_byte_limit,
+                timeout: Duration::from_secs(args.timeout),
+            })
+        });
+
+        // recent room events, for debugging
+        let event_log = EventLog::new(args.event_log_size);
+
+        // admin interface, if requested
+        if let Some(admin_socket) = &args.admin_socket {
+            #[cfg(unix)]
+            {
+                let listener = crate::admin::bind(admin_socket)?;
+                info!("Answering admin commands on {admin_socket:?}.");
+                abort_handles.push(background.spawn(crate::admin::serve(
+                    listener,
+                    event_log.clone(),
+                    tenants.clone(),
+                )));
+            }
+            #[cfg(not(unix))]
+            log::warn!(
+                "Ignoring admin socket {admin_socket:?}, since this platform doesn't support it."
+            );
+        }
+
+        // limits on simultaneously open connections
+        let limiter = ConnectionLimiter::new(args.max_connections_per_ip, args.max_connections);
+
+        // subnets to accept or reject connections from
+        let ip_filter = IpFilter::new(args.allow_cidr.clone(), args.deny_cidr.clone());
+        #[cfg(unix)]
+        if let Some(config_path) = &args.config {
+            abort_handles.push(background.spawn(crate::reload_ip_filter_on_sighup(
+                config_path.clone(),
+                ip_filter.clone(),
+            )));
+        }
+
+        // log the addresses being listened on
+        info!("Listening on these addresses: {addresses:?}");
+        info!("Is encrypted?: {}", tls_mode.is_encrypted());
+        if args.proxy_protocol {
+            info!("Expecting PROXY protocol headers from a load balancer.");
+        }
+        if let Some(domain) = &args.acme_domain {
+            info!("Managing TLS certificate for '{domain}' with ACME.");
+        }
+        info!(
+            "Critical requests per minute per IP address limit: {} (burst of {})",
+            args.request_limit, args.request_burst
+        );
+        info!(
+            "Open connections limit: {} per IP address, {} total",
+            args.max_connections_per_ip, args.max_connections
+        );
+        info!(
+            "Number of seconds before a new room is deleted: {}",
+            args.timeout
+        );
+        if !args.tenants.is_empty() {
+            info!(
+                "Serving {} tenants. Authentication required?: {}",
+                args.tenants.len(),
+                args.require_tenant
+            );
+        }
+        if args.relay {
+            info!(
+                "Relaying traffic: {} bytes per second and {} bytes total per pair of peers",
+                args.relay_bandwidth, args.relay_byte_limit
+            );
+        }
+        info!("Server is now running.");
+
+        let listener = Listener {
+            tenants: tenants.clone(),
+            tls_mode,
+            limiter,
+            ip_filter,
+            relay: relay.clone(),
+            event_log: event_log.clone(),
+            proxy_protocol: args.proxy_protocol,
+        };
+
+        let mut listeners = JoinSet::new();
+        for tcp_listener in tcp_listeners {
+            abort_handles.push(listeners.spawn(run_single_server(tcp_listener, listener.clone())));
+        }
+
+        Ok(Server {
+            addresses,
+            tenants,
+            relay,
+            event_log,
+            shutdown: ShutdownHandle {
+                tasks: Arc::new(abort_handles),
+            },
+            listeners,
+            _background: background,
+        })
+    }
+}
+
+/// A running server, started with [`ServerBuilder::start()`].
+///
+/// Dropping it stops the server from accepting new connections,
+/// but lets already open connections finish.
+#[derive(Debug)]
+pub struct Server {
+    addresses: Vec<SocketAddr>,
+    tenants: Tenants,
+    relay: Option<Relay>,
+    event_log: EventLog,
+    shutdown: ShutdownHandle,
+    /// Tasks accepting connections
+    listeners: JoinSet<()>,
+    /// Other tasks that run as long as the server.
+    /// Only held so they're aborted when the server is dropped.
+    _background: JoinSet<()>,
+}
+
+impl Server {
+    /// Returns a [`ServerBuilder`] with the same defaults as the command line flags.
+    ///
+    /// One of [`ServerBuilder::unencrypted()`], [`ServerBuilder::tls()`],
+    /// or [`ServerBuilder::acme()`] must be called before starting.
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::from(Args::default())
+    }
+
+    /// Returns the addresses the server is listening on.
+    pub fn addresses(&self) -> &[SocketAddr] {
+        &self.addresses
+    }
+
+    /// Returns the number of open rooms, including those of all tenants.
+    pub fn room_count(&self) -> usize {
+        self.tenants.default_state().room_count()
+            + self
+                .tenants
+                .iter()
+                .map(|tenant| tenant.state.room_count())
+                .sum::<usize>()
+    }
+
+    /// Returns the number of pairs of peers whose traffic is being relayed.
+    pub fn active_relays(&self) -> u64 {
+        self.relay
+            .as_ref()
+            .map_or(0, |relay| relay.metrics().active_rooms)
+    }
+
+    /// Returns the recent room events, oldest first.
+    pub fn events(&self) -> Vec<RoomEvent> {
+        self.event_log.events(|_| true)
+    }
+
+    /// Returns a handle that stops the server from another task.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Waits until the server stops accepting connections, which only
+    /// happens after [`ShutdownHandle::shutdown()`], or if it panics.
+    pub async fn wait(&mut self) {
+        while let Some(result) = self.listeners.join_next().await {
+            if let Err(err) = result {
+                if err.is_panic() {
+                    std::panic::resume_unwind(err.into_panic());
+                }
+            }
+        }
+    }
+}
+
+/// Stops a [`Server`] from accepting new connections,
+/// with [`ShutdownHandle::shutdown()`].
+///
+/// Cloning returns a handle to the same server.
+#[derive(Clone, Debug)]
+pub struct ShutdownHandle {
+    tasks: Arc<Vec<AbortHandle>>,
+}
+
+impl ShutdownHandle {
+    /// Stops the server from accepting new connections,
+    /// and stops its background tasks.
+    ///
+    /// Already open connections are left to finish.
+    pub fn shutdown(&self) {
+        for task in self.tasks.iter() {
+            task.abort();
+        }
+    }
+}
@@ -1,12 +1,20 @@
-use gday_contact_exchange_protocol::FullContact;
+use crate::logging::room_hash;
+use gday_contact_exchange_protocol::{FullContact, PortMapping};
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     net::{IpAddr, SocketAddr},
-    sync::{Arc, Mutex},
-    time::Duration,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime},
 };
 use thiserror::Error;
-use tokio::{sync::oneshot, time::MissedTickBehavior};
+use tokio::{
+    sync::oneshot,
+    time::{Instant, MissedTickBehavior},
+};
 
 /// Information about a client in a [`Room`].
 #[derive(Default, Debug)]
@@ -14,25 +22,54 @@ struct Client {
     /// Contact info of this client
     contact: FullContact,
     /// - `None` if the other peer isn't done and
-    ///     isn't ready to receive this peer's contacts.
+    ///   isn't ready to receive this peer's contacts.
     /// - `Some` if the other peer is done and
-    ///     ready to receive this peer's contacts.
+    ///   ready to receive this peer's contacts.
     ///
     /// Once this peer is done, and `contact_sender` isn't `None`,
     /// this sender sends [`Self::contact`].
     contact_sender: Option<oneshot::Sender<FullContact>>,
+    /// Whether this client's public IPv4 port
+    /// differed across its connections
+    public_port_varied: bool,
+}
+
+impl Client {
+    /// Returns how this client's NAT seems to map its IPv4 port,
+    /// from what the server observed so far.
+    fn port_mapping(&self) -> PortMapping {
+        if self.public_port_varied {
+            return PortMapping::Varies;
+        }
+        match (self.contact.local.v4, self.contact.public.v4) {
+            (Some(local), Some(public)) if local.port() == public.port() => PortMapping::Preserved,
+            (Some(_), Some(_)) => PortMapping::Translated,
+            _ => PortMapping::Unknown,
+        }
+    }
 }
 
 /// A room holds 2 [Client]s that want to exchange their contact info
-#[derive(Default, Debug)]
+#[derive(Debug)]
 struct Room {
     /// The client that created this room
     creator: Client,
     /// The client that joined this room
     joiner: Client,
+    /// When this room will be deleted
+    expires_at: SystemTime,
 }
 
 impl Room {
+    /// Creates an empty room that expires at `expires_at`.
+    fn new(expires_at: SystemTime) -> Self {
+        Self {
+            creator: Client::default(),
+            joiner: Client::default(),
+            expires_at,
+        }
+    }
+
     /// Get a reference to a client from this room
     fn get_client(&mut self, is_creator: bool) -> &Client {
         if is_creator {
@@ -52,6 +89,55 @@ impl Room {
     }
 }
 
+/// The contacts and deadline of a room,
+/// saved so the room can be restored after a server restart.
+///
+/// Doesn't include which clients are done, since their
+/// connections won't survive the restart anyway.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RoomSnapshot {
+    /// Code of the room
+    pub room_code: [u8; 32],
+    /// Contact of the client that created the room
+    pub creator: FullContact,
+    /// Contact of the client that joined the room
+    pub joiner: FullContact,
+    /// When the room will be deleted
+    pub expires_at: SystemTime,
+}
+
+/// Token bucket that limits how often an IP address can send requests.
+///
+/// Holds up to `burst` tokens, and regains them continuously
+/// at a fixed rate. Each request takes one token, and is rejected
+/// if none are left.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TokenBucket {
+    /// Tokens left as of `last_update`
+    pub tokens: f64,
+    /// When `tokens` was last updated
+    pub last_update: Instant,
+}
+
+impl TokenBucket {
+    /// Returns the number of tokens in this bucket at `now`.
+    pub fn tokens_at(&self, now: Instant, tokens_per_sec: f64, burst: f64) -> f64 {
+        let elapsed = now
+            .saturating_duration_since(self.last_update)
+            .as_secs_f64();
+        (self.tokens + elapsed * tokens_per_sec).min(burst)
+    }
+}
+
+/// The requests of an IP address.
+#[derive(Debug, Clone, Copy)]
+struct IpRequests {
+    /// Limits how often the IP address can send requests
+    bucket: TokenBucket,
+    /// Number of requests sent since `bucket` was last full
+    count: u64,
+}
+
 /// A reference to the server's shared state.
 ///
 /// Can only be used in a tokio runtime.
@@ -60,33 +146,63 @@ impl Room {
 /// is acquired at any given time. This is to prevent deadlock.
 #[derive(Clone, Debug)]
 pub struct State {
+    /// Same for clones of this [`State`], and different for all others
+    id: u64,
+
     /// Maps room code to rooms
     rooms: Arc<Mutex<HashMap<[u8; 32], Room>>>,
 
-    /// Maps IP addresses to the number of critical
-    /// requests they sent this minute.
-    request_counts: Arc<Mutex<HashMap<IpAddr, u32>>>,
+    /// Codes of rooms whose clients exchanged contacts
+    /// within the last room timeout, and so may be relayed
+    exchanged: Arc<Mutex<HashSet<[u8; 32]>>>,
+
+    /// Maps IP addresses to buckets limiting their critical requests.
+    request_buckets: Arc<Mutex<HashMap<IpAddr, IpRequests>>>,
 
-    /// Maximum number of requests an IP address can
-    /// send per minute before they're rejected.
-    max_requests_per_minute: Arc<u32>,
+    /// Average number of requests per second an
+    /// IP address can send before being rejected.
+    requests_per_sec: Arc<f64>,
+
+    /// Number of requests an IP address can send
+    /// in a quick burst before being rejected.
+    request_burst: Arc<f64>,
 
     /// Seconds before a newly created room is deleted
     room_timeout: Arc<std::time::Duration>,
+
+    /// Max number of rooms open at once, if limited
+    max_rooms: Arc<Option<usize>>,
 }
 
 impl State {
     /// Creates a new [`State`] with the given config settings
-    pub fn new(max_requests_per_minute: u32, room_timeout: std::time::Duration) -> Self {
+    ///
+    /// Each IP address may send `request_burst` requests at once,
+    /// and regains the ability to send `requests_per_minute` requests
+    /// every minute.
+    pub fn new(
+        requests_per_minute: u32,
+        request_burst: u32,
+        room_timeout: std::time::Duration,
+    ) -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
         let this = Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
             rooms: Arc::default(),
-            request_counts: Arc::default(),
-            max_requests_per_minute: Arc::new(max_requests_per_minute),
+            exchanged: Arc::default(),
+            request_buckets: Arc::default(),
+            requests_per_sec: Arc::new(f64::from(requests_per_minute) / 60.0),
+            request_burst: Arc::new(f64::from(request_burst)),
             room_timeout: Arc::new(room_timeout),
+            max_rooms: Arc::new(None),
         };
 
-        // spawn a backround thread that clears `request_counts` every minute
-        let request_counts = this.request_counts.clone();
+        // spawn a backround thread that forgets full buckets every minute,
+        // since they're no different from new ones
+        let request_buckets = this.request_buckets.clone();
+        let requests_per_sec = *this.requests_per_sec;
+        let request_burst = *this.request_burst;
         tokio::spawn(async move {
             // Don't burst if the interval is missed.
             // First tick should resolve immediately.
@@ -96,21 +212,44 @@ impl State {
 
             loop {
                 interval.tick().await;
-                request_counts
+                let now = Instant::now();
+                request_buckets
                     .lock()
                     .expect("Couldn't acquire state lock.")
-                    .clear();
+                    .retain(|_, requests| {
+                        requests
+                            .bucket
+                            .tokens_at(now, requests_per_sec, request_burst)
+                            < request_burst
+                    });
             }
         });
 
         this
     }
 
+    /// Returns an ID that's the same for clones of this [`State`],
+    /// and different for every other [`State`], such as another tenant's.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Limits this [`State`] to `max_rooms` rooms open at once.
+    ///
+    /// Rooms restored with [`State::restore_rooms()`] don't count
+    /// towards the limit.
+    pub fn with_max_rooms(mut self, max_rooms: usize) -> Self {
+        self.max_rooms = Arc::new(Some(max_rooms));
+        self
+    }
+
     /// Creates a new room with `room_code`.
     ///
     /// - Returns [`Error::TooManyRequests`] if `origin`'s
     ///   request limit is exceeded.
     /// - Returns [`Error::RoomCodeTaken`] if the room already exists.
+    /// - Returns [`Error::TooManyRooms`] if the max number
+    ///   of rooms are already open.
     pub fn create_room(&mut self, room_code: [u8; 32], origin: IpAddr) -> Result<(), Error> {
         self.increment_request_count(origin)?;
 
@@ -121,12 +260,68 @@ impl State {
             if rooms.contains_key(&room_code) {
                 return Err(Error::RoomCodeTaken);
             }
-            rooms.insert(room_code, Room::default());
+            if self
+                .max_rooms
+                .is_some_and(|max_rooms| rooms.len() >= max_rooms)
+            {
+                return Err(Error::TooManyRooms);
+            }
+            rooms.insert(room_code, Room::new(SystemTime::now() + *self.room_timeout));
+        }
+
+        self.schedule_removal(room_code, *self.room_timeout);
+
+        Ok(())
+    }
+
+    /// Returns snapshots of all open rooms.
+    pub fn snapshot_rooms(&self) -> Vec<RoomSnapshot> {
+        let rooms = self.rooms.lock().expect("Couldn't acquire state lock.");
+        rooms
+            .iter()
+            .map(|(room_code, room)| RoomSnapshot {
+                room_code: *room_code,
+                creator: room.creator.contact,
+                joiner: room.joiner.contact,
+                expires_at: room.expires_at,
+            })
+            .collect()
+    }
+
+    /// Re-creates rooms from `snapshots`, each with its remaining time.
+    ///
+    /// Skips rooms that already expired, or whose code is taken.
+    /// Returns the number of rooms restored.
+    pub fn restore_rooms(&mut self, snapshots: Vec<RoomSnapshot>) -> usize {
+        let now = SystemTime::now();
+        let mut restored = 0;
+
+        for snapshot in snapshots {
+            let Ok(remaining) = snapshot.expires_at.duration_since(now) else {
+                continue;
+            };
+
+            {
+                let mut rooms = self.rooms.lock().expect("Couldn't acquire state lock.");
+                if rooms.contains_key(&snapshot.room_code) {
+                    continue;
+                }
+                let mut room = Room::new(snapshot.expires_at);
+                room.creator.contact = snapshot.creator;
+                room.joiner.contact = snapshot.joiner;
+                rooms.insert(snapshot.room_code, room);
+            }
+
+            self.schedule_removal(snapshot.room_code, remaining);
+            restored += 1;
         }
 
-        // spawn a thread that will remove this
-        // room if it still exists after the timeout
-        let timeout = *self.room_timeout;
+        restored
+    }
+
+    /// Spawns a thread that will remove the room with
+    /// `room_code` if it still exists after `timeout`.
+    fn schedule_removal(&self, room_code: [u8; 32], timeout: Duration) {
         let rooms = self.rooms.clone();
         tokio::spawn(async move {
             tokio::time::sleep(timeout).await;
@@ -135,8 +330,6 @@ impl State {
                 .expect("Couldn't acquire state lock.")
                 .remove(&room_code);
         });
-
-        Ok(())
     }
 
     /// Updates the contact information of a client in the room with `room_code`.
@@ -170,6 +363,16 @@ impl State {
 
         // get the client's contact
         let client = &mut room.get_client_mut(is_creator);
+
+        // a new public IPv4 port for the same client hints at a symmetric NAT
+        if let (true, SocketAddr::V4(addr), Some(old)) =
+            (public, endpoint, client.contact.public.v4)
+        {
+            if old.port() != addr.port() {
+                client.public_port_varied = true;
+            }
+        }
+
         let contact = if public {
             &mut client.contact.public
         } else {
@@ -210,6 +413,10 @@ impl State {
 
         let (tx, rx) = oneshot::channel();
 
+        // report what was observed about this client's NAT
+        let client = room.get_client_mut(is_creator);
+        client.contact.nat.port_mapping = client.port_mapping();
+
         // Give the peer a contact sender.
         // Once the peer gets `set_client_done()` called,
         // they will send their own contact info via this sender.
@@ -236,6 +443,22 @@ impl State {
 
                     // remove their room
                     rooms.remove(&room_code);
+                    drop(rooms);
+
+                    // but let them fall back to the relay for a while
+                    self.exchanged
+                        .lock()
+                        .expect("Couldn't acquire state lock.")
+                        .insert(room_code);
+                    let exchanged = self.exchanged.clone();
+                    let timeout = *self.room_timeout;
+                    tokio::spawn(async move {
+                        tokio::time::sleep(timeout).await;
+                        exchanged
+                            .lock()
+                            .expect("Couldn't acquire state lock.")
+                            .remove(&room_code);
+                    });
                 }
             }
         }
@@ -243,24 +466,108 @@ impl State {
         Ok((client_contact, rx))
     }
 
-    /// Increments the request count of this IP address.
+    /// Checks that clients may be relayed in the room with `room_code`.
+    ///
+    /// They may if the room is open, or if its clients
+    /// exchanged contacts within the last room timeout.
+    ///
+    /// - Returns [`Error::NoSuchRoomCode`] otherwise.
+    /// - Returns [`Error::TooManyRequests`] if `origin`'s
+    ///   request limit is exceeded.
+    pub fn check_relay_room(&self, room_code: [u8; 32], origin: IpAddr) -> Result<(), Error> {
+        let open = self
+            .rooms
+            .lock()
+            .expect("Couldn't acquire state lock.")
+            .contains_key(&room_code);
+        let exchanged = self
+            .exchanged
+            .lock()
+            .expect("Couldn't acquire state lock.")
+            .contains(&room_code);
+        if open || exchanged {
+            Ok(())
+        } else {
+            self.increment_request_count(origin)?;
+            Err(Error::NoSuchRoomCode)
+        }
+    }
+
+    /// Closes the room with `room_code`, if it's open,
+    /// so that its code can be used for a new room.
+    pub fn close_room(&self, room_code: [u8; 32]) {
+        self.rooms
+            .lock()
+            .expect("Couldn't acquire state lock.")
+            .remove(&room_code);
+    }
+
+    /// Takes a token from the request bucket of this IP address.
     ///
-    /// Returns an [`Error::TooManyRequests`] if [`State::max_requests_per_minute`]
-    /// is exceeded.
-    fn increment_request_count(&self, ip: IpAddr) -> Result<(), Error> {
-        let mut request_counts = self
-            .request_counts
+    /// Returns an [`Error::TooManyRequests`] if the bucket is empty.
+    pub fn increment_request_count(&self, ip: IpAddr) -> Result<(), Error> {
+        let mut request_buckets = self
+            .request_buckets
             .lock()
             .expect("Couldn't acquire state lock.");
-        let conns_count = request_counts.entry(ip).or_insert(0);
 
-        if *conns_count >= *self.max_requests_per_minute {
+        let now = Instant::now();
+        let requests = request_buckets.entry(ip).or_insert(IpRequests {
+            bucket: TokenBucket {
+                tokens: *self.request_burst,
+                last_update: now,
+            },
+            count: 0,
+        });
+        requests.count += 1;
+
+        let tokens = requests
+            .bucket
+            .tokens_at(now, *self.requests_per_sec, *self.request_burst);
+
+        if tokens < 1.0 {
             Err(Error::TooManyRequests)
         } else {
-            *conns_count = conns_count.saturating_add(1);
+            requests.bucket = TokenBucket {
+                tokens: tokens - 1.0,
+                last_update: now,
+            };
             Ok(())
         }
     }
+
+    /// Returns the number of open rooms.
+    pub fn room_count(&self) -> usize {
+        self.rooms
+            .lock()
+            .expect("Couldn't acquire state lock.")
+            .len()
+    }
+
+    /// Returns the number of requests each IP address sent, including
+    /// rejected ones, since it last went a while without requests.
+    pub fn request_counts(&self) -> Vec<(IpAddr, u64)> {
+        let request_buckets = self
+            .request_buckets
+            .lock()
+            .expect("Couldn't acquire state lock.");
+        request_buckets
+            .iter()
+            .map(|(ip, requests)| (*ip, requests.count))
+            .collect()
+    }
+
+    /// Deletes the room whose [`room_hash()`] is `hash`, if there is one.
+    ///
+    /// Clients waiting for their peer in this room
+    /// get [`ServerMsg::ErrorPeerTimedOut`](gday_contact_exchange_protocol::ServerMsg::ErrorPeerTimedOut).
+    /// Returns `true` if a room was deleted.
+    pub fn expire_room(&self, hash: &str) -> bool {
+        let mut rooms = self.rooms.lock().expect("Couldn't acquire state lock.");
+        let len = rooms.len();
+        rooms.retain(|room_code, _| room_hash(room_code) != hash);
+        rooms.len() != len
+    }
 }
 
 /// Error while trying to update the global server state.
@@ -270,8 +577,8 @@ pub enum Error {
     #[error("No room exists with this code.")]
     NoSuchRoomCode,
 
-    /// Exceeded the request per minute limit. Try again in a minute.
-    #[error("Exceeded the request per minute limit. Try again in a minute.")]
+    /// Exceeded the request rate limit. Try again later.
+    #[error("Exceeded the request rate limit. Try again later.")]
     TooManyRequests,
 
     /// This room code is currently taken.
@@ -281,19 +588,25 @@ pub enum Error {
     /// Can't update client after it was set to done.
     #[error("Can't update client after they were set to done.")]
     CantUpdateDoneClient,
+
+    /// The max number of rooms are already open.
+    #[error("The max number of rooms are already open.")]
+    TooManyRooms,
 }
 
 #[cfg(test)]
 mod tests {
     use super::Error;
     use super::State;
+    use crate::logging::room_hash;
     use gday_contact_exchange_protocol::Contact;
     use gday_contact_exchange_protocol::FullContact;
+    use gday_contact_exchange_protocol::{NatBehavior, PortMapping};
     use std::{net::IpAddr, time::Duration};
 
     #[tokio::test]
     async fn test_general() {
-        let mut state1 = State::new(100, Duration::from_secs(100));
+        let mut state1 = State::new(100, 100, Duration::from_secs(100));
         let mut state2 = state1.clone();
 
         // Origins are only used to limit requests,
@@ -302,7 +615,7 @@ mod tests {
         let origin1 = IpAddr::V4(123.into());
         let origin2 = IpAddr::V6(456.into());
 
-        let contact1 = FullContact {
+        let mut contact1 = FullContact {
             local: Contact {
                 v4: Some("1.8.3.1:2304".parse().unwrap()),
                 v6: Some("[ab:41::b:43]:92".parse().unwrap()),
@@ -311,7 +624,10 @@ mod tests {
                 v4: Some("12.98.11.20:11".parse().unwrap()),
                 v6: Some("[12:1::9:ab]:56".parse().unwrap()),
             },
+            nat: NatBehavior::default(),
         };
+        // different local and public IPv4 ports
+        contact1.nat.port_mapping = PortMapping::Translated;
 
         let contact2 = FullContact {
             local: Contact {
@@ -322,6 +638,7 @@ mod tests {
                 v4: Some("5.20.100.50:2".parse().unwrap()),
                 v6: None,
             },
+            nat: NatBehavior::default(),
         };
 
         const ROOM: [u8; 32] = *b"hduejdlameu7493mzajfjdlf;sdafsda";
@@ -393,7 +710,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_request_limit() {
-        let mut state1 = State::new(100, Duration::from_secs(100));
+        let mut state1 = State::new(100, 100, Duration::from_secs(100));
         let mut state2 = state1.clone();
 
         let origin1 = IpAddr::V4(123.into());
@@ -414,9 +731,175 @@ mod tests {
         ));
     }
 
+    #[tokio::test]
+    async fn test_port_mapping() {
+        let mut state = State::new(100, 100, Duration::from_secs(100));
+        let origin = IpAddr::V4(123.into());
+        let addr = |s: &str| s.parse().unwrap();
+
+        state.create_room([1; 32], origin).unwrap();
+
+        // the creator's NAT preserves its port
+        state
+            .update_client([1; 32], true, addr("1.1.1.1:500"), true, origin)
+            .unwrap();
+        state
+            .update_client([1; 32], true, addr("10.0.0.1:500"), false, origin)
+            .unwrap();
+
+        // the joiner's public port changes between connections
+        state
+            .update_client([1; 32], false, addr("2.2.2.2:600"), true, origin)
+            .unwrap();
+        state
+            .update_client([1; 32], false, addr("2.2.2.2:601"), true, origin)
+            .unwrap();
+        state
+            .update_client([1; 32], false, addr("10.0.0.2:500"), false, origin)
+            .unwrap();
+
+        let (creator, _rx1) = state.set_client_done([1; 32], true, origin).unwrap();
+        let (joiner, _rx2) = state.set_client_done([1; 32], false, origin).unwrap();
+        assert_eq!(creator.nat.port_mapping, PortMapping::Preserved);
+        assert_eq!(joiner.nat.port_mapping, PortMapping::Varies);
+    }
+
+    #[tokio::test]
+    async fn test_expire_room() {
+        let mut state = State::new(100, 100, Duration::from_secs(100));
+        let origin = IpAddr::V4(123.into());
+
+        state.create_room([1; 32], origin).unwrap();
+        state.create_room([2; 32], origin).unwrap();
+        let _ = state.create_room([2; 32], origin);
+        assert_eq!(state.room_count(), 2);
+        assert_eq!(state.request_counts(), vec![(origin, 3)]);
+
+        assert!(state.expire_room(&room_hash(&[1; 32])));
+        assert!(!state.expire_room(&room_hash(&[1; 32])));
+        assert_eq!(state.room_count(), 1);
+        assert!(matches!(
+            state.update_client([1; 32], true, "1.2.3.4:5".parse().unwrap(), true, origin),
+            Err(Error::NoSuchRoomCode)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_relay_room() {
+        let mut state = State::new(100, 100, Duration::from_secs(100));
+        let other_tenant = State::new(100, 100, Duration::from_secs(100));
+        let origin = IpAddr::V4(123.into());
+        assert_ne!(state.id(), other_tenant.id());
+        assert_eq!(state.id(), state.clone().id());
+
+        // only rooms of this state may be relayed
+        assert!(matches!(
+            state.check_relay_room([1; 32], origin),
+            Err(Error::NoSuchRoomCode)
+        ));
+        state.create_room([1; 32], origin).unwrap();
+        state.check_relay_room([1; 32], origin).unwrap();
+        assert!(matches!(
+            other_tenant.check_relay_room([1; 32], origin),
+            Err(Error::NoSuchRoomCode)
+        ));
+
+        // including once its clients exchanged contacts
+        let (_, _rx1) = state.set_client_done([1; 32], true, origin).unwrap();
+        let (_, _rx2) = state.set_client_done([1; 32], false, origin).unwrap();
+        assert_eq!(state.room_count(), 0);
+        state.check_relay_room([1; 32], origin).unwrap();
+
+        // closing a room lets its code be reused
+        state.create_room([2; 32], origin).unwrap();
+        state.close_room([2; 32]);
+        state.create_room([2; 32], origin).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_request_refill() {
+        // 1 request per 100 milliseconds, with bursts of 2
+        let mut state = State::new(600, 2, Duration::from_secs(100));
+        let origin = IpAddr::V4(123.into());
+
+        // use up the burst
+        state.create_room([1; 32], origin).unwrap();
+        state.create_room([2; 32], origin).unwrap();
+        assert!(matches!(
+            state.create_room([3; 32], origin),
+            Err(Error::TooManyRequests)
+        ));
+
+        // wait for one token to refill
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        state.create_room([3; 32], origin).unwrap();
+        assert!(matches!(
+            state.create_room([4; 32], origin),
+            Err(Error::TooManyRequests)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_max_rooms() {
+        let mut state = State::new(100, 100, Duration::from_secs(100)).with_max_rooms(2);
+        let origin = IpAddr::V4(123.into());
+
+        state.create_room([1; 32], origin).unwrap();
+        state.create_room([2; 32], origin).unwrap();
+        assert!(matches!(
+            state.create_room([3; 32], origin),
+            Err(Error::TooManyRooms)
+        ));
+
+        // closing a room makes space for another
+        let (_, _rx1) = state.set_client_done([1; 32], true, origin).unwrap();
+        let (_, _rx2) = state.set_client_done([1; 32], false, origin).unwrap();
+        state.create_room([3; 32], origin).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_and_restore() {
+        let mut state1 = State::new(100, 100, Duration::from_secs(100));
+        let origin = IpAddr::V4(123.into());
+        let endpoint = "12.213.31.13:342".parse().unwrap();
+
+        const ROOM: [u8; 32] = *b"fjdkl;aeiwo58qp3jf9e0ajfkd;aslfj";
+
+        state1.create_room(ROOM, origin).unwrap();
+        state1
+            .update_client(ROOM, true, endpoint, true, origin)
+            .unwrap();
+
+        let snapshots = state1.snapshot_rooms();
+        assert_eq!(snapshots.len(), 1);
+        assert_eq!(snapshots[0].room_code, ROOM);
+        assert_eq!(
+            snapshots[0].creator.public.v4,
+            Some("12.213.31.13:342".parse().unwrap())
+        );
+
+        // an already expired room isn't restored
+        let mut expired = snapshots[0].clone();
+        expired.room_code = [7; 32];
+        expired.expires_at = std::time::SystemTime::now() - Duration::from_secs(1);
+
+        // restore into a fresh server
+        let mut state2 = State::new(100, 100, Duration::from_secs(100));
+        assert_eq!(state2.restore_rooms(vec![snapshots[0].clone(), expired]), 1);
+        assert_eq!(state2.snapshot_rooms(), snapshots);
+
+        // the restored room is usable
+        assert!(matches!(
+            state2.create_room(ROOM, origin),
+            Err(Error::RoomCodeTaken)
+        ));
+        let (contact, _rx) = state2.set_client_done(ROOM, true, origin).unwrap();
+        assert_eq!(contact, snapshots[0].creator);
+    }
+
     #[tokio::test]
     async fn test_room_timeout() {
-        let mut state1 = State::new(100, Duration::from_millis(30));
+        let mut state1 = State::new(100, 100, Duration::from_millis(30));
         let mut state2 = state1.clone();
 
         let origin1 = IpAddr::V4(123.into());
@@ -0,0 +1,110 @@
+//! Lets one server be shared by several apps or groups of users,
+//! each with its own rooms and request limits.
+
+use crate::{state::State, Args};
+use serde::Deserialize;
+use sha2::Digest;
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+/// A tenant that clients can authenticate as, set in the config file.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct TenantConfig {
+    /// Name of the tenant, shown in logs
+    pub name: String,
+    /// Secret that the tenant's clients authenticate with
+    pub api_key: String,
+    /// Like `request_limit`, but for this tenant's clients.
+    /// Defaults to the server's `request_limit`.
+    pub request_limit: Option<u32>,
+    /// Like `request_burst`, but for this tenant's clients.
+    /// Defaults to the server's `request_burst`.
+    pub request_burst: Option<u32>,
+    /// Max number of rooms the tenant can have open at once.
+    /// Unlimited if not set.
+    pub max_rooms: Option<usize>,
+}
+
+/// A tenant's name and separate [`State`].
+#[derive(Clone, Debug)]
+pub struct Tenant {
+    /// Name of the tenant
+    pub name: Arc<str>,
+    /// Rooms and request limits of the tenant
+    pub state: State,
+}
+
+/// The server's tenants, and the [`State`] used
+/// by clients that don't authenticate.
+///
+/// Cloning returns a reference to the same tenants.
+#[derive(Clone, Debug)]
+pub struct Tenants {
+    /// Maps each tenant's token to the tenant
+    by_token: Arc<HashMap<[u8; 32], Tenant>>,
+    /// State of clients that don't authenticate
+    default_state: State,
+    /// Whether clients must authenticate to use the server
+    required: bool,
+}
+
+impl Tenants {
+    /// Creates a [`State`] for each tenant in `args`.
+    ///
+    /// Clients that don't authenticate use `default_state`.
+    pub fn new(args: &Args, default_state: State) -> Self {
+        let by_token = args
+            .tenants
+            .iter()
+            .map(|config| {
+                let mut state = State::new(
+                    config.request_limit.unwrap_or(args.request_limit),
+                    config.request_burst.unwrap_or(args.request_burst),
+                    Duration::from_secs(args.timeout),
+                );
+                if let Some(max_rooms) = config.max_rooms {
+                    state = state.with_max_rooms(max_rooms);
+                }
+                let tenant = Tenant {
+                    name: config.name.as_str().into(),
+                    state,
+                };
+                (token(&config.api_key), tenant)
+            })
+            .collect();
+
+        Self {
+            by_token: Arc::new(by_token),
+            default_state,
+            required: args.require_tenant,
+        }
+    }
+
+    /// Returns the tenant with `token`, if there is one.
+    pub fn get(&self, token: &[u8; 32]) -> Option<&Tenant> {
+        self.by_token.get(token)
+    }
+
+    /// Returns an iterator over all tenants.
+    pub fn iter(&self) -> impl Iterator<Item = &Tenant> {
+        self.by_token.values()
+    }
+
+    /// Returns the [`State`] of clients that don't authenticate.
+    ///
+    /// Even if [`Tenants::required()`], failed authentication
+    /// attempts count towards its request limits.
+    pub fn default_state(&self) -> &State {
+        &self.default_state
+    }
+
+    /// Whether clients must authenticate as a tenant to use the server.
+    pub fn required(&self) -> bool {
+        self.required
+    }
+}
+
+/// Returns the token that clients send to authenticate with `api_key`.
+pub fn token(api_key: &str) -> [u8; 32] {
+    sha2::Sha256::digest(api_key.as_bytes()).into()
+}
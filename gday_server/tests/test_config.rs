@@ -0,0 +1,140 @@
+#![forbid(unsafe_code)]
+#![warn(clippy::all)]
+
+use gday_server::Args;
+use std::io::Write;
+
+/// Write `contents` to a temporary TOML file.
+fn make_config_file(contents: &str) -> tempfile::NamedTempFile {
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    write!(file, "{contents}").unwrap();
+    file
+}
+
+/// Confirm options are read from the config file,
+/// and that flags on the command line override them.
+#[test]
+fn test_config_file() {
+    let file = make_config_file(
+        r#"
+        unencrypted = true
+        addresses = ["127.0.0.1:2000"]
+        timeout = 100
+        request_limit = 20
+        verbosity = "info"
+        deny_cidr = ["10.0.0.0/8", "fd00::/8"]
+        "#,
+    );
+    let path = file.path().to_str().unwrap();
+
+    // file only
+    let args = Args::from_config_file(path).unwrap();
+    assert!(args.unencrypted);
+    assert_eq!(args.addresses, vec!["127.0.0.1:2000".parse().unwrap()]);
+    assert_eq!(args.timeout, 100);
+    assert_eq!(args.request_limit, 20);
+    assert_eq!(args.verbosity, log::LevelFilter::Info);
+    assert_eq!(
+        args.deny_cidr,
+        vec!["10.0.0.0/8".parse().unwrap(), "fd00::/8".parse().unwrap()]
+    );
+    assert!(args.allow_cidr.is_empty());
+    // not in the file, so it keeps its default
+    assert_eq!(args.acme_cache, std::path::PathBuf::from("acme_cache"));
+
+    // command line flags take precedence
+    let args = Args::parse_with_config_from([
+        "gday_server",
+        "--config",
+        path,
+        "--timeout",
+        "5",
+        "--key",
+        "key.pem",
+        "--certificate",
+        "cert.pem",
+    ])
+    .unwrap();
+    assert!(!args.unencrypted);
+    assert_eq!(args.key, Some("key.pem".into()));
+    assert_eq!(args.timeout, 5);
+    assert_eq!(args.request_limit, 20);
+}
+
+/// Confirm invalid config files are rejected.
+#[test]
+fn test_config_file_errors() {
+    // unknown key
+    let file = make_config_file("not_an_option = 3");
+    assert!(Args::from_config_file(file.path()).is_err());
+
+    // missing file
+    assert!(Args::from_config_file("nonexistent_config.toml").is_err());
+}
+
+/// Confirm invalid command line arguments are returned as errors.
+#[test]
+fn test_config_bad_arguments() {
+    // unknown flag
+    assert!(Args::parse_with_config_from(["gday_server", "--unencrypted", "--bogus"]).is_err());
+
+    // invalid value
+    assert!(
+        Args::parse_with_config_from(["gday_server", "--unencrypted", "--timeout", "soon"])
+            .is_err()
+    );
+
+    // no TLS mode
+    assert!(Args::parse_with_config_from(["gday_server"]).is_err());
+}
+
+/// Confirm the server refuses to start without a TLS configuration.
+#[tokio::test]
+async fn test_config_missing_tls() {
+    let file = make_config_file(r#"addresses = ["127.0.0.1:0"]"#);
+    let args = Args::from_config_file(file.path()).unwrap();
+    assert!(gday_server::ServerBuilder::from(args).start().is_err());
+}
+
+/// Confirm tenants are read from `[[tenants]]` tables.
+#[test]
+fn test_config_tenants() {
+    let file = make_config_file(
+        r#"
+        unencrypted = true
+        require_tenant = true
+
+        [[tenants]]
+        name = "alpha"
+        api_key = "alpha secret"
+        max_rooms = 5
+
+        [[tenants]]
+        name = "beta"
+        api_key = "beta secret"
+        request_limit = 100
+        "#,
+    );
+
+    let args = Args::from_config_file(file.path()).unwrap();
+    assert!(args.require_tenant);
+    assert_eq!(
+        args.tenants,
+        vec![
+            gday_server::TenantConfig {
+                name: "alpha".to_string(),
+                api_key: "alpha secret".to_string(),
+                request_limit: None,
+                request_burst: None,
+                max_rooms: Some(5),
+            },
+            gday_server::TenantConfig {
+                name: "beta".to_string(),
+                api_key: "beta secret".to_string(),
+                request_limit: Some(100),
+                request_burst: None,
+                max_rooms: None,
+            },
+        ]
+    );
+}
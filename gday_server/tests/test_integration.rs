@@ -1,23 +1,27 @@
 #![forbid(unsafe_code)]
 #![warn(clippy::all)]
 
-use std::io::Read;
+use std::{
+    io::{Read, Write},
+    time::Duration,
+};
 
 use gday_contact_exchange_protocol::{read_from, write_to, ClientMsg, Contact, ServerMsg};
+use gday_server::Server;
+use sha2::Digest;
 
 #[tokio::test]
 async fn test_integration() {
     // start the server in the background
-    let args = gday_server::Args {
-        key: None,
-        certificate: None,
-        unencrypted: true,
-        addresses: vec!["127.0.0.1:0".parse().unwrap(), "[::1]:0".parse().unwrap()],
-        timeout: 3600,
-        request_limit: 10,
-        verbosity: log::LevelFilter::Off,
-    };
-    let (server_addrs, _joinset) = gday_server::start_server(args).unwrap();
+    let server = Server::builder()
+        .unencrypted()
+        .addresses(["127.0.0.1:0".parse().unwrap(), "[::1]:0".parse().unwrap()])
+        .room_timeout(Duration::from_secs(3600))
+        .request_limit(10, 10)
+        .connection_limit(100, 1000)
+        .start()
+        .unwrap();
+    let server_addrs = server.addresses();
     let server_ipv4 = *server_addrs.iter().find(|a| a.is_ipv4()).unwrap();
     let server_ipv6 = *server_addrs.iter().find(|a| a.is_ipv6()).unwrap();
 
@@ -163,6 +167,18 @@ async fn test_integration() {
         };
         assert_eq!(peer_contact.local, local_contact_1);
 
+        // this server doesn't relay
+        write_to(
+            ClientMsg::RelayConnect {
+                room_code: [123; 32],
+                is_creator: true,
+            },
+            &mut stream_v4,
+        )
+        .unwrap();
+        let response: ServerMsg = read_from(&mut stream_v4).unwrap();
+        assert_eq!(response, ServerMsg::ErrorRelayDisabled);
+
         // ensure the room was closed, and can be reopened
         write_to(
             ClientMsg::CreateRoom {
@@ -181,16 +197,15 @@ async fn test_integration() {
 #[tokio::test]
 async fn test_request_limit() {
     // start the server in the background
-    let args = gday_server::Args {
-        key: None,
-        certificate: None,
-        unencrypted: true,
-        addresses: vec!["127.0.0.1:0".parse().unwrap(), "[::1]:0".parse().unwrap()],
-        timeout: 3600,
-        request_limit: 10,
-        verbosity: log::LevelFilter::Off,
-    };
-    let (server_addrs, _joinset) = gday_server::start_server(args).unwrap();
+    let server = Server::builder()
+        .unencrypted()
+        .addresses(["127.0.0.1:0".parse().unwrap(), "[::1]:0".parse().unwrap()])
+        .room_timeout(Duration::from_secs(3600))
+        .request_limit(10, 10)
+        .connection_limit(100, 1000)
+        .start()
+        .unwrap();
+    let server_addrs = server.addresses();
     let server_ipv4 = *server_addrs.iter().find(|a| a.is_ipv4()).unwrap();
     let server_ipv6 = *server_addrs.iter().find(|a| a.is_ipv6()).unwrap();
 
@@ -240,3 +255,361 @@ async fn test_request_limit() {
     .await
     .unwrap();
 }
+
+#[tokio::test]
+async fn test_relay() {
+    // start the server in the background
+    let server = Server::builder()
+        .unencrypted()
+        .addresses(["127.0.0.1:0".parse().unwrap(), "[::1]:0".parse().unwrap()])
+        .room_timeout(Duration::from_secs(3600))
+        .request_limit(10, 10)
+        .connection_limit(100, 1000)
+        .relay(1_000_000, 100)
+        .start()
+        .unwrap();
+    let server_addrs = server.addresses();
+    let server_ipv4 = *server_addrs.iter().find(|a| a.is_ipv4()).unwrap();
+
+    tokio::task::spawn_blocking(move || {
+        let mut creator = std::net::TcpStream::connect(server_ipv4).unwrap();
+        let mut joiner = std::net::TcpStream::connect(server_ipv4).unwrap();
+
+        // peers that didn't meet in a room aren't relayed
+        write_to(
+            ClientMsg::RelayConnect {
+                room_code: [5; 32],
+                is_creator: true,
+            },
+            &mut creator,
+        )
+        .unwrap();
+        let response: ServerMsg = read_from(&mut creator).unwrap();
+        assert_eq!(response, ServerMsg::ErrorNoSuchRoomCode);
+
+        write_to(ClientMsg::CreateRoom { room_code: [5; 32] }, &mut creator).unwrap();
+        let response: ServerMsg = read_from(&mut creator).unwrap();
+        assert_eq!(response, ServerMsg::RoomCreated);
+
+        for (stream, is_creator) in [(&mut creator, true), (&mut joiner, false)] {
+            write_to(
+                ClientMsg::RelayConnect {
+                    room_code: [5; 32],
+                    is_creator,
+                },
+                stream,
+            )
+            .unwrap();
+        }
+
+        let response: ServerMsg = read_from(&mut creator).unwrap();
+        assert_eq!(response, ServerMsg::RelayStarted);
+        let response: ServerMsg = read_from(&mut joiner).unwrap();
+        assert_eq!(response, ServerMsg::RelayStarted);
+
+        // bytes are relayed both ways
+        creator.write_all(b"hello").unwrap();
+        let mut buf = [0; 5];
+        joiner.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+
+        joiner.write_all(b"there").unwrap();
+        creator.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"there");
+
+        // exceeding the byte limit disconnects both peers
+        creator.write_all(&[0; 200]).unwrap();
+        assert_eq!(joiner.read(&mut buf).unwrap(), 0);
+
+        // the relayed room was closed, so it can be reopened
+        let mut creator = std::net::TcpStream::connect(server_ipv4).unwrap();
+        write_to(ClientMsg::CreateRoom { room_code: [5; 32] }, &mut creator).unwrap();
+        let response: ServerMsg = read_from(&mut creator).unwrap();
+        assert_eq!(response, ServerMsg::RoomCreated);
+    })
+    .await
+    .unwrap();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_admin_events() {
+    use std::io::BufRead;
+
+    let dir = tempfile::tempdir().unwrap();
+    let admin_socket = dir.path().join("admin.sock");
+
+    // start the server in the background
+    let server = Server::builder()
+        .unencrypted()
+        .addresses(["127.0.0.1:0".parse().unwrap(), "[::1]:0".parse().unwrap()])
+        .room_timeout(Duration::from_secs(3600))
+        .request_limit(10, 10)
+        .connection_limit(100, 1000)
+        .admin_socket(&admin_socket)
+        .start()
+        .unwrap();
+    let server_addrs = server.addresses();
+    let server_ipv4 = *server_addrs.iter().find(|a| a.is_ipv4()).unwrap();
+
+    tokio::task::spawn_blocking(move || {
+        let mut stream = std::net::TcpStream::connect(server_ipv4).unwrap();
+
+        write_to(ClientMsg::CreateRoom { room_code: [7; 32] }, &mut stream).unwrap();
+        let response: ServerMsg = read_from(&mut stream).unwrap();
+        assert_eq!(response, ServerMsg::RoomCreated);
+
+        write_to(ClientMsg::CreateRoom { room_code: [7; 32] }, &mut stream).unwrap();
+        let response: ServerMsg = read_from(&mut stream).unwrap();
+        assert_eq!(response, ServerMsg::ErrorRoomTaken);
+
+        // ask the admin socket for events from this client
+        let mut admin = std::os::unix::net::UnixStream::connect(&admin_socket).unwrap();
+        writeln!(admin, "events 127.0.0.1").unwrap();
+        let mut reply = String::new();
+        std::io::BufReader::new(&admin)
+            .read_line(&mut reply)
+            .unwrap();
+
+        let events: serde_json::Value = serde_json::from_str(&reply).unwrap();
+        let kinds: Vec<&str> = events
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|event| event["kind"].as_str().unwrap())
+            .collect();
+        assert_eq!(kinds, ["created", "room_taken"]);
+
+        // no events from other addresses
+        writeln!(admin, "events 10.0.0.1").unwrap();
+        let mut reply = String::new();
+        std::io::BufReader::new(&admin)
+            .read_line(&mut reply)
+            .unwrap();
+        assert_eq!(reply, "[]\n");
+
+        // send an admin command, and parse its reply
+        let mut command = |command: &str| {
+            writeln!(admin, "{command}").unwrap();
+            let mut reply = String::new();
+            std::io::BufReader::new(&admin)
+                .read_line(&mut reply)
+                .unwrap();
+            serde_json::from_str::<serde_json::Value>(&reply).unwrap()
+        };
+
+        let status = command("status");
+        assert_eq!(status["rooms"], 1);
+
+        let requests = command("requests");
+        assert_eq!(requests[0]["ip"], "127.0.0.1");
+        assert_eq!(requests[0]["requests"], 2);
+
+        // force-expire the room
+        let room = events[0]["room"].as_str().unwrap();
+        assert_eq!(command(&format!("expire {room}"))["expired"], true);
+        assert_eq!(command(&format!("expire {room}"))["expired"], false);
+        assert_eq!(command("status")["rooms"], 0);
+
+        assert!(command("nonsense").get("error").is_some());
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_proxy_protocol() {
+    // start the server in the background
+    let server = Server::builder()
+        .unencrypted()
+        .addresses(["127.0.0.1:0".parse().unwrap(), "[::1]:0".parse().unwrap()])
+        .room_timeout(Duration::from_secs(3600))
+        .request_limit(10, 10)
+        .connection_limit(100, 1000)
+        .proxy_protocol()
+        .start()
+        .unwrap();
+    let server_addrs = server.addresses();
+    let server_ipv4 = *server_addrs.iter().find(|a| a.is_ipv4()).unwrap();
+
+    tokio::task::spawn_blocking(move || {
+        let mut stream = std::net::TcpStream::connect(server_ipv4).unwrap();
+
+        // pretend to be a load balancer relaying a client
+        stream
+            .write_all(b"PROXY TCP4 203.0.113.7 198.51.100.1 4000 2311\r\n")
+            .unwrap();
+
+        let room_code = [9; 32];
+        write_to(ClientMsg::CreateRoom { room_code }, &mut stream).unwrap();
+        let response: ServerMsg = read_from(&mut stream).unwrap();
+        assert_eq!(response, ServerMsg::RoomCreated);
+
+        write_to(
+            ClientMsg::RecordPublicAddr {
+                room_code,
+                is_creator: true,
+            },
+            &mut stream,
+        )
+        .unwrap();
+        let response: ServerMsg = read_from(&mut stream).unwrap();
+        assert_eq!(response, ServerMsg::ReceivedAddr);
+
+        write_to(
+            ClientMsg::ReadyToShare {
+                local_contact: Contact::default(),
+                room_code,
+                is_creator: true,
+            },
+            &mut stream,
+        )
+        .unwrap();
+        let response: ServerMsg = read_from(&mut stream).unwrap();
+        let ServerMsg::ClientContact(contact) = response else {
+            panic!("Server replied with {response:?} instead of ClientContact");
+        };
+
+        // the server recorded the client's address, not the load balancer's
+        assert_eq!(contact.public.v4, Some("203.0.113.7:4000".parse().unwrap()));
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_tenants() {
+    let tenant = |name: &str, max_rooms| gday_server::TenantConfig {
+        name: name.to_string(),
+        api_key: format!("{name} secret"),
+        request_limit: None,
+        request_burst: None,
+        max_rooms,
+    };
+
+    // start the server in the background
+    let server = Server::builder()
+        .unencrypted()
+        .addresses(["127.0.0.1:0".parse().unwrap(), "[::1]:0".parse().unwrap()])
+        .room_timeout(Duration::from_secs(3600))
+        .request_limit(10, 10)
+        .connection_limit(100, 1000)
+        .tenants(vec![tenant("alpha", Some(1)), tenant("beta", None)], true)
+        .start()
+        .unwrap();
+    let server_addrs = server.addresses();
+    let server_ipv4 = *server_addrs.iter().find(|a| a.is_ipv4()).unwrap();
+
+    tokio::task::spawn_blocking(move || {
+        let token = |api_key: &str| sha2::Sha256::digest(api_key.as_bytes()).into();
+        let room_code = [5; 32];
+
+        // unauthenticated clients are rejected
+        let mut stream = std::net::TcpStream::connect(server_ipv4).unwrap();
+        write_to(ClientMsg::CreateRoom { room_code }, &mut stream).unwrap();
+        let response: ServerMsg = read_from(&mut stream).unwrap();
+        assert_eq!(response, ServerMsg::ErrorAuthRequired);
+
+        // and so are unknown tokens
+        let mut stream = std::net::TcpStream::connect(server_ipv4).unwrap();
+        let msg = ClientMsg::Authenticate {
+            token: token("wrong secret"),
+        };
+        write_to(msg, &mut stream).unwrap();
+        let response: ServerMsg = read_from(&mut stream).unwrap();
+        assert_eq!(response, ServerMsg::ErrorInvalidToken);
+
+        // a tenant can create up to its max number of rooms
+        let mut alpha = std::net::TcpStream::connect(server_ipv4).unwrap();
+        let msg = ClientMsg::Authenticate {
+            token: token("alpha secret"),
+        };
+        write_to(msg, &mut alpha).unwrap();
+        let response: ServerMsg = read_from(&mut alpha).unwrap();
+        assert_eq!(response, ServerMsg::Authenticated);
+
+        write_to(ClientMsg::CreateRoom { room_code }, &mut alpha).unwrap();
+        let response: ServerMsg = read_from(&mut alpha).unwrap();
+        assert_eq!(response, ServerMsg::RoomCreated);
+
+        write_to(ClientMsg::CreateRoom { room_code: [6; 32] }, &mut alpha).unwrap();
+        let response: ServerMsg = read_from(&mut alpha).unwrap();
+        assert_eq!(response, ServerMsg::ErrorTooManyRooms);
+
+        // another tenant's rooms are separate
+        let mut beta = std::net::TcpStream::connect(server_ipv4).unwrap();
+        let msg = ClientMsg::Authenticate {
+            token: token("beta secret"),
+        };
+        write_to(msg, &mut beta).unwrap();
+        let response: ServerMsg = read_from(&mut beta).unwrap();
+        assert_eq!(response, ServerMsg::Authenticated);
+
+        write_to(ClientMsg::CreateRoom { room_code }, &mut beta).unwrap();
+        let response: ServerMsg = read_from(&mut beta).unwrap();
+        assert_eq!(response, ServerMsg::RoomCreated);
+    })
+    .await
+    .unwrap();
+}
+
+/// Confirm a [`Server`] reports its rooms, and stops after shutdown.
+#[tokio::test]
+async fn test_server_handle() {
+    let mut server = Server::builder()
+        .unencrypted()
+        .addresses(["127.0.0.1:0".parse().unwrap()])
+        .room_timeout(Duration::from_secs(3600))
+        .request_limit(10, 10)
+        .connection_limit(100, 1000)
+        .start()
+        .unwrap();
+    let server_addr = server.addresses()[0];
+    assert_eq!(server.room_count(), 0);
+
+    let mut stream = tokio::task::spawn_blocking(move || {
+        let mut stream = std::net::TcpStream::connect(server_addr).unwrap();
+        write_to(
+            ClientMsg::CreateRoom {
+                room_code: [123; 32],
+            },
+            &mut stream,
+        )
+        .unwrap();
+        let response: ServerMsg = read_from(&mut stream).unwrap();
+        assert_eq!(response, ServerMsg::RoomCreated);
+        stream
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(server.room_count(), 1);
+    assert_eq!(server.active_relays(), 0);
+    assert!(!server.events().is_empty());
+
+    // stop the server from another task
+    let handle = server.shutdown_handle();
+    tokio::spawn(async move { handle.shutdown() });
+    tokio::time::timeout(Duration::from_secs(5), server.wait())
+        .await
+        .unwrap();
+
+    // no longer accepts connections
+    assert!(std::net::TcpStream::connect(server_addr).is_err());
+
+    // already open connections are left to finish
+    tokio::task::spawn_blocking(move || {
+        write_to(
+            ClientMsg::CreateRoom {
+                room_code: [42; 32],
+            },
+            &mut stream,
+        )
+        .unwrap();
+        let response: ServerMsg = read_from(&mut stream).unwrap();
+        assert_eq!(response, ServerMsg::RoomCreated);
+    })
+    .await
+    .unwrap();
+    assert_eq!(server.room_count(), 2);
+}
@@ -0,0 +1,107 @@
+//! This library lets you send files to a peer, or receive files from one, in a few calls.
+//!
+//! It does what [gday](https://crates.io/crates/gday) does to connect to a peer:
+//! meets the peer through a contact exchange server with
+//! [gday_hole_punch](https://docs.rs/gday_hole_punch/),
+//! encrypts the connection with
+//! [gday_encryption](https://docs.rs/gday_encryption/),
+//! and offers the files with
+//! [gday_file_transfer](https://docs.rs/gday_file_transfer/).
+//! So it can also send files to, and receive files from, a peer running gday.
+//!
+//! # Example
+//! Peer A and peer B are on different computers in this example.
+//! ```no_run
+//! # use gday_session::{Sender, Receiver};
+//! # use gday_file_transfer::FileResponseMsg;
+//! # use std::path::Path;
+//! #
+//! # let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+//! # rt.block_on( async {
+//! //////// Peer A ////////
+//!
+//! // Offer files and folders through a random server in the default list
+//! let sender = Sender::offer(&["folder/to/send/".into(), "a/file.txt".into()]).await?;
+//!
+//! // Give the code to peer B, for example over email
+//! let code_to_share = String::try_from(sender.code())?;
+//!
+//! // Wait for peer B to connect and respond to the offer
+//! let upload = sender.connect().await?;
+//! upload.send(|progress| {}).await?;
+//!
+//! //////// Peer B ////////
+//!
+//! // Connect to peer A with the code they gave you
+//! let receiver = Receiver::connect(&code_to_share.parse()?).await?;
+//!
+//! // Accept the files not saved yet, and resume interrupted ones
+//! let save_path = Path::new("save/the/files/here/");
+//! let response = FileResponseMsg::accept_only_new_and_interrupted(receiver.offer(), save_path)?;
+//! receiver.receive(response, save_path, |progress| {}).await?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! # }).unwrap();
+//! ```
+#![forbid(unsafe_code)]
+#![warn(clippy::all)]
+
+mod receiver;
+mod sender;
+
+use gday_contact_exchange_protocol::FullContact;
+use gday_hole_punch::Secret;
+use std::time::Duration;
+use thiserror::Error;
+
+pub use crate::receiver::Receiver;
+pub use crate::sender::{Sender, Upload};
+
+/// The encrypted connection to the peer.
+pub type PeerStream = gday_encryption::EncryptedStream<tokio::net::TcpStream>;
+
+/// How long to wait when connecting to a contact exchange server.
+const SERVER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to try hole punching to the peer before giving up,
+/// once both peers shared their contacts.
+const HOLE_PUNCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Length of the room code and shared secret that [`Sender::offer()`] generates.
+const CODE_LENGTH: usize = 5;
+
+/// `gday_session` error.
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Error connecting to the server or the peer.
+    #[error("{0}")]
+    HolePunch(#[from] gday_hole_punch::Error),
+
+    /// Error encrypting the connection to the peer.
+    #[error("{0}")]
+    Encryption(#[from] gday_encryption::Error),
+
+    /// Error offering or transferring the files.
+    #[error("{0}")]
+    FileTransfer(#[from] gday_file_transfer::Error),
+}
+
+/// Connects to the peer with `peer_contact` with hole punching,
+/// and encrypts the connection with a key derived from `shared_secret`.
+///
+/// `my_contact` is the contact that [`gday_hole_punch::share_contacts()`] returned.
+/// Returns [`gday_hole_punch::Error::HolePunchTimeout`]
+/// if that takes longer than [`HOLE_PUNCH_TIMEOUT`].
+async fn connect_to_peer(
+    my_contact: FullContact,
+    peer_contact: FullContact,
+    shared_secret: &Secret<String>,
+) -> Result<PeerStream, Error> {
+    let (stream, shared_key, _) = tokio::time::timeout(
+        HOLE_PUNCH_TIMEOUT,
+        gday_hole_punch::try_connect_to_peer(my_contact.local, peer_contact, shared_secret),
+    )
+    .await
+    .map_err(|_| gday_hole_punch::Error::HolePunchTimeout)??;
+    Ok(PeerStream::encrypt_connection(stream, shared_key.expose_secret()).await?)
+}
@@ -0,0 +1,103 @@
+use crate::{Error, PeerStream, SERVER_TIMEOUT};
+use gday_encryption::Fingerprint;
+use gday_file_transfer::{
+    read_from_async, write_to_async, EntryPolicy, FileOfferMsg, FileResponseMsg, TransferReport,
+    TransferSummary,
+};
+use gday_hole_punch::server_connector::{self, ServerConnection, DEFAULT_SERVERS};
+use gday_hole_punch::PeerCode;
+use std::path::Path;
+use std::time::Duration;
+
+/// A connection to a peer that offered files,
+/// waiting for a response to the offer.
+pub struct Receiver {
+    stream: PeerStream,
+    offer: FileOfferMsg,
+}
+
+impl Receiver {
+    /// Connects to the peer that gave this `peer_code`, through its server in
+    /// [`DEFAULT_SERVERS`], and gets its offer.
+    pub async fn connect(peer_code: &PeerCode) -> Result<Self, Error> {
+        let server_connection = server_connector::connect_to_server_id(
+            DEFAULT_SERVERS,
+            peer_code.server_id,
+            SERVER_TIMEOUT,
+        )
+        .await?;
+        Self::connect_through(server_connection, peer_code).await
+    }
+
+    /// Like [`Self::connect()`], but joins the room of `peer_code`
+    /// in the server `server_connection` is connected to,
+    /// such as a custom server.
+    pub async fn connect_through(
+        mut server_connection: ServerConnection,
+        peer_code: &PeerCode,
+    ) -> Result<Self, Error> {
+        let (my_contact, peer_contact_fut) =
+            gday_hole_punch::share_contacts(&mut server_connection, &peer_code.room_code, false)
+                .await?;
+        let peer_contact = peer_contact_fut.await?;
+        let mut stream =
+            crate::connect_to_peer(my_contact, peer_contact, &peer_code.shared_secret).await?;
+
+        let clock_skew = gday_file_transfer::estimate_clock_skew(&mut stream, false).await?;
+        let mut offer: FileOfferMsg = read_from_async(&mut stream).await?;
+        offer.adjust_for_clock_skew(&clock_skew);
+
+        Ok(Self { stream, offer })
+    }
+
+    /// The files the peer offered.
+    pub fn offer(&self) -> &FileOfferMsg {
+        &self.offer
+    }
+
+    /// The fingerprint of the connection, which the peer sees too.
+    ///
+    /// Compare it with the peer over another channel to
+    /// make sure no one connected in its place.
+    pub fn fingerprint(&self) -> Fingerprint {
+        self.stream.fingerprint()
+    }
+
+    /// Accepts the files chosen in `response`, and saves them in `save_dir`,
+    /// calling `progress_callback` with the progress.
+    ///
+    /// Also recreates the offered symlinks and empty folders.
+    /// Returns what happened to each offered file,
+    /// or `None` if `response` accepted none.
+    pub async fn receive(
+        mut self,
+        response: FileResponseMsg,
+        save_dir: &Path,
+        progress_callback: impl FnMut(&TransferReport),
+    ) -> Result<Option<TransferSummary>, Error> {
+        gday_file_transfer::check_entries(&self.offer, EntryPolicy::Recreate)?;
+        write_to_async(&response, &mut self.stream).await?;
+        if response.get_num_not_rejected() == 0 {
+            return Ok(None);
+        }
+
+        gday_file_transfer::wait_for_start(&mut self.stream, Duration::ZERO, |_| {}).await?;
+        let summary = gday_file_transfer::receive_files(
+            &self.offer,
+            &response,
+            save_dir,
+            &mut self.stream,
+            progress_callback,
+        )
+        .await?;
+        gday_file_transfer::create_entries(&self.offer, save_dir, EntryPolicy::Recreate)?;
+        Ok(Some(summary))
+    }
+
+    /// Rejects all the offered files.
+    pub async fn reject(mut self) -> Result<(), Error> {
+        let response = FileResponseMsg::reject_all_files(&self.offer);
+        write_to_async(&response, &mut self.stream).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+use crate::{Error, PeerStream, CODE_LENGTH, SERVER_TIMEOUT};
+use gday_contact_exchange_protocol::FullContact;
+use gday_encryption::Fingerprint;
+use gday_file_transfer::{
+    read_from_async, write_to_async, FileMetaLocal, FileOfferMsg, FileResponseMsg, OfferOptions,
+    TransferReport,
+};
+use gday_hole_punch::server_connector::{self, ServerConnection, DEFAULT_SERVERS};
+use gday_hole_punch::PeerCode;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Files offered in a room of a contact exchange server,
+/// waiting for a peer to join with [`Sender::code()`].
+pub struct Sender {
+    server_connection: ServerConnection,
+    peer_code: PeerCode,
+    my_contact: FullContact,
+    files: Vec<FileMetaLocal>,
+    offer: FileOfferMsg,
+}
+
+impl Sender {
+    /// Offers the files and folders at `paths`
+    /// in a new room of a random server in [`DEFAULT_SERVERS`].
+    ///
+    /// Give [`Self::code()`] to the peer, then call [`Self::connect()`].
+    pub async fn offer(paths: &[PathBuf]) -> Result<Self, Error> {
+        let (server_connection, server_id) =
+            server_connector::connect_to_random_server(DEFAULT_SERVERS, SERVER_TIMEOUT).await?;
+        let peer_code = PeerCode::random(server_id, CODE_LENGTH);
+        Self::offer_through(server_connection, peer_code, paths).await
+    }
+
+    /// Like [`Self::offer()`], but creates the room of `peer_code`
+    /// in the server `server_connection` is connected to,
+    /// such as a custom server.
+    pub async fn offer_through(
+        mut server_connection: ServerConnection,
+        peer_code: PeerCode,
+        paths: &[PathBuf],
+    ) -> Result<Self, Error> {
+        let mut tree = gday_file_transfer::get_file_tree(paths, &OfferOptions::default())?;
+        // lets the receiver resume partial downloads saved under another folder name
+        for file in &mut tree.files {
+            file.compute_head_hash()?;
+        }
+        let files = tree.files.clone();
+        let offer = FileOfferMsg::from(tree);
+
+        // the peer's contact is read later, in `connect()`
+        let (my_contact, _) =
+            gday_hole_punch::share_contacts(&mut server_connection, &peer_code.room_code, true)
+                .await?;
+
+        Ok(Self {
+            server_connection,
+            peer_code,
+            my_contact,
+            files,
+            offer,
+        })
+    }
+
+    /// The code the peer needs to receive the files with [`crate::Receiver::connect()`],
+    /// or with "gday get".
+    pub fn code(&self) -> &PeerCode {
+        &self.peer_code
+    }
+
+    /// The offer the peer will get.
+    pub fn offer_msg(&self) -> &FileOfferMsg {
+        &self.offer
+    }
+
+    /// Waits for the peer to join the room, connects to it, and offers it the files.
+    ///
+    /// Returns once the peer responds to the offer.
+    pub async fn connect(mut self) -> Result<Upload, Error> {
+        let peer_contact = gday_hole_punch::get_peer_contact(&mut self.server_connection).await?;
+        let mut stream =
+            crate::connect_to_peer(self.my_contact, peer_contact, &self.peer_code.shared_secret)
+                .await?;
+
+        // the receiver dates the files by its own clock
+        gday_file_transfer::estimate_clock_skew(&mut stream, true).await?;
+
+        write_to_async(&self.offer, &mut stream).await?;
+        let response = read_from_async(&mut stream).await?;
+
+        Ok(Upload {
+            stream,
+            files: self.files,
+            response,
+        })
+    }
+}
+
+/// Files offered to a connected peer, which responded to the offer.
+pub struct Upload {
+    stream: PeerStream,
+    files: Vec<FileMetaLocal>,
+    response: FileResponseMsg,
+}
+
+impl Upload {
+    /// Which files the peer accepted.
+    pub fn response(&self) -> &FileResponseMsg {
+        &self.response
+    }
+
+    /// The fingerprint of the connection, which the peer sees too.
+    ///
+    /// Compare it with the peer over another channel to
+    /// make sure no one connected in its place.
+    pub fn fingerprint(&self) -> Fingerprint {
+        self.stream.fingerprint()
+    }
+
+    /// Sends the files the peer accepted, calling `progress_callback` with the progress.
+    pub async fn send(
+        mut self,
+        progress_callback: impl FnMut(&TransferReport),
+    ) -> Result<(), Error> {
+        if self.response.get_num_not_rejected() == 0 {
+            return Ok(());
+        }
+        gday_file_transfer::wait_for_start(&mut self.stream, Duration::ZERO, |_| {}).await?;
+        gday_file_transfer::send_files(
+            &self.files,
+            &self.response,
+            &mut self.stream,
+            progress_callback,
+        )
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+#![forbid(unsafe_code)]
+#![warn(clippy::all)]
+
+use gday_file_transfer::FileResponseMsg;
+use gday_hole_punch::{server_connector, PeerCode, Secret};
+use gday_server::Server;
+use gday_session::{Receiver, Sender};
+use std::time::Duration;
+
+/// Confirm that a [`Sender`] and [`Receiver`] can
+/// meet through a server and transfer a folder.
+#[tokio::test]
+async fn test_session() {
+    let server = Server::builder()
+        .unencrypted()
+        .addresses(["127.0.0.1:0".parse().unwrap()])
+        .room_timeout(Duration::from_secs(3600))
+        .request_limit(10, 10)
+        .connection_limit(100, 1000)
+        .start()
+        .unwrap();
+    let server_addr = server.addresses()[0];
+    let timeout = Duration::from_secs(5);
+
+    let send_dir = tempfile::tempdir().unwrap();
+    let folder = send_dir.path().join("folder");
+    std::fs::create_dir_all(folder.join("nested")).unwrap();
+    std::fs::write(folder.join("a.txt"), b"Hello!").unwrap();
+    std::fs::write(folder.join("nested").join("b.bin"), vec![7_u8; 100_000]).unwrap();
+
+    let peer_code = PeerCode {
+        server_id: 0,
+        room_code: Secret::new("room".to_string()),
+        shared_secret: Secret::new("secret".to_string()),
+    };
+
+    let server_connection = server_connector::connect_tcp(server_addr, timeout)
+        .await
+        .unwrap();
+    let sender = Sender::offer_through(server_connection, peer_code.clone(), &[folder])
+        .await
+        .unwrap();
+    assert_eq!(sender.offer_msg().files.len(), 2);
+
+    let sending = tokio::spawn(async move {
+        let upload = sender.connect().await.unwrap();
+        assert_eq!(upload.response().get_num_not_rejected(), 2);
+        let fingerprint = upload.fingerprint();
+        upload.send(|_| {}).await.unwrap();
+        fingerprint
+    });
+
+    let server_connection = server_connector::connect_tcp(server_addr, timeout)
+        .await
+        .unwrap();
+    let receiver = Receiver::connect_through(server_connection, &peer_code)
+        .await
+        .unwrap();
+    let fingerprint = receiver.fingerprint();
+    let response = FileResponseMsg::accept_all_files(receiver.offer());
+    let save_dir = tempfile::tempdir().unwrap();
+    let summary = receiver
+        .receive(response, save_dir.path(), |_| {})
+        .await
+        .unwrap();
+
+    assert_eq!(sending.await.unwrap(), fingerprint);
+    assert_eq!(summary.unwrap().files.len(), 2);
+    assert_eq!(
+        std::fs::read(save_dir.path().join("folder").join("a.txt")).unwrap(),
+        b"Hello!"
+    );
+    assert_eq!(
+        std::fs::read(save_dir.path().join("folder").join("nested").join("b.bin")).unwrap(),
+        vec![7_u8; 100_000]
+    );
+}
+
+/// Confirm that a [`Sender`] learns when its offer is rejected.
+#[tokio::test]
+async fn test_reject() {
+    let server = Server::builder()
+        .unencrypted()
+        .addresses(["127.0.0.1:0".parse().unwrap()])
+        .start()
+        .unwrap();
+    let server_addr = server.addresses()[0];
+    let timeout = Duration::from_secs(5);
+
+    let send_dir = tempfile::tempdir().unwrap();
+    let file = send_dir.path().join("a.txt");
+    std::fs::write(&file, b"Hello!").unwrap();
+
+    let peer_code = PeerCode::random(0, 5);
+    let server_connection = server_connector::connect_tcp(server_addr, timeout)
+        .await
+        .unwrap();
+    let sender = Sender::offer_through(server_connection, peer_code.clone(), &[file])
+        .await
+        .unwrap();
+    let sending = tokio::spawn(async move {
+        let upload = sender.connect().await.unwrap();
+        assert_eq!(upload.response().get_num_not_rejected(), 0);
+        upload.send(|_| {}).await.unwrap();
+    });
+
+    let server_connection = server_connector::connect_tcp(server_addr, timeout)
+        .await
+        .unwrap();
+    let receiver = Receiver::connect_through(server_connection, &peer_code)
+        .await
+        .unwrap();
+    receiver.reject().await.unwrap();
+    sending.await.unwrap();
+}